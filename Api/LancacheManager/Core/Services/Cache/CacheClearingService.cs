@@ -21,6 +21,7 @@ public class CacheClearingService : ScheduledBackgroundService
     private readonly IUnifiedOperationTracker _operationTracker;
     private readonly IDbContextFactory<AppDbContext> _dbContextFactory;
     private readonly GameCacheDetectionService _gameCacheDetectionService;
+    private readonly DatasourceCapabilityService _capabilityService;
     private readonly SemaphoreSlim _startLock = new(1, 1);
     private string _cachePath = null!;
     private CacheDeleteMode _deleteMode;
@@ -53,7 +54,8 @@ public class CacheClearingService : ScheduledBackgroundService
         DatasourceService datasourceService,
         IUnifiedOperationTracker operationTracker,
         IDbContextFactory<AppDbContext> dbContextFactory,
-        GameCacheDetectionService gameCacheDetectionService)
+        GameCacheDetectionService gameCacheDetectionService,
+        DatasourceCapabilityService capabilityService)
         : base(logger, configuration)
     {
         _notifications = notifications;
@@ -64,6 +66,7 @@ public class CacheClearingService : ScheduledBackgroundService
         _operationTracker = operationTracker;
         _dbContextFactory = dbContextFactory;
         _gameCacheDetectionService = gameCacheDetectionService;
+        _capabilityService = capabilityService;
 
         _deleteMode = CacheDeleteMode.Preserve;
 
@@ -269,8 +272,9 @@ public class CacheClearingService : ScheduledBackgroundService
                 _logger.LogInformation($"Cache clear will process {datasources.Count} datasource(s)");
             }
 
-            // Collect all valid cache paths with their directory counts
-            var validCachePaths = new List<(string Name, string Path, int DirCount)>();
+            // Collect all valid cache paths with their directory counts and, for the ones the Rust
+            // cleaner will walk, the key scheme it needs to recognise protected games' files.
+            var validCachePaths = new List<(string Name, string Path, int DirCount, string? KeyScheme)>();
             foreach (var ds in datasources)
             {
                 if (!Directory.Exists(ds.CachePath))
@@ -288,7 +292,29 @@ public class CacheClearingService : ScheduledBackgroundService
 
                 if (cacheSubdirs.Any())
                 {
-                    validCachePaths.Add((ds.Name, ds.CachePath, cacheSubdirs.Count));
+                    string keyScheme;
+                    try
+                    {
+                        keyScheme = _capabilityService.GetKeySchemeWireValue(ds);
+                    }
+                    catch (InvalidOperationException ex)
+                    {
+                        // Without the key scheme the cleaner cannot tell which files belong to
+                        // protected games, so refuse before anything is deleted.
+                        var error = $"Cannot clear datasource {ds.Name}: {ex.Message}";
+                        _logger.LogWarning("Cache clear operation {OperationId} failed: {Error}", operationId, error);
+
+                        // Terminal CacheClearingComplete (failed) is emitted by the onTerminalEmit closure.
+                        _operationTracker.CompleteOperation(operationId, success: false, error: error);
+                        _currentTrackerOperationId = null;
+
+                        await ReportProgressAsync(operationId);
+                        SaveOperationToState(trackerKey, operationId);
+
+                        return;
+                    }
+
+                    validCachePaths.Add((ds.Name, ds.CachePath, cacheSubdirs.Count, keyScheme));
                     _logger.LogInformation($"Datasource {ds.Name}: {cacheSubdirs.Count} cache directories at {ds.CachePath}");
                 }
                 else
@@ -300,7 +326,7 @@ public class CacheClearingService : ScheduledBackgroundService
                     // deliberately refuses an empty root because an unmounted cache looks the
                     // same, and failing the whole clear here left rows from an earlier wipe
                     // permanently unreconciled). Missing paths above still fail out.
-                    validCachePaths.Add((ds.Name, ds.CachePath, 0));
+                    validCachePaths.Add((ds.Name, ds.CachePath, 0, null));
                     _logger.LogInformation($"Datasource {ds.Name}: cache already empty at {ds.CachePath}; clear will reconcile the database only");
                 }
             }
@@ -373,7 +399,7 @@ public class CacheClearingService : ScheduledBackgroundService
             // Process each datasource cache path sequentially
             for (var dsIndex = 0; dsIndex < validCachePaths.Count; dsIndex++)
             {
-                var (dsName, cachePath, dirCount) = validCachePaths[dsIndex];
+                var (dsName, cachePath, dirCount, keyScheme) = validCachePaths[dsIndex];
 
                 // Already-empty root: nothing for the Rust cleaner to delete. The datasource
                 // stays in validCachePaths so the reconciliation after this loop covers it.
@@ -407,7 +433,9 @@ public class CacheClearingService : ScheduledBackgroundService
                 // Build arguments - Rust auto-detects optimal thread count. --progress enables
                 // cache_clear.rs's live stdout progress events, which the hybrid callback below
                 // waits on; without it ProgressReporter.is_enabled() is false and no events flow.
-                var arguments = $"\"{cachePath}\" \"{progressFile}\" {_deleteMode.ToWireString()} --progress";
+                // --key-scheme matches the removal callers: cache_clear only finds protected
+                // games' files under the scheme the datasource was filled with.
+                var arguments = $"\"{cachePath}\" \"{progressFile}\" {_deleteMode.ToWireString()} --progress --key-scheme {keyScheme}";
 
                 var startInfo = _rustProcessHelper.CreateProcessStartInfo(
                     rustBinaryPath,
@@ -709,6 +737,7 @@ public class CacheClearingService : ScheduledBackgroundService
             {
                 var invalidated = await InvalidateCachedDetectionResultsCoreAsync(
                     context,
+                    keepProtectedGames: false,
                     cancellationToken);
 
                 await transaction.CommitAsync(cancellationToken);
@@ -726,7 +755,8 @@ public class CacheClearingService : ScheduledBackgroundService
     /// Reconciles a cache clear that the application itself completed successfully. Unlike the
     /// generic eviction scan, this path can trust an empty cache root because the application
     /// performed the deletion. Active and zero-byte Downloads remain untouched: they either may
-    /// be writing new cache data now or never proved that cache content existed.
+    /// be writing new cache data now or never proved that cache content existed. Protected games'
+    /// files survive the clear, so their Downloads and game detections are left as they are.
     /// </summary>
     internal static async Task<(
         int DownloadsEvicted,
@@ -762,18 +792,30 @@ public class CacheClearingService : ScheduledBackgroundService
                 cancellationToken);
             try
             {
-                var downloadsEvicted = await context.Downloads
-                    .Where(download =>
-                        !download.IsActive
-                        && !download.IsEvicted
-                        && (download.CacheHitBytes > 0 || download.CacheMissBytes > 0)
-                        && datasourceNames.Contains(download.Datasource.ToLower()))
-                    .ExecuteUpdateAsync(
-                        setters => setters.SetProperty(download => download.IsEvicted, true),
-                        cancellationToken);
+                // protected_games belongs to the Rust processor and only exists once a game has
+                // been protected; cache_clear skips the files of every game listed in it.
+                var hasProtectedGames = await context.Database
+                    .SqlQueryRaw<bool>("SELECT to_regclass('protected_games') IS NOT NULL AS \"Value\"")
+                    .SingleAsync(cancellationToken);
+
+                var downloadsEvicted = hasProtectedGames
+                    ? await context.Database.ExecuteSqlRawAsync(
+                        EvictUnprotectedDownloadsSql,
+                        [datasourceNames],
+                        cancellationToken)
+                    : await context.Downloads
+                        .Where(download =>
+                            !download.IsActive
+                            && !download.IsEvicted
+                            && (download.CacheHitBytes > 0 || download.CacheMissBytes > 0)
+                            && datasourceNames.Contains(download.Datasource.ToLower()))
+                        .ExecuteUpdateAsync(
+                            setters => setters.SetProperty(download => download.IsEvicted, true),
+                            cancellationToken);
 
                 var invalidated = await InvalidateCachedDetectionResultsCoreAsync(
                     context,
+                    hasProtectedGames,
                     cancellationToken);
 
                 await transaction.CommitAsync(cancellationToken);
@@ -798,11 +840,16 @@ public class CacheClearingService : ScheduledBackgroundService
         int CorruptionCandidates,
         int CorruptionScans)> InvalidateCachedDetectionResultsCoreAsync(
         AppDbContext context,
+        bool keepProtectedGames,
         CancellationToken cancellationToken)
     {
         // Direct DbContext deletes are deliberate: cache clear wipes whole detection tables,
         // not the load/upsert flow GameCacheDetectionDataService owns.
-        var games = await context.CachedGameDetections.ExecuteDeleteAsync(cancellationToken);
+        var games = keepProtectedGames
+            ? await context.Database.ExecuteSqlRawAsync(
+                DeleteUnprotectedGameDetectionsSql,
+                cancellationToken)
+            : await context.CachedGameDetections.ExecuteDeleteAsync(cancellationToken);
         var services = await context.CachedServiceDetections.ExecuteDeleteAsync(cancellationToken);
         var (corruptionCandidates, corruptionScans) =
             await DatabaseService.DeleteCachedCorruptionEvidenceAsync(
@@ -815,6 +862,39 @@ public class CacheClearingService : ScheduledBackgroundService
         return (games, services, corruptionCandidates, corruptionScans);
     }
 
+    /// <summary>
+    /// The eviction above, minus protected games' Downloads. The protection test is the one the
+    /// Rust removal paths apply (protected_games::download_is_protected); {0} is the lowercased
+    /// datasource names.
+    /// </summary>
+    private const string EvictUnprotectedDownloadsSql =
+        """
+        UPDATE "Downloads" d SET "IsEvicted" = TRUE
+        WHERE NOT d."IsActive"
+          AND NOT d."IsEvicted"
+          AND (d."CacheHitBytes" > 0 OR d."CacheMissBytes" > 0)
+          AND LOWER(d."Datasource") = ANY({0})
+          AND NOT EXISTS (SELECT 1 FROM protected_games pg
+              WHERE pg.steam_app_id = d."GameAppId"
+                 OR pg.epic_app_id = d."EpicAppId"
+                 OR (d."GameAppId" IS NULL AND d."EpicAppId" IS NULL
+                     AND pg.service = LOWER(d."Service")
+                     AND pg.game_name = d."GameName"))
+        """;
+
+    /// <summary>
+    /// Every game detection except those of protected games, matched on the same three
+    /// identities.
+    /// </summary>
+    private const string DeleteUnprotectedGameDetectionsSql =
+        """
+        DELETE FROM "CachedGameDetections" c
+        WHERE NOT EXISTS (SELECT 1 FROM protected_games pg
+            WHERE pg.steam_app_id = c."GameAppId"
+               OR pg.epic_app_id = c."EpicAppId"
+               OR (pg.service = LOWER(c."Service") AND pg.game_name = c."GameName"))
+        """;
+
     internal static async Task InvalidateStructuralCorruptionStateAsync(
         AppDbContext dbContext,
         IPathResolver pathResolver,
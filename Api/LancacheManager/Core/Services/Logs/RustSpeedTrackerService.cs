@@ -341,9 +341,10 @@ public class RustSpeedTrackerService : ScheduledBackgroundService
                 // each directory (the monolithic cachelog access.log AND per-service bare-metal
                 // *-access.log files), so any datasource whose scheme supports live speed is passed its
                 // directory. Datasources with no single trustworthy layout (Unknown/Mixed) are skipped.
+                // Each is passed as --datasource <name>=<dir> so snapshot rows carry the datasource name.
                 var logDirs = datasources
                     .Where(d => d.Enabled && _capabilityService.GetCapabilities(d).CanTrackLiveSpeed)
-                    .Select(d => $"\"{d.LogPath}\"")
+                    .Select(d => $"--datasource \"{d.Name}={d.LogPath}\"")
                     .ToList();
 
                 if (logDirs.Count == 0)
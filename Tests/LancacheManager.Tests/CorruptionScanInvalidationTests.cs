@@ -299,6 +299,78 @@ public sealed class CorruptionScanInvalidationTests
         }
     }
 
+    /// <summary>
+    /// cache_clear leaves protected games' files in place, so reconciling the clear must not
+    /// evict their Downloads or drop their game detections.
+    /// </summary>
+    [Fact]
+    public async Task CacheClearing_SuccessKeepsProtectedGamesAsync()
+    {
+        await using var database = await TestDatabase.CreateAsync();
+
+        await using (var setup = new AppDbContext(database.Options))
+        {
+            await setup.Database.ExecuteSqlRawAsync(
+                """
+                CREATE TABLE protected_games(
+                    id BIGSERIAL PRIMARY KEY,
+                    steam_app_id BIGINT NULL,
+                    epic_app_id TEXT NULL,
+                    service TEXT NULL,
+                    game_name TEXT NULL);
+                INSERT INTO protected_games(steam_app_id) VALUES (730);
+                """);
+            var protectedDownload = CreateDownload("protected", "Default", cacheHitBytes: 1024);
+            protectedDownload.GameAppId = 730;
+            var unprotectedDownload = CreateDownload("unprotected", "Default", cacheHitBytes: 1024);
+            unprotectedDownload.GameAppId = 440;
+            setup.Downloads.AddRange(protectedDownload, unprotectedDownload);
+            setup.CachedGameDetections.AddRange(
+                new CachedGameDetection
+                {
+                    GameAppId = 730,
+                    GameName = "Protected Game",
+                    Service = "steam",
+                    CacheFilesFound = 1,
+                    TotalSizeBytes = 1024,
+                    LastDetectedUtc = DateTime.UtcNow,
+                    CreatedAtUtc = DateTime.UtcNow
+                },
+                new CachedGameDetection
+                {
+                    GameAppId = 440,
+                    GameName = "Unprotected Game",
+                    Service = "steam",
+                    CacheFilesFound = 1,
+                    TotalSizeBytes = 1024,
+                    LastDetectedUtc = DateTime.UtcNow,
+                    CreatedAtUtc = DateTime.UtcNow
+                });
+            await setup.SaveChangesAsync();
+        }
+
+        await using (var context = new AppDbContext(database.Options))
+        {
+            var result = await CacheClearingService.ReconcileSuccessfulCacheClearAsync(
+                context,
+                ["Default"],
+                CancellationToken.None);
+
+            Assert.Equal(1, result.DownloadsEvicted);
+            Assert.Equal(1, result.Games);
+        }
+
+        await using (var assertContext = new AppDbContext(database.Options))
+        {
+            var states = await assertContext.Downloads
+                .ToDictionaryAsync(download => download.ClientIp, download => download.IsEvicted);
+            Assert.False(states["protected"]);
+            Assert.True(states["unprotected"]);
+            var remaining = Assert.Single(await assertContext.CachedGameDetections.ToListAsync());
+            Assert.Equal(730, remaining.GameAppId);
+        }
+    }
+
     [Fact]
     public async Task CacheClearing_ReconciliationRollsBackEvictionFlagsWhenInvalidationFailsAsync()
     {
@@ -78,7 +78,8 @@ public sealed class SystemConfigVisibilityTests : IDisposable
             datasourceService,
             operationTracker: null!,
             dbContextFactory: null!,
-            gameCacheDetectionService: null!);
+            gameCacheDetectionService: null!,
+            capabilityService);
         var cacheManagementService = new CacheManagementService(
             configuration,
             NullLogger<CacheManagementService>.Instance,
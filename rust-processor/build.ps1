@@ -27,7 +27,21 @@ $Binaries = @(
     "cache_service_remove",    # Remove service from cache (was service_remover)
     "cache_eviction_scan",     # Scan cache and mark evicted downloads
     "cache_purge_log_entries", # Bulk-purge access.log entries for evicted games
-    "db_reset"                 # Reset database (was database_reset)
+    "cache_inventory",         # Index cache files by KEY header; size/orphan/game reports
+    "cache_eviction_forecast", # Forecast LRU/inactive evictions per game
+    "cache_simulate",          # Replay history through cache models at several sizes
+    "cache_cold_games",        # Rank cached games by cache value, least valuable first
+    "cache_policy",            # Retention rules enforced through the removal bins
+    "cache_protect",           # Games no removal may touch
+    "cache_quarantine",        # Quarantined removal batches
+    "cache_export",            # Offline seeding export
+    "cache_import",            # Offline seeding import
+    "cache_rekey",             # Key-scheme migration
+    "db_reset",                # Reset database (was database_reset)
+    "db_rebuild",              # Regenerate Downloads/stats from LogEntries
+    "db_check",                # Check (and optionally repair) database invariants
+    "db_restore_backup",       # Restore a pre-flight database backup archive
+    "db_maintain"              # Bloat report, VACUUM and REINDEX per table
 )
 
 function Build-ForTarget {
@@ -0,0 +1,631 @@
+//! Portable archives of cache slices, for seeding another lancache offline.
+//!
+//! `cache_export` copies the slices a game's removal head would plan into an archive;
+//! `cache_import` places them into another cache. An archive is a plain directory, so it fits on
+//! any USB drive filesystem:
+//!
+//! ```text
+//! <archive>/manifest.json
+//! <archive>/slices/<last 2 hex>/<md5 of the key>
+//! ```
+//!
+//! A slice is only taken on the strength of its embedded `KEY:` header: the key must hash to the
+//! file name and follow the archive's key scheme, so the importer needs nothing but the key to
+//! place it. The manifest records each slice's key, size, SHA-256 and mode, and is written last;
+//! an archive without one is refused. The importer checks size, hash and key again on the copy
+//! before hard-linking it into place, and never replaces a file the target cache already holds.
+//! A slice of the other key scheme is rekeyed on the way in, as `rekey_core` rekeys a cache file;
+//! one whose key has no equivalent there is skipped.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::cache_structural_scanner;
+use crate::cache_utils::{self, CacheKeyScheme, RekeyBlocker};
+use crate::progress_utils;
+use crate::rekey_core;
+use crate::removal_plan::PlanTarget;
+
+pub const ARCHIVE_VERSION: u32 = 1;
+pub const MANIFEST_FILE: &str = "manifest.json";
+const SLICES_DIR: &str = "slices";
+
+/// Mode of an imported slice whose archive did not record one (an export from Windows).
+const DEFAULT_FILE_MODE: u32 = 0o600;
+
+/// One cache file in an archive.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchivedSlice {
+    /// The embedded `KEY:` header; its md5 is the file name.
+    pub key: String,
+    pub name: String,
+    pub bytes: u64,
+    pub sha256: String,
+    /// Unix permission bits of the exported file.
+    pub mode: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchiveManifest {
+    pub version: u32,
+    pub created_at: String,
+    pub target: PlanTarget,
+    /// `monolithic` | `bare_metal`
+    pub key_scheme: String,
+    pub source_cache_dir: PathBuf,
+    pub slices: Vec<ArchivedSlice>,
+    pub total_bytes: u64,
+}
+
+/// The key a cache file proves it holds: its `KEY:` header, when that follows `scheme` and hashes
+/// to the file name.
+pub fn verified_slice_key(path: &Path, scheme: CacheKeyScheme) -> Option<String> {
+    let key = cache_utils::read_cache_file_key(path)?;
+    let name = path.file_name()?.to_str()?;
+    let parts = cache_utils::split_cache_key(&key)?;
+    (parts.scheme == scheme && cache_utils::calculate_md5(&key) == name).then_some(key)
+}
+
+fn slice_path(archive_dir: &Path, name: &str) -> PathBuf {
+    archive_dir
+        .join(SLICES_DIR)
+        .join(&name[name.len() - 2..])
+        .join(name)
+}
+
+/// Copies `from` to a new file `to`, returning the bytes copied and their SHA-256.
+fn copy_hashed(from: &Path, to: &Path) -> io::Result<(u64, String)> {
+    let mut source = fs::File::open(from)?;
+    let mut target = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(to)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1 << 20];
+    let mut bytes = 0u64;
+    loop {
+        let read = match source.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        };
+        hasher.update(&buffer[..read]);
+        target.write_all(&buffer[..read])?;
+        bytes += read as u64;
+    }
+    target.sync_all()?;
+    Ok((bytes, format!("{:x}", hasher.finalize())))
+}
+
+#[cfg(unix)]
+pub(crate) fn file_mode(metadata: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn file_mode(_metadata: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+/// An archive being written by `cache_export`.
+pub struct ArchiveWriter {
+    dir: PathBuf,
+    scheme: CacheKeyScheme,
+    names: HashSet<String>,
+    manifest: ArchiveManifest,
+}
+
+impl ArchiveWriter {
+    /// Starts an archive in `dir`, which must be missing or empty.
+    pub fn create(
+        dir: &Path,
+        target: PlanTarget,
+        scheme: CacheKeyScheme,
+        source_cache_dir: &Path,
+    ) -> Result<Self> {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create archive directory {}", dir.display()))?;
+        if fs::read_dir(dir)?.next().is_some() {
+            bail!(
+                "archive directory {} is not empty; export into a new directory",
+                dir.display()
+            );
+        }
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            scheme,
+            names: HashSet::new(),
+            manifest: ArchiveManifest {
+                version: ARCHIVE_VERSION,
+                created_at: progress_utils::current_timestamp(),
+                target,
+                key_scheme: scheme.as_config_str().to_string(),
+                source_cache_dir: source_cache_dir.to_path_buf(),
+                slices: Vec::new(),
+                total_bytes: 0,
+            },
+        })
+    }
+
+    /// Copies one cache file in. `Ok(false)` when its `KEY:` header does not verify; a file already
+    /// in the archive counts as added.
+    pub fn add(&mut self, path: &Path) -> Result<bool> {
+        let Some(key) = verified_slice_key(path, self.scheme) else {
+            return Ok(false);
+        };
+        let name = cache_utils::calculate_md5(&key);
+        if self.names.contains(&name) {
+            return Ok(true);
+        }
+        let metadata =
+            fs::metadata(path).with_context(|| format!("failed to inspect {}", path.display()))?;
+        let target = slice_path(&self.dir, &name);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+        let (bytes, sha256) = copy_hashed(path, &target)
+            .with_context(|| format!("failed to copy {} into the archive", path.display()))?;
+        self.manifest.total_bytes += bytes;
+        self.manifest.slices.push(ArchivedSlice {
+            key,
+            name: name.clone(),
+            bytes,
+            sha256,
+            mode: file_mode(&metadata),
+        });
+        self.names.insert(name);
+        Ok(true)
+    }
+
+    /// Writes the manifest, which makes the archive importable.
+    pub fn finish(mut self) -> Result<ArchiveManifest> {
+        self.manifest.slices.sort_by(|a, b| a.name.cmp(&b.name));
+        progress_utils::write_progress_json(&self.dir.join(MANIFEST_FILE), &self.manifest)
+            .context("failed to write the archive manifest")?;
+        Ok(self.manifest)
+    }
+}
+
+/// An archive being read by `cache_import`.
+#[derive(Debug, Clone)]
+pub struct Archive {
+    pub dir: PathBuf,
+    pub manifest: ArchiveManifest,
+}
+
+impl Archive {
+    pub fn open(dir: &Path) -> Result<Self> {
+        let path = dir.join(MANIFEST_FILE);
+        let bytes = fs::read(&path).with_context(|| {
+            format!(
+                "failed to read {}; an export that did not finish has no manifest",
+                path.display()
+            )
+        })?;
+        let manifest: ArchiveManifest = serde_json::from_slice(&bytes)
+            .with_context(|| format!("{} is not a cache archive manifest", path.display()))?;
+        if manifest.version != ARCHIVE_VERSION {
+            bail!(
+                "unsupported cache archive version {} (this build reads version {})",
+                manifest.version,
+                ARCHIVE_VERSION
+            );
+        }
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            manifest,
+        })
+    }
+}
+
+/// Owner and directory mode given to what an import creates.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Placement {
+    pub owner: Option<(u32, u32)>,
+    pub dir_mode: Option<u32>,
+}
+
+impl Placement {
+    /// The cache root's owner and mode, which nginx's own files and directories carry.
+    #[cfg(unix)]
+    pub fn of_cache_dir(cache_dir: &Path) -> Result<Self> {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        let metadata = fs::metadata(cache_dir)
+            .with_context(|| format!("failed to inspect {}", cache_dir.display()))?;
+        Ok(Self {
+            owner: Some((metadata.uid(), metadata.gid())),
+            dir_mode: Some(metadata.permissions().mode() & 0o7777),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn of_cache_dir(_cache_dir: &Path) -> Result<Self> {
+        Ok(Self::default())
+    }
+
+    #[cfg(unix)]
+    pub(crate) fn apply(&self, path: &Path, mode: Option<u32>) -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        if let Some((uid, gid)) = self.owner {
+            std::os::unix::fs::chown(path, Some(uid), Some(gid))?;
+        }
+        if let Some(mode) = mode {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn apply(&self, _path: &Path, _mode: Option<u32>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// What importing one slice did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportDisposition {
+    Imported,
+    /// The target cache already holds the same key.
+    AlreadyCached,
+    /// The target cache holds another key under the same name; left alone.
+    Conflict,
+    /// The archived copy failed its size, hash or key check.
+    Corrupt,
+    /// The key has no equivalent in the target cache's key scheme; skipped.
+    Blocked(RekeyBlocker),
+    /// The key has an equivalent, but this build cannot rewrite the slice's nginx header for it;
+    /// skipped.
+    Unconvertible,
+}
+
+/// Places one archived slice under `cache_dir` (nginx `levels=2:2`), keyed for `scheme`.
+pub fn import_slice(
+    archive: &Archive,
+    slice: &ArchivedSlice,
+    cache_dir: &Path,
+    placement: &Placement,
+    scheme: CacheKeyScheme,
+) -> Result<ImportDisposition> {
+    if cache_utils::parse_cache_file_digest(&slice.name).is_none()
+        || cache_utils::calculate_md5(&slice.key) != slice.name
+    {
+        return Ok(ImportDisposition::Corrupt);
+    }
+    let key = match cache_utils::equivalent_cache_key(&slice.key, scheme) {
+        Ok(key) => key,
+        Err(blocker) => return Ok(ImportDisposition::Blocked(blocker)),
+    };
+    let destination =
+        cache_utils::cache_path_for_digest(cache_dir, cache_utils::calculate_md5_digest(&key));
+    if fs::symlink_metadata(&destination).is_ok() {
+        return Ok(existing_disposition(&destination, &key));
+    }
+
+    let parent = destination
+        .parent()
+        .context("cache slice path has no parent")?;
+    create_level_dirs(cache_dir, parent, placement)?;
+    let staging = parent.join(format!(".{}.import", slice.name));
+    let _ = fs::remove_file(&staging);
+    let source = slice_path(&archive.dir, &slice.name);
+    let (bytes, sha256) = copy_hashed(&source, &staging)
+        .with_context(|| format!("failed to copy {} into the cache", source.display()))?;
+    if bytes != slice.bytes
+        || sha256 != slice.sha256
+        || cache_utils::cache_file_key_matches(&staging, &slice.key) != Some(true)
+    {
+        let _ = fs::remove_file(&staging);
+        return Ok(ImportDisposition::Corrupt);
+    }
+    let staging = if key == slice.key {
+        staging
+    } else {
+        let rekeyed = parent.join(format!(
+            ".{}.{}.import",
+            cache_utils::calculate_md5(&key),
+            slice.name
+        ));
+        let _ = fs::remove_file(&rekeyed);
+        let written = write_rekeyed_copy(&staging, &slice.key, &key, &rekeyed);
+        let _ = fs::remove_file(&staging);
+        match written {
+            Ok(true) => rekeyed,
+            Ok(false) => return Ok(ImportDisposition::Unconvertible),
+            Err(error) => {
+                let _ = fs::remove_file(&rekeyed);
+                return Err(error)
+                    .with_context(|| format!("failed to write {}", rekeyed.display()));
+            }
+        }
+    };
+    // Only permission bits are restored: a manifest is not trusted to set setuid or setgid.
+    let mode = slice.mode.map_or(DEFAULT_FILE_MODE, |mode| mode & 0o777);
+    if let Err(error) = placement.apply(&staging, Some(mode)) {
+        let _ = fs::remove_file(&staging);
+        return Err(error)
+            .with_context(|| format!("failed to set the owner of {}", staging.display()));
+    }
+    // A hard link never replaces: if nginx or another import took the path meanwhile, the file
+    // there is compared instead.
+    let linked = fs::hard_link(&staging, &destination);
+    let _ = fs::remove_file(&staging);
+    match linked {
+        Ok(()) => Ok(ImportDisposition::Imported),
+        Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {
+            Ok(existing_disposition(&destination, &key))
+        }
+        Err(error) => {
+            Err(error).with_context(|| format!("failed to place {}", destination.display()))
+        }
+    }
+}
+
+/// Writes the verified copy at `copy`, keyed `key`, to `rekeyed` under `new_key`. False when its
+/// header cannot be rewritten.
+fn write_rekeyed_copy(copy: &Path, key: &str, new_key: &str, rekeyed: &Path) -> Result<bool> {
+    let mut source = fs::File::open(copy)?;
+    let Some(header) = cache_structural_scanner::rekey_cache_header(&mut source, key, new_key)?
+    else {
+        return Ok(false);
+    };
+    rekey_core::write_rekeyed(&mut source, &header, rekeyed)?;
+    Ok(true)
+}
+
+/// What an import finds when the slice's path is already taken.
+fn existing_disposition(destination: &Path, key: &str) -> ImportDisposition {
+    match cache_utils::cache_file_key_matches(destination, key) {
+        Some(true) => ImportDisposition::AlreadyCached,
+        _ => ImportDisposition::Conflict,
+    }
+}
+
+/// Creates the two hash-level directories above a slice, owned like the cache's own.
+pub(crate) fn create_level_dirs(
+    cache_dir: &Path,
+    parent: &Path,
+    placement: &Placement,
+) -> Result<()> {
+    let Ok(relative) = parent.strip_prefix(cache_dir) else {
+        bail!("{} is not under {}", parent.display(), cache_dir.display());
+    };
+    let mut dir = cache_dir.to_path_buf();
+    for component in relative.components() {
+        dir.push(component);
+        match fs::create_dir(&dir) {
+            Ok(()) => placement
+                .apply(&dir, placement.dir_mode)
+                .with_context(|| format!("failed to set the owner of {}", dir.display()))?,
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(error) => {
+                return Err(error).with_context(|| format!("failed to create {}", dir.display()))
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "steam/depot/1/chunk/abcbytes=0-1048575";
+
+    fn write_slice(cache_dir: &Path, key: &str, body: &[u8]) -> PathBuf {
+        let path =
+            cache_utils::cache_path_for_digest(cache_dir, cache_utils::calculate_md5_digest(key));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let mut contents = format!("\0\nKEY: {key}\n").into_bytes();
+        contents.extend_from_slice(body);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn export(source: &Path, archive_dir: &Path, paths: &[PathBuf]) -> ArchiveManifest {
+        let mut writer = ArchiveWriter::create(
+            archive_dir,
+            PlanTarget::Steam { game_app_id: 1 },
+            CacheKeyScheme::Monolithic,
+            source,
+        )
+        .unwrap();
+        for path in paths {
+            writer.add(path).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn exported_slices_land_at_their_key_path_once() {
+        let temp = tempfile::tempdir().unwrap();
+        let source = temp.path().join("source");
+        let slice = write_slice(&source, KEY, b"body");
+        let manifest = export(
+            &source,
+            &temp.path().join("archive"),
+            &[slice.clone(), slice],
+        );
+        assert_eq!(manifest.slices.len(), 1);
+        assert_eq!(manifest.slices[0].key, KEY);
+
+        let target = temp.path().join("target");
+        fs::create_dir_all(&target).unwrap();
+        let archive = Archive::open(&temp.path().join("archive")).unwrap();
+        let placement = Placement::of_cache_dir(&target).unwrap();
+        assert_eq!(
+            import_slice(
+                &archive,
+                &manifest.slices[0],
+                &target,
+                &placement,
+                CacheKeyScheme::Monolithic
+            )
+            .unwrap(),
+            ImportDisposition::Imported
+        );
+        let placed =
+            cache_utils::cache_path_for_digest(&target, cache_utils::calculate_md5_digest(KEY));
+        assert_eq!(
+            cache_utils::read_cache_file_key(&placed).as_deref(),
+            Some(KEY)
+        );
+        assert_eq!(
+            import_slice(
+                &archive,
+                &manifest.slices[0],
+                &target,
+                &placement,
+                CacheKeyScheme::Monolithic
+            )
+            .unwrap(),
+            ImportDisposition::AlreadyCached
+        );
+    }
+
+    #[test]
+    fn slices_whose_key_does_not_match_their_name_are_not_exported() {
+        let temp = tempfile::tempdir().unwrap();
+        let source = temp.path().join("source");
+        let slice = write_slice(&source, KEY, b"body");
+        let renamed = slice.with_file_name("0".repeat(32));
+        fs::rename(&slice, &renamed).unwrap();
+        let manifest = export(&source, &temp.path().join("archive"), &[renamed]);
+        assert!(manifest.slices.is_empty());
+        assert!(ArchiveWriter::create(
+            &temp.path().join("archive"),
+            PlanTarget::Steam { game_app_id: 1 },
+            CacheKeyScheme::Monolithic,
+            &source,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn a_damaged_archive_copy_is_never_placed() {
+        let temp = tempfile::tempdir().unwrap();
+        let source = temp.path().join("source");
+        let slice = write_slice(&source, KEY, b"body");
+        let archive_dir = temp.path().join("archive");
+        let manifest = export(&source, &archive_dir, &[slice]);
+        let archived = slice_path(&archive_dir, &manifest.slices[0].name);
+        let mut contents = fs::read(&archived).unwrap();
+        *contents.last_mut().unwrap() ^= 1;
+        fs::write(&archived, contents).unwrap();
+
+        let target = temp.path().join("target");
+        fs::create_dir_all(&target).unwrap();
+        let archive = Archive::open(&archive_dir).unwrap();
+        assert_eq!(
+            import_slice(
+                &archive,
+                &manifest.slices[0],
+                &target,
+                &Placement::default(),
+                CacheKeyScheme::Monolithic
+            )
+            .unwrap(),
+            ImportDisposition::Corrupt
+        );
+        let parent =
+            cache_utils::cache_path_for_digest(&target, cache_utils::calculate_md5_digest(KEY));
+        assert_eq!(fs::read_dir(parent.parent().unwrap()).unwrap().count(), 0);
+    }
+
+    #[cfg(all(unix, target_pointer_width = "64"))]
+    #[test]
+    fn slices_of_the_other_key_scheme_are_rekeyed_or_skipped() {
+        use crate::cache_structural_scanner::write_linux_v5_test_fixture;
+
+        const BLIZZARD: &str = "blizzard/tpr/wow/data/ab/cdbytes=0-1048575";
+        const HEADERS: &[u8] =
+            b"HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 0-3/4\r\n\r\n";
+        let temp = tempfile::tempdir().unwrap();
+        let source = temp.path().join("source");
+        let blizzard = write_linux_v5_test_fixture(&source, BLIZZARD.as_bytes(), HEADERS, b"body");
+        let steam = write_linux_v5_test_fixture(&source, KEY.as_bytes(), HEADERS, b"body");
+        let archive_dir = temp.path().join("archive");
+        let manifest = export(&source, &archive_dir, &[blizzard, steam]);
+        let archive = Archive::open(&archive_dir).unwrap();
+        let slice = |key: &str| {
+            manifest
+                .slices
+                .iter()
+                .find(|slice| slice.key == key)
+                .unwrap()
+        };
+
+        let target = temp.path().join("target");
+        fs::create_dir_all(&target).unwrap();
+        let placement = Placement::default();
+        let import = |key: &str| {
+            import_slice(
+                &archive,
+                slice(key),
+                &target,
+                &placement,
+                CacheKeyScheme::BareMetal,
+            )
+            .unwrap()
+        };
+        assert_eq!(import(BLIZZARD), ImportDisposition::Imported);
+        let bare_metal = format!("lancache-{BLIZZARD}");
+        let placed = cache_utils::cache_path_for_digest(
+            &target,
+            cache_utils::calculate_md5_digest(&bare_metal),
+        );
+        assert_eq!(
+            cache_utils::read_cache_file_key(&placed).as_deref(),
+            Some(bare_metal.as_str())
+        );
+        assert!(fs::read(&placed).unwrap().ends_with(b"body"));
+        assert_eq!(fs::read_dir(placed.parent().unwrap()).unwrap().count(), 1);
+        assert_eq!(import(BLIZZARD), ImportDisposition::AlreadyCached);
+        assert_eq!(
+            import(KEY),
+            ImportDisposition::Blocked(RekeyBlocker::SliceShapeDiffers)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn an_imported_slice_gets_only_the_permission_bits_of_its_recorded_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = tempfile::tempdir().unwrap();
+        let source = temp.path().join("source");
+        let slice = write_slice(&source, KEY, b"body");
+        let archive_dir = temp.path().join("archive");
+        let mut manifest = export(&source, &archive_dir, &[slice]);
+        manifest.slices[0].mode = Some(0o6755);
+
+        let target = temp.path().join("target");
+        fs::create_dir_all(&target).unwrap();
+        let archive = Archive::open(&archive_dir).unwrap();
+        assert_eq!(
+            import_slice(
+                &archive,
+                &manifest.slices[0],
+                &target,
+                &Placement::default(),
+                CacheKeyScheme::Monolithic
+            )
+            .unwrap(),
+            ImportDisposition::Imported
+        );
+        let placed =
+            cache_utils::cache_path_for_digest(&target, cache_utils::calculate_md5_digest(KEY));
+        let mode = fs::metadata(&placed).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o755);
+        assert_eq!(fs::read_dir(placed.parent().unwrap()).unwrap().count(), 1);
+    }
+}
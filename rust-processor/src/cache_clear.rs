@@ -21,6 +21,8 @@ use std::os::unix::ffi::OsStrExt;
 use lancache_processor::cache_utils;
 use lancache_processor::cancel;
 use lancache_processor::progress_events;
+use lancache_processor::protected_games::{self, ProtectedFiles};
+use lancache_processor::quarantine::{DeferredCleanup, Quarantine, QuarantineArgs};
 use cache_utils::{detect_filesystem_type, FilesystemType};
 use progress_events::ProgressReporter;
 
@@ -42,6 +44,20 @@ struct Args {
     /// Number of threads to use
     thread_count: Option<usize>,
 
+    /// Cache-key recipe of the target datasource, used to find the files of protected games:
+    /// "monolithic" (default) | "bare_metal"
+    #[arg(
+        long = "key-scheme",
+        default_value = "monolithic",
+        value_parser = ["monolithic", "bare_metal"]
+    )]
+    key_scheme: String,
+
+    // With a quarantine directory every hex directory is moved into one batch, whatever the
+    // deletion mode.
+    #[command(flatten)]
+    quarantine: QuarantineArgs,
+
     /// Emit JSON progress events to stdout
     #[arg(short, long)]
     progress: bool,
@@ -107,13 +123,22 @@ fn write_progress(progress_path: &Path, progress: &ProgressData) -> Result<()> {
     progress_utils::write_progress_json(progress_path, progress)
 }
 
+/// How many kept paths the completion context lists; the count is always exact.
+const PROTECTED_FILES_IN_CONTEXT: usize = 100;
+
 fn is_hex(value: &str) -> bool {
     value.len() == 2 && value.chars().all(|c| c.is_ascii_hexdigit())
 }
 
+/// Preserve-mode sweep. Files in `protected` are left in place and pushed onto `kept`;
+/// their directories then fail the best-effort `remove_dir` and stay too. With a quarantine the
+/// other files are moved into it instead of deleted.
 fn delete_directory_contents(
     dir_path: &Path,
     files_counter: &AtomicU64,
+    protected: &ProtectedFiles,
+    kept: &Mutex<Vec<PathBuf>>,
+    quarantine: Option<&Quarantine>,
 ) -> Result<()> {
     if !dir_path.exists() {
         return Ok(());
@@ -133,6 +158,9 @@ fn delete_directory_contents(
         root: &Path,
         dir: &Path,
         files_counter: &AtomicU64,
+        protected: &ProtectedFiles,
+        kept: &Mutex<Vec<PathBuf>>,
+        quarantine: Option<&Quarantine>,
     ) -> Result<()> {
         if dir.is_dir() {
             for entry_result in fs::read_dir(dir)? {
@@ -155,7 +183,7 @@ fn delete_directory_contents(
                 if path.is_dir() {
                     match cache_utils::safe_path_under_root(root, &path) {
                         Ok(_) => {
-                            delete_recursive(root, &path, files_counter)?;
+                            delete_recursive(root, &path, files_counter, protected, kept, quarantine)?;
                             // Try to remove the empty directory
                             let _ = fs::remove_dir(&path);
                         }
@@ -166,10 +194,16 @@ fn delete_directory_contents(
                     }
                 } else {
                     match cache_utils::safe_path_under_root(root, &path) {
+                        Ok(_) if protected.contains(&path) => {
+                            kept.lock().unwrap_or_else(|err| err.into_inner()).push(path);
+                        }
                         Ok(_) => {
                             // Just count and delete - NO metadata read for speed
                             files_counter.fetch_add(1, Ordering::Relaxed);
-                            let _ = fs::remove_file(&path);
+                            let _ = match quarantine {
+                                Some(quarantine) => quarantine.take(&path),
+                                None => fs::remove_file(&path),
+                            };
                         }
                         Err(e) => {
                             eprintln!("skipping unsafe path {}: {}", path.display(), e);
@@ -182,11 +216,48 @@ fn delete_directory_contents(
         Ok(())
     }
 
-    delete_recursive(&root, &root, files_counter)?;
+    delete_recursive(&root, &root, files_counter, protected, kept, quarantine)?;
 
     Ok(())
 }
 
+/// Quarantine-mode counterpart of `delete_directory_full`: one rename moves the whole hex
+/// directory into the batch.
+fn quarantine_directory(
+    dir_path: &Path,
+    files_counter: &AtomicU64,
+    quarantine: &Quarantine,
+) -> Result<()> {
+    fn count_files(dir: &Path) -> u64 {
+        fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| match entry.file_type() {
+                        Ok(ft) if ft.is_dir() => count_files(&entry.path()),
+                        Ok(ft) if ft.is_file() => 1,
+                        _ => 0,
+                    })
+                    .sum()
+            })
+            .unwrap_or(0)
+    }
+    let file_count = count_files(dir_path);
+
+    match quarantine.take(dir_path) {
+        Ok(()) => {
+            files_counter.fetch_add(file_count, Ordering::Relaxed);
+            Ok(())
+        }
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(anyhow::anyhow!(
+            "Failed to quarantine directory {}: {}",
+            dir_path.display(),
+            err
+        )),
+    }
+}
+
 fn delete_directory_full(
     dir_path: &Path,
     files_counter: &AtomicU64,
@@ -419,7 +490,15 @@ fn get_available_bytes(_path: &Path) -> Result<u64> {
     Ok(0)
 }
 
-fn clear_cache(cache_path: &str, progress_path: &Path, thread_count: usize, delete_mode: &str, reporter: &Arc<ProgressReporter>) -> Result<(usize, usize)> {
+fn clear_cache(
+    cache_path: &str,
+    progress_path: &Path,
+    thread_count: usize,
+    delete_mode: &str,
+    key_scheme: cache_utils::CacheKeyScheme,
+    quarantine: &QuarantineArgs,
+    reporter: &Arc<ProgressReporter>,
+) -> Result<serde_json::Value> {
     let start_time = Instant::now();
     eprintln!("Starting cache clear operation...");
     eprintln!("Cache path: {}", cache_path);
@@ -459,6 +538,20 @@ fn clear_cache(cache_path: &str, progress_path: &Path, thread_count: usize, dele
         anyhow::bail!("{}", msg);
     }
 
+    // Hex dirs holding a protected game's files are swept file by file whatever the mode, so
+    // those files can be skipped; `full` and `rsync` cannot leave anything behind.
+    let protected = protected_games::load_files_blocking(cache_dir, key_scheme)?;
+    let protected_dirs = protected.level_one_dirs(cache_dir);
+    let kept_files = Mutex::new(Vec::<PathBuf>::new());
+    if !protected.is_empty() {
+        eprintln!(
+            "Keeping {} protected file(s) in {} directories",
+            protected.len(),
+            protected_dirs.len()
+        );
+    }
+    let quarantine = quarantine.open(cache_dir, "cache_clear")?;
+
     // Find all hex directories (00-ff)
     let hex_dirs: Vec<PathBuf> = fs::read_dir(cache_dir)?
         .filter_map(|entry| entry.ok())
@@ -611,10 +704,16 @@ fn clear_cache(cache_path: &str, progress_path: &Path, thread_count: usize, dele
 
         eprintln!("Processing directory {}", dir_name);
 
-        let result = match delete_mode {
-            "full" => delete_directory_full(dir, &total_files_deleted),
-            "rsync" => delete_directory_rsync(dir, &total_files_deleted),
-            _ => delete_directory_contents(dir, &total_files_deleted),
+        let result = if protected_dirs.contains(dir) {
+            delete_directory_contents(dir, &total_files_deleted, &protected, &kept_files, quarantine.as_ref())
+        } else if let Some(quarantine) = &quarantine {
+            quarantine_directory(dir, &total_files_deleted, quarantine)
+        } else {
+            match delete_mode {
+                "full" => delete_directory_full(dir, &total_files_deleted),
+                "rsync" => delete_directory_rsync(dir, &total_files_deleted),
+                _ => delete_directory_contents(dir, &total_files_deleted, &protected, &kept_files, None),
+            }
         };
 
         // Remove from active list
@@ -692,13 +791,36 @@ fn clear_cache(cache_path: &str, progress_path: &Path, thread_count: usize, dele
     eprintln!("  Bytes deleted: {} ({:.2} GB)", final_bytes, final_bytes as f64 / 1_073_741_824.0);
     eprintln!("  Time elapsed: {:.2}s", elapsed.as_secs_f64());
 
+    let mut kept_files = kept_files.into_inner().unwrap_or_else(|err| err.into_inner());
+    kept_files.sort();
+    if !kept_files.is_empty() {
+        eprintln!("  Protected files kept: {}", kept_files.len());
+        for path in &kept_files {
+            eprintln!("    {}", path.display());
+        }
+    }
+    let mut completed_context = json!({
+        "processed": final_dirs,
+        "totalDirs": total_dirs,
+        "activeCount": 0usize,
+        "protectedSkipped": kept_files.len(),
+        "protectedFiles": kept_files
+            .iter()
+            .take(PROTECTED_FILES_IN_CONTEXT)
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>(),
+    });
+    if let Some(quarantine) = quarantine {
+        completed_context["quarantineBatch"] = json!(quarantine.finish(DeferredCleanup::None)?.id);
+    }
+
     // Final progress
     let progress = ProgressData::new(
         false,
         100.0,
         "completed".to_string(),
         "signalr.cacheClear.progress".to_string(),
-        json!({ "processed": final_dirs, "totalDirs": total_dirs, "activeCount": 0usize }),
+        completed_context.clone(),
         final_dirs,
         total_dirs,
         final_bytes,
@@ -707,7 +829,7 @@ fn clear_cache(cache_path: &str, progress_path: &Path, thread_count: usize, dele
     );
     write_progress(progress_path, &progress)?;
 
-    Ok((final_dirs, total_dirs))
+    Ok(completed_context)
 }
 
 /// Determine optimal thread count based on delete mode, filesystem type, and available CPUs
@@ -802,11 +924,12 @@ fn main() -> anyhow::Result<()> {
 
     eprintln!("Thread count: {} (mode: {})", thread_count, delete_mode);
 
-    match clear_cache(cache_path, progress_path, thread_count, delete_mode, &reporter) {
-        Ok((final_dirs, total_dirs)) => {
+    let key_scheme = cache_utils::CacheKeyScheme::from_config_str(&args.key_scheme);
+    match clear_cache(cache_path, progress_path, thread_count, delete_mode, key_scheme, &args.quarantine, &reporter) {
+        Ok(completed_context) => {
             // Same final counts the last write_progress call persisted to the file -
             // no more hardcoded zeros on the stdout complete event.
-            reporter.emit_complete("signalr.cacheClear.progress", json!({ "processed": final_dirs, "totalDirs": total_dirs, "activeCount": 0usize }));
+            reporter.emit_complete("signalr.cacheClear.progress", completed_context);
             Ok(())
         }
         Err(e) => {
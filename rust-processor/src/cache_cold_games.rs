@@ -0,0 +1,193 @@
+//! Ranks cached games by how little their disk space is worth keeping.
+//!
+//! Detections, download history and the value score come from `cached_game_value`: what the
+//! cached copy has earned in HIT bytes and distinct clients, halved for every `--half-life-days`
+//! it has sat idle. Games are listed lowest score first with a running byte total, so the first
+//! rows are the gigabytes that cost least to give up.
+
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use clap::Parser;
+use serde::Serialize;
+use std::fs;
+
+use lancache_processor::cached_game_value::{self, days_between, value_score};
+use lancache_processor::db;
+use lancache_processor::progress_utils;
+
+#[derive(Parser, Debug)]
+#[command(name = "cache_cold_games")]
+#[command(about = "Scores cached games by cache value and lists the least valuable bytes first")]
+struct Args {
+    output_json: String,
+
+    /// Days of idleness that halve a game's score
+    #[arg(long, default_value_t = 30.0)]
+    half_life_days: f64,
+
+    /// Only list the first N games (default: all)
+    #[arg(long)]
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ColdGame {
+    service: String,
+    game_app_id: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    epic_app_id: Option<String>,
+    game_name: String,
+    cached_bytes: i64,
+    cache_files: i64,
+    downloads: i64,
+    distinct_clients: i64,
+    hit_bytes: i64,
+    miss_bytes: i64,
+    hit_bytes_per_cached_byte: f64,
+    last_hit_at: Option<String>,
+    last_download_at: Option<String>,
+    /// Days since the last download; None when no download of the game is on record.
+    days_since_last_download: Option<f64>,
+    value_score: f64,
+    /// Bytes freed by removing this game and every game listed before it.
+    cumulative_bytes: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ColdGameReport {
+    success: bool,
+    half_life_days: f64,
+    total_games: usize,
+    total_cached_bytes: i64,
+    games: Vec<ColdGame>,
+    timestamp: String,
+}
+
+/// Orders games lowest score first; among equal scores the larger game frees more, so it leads.
+/// Then fills in the running byte total.
+fn rank_coldest_first(games: &mut [ColdGame]) {
+    games.sort_by(|a, b| {
+        a.value_score
+            .total_cmp(&b.value_score)
+            .then(b.cached_bytes.cmp(&a.cached_bytes))
+            .then(a.game_name.cmp(&b.game_name))
+    });
+    let mut cumulative = 0i64;
+    for game in games {
+        cumulative += game.cached_bytes;
+        game.cumulative_bytes = cumulative;
+    }
+}
+
+async fn run(args: &Args) -> Result<()> {
+    if args.half_life_days.is_nan() || args.half_life_days <= 0.0 {
+        bail!("--half-life-days must be positive");
+    }
+    let pool = db::create_pool().await?;
+    let detections = cached_game_value::load_detected_games(&pool).await?;
+    let activity = cached_game_value::load_activity(&pool).await?;
+    let now = Utc::now();
+
+    let mut games = Vec::with_capacity(detections.len());
+    for detection in detections {
+        let history = activity.get(&detection.key()).cloned().unwrap_or_default();
+        let hit_bytes_per_cached_byte = history.hit_bytes as f64 / detection.cached_bytes as f64;
+        let days_since_last_download = history.last_download_at.map(|at| days_between(at, now));
+        games.push(ColdGame {
+            service: detection.service,
+            game_app_id: detection.game_app_id,
+            epic_app_id: detection.epic_app_id,
+            game_name: detection.game_name,
+            cached_bytes: detection.cached_bytes,
+            cache_files: detection.cache_files,
+            downloads: history.downloads,
+            distinct_clients: history.distinct_clients,
+            hit_bytes: history.hit_bytes,
+            miss_bytes: history.miss_bytes,
+            hit_bytes_per_cached_byte,
+            last_hit_at: history.last_hit_at.map(|at| at.to_rfc3339()),
+            last_download_at: history.last_download_at.map(|at| at.to_rfc3339()),
+            days_since_last_download,
+            value_score: value_score(
+                hit_bytes_per_cached_byte,
+                history.distinct_clients,
+                history.last_access().map(|at| days_between(at, now)),
+                args.half_life_days,
+            ),
+            cumulative_bytes: 0,
+        });
+    }
+    rank_coldest_first(&mut games);
+
+    let total_games = games.len();
+    let total_cached_bytes = games.last().map_or(0, |game| game.cumulative_bytes);
+    if let Some(limit) = args.limit {
+        games.truncate(limit);
+    }
+    let report = ColdGameReport {
+        success: true,
+        half_life_days: args.half_life_days,
+        total_games,
+        total_cached_bytes,
+        games,
+        timestamp: progress_utils::current_timestamp(),
+    };
+    let payload =
+        serde_json::to_string_pretty(&report).context("Failed to serialize cold game report")?;
+    fs::write(&args.output_json, payload)
+        .with_context(|| format!("Failed to write output JSON to {}", args.output_json))?;
+    eprintln!(
+        "[ColdGames] Scored {} cached games holding {} bytes",
+        total_games, total_cached_bytes
+    );
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    run(&args).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game(name: &str, cached_bytes: i64, value_score: f64) -> ColdGame {
+        ColdGame {
+            service: "steam".to_string(),
+            game_app_id: 1,
+            epic_app_id: None,
+            game_name: name.to_string(),
+            cached_bytes,
+            cache_files: 1,
+            downloads: 0,
+            distinct_clients: 0,
+            hit_bytes: 0,
+            miss_bytes: 0,
+            hit_bytes_per_cached_byte: 0.0,
+            last_hit_at: None,
+            last_download_at: None,
+            days_since_last_download: None,
+            value_score,
+            cumulative_bytes: 0,
+        }
+    }
+
+    #[test]
+    fn coldest_games_lead_with_a_running_total_and_big_ties_first() {
+        let mut games = vec![
+            game("warm", 100, 2.0),
+            game("small", 10, 0.0),
+            game("large", 50, 0.0),
+        ];
+        rank_coldest_first(&mut games);
+        let order: Vec<(&str, i64)> = games
+            .iter()
+            .map(|game| (game.game_name.as_str(), game.cumulative_bytes))
+            .collect();
+        assert_eq!(order, vec![("large", 50), ("small", 60), ("warm", 160)]);
+    }
+}
@@ -3,7 +3,6 @@ use chrono::{DateTime, Utc};
 use clap::{Parser, Subcommand};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use sqlx::{PgPool, Row};
 use std::collections::{BTreeMap, HashSet};
 use std::fs::File;
 use std::io::{BufWriter, Write};
@@ -15,34 +14,22 @@ use lancache_processor::cache_structural_scanner;
 use lancache_processor::cache_structural_state;
 use lancache_processor::cache_utils;
 use lancache_processor::cancel;
+use lancache_processor::corruption_cleanup::{self, stored_log_url};
 use lancache_processor::db;
 use lancache_processor::log_layout;
 use lancache_processor::log_purge;
 use lancache_processor::progress_events;
 use lancache_processor::progress_utils;
+use lancache_processor::protected_games::{self, ProtectedFiles, Protection};
+use lancache_processor::quarantine::{DeferredCleanup, Quarantine, QuarantineArgs};
 use cache_corruption_detector::{
     CorruptionCandidate, CorruptionDetector, CorruptionEvidence, DetectionMethod,
     CORRUPTION_CONTRACT_VERSION, DEFAULT_LOOKBACK_DAYS,
 };
 use cache_utils::{CacheSliceKind, ObservedByteRange};
-use log_purge::{ExactLogMatcher, ExactLogObservation};
+use log_purge::ExactLogObservation;
 use progress_events::ProgressReporter;
 
-const EXACT_DB_DELETE_SQL: &str = r#"
-DELETE FROM "LogEntries"
-WHERE LOWER("Service") = LOWER($1)
-  AND "Datasource" = $2
-  AND "ClientIp" = $3
-  AND "Timestamp" = $4
-  AND "Method" = $5
-  AND "Url" = $6
-  AND "StatusCode" = $7
-  AND "CacheStatus" = $8
-  AND "HttpRange" = $9
-  AND "BytesServed" = $10
-RETURNING "DownloadId"
-"#;
-
 #[derive(Parser, Debug)]
 #[command(name = "cache_corruption")]
 #[command(about = "Detects and removes corrupted cache chunks")]
@@ -127,6 +114,8 @@ enum Commands {
         progress_json: String,
         #[arg(long)]
         evidence_file: String,
+        #[command(flatten)]
+        quarantine: QuarantineArgs,
         #[arg(short, long)]
         progress: bool,
     },
@@ -136,6 +125,8 @@ enum Commands {
         progress_json: String,
         #[arg(long)]
         evidence_file: String,
+        #[command(flatten)]
+        quarantine: QuarantineArgs,
         #[arg(short, long)]
         progress: bool,
     },
@@ -746,6 +737,7 @@ fn delete_exact_paths(
     paths: &[ExactRemovalPath],
     progress_path: &Path,
     reporter: &ProgressReporter,
+    quarantine: Option<&Quarantine>,
 ) -> Result<ExactPathRemovalOutcome> {
     delete_exact_paths_with(
         cache_dir,
@@ -753,7 +745,10 @@ fn delete_exact_paths(
         progress_path,
         reporter,
         cache_utils::active_key_scheme(),
-        |path| std::fs::remove_file(path),
+        |path| match quarantine {
+            Some(quarantine) => quarantine.take(path),
+            None => std::fs::remove_file(path),
+        },
     )
 }
 
@@ -767,114 +762,6 @@ fn completed_exact_removal_count(outcome: &ExactPathRemovalOutcome) -> Result<us
     Ok(outcome.deleted_files)
 }
 
-fn stored_log_url(raw_url: &str) -> String {
-    if !raw_url.as_bytes().windows(2).any(|pair| pair == b"//") {
-        return raw_url.to_string();
-    }
-    let mut normalized = String::with_capacity(raw_url.len());
-    let mut previous_slash = false;
-    for character in raw_url.chars() {
-        if character == '/' {
-            if !previous_slash {
-                normalized.push(character);
-            }
-            previous_slash = true;
-        } else {
-            normalized.push(character);
-            previous_slash = false;
-        }
-    }
-    normalized
-}
-
-fn database_http_range(observation: &ExactLogObservation) -> &str {
-    observation.raw_range.as_deref().unwrap_or("")
-}
-
-fn affected_download_delete_sql(placeholders: &str) -> String {
-    format!(
-        "DELETE FROM \"Downloads\" WHERE \"Id\" IN ({placeholders}) AND NOT \"IsActive\" AND NOT EXISTS (SELECT 1 FROM \"LogEntries\" WHERE \"LogEntries\".\"DownloadId\" = \"Downloads\".\"Id\")"
-    )
-}
-
-fn affected_survivor_recompute_sql(placeholders: &str) -> String {
-    format!(
-        "UPDATE \"Downloads\" SET \"CacheHitBytes\" = COALESCE((SELECT SUM(\"BytesServed\") FROM \"LogEntries\" WHERE \"LogEntries\".\"DownloadId\" = \"Downloads\".\"Id\" AND \"CacheStatus\" = 'HIT'), 0), \"CacheMissBytes\" = COALESCE((SELECT SUM(\"BytesServed\") FROM \"LogEntries\" WHERE \"LogEntries\".\"DownloadId\" = \"Downloads\".\"Id\" AND \"CacheStatus\" IN ('MISS', 'UNKNOWN')), 0) WHERE \"Id\" IN ({placeholders})"
-    )
-}
-
-async fn delete_database_observations(
-    pool: &PgPool,
-    datasource: &str,
-    observations: &[ExactLogObservation],
-) -> Result<(usize, usize)> {
-    let mut transaction = pool
-        .begin()
-        .await
-        .context("failed to begin exact corruption database cleanup")?;
-    let mut affected_download_ids = HashSet::new();
-    let mut log_entries_deleted = 0usize;
-
-    for observation in observations {
-        let rows = sqlx::query(EXACT_DB_DELETE_SQL)
-            .bind(&observation.service)
-            .bind(datasource)
-            .bind(&observation.client_ip)
-            .bind(observation.timestamp)
-            .bind(&observation.method)
-            .bind(stored_log_url(&observation.raw_url))
-            .bind(observation.http_status)
-            .bind(&observation.cache_status)
-            // New no-range rows persist ""; historical nullable rows never match this predicate.
-            .bind(database_http_range(observation))
-            .bind(observation.bytes_served)
-            .fetch_all(&mut *transaction)
-            .await
-            .context("failed to delete exact corruption LogEntries")?;
-        log_entries_deleted += rows.len();
-        for row in rows {
-            if let Some(download_id) = row.try_get::<Option<i64>, _>("DownloadId")? {
-                affected_download_ids.insert(download_id);
-            }
-        }
-    }
-
-    let mut downloads_deleted = 0usize;
-    let download_ids: Vec<i64> = affected_download_ids.into_iter().collect();
-    for chunk in download_ids.chunks(400) {
-        let placeholders = (1..=chunk.len())
-            .map(|index| format!("${index}"))
-            .collect::<Vec<_>>()
-            .join(", ");
-        let delete_downloads = affected_download_delete_sql(&placeholders);
-        let mut query = sqlx::query(&delete_downloads);
-        for id in chunk {
-            query = query.bind(*id);
-        }
-        downloads_deleted += query
-            .execute(&mut *transaction)
-            .await
-            .context("failed to delete affected inactive zero-row Downloads")?
-            .rows_affected() as usize;
-
-        let update_downloads = affected_survivor_recompute_sql(&placeholders);
-        let mut query = sqlx::query(&update_downloads);
-        for id in chunk {
-            query = query.bind(*id);
-        }
-        query
-            .execute(&mut *transaction)
-            .await
-            .context("failed to recompute affected survivor byte totals")?;
-    }
-
-    transaction
-        .commit()
-        .await
-        .context("failed to commit exact corruption database cleanup")?;
-    Ok((downloads_deleted, log_entries_deleted))
-}
-
 fn seed_scan_progress(
     progress_path: Option<&Path>,
     stage_key: &str,
@@ -991,6 +878,8 @@ fn run_structural_remove(
     progress_path: &Path,
     evidence_path: &Path,
     reporter: &ProgressReporter,
+    protected: &ProtectedFiles,
+    quarantine: &QuarantineArgs,
 ) -> Result<()> {
     write_progress(
         progress_path,
@@ -1014,7 +903,13 @@ fn run_structural_remove(
     let now = std::time::SystemTime::now();
     let mut preflight = Vec::with_capacity(evidence.candidates.len());
     let mut outcome = StructuralRemovalOutcome::default();
+    let mut protected_skipped = Vec::new();
     for candidate in &evidence.candidates {
+        // A protected game keeps even a corrupt file; it is reported instead.
+        if protected.contains(Path::new(&candidate.exact_paths[0])) {
+            protected_skipped.push(candidate.exact_paths[0].clone());
+            continue;
+        }
         if cancel::is_cancelled() {
             reporter.emit_cancelled(
                 "signalr.corruptionRemove.complete",
@@ -1054,6 +949,7 @@ fn run_structural_remove(
         }
     }
 
+    let quarantine = quarantine.open(cache_dir, "corruption_remove")?;
     let mut parent_dirs = HashSet::new();
     for (index, (candidate, _)) in preflight.iter().enumerate() {
         if cancel::is_cancelled() {
@@ -1130,7 +1026,11 @@ fn run_structural_remove(
                         continue;
                     }
                 }
-                std::fs::remove_file(&path).with_context(|| {
+                match &quarantine {
+                    Some(quarantine) => quarantine.take(&path),
+                    None => std::fs::remove_file(&path),
+                }
+                .with_context(|| {
                     format!("failed to delete structural cache path {}", path.display())
                 })?;
                 outcome.deleted_files += 1;
@@ -1142,20 +1042,26 @@ fn run_structural_remove(
         }
     }
     cache_utils::cleanup_empty_directories(cache_dir, parent_dirs);
+    let mut context = json!({
+        "detectionMethod": "structural",
+        "count": evidence.candidates.len(),
+        "files": outcome.deleted_files,
+        "alreadyMissing": outcome.already_missing,
+        "healed": outcome.healed,
+        "keyVerificationSkipped": outcome.key_verification_skipped,
+        "bytesFreed": outcome.bytes_freed,
+        "protectedSkipped": protected_skipped.len(),
+        "protectedFiles": protected_skipped
+    });
+    if let Some(quarantine) = quarantine {
+        context["quarantineBatch"] = json!(quarantine.finish(DeferredCleanup::None)?.id);
+    }
     write_progress(
         progress_path,
         reporter,
         "completed",
         "signalr.corruptionRemove.complete",
-        json!({
-            "detectionMethod": "structural",
-            "count": evidence.candidates.len(),
-            "files": outcome.deleted_files,
-            "alreadyMissing": outcome.already_missing,
-            "healed": outcome.healed,
-            "keyVerificationSkipped": outcome.key_verification_skipped,
-            "bytesFreed": outcome.bytes_freed
-        }),
+        context,
         100.0,
         evidence.candidates.len(),
         evidence.candidates.len(),
@@ -1170,6 +1076,7 @@ async fn run_remove(
     progress_path: &Path,
     evidence_path: &Path,
     reporter: &ProgressReporter,
+    quarantine: &QuarantineArgs,
 ) -> Result<()> {
     write_progress(
         progress_path,
@@ -1195,26 +1102,92 @@ async fn run_remove(
     // still retains persisted evidence, but avoid preventable partial work (bad log root/DB config).
     crate::log_layout::discover_log_sources(log_dir)
         .context("failed to discover access logs before removal")?;
-    let matcher = ExactLogMatcher::new(evidence.observations.clone());
-    let prefilter = matcher.prefilter()?;
     let pool = db::create_pool()
         .await
         .context("failed to connect to the database before corruption removal")?;
 
+    // A protected game keeps even its corrupt files, along with the log lines and LogEntries rows
+    // that describe them.
+    let protection = Protection::load(&pool).await?;
+    let protected = protection.files(cache_dir, cache_utils::active_key_scheme());
+    let (protected_paths, exact_paths): (Vec<ExactRemovalPath>, Vec<ExactRemovalPath>) = evidence
+        .exact_paths
+        .iter()
+        .cloned()
+        .partition(|candidate| protected.contains(&candidate.path));
+    let observations: Vec<ExactLogObservation> = evidence
+        .observations
+        .iter()
+        .filter(|observation| {
+            !protection.protects_url(&observation.service, &stored_log_url(&observation.raw_url))
+        })
+        .cloned()
+        .collect();
+    let protected_observations = evidence.observations.len() - observations.len();
+    if !protected_paths.is_empty() || protected_observations > 0 {
+        eprintln!(
+            "Keeping {} protected file(s) and {} protected observation(s)",
+            protected_paths.len(),
+            protected_observations
+        );
+    }
+    let quarantine = quarantine.open(cache_dir, "corruption_remove")?;
+
     write_progress(
         progress_path,
         reporter,
         "removing_cache",
         "signalr.corruptionRemove.removingCacheFiles",
-        json!({ "totalFiles": evidence.exact_paths.len() }),
+        json!({ "totalFiles": exact_paths.len() }),
         10.0,
         0,
-        evidence.exact_paths.len(),
+        exact_paths.len(),
+    )?;
+    let cache_outcome = delete_exact_paths(
+        cache_dir,
+        &exact_paths,
+        progress_path,
+        reporter,
+        quarantine.as_ref(),
     )?;
-    let cache_outcome =
-        delete_exact_paths(cache_dir, &evidence.exact_paths, progress_path, reporter)?;
     let removed_count = completed_exact_removal_count(&cache_outcome)?;
 
+    // Quarantined: the observations wait in the batch for its purge.
+    if let Some(quarantine) = quarantine {
+        let manifest = quarantine.finish(DeferredCleanup::CorruptionEvidence {
+            log_dir: log_dir.to_path_buf(),
+            datasource: evidence.datasource.clone(),
+            observations,
+        })?;
+        return write_progress(
+            progress_path,
+            reporter,
+            "completed",
+            "signalr.corruptionRemove.complete",
+            json!({
+                "count": removed_count,
+                "service": service,
+                "files": cache_outcome.deleted_files,
+                "alreadyMissing": cache_outcome.already_missing,
+                "keyVerificationSkipped": cache_outcome.key_verification_skipped,
+                "bytesFreed": cache_outcome.bytes_freed,
+                "logLines": 0,
+                "downloads": 0,
+                "logEntries": 0,
+                "protectedSkipped": protected_paths.len(),
+                "protectedFiles": protected_paths
+                    .iter()
+                    .map(|candidate| candidate.path.display().to_string())
+                    .collect::<Vec<_>>(),
+                "protectedObservationsKept": protected_observations,
+                "quarantineBatch": manifest.id
+            }),
+            100.0,
+            evidence.exact_paths.len(),
+            evidence.exact_paths.len(),
+        );
+    }
+
     write_progress(
         progress_path,
         reporter,
@@ -1238,20 +1211,8 @@ async fn run_remove(
             total,
         );
     };
-    let log_outcome = log_purge::rewrite_matching_log_entries_strict(
-        log_dir,
-        "exact corruption evidence",
-        &prefilter,
-        |entry| matcher.matches(entry),
-        Some(&filter_callback),
-    )?;
-    if log_outcome.permission_errors > 0 || log_outcome.other_errors > 0 {
-        bail!(
-            "access-log cleanup was partial ({} permission errors, {} other errors); database evidence was retained",
-            log_outcome.permission_errors,
-            log_outcome.other_errors
-        );
-    }
+    let log_lines_removed =
+        corruption_cleanup::purge_log_observations(log_dir, &observations, Some(&filter_callback))?;
 
     write_progress(
         progress_path,
@@ -1264,7 +1225,8 @@ async fn run_remove(
         0,
     )?;
     let (downloads_deleted, log_entries_deleted) =
-        delete_database_observations(&pool, &evidence.datasource, &evidence.observations).await?;
+        corruption_cleanup::delete_database_observations(&pool, &evidence.datasource, &observations)
+            .await?;
 
     write_progress(
         progress_path,
@@ -1278,9 +1240,15 @@ async fn run_remove(
             "alreadyMissing": cache_outcome.already_missing,
             "keyVerificationSkipped": cache_outcome.key_verification_skipped,
             "bytesFreed": cache_outcome.bytes_freed,
-            "logLines": log_outcome.lines_removed,
+            "logLines": log_lines_removed,
             "downloads": downloads_deleted,
-            "logEntries": log_entries_deleted
+            "logEntries": log_entries_deleted,
+            "protectedSkipped": protected_paths.len(),
+            "protectedFiles": protected_paths
+                .iter()
+                .map(|candidate| candidate.path.display().to_string())
+                .collect::<Vec<_>>(),
+            "protectedObservationsKept": protected_observations
         }),
         100.0,
         evidence.exact_paths.len(),
@@ -1486,6 +1454,7 @@ async fn main() -> Result<()> {
             service,
             progress_json,
             evidence_file,
+            quarantine,
             progress,
         } => {
             let reporter = ProgressReporter::new(progress);
@@ -1496,6 +1465,7 @@ async fn main() -> Result<()> {
                 Path::new(&progress_json),
                 Path::new(&evidence_file),
                 &reporter,
+                &quarantine,
             )
             .await;
             progress_events::finish_or_exit(
@@ -1508,15 +1478,26 @@ async fn main() -> Result<()> {
             cache_dir,
             progress_json,
             evidence_file,
+            quarantine,
             progress,
         } => {
             let reporter = ProgressReporter::new(progress);
-            let result = run_structural_remove(
-                Path::new(&cache_dir),
-                Path::new(&progress_json),
-                Path::new(&evidence_file),
-                &reporter,
-            );
+            let result = async {
+                let protected = protected_games::load_files(
+                    Path::new(&cache_dir),
+                    cache_utils::active_key_scheme(),
+                )
+                .await?;
+                run_structural_remove(
+                    Path::new(&cache_dir),
+                    Path::new(&progress_json),
+                    Path::new(&evidence_file),
+                    &reporter,
+                    &protected,
+                    &quarantine,
+                )
+            }
+            .await;
             progress_events::finish_or_exit(
                 &reporter,
                 "signalr.corruptionRemove.error.fatal",
@@ -1531,6 +1512,8 @@ async fn main() -> Result<()> {
 mod tests {
     use super::*;
     use cache_corruption_detector::CandidateObservation;
+    use lancache_processor::corruption_cleanup::EXACT_DB_DELETE_SQL;
+    use log_purge::ExactLogMatcher;
     use std::sync::atomic::{AtomicUsize, Ordering};
 
     fn make_candidate(cache_dir: &Path, datasource: &str, threshold: usize) -> RemovalCandidate {
@@ -2031,6 +2014,8 @@ mod tests {
             &fixture.path().join("progress.json"),
             &evidence,
             &ProgressReporter::new(false),
+            &ProtectedFiles::none(),
+            &QuarantineArgs::default(),
         )
         .unwrap();
         assert!(!exact.exists());
@@ -2055,6 +2040,8 @@ mod tests {
             &fixture.path().join("progress.json"),
             &evidence,
             &ProgressReporter::new(false),
+            &ProtectedFiles::none(),
+            &QuarantineArgs::default(),
         )
         .is_err());
         assert!(
@@ -2350,33 +2337,6 @@ mod tests {
         assert_eq!(calls.load(Ordering::SeqCst), 2);
     }
 
-    #[test]
-    fn database_cleanup_sql_preserves_exact_scope_and_survivor_recompute() {
-        assert!(EXACT_DB_DELETE_SQL.contains("\"Datasource\" = $2"));
-        assert!(EXACT_DB_DELETE_SQL.contains("\"ClientIp\" = $3"));
-        assert!(EXACT_DB_DELETE_SQL.contains("\"Timestamp\" = $4"));
-        assert!(EXACT_DB_DELETE_SQL.contains("\"BytesServed\" = $10"));
-        assert_eq!(
-            database_http_range(&ExactLogObservation {
-                service: "steam".into(),
-                raw_url: "/a".into(),
-                timestamp: Utc::now(),
-                client_ip: "client".into(),
-                method: "GET".into(),
-                http_status: 200,
-                bytes_served: 1,
-                cache_status: "MISS".into(),
-                raw_range: None,
-            }),
-            ""
-        );
-        let delete = affected_download_delete_sql("$1");
-        assert!(delete.contains("NOT \"IsActive\""));
-        assert!(delete.contains("NOT EXISTS"));
-        let update = affected_survivor_recompute_sql("$1");
-        assert!(update.contains("COALESCE((SELECT SUM(\"BytesServed\")"));
-    }
-
     #[test]
     fn streamed_pretty_json_matches_serde_serialization() {
         let fixture = tempfile::tempdir().unwrap();
@@ -11,8 +11,12 @@ use std::path::PathBuf;
 use lancache_processor::cache_utils;
 use lancache_processor::cancel;
 use lancache_processor::db;
+use lancache_processor::db_backup;
 use lancache_processor::progress_events;
+use lancache_processor::protected_games::{self, ProtectedGame, Protection};
+use lancache_processor::quarantine::{DeferredCleanup, QuarantineArgs};
 use lancache_processor::removal_core;
+use lancache_processor::removal_plan::{self, PlanRun, PlanStageKeys, PlanTarget, RemovalPlan};
 use progress_events::ProgressReporter;
 use removal_core::{LogScope, ProgressCadence, RemovalStageKeys};
 
@@ -21,7 +25,9 @@ use removal_core::{LogScope, ProgressCadence, RemovalStageKeys};
 ///
 /// Identity is `(GameName, EpicAppId IS NOT NULL)`. The shared delete/cleanup/
 /// purge/permission tail lives in `removal_core`; this bin owns only the Epic
-/// HEAD: the `GameName + EpicAppId` URL query and the matching DB-row delete.
+/// HEAD: the `GameName + EpicAppId` URL query and the matching DB-row delete. `--plan`/`--apply`
+/// go through `removal_plan`; `--quarantine-dir` defers the log and row cleanup to the purge of a
+/// `quarantine` batch.
 #[derive(clap::Parser, Debug)]
 #[command(name = "cache_epic_remove")]
 #[command(about = "Removes all cache files for a specific Epic game by name")]
@@ -45,6 +51,15 @@ struct Args {
     #[arg(long = "key-scheme", default_value = "monolithic")]
     key_scheme: String,
 
+    #[command(flatten)]
+    backup: db_backup::BackupArgs,
+
+    #[command(flatten)]
+    plan: removal_plan::PlanArgs,
+
+    #[command(flatten)]
+    quarantine: QuarantineArgs,
+
     /// Emit JSON progress events to stdout
     #[arg(short, long)]
     progress: bool,
@@ -57,6 +72,20 @@ const EPIC_STAGE_KEYS: RemovalStageKeys = RemovalStageKeys {
     cache_file_progress: "signalr.epicRemove.cache.file.progress",
 };
 
+const EPIC_PLAN_KEYS: PlanStageKeys = PlanStageKeys {
+    planned: "signalr.epicRemove.planned",
+    cache_removing: "signalr.epicRemove.cache.removing",
+    cache_file_progress: "signalr.epicRemove.cache.file.progress",
+    dirs_cleaning: "signalr.epicRemove.dirs.cleaning",
+    logs_removing: "signalr.epicRemove.logs.removing",
+    db_deleting: "signalr.epicRemove.db.deleting",
+    complete: "signalr.epicRemove.complete",
+};
+
+/// Downloads of the Epic game (alias `d`, $1 = GameName), as planned; the removal backs up and
+/// deletes the unprotected ones.
+const EPIC_DOWNLOADS_FILTER: &str = r#"d."GameName" = $1 AND d."EpicAppId" IS NOT NULL"#;
+
 #[derive(Debug, Serialize)]
 struct RemovalReport {
     game_name: String,
@@ -64,6 +93,11 @@ struct RemovalReport {
     total_bytes_freed: u64,
     empty_dirs_removed: usize,
     log_entries_removed: u64,
+    /// Cache files left in place because a protected game also uses them.
+    protected_files_skipped: Vec<String>,
+    /// Batch holding the files when they were quarantined instead of deleted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quarantine_batch: Option<String>,
 }
 
 /// Preserve URL provenance when a bare-metal candidate's recipe-computed key
@@ -145,16 +179,41 @@ async fn get_epic_game_urls_from_db(pool: &PgPool, game_name: &str) -> Result<Ha
     Ok(url_data)
 }
 
+/// Epic protections are keyed by EpicAppId while this bin targets a name, so refuse when any
+/// EpicAppId recorded under the name is protected.
+async fn ensure_epic_game_not_protected(
+    pool: &PgPool,
+    protection: &Protection,
+    game_name: &str,
+) -> Result<()> {
+    if protection.is_empty() {
+        return Ok(());
+    }
+    let epic_app_ids: Vec<String> = sqlx::query_scalar(
+        "SELECT DISTINCT \"EpicAppId\" FROM \"Downloads\" WHERE \"GameName\" = $1 AND \"EpicAppId\" IS NOT NULL"
+    )
+    .bind(game_name)
+    .fetch_all(pool)
+    .await?;
+    for epic_app_id in epic_app_ids {
+        protection.ensure_not_protected(&ProtectedGame::Epic { epic_app_id })?;
+    }
+    Ok(())
+}
+
 /// Delete database records for the Epic game (LogEntries + Downloads).
 async fn delete_epic_game_from_database(pool: &PgPool, game_name: &str) -> Result<(u64, u64)> {
     eprintln!("Deleting database records for Epic game '{}'...", game_name);
 
+    // `main` refused a protected target; the predicate covers a protection added mid-run.
+    let downloads = protected_games::unprotected_downloads(EPIC_DOWNLOADS_FILTER);
+
     // First, delete LogEntries that reference these downloads (foreign key constraint)
-    let log_result = sqlx::query(
+    let log_result = sqlx::query(&format!(
         "DELETE FROM \"LogEntries\" WHERE \"DownloadId\" IN (
-             SELECT \"Id\" FROM \"Downloads\" WHERE \"GameName\" = $1 AND \"EpicAppId\" IS NOT NULL
+             SELECT d.\"Id\" FROM \"Downloads\" d WHERE {downloads}
          )"
-    )
+    ))
     .bind(game_name)
     .execute(pool)
     .await?;
@@ -162,9 +221,9 @@ async fn delete_epic_game_from_database(pool: &PgPool, game_name: &str) -> Resul
     eprintln!("  Deleted {} log entry records", log_entries_deleted);
 
     // Now safe to delete the downloads
-    let downloads_result = sqlx::query(
-        "DELETE FROM \"Downloads\" WHERE \"GameName\" = $1 AND \"EpicAppId\" IS NOT NULL"
-    )
+    let downloads_result = sqlx::query(&format!(
+        "DELETE FROM \"Downloads\" d WHERE {downloads}"
+    ))
     .bind(game_name)
     .execute(pool)
     .await?;
@@ -209,13 +268,51 @@ async fn main() -> Result<()> {
     }
 
     let pool = db::create_pool().await?;
+    let protection = Protection::load(&pool).await?;
+    // A plan deletes nothing; a protected game's plan lists its slices as protected, which is
+    // what `cache_export` reads.
+    if !args.plan.plan {
+        ensure_epic_game_not_protected(&pool, &protection, game_name).await?;
+    }
 
     removal_core::write_progress(&progress_path, &reporter, "starting", "signalr.epicRemove.starting", json!({ "gameName": game_name }), 0.0, 0, 0)?;
 
+    let scheme = cache_utils::active_key_scheme();
+    let target = PlanTarget::Epic { game_name: game_name.clone() };
+    let plan_run = PlanRun {
+        output_json: &output_json,
+        progress_path: &progress_path,
+        reporter: &reporter,
+        keys: &EPIC_PLAN_KEYS,
+        identity: json!({ "gameName": game_name }),
+    };
+    if let Some(plan_path) = &args.plan.apply {
+        let plan = RemovalPlan::load(plan_path, &target, &log_dir, &cache_dir, scheme)?;
+        let protected = protection.files(&cache_dir, scheme);
+        let quarantine = args.quarantine.open(&cache_dir, "epic_remove")?;
+        return removal_plan::apply(&pool, &plan, &protected, args.backup.policy().as_ref(), quarantine, &plan_run).await;
+    }
+
     // Query database for URLs
     removal_core::write_progress(&progress_path, &reporter, "querying_database", "signalr.epicRemove.db.querying", json!({}), 5.0, 0, 0)?;
     let url_data = get_epic_game_urls_from_db(&pool, game_name).await?;
 
+    if args.plan.plan {
+        let plan = removal_plan::plan_url_removal(
+            &pool,
+            target,
+            &log_dir,
+            &cache_dir,
+            scheme,
+            &url_data,
+            &protection.files(&cache_dir, scheme),
+            EPIC_DOWNLOADS_FILTER,
+            |q| q.bind(game_name.to_string()),
+        )
+        .await?;
+        return removal_plan::write_plan(&plan, &plan_run);
+    }
+
     if url_data.is_empty() {
         eprintln!("No URLs found for Epic game '{}'", game_name);
 
@@ -225,6 +322,8 @@ async fn main() -> Result<()> {
             total_bytes_freed: 0,
             empty_dirs_removed: 0,
             log_entries_removed: 0,
+            protected_files_skipped: Vec::new(),
+            quarantine_batch: None,
         };
 
         let json = serde_json::to_string_pretty(&report)?;
@@ -236,6 +335,19 @@ async fn main() -> Result<()> {
 
     eprintln!("Found {} unique URLs for '{}'", url_data.len(), game_name);
 
+    // Pre-flight backup of the rows `delete_epic_game_from_database` removes. A quarantined
+    // removal deletes no rows; the purge of its batch takes the backup instead.
+    let backup = db_backup::backup_download_cascade(
+        &pool,
+        args.backup.policy().filter(|_| args.quarantine.quarantine_dir.is_none()).as_ref(),
+        "epic_remove",
+        json!({ "gameName": game_name }),
+        &protected_games::unprotected_downloads(EPIC_DOWNLOADS_FILTER),
+        |q| q.bind(game_name.to_string()),
+    )
+    .await?;
+    let quarantine = args.quarantine.open(&cache_dir, "epic_remove")?;
+
     // Step 1: Remove cache files
     let url_count = url_data.len();
     removal_core::write_progress(&progress_path, &reporter, "removing_cache", "signalr.epicRemove.cache.removing", json!({ "count": url_count }), 10.0, 0, 0)?;
@@ -248,7 +360,10 @@ async fn main() -> Result<()> {
         &EPIC_STAGE_KEYS,
         ProgressCadence::OnPercentAdvance,
         cache_utils::active_key_scheme(),
+        &protection.files(&cache_dir, cache_utils::active_key_scheme()),
+        quarantine.as_ref(),
     )?;
+    let protected_files_skipped = outcome.protected_file_names();
 
     // If cancellation arrived during cache removal, do directory cleanup and exit 0.
     if cancel::is_cancelled() {
@@ -269,16 +384,51 @@ async fn main() -> Result<()> {
             total_bytes_freed: outcome.bytes_freed,
             empty_dirs_removed,
             log_entries_removed: 0,
+            protected_files_skipped,
+            quarantine_batch: None,
         };
         let json = serde_json::to_string_pretty(&report)?;
         fs::write(&output_json, json)?;
         return Err(error);
     }
 
+    // Lines of kept files stay, so later scans still see what is on disk.
+    let urls_to_remove: HashSet<String> = url_data
+        .keys()
+        .filter(|url| !outcome.protected_urls.contains(*url))
+        .cloned()
+        .collect();
+
+    // Quarantined: the log purge and row delete are recorded in the batch for its purge.
+    if let Some(quarantine) = quarantine {
+        let database = removal_plan::plan_download_rows(&pool, EPIC_DOWNLOADS_FILTER, |q| {
+            q.bind(game_name.to_string())
+        })
+        .await?;
+        let report = RemovalReport {
+            game_name: game_name.clone(),
+            cache_files_deleted: outcome.deleted_files,
+            total_bytes_freed: outcome.bytes_freed,
+            empty_dirs_removed,
+            log_entries_removed: 0,
+            protected_files_skipped,
+            quarantine_batch: Some(quarantine.id().to_string()),
+        };
+        let finished = removal_core::finish_quarantine(
+            quarantine,
+            outcome.permission_errors,
+            DeferredCleanup::removal(&log_dir, None, urls_to_remove, HashSet::new(), database),
+        );
+        fs::write(&output_json, serde_json::to_string_pretty(&report)?)?;
+        finished?;
+
+        let complete_context = json!({ "files": report.cache_files_deleted, "gb": report.total_bytes_freed as f64 / 1_073_741_824.0, "logEntries": 0, "gameName": game_name, "protectedSkipped": report.protected_files_skipped.len(), "quarantineBatch": report.quarantine_batch });
+        return removal_core::write_progress(&progress_path, &reporter, "completed", "signalr.epicRemove.complete", complete_context, 100.0, 0, 0);
+    }
+
     // Step 3: Remove log entries from access log text files
     removal_core::write_progress(&progress_path, &reporter, "removing_logs", "signalr.epicRemove.logs.removing", json!({}), 80.0, 0, 0)?;
     eprintln!("\nRemoving log entries...");
-    let urls_to_remove: HashSet<String> = url_data.keys().cloned().collect();
     let (log_entries_removed, log_permission_errors) =
         removal_core::purge_log_entries(&log_dir, &urls_to_remove, &LogScope::Urls)?;
 
@@ -298,6 +448,8 @@ async fn main() -> Result<()> {
             total_bytes_freed: outcome.bytes_freed,
             empty_dirs_removed,
             log_entries_removed,
+            protected_files_skipped,
+            quarantine_batch: None,
         };
         let json = serde_json::to_string_pretty(&report)?;
         fs::write(&output_json, json)?;
@@ -317,12 +469,16 @@ async fn main() -> Result<()> {
         total_bytes_freed: outcome.bytes_freed,
         empty_dirs_removed,
         log_entries_removed,
+        protected_files_skipped,
+        quarantine_batch: None,
     };
 
     let json = serde_json::to_string_pretty(&report)?;
     fs::write(&output_json, json)?;
 
-    removal_core::write_progress(&progress_path, &reporter, "completed", "signalr.epicRemove.complete", json!({ "files": report.cache_files_deleted, "gb": report.total_bytes_freed as f64 / 1_073_741_824.0, "logEntries": report.log_entries_removed, "gameName": game_name }), 100.0, 0, 0)?;
+    let mut complete_context = json!({ "files": report.cache_files_deleted, "gb": report.total_bytes_freed as f64 / 1_073_741_824.0, "logEntries": report.log_entries_removed, "gameName": game_name, "protectedSkipped": report.protected_files_skipped.len() });
+    db_backup::annotate_context(&mut complete_context, backup.as_ref());
+    removal_core::write_progress(&progress_path, &reporter, "completed", "signalr.epicRemove.complete", complete_context, 100.0, 0, 0)?;
 
     eprintln!("\n=== Removal Summary ===");
     eprintln!("Cache files deleted: {}", report.cache_files_deleted);
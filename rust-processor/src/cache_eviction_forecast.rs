@@ -0,0 +1,872 @@
+//! Forecasts which cached games nginx will evict next, and when.
+//!
+//! nginx's cache manager removes a file for one of two reasons: nobody requested it within the
+//! `inactive` window, or the cache grew past `max_size` and the file is the least recently used.
+//! Both orders agree: a file's inactive deadline is its last access plus a constant, so the file
+//! that expires first is also the one LRU would pick first. The forecast therefore sorts the
+//! on-disk inventory (`cache_inventory`) by last access once and gives every file the earlier of
+//! its inactive deadline and the moment new content, written at the recent MISS rate, pushes the
+//! cache past `max_size` with it still inside.
+//!
+//! The model assumes traffic continues as it did over the lookback window and that nothing
+//! already cached is requested again; a re-read moves a file to the fresh end of the LRU list
+//! and makes its real eviction later than forecast. Per-download results are stored in
+//! `download_eviction_forecasts`, next to the `IsEvicted` flag `cache_eviction_scan` maintains.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::Parser;
+use futures_util::TryStreamExt;
+use serde::Serialize;
+use serde_json::json;
+use sqlx::{Connection, PgPool, Row};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use lancache_processor::cache_inventory_store;
+use lancache_processor::cancel;
+use lancache_processor::db;
+use lancache_processor::progress_events;
+use lancache_processor::progress_utils;
+use progress_events::ProgressReporter;
+
+/// Serializes first-time table creation against a second forecast starting on the same database.
+const SCHEMA_SETUP_LOCK_KEY: i64 = i64::from_be_bytes(*b"evictfct");
+
+/// Download forecasts written per round trip.
+const STORE_BATCH_SIZE: usize = 5_000;
+
+/// Inventory rows loaded between two progress updates.
+const PROGRESS_INTERVAL: usize = 50_000;
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+#[derive(Parser, Debug)]
+#[command(name = "cache_eviction_forecast")]
+#[command(about = "Simulates nginx's cache manager over the cache inventory to forecast evictions")]
+struct Args {
+    output_json: String,
+
+    /// Path to progress JSON file (use "none" to skip)
+    #[arg(default_value = "none")]
+    progress_json: String,
+
+    /// Datasource whose inventory is forecast, as recorded in LogEntries
+    #[arg(long, default_value = "default")]
+    datasource: String,
+
+    /// nginx proxy_cache_path max_size, in nginx size syntax (e.g. 1000g)
+    #[arg(long)]
+    max_size: String,
+
+    /// nginx proxy_cache_path inactive, in nginx time syntax (e.g. 200d)
+    #[arg(long, default_value = "3650d")]
+    inactive: String,
+
+    /// Days of MISS traffic the fill rate is averaged over
+    #[arg(long, default_value_t = 14)]
+    lookback_days: i64,
+
+    /// Emit JSON progress events to stdout
+    #[arg(short, long)]
+    progress: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProgressData {
+    status: String,
+    stage_key: String,
+    context: serde_json::Value,
+    percent_complete: f64,
+    files_loaded: usize,
+    total_files: i64,
+    timestamp: String,
+}
+
+/// One inventory file, with the downloads whose log entries requested its URL.
+#[derive(Debug, Clone, PartialEq)]
+struct CachedFile {
+    /// Unix seconds of the latest request for the file, or its mtime when that is later.
+    last_access: i64,
+    size: i64,
+    downloads: Vec<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum EvictionReason {
+    /// Not requested within the `inactive` window.
+    Inactive,
+    /// Least recently used while the cache was over `max_size`.
+    MaxSize,
+}
+
+impl EvictionReason {
+    fn as_str(self) -> &'static str {
+        match self {
+            EvictionReason::Inactive => "inactive",
+            EvictionReason::MaxSize => "max_size",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Eviction {
+    at: i64,
+    reason: EvictionReason,
+}
+
+/// The cache manager settings and traffic the simulation runs against.
+#[derive(Debug, Clone, Copy)]
+struct CacheModel {
+    now: i64,
+    max_size: i64,
+    inactive_seconds: i64,
+    /// Bytes of new content written per second.
+    fill_rate: f64,
+}
+
+/// Forecast for a set of files: the first one to go and the last one to go.
+#[derive(Debug, Clone, Copy)]
+struct Aggregate {
+    files: i64,
+    cached_bytes: i64,
+    first: Eviction,
+    last: Eviction,
+}
+
+impl Aggregate {
+    fn new(size: i64, eviction: Eviction) -> Self {
+        Self {
+            files: 1,
+            cached_bytes: size,
+            first: eviction,
+            last: eviction,
+        }
+    }
+
+    fn add(&mut self, size: i64, eviction: Eviction) {
+        self.files += 1;
+        self.cached_bytes += size;
+        if eviction.at < self.first.at {
+            self.first = eviction;
+        }
+        if eviction.at >= self.last.at {
+            self.last = eviction;
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct GameKey {
+    service: String,
+    game_app_id: Option<i64>,
+    game_name: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GameForecast {
+    service: String,
+    game_app_id: Option<i64>,
+    game_name: Option<String>,
+    files: i64,
+    cached_bytes: i64,
+    /// When the first of the game's files goes and the game becomes partially cached.
+    first_eviction_at: String,
+    /// When the last of the game's files goes, which is when `cache_eviction_scan` would flag it.
+    evicted_at: String,
+    reason: EvictionReason,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ForecastReport {
+    success: bool,
+    datasource: String,
+    max_size_bytes: i64,
+    inactive_seconds: i64,
+    used_bytes: i64,
+    files: usize,
+    lookback_days: i64,
+    fill_bytes_per_day: i64,
+    /// When the cache reaches `max_size` at the current fill rate; None if nothing is written.
+    full_at: Option<String>,
+    downloads_forecast: usize,
+    games: Vec<GameForecast>,
+    timestamp: String,
+}
+
+/// Parses an nginx size ("1000g", "512m", "4096"): bytes with an optional k, m or g suffix.
+fn parse_nginx_size(value: &str) -> Result<i64> {
+    let value = value.trim();
+    let (digits, multiplier) = match value.char_indices().last() {
+        Some((index, 'k' | 'K')) => (&value[..index], 1i64 << 10),
+        Some((index, 'm' | 'M')) => (&value[..index], 1i64 << 20),
+        Some((index, 'g' | 'G')) => (&value[..index], 1i64 << 30),
+        _ => (value, 1),
+    };
+    let number: i64 = digits
+        .parse()
+        .with_context(|| format!("invalid nginx size '{}'", value))?;
+    number
+        .checked_mul(multiplier)
+        .filter(|bytes| *bytes > 0)
+        .with_context(|| format!("nginx size '{}' is out of range", value))
+}
+
+/// Parses an nginx time ("200d", "1h30m", "90"): a run of numbers, each with a unit of ms, s, m,
+/// h, d, w, M (30 days) or y (365 days). A bare number is seconds. Returns whole seconds.
+fn parse_nginx_time(value: &str) -> Result<i64> {
+    let value = value.trim();
+    if value.is_empty() {
+        bail!("empty nginx time");
+    }
+    if let Ok(seconds) = value.parse::<i64>() {
+        return Ok(seconds);
+    }
+    let mut total_ms: i64 = 0;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let digits_end = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        if digits_end == 0 {
+            bail!("invalid nginx time '{}'", value);
+        }
+        let number: i64 = rest[..digits_end]
+            .parse()
+            .with_context(|| format!("invalid nginx time '{}'", value))?;
+        rest = &rest[digits_end..];
+        let (unit_ms, unit_len) = if rest.starts_with("ms") {
+            (1, 2)
+        } else {
+            match rest.chars().next() {
+                Some('s') => (1_000, 1),
+                Some('m') => (60_000, 1),
+                Some('h') => (3_600_000, 1),
+                Some('d') => (SECONDS_PER_DAY * 1_000, 1),
+                Some('w') => (7 * SECONDS_PER_DAY * 1_000, 1),
+                Some('M') => (30 * SECONDS_PER_DAY * 1_000, 1),
+                Some('y') => (365 * SECONDS_PER_DAY * 1_000, 1),
+                _ => bail!("invalid unit in nginx time '{}'", value),
+            }
+        };
+        rest = &rest[unit_len..];
+        total_ms = number
+            .checked_mul(unit_ms)
+            .and_then(|ms| total_ms.checked_add(ms))
+            .with_context(|| format!("nginx time '{}' is out of range", value))?;
+    }
+    Ok(total_ms / 1_000)
+}
+
+/// When `bytes` more content will have been written at the model's fill rate; now if nothing
+/// needs to be written, None if nothing is being written.
+fn after_writing(model: &CacheModel, bytes: i64) -> Option<i64> {
+    if bytes <= 0 {
+        Some(model.now)
+    } else if model.fill_rate > 0.0 {
+        let seconds = (bytes as f64 / model.fill_rate).ceil().min(i64::MAX as f64) as i64;
+        Some(model.now.saturating_add(seconds))
+    } else {
+        None
+    }
+}
+
+/// Sorts `files` into LRU order and forecasts each one's eviction, returned in the same order.
+///
+/// File `i` outlives the cache pressure until everything older than it is gone and the content
+/// written since `now` still does not fit next to what remains: used - older + written > max.
+/// A cache already over `max_size` sheds its oldest files immediately.
+fn forecast_evictions(files: &mut [CachedFile], model: &CacheModel) -> Vec<Eviction> {
+    files.sort_by_key(|file| file.last_access);
+    let used: i64 = files.iter().map(|file| file.size).sum();
+    let mut older: i64 = 0;
+    files
+        .iter()
+        .map(|file| {
+            let headroom = model.max_size - used + older;
+            older += file.size;
+            let size_deadline = after_writing(model, headroom);
+            let inactive_deadline = file
+                .last_access
+                .saturating_add(model.inactive_seconds)
+                .max(model.now);
+            match size_deadline {
+                Some(at) if at < inactive_deadline => Eviction {
+                    at,
+                    reason: EvictionReason::MaxSize,
+                },
+                _ => Eviction {
+                    at: inactive_deadline,
+                    reason: EvictionReason::Inactive,
+                },
+            }
+        })
+        .collect()
+}
+
+/// Folds file forecasts into one aggregate per download.
+fn forecast_downloads(files: &[CachedFile], evictions: &[Eviction]) -> HashMap<i64, Aggregate> {
+    let mut downloads: HashMap<i64, Aggregate> = HashMap::new();
+    for (file, eviction) in files.iter().zip(evictions) {
+        for download_id in &file.downloads {
+            downloads
+                .entry(*download_id)
+                .and_modify(|aggregate| aggregate.add(file.size, *eviction))
+                .or_insert_with(|| Aggregate::new(file.size, *eviction));
+        }
+    }
+    downloads
+}
+
+/// Folds file forecasts into one aggregate per game. A file that several downloads of the same
+/// game requested counts once.
+fn forecast_games(
+    files: &[CachedFile],
+    evictions: &[Eviction],
+    games_by_download: &HashMap<i64, GameKey>,
+) -> HashMap<GameKey, Aggregate> {
+    let mut games: HashMap<GameKey, Aggregate> = HashMap::new();
+    for (file, eviction) in files.iter().zip(evictions) {
+        let mut keys: Vec<&GameKey> = file
+            .downloads
+            .iter()
+            .filter_map(|download_id| games_by_download.get(download_id))
+            .collect();
+        keys.sort_unstable();
+        keys.dedup();
+        for key in keys {
+            games
+                .entry(key.clone())
+                .and_modify(|aggregate| aggregate.add(file.size, *eviction))
+                .or_insert_with(|| Aggregate::new(file.size, *eviction));
+        }
+    }
+    games
+}
+
+fn format_time(seconds: i64) -> String {
+    DateTime::<Utc>::from_timestamp(seconds, 0)
+        .unwrap_or(DateTime::<Utc>::MAX_UTC)
+        .to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Writes the progress file (when a path was supplied) and THEN emits the matching stdout event.
+fn write_progress(
+    progress_path: Option<&Path>,
+    reporter: &ProgressReporter,
+    status: &str,
+    stage_key: &str,
+    files_loaded: usize,
+    total_files: i64,
+) -> Result<()> {
+    let percent_complete = match status {
+        "completed" => 100.0,
+        _ if total_files <= 0 => 0.0,
+        _ => (files_loaded as f64 / total_files as f64 * 100.0).min(99.0),
+    };
+    let context = json!({
+        "filesLoaded": files_loaded,
+        "totalFiles": total_files,
+    });
+    if let Some(path) = progress_path {
+        let progress = ProgressData {
+            status: status.to_string(),
+            stage_key: stage_key.to_string(),
+            context: context.clone(),
+            percent_complete,
+            files_loaded,
+            total_files,
+            timestamp: progress_utils::current_timestamp(),
+        };
+        progress_utils::write_progress_json(path, &progress)?;
+    }
+
+    match status {
+        "starting" => reporter.emit_started(stage_key, context),
+        "completed" => reporter.emit_complete(stage_key, context),
+        "cancelled" => reporter.emit_cancelled(stage_key, context),
+        _ => reporter.emit_progress(percent_complete, stage_key, context),
+    }
+
+    Ok(())
+}
+
+async fn initialize_schema(pool: &PgPool) -> Result<()> {
+    let mut connection = pool.acquire().await?;
+    let mut transaction = connection
+        .begin()
+        .await
+        .context("failed to begin eviction forecast schema setup")?;
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(SCHEMA_SETUP_LOCK_KEY)
+        .execute(&mut *transaction)
+        .await?;
+    // One row per download that still has files on disk. Cascades with the download so a
+    // database reset or rebuild never leaves forecasts for ids that were reused.
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS download_eviction_forecasts( \
+            download_id BIGINT PRIMARY KEY REFERENCES \"Downloads\"(\"Id\") ON DELETE CASCADE, \
+            datasource TEXT NOT NULL, \
+            cached_files BIGINT NOT NULL, \
+            cached_bytes BIGINT NOT NULL, \
+            first_eviction_at TIMESTAMPTZ NOT NULL, \
+            evicted_at TIMESTAMPTZ NOT NULL, \
+            reason TEXT NOT NULL, \
+            forecast_at TIMESTAMPTZ NOT NULL \
+         )",
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query(
+        "CREATE INDEX IF NOT EXISTS idx_download_eviction_forecasts_datasource \
+         ON download_eviction_forecasts(datasource)",
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Bytes per second nginx wrote over the lookback window, taking MISS and EXPIRED responses as
+/// new content on disk.
+async fn fill_rate(pool: &PgPool, datasource: &str, lookback_days: i64) -> Result<f64> {
+    let bytes: i64 = sqlx::query_scalar(
+        "SELECT COALESCE(SUM(\"BytesServed\"), 0)::bigint FROM \"LogEntries\" \
+         WHERE \"Datasource\" = $1 AND \"CacheStatus\" IN ('MISS', 'EXPIRED') \
+           AND \"Timestamp\" >= now() - make_interval(days => $2::int)",
+    )
+    .bind(datasource)
+    .bind(lookback_days)
+    .fetch_one(pool)
+    .await
+    .context("failed to sum recent cache misses")?;
+    Ok(bytes as f64 / (lookback_days * SECONDS_PER_DAY) as f64)
+}
+
+/// Loads the datasource's inventory with each file's latest request and requesting downloads.
+/// Returns None when cancelled.
+async fn load_files(
+    pool: &PgPool,
+    datasource: &str,
+    mut on_progress: impl FnMut(usize) -> Result<()>,
+) -> Result<Option<Vec<CachedFile>>> {
+    let mut rows = sqlx::query(
+        "SELECT i.size_bytes, i.mtime_ns / 1000000000 AS mtime, a.last_access, a.download_ids \
+         FROM cache_inventory i \
+         LEFT JOIN ( \
+            SELECT l.\"Url\" AS url, \
+                EXTRACT(EPOCH FROM MAX(l.\"Timestamp\"))::bigint AS last_access, \
+                ARRAY_AGG(DISTINCT l.\"DownloadId\") \
+                    FILTER (WHERE l.\"DownloadId\" IS NOT NULL) AS download_ids \
+            FROM \"LogEntries\" l WHERE l.\"Datasource\" = $1 GROUP BY l.\"Url\" \
+         ) a ON a.url = i.url \
+         WHERE i.datasource = $1",
+    )
+    .bind(datasource)
+    .fetch(pool);
+    let mut files = Vec::new();
+    while let Some(row) = rows
+        .try_next()
+        .await
+        .context("failed to load the cache inventory")?
+    {
+        let mtime: i64 = row.try_get("mtime")?;
+        let last_access: Option<i64> = row.try_get("last_access")?;
+        files.push(CachedFile {
+            last_access: last_access.map_or(mtime, |access| access.max(mtime)),
+            size: row.try_get("size_bytes")?,
+            downloads: row
+                .try_get::<Option<Vec<i64>>, _>("download_ids")?
+                .unwrap_or_default(),
+        });
+        if files.len() % PROGRESS_INTERVAL == 0 {
+            if cancel::is_cancelled() {
+                return Ok(None);
+            }
+            on_progress(files.len())?;
+        }
+    }
+    Ok(Some(files))
+}
+
+async fn load_games(pool: &PgPool, download_ids: &[i64]) -> Result<HashMap<i64, GameKey>> {
+    let rows = sqlx::query(
+        "SELECT \"Id\", \"Service\", \"GameAppId\", \"GameName\" FROM \"Downloads\" \
+         WHERE \"Id\" = ANY($1) AND (\"GameAppId\" IS NOT NULL OR \"GameName\" IS NOT NULL)",
+    )
+    .bind(download_ids)
+    .fetch_all(pool)
+    .await
+    .context("failed to load games for forecast downloads")?;
+    rows.iter()
+        .map(|row| {
+            Ok((
+                row.try_get("Id")?,
+                GameKey {
+                    service: row.try_get("Service")?,
+                    game_app_id: row.try_get("GameAppId")?,
+                    game_name: row.try_get("GameName")?,
+                },
+            ))
+        })
+        .collect()
+}
+
+/// Replaces the datasource's stored forecasts in one transaction, so a reader never sees a mix
+/// of two runs.
+async fn store_forecasts(
+    pool: &PgPool,
+    datasource: &str,
+    forecast_at: i64,
+    downloads: &HashMap<i64, Aggregate>,
+) -> Result<()> {
+    let forecast_at =
+        DateTime::<Utc>::from_timestamp(forecast_at, 0).context("forecast time is out of range")?;
+    let to_time = |seconds: i64| {
+        DateTime::<Utc>::from_timestamp(seconds, 0).unwrap_or(DateTime::<Utc>::MAX_UTC)
+    };
+    let mut entries: Vec<(&i64, &Aggregate)> = downloads.iter().collect();
+    entries.sort_by_key(|(download_id, _)| **download_id);
+
+    let mut transaction = pool.begin().await?;
+    sqlx::query("DELETE FROM download_eviction_forecasts WHERE datasource = $1")
+        .bind(datasource)
+        .execute(&mut *transaction)
+        .await
+        .context("failed to clear previous eviction forecasts")?;
+    for chunk in entries.chunks(STORE_BATCH_SIZE) {
+        let ids: Vec<i64> = chunk.iter().map(|(id, _)| **id).collect();
+        let files: Vec<i64> = chunk.iter().map(|(_, a)| a.files).collect();
+        let bytes: Vec<i64> = chunk.iter().map(|(_, a)| a.cached_bytes).collect();
+        let first: Vec<DateTime<Utc>> = chunk.iter().map(|(_, a)| to_time(a.first.at)).collect();
+        let last: Vec<DateTime<Utc>> = chunk.iter().map(|(_, a)| to_time(a.last.at)).collect();
+        let reasons: Vec<&str> = chunk.iter().map(|(_, a)| a.last.reason.as_str()).collect();
+        // Joined against Downloads so a download deleted since its log entries were read is
+        // dropped instead of failing the foreign key.
+        sqlx::query(
+            "INSERT INTO download_eviction_forecasts \
+                (download_id, datasource, cached_files, cached_bytes, first_eviction_at, \
+                 evicted_at, reason, forecast_at) \
+             SELECT f.id, $1, f.files, f.bytes, f.first_at, f.last_at, f.reason, $8 \
+             FROM UNNEST($2::bigint[], $3::bigint[], $4::bigint[], $5::timestamptz[], \
+                         $6::timestamptz[], $7::text[]) \
+                  AS f(id, files, bytes, first_at, last_at, reason) \
+             JOIN \"Downloads\" d ON d.\"Id\" = f.id \
+             ON CONFLICT (download_id) DO UPDATE SET \
+                datasource = EXCLUDED.datasource, cached_files = EXCLUDED.cached_files, \
+                cached_bytes = EXCLUDED.cached_bytes, \
+                first_eviction_at = EXCLUDED.first_eviction_at, \
+                evicted_at = EXCLUDED.evicted_at, reason = EXCLUDED.reason, \
+                forecast_at = EXCLUDED.forecast_at",
+        )
+        .bind(datasource)
+        .bind(&ids)
+        .bind(&files)
+        .bind(&bytes)
+        .bind(&first)
+        .bind(&last)
+        .bind(&reasons)
+        .bind(forecast_at)
+        .execute(&mut *transaction)
+        .await
+        .context("failed to store eviction forecasts")?;
+    }
+    transaction.commit().await?;
+    Ok(())
+}
+
+async fn run(args: &Args, progress_path: Option<&Path>, reporter: &ProgressReporter) -> Result<()> {
+    let max_size = parse_nginx_size(&args.max_size)?;
+    let inactive_seconds = parse_nginx_time(&args.inactive)?;
+    if args.lookback_days <= 0 {
+        bail!("--lookback-days must be positive");
+    }
+    write_progress(
+        progress_path,
+        reporter,
+        "starting",
+        "signalr.cacheEvictionForecast.starting",
+        0,
+        0,
+    )?;
+
+    let pool = db::create_pool().await?;
+    if cache_inventory_store::last_refreshed_at(&pool, &args.datasource)
+        .await?
+        .is_none()
+    {
+        bail!(
+            "datasource '{}' has no cache inventory; run `cache_inventory refresh` first",
+            args.datasource
+        );
+    }
+    initialize_schema(&pool).await?;
+    let total_files: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM cache_inventory WHERE datasource = $1")
+            .bind(&args.datasource)
+            .fetch_one(&pool)
+            .await?;
+
+    let Some(mut files) = load_files(&pool, &args.datasource, |loaded| {
+        write_progress(
+            progress_path,
+            reporter,
+            "loading",
+            "signalr.cacheEvictionForecast.loading",
+            loaded,
+            total_files,
+        )
+    })
+    .await?
+    else {
+        eprintln!("[EvictionForecast] Cancelled while loading the inventory; nothing was stored");
+        return write_progress(
+            progress_path,
+            reporter,
+            "cancelled",
+            "signalr.cacheEvictionForecast.cancelled",
+            0,
+            total_files,
+        );
+    };
+
+    let model = CacheModel {
+        now: Utc::now().timestamp(),
+        max_size,
+        inactive_seconds,
+        fill_rate: fill_rate(&pool, &args.datasource, args.lookback_days).await?,
+    };
+    let used_bytes: i64 = files.iter().map(|file| file.size).sum();
+    let evictions = forecast_evictions(&mut files, &model);
+    let downloads = forecast_downloads(&files, &evictions);
+    let mut download_ids: Vec<i64> = downloads.keys().copied().collect();
+    download_ids.sort_unstable();
+    let games_by_download = load_games(&pool, &download_ids).await?;
+
+    write_progress(
+        progress_path,
+        reporter,
+        "storing",
+        "signalr.cacheEvictionForecast.storing",
+        files.len(),
+        total_files,
+    )?;
+    store_forecasts(&pool, &args.datasource, model.now, &downloads).await?;
+
+    let mut games: Vec<GameForecast> = forecast_games(&files, &evictions, &games_by_download)
+        .into_iter()
+        .map(|(key, aggregate)| GameForecast {
+            service: key.service,
+            game_app_id: key.game_app_id,
+            game_name: key.game_name,
+            files: aggregate.files,
+            cached_bytes: aggregate.cached_bytes,
+            first_eviction_at: format_time(aggregate.first.at),
+            evicted_at: format_time(aggregate.last.at),
+            reason: aggregate.last.reason,
+        })
+        .collect();
+    // RFC 3339 strings in UTC with whole seconds sort the same as the times they hold.
+    games.sort_by(|a, b| {
+        (&a.first_eviction_at, &a.game_name).cmp(&(&b.first_eviction_at, &b.game_name))
+    });
+
+    let full_at = after_writing(&model, max_size - used_bytes);
+    let report = ForecastReport {
+        success: true,
+        datasource: args.datasource.clone(),
+        max_size_bytes: max_size,
+        inactive_seconds,
+        used_bytes,
+        files: files.len(),
+        lookback_days: args.lookback_days,
+        fill_bytes_per_day: (model.fill_rate * SECONDS_PER_DAY as f64) as i64,
+        full_at: full_at.map(format_time),
+        downloads_forecast: downloads.len(),
+        games,
+        timestamp: progress_utils::current_timestamp(),
+    };
+    let payload =
+        serde_json::to_string_pretty(&report).context("Failed to serialize eviction forecast")?;
+    fs::write(&args.output_json, payload)
+        .with_context(|| format!("Failed to write output JSON to {}", args.output_json))?;
+
+    eprintln!(
+        "[EvictionForecast] {} files ({} of {} bytes), filling {} bytes/day: forecast {} downloads in {} games",
+        report.files,
+        report.used_bytes,
+        report.max_size_bytes,
+        report.fill_bytes_per_day,
+        report.downloads_forecast,
+        report.games.len()
+    );
+    write_progress(
+        progress_path,
+        reporter,
+        "completed",
+        "signalr.cacheEvictionForecast.complete",
+        report.files,
+        total_files,
+    )
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    cancel::install();
+    let args = Args::parse();
+    let reporter = ProgressReporter::new(args.progress);
+    let progress_path = (args.progress_json != "none").then(|| PathBuf::from(&args.progress_json));
+    let result = run(&args, progress_path.as_deref(), &reporter).await;
+    progress_events::finish_or_exit(
+        &reporter,
+        "signalr.cacheEvictionForecast.error.fatal",
+        result,
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(last_access: i64, size: i64, downloads: &[i64]) -> CachedFile {
+        CachedFile {
+            last_access,
+            size,
+            downloads: downloads.to_vec(),
+        }
+    }
+
+    #[test]
+    fn nginx_sizes_and_times_parse_like_nginx() {
+        assert_eq!(parse_nginx_size("1000g").unwrap(), 1000 << 30);
+        assert_eq!(parse_nginx_size("512M").unwrap(), 512 << 20);
+        assert_eq!(parse_nginx_size("4096").unwrap(), 4096);
+        assert!(parse_nginx_size("1t").is_err());
+        assert!(parse_nginx_size("0").is_err());
+
+        assert_eq!(parse_nginx_time("200d").unwrap(), 200 * SECONDS_PER_DAY);
+        assert_eq!(parse_nginx_time("1h30m").unwrap(), 5_400);
+        assert_eq!(parse_nginx_time("90").unwrap(), 90);
+        assert_eq!(parse_nginx_time("1500ms").unwrap(), 1);
+        assert_eq!(parse_nginx_time("1y").unwrap(), 365 * SECONDS_PER_DAY);
+        assert!(parse_nginx_time("10x").is_err());
+        assert!(parse_nginx_time("d").is_err());
+    }
+
+    #[test]
+    fn fill_pressure_evicts_oldest_first_and_inactive_caps_the_wait() {
+        let model = CacheModel {
+            now: 1_000_000,
+            max_size: 1_000,
+            inactive_seconds: 10_000,
+            fill_rate: 1.0,
+        };
+        // 900 of 1000 bytes used: 100 bytes of headroom before the oldest file must go.
+        let mut files = vec![
+            file(999_000, 300, &[2]),
+            file(995_000, 300, &[1]),
+            file(999_500, 300, &[2]),
+        ];
+        let evictions = forecast_evictions(&mut files, &model);
+        assert_eq!(files[0].last_access, 995_000);
+        assert_eq!(
+            evictions,
+            vec![
+                // Fill pressure after 100 bytes comes before inactivity at 995_000 + 10_000.
+                Eviction {
+                    at: 1_000_100,
+                    reason: EvictionReason::MaxSize
+                },
+                Eviction {
+                    at: 1_000_400,
+                    reason: EvictionReason::MaxSize
+                },
+                Eviction {
+                    at: 1_000_700,
+                    reason: EvictionReason::MaxSize
+                },
+            ]
+        );
+
+        let idle = CacheModel {
+            fill_rate: 0.0,
+            ..model
+        };
+        let evictions = forecast_evictions(&mut files, &idle);
+        assert_eq!(
+            evictions[0],
+            Eviction {
+                at: 1_005_000,
+                reason: EvictionReason::Inactive
+            }
+        );
+    }
+
+    #[test]
+    fn an_overfull_cache_sheds_its_tail_now_and_long_expired_files_are_due_now() {
+        let model = CacheModel {
+            now: 1_000_000,
+            max_size: 500,
+            inactive_seconds: 100,
+            fill_rate: 0.0,
+        };
+        let mut files = vec![file(999_990, 400, &[]), file(999_995, 400, &[])];
+        let evictions = forecast_evictions(&mut files, &model);
+        assert_eq!(evictions[0].at, model.now);
+        assert_eq!(evictions[0].reason, EvictionReason::MaxSize);
+        // Only 400 bytes remain once the first file goes, so the second waits for inactivity.
+        assert_eq!(evictions[1].at, 1_000_095);
+        assert_eq!(evictions[1].reason, EvictionReason::Inactive);
+
+        let mut stale = vec![file(10, 1, &[])];
+        assert_eq!(
+            forecast_evictions(&mut stale, &model)[0],
+            Eviction {
+                at: model.now,
+                reason: EvictionReason::Inactive
+            }
+        );
+    }
+
+    #[test]
+    fn games_count_shared_files_once_and_end_with_their_last_file() {
+        let files = vec![file(1, 10, &[1, 2]), file(2, 20, &[2]), file(3, 5, &[3])];
+        let evictions = vec![
+            Eviction {
+                at: 100,
+                reason: EvictionReason::MaxSize,
+            },
+            Eviction {
+                at: 200,
+                reason: EvictionReason::Inactive,
+            },
+            Eviction {
+                at: 300,
+                reason: EvictionReason::MaxSize,
+            },
+        ];
+        let downloads = forecast_downloads(&files, &evictions);
+        assert_eq!(downloads[&1].cached_bytes, 10);
+        assert_eq!(downloads[&2].files, 2);
+        assert_eq!(downloads[&2].first.at, 100);
+        assert_eq!(downloads[&2].last.reason, EvictionReason::Inactive);
+
+        let game = GameKey {
+            service: "steam".to_string(),
+            game_app_id: Some(10),
+            game_name: Some("Game".to_string()),
+        };
+        let games_by_download = HashMap::from([(1, game.clone()), (2, game.clone())]);
+        let games = forecast_games(&files, &evictions, &games_by_download);
+        assert_eq!(games.len(), 1);
+        assert_eq!(games[&game].files, 2);
+        assert_eq!(games[&game].cached_bytes, 30);
+        assert_eq!(games[&game].last.at, 200);
+    }
+}
@@ -0,0 +1,302 @@
+//! Exports one game's (or service's) cache slices into a portable archive (see `cache_archive`).
+//!
+//! The slices are the ones the target's removal head finds: this bin runs that head with
+//! `--plan` from the directory it lives in, exactly as `cache_policy` runs the heads, and copies
+//! every planned slice, including the ones a protected game shares. Slices whose `KEY:` header does
+//! not verify are left out and listed in the report. Nothing in the cache, logs or database is
+//! changed.
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use serde::Serialize;
+use serde_json::json;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::process::Command;
+
+use lancache_processor::cache_archive::ArchiveWriter;
+use lancache_processor::cache_utils;
+use lancache_processor::cancel;
+use lancache_processor::progress_events;
+use lancache_processor::progress_utils;
+use lancache_processor::removal_core;
+use lancache_processor::removal_plan::{PlanTarget, RemovalPlan};
+use progress_events::ProgressReporter;
+
+#[derive(Parser, Debug)]
+#[command(name = "cache_export")]
+#[command(about = "Copies a game's cache slices into a portable archive")]
+struct Args {
+    /// Directory containing log files
+    log_dir: String,
+
+    /// Cache directory root (e.g., /cache or H:/cache)
+    cache_dir: String,
+
+    /// New or empty directory the archive is written to
+    archive_dir: String,
+
+    /// Path to output JSON report
+    output_json: String,
+
+    /// Path to progress JSON file
+    progress_json: String,
+
+    #[command(flatten)]
+    target: Target,
+
+    /// Cache-key recipe of the source datasource: "monolithic" (default) | "bare_metal"
+    #[arg(
+        long = "key-scheme",
+        default_value = "monolithic",
+        value_parser = ["monolithic", "bare_metal"]
+    )]
+    key_scheme: String,
+
+    /// Emit JSON progress events to stdout
+    #[arg(short, long)]
+    progress: bool,
+}
+
+/// Exactly one of: `--steam-app-id`, `--epic-game-name`, `--service` with `--game-name`, or
+/// `--service` alone for the whole service.
+#[derive(clap::Args, Debug)]
+struct Target {
+    #[arg(long)]
+    steam_app_id: Option<u32>,
+    #[arg(long)]
+    epic_game_name: Option<String>,
+    /// Owning service of a name-keyed game (blizzard, riot, xbox), or the service to export whole
+    #[arg(long)]
+    service: Option<String>,
+    #[arg(long, requires = "service")]
+    game_name: Option<String>,
+}
+
+impl Target {
+    fn plan_target(&self) -> Result<PlanTarget> {
+        match (
+            self.steam_app_id,
+            self.epic_game_name.as_deref(),
+            self.service.as_deref(),
+            self.game_name.as_deref(),
+        ) {
+            (Some(game_app_id), None, None, None) => Ok(PlanTarget::Steam { game_app_id }),
+            (None, Some(game_name), None, None) if !game_name.trim().is_empty() => {
+                Ok(PlanTarget::Epic {
+                    game_name: game_name.to_string(),
+                })
+            }
+            (None, None, Some(service), Some(game_name))
+                if !service.trim().is_empty() && !game_name.trim().is_empty() =>
+            {
+                Ok(PlanTarget::Named {
+                    service: service.to_lowercase(),
+                    game_name: game_name.to_string(),
+                })
+            }
+            (None, None, Some(service), None) if !service.trim().is_empty() => {
+                Ok(PlanTarget::Service {
+                    service: service.to_lowercase(),
+                })
+            }
+            _ => bail!(
+                "name exactly one target: --steam-app-id, --epic-game-name, --service with --game-name, or --service"
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportReport {
+    archive_dir: String,
+    target: PlanTarget,
+    key_scheme: String,
+    slices: usize,
+    total_bytes: u64,
+    /// Planned slices whose `KEY:` header did not verify; not exported.
+    unverified_files: Vec<PathBuf>,
+    timestamp: String,
+}
+
+/// Runs the target's removal head with `--plan` and reads the plan back.
+async fn plan_with_head(args: &Args, target: &PlanTarget, work_dir: &Path) -> Result<RemovalPlan> {
+    let bin_dir = std::env::current_exe()
+        .context("failed to locate the running executable")?
+        .parent()
+        .map(Path::to_path_buf)
+        .context("the running executable has no parent directory")?;
+    let (head, argument) = target.head();
+    let binary = bin_dir.join(format!("{}{}", head, std::env::consts::EXE_SUFFIX));
+    let plan_path = work_dir.join("plan.json");
+    let status = Command::new(&binary)
+        .arg(&args.log_dir)
+        .arg(&args.cache_dir)
+        .arg(&argument)
+        .arg(&plan_path)
+        .arg(work_dir.join("plan_progress.json"))
+        .arg("--key-scheme")
+        .arg(&args.key_scheme)
+        .arg("--plan")
+        // Progress events belong to this run; the head's log lines still reach our stderr.
+        .stdout(Stdio::null())
+        .stderr(Stdio::inherit())
+        .status()
+        .await
+        .with_context(|| format!("failed to start {}", binary.display()))?;
+    if !status.success() {
+        bail!("{head} could not plan {target} ({status})");
+    }
+    RemovalPlan::read(&plan_path)
+}
+
+async fn run(args: &Args, progress_path: &Path, reporter: &ProgressReporter) -> Result<()> {
+    let target = args.target.plan_target()?;
+    let scheme = cache_utils::CacheKeyScheme::from_config_str(&args.key_scheme);
+    let cache_dir = Path::new(&args.cache_dir);
+    let archive_dir = Path::new(&args.archive_dir);
+    removal_core::write_progress(
+        progress_path,
+        reporter,
+        "starting",
+        "signalr.cacheExport.starting",
+        json!({ "target": target.to_string() }),
+        0.0,
+        0,
+        0,
+    )?;
+
+    let work_dir = tempfile::tempdir().context("failed to create a working directory")?;
+    let plan = plan_with_head(args, &target, work_dir.path()).await?;
+    let mut paths: Vec<&PathBuf> = plan
+        .files
+        .iter()
+        .map(|file| &file.path)
+        .chain(&plan.protected_files)
+        .collect();
+    paths.sort();
+    eprintln!(
+        "[CacheExport] {} slice(s) of {} planned, {} unverified",
+        paths.len(),
+        target,
+        plan.unverified_files.len()
+    );
+
+    let mut writer = ArchiveWriter::create(archive_dir, target.clone(), scheme, cache_dir)?;
+    let mut unverified_files = plan.unverified_files.clone();
+    for (index, path) in paths.iter().enumerate() {
+        if cancel::is_cancelled() {
+            reporter.emit_cancelled(
+                "signalr.cacheExport.complete",
+                json!({ "files": index, "totalFiles": paths.len() }),
+            );
+            eprintln!(
+                "[CacheExport] Cancelled; {} has no manifest and cannot be imported",
+                archive_dir.display()
+            );
+            return Ok(());
+        }
+        if !writer.add(path)? {
+            unverified_files.push(path.to_path_buf());
+        }
+        if index % 100 == 0 {
+            removal_core::write_progress(
+                progress_path,
+                reporter,
+                "exporting",
+                "signalr.cacheExport.copying",
+                json!({ "n": index, "total": paths.len() }),
+                5.0 + (index as f64 / paths.len().max(1) as f64) * 90.0,
+                index,
+                paths.len(),
+            )?;
+        }
+    }
+    let manifest = writer.finish()?;
+    unverified_files.sort();
+
+    let report = ExportReport {
+        archive_dir: args.archive_dir.clone(),
+        target,
+        key_scheme: manifest.key_scheme.clone(),
+        slices: manifest.slices.len(),
+        total_bytes: manifest.total_bytes,
+        unverified_files,
+        timestamp: progress_utils::current_timestamp(),
+    };
+    fs::write(&args.output_json, serde_json::to_string_pretty(&report)?)
+        .with_context(|| format!("Failed to write {}", args.output_json))?;
+    eprintln!(
+        "[CacheExport] Exported {} slice(s), {:.2} GB, to {}",
+        report.slices,
+        report.total_bytes as f64 / 1_073_741_824.0,
+        archive_dir.display()
+    );
+    removal_core::write_progress(
+        progress_path,
+        reporter,
+        "completed",
+        "signalr.cacheExport.complete",
+        json!({
+            "files": report.slices,
+            "gb": report.total_bytes as f64 / 1_073_741_824.0,
+            "unverified": report.unverified_files.len(),
+        }),
+        100.0,
+        report.slices,
+        report.slices,
+    )
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    cancel::install();
+    let args = Args::parse();
+    let reporter = ProgressReporter::new(args.progress);
+    let result = run(&args, Path::new(&args.progress_json), &reporter).await;
+    progress_events::finish_or_exit(&reporter, "signalr.cacheExport.error.fatal", result);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(targets: &[&str]) -> Result<PlanTarget> {
+        let mut argv = vec![
+            "cache_export",
+            "logs",
+            "cache",
+            "archive",
+            "out",
+            "progress",
+        ];
+        argv.extend_from_slice(targets);
+        Args::try_parse_from(argv)?.target.plan_target()
+    }
+
+    #[test]
+    fn exactly_one_target_is_named() {
+        assert_eq!(
+            parse(&["--steam-app-id", "440"]).unwrap(),
+            PlanTarget::Steam { game_app_id: 440 }
+        );
+        assert_eq!(
+            parse(&["--service", "Riot", "--game-name", "Valorant"]).unwrap(),
+            PlanTarget::Named {
+                service: "riot".into(),
+                game_name: "Valorant".into()
+            }
+        );
+        assert_eq!(
+            parse(&["--service", "wsus"]).unwrap(),
+            PlanTarget::Service {
+                service: "wsus".into()
+            }
+        );
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["--steam-app-id", "440", "--epic-game-name", "Fortnite"]).is_err());
+    }
+}
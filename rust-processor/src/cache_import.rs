@@ -0,0 +1,209 @@
+//! Places the slices of a `cache_export` archive into a cache (see `cache_archive`).
+//!
+//! Each slice goes to the path its key hashes to, owned like the cache root (or `--owner`) and
+//! with the mode it was exported with. A slice exported under the other key scheme is rekeyed for
+//! `--key-scheme`, and skipped when its key has no equivalent there. Slices the cache already holds
+//! are skipped, a different key under the same name is left alone, and a damaged copy is never
+//! placed; the run fails after the report is written if any copy was damaged. Logs and the
+//! database are not touched: the next access to an imported slice is an ordinary cache hit.
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use lancache_processor::cache_archive::{self, Archive, ImportDisposition, Placement};
+use lancache_processor::cache_utils::{self, RekeyBlocker};
+use lancache_processor::cancel;
+use lancache_processor::progress_events;
+use lancache_processor::progress_utils;
+use lancache_processor::removal_core;
+use progress_events::ProgressReporter;
+
+#[derive(Parser, Debug)]
+#[command(name = "cache_import")]
+#[command(about = "Places the slices of a cache_export archive into a cache")]
+struct Args {
+    /// Archive directory written by cache_export
+    archive_dir: String,
+
+    /// Cache directory root (e.g., /cache or H:/cache)
+    cache_dir: String,
+
+    /// Path to output JSON report
+    output_json: String,
+
+    /// Path to progress JSON file
+    progress_json: String,
+
+    /// Cache-key recipe of the target datasource: "monolithic" (default) | "bare_metal"
+    #[arg(
+        long = "key-scheme",
+        default_value = "monolithic",
+        value_parser = ["monolithic", "bare_metal"]
+    )]
+    key_scheme: String,
+
+    /// Owner of what the import creates, instead of the cache root's owner
+    #[arg(long, value_name = "UID:GID", value_parser = parse_owner)]
+    owner: Option<(u32, u32)>,
+
+    /// Emit JSON progress events to stdout
+    #[arg(short, long)]
+    progress: bool,
+}
+
+fn parse_owner(value: &str) -> Result<(u32, u32), String> {
+    let (uid, gid) = value
+        .split_once(':')
+        .ok_or_else(|| format!("expected UID:GID, got {value:?}"))?;
+    let id = |part: &str| {
+        part.trim()
+            .parse::<u32>()
+            .map_err(|_| format!("{part:?} is not a numeric id"))
+    };
+    Ok((id(uid)?, id(gid)?))
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ImportReport {
+    archive_dir: String,
+    cache_dir: String,
+    key_scheme: String,
+    imported: usize,
+    bytes_imported: u64,
+    already_cached: usize,
+    /// Names the cache already uses for another key; left untouched.
+    conflicts: Vec<String>,
+    /// Slices whose archived copy failed its size, hash or key check.
+    corrupt: Vec<String>,
+    /// Slices whose key has no equivalent in the target key scheme, by reason; skipped.
+    blocked: BTreeMap<RekeyBlocker, usize>,
+    /// Slices whose nginx header this build cannot rewrite for the target key; skipped.
+    unconvertible: Vec<String>,
+    cancelled: bool,
+    timestamp: String,
+}
+
+fn write_report(output_json: &str, report: &ImportReport) -> Result<()> {
+    fs::write(output_json, serde_json::to_string_pretty(report)?)
+        .with_context(|| format!("Failed to write {}", output_json))
+}
+
+async fn run(args: &Args, progress_path: &Path, reporter: &ProgressReporter) -> Result<()> {
+    let scheme = cache_utils::CacheKeyScheme::from_config_str(&args.key_scheme);
+    let cache_dir = PathBuf::from(&args.cache_dir);
+    let archive = Archive::open(Path::new(&args.archive_dir))?;
+    let mut placement = Placement::of_cache_dir(&cache_dir)?;
+    if args.owner.is_some() {
+        placement.owner = args.owner;
+    }
+    let slices = &archive.manifest.slices;
+    removal_core::write_progress(
+        progress_path,
+        reporter,
+        "starting",
+        "signalr.cacheImport.starting",
+        json!({ "target": archive.manifest.target.to_string(), "files": slices.len() }),
+        0.0,
+        0,
+        slices.len(),
+    )?;
+    eprintln!(
+        "[CacheImport] Importing {} slice(s) of {} into {}",
+        slices.len(),
+        archive.manifest.target,
+        cache_dir.display()
+    );
+
+    let mut report = ImportReport {
+        archive_dir: args.archive_dir.clone(),
+        cache_dir: args.cache_dir.clone(),
+        key_scheme: args.key_scheme.clone(),
+        ..ImportReport::default()
+    };
+    for (index, slice) in slices.iter().enumerate() {
+        if cancel::is_cancelled() {
+            report.cancelled = true;
+            break;
+        }
+        match cache_archive::import_slice(&archive, slice, &cache_dir, &placement, scheme)? {
+            ImportDisposition::Imported => {
+                report.imported += 1;
+                report.bytes_imported += slice.bytes;
+            }
+            ImportDisposition::AlreadyCached => report.already_cached += 1,
+            ImportDisposition::Conflict => report.conflicts.push(slice.name.clone()),
+            ImportDisposition::Corrupt => report.corrupt.push(slice.name.clone()),
+            ImportDisposition::Blocked(blocker) => *report.blocked.entry(blocker).or_default() += 1,
+            ImportDisposition::Unconvertible => report.unconvertible.push(slice.name.clone()),
+        }
+        if index % 100 == 0 {
+            removal_core::write_progress(
+                progress_path,
+                reporter,
+                "importing",
+                "signalr.cacheImport.placing",
+                json!({ "n": index, "total": slices.len() }),
+                (index as f64 / slices.len().max(1) as f64) * 95.0,
+                index,
+                slices.len(),
+            )?;
+        }
+    }
+    report.timestamp = progress_utils::current_timestamp();
+    write_report(&args.output_json, &report)?;
+    let skipped = report.blocked.values().sum::<usize>() + report.unconvertible.len();
+    eprintln!(
+        "[CacheImport] {} imported ({:.2} GB), {} already cached, {} conflict(s), {} corrupt, {} skipped",
+        report.imported,
+        report.bytes_imported as f64 / 1_073_741_824.0,
+        report.already_cached,
+        report.conflicts.len(),
+        report.corrupt.len(),
+        skipped
+    );
+
+    let context = json!({
+        "files": report.imported,
+        "gb": report.bytes_imported as f64 / 1_073_741_824.0,
+        "alreadyCached": report.already_cached,
+        "conflicts": report.conflicts.len(),
+        "skipped": skipped,
+    });
+    if report.cancelled {
+        reporter.emit_cancelled("signalr.cacheImport.complete", context);
+        return Ok(());
+    }
+    if !report.corrupt.is_empty() {
+        bail!(
+            "{} archived slice(s) failed verification and were not placed; see {}",
+            report.corrupt.len(),
+            args.output_json
+        );
+    }
+    removal_core::write_progress(
+        progress_path,
+        reporter,
+        "completed",
+        "signalr.cacheImport.complete",
+        context,
+        100.0,
+        slices.len(),
+        slices.len(),
+    )
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    cancel::install();
+    let args = Args::parse();
+    let reporter = ProgressReporter::new(args.progress);
+    let result = run(&args, Path::new(&args.progress_json), &reporter).await;
+    progress_events::finish_or_exit(&reporter, "signalr.cacheImport.error.fatal", result);
+    Ok(())
+}
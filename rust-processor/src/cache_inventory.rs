@@ -0,0 +1,265 @@
+//! Builds and queries the persistent cache inventory (see `cache_inventory_store`).
+//!
+//! `refresh` walks one datasource's cache directory and brings its `cache_inventory` rows up to
+//! date, reading the header only of files that are new or changed since the last walk. `report`
+//! answers from the database alone: bytes per service, files no log entry accounts for, and
+//! bytes per game.
+
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use serde::Serialize;
+use serde_json::json;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use lancache_processor::cache_inventory_store::{
+    self, GameUsage, OrphanReport, RefreshSummary, ServiceUsage,
+};
+use lancache_processor::cancel;
+use lancache_processor::db;
+use lancache_processor::progress_events;
+use lancache_processor::progress_utils;
+use progress_events::ProgressReporter;
+
+#[derive(Parser, Debug)]
+#[command(name = "cache_inventory")]
+#[command(about = "Indexes cache files by their KEY headers and reports from the index")]
+struct Args {
+    #[command(subcommand)]
+    command: Commands,
+    /// Datasource the cache directory belongs to, as recorded in LogEntries
+    #[arg(long, default_value = "default", global = true)]
+    datasource: String,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Walk the cache directory and update the inventory incrementally.
+    Refresh {
+        cache_dir: String,
+        output_json: String,
+        /// Path to progress JSON file (use "none" to skip)
+        #[arg(default_value = "none")]
+        progress_json: String,
+        /// Emit JSON progress events to stdout
+        #[arg(short, long)]
+        progress: bool,
+    },
+    /// Write size-by-service, orphan and per-game totals from the stored inventory.
+    Report {
+        output_json: String,
+        /// How many of the largest orphaned files to list
+        #[arg(long, default_value_t = 100)]
+        largest_orphans: i64,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProgressData {
+    status: String,
+    stage_key: String,
+    context: serde_json::Value,
+    percent_complete: f64,
+    directories_scanned: usize,
+    total_directories: usize,
+    files_discovered: usize,
+    timestamp: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RefreshReport {
+    success: bool,
+    datasource: String,
+    #[serde(flatten)]
+    summary: RefreshSummary,
+    timestamp: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct InventoryReport {
+    datasource: String,
+    /// Unix seconds of the last completed refresh; None if the cache was never walked.
+    refreshed_at: Option<i64>,
+    services: Vec<ServiceUsage>,
+    orphans: OrphanReport,
+    games: Vec<GameUsage>,
+    timestamp: String,
+}
+
+/// Writes the progress file (when a path was supplied) and THEN emits the matching stdout event.
+fn write_progress(
+    progress_path: Option<&Path>,
+    reporter: &ProgressReporter,
+    status: &str,
+    stage_key: &str,
+    summary: &RefreshSummary,
+) -> Result<()> {
+    let percent_complete = match status {
+        "completed" => 100.0,
+        _ if summary.total_directories == 0 => 0.0,
+        _ => summary.directories_scanned as f64 / summary.total_directories as f64 * 100.0,
+    };
+    let context = json!({
+        "directoriesScanned": summary.directories_scanned,
+        "totalDirectories": summary.total_directories,
+        "filesDiscovered": summary.files_discovered,
+        "filesReused": summary.files_reused,
+        "filesIndexed": summary.files_indexed,
+        "filesRemoved": summary.files_removed,
+        "totalBytes": summary.total_bytes,
+    });
+    if let Some(path) = progress_path {
+        let progress = ProgressData {
+            status: status.to_string(),
+            stage_key: stage_key.to_string(),
+            context: context.clone(),
+            percent_complete,
+            directories_scanned: summary.directories_scanned,
+            total_directories: summary.total_directories,
+            files_discovered: summary.files_discovered,
+            timestamp: progress_utils::current_timestamp(),
+        };
+        progress_utils::write_progress_json(path, &progress)?;
+    }
+
+    match status {
+        "starting" => reporter.emit_started(stage_key, context),
+        "completed" => reporter.emit_complete(stage_key, context),
+        "cancelled" => reporter.emit_cancelled(stage_key, context),
+        _ => reporter.emit_progress(percent_complete, stage_key, context),
+    }
+
+    Ok(())
+}
+
+fn write_json<T: Serialize>(output_json: &str, value: &T) -> Result<()> {
+    let payload =
+        serde_json::to_string_pretty(value).context("Failed to serialize inventory output")?;
+    fs::write(output_json, payload)
+        .with_context(|| format!("Failed to write output JSON to {}", output_json))
+}
+
+async fn refresh(
+    datasource: &str,
+    cache_dir: &str,
+    output_json: &str,
+    progress_path: Option<&Path>,
+    reporter: &ProgressReporter,
+) -> Result<()> {
+    write_progress(
+        progress_path,
+        reporter,
+        "starting",
+        "signalr.cacheInventory.starting",
+        &RefreshSummary::default(),
+    )?;
+    let pool = db::create_pool().await?;
+    let summary =
+        cache_inventory_store::refresh(&pool, datasource, Path::new(cache_dir), |summary| {
+            write_progress(
+                progress_path,
+                reporter,
+                "scanning",
+                "signalr.cacheInventory.scanning",
+                summary,
+            )
+        })
+        .await?;
+
+    write_json(
+        output_json,
+        &RefreshReport {
+            success: !summary.cancelled,
+            datasource: datasource.to_string(),
+            summary: summary.clone(),
+            timestamp: progress_utils::current_timestamp(),
+        },
+    )?;
+    if summary.cancelled {
+        eprintln!(
+            "[CacheInventory] Cancelled after {} of {} directories; nothing was pruned",
+            summary.directories_scanned, summary.total_directories
+        );
+        return write_progress(
+            progress_path,
+            reporter,
+            "cancelled",
+            "signalr.cacheInventory.cancelled",
+            &summary,
+        );
+    }
+    eprintln!(
+        "[CacheInventory] {} files ({} bytes): {} reused, {} indexed, {} unreadable, {} removed",
+        summary.files_discovered,
+        summary.total_bytes,
+        summary.files_reused,
+        summary.files_indexed,
+        summary.files_unreadable,
+        summary.files_removed
+    );
+    write_progress(
+        progress_path,
+        reporter,
+        "completed",
+        "signalr.cacheInventory.complete",
+        &summary,
+    )
+}
+
+async fn report(datasource: &str, output_json: &str, largest_orphans: i64) -> Result<()> {
+    let pool = db::create_pool().await?;
+    let refreshed_at = cache_inventory_store::last_refreshed_at(&pool, datasource).await?;
+    if refreshed_at.is_none() {
+        eprintln!(
+            "[CacheInventory] {} has never been refreshed; the report is empty",
+            datasource
+        );
+    }
+    let report = InventoryReport {
+        datasource: datasource.to_string(),
+        refreshed_at,
+        services: cache_inventory_store::size_by_service(&pool, datasource).await?,
+        orphans: cache_inventory_store::orphans(&pool, datasource, largest_orphans.max(0)).await?,
+        games: cache_inventory_store::game_sizes(&pool, datasource).await?,
+        timestamp: progress_utils::current_timestamp(),
+    };
+    write_json(output_json, &report)
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    cancel::install();
+    let args = Args::parse();
+    match &args.command {
+        Commands::Refresh {
+            cache_dir,
+            output_json,
+            progress_json,
+            progress,
+        } => {
+            let reporter = ProgressReporter::new(*progress);
+            let progress_path = (progress_json != "none").then(|| PathBuf::from(progress_json));
+            let result = refresh(
+                &args.datasource,
+                cache_dir,
+                output_json,
+                progress_path.as_deref(),
+                &reporter,
+            )
+            .await;
+            progress_events::finish_or_exit(
+                &reporter,
+                "signalr.cacheInventory.error.fatal",
+                result,
+            );
+            Ok(())
+        }
+        Commands::Report {
+            output_json,
+            largest_orphans,
+        } => report(&args.datasource, output_json, *largest_orphans).await,
+    }
+}
@@ -0,0 +1,690 @@
+//! Persistent inventory of the files in a cache directory, keyed by digest.
+//!
+//! Every other cache tool starts from a database URL and hashes its way to a path, so a file no
+//! surviving LogEntries row points at is invisible to all of them. The inventory goes the other
+//! way: it walks the cache, reads each file's `KEY:` header, and stores what the key says
+//! (service, URL, slice range) next to the file's size, mtime and header timestamps. Size by
+//! service, orphan detection and per-game sizing then become queries against `cache_inventory`.
+//!
+//! A refresh is incremental the same way the structural scan is. Each row carries the file's
+//! `FileFingerprint`, the identity `structural_file_state` stores, and a file whose fingerprint
+//! still matches (`same_file`) is not opened again. Only new or rewritten files cost a header
+//! read, and rows for files that are gone are deleted once a walk has completed.
+
+use crate::cache_corruption_detector::FileFingerprint;
+use crate::cache_structural_scanner::{self, CacheFileHeader};
+use crate::{cache_utils, cancel};
+use anyhow::{bail, Context, Result};
+use futures_util::TryStreamExt;
+use serde::Serialize;
+use sqlx::{Connection, PgPool, Row};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Files looked up, read and upserted per round trip.
+pub const INVENTORY_BATCH_SIZE: usize = 500;
+
+/// Serializes first-time table creation against a second refresh starting on the same database.
+const SCHEMA_SETUP_LOCK_KEY: i64 = i64::from_be_bytes(*b"cacheinv");
+
+/// One file as the inventory records it. `service` is "unknown" when the header held no key
+/// this build could split; such files still count towards sizes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InventoryRow {
+    pub digest: u128,
+    pub cache_key: Option<String>,
+    pub service: String,
+    pub url: Option<String>,
+    pub slice: Option<(u64, u64)>,
+    pub header_date: Option<i64>,
+    pub last_modified: Option<i64>,
+    pub valid_until: Option<i64>,
+    pub fingerprint: FileFingerprint,
+}
+
+impl InventoryRow {
+    pub fn new(
+        digest: u128,
+        fingerprint: FileFingerprint,
+        header: Option<CacheFileHeader>,
+    ) -> Self {
+        let header = header.unwrap_or(CacheFileHeader {
+            cache_key: None,
+            valid_until: None,
+            last_modified: None,
+            date: None,
+        });
+        let parts = header
+            .cache_key
+            .as_deref()
+            .and_then(cache_utils::split_cache_key);
+        Self {
+            digest,
+            service: parts
+                .as_ref()
+                .map_or_else(|| "unknown".to_string(), |parts| parts.service.clone()),
+            url: parts.as_ref().map(|parts| parts.url.clone()),
+            slice: parts.and_then(|parts| parts.slice),
+            cache_key: header.cache_key,
+            header_date: header.date,
+            last_modified: header.last_modified,
+            valid_until: header.valid_until,
+            fingerprint,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RefreshSummary {
+    pub directories_scanned: usize,
+    pub total_directories: usize,
+    pub files_discovered: usize,
+    /// Fingerprint still matched the stored row, so the file was not opened.
+    pub files_reused: usize,
+    /// New or changed files whose header was read and stored.
+    pub files_indexed: usize,
+    /// Files whose header could not be read. They are stored with what the stat gave.
+    pub files_unreadable: usize,
+    pub files_removed: usize,
+    pub total_bytes: u64,
+    pub cancelled: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceUsage {
+    pub service: String,
+    pub files: i64,
+    pub total_bytes: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InventoryFile {
+    pub digest: String,
+    pub service: String,
+    pub url: Option<String>,
+    pub size_bytes: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrphanReport {
+    pub files: i64,
+    pub total_bytes: i64,
+    pub by_service: Vec<ServiceUsage>,
+    /// Largest orphaned files first.
+    pub largest: Vec<InventoryFile>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GameUsage {
+    pub service: String,
+    pub game_app_id: Option<i64>,
+    pub game_name: Option<String>,
+    pub files: i64,
+    pub total_bytes: i64,
+}
+
+pub async fn initialize_schema(pool: &PgPool) -> Result<()> {
+    let mut connection = pool.acquire().await?;
+    let mut transaction = connection
+        .begin()
+        .await
+        .context("failed to begin cache inventory schema setup")?;
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(SCHEMA_SETUP_LOCK_KEY)
+        .execute(&mut *transaction)
+        .await?;
+    // Keyed by datasource name rather than the structural state scope: the scope is an opaque
+    // hash, and every query below joins on LogEntries."Datasource".
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS cache_inventory( \
+            datasource TEXT NOT NULL, \
+            digest BYTEA NOT NULL CHECK(octet_length(digest) = 16), \
+            cache_key TEXT NULL, \
+            service TEXT NOT NULL, \
+            url TEXT NULL, \
+            slice_start BIGINT NULL, \
+            slice_end BIGINT NULL, \
+            size_bytes BIGINT NOT NULL, \
+            dev BIGINT NOT NULL, ino BIGINT NOT NULL, \
+            mtime_ns BIGINT NOT NULL, ctime_ns BIGINT NOT NULL, \
+            header_date BIGINT NULL, \
+            last_modified BIGINT NULL, \
+            valid_until BIGINT NULL, \
+            indexed_at BIGINT NOT NULL, \
+            PRIMARY KEY(datasource, digest) \
+         )",
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query(
+        "CREATE INDEX IF NOT EXISTS idx_cache_inventory_service \
+         ON cache_inventory(datasource, service)",
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query("CREATE INDEX IF NOT EXISTS idx_cache_inventory_url ON cache_inventory(url)")
+        .execute(&mut *transaction)
+        .await?;
+    // When each datasource was last walked to the end, so a caller can tell a stale inventory
+    // from an empty cache.
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS cache_inventory_refreshes( \
+            datasource TEXT PRIMARY KEY, \
+            cache_root TEXT NOT NULL, \
+            refreshed_at BIGINT NOT NULL, \
+            files BIGINT NOT NULL, \
+            total_bytes BIGINT NOT NULL \
+         )",
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+/// Session lock key for one datasource's refresh: two walks of the same cache would each delete
+/// what the other had not reached yet.
+fn refresh_lock_key(datasource: &str) -> i64 {
+    let digest = md5::compute(format!("cache_inventory\n{datasource}"));
+    i64::from_be_bytes(digest.0[..8].try_into().expect("md5 digest is 16 bytes"))
+}
+
+/// Walks `cache_root` and brings the datasource's inventory up to date. `on_progress` is called
+/// after every hash directory with the running totals. A cancelled walk keeps what it stored
+/// but deletes nothing, since it never proved the unvisited files are gone.
+pub async fn refresh<F>(
+    pool: &PgPool,
+    datasource: &str,
+    cache_root: &Path,
+    mut on_progress: F,
+) -> Result<RefreshSummary>
+where
+    F: FnMut(&RefreshSummary) -> Result<()>,
+{
+    initialize_schema(pool).await?;
+    let root = fs::canonicalize(cache_root)
+        .with_context(|| format!("failed to resolve cache root {}", cache_root.display()))?;
+    let mut lock = pool.acquire().await?;
+    let locked: bool = sqlx::query_scalar("SELECT pg_try_advisory_lock($1)")
+        .bind(refresh_lock_key(datasource))
+        .fetch_one(&mut *lock)
+        .await
+        .context("failed to take cache inventory lock")?;
+    if !locked {
+        bail!("another cache inventory refresh is already running for {datasource}");
+    }
+    let result = refresh_locked(pool, datasource, &root, &mut on_progress).await;
+    let _ = sqlx::query("SELECT pg_advisory_unlock($1)")
+        .bind(refresh_lock_key(datasource))
+        .execute(&mut *lock)
+        .await;
+    result
+}
+
+async fn refresh_locked<F>(
+    pool: &PgPool,
+    datasource: &str,
+    root: &Path,
+    on_progress: &mut F,
+) -> Result<RefreshSummary>
+where
+    F: FnMut(&RefreshSummary) -> Result<()>,
+{
+    let top_level = sorted_directories(root)?;
+    let mut summary = RefreshSummary {
+        total_directories: top_level.len(),
+        ..RefreshSummary::default()
+    };
+    let mut seen = HashSet::<u128>::new();
+    let mut batch = Vec::with_capacity(INVENTORY_BATCH_SIZE);
+    on_progress(&summary)?;
+    for directory in top_level {
+        for nested in sorted_directories(&directory)? {
+            let entries = match fs::read_dir(&nested) {
+                Ok(entries) => entries,
+                // nginx's cache manager removes emptied directories under a running walk.
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(error) => {
+                    return Err(error)
+                        .with_context(|| format!("failed to list {}", nested.display()))
+                }
+            };
+            for entry in entries {
+                let path = entry?.path();
+                let Some(digest) = cache_utils::strict_cache_file_digest(root, &path) else {
+                    continue;
+                };
+                batch.push((digest, path));
+                if batch.len() >= INVENTORY_BATCH_SIZE {
+                    process_batch(pool, datasource, &mut batch, &mut seen, &mut summary).await?;
+                }
+            }
+            if cancel::is_cancelled() {
+                summary.cancelled = true;
+                break;
+            }
+        }
+        process_batch(pool, datasource, &mut batch, &mut seen, &mut summary).await?;
+        if summary.cancelled {
+            break;
+        }
+        summary.directories_scanned += 1;
+        on_progress(&summary)?;
+    }
+    if summary.cancelled {
+        return Ok(summary);
+    }
+
+    summary.files_removed = delete_unseen(pool, datasource, &seen).await?;
+    sqlx::query(
+        "INSERT INTO cache_inventory_refreshes(datasource, cache_root, refreshed_at, files, total_bytes) \
+         VALUES($1, $2, $3, $4, $5) \
+         ON CONFLICT (datasource) DO UPDATE SET cache_root = EXCLUDED.cache_root, \
+            refreshed_at = EXCLUDED.refreshed_at, files = EXCLUDED.files, \
+            total_bytes = EXCLUDED.total_bytes",
+    )
+    .bind(datasource)
+    .bind(root.display().to_string())
+    .bind(unix_timestamp()?)
+    .bind(seen.len() as i64)
+    .bind(summary.total_bytes as i64)
+    .execute(pool)
+    .await
+    .context("failed to record cache inventory refresh")?;
+    Ok(summary)
+}
+
+fn sorted_directories(parent: &Path) -> Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(parent) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => {
+            return Err(error).with_context(|| format!("failed to list {}", parent.display()))
+        }
+    };
+    let mut directories = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        // file_type() does not follow symlinks, so a linked directory is never walked into.
+        if entry.file_type()?.is_dir() {
+            directories.push(entry.path());
+        }
+    }
+    directories.sort();
+    Ok(directories)
+}
+
+async fn process_batch(
+    pool: &PgPool,
+    datasource: &str,
+    batch: &mut Vec<(u128, PathBuf)>,
+    seen: &mut HashSet<u128>,
+    summary: &mut RefreshSummary,
+) -> Result<()> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    let mut current = Vec::with_capacity(batch.len());
+    for (digest, path) in batch.drain(..) {
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(error) => {
+                return Err(error).with_context(|| format!("failed to stat {}", path.display()))
+            }
+        };
+        if !metadata.file_type().is_file() {
+            continue;
+        }
+        current.push((
+            digest,
+            path,
+            cache_structural_scanner::fingerprint(&metadata),
+        ));
+    }
+
+    let stored = stored_fingerprints(pool, datasource, &current).await?;
+    let mut changed = Vec::new();
+    for (digest, path, fingerprint) in current {
+        summary.files_discovered += 1;
+        summary.total_bytes += fingerprint.len;
+        seen.insert(digest);
+        if stored
+            .get(&digest)
+            .is_some_and(|stored| stored.same_file(&fingerprint))
+        {
+            summary.files_reused += 1;
+            continue;
+        }
+        let header = match cache_structural_scanner::read_cache_header(&path) {
+            Ok(header) => header,
+            Err(error) => {
+                eprintln!("[CacheInventory] {error:#}");
+                None
+            }
+        };
+        if header
+            .as_ref()
+            .is_none_or(|header| header.cache_key.is_none())
+        {
+            summary.files_unreadable += 1;
+        } else {
+            summary.files_indexed += 1;
+        }
+        changed.push(InventoryRow::new(digest, fingerprint, header));
+    }
+    upsert_rows(pool, datasource, &changed).await
+}
+
+async fn stored_fingerprints(
+    pool: &PgPool,
+    datasource: &str,
+    files: &[(u128, PathBuf, FileFingerprint)],
+) -> Result<HashMap<u128, FileFingerprint>> {
+    let digests = files
+        .iter()
+        .map(|(digest, _, _)| digest.to_be_bytes().to_vec())
+        .collect::<Vec<_>>();
+    let rows = sqlx::query(
+        "SELECT digest, dev, ino, size_bytes, mtime_ns, ctime_ns FROM cache_inventory \
+         WHERE datasource = $1 AND digest = ANY($2)",
+    )
+    .bind(datasource)
+    .bind(digests)
+    .fetch_all(pool)
+    .await
+    .context("failed to look up cache inventory fingerprints")?;
+    let mut stored = HashMap::with_capacity(rows.len());
+    for row in rows {
+        let digest: Vec<u8> = row.try_get("digest")?;
+        stored.insert(
+            decode_digest(&digest)?,
+            FileFingerprint {
+                dev: row.try_get::<i64, _>("dev")? as u64,
+                ino: row.try_get::<i64, _>("ino")? as u64,
+                len: row.try_get::<i64, _>("size_bytes")? as u64,
+                mtime_ns: row.try_get("mtime_ns")?,
+                ctime_ns: row.try_get("ctime_ns")?,
+            },
+        );
+    }
+    Ok(stored)
+}
+
+async fn upsert_rows(pool: &PgPool, datasource: &str, rows: &[InventoryRow]) -> Result<()> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+    sqlx::query(
+        "INSERT INTO cache_inventory(datasource, digest, cache_key, service, url, slice_start, \
+            slice_end, size_bytes, dev, ino, mtime_ns, ctime_ns, header_date, last_modified, \
+            valid_until, indexed_at) \
+         SELECT $1, *, $16 FROM UNNEST($2::bytea[], $3::text[], $4::text[], $5::text[], \
+            $6::int8[], $7::int8[], $8::int8[], $9::int8[], $10::int8[], $11::int8[], \
+            $12::int8[], $13::int8[], $14::int8[], $15::int8[]) \
+         ON CONFLICT (datasource, digest) DO UPDATE SET \
+            cache_key = EXCLUDED.cache_key, service = EXCLUDED.service, url = EXCLUDED.url, \
+            slice_start = EXCLUDED.slice_start, slice_end = EXCLUDED.slice_end, \
+            size_bytes = EXCLUDED.size_bytes, dev = EXCLUDED.dev, ino = EXCLUDED.ino, \
+            mtime_ns = EXCLUDED.mtime_ns, ctime_ns = EXCLUDED.ctime_ns, \
+            header_date = EXCLUDED.header_date, last_modified = EXCLUDED.last_modified, \
+            valid_until = EXCLUDED.valid_until, indexed_at = EXCLUDED.indexed_at",
+    )
+    .bind(datasource)
+    .bind(
+        rows.iter()
+            .map(|r| r.digest.to_be_bytes().to_vec())
+            .collect::<Vec<_>>(),
+    )
+    .bind(rows.iter().map(|r| r.cache_key.clone()).collect::<Vec<_>>())
+    .bind(rows.iter().map(|r| r.service.clone()).collect::<Vec<_>>())
+    .bind(rows.iter().map(|r| r.url.clone()).collect::<Vec<_>>())
+    .bind(
+        rows.iter()
+            .map(|r| r.slice.map(|s| s.0 as i64))
+            .collect::<Vec<_>>(),
+    )
+    .bind(
+        rows.iter()
+            .map(|r| r.slice.map(|s| s.1 as i64))
+            .collect::<Vec<_>>(),
+    )
+    .bind(
+        rows.iter()
+            .map(|r| r.fingerprint.len as i64)
+            .collect::<Vec<_>>(),
+    )
+    .bind(
+        rows.iter()
+            .map(|r| r.fingerprint.dev as i64)
+            .collect::<Vec<_>>(),
+    )
+    .bind(
+        rows.iter()
+            .map(|r| r.fingerprint.ino as i64)
+            .collect::<Vec<_>>(),
+    )
+    .bind(
+        rows.iter()
+            .map(|r| r.fingerprint.mtime_ns)
+            .collect::<Vec<_>>(),
+    )
+    .bind(
+        rows.iter()
+            .map(|r| r.fingerprint.ctime_ns)
+            .collect::<Vec<_>>(),
+    )
+    .bind(rows.iter().map(|r| r.header_date).collect::<Vec<_>>())
+    .bind(rows.iter().map(|r| r.last_modified).collect::<Vec<_>>())
+    .bind(rows.iter().map(|r| r.valid_until).collect::<Vec<_>>())
+    .bind(unix_timestamp()?)
+    .execute(pool)
+    .await
+    .context("failed to store cache inventory rows")?;
+    Ok(())
+}
+
+/// Deletes the rows of files the completed walk did not find. The stored digests are streamed
+/// and compared in memory instead of stamping every seen row, which would rewrite the whole
+/// table on each refresh just to prove nothing changed.
+async fn delete_unseen(pool: &PgPool, datasource: &str, seen: &HashSet<u128>) -> Result<usize> {
+    let mut gone = Vec::new();
+    {
+        let mut rows = sqlx::query("SELECT digest FROM cache_inventory WHERE datasource = $1")
+            .bind(datasource)
+            .fetch(pool);
+        while let Some(row) = rows.try_next().await? {
+            let digest: Vec<u8> = row.try_get("digest")?;
+            if !seen.contains(&decode_digest(&digest)?) {
+                gone.push(digest);
+            }
+        }
+    }
+    for chunk in gone.chunks(INVENTORY_BATCH_SIZE) {
+        sqlx::query("DELETE FROM cache_inventory WHERE datasource = $1 AND digest = ANY($2)")
+            .bind(datasource)
+            .bind(chunk)
+            .execute(pool)
+            .await
+            .context("failed to delete vanished cache inventory rows")?;
+    }
+    Ok(gone.len())
+}
+
+/// When the datasource was last walked to the end, or None if it never was.
+pub async fn last_refreshed_at(pool: &PgPool, datasource: &str) -> Result<Option<i64>> {
+    initialize_schema(pool).await?;
+    Ok(sqlx::query_scalar(
+        "SELECT refreshed_at FROM cache_inventory_refreshes WHERE datasource = $1",
+    )
+    .bind(datasource)
+    .fetch_optional(pool)
+    .await?)
+}
+
+pub async fn size_by_service(pool: &PgPool, datasource: &str) -> Result<Vec<ServiceUsage>> {
+    initialize_schema(pool).await?;
+    let rows = sqlx::query(
+        "SELECT service, COUNT(*) AS files, COALESCE(SUM(size_bytes), 0)::bigint AS total_bytes \
+         FROM cache_inventory WHERE datasource = $1 \
+         GROUP BY service ORDER BY total_bytes DESC, service",
+    )
+    .bind(datasource)
+    .fetch_all(pool)
+    .await
+    .context("failed to sum cache inventory by service")?;
+    rows.iter().map(service_usage).collect()
+}
+
+/// Files no LogEntries row of this datasource requests. A file whose key could not be read has
+/// no URL and is always counted here: nothing in the database can be traced to it.
+pub async fn orphans(pool: &PgPool, datasource: &str, largest: i64) -> Result<OrphanReport> {
+    initialize_schema(pool).await?;
+    const ORPHAN_FILTER: &str = "i.datasource = $1 AND NOT EXISTS ( \
+            SELECT 1 FROM \"LogEntries\" l \
+            WHERE l.\"Datasource\" = i.datasource AND l.\"Url\" = i.url)";
+    let by_service = sqlx::query(&format!(
+        "SELECT i.service, COUNT(*) AS files, COALESCE(SUM(i.size_bytes), 0)::bigint AS total_bytes \
+         FROM cache_inventory i WHERE {ORPHAN_FILTER} \
+         GROUP BY i.service ORDER BY total_bytes DESC, i.service"
+    ))
+    .bind(datasource)
+    .fetch_all(pool)
+    .await
+    .context("failed to find orphaned cache files")?
+    .iter()
+    .map(service_usage)
+    .collect::<Result<Vec<_>>>()?;
+    let largest = sqlx::query(&format!(
+        "SELECT i.digest, i.service, i.url, i.size_bytes FROM cache_inventory i \
+         WHERE {ORPHAN_FILTER} ORDER BY i.size_bytes DESC, i.digest LIMIT $2"
+    ))
+    .bind(datasource)
+    .bind(largest)
+    .fetch_all(pool)
+    .await
+    .context("failed to list the largest orphaned cache files")?
+    .iter()
+    .map(|row| {
+        let digest: Vec<u8> = row.try_get("digest")?;
+        Ok(InventoryFile {
+            digest: format!("{:032x}", decode_digest(&digest)?),
+            service: row.try_get("service")?,
+            url: row.try_get("url")?,
+            size_bytes: row.try_get("size_bytes")?,
+        })
+    })
+    .collect::<Result<Vec<_>>>()?;
+    Ok(OrphanReport {
+        files: by_service.iter().map(|s| s.files).sum(),
+        total_bytes: by_service.iter().map(|s| s.total_bytes).sum(),
+        by_service,
+        largest,
+    })
+}
+
+/// Bytes on disk per game, attributing each file through the downloads that requested its URL.
+/// A file shared by two games (a common redistributable depot) counts towards both.
+pub async fn game_sizes(pool: &PgPool, datasource: &str) -> Result<Vec<GameUsage>> {
+    initialize_schema(pool).await?;
+    let rows = sqlx::query(
+        "SELECT g.service, g.game_app_id, g.game_name, COUNT(*) AS files, \
+            COALESCE(SUM(i.size_bytes), 0)::bigint AS total_bytes \
+         FROM cache_inventory i \
+         JOIN LATERAL ( \
+            SELECT DISTINCT d.\"Service\" AS service, d.\"GameAppId\" AS game_app_id, \
+                d.\"GameName\" AS game_name \
+            FROM \"LogEntries\" l JOIN \"Downloads\" d ON d.\"Id\" = l.\"DownloadId\" \
+            WHERE l.\"Datasource\" = i.datasource AND l.\"Url\" = i.url \
+              AND (d.\"GameAppId\" IS NOT NULL OR d.\"GameName\" IS NOT NULL) \
+         ) g ON TRUE \
+         WHERE i.datasource = $1 \
+         GROUP BY g.service, g.game_app_id, g.game_name \
+         ORDER BY total_bytes DESC, g.game_name",
+    )
+    .bind(datasource)
+    .fetch_all(pool)
+    .await
+    .context("failed to size games from the cache inventory")?;
+    rows.iter()
+        .map(|row| {
+            Ok(GameUsage {
+                service: row.try_get("service")?,
+                game_app_id: row.try_get("game_app_id")?,
+                game_name: row.try_get("game_name")?,
+                files: row.try_get("files")?,
+                total_bytes: row.try_get("total_bytes")?,
+            })
+        })
+        .collect()
+}
+
+fn service_usage(row: &sqlx::postgres::PgRow) -> Result<ServiceUsage> {
+    Ok(ServiceUsage {
+        service: row.try_get("service")?,
+        files: row.try_get("files")?,
+        total_bytes: row.try_get("total_bytes")?,
+    })
+}
+
+fn decode_digest(bytes: &[u8]) -> Result<u128> {
+    let value: [u8; 16] = bytes
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("cache inventory digest was not 16 bytes"))?;
+    Ok(u128::from_be_bytes(value))
+}
+
+fn unix_timestamp() -> Result<i64> {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .context("system clock is before the Unix epoch")?
+        .as_secs();
+    i64::try_from(seconds).context("system clock overflowed the inventory timestamp")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fingerprint() -> FileFingerprint {
+        FileFingerprint {
+            dev: 1,
+            ino: 2,
+            len: 1_048_576,
+            mtime_ns: 3,
+            ctime_ns: 4,
+        }
+    }
+
+    #[test]
+    fn a_readable_key_fills_service_url_and_slice() {
+        let header = CacheFileHeader {
+            cache_key: Some("steam/depot/440/chunk/abcbytes=1048576-2097151".to_string()),
+            valid_until: Some(1_900_000_000),
+            last_modified: None,
+            date: Some(1_700_000_000),
+        };
+        let row = InventoryRow::new(7, fingerprint(), Some(header));
+        assert_eq!(row.service, "steam");
+        assert_eq!(row.url.as_deref(), Some("/depot/440/chunk/abc"));
+        assert_eq!(row.slice, Some((1_048_576, 2_097_151)));
+        assert_eq!(row.header_date, Some(1_700_000_000));
+        assert_eq!(row.valid_until, Some(1_900_000_000));
+    }
+
+    #[test]
+    fn an_unreadable_header_is_kept_as_unknown() {
+        let row = InventoryRow::new(7, fingerprint(), None);
+        assert_eq!(row.service, "unknown");
+        assert_eq!(row.cache_key, None);
+        assert_eq!(row.url, None);
+        assert_eq!(row.fingerprint.len, 1_048_576);
+    }
+}
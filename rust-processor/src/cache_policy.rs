@@ -0,0 +1,1196 @@
+//! Enforces declarative retention rules on the cache.
+//!
+//! A rules file lists what the cache should look like, for example:
+//!
+//! ```json
+//! { "rules": [
+//!     { "name": "stale steam", "kind": "notDownloadedFor", "service": "steam", "days": 60 },
+//!     { "kind": "serviceCap", "service": "wsus", "maxBytes": 536870912000 },
+//!     { "kind": "diskUsage", "maxPercent": 90 }
+//! ] }
+//! ```
+//!
+//! Rules are evaluated in file order against the detected games and their access history
+//! (`cached_game_value`). `notDownloadedFor` picks every game of the service whose last download
+//! is older than `days`, or that has none on record. `serviceCap` and `diskUsage` remove the least
+//! valuable games first until the service, or the cache filesystem, is back under its limit;
+//! `serviceCap` may fall back to removing the whole service when `removeService` is set. A game
+//! chosen by an earlier rule counts as freed for the later ones, so it is never removed twice.
+//!
+//! The plan is carried out by the existing removal bins, run one at a time from the directory
+//! this bin lives in: `cache_steam_remove`, `cache_epic_remove`, the named-game wrappers and
+//! `cache_service_remove`. Each keeps its own target selection (Steam's depot safety included) and
+//! the shared `removal_core` tail. After a bin succeeds its detection row is dropped, as the host
+//! does after a manual removal. The output JSON is the audit: every rule, every planned removal
+//! with the rule and reason that chose it, and each bin's exit status and report.
+//!
+//! Games on the protection list (`protected_games`) are never planned: a rule that selects one
+//! reports it as skipped, and a whole-service removal expects to free only the unprotected bytes.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{PgPool, Row};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::process::Command;
+
+use lancache_processor::cache_utils;
+use lancache_processor::cached_game_value::{
+    self, days_between, value_score, DetectedGame, GameKey,
+};
+use lancache_processor::cancel;
+use lancache_processor::db;
+use lancache_processor::db_backup;
+use lancache_processor::progress_events;
+use lancache_processor::progress_utils;
+use lancache_processor::protected_games::{ProtectedGame, Protection};
+use progress_events::ProgressReporter;
+
+#[derive(Parser, Debug)]
+#[command(name = "cache_policy")]
+#[command(about = "Evaluates retention rules and removes the games they select")]
+struct Args {
+    /// Directory containing log files
+    log_dir: String,
+
+    /// Cache directory root (e.g., /cache or H:/cache)
+    cache_dir: String,
+
+    /// Path to the rules JSON file
+    rules_json: String,
+
+    /// Path to output JSON audit
+    output_json: String,
+
+    /// Path to progress JSON file (use "none" to skip)
+    #[arg(default_value = "none")]
+    progress_json: String,
+
+    /// Cache-key recipe of the target datasource: "monolithic" (default) | "bare_metal"
+    #[arg(long = "key-scheme", default_value = "monolithic")]
+    key_scheme: String,
+
+    /// Days of idleness that halve a game's value when choosing what to remove first
+    #[arg(long, default_value_t = 30.0)]
+    half_life_days: f64,
+
+    /// Write the plan to the audit without removing anything
+    #[arg(long)]
+    plan_only: bool,
+
+    #[command(flatten)]
+    backup: db_backup::BackupArgs,
+
+    /// Emit JSON progress events to stdout
+    #[arg(short, long)]
+    progress: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProgressData {
+    status: String,
+    stage_key: String,
+    context: serde_json::Value,
+    percent_complete: f64,
+    actions_done: usize,
+    total_actions: usize,
+    timestamp: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PolicyFile {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct Rule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(flatten)]
+    condition: Condition,
+}
+
+impl Rule {
+    /// The rule's name, or its position and kind when it has none.
+    fn label(&self, index: usize) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => format!("#{} {}", index + 1, self.condition.kind()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+enum Condition {
+    /// Games with no download in the last `days` days; every service when `service` is absent.
+    #[serde(rename_all = "camelCase")]
+    NotDownloadedFor {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        service: Option<String>,
+        days: f64,
+    },
+    /// Bytes cached for one service, games and service-level content together.
+    #[serde(rename_all = "camelCase")]
+    ServiceCap {
+        service: String,
+        max_bytes: u64,
+        /// Remove the whole service with `cache_service_remove` when its games are not enough.
+        #[serde(default)]
+        remove_service: bool,
+    },
+    /// Used share of the filesystem holding the cache directory.
+    #[serde(rename_all = "camelCase")]
+    DiskUsage { max_percent: f64 },
+}
+
+impl Condition {
+    fn kind(&self) -> &'static str {
+        match self {
+            Condition::NotDownloadedFor { .. } => "notDownloadedFor",
+            Condition::ServiceCap { .. } => "serviceCap",
+            Condition::DiskUsage { .. } => "diskUsage",
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        match self {
+            Condition::NotDownloadedFor { days, .. } if days.is_nan() || *days < 0.0 => {
+                bail!("notDownloadedFor.days must not be negative")
+            }
+            Condition::DiskUsage { max_percent }
+                if max_percent.is_nan() || !(0.0..=100.0).contains(max_percent) =>
+            {
+                bail!("diskUsage.maxPercent must be between 0 and 100")
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The removal bin that removes a target, and the argument naming the target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Head {
+    Steam(u32),
+    Epic(String),
+    Named {
+        binary: &'static str,
+        game_name: String,
+    },
+    Service(String),
+}
+
+impl Head {
+    /// None when no removal bin handles the game's service.
+    fn for_game(game: &DetectedGame) -> Option<Head> {
+        if game.epic_app_id.is_some() {
+            return Some(Head::Epic(game.game_name.clone()));
+        }
+        if game.game_app_id != 0 {
+            return u32::try_from(game.game_app_id).ok().map(Head::Steam);
+        }
+        let binary = match game.service.as_str() {
+            "blizzard" => "cache_blizzard_remove",
+            "riot" => "cache_riot_remove",
+            "xbox" => "cache_xbox_remove",
+            _ => return None,
+        };
+        Some(Head::Named {
+            binary,
+            game_name: game.game_name.clone(),
+        })
+    }
+
+    fn binary(&self) -> &'static str {
+        match self {
+            Head::Steam(_) => "cache_steam_remove",
+            Head::Epic(_) => "cache_epic_remove",
+            Head::Named { binary, .. } => binary,
+            Head::Service(_) => "cache_service_remove",
+        }
+    }
+
+    fn argument(&self) -> String {
+        match self {
+            Head::Steam(app_id) => app_id.to_string(),
+            Head::Epic(game_name) | Head::Named { game_name, .. } => game_name.clone(),
+            Head::Service(service) => service.clone(),
+        }
+    }
+}
+
+/// A detected game with what the planner needs to rank and remove it.
+#[derive(Debug, Clone)]
+struct Candidate {
+    game: DetectedGame,
+    head: Option<Head>,
+    value_score: f64,
+    days_since_last_download: Option<f64>,
+    /// On the protection list; the removal bins would refuse it.
+    protected: bool,
+}
+
+impl Candidate {
+    fn removable(&self) -> bool {
+        self.head.is_some() && !self.protected
+    }
+
+    fn skipped(&self, rule: &str, reason: String) -> SkippedGame {
+        SkippedGame {
+            rule: rule.to_string(),
+            service: self.game.service.clone(),
+            game_name: self.game.game_name.clone(),
+            cached_bytes: self.game.cached_bytes,
+            reason,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DiskUsage {
+    total_bytes: u64,
+    used_bytes: u64,
+    available_bytes: u64,
+    /// Used share of the space available to the cache, as `df` reports it.
+    used_percent: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PlannedAction {
+    rule: String,
+    reason: String,
+    service: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    game_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    game_app_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    epic_app_id: Option<String>,
+    binary: String,
+    argument: String,
+    /// Bytes on disk per the latest detection; what the planner expects the removal to free.
+    cached_bytes: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value_score: Option<f64>,
+    #[serde(skip)]
+    head: Option<Head>,
+}
+
+/// A game a rule selected that is protected or that no removal bin can remove.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SkippedGame {
+    rule: String,
+    service: String,
+    game_name: String,
+    cached_bytes: i64,
+    reason: String,
+}
+
+/// A limit the plan cannot bring the cache under.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Shortfall {
+    rule: String,
+    reason: String,
+}
+
+#[derive(Debug, Default)]
+struct Plan {
+    actions: Vec<PlannedAction>,
+    skipped: Vec<SkippedGame>,
+    shortfalls: Vec<Shortfall>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ActionResult {
+    #[serde(flatten)]
+    action: PlannedAction,
+    /// "removed", "failed", or "notRun" after a cancel or a plan-only run.
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bytes_freed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    report: Option<serde_json::Value>,
+    /// Context of the bin's last progress entry; the completion summary when it finished.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    completion: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PolicyAudit {
+    success: bool,
+    plan_only: bool,
+    cancelled: bool,
+    rules_file: String,
+    rules: Vec<Rule>,
+    started_at: String,
+    disk_before: Option<DiskUsage>,
+    disk_after: Option<DiskUsage>,
+    planned_bytes: i64,
+    bytes_freed: u64,
+    actions: Vec<ActionResult>,
+    skipped: Vec<SkippedGame>,
+    shortfalls: Vec<Shortfall>,
+    timestamp: String,
+}
+
+fn action_for_game(rule: &str, reason: String, candidate: &Candidate) -> PlannedAction {
+    let head = candidate
+        .head
+        .clone()
+        .expect("only removable candidates are planned");
+    PlannedAction {
+        rule: rule.to_string(),
+        reason,
+        service: candidate.game.service.clone(),
+        game_name: Some(candidate.game.game_name.clone()),
+        game_app_id: (candidate.game.game_app_id != 0).then_some(candidate.game.game_app_id),
+        epic_app_id: candidate.game.epic_app_id.clone(),
+        binary: head.binary().to_string(),
+        argument: head.argument(),
+        cached_bytes: candidate.game.cached_bytes,
+        value_score: Some(candidate.value_score),
+        head: Some(head),
+    }
+}
+
+/// Evaluates the rules in order. `candidates` must be ordered least valuable first;
+/// `service_bytes` holds the service-level bytes outside any game, by lowercased service.
+fn plan_rules(
+    rules: &[Rule],
+    candidates: &[Candidate],
+    service_bytes: &HashMap<String, i64>,
+    disk: Option<DiskUsage>,
+) -> Plan {
+    let mut plan = Plan::default();
+    // `taken`: a rule already planned or reported the game. `freed`: a planned removal takes
+    // its bytes, so later caps no longer count them.
+    let mut taken = vec![false; candidates.len()];
+    let mut freed = vec![false; candidates.len()];
+    let mut removed_services: Vec<String> = Vec::new();
+    let mut planned_bytes = 0i64;
+
+    for (index, rule) in rules.iter().enumerate() {
+        let label = rule.label(index);
+        match &rule.condition {
+            Condition::NotDownloadedFor { service, days } => {
+                let service = service.as_deref().map(str::to_lowercase);
+                for (i, candidate) in candidates.iter().enumerate() {
+                    if taken[i]
+                        || service
+                            .as_ref()
+                            .is_some_and(|s| *s != candidate.game.service)
+                    {
+                        continue;
+                    }
+                    let reason = match candidate.days_since_last_download {
+                        Some(idle) if idle < *days => continue,
+                        Some(idle) => format!("last downloaded {idle:.0} days ago"),
+                        None => "no download on record".to_string(),
+                    };
+                    taken[i] = true;
+                    if candidate.protected {
+                        plan.skipped
+                            .push(candidate.skipped(&label, format!("{reason}, but the game is protected")));
+                        continue;
+                    }
+                    if candidate.head.is_none() {
+                        plan.skipped.push(candidate.skipped(
+                            &label,
+                            format!("{reason}, but no removal bin handles this service"),
+                        ));
+                        continue;
+                    }
+                    freed[i] = true;
+                    planned_bytes += candidate.game.cached_bytes;
+                    plan.actions
+                        .push(action_for_game(&label, reason, candidate));
+                }
+            }
+            Condition::ServiceCap {
+                service,
+                max_bytes,
+                remove_service,
+            } => {
+                let service = service.to_lowercase();
+                if removed_services.contains(&service) {
+                    continue;
+                }
+                let max_bytes = i64::try_from(*max_bytes).unwrap_or(i64::MAX);
+                let residual = service_bytes.get(&service).copied().unwrap_or(0);
+                let mut usage = residual
+                    + candidates
+                        .iter()
+                        .zip(&freed)
+                        .filter(|(c, freed)| !**freed && c.game.service == service)
+                        .map(|(c, _)| c.game.cached_bytes)
+                        .sum::<i64>();
+                for (i, candidate) in candidates.iter().enumerate() {
+                    if usage <= max_bytes {
+                        break;
+                    }
+                    if taken[i] || candidate.game.service != service || !candidate.removable() {
+                        continue;
+                    }
+                    let reason = format!(
+                        "{service} held {usage} bytes over its {max_bytes} byte cap; least valuable remaining game"
+                    );
+                    taken[i] = true;
+                    freed[i] = true;
+                    usage -= candidate.game.cached_bytes;
+                    planned_bytes += candidate.game.cached_bytes;
+                    plan.actions
+                        .push(action_for_game(&label, reason, candidate));
+                }
+                if usage <= max_bytes {
+                    continue;
+                }
+                if !remove_service {
+                    plan.shortfalls.push(Shortfall {
+                        rule: label,
+                        reason: format!(
+                            "{service} still holds {usage} bytes after removing its games; set removeService to remove the whole service"
+                        ),
+                    });
+                    continue;
+                }
+                // The service bin removes everything logged under the service, the remaining
+                // games included, so none of them is planned on its own afterwards. Protected
+                // games survive it.
+                let mut protected_bytes = 0i64;
+                for (i, candidate) in candidates.iter().enumerate() {
+                    if candidate.game.service != service {
+                        continue;
+                    }
+                    if candidate.protected {
+                        protected_bytes += candidate.game.cached_bytes;
+                        plan.skipped.push(candidate.skipped(
+                            &label,
+                            "kept through the service removal; the game is protected".to_string(),
+                        ));
+                    } else {
+                        freed[i] = true;
+                    }
+                    taken[i] = true;
+                }
+                if protected_bytes > max_bytes {
+                    plan.shortfalls.push(Shortfall {
+                        rule: label.clone(),
+                        reason: format!(
+                            "protected {service} games hold {protected_bytes} bytes, over the {max_bytes} byte cap"
+                        ),
+                    });
+                }
+                let usage = usage - protected_bytes;
+                let head = Head::Service(service.clone());
+                plan.actions.push(PlannedAction {
+                    rule: label,
+                    reason: format!(
+                        "{service} still held {usage} bytes over its {max_bytes} byte cap after removing its games"
+                    ),
+                    service: service.clone(),
+                    game_name: None,
+                    game_app_id: None,
+                    epic_app_id: None,
+                    binary: head.binary().to_string(),
+                    argument: head.argument(),
+                    cached_bytes: usage,
+                    value_score: None,
+                    head: Some(head),
+                });
+                planned_bytes += usage;
+                removed_services.push(service);
+            }
+            Condition::DiskUsage { max_percent } => {
+                let Some(disk) = disk else {
+                    plan.shortfalls.push(Shortfall {
+                        rule: label,
+                        reason: "disk usage of the cache directory is unavailable".to_string(),
+                    });
+                    continue;
+                };
+                let capacity = disk.used_bytes.saturating_add(disk.available_bytes);
+                let limit = (capacity as f64 * max_percent / 100.0) as i64;
+                let mut used = disk.used_bytes as i64 - planned_bytes;
+                for (i, candidate) in candidates.iter().enumerate() {
+                    if used <= limit {
+                        break;
+                    }
+                    if taken[i] || !candidate.removable() {
+                        continue;
+                    }
+                    let reason = format!(
+                        "disk {:.1}% used against a {max_percent}% limit; least valuable remaining game",
+                        used as f64 / capacity as f64 * 100.0
+                    );
+                    taken[i] = true;
+                    freed[i] = true;
+                    used -= candidate.game.cached_bytes;
+                    planned_bytes += candidate.game.cached_bytes;
+                    plan.actions
+                        .push(action_for_game(&label, reason, candidate));
+                }
+                if used > limit {
+                    plan.shortfalls.push(Shortfall {
+                        rule: label,
+                        reason: format!(
+                            "{} bytes over the limit remain after planning every removable game",
+                            used - limit
+                        ),
+                    });
+                }
+            }
+        }
+    }
+    plan
+}
+
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)] // statvfs field widths differ between platforms
+fn disk_usage(path: &Path) -> Result<DiskUsage> {
+    use std::ffi::CString;
+    use std::mem::MaybeUninit;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat: MaybeUninit<libc::statvfs> = MaybeUninit::uninit();
+    let res = unsafe { libc::statvfs(c_path.as_ptr(), stat.as_mut_ptr()) };
+    if res != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let stat = unsafe { stat.assume_init() };
+    let fragment = stat.f_frsize as u64;
+    let total_bytes = stat.f_blocks as u64 * fragment;
+    let used_bytes = total_bytes.saturating_sub(stat.f_bfree as u64 * fragment);
+    let available_bytes = stat.f_bavail as u64 * fragment;
+    let usable = used_bytes + available_bytes;
+    Ok(DiskUsage {
+        total_bytes,
+        used_bytes,
+        available_bytes,
+        used_percent: if usable == 0 {
+            0.0
+        } else {
+            used_bytes as f64 / usable as f64 * 100.0
+        },
+    })
+}
+
+#[cfg(not(unix))]
+fn disk_usage(_path: &Path) -> Result<DiskUsage> {
+    bail!("disk usage is only available on unix")
+}
+
+fn write_progress(
+    progress_path: Option<&Path>,
+    reporter: &ProgressReporter,
+    status: &str,
+    stage_key: &str,
+    context: serde_json::Value,
+    actions_done: usize,
+    total_actions: usize,
+) -> Result<()> {
+    let percent_complete = match status {
+        "completed" => 100.0,
+        _ if total_actions == 0 => 0.0,
+        _ => (actions_done as f64 / total_actions as f64 * 100.0).min(99.0),
+    };
+    if let Some(path) = progress_path {
+        let progress = ProgressData {
+            status: status.to_string(),
+            stage_key: stage_key.to_string(),
+            context: context.clone(),
+            percent_complete,
+            actions_done,
+            total_actions,
+            timestamp: progress_utils::current_timestamp(),
+        };
+        progress_utils::write_progress_json(path, &progress)?;
+    }
+
+    match status {
+        "starting" => reporter.emit_started(stage_key, context),
+        "completed" => reporter.emit_complete(stage_key, context),
+        "cancelled" => reporter.emit_cancelled(stage_key, context),
+        _ => reporter.emit_progress(percent_complete, stage_key, context),
+    }
+
+    Ok(())
+}
+
+fn protection_key(key: GameKey) -> ProtectedGame {
+    match key {
+        GameKey::Epic(epic_app_id) => ProtectedGame::Epic { epic_app_id },
+        GameKey::Named { service, game_name } => ProtectedGame::Named { service, game_name },
+        GameKey::Steam(app_id) => ProtectedGame::Steam { app_id },
+    }
+}
+
+async fn load_candidates(
+    pool: &PgPool,
+    half_life_days: f64,
+    protection: &Protection,
+) -> Result<Vec<Candidate>> {
+    let detections = cached_game_value::load_detected_games(pool).await?;
+    let activity = cached_game_value::load_activity(pool).await?;
+    let now = Utc::now();
+    let mut candidates: Vec<Candidate> = detections
+        .into_iter()
+        .map(|game| {
+            let history = activity.get(&game.key()).cloned().unwrap_or_default();
+            let since = |at: DateTime<Utc>| days_between(at, now);
+            Candidate {
+                head: Head::for_game(&game),
+                protected: protection.protects(&protection_key(game.key())),
+                value_score: value_score(
+                    history.hit_bytes as f64 / game.cached_bytes as f64,
+                    history.distinct_clients,
+                    history.last_access().map(since),
+                    half_life_days,
+                ),
+                days_since_last_download: history.last_download_at.map(since),
+                game,
+            }
+        })
+        .collect();
+    candidates.sort_by(|a, b| {
+        a.value_score
+            .total_cmp(&b.value_score)
+            .then(b.game.cached_bytes.cmp(&a.game.cached_bytes))
+            .then(a.game.game_name.cmp(&b.game.game_name))
+    });
+    Ok(candidates)
+}
+
+async fn load_service_bytes(pool: &PgPool) -> Result<HashMap<String, i64>> {
+    let rows = sqlx::query(
+        "SELECT LOWER(\"ServiceName\") AS service, SUM(\"TotalSizeBytes\")::bigint AS cached_bytes \
+         FROM \"CachedServiceDetections\" \
+         WHERE NOT \"IsEvicted\" \
+         GROUP BY 1",
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to load cached service detections")?;
+    rows.iter()
+        .map(|row| Ok((row.try_get("service")?, row.try_get("cached_bytes")?)))
+        .collect()
+}
+
+/// Drops the detection row of a removed target, matching the host's post-removal cleanup.
+async fn forget_detection(pool: &PgPool, head: &Head, service: &str) -> Result<()> {
+    let query = match head {
+        Head::Steam(app_id) => {
+            sqlx::query("DELETE FROM \"CachedGameDetections\" WHERE \"GameAppId\" = $1")
+                .bind(*app_id as i64)
+        }
+        Head::Epic(game_name) => sqlx::query(
+            "DELETE FROM \"CachedGameDetections\" \
+             WHERE \"EpicAppId\" IS NOT NULL AND \"GameName\" = $1",
+        )
+        .bind(game_name),
+        Head::Named { game_name, .. } => sqlx::query(
+            "DELETE FROM \"CachedGameDetections\" \
+             WHERE \"GameAppId\" = 0 AND \"EpicAppId\" IS NULL \
+               AND LOWER(\"Service\") = $1 AND \"GameName\" = $2",
+        )
+        .bind(service)
+        .bind(game_name),
+        Head::Service(service) => {
+            sqlx::query("DELETE FROM \"CachedServiceDetections\" WHERE LOWER(\"ServiceName\") = $1")
+                .bind(service)
+        }
+    };
+    query
+        .execute(pool)
+        .await
+        .context("failed to drop the detection row of a removed target")?;
+    Ok(())
+}
+
+/// Runs one removal bin to completion and reads back its report.
+async fn run_head(
+    args: &Args,
+    bin_dir: &Path,
+    work_dir: &Path,
+    index: usize,
+    action: &PlannedAction,
+) -> ActionResult {
+    let head = action.head.as_ref().expect("planned actions carry a head");
+    let binary = bin_dir.join(format!("{}{}", head.binary(), std::env::consts::EXE_SUFFIX));
+    let report_path = work_dir.join(format!("action_{index}_report.json"));
+    let progress_path = work_dir.join(format!("action_{index}_progress.json"));
+
+    let mut command = Command::new(&binary);
+    command
+        .arg(&args.log_dir)
+        .arg(&args.cache_dir)
+        .arg(head.argument())
+        .arg(&report_path)
+        .arg(&progress_path)
+        .arg("--key-scheme")
+        .arg(&args.key_scheme);
+    if let Some(dir) = &args.backup.backup_dir {
+        command
+            .arg("--backup-dir")
+            .arg(dir)
+            .arg("--backup-keep")
+            .arg(args.backup.backup_keep.to_string());
+        if let Some(days) = args.backup.backup_max_age_days {
+            command.arg("--backup-max-age-days").arg(days.to_string());
+        }
+    }
+    // Progress events belong to this run; the bin's log lines still reach our stderr.
+    command.stdout(Stdio::null()).stderr(Stdio::inherit());
+
+    let mut result = ActionResult {
+        action: action.clone(),
+        status: "failed",
+        exit_code: None,
+        bytes_freed: None,
+        report: None,
+        completion: None,
+        error: None,
+    };
+    let status = match command.status().await {
+        Ok(status) => status,
+        Err(e) => {
+            result.error = Some(format!("failed to start {}: {e}", binary.display()));
+            return result;
+        }
+    };
+    result.exit_code = status.code();
+    result.report = fs::read_to_string(&report_path)
+        .ok()
+        .and_then(|text| serde_json::from_str(&text).ok());
+    result.bytes_freed = result
+        .report
+        .as_ref()
+        .and_then(|report| report.get("total_bytes_freed"))
+        .and_then(|bytes| bytes.as_u64());
+    // Not every bin writes a report on success, and a cancelled bin still exits 0, so the
+    // last progress entry is what says whether the removal ran to the end.
+    let last_progress: Option<serde_json::Value> = fs::read_to_string(&progress_path)
+        .ok()
+        .and_then(|text| serde_json::from_str(&text).ok());
+    let finished = last_progress
+        .as_ref()
+        .and_then(|progress| progress.get("status"))
+        .and_then(|status| status.as_str())
+        == Some("completed");
+    if !status.success() {
+        result.error = Some(format!("{} exited with {status}", head.binary()));
+    } else if !finished {
+        result.error = Some(format!(
+            "{} stopped before completing; it was likely cancelled",
+            head.binary()
+        ));
+    } else {
+        result.status = "removed";
+    }
+    result.completion = last_progress.and_then(|progress| progress.get("context").cloned());
+    let _ = fs::remove_file(&report_path);
+    let _ = fs::remove_file(&progress_path);
+    result
+}
+
+fn write_audit(path: &str, audit: &PolicyAudit) -> Result<()> {
+    let payload =
+        serde_json::to_string_pretty(audit).context("Failed to serialize policy audit")?;
+    fs::write(path, payload).with_context(|| format!("Failed to write output JSON to {path}"))
+}
+
+async fn run(args: &Args, progress_path: Option<&Path>, reporter: &ProgressReporter) -> Result<()> {
+    if !cache_utils::is_valid_scheme_str(&args.key_scheme) {
+        bail!("--key-scheme must be monolithic or bare_metal");
+    }
+    if args.half_life_days.is_nan() || args.half_life_days <= 0.0 {
+        bail!("--half-life-days must be positive");
+    }
+    let started_at = progress_utils::current_timestamp();
+    let rules_text = fs::read_to_string(&args.rules_json)
+        .with_context(|| format!("Failed to read rules from {}", args.rules_json))?;
+    let policy: PolicyFile = serde_json::from_str(&rules_text)
+        .with_context(|| format!("Failed to parse rules in {}", args.rules_json))?;
+    for (index, rule) in policy.rules.iter().enumerate() {
+        rule.condition
+            .validate()
+            .with_context(|| format!("invalid rule {}", rule.label(index)))?;
+    }
+    write_progress(
+        progress_path,
+        reporter,
+        "starting",
+        "signalr.cachePolicy.starting",
+        json!({ "rules": policy.rules.len() }),
+        0,
+        0,
+    )?;
+
+    let pool = db::create_pool().await?;
+    let protection = Protection::load(&pool).await?;
+    let candidates = load_candidates(&pool, args.half_life_days, &protection).await?;
+    let service_bytes = load_service_bytes(&pool).await?;
+    let disk_before = match disk_usage(Path::new(&args.cache_dir)) {
+        Ok(usage) => Some(usage),
+        Err(e) => {
+            eprintln!(
+                "[Policy] Disk usage of {} unavailable: {e:#}",
+                args.cache_dir
+            );
+            None
+        }
+    };
+    let plan = plan_rules(&policy.rules, &candidates, &service_bytes, disk_before);
+    let planned_bytes = plan.actions.iter().map(|action| action.cached_bytes).sum();
+    let total_actions = plan.actions.len();
+    eprintln!(
+        "[Policy] {} rules selected {} removals ({} bytes), {} skipped games, {} shortfalls",
+        policy.rules.len(),
+        total_actions,
+        planned_bytes,
+        plan.skipped.len(),
+        plan.shortfalls.len()
+    );
+    write_progress(
+        progress_path,
+        reporter,
+        "planned",
+        "signalr.cachePolicy.planned",
+        json!({ "actions": total_actions, "plannedBytes": planned_bytes }),
+        0,
+        total_actions,
+    )?;
+
+    let mut audit = PolicyAudit {
+        success: true,
+        plan_only: args.plan_only,
+        cancelled: false,
+        rules_file: args.rules_json.clone(),
+        rules: policy.rules.clone(),
+        started_at,
+        disk_before,
+        disk_after: None,
+        planned_bytes,
+        bytes_freed: 0,
+        actions: Vec::with_capacity(total_actions),
+        skipped: plan.skipped,
+        shortfalls: plan.shortfalls,
+        timestamp: String::new(),
+    };
+
+    let bin_dir = std::env::current_exe()
+        .context("failed to locate the running executable")?
+        .parent()
+        .map(Path::to_path_buf)
+        .context("the running executable has no parent directory")?;
+    let work_dir = std::env::temp_dir().join(format!("cache_policy_{}", std::process::id()));
+    if !args.plan_only && total_actions > 0 {
+        fs::create_dir_all(&work_dir)
+            .with_context(|| format!("Failed to create {}", work_dir.display()))?;
+    }
+
+    for (index, action) in plan.actions.into_iter().enumerate() {
+        if args.plan_only || audit.cancelled || cancel::is_cancelled() {
+            audit.cancelled |= !args.plan_only;
+            audit.actions.push(ActionResult {
+                action,
+                status: "notRun",
+                exit_code: None,
+                bytes_freed: None,
+                report: None,
+                completion: None,
+                error: None,
+            });
+            continue;
+        }
+        let target = action
+            .game_name
+            .clone()
+            .unwrap_or_else(|| action.service.clone());
+        write_progress(
+            progress_path,
+            reporter,
+            "removing",
+            "signalr.cachePolicy.removing",
+            json!({ "target": target, "rule": action.rule, "binary": action.binary }),
+            index,
+            total_actions,
+        )?;
+        eprintln!("[Policy] {} ({}): {}", target, action.rule, action.reason);
+        let result = run_head(args, &bin_dir, &work_dir, index, &action).await;
+        if result.status == "removed" {
+            let head = result
+                .action
+                .head
+                .as_ref()
+                .expect("planned actions carry a head");
+            forget_detection(&pool, head, &result.action.service).await?;
+            audit.bytes_freed += result.bytes_freed.unwrap_or(0);
+        } else {
+            eprintln!(
+                "[Policy] Removing {} failed: {}",
+                target,
+                result.error.as_deref().unwrap_or("unknown error")
+            );
+            audit.success = false;
+        }
+        audit.actions.push(result);
+    }
+    let _ = fs::remove_dir(&work_dir);
+
+    if !args.plan_only {
+        audit.disk_after = disk_usage(Path::new(&args.cache_dir)).ok();
+    }
+    audit.success &= !audit.cancelled;
+    audit.timestamp = progress_utils::current_timestamp();
+    write_audit(&args.output_json, &audit)?;
+
+    let removed = audit
+        .actions
+        .iter()
+        .filter(|a| a.status == "removed")
+        .count();
+    let context = json!({
+        "removed": removed,
+        "failed": audit.actions.iter().filter(|a| a.status == "failed").count(),
+        "bytesFreed": audit.bytes_freed,
+        "planOnly": args.plan_only,
+    });
+    if audit.cancelled {
+        write_progress(
+            progress_path,
+            reporter,
+            "cancelled",
+            "signalr.cachePolicy.cancelled",
+            context,
+            removed,
+            total_actions,
+        )?;
+        return Ok(());
+    }
+    write_progress(
+        progress_path,
+        reporter,
+        "completed",
+        "signalr.cachePolicy.complete",
+        context,
+        total_actions,
+        total_actions,
+    )?;
+    if !args.plan_only {
+        eprintln!(
+            "[Policy] Removed {} of {} planned targets, {} bytes freed",
+            removed, total_actions, audit.bytes_freed
+        );
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    cancel::install();
+    let args = Args::parse();
+    let reporter = ProgressReporter::new(args.progress);
+    let progress_path = (args.progress_json != "none").then(|| PathBuf::from(&args.progress_json));
+    let result = run(&args, progress_path.as_deref(), &reporter).await;
+    progress_events::finish_or_exit(&reporter, "signalr.cachePolicy.error.fatal", result);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(service: &str, name: &str, bytes: i64, idle_days: Option<f64>) -> Candidate {
+        let game = DetectedGame {
+            service: service.to_string(),
+            game_app_id: if service == "steam" { 10 } else { 0 },
+            epic_app_id: None,
+            game_name: name.to_string(),
+            cached_bytes: bytes,
+            cache_files: 1,
+        };
+        Candidate {
+            head: Head::for_game(&game),
+            protected: false,
+            value_score: 0.0,
+            days_since_last_download: idle_days,
+            game,
+        }
+    }
+
+    fn rules(json: &str) -> Vec<Rule> {
+        serde_json::from_str::<PolicyFile>(json).unwrap().rules
+    }
+
+    fn targets(plan: &Plan) -> Vec<(&str, &str)> {
+        plan.actions
+            .iter()
+            .map(|a| (a.rule.as_str(), a.argument.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn stale_games_are_selected_and_unremovable_ones_reported() {
+        let candidates = vec![
+            candidate("steam", "old", 10, Some(90.0)),
+            candidate("steam", "fresh", 10, Some(5.0)),
+            candidate("blizzard", "never", 10, None),
+            candidate("origin", "orphan", 10, None),
+        ];
+        let rules = rules(r#"{"rules":[{"name":"stale","kind":"notDownloadedFor","days":60}]}"#);
+        let plan = plan_rules(&rules, &candidates, &HashMap::new(), None);
+        assert_eq!(targets(&plan), vec![("stale", "10"), ("stale", "never")]);
+        assert_eq!(plan.actions[1].binary, "cache_blizzard_remove");
+        assert_eq!(plan.skipped.len(), 1);
+        assert_eq!(plan.skipped[0].game_name, "orphan");
+    }
+
+    #[test]
+    fn service_caps_remove_coldest_games_then_fall_back_to_the_service() {
+        let candidates = vec![
+            candidate("blizzard", "cold", 40, Some(1.0)),
+            candidate("blizzard", "warm", 40, Some(1.0)),
+            candidate("riot", "other", 500, Some(1.0)),
+        ];
+        let residual = HashMap::from([("blizzard".to_string(), 30)]);
+        let capped =
+            rules(r#"{"rules":[{"kind":"serviceCap","service":"Blizzard","maxBytes":80}]}"#);
+        let plan = plan_rules(&capped, &candidates, &residual, None);
+        assert_eq!(targets(&plan), vec![("#1 serviceCap", "cold")]);
+        assert!(plan.shortfalls.is_empty());
+
+        let strict = rules(
+            r#"{"rules":[{"kind":"serviceCap","service":"blizzard","maxBytes":10,"removeService":true}]}"#,
+        );
+        let plan = plan_rules(&strict, &candidates, &residual, None);
+        assert_eq!(
+            targets(&plan),
+            vec![
+                ("#1 serviceCap", "cold"),
+                ("#1 serviceCap", "warm"),
+                ("#1 serviceCap", "blizzard")
+            ]
+        );
+        assert_eq!(plan.actions[2].cached_bytes, 30);
+
+        let mut no_fallback = strict;
+        no_fallback[0].condition = Condition::ServiceCap {
+            service: "blizzard".to_string(),
+            max_bytes: 10,
+            remove_service: false,
+        };
+        let plan = plan_rules(&no_fallback, &candidates, &residual, None);
+        assert_eq!(plan.actions.len(), 2);
+        assert_eq!(plan.shortfalls.len(), 1);
+    }
+
+    #[test]
+    fn disk_limit_counts_bytes_earlier_rules_already_free() {
+        let candidates = vec![
+            candidate("steam", "stale", 300, Some(100.0)),
+            candidate("blizzard", "cold", 200, Some(1.0)),
+            candidate("riot", "warm", 200, Some(1.0)),
+        ];
+        let disk = DiskUsage {
+            total_bytes: 1_000,
+            used_bytes: 950,
+            available_bytes: 50,
+            used_percent: 95.0,
+        };
+        let rules = rules(
+            r#"{"rules":[
+                {"kind":"notDownloadedFor","service":"steam","days":30},
+                {"kind":"diskUsage","maxPercent":50}
+            ]}"#,
+        );
+        let plan = plan_rules(&rules, &candidates, &HashMap::new(), Some(disk));
+        // 950 - 300 = 650 is still over 500, so one more game goes; 450 is under.
+        assert_eq!(
+            targets(&plan),
+            vec![("#1 notDownloadedFor", "10"), ("#2 diskUsage", "cold")]
+        );
+
+        let plan = plan_rules(&rules[1..], &candidates[1..], &HashMap::new(), None);
+        assert!(plan.actions.is_empty());
+        assert_eq!(plan.shortfalls.len(), 1);
+    }
+
+    #[test]
+    fn protected_games_are_reported_and_never_planned() {
+        let mut kept = candidate("blizzard", "lan", 40, Some(90.0));
+        kept.protected = true;
+        let candidates = vec![kept, candidate("blizzard", "cold", 40, Some(90.0))];
+        let stale = rules(r#"{"rules":[{"kind":"notDownloadedFor","days":60}]}"#);
+        let plan = plan_rules(&stale, &candidates, &HashMap::new(), None);
+        assert_eq!(targets(&plan), vec![("#1 notDownloadedFor", "cold")]);
+        assert_eq!(plan.skipped[0].game_name, "lan");
+        assert!(plan.skipped[0].reason.ends_with("the game is protected"));
+
+        let residual = HashMap::from([("blizzard".to_string(), 30)]);
+        let strict = rules(
+            r#"{"rules":[{"kind":"serviceCap","service":"blizzard","maxBytes":10,"removeService":true}]}"#,
+        );
+        let plan = plan_rules(&strict, &candidates, &residual, None);
+        assert_eq!(
+            targets(&plan),
+            vec![("#1 serviceCap", "cold"), ("#1 serviceCap", "blizzard")]
+        );
+        // The service removal frees the residual bytes only; the protected game stays over cap.
+        assert_eq!(plan.actions[1].cached_bytes, 30);
+        assert_eq!(plan.skipped.len(), 1);
+        assert_eq!(plan.shortfalls.len(), 1);
+    }
+
+    #[test]
+    fn a_protected_game_an_earlier_rule_skipped_still_counts_against_a_cap() {
+        let mut kept = candidate("blizzard", "lan", 40, Some(90.0));
+        kept.protected = true;
+        let candidates = vec![kept, candidate("blizzard", "cold", 40, Some(1.0))];
+        let residual = HashMap::from([("blizzard".to_string(), 30)]);
+        let capped = rules(
+            r#"{"rules":[
+                {"kind":"notDownloadedFor","service":"blizzard","days":60},
+                {"kind":"serviceCap","service":"blizzard","maxBytes":50}
+            ]}"#,
+        );
+        // 110 bytes: removing "cold" leaves 70, the protected game included, still over 50.
+        let plan = plan_rules(&capped, &candidates, &residual, None);
+        assert_eq!(targets(&plan), vec![("#2 serviceCap", "cold")]);
+        assert_eq!(plan.shortfalls.len(), 1);
+        assert!(plan.shortfalls[0].reason.contains("still holds 70 bytes"));
+
+        let mut strict = capped;
+        strict[1].condition = Condition::ServiceCap {
+            service: "blizzard".to_string(),
+            max_bytes: 10,
+            remove_service: true,
+        };
+        let plan = plan_rules(&strict, &candidates, &residual, None);
+        assert_eq!(
+            targets(&plan),
+            vec![("#2 serviceCap", "cold"), ("#2 serviceCap", "blizzard")]
+        );
+        assert_eq!(plan.actions[1].cached_bytes, 30);
+        let skipped: Vec<&str> = plan.skipped.iter().map(|s| s.rule.as_str()).collect();
+        assert_eq!(skipped, vec!["#1 notDownloadedFor", "#2 serviceCap"]);
+        assert_eq!(plan.shortfalls.len(), 1);
+        assert!(plan.shortfalls[0].reason.starts_with("protected blizzard games"));
+    }
+}
@@ -0,0 +1,172 @@
+//! Manages the protection list (see `protected_games`).
+//!
+//! `add` and `remove` change one entry; every subcommand writes the resulting list to
+//! `output_json`, so the caller always sees the state the removal bins will enforce.
+
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand};
+use serde::Serialize;
+use std::fs;
+
+use lancache_processor::db;
+use lancache_processor::progress_utils;
+use lancache_processor::protected_games::{self, ProtectedGame, ProtectionEntry};
+
+#[derive(Parser, Debug)]
+#[command(name = "cache_protect")]
+#[command(about = "Lists, adds and removes games that no removal may touch")]
+struct Args {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Write the protection list.
+    List { output_json: String },
+    /// Protect one game.
+    Add {
+        output_json: String,
+        #[command(flatten)]
+        identity: Identity,
+        /// Why the game is protected, e.g. "LAN party 2026-11"
+        #[arg(long)]
+        note: Option<String>,
+    },
+    /// Lift the protection of one game.
+    Remove {
+        output_json: String,
+        #[command(flatten)]
+        identity: Identity,
+    },
+}
+
+/// Exactly one of: `--steam-app-id`, `--epic-app-id`, or `--service` with `--game-name`.
+#[derive(clap::Args, Debug)]
+struct Identity {
+    #[arg(long)]
+    steam_app_id: Option<i64>,
+    #[arg(long)]
+    epic_app_id: Option<String>,
+    /// Owning service of a name-keyed game (blizzard, riot, xbox, ...)
+    #[arg(long, requires = "game_name")]
+    service: Option<String>,
+    #[arg(long, requires = "service")]
+    game_name: Option<String>,
+}
+
+impl Identity {
+    fn game(&self) -> Result<ProtectedGame> {
+        match (
+            self.steam_app_id,
+            self.epic_app_id.as_deref(),
+            self.service.as_deref(),
+            self.game_name.as_deref(),
+        ) {
+            (Some(app_id), None, None, None) => Ok(ProtectedGame::Steam { app_id }),
+            (None, Some(epic_app_id), None, None) if !epic_app_id.trim().is_empty() => {
+                Ok(ProtectedGame::Epic {
+                    epic_app_id: epic_app_id.to_string(),
+                })
+            }
+            (None, None, Some(service), Some(game_name))
+                if !service.trim().is_empty() && !game_name.trim().is_empty() =>
+            {
+                Ok(ProtectedGame::named(service, game_name))
+            }
+            _ => bail!(
+                "name exactly one game: --steam-app-id, --epic-app-id, or --service with --game-name"
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProtectReport {
+    /// Whether `add`/`remove` changed the list; None for `list`.
+    changed: Option<bool>,
+    protections: Vec<ProtectionEntry>,
+    timestamp: String,
+}
+
+fn write_json<T: Serialize>(output_json: &str, value: &T) -> Result<()> {
+    let payload =
+        serde_json::to_string_pretty(value).context("Failed to serialize protection list")?;
+    fs::write(output_json, payload).with_context(|| format!("Failed to write {}", output_json))
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let pool = db::create_pool().await?;
+    let (output_json, changed) = match &args.command {
+        Commands::List { output_json } => (output_json, None),
+        Commands::Add {
+            output_json,
+            identity,
+            note,
+        } => {
+            let game = identity.game()?;
+            let added = protected_games::add(&pool, &game, note.as_deref()).await?;
+            if added {
+                eprintln!("[CacheProtect] Protected {game}");
+            } else {
+                eprintln!("[CacheProtect] {game} was already protected");
+            }
+            (output_json, Some(added))
+        }
+        Commands::Remove {
+            output_json,
+            identity,
+        } => {
+            let game = identity.game()?;
+            let removed = protected_games::remove(&pool, &game).await?;
+            if removed {
+                eprintln!("[CacheProtect] Lifted the protection of {game}");
+            } else {
+                eprintln!("[CacheProtect] {game} was not protected");
+            }
+            (output_json, Some(removed))
+        }
+    };
+    let protections = protected_games::list(&pool).await?;
+    eprintln!("[CacheProtect] {} protected game(s)", protections.len());
+    write_json(
+        output_json,
+        &ProtectReport {
+            changed,
+            protections,
+            timestamp: progress_utils::current_timestamp(),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<ProtectedGame> {
+        let mut argv = vec!["cache_protect", "add", "out.json"];
+        argv.extend_from_slice(args);
+        match Args::try_parse_from(argv)?.command {
+            Commands::Add { identity, .. } => identity.game(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn exactly_one_identity_is_accepted() {
+        assert_eq!(
+            parse(&["--steam-app-id", "730"]).unwrap(),
+            ProtectedGame::Steam { app_id: 730 }
+        );
+        assert_eq!(
+            parse(&["--service", "Blizzard", "--game-name", "Diablo IV"]).unwrap(),
+            ProtectedGame::named("blizzard", "Diablo IV")
+        );
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["--steam-app-id", "730", "--epic-app-id", "fn"]).is_err());
+        assert!(parse(&["--service", "riot"]).is_err());
+    }
+}
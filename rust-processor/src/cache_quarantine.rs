@@ -0,0 +1,222 @@
+//! Lists, restores and purges quarantine batches (see `quarantine`).
+//!
+//! `restore` moves a batch's files back under the cache root; `purge` and `age-out` run the log
+//! and database cleanup the removal deferred, then delete the files. Every subcommand writes its
+//! result to `output_json`.
+
+use anyhow::{Context, Result};
+use chrono::{Duration, Utc};
+use clap::{Parser, Subcommand};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use lancache_processor::db;
+use lancache_processor::db_backup;
+use lancache_processor::progress_utils;
+use lancache_processor::quarantine::{self, PurgeOutcome, QuarantineBatch, RestoreOutcome};
+
+#[derive(Parser, Debug)]
+#[command(name = "cache_quarantine")]
+#[command(about = "Lists, restores, purges and ages out quarantined cache files")]
+struct Args {
+    /// Directory holding the quarantine batches (the removals' --quarantine-dir)
+    #[arg(long = "quarantine-dir", value_name = "DIR")]
+    quarantine_dir: PathBuf,
+
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Write every batch with its size.
+    List { output_json: String },
+    /// Move one batch's files back into the cache.
+    Restore { output_json: String, id: String },
+    /// Run one batch's deferred log and database cleanup, then delete it.
+    Purge {
+        output_json: String,
+        id: String,
+        #[command(flatten)]
+        backup: db_backup::BackupArgs,
+    },
+    /// Purge every batch older than `--older-than-days`, oldest first.
+    AgeOut {
+        output_json: String,
+        #[arg(long)]
+        older_than_days: u32,
+        #[command(flatten)]
+        backup: db_backup::BackupArgs,
+    },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BatchEntry {
+    id: String,
+    operation: String,
+    created_at: String,
+    cache_dir: PathBuf,
+    /// False when the removal that filled the batch did not finish.
+    complete: bool,
+    /// Whether a purge runs deferred log and database cleanup.
+    needs_database: bool,
+    files: u64,
+    bytes: u64,
+}
+
+impl BatchEntry {
+    fn new(batch: &QuarantineBatch) -> Result<Self> {
+        let contents = batch.contents()?;
+        Ok(Self {
+            id: batch.manifest.id.clone(),
+            operation: batch.manifest.operation.clone(),
+            created_at: batch.manifest.created_at.clone(),
+            cache_dir: batch.manifest.cache_dir.clone(),
+            complete: batch.manifest.complete,
+            needs_database: batch.needs_database(),
+            files: contents.files,
+            bytes: contents.bytes,
+        })
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct QuarantineReport {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    batches: Option<Vec<BatchEntry>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    restored: Option<RestoreOutcome>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    purged: Option<Vec<PurgeOutcome>>,
+    timestamp: String,
+}
+
+fn write_json<T: Serialize>(output_json: &str, value: &T) -> Result<()> {
+    let payload =
+        serde_json::to_string_pretty(value).context("Failed to serialize quarantine report")?;
+    fs::write(output_json, payload).with_context(|| format!("Failed to write {}", output_json))
+}
+
+/// Purges `batches` in order, connecting to the database only for the first batch that needs it.
+async fn purge_batches(
+    batches: &[QuarantineBatch],
+    backup: &db_backup::BackupArgs,
+) -> Result<Vec<PurgeOutcome>> {
+    let policy = backup.policy();
+    let mut pool: Option<PgPool> = None;
+    let mut purged = Vec::with_capacity(batches.len());
+    for batch in batches {
+        if batch.needs_database() && pool.is_none() {
+            pool = Some(db::create_pool().await?);
+        }
+        eprintln!("[CacheQuarantine] Purging {}", batch.manifest.id);
+        let outcome = batch
+            .purge(pool.as_ref(), policy.as_ref())
+            .await
+            .with_context(|| format!("failed to purge quarantine batch {}", batch.manifest.id))?;
+        eprintln!(
+            "[CacheQuarantine] Purged {}: {} file(s), {} log line(s), {} row(s)",
+            outcome.id,
+            outcome.purged.files,
+            outcome.log_lines_removed,
+            outcome.database_rows_deleted
+        );
+        purged.push(outcome);
+    }
+    Ok(purged)
+}
+
+/// Batches created more than `days` days ago. A batch without a readable creation time is kept.
+fn batches_older_than(root: &Path, days: u32) -> Result<Vec<QuarantineBatch>> {
+    let cutoff = Utc::now() - Duration::days(i64::from(days));
+    Ok(quarantine::list_batches(root)?
+        .into_iter()
+        .filter(|batch| batch.created_at().is_some_and(|created| created < cutoff))
+        .collect())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    let root = &args.quarantine_dir;
+    let (output_json, report) = match &args.command {
+        Commands::List { output_json } => {
+            let batches = quarantine::list_batches(root)?
+                .iter()
+                .map(BatchEntry::new)
+                .collect::<Result<Vec<_>>>()?;
+            eprintln!("[CacheQuarantine] {} batch(es)", batches.len());
+            (
+                output_json,
+                QuarantineReport {
+                    batches: Some(batches),
+                    ..QuarantineReport::default()
+                },
+            )
+        }
+        Commands::Restore { output_json, id } => {
+            let outcome = quarantine::load_batch(root, id)?.restore()?;
+            eprintln!(
+                "[CacheQuarantine] Restored {} file(s) of {}",
+                outcome.restored.files, id
+            );
+            if !outcome.conflicts.is_empty() {
+                eprintln!(
+                    "[CacheQuarantine] {} file(s) are cached again and stay in the batch",
+                    outcome.conflicts.len()
+                );
+            }
+            (
+                output_json,
+                QuarantineReport {
+                    restored: Some(outcome),
+                    ..QuarantineReport::default()
+                },
+            )
+        }
+        Commands::Purge {
+            output_json,
+            id,
+            backup,
+        } => {
+            let batch = quarantine::load_batch(root, id)?;
+            (
+                output_json,
+                QuarantineReport {
+                    purged: Some(purge_batches(&[batch], backup).await?),
+                    ..QuarantineReport::default()
+                },
+            )
+        }
+        Commands::AgeOut {
+            output_json,
+            older_than_days,
+            backup,
+        } => {
+            let batches = batches_older_than(root, *older_than_days)?;
+            eprintln!(
+                "[CacheQuarantine] {} batch(es) older than {} day(s)",
+                batches.len(),
+                older_than_days
+            );
+            (
+                output_json,
+                QuarantineReport {
+                    purged: Some(purge_batches(&batches, backup).await?),
+                    ..QuarantineReport::default()
+                },
+            )
+        }
+    };
+    write_json(
+        output_json,
+        &QuarantineReport {
+            timestamp: progress_utils::current_timestamp(),
+            ..report
+        },
+    )
+}
@@ -0,0 +1,414 @@
+//! Rekeys a whole cache between the monolithic and bare-metal key schemes (see `rekey_core`).
+//!
+//! Moving from the container lancache to lancache-bare-metal, or back, otherwise means starting
+//! with an empty cache: the two nginx setups hash different keys, so neither finds the other's
+//! files. This walks the cache one top-level hash directory at a time and moves every file whose
+//! key has an equivalent to its new path. Stop nginx first; it must not fill the cache mid-walk.
+//!
+//! `--dry-run` reads every header and reports what would move without changing anything.
+//! `--checkpoint` records each finished top-level directory, so a cancelled or crashed run picks
+//! up where it stopped; the checkpoint is removed once the whole cache is done.
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use rayon::prelude::*;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use lancache_processor::cache_archive::Placement;
+use lancache_processor::cache_utils::{self, CacheKeyScheme, RekeyBlocker};
+use lancache_processor::cancel;
+use lancache_processor::progress_events;
+use lancache_processor::progress_utils;
+use lancache_processor::rekey_core::{self, Checkpoint, RekeyOutcome};
+use lancache_processor::removal_core;
+use progress_events::ProgressReporter;
+
+/// Conflicting files listed in the report; the count is always complete.
+const MAX_REPORTED_CONFLICTS: usize = 1000;
+
+#[derive(Parser, Debug)]
+#[command(name = "cache_rekey")]
+#[command(about = "Moves cache files to the keys of the other cache-key scheme")]
+struct Args {
+    /// Cache directory root (e.g., /cache or H:/cache)
+    cache_dir: String,
+
+    /// Path to output JSON report
+    output_json: String,
+
+    /// Path to progress JSON file
+    progress_json: String,
+
+    /// Key scheme to move the cache to: "monolithic" | "bare_metal"
+    #[arg(long = "to", value_parser = ["monolithic", "bare_metal"])]
+    to: String,
+
+    /// Report what would move without changing anything
+    #[arg(long)]
+    dry_run: bool,
+
+    /// File recording finished directories, so a rerun resumes where this one stopped
+    #[arg(long, value_name = "FILE")]
+    checkpoint: Option<PathBuf>,
+
+    /// Emit JSON progress events to stdout
+    #[arg(short, long)]
+    progress: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RekeyReport {
+    cache_dir: String,
+    target_scheme: String,
+    dry_run: bool,
+    /// Top-level directories a checkpoint had already marked done.
+    resumed_dirs: usize,
+    rekeyed: u64,
+    bytes_rekeyed: u64,
+    /// Includes the files this run moved into a directory it had not reached yet.
+    already_target: u64,
+    /// Originals removed because their new path already held their rekeyed copy.
+    finished_moves: u64,
+    conflicts: u64,
+    conflict_files: Vec<PathBuf>,
+    /// Files whose key has no equivalent, by reason.
+    blocked: BTreeMap<RekeyBlocker, u64>,
+    unverified: u64,
+    /// Staged copies an interrupted run left behind, removed.
+    stale_staging_removed: u64,
+    cancelled: bool,
+    timestamp: String,
+}
+
+impl RekeyReport {
+    fn record(&mut self, path: PathBuf, outcome: RekeyOutcome) {
+        match outcome {
+            RekeyOutcome::Rekeyed { bytes, .. } => {
+                self.rekeyed += 1;
+                self.bytes_rekeyed += bytes;
+            }
+            RekeyOutcome::AlreadyTarget => self.already_target += 1,
+            RekeyOutcome::FinishedMove => self.finished_moves += 1,
+            RekeyOutcome::Conflict => {
+                self.conflicts += 1;
+                if self.conflict_files.len() < MAX_REPORTED_CONFLICTS {
+                    self.conflict_files.push(path);
+                }
+            }
+            RekeyOutcome::Blocked(blocker) => *self.blocked.entry(blocker).or_default() += 1,
+            RekeyOutcome::Unverified => self.unverified += 1,
+        }
+    }
+}
+
+fn is_level_dir(path: &Path) -> bool {
+    path.is_dir()
+        && path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.len() == 2 && name.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+/// The cache files under one top-level directory (`levels=2:2`), and the staged copies a previous
+/// run left there.
+fn level_files(top: &Path) -> Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let mut files = Vec::new();
+    let mut staging = Vec::new();
+    for second in fs::read_dir(top).with_context(|| format!("failed to read {}", top.display()))? {
+        let second = second?.path();
+        if !is_level_dir(&second) {
+            continue;
+        }
+        for entry in
+            fs::read_dir(&second).with_context(|| format!("failed to read {}", second.display()))?
+        {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if rekey_core::is_staging_file(&name) {
+                staging.push(entry.path());
+            } else if cache_utils::parse_cache_file_digest(&name).is_some() {
+                files.push(entry.path());
+            }
+        }
+    }
+    files.sort();
+    Ok((files, staging))
+}
+
+fn run<F>(
+    args: &Args,
+    progress_path: &Path,
+    reporter: &ProgressReporter,
+    is_cancelled: F,
+) -> Result<()>
+where
+    F: Fn() -> bool + Sync,
+{
+    let target = CacheKeyScheme::from_config_str(&args.to);
+    let cache_dir = PathBuf::from(&args.cache_dir);
+    let placement = Placement::of_cache_dir(&cache_dir)?;
+    let mut checkpoint = match &args.checkpoint {
+        Some(path) => Some(Checkpoint::load_or_new(path, &cache_dir, target)?),
+        None => None,
+    };
+    let mut top_dirs: Vec<PathBuf> = fs::read_dir(&cache_dir)
+        .with_context(|| format!("failed to read {}", cache_dir.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| is_level_dir(path))
+        .collect();
+    top_dirs.sort();
+    let total_dirs = top_dirs.len();
+
+    let mut report = RekeyReport {
+        cache_dir: args.cache_dir.clone(),
+        target_scheme: target.as_config_str().to_string(),
+        dry_run: args.dry_run,
+        ..RekeyReport::default()
+    };
+    removal_core::write_progress(
+        progress_path,
+        reporter,
+        "starting",
+        "signalr.cacheRekey.starting",
+        json!({ "to": report.target_scheme, "dryRun": args.dry_run }),
+        0.0,
+        0,
+        total_dirs,
+    )?;
+    let threads = cache_utils::detect_filesystem_type(&cache_dir).recommended_parallelism();
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .context("failed to build the rekey thread pool")?;
+    eprintln!(
+        "[CacheRekey] Rekeying {} directories of {} to {} with {} thread(s){}",
+        total_dirs,
+        cache_dir.display(),
+        report.target_scheme,
+        threads,
+        if args.dry_run { " (dry run)" } else { "" }
+    );
+
+    for (index, top) in top_dirs.iter().enumerate() {
+        let dir_name = top
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if checkpoint
+            .as_ref()
+            .is_some_and(|checkpoint| checkpoint.completed_dirs.contains(&dir_name))
+        {
+            report.resumed_dirs += 1;
+            continue;
+        }
+        if is_cancelled() {
+            report.cancelled = true;
+            break;
+        }
+
+        let (files, staging) = level_files(top)?;
+        if !args.dry_run {
+            for stale in staging {
+                fs::remove_file(&stale)
+                    .with_context(|| format!("failed to remove {}", stale.display()))?;
+                report.stale_staging_removed += 1;
+            }
+        }
+        let outcomes: Vec<(PathBuf, Option<RekeyOutcome>)> = pool.install(|| {
+            files
+                .into_par_iter()
+                .map(|path| {
+                    if is_cancelled() {
+                        return Ok((path, None));
+                    }
+                    let outcome =
+                        rekey_core::rekey_file(&cache_dir, &path, target, &placement, args.dry_run)
+                            .with_context(|| format!("failed to rekey {}", path.display()))?;
+                    Ok((path, Some(outcome)))
+                })
+                .collect::<Result<_>>()
+        })?;
+        let mut finished = true;
+        for (path, outcome) in outcomes {
+            match outcome {
+                Some(outcome) => report.record(path, outcome),
+                None => finished = false,
+            }
+        }
+        if !finished {
+            report.cancelled = true;
+            break;
+        }
+        if let (Some(checkpoint), Some(path)) = (checkpoint.as_mut(), &args.checkpoint) {
+            if !args.dry_run {
+                checkpoint.completed_dirs.insert(dir_name);
+                progress_utils::write_progress_json(path, checkpoint)?;
+            }
+        }
+        removal_core::write_progress(
+            progress_path,
+            reporter,
+            "rekeying",
+            "signalr.cacheRekey.progress",
+            json!({ "dirs": index + 1, "totalDirs": total_dirs, "files": report.rekeyed }),
+            (index + 1) as f64 / total_dirs.max(1) as f64 * 100.0,
+            index + 1,
+            total_dirs,
+        )?;
+    }
+
+    report.timestamp = progress_utils::current_timestamp();
+    fs::write(&args.output_json, serde_json::to_string_pretty(&report)?)
+        .with_context(|| format!("Failed to write {}", args.output_json))?;
+    let blocked: u64 = report.blocked.values().sum();
+    eprintln!(
+        "[CacheRekey] {} rekeyed ({:.2} GB), {} already {}, {} finished, {} conflict(s), {} without an equivalent, {} unverified",
+        report.rekeyed,
+        report.bytes_rekeyed as f64 / 1_073_741_824.0,
+        report.already_target,
+        report.target_scheme,
+        report.finished_moves,
+        report.conflicts,
+        blocked,
+        report.unverified
+    );
+    let context = json!({
+        "files": report.rekeyed,
+        "gb": report.bytes_rekeyed as f64 / 1_073_741_824.0,
+        "conflicts": report.conflicts,
+        "blocked": blocked,
+        "dryRun": args.dry_run,
+    });
+    if report.cancelled {
+        reporter.emit_cancelled("signalr.cacheRekey.complete", context);
+        return Ok(());
+    }
+    if let Some(path) = &args.checkpoint {
+        if !args.dry_run {
+            let _ = fs::remove_file(path);
+        }
+    }
+    removal_core::write_progress(
+        progress_path,
+        reporter,
+        "completed",
+        "signalr.cacheRekey.complete",
+        context,
+        100.0,
+        total_dirs,
+        total_dirs,
+    )
+}
+
+fn main() -> Result<()> {
+    cancel::install();
+    let args = Args::parse();
+    let reporter = ProgressReporter::new(args.progress);
+    let result = run(
+        &args,
+        Path::new(&args.progress_json),
+        &reporter,
+        cancel::is_cancelled,
+    );
+    progress_events::finish_or_exit(&reporter, "signalr.cacheRekey.error.fatal", result);
+    Ok(())
+}
+
+#[cfg(all(test, unix, target_pointer_width = "64"))]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A Linux x86_64 v5 cache file holding `key`, the layout `rekey_core` rewrites.
+    fn write_cache_file(cache_dir: &Path, key: &str) {
+        let headers: &[u8] = b"HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 0-3/4\r\n\r\n";
+        let header_start = 336 + b"\nKEY: ".len() + key.len() + 1;
+        let body_start = header_start + headers.len();
+        let mut bytes = vec![0; body_start];
+        bytes[..8].copy_from_slice(&5usize.to_ne_bytes());
+        bytes[48..52].copy_from_slice(&crc32fast::hash(key.as_bytes()).to_ne_bytes());
+        bytes[54..56].copy_from_slice(&(header_start as u16).to_ne_bytes());
+        bytes[56..58].copy_from_slice(&(body_start as u16).to_ne_bytes());
+        bytes[336..header_start - 1].copy_from_slice(format!("\nKEY: {key}").as_bytes());
+        bytes[header_start - 1] = b'\n';
+        bytes[header_start..].copy_from_slice(headers);
+        bytes.extend_from_slice(b"body");
+        let path =
+            cache_utils::cache_path_for_digest(cache_dir, cache_utils::calculate_md5_digest(key));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, bytes).unwrap();
+    }
+
+    fn rekey(temp: &Path, is_cancelled: impl Fn() -> bool + Sync) -> serde_json::Value {
+        let args = Args {
+            cache_dir: temp.join("cache").to_string_lossy().into_owned(),
+            output_json: temp.join("report.json").to_string_lossy().into_owned(),
+            progress_json: temp.join("progress.json").to_string_lossy().into_owned(),
+            to: "bare_metal".to_string(),
+            dry_run: false,
+            checkpoint: Some(temp.join("checkpoint.json")),
+            progress: false,
+        };
+        run(
+            &args,
+            Path::new(&args.progress_json),
+            &ProgressReporter::new(false),
+            is_cancelled,
+        )
+        .unwrap();
+        serde_json::from_slice(&fs::read(&args.output_json).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn a_cancelled_run_resumes_from_its_checkpoint_without_redoing_files() {
+        let temp = tempfile::tempdir().unwrap();
+        let cache_dir = temp.path().join("cache");
+        let keys: Vec<String> = (0..8)
+            .map(|index| format!("blizzard/tpr/wow/data/ab/cd{index}bytes=0-1048575"))
+            .collect();
+        for key in &keys {
+            write_cache_file(&cache_dir, key);
+        }
+
+        // Cancel after a few checks: the first directories finish, the rest wait for the rerun.
+        let checks = AtomicUsize::new(0);
+        let first = rekey(temp.path(), || checks.fetch_add(1, Ordering::SeqCst) >= 4);
+        assert_eq!(first["cancelled"], true);
+        assert!(temp.path().join("checkpoint.json").exists());
+        let first_rekeyed = first["rekeyed"].as_u64().unwrap();
+        assert!(first_rekeyed > 0 && first_rekeyed < keys.len() as u64);
+
+        let second = rekey(temp.path(), || false);
+        assert_eq!(second["cancelled"], false);
+        assert!(second["resumedDirs"].as_u64().unwrap() > 0);
+        assert_eq!(
+            first_rekeyed + second["rekeyed"].as_u64().unwrap(),
+            keys.len() as u64
+        );
+        for report in [&first, &second] {
+            assert_eq!(report["finishedMoves"], 0);
+            assert_eq!(report["conflicts"], 0);
+        }
+        for key in &keys {
+            let bare_metal = format!("lancache-{key}");
+            let moved = cache_utils::cache_path_for_digest(
+                &cache_dir,
+                cache_utils::calculate_md5_digest(&bare_metal),
+            );
+            assert_eq!(
+                cache_utils::read_cache_file_key(&moved).as_deref(),
+                Some(bare_metal.as_str())
+            );
+        }
+        assert!(!temp.path().join("checkpoint.json").exists());
+    }
+}
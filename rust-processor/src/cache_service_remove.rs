@@ -11,9 +11,17 @@ use std::path::{Path, PathBuf};
 use lancache_processor::cache_utils;
 use lancache_processor::cancel;
 use lancache_processor::db;
+use lancache_processor::db_backup::{self, BackupArchive};
 use lancache_processor::log_purge;
 use lancache_processor::progress_events;
 use lancache_processor::progress_utils;
+use lancache_processor::protected_games::{self, ProtectedFiles, Protection};
+use lancache_processor::quarantine::{DeferredCleanup, Quarantine, QuarantineArgs};
+use lancache_processor::removal_core::{self, CacheRemovalOutcome};
+use lancache_processor::removal_plan::{
+    self, PlanRun, PlanStageKeys, PlanTarget, PlannedFiles, PlannedLogPurge, PlannedRows,
+    RemovalPlan,
+};
 use log_purge::remove_log_entries_for_service;
 use progress_events::ProgressReporter;
 
@@ -45,6 +53,15 @@ struct Args {
     )]
     key_scheme: String,
 
+    #[command(flatten)]
+    backup: db_backup::BackupArgs,
+
+    #[command(flatten)]
+    plan: removal_plan::PlanArgs,
+
+    #[command(flatten)]
+    quarantine: QuarantineArgs,
+
     /// Emit JSON progress events to stdout
     #[arg(short, long)]
     progress: bool,
@@ -65,6 +82,18 @@ struct ProgressData {
     timestamp: String,
 }
 
+/// The service flow has no directory-cleanup stage; an applied plan reports its short cleanup
+/// as finalizing.
+const SERVICE_PLAN_KEYS: PlanStageKeys = PlanStageKeys {
+    planned: "signalr.serviceRemove.planned",
+    cache_removing: "signalr.serviceRemove.cache.removing",
+    cache_file_progress: "signalr.serviceRemove.cache.file.progress",
+    dirs_cleaning: "signalr.serviceRemove.finalizing",
+    logs_removing: "signalr.serviceRemove.logs.removing",
+    db_deleting: "signalr.serviceRemove.db.deleting",
+    complete: "signalr.serviceRemove.complete",
+};
+
 #[derive(Debug, Serialize)]
 struct RemovalReport {
     service_name: String,
@@ -72,16 +101,23 @@ struct RemovalReport {
     total_bytes_freed: u64,
     log_entries_removed: u64,
     database_entries_deleted: u64,
+    /// Cache files of protected games, left in place.
+    protected_files_skipped: Vec<String>,
+    /// Batch holding the files when they were quarantined instead of deleted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quarantine_batch: Option<String>,
 }
 
 impl RemovalReport {
-    fn partial(service: &str, cache_files_deleted: usize, total_bytes_freed: u64) -> Self {
+    fn partial(service: &str, cache: &CacheRemovalOutcome) -> Self {
         Self {
             service_name: service.to_string(),
-            cache_files_deleted,
-            total_bytes_freed,
+            cache_files_deleted: cache.deleted_files,
+            total_bytes_freed: cache.bytes_freed,
             log_entries_removed: 0,
             database_entries_deleted: 0,
+            protected_files_skipped: cache.protected_file_names(),
+            quarantine_batch: None,
         }
     }
 }
@@ -194,6 +230,7 @@ async fn get_service_urls_from_db(pool: &PgPool, service: &str) -> Result<HashMa
     Ok(urls)
 }
 
+#[allow(clippy::too_many_arguments)]
 fn remove_cache_files_for_service(
     cache_dir: &Path,
     service: &str,
@@ -201,10 +238,12 @@ fn remove_cache_files_for_service(
     progress_path: &Path,
     reporter: &ProgressReporter,
     scheme: cache_utils::CacheKeyScheme,
-) -> Result<(usize, u64, usize, usize)> {
-    // Returns (deleted_count, bytes_freed, permission_errors, verification_skips).
+    protected: &ProtectedFiles,
+    quarantine: Option<&Quarantine>,
+) -> Result<CacheRemovalOutcome> {
     use rayon::prelude::*;
     use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+    use std::sync::Mutex;
 
     eprintln!("Removing cache files for service '{}'...", service);
     eprintln!("Collecting cache file paths for deletion...");
@@ -215,7 +254,7 @@ fn remove_cache_files_for_service(
     // file-name digests - a steam-sized service is millions of slices, and holding a full
     // PathBuf per slice peaked at hundreds of MB. The canonical path is rebuilt per digest
     // at deletion time (the same layout the existence probe below uses).
-    let digests_to_delete: Vec<(u128, Option<String>)> = urls
+    let digests_to_delete: Vec<(u128, Option<String>, &str)> = urls
         .par_iter()
         .flat_map(|(url, _total_bytes)| match scheme {
             cache_utils::CacheKeyScheme::Monolithic => {
@@ -223,7 +262,7 @@ fn remove_cache_files_for_service(
                     cache_utils::cache_path_for_digest(cache_dir, digest).exists()
                 })
                 .into_iter()
-                .map(|digest| (digest, None))
+                .map(|digest| (digest, None, url.as_str()))
                 .collect::<Vec<_>>()
             }
             cache_utils::CacheKeyScheme::BareMetal => {
@@ -231,7 +270,7 @@ fn remove_cache_files_for_service(
                     cache_utils::cache_path_for_digest(cache_dir, digest).exists()
                 })
                 .into_iter()
-                .map(|(digest, key)| (digest, Some(key)))
+                .map(|(digest, key)| (digest, Some(key), url.as_str()))
                 .collect::<Vec<_>>()
             }
         })
@@ -244,12 +283,13 @@ fn remove_cache_files_for_service(
     let bytes_freed = AtomicU64::new(0);
     let permission_errors = AtomicUsize::new(0);
     let verification_skips = AtomicUsize::new(0);
+    let protected_hits: Mutex<Vec<(u128, &str)>> = Mutex::new(Vec::new());
     // Track how many paths have been checked for progress (not just deleted)
     let paths_checked = AtomicUsize::new(0);
     // Track last reported percent to avoid writing progress too frequently
     let last_reported_percent = AtomicUsize::new(0);
 
-    digests_to_delete.par_iter().for_each(|(digest, expected_key)| {
+    digests_to_delete.par_iter().for_each(|(digest, expected_key, url)| {
         // Cooperative cancellation: skip remaining files if cancel was requested.
         // Already-deleted files stay deleted — consistent partial state that C# reconciles.
         if cancel::is_cancelled() {
@@ -261,6 +301,12 @@ fn remove_cache_files_for_service(
         let cache_path = cache_utils::cache_path_for_digest(cache_dir, *digest);
         if cache_path.exists() {
             match cache_utils::safe_path_under_root(cache_dir, &cache_path) {
+                Ok(_) if protected.contains_digest(*digest) => {
+                    protected_hits
+                        .lock()
+                        .unwrap_or_else(|err| err.into_inner())
+                        .push((*digest, *url));
+                }
                 Ok(_) => {
                     // Bare-metal deletion gate: the file must prove it holds the
                     // recipe-computed key. A missing expected key, unreadable header,
@@ -282,7 +328,11 @@ fn remove_cache_files_for_service(
                             bytes_freed.fetch_add(metadata.len(), Ordering::Relaxed);
                         }
 
-                        match fs::remove_file(&cache_path) {
+                        let removed = match quarantine {
+                            Some(quarantine) => quarantine.take(&cache_path),
+                            None => fs::remove_file(&cache_path),
+                        };
+                        match removed {
                             Ok(_) => {
                                 let count = deleted_files.fetch_add(1, Ordering::Relaxed) + 1;
                                 if count.is_multiple_of(100) {
@@ -358,32 +408,126 @@ fn remove_cache_files_for_service(
         );
     }
 
-    Ok((
-        final_deleted,
-        final_bytes,
-        final_permission_errors,
-        final_verification_skips,
+    let mut protected_files = Vec::new();
+    let mut protected_urls = HashSet::new();
+    for (digest, url) in protected_hits.into_inner().unwrap_or_else(|err| err.into_inner()) {
+        protected_files.push(cache_utils::cache_path_for_digest(cache_dir, digest));
+        protected_urls.insert(url.to_string());
+    }
+    protected_files.sort();
+    if !protected_files.is_empty() {
+        eprintln!(
+            "Kept {} file(s) of protected games ({} URL(s))",
+            protected_files.len(),
+            protected_urls.len()
+        );
+    }
+
+    Ok(CacheRemovalOutcome {
+        deleted_files: final_deleted,
+        bytes_freed: final_bytes,
+        parent_dirs: HashSet::new(),
+        permission_errors: final_permission_errors,
+        verification_skips: final_verification_skips,
+        protected_files,
+        protected_urls,
+    })
+}
+
+/// `--plan`: what this removal would delete, scoped exactly like the removal below.
+async fn plan_removal(
+    pool: &PgPool,
+    args: &Args,
+    scheme: cache_utils::CacheKeyScheme,
+    protection: &Protection,
+    urls: &HashMap<String, i64>,
+) -> Result<RemovalPlan> {
+    let log_dir = Path::new(&args.log_dir);
+    let cache_dir = Path::new(&args.cache_dir);
+    let target = PlanTarget::Service {
+        service: args.service.to_lowercase(),
+    };
+    // An empty URL set ends the removal before logs and rows are touched.
+    if urls.is_empty() {
+        return Ok(RemovalPlan::new(
+            target,
+            log_dir,
+            cache_dir,
+            scheme,
+            PlannedFiles::default(),
+            PlannedLogPurge::default(),
+            PlannedRows::default(),
+        ));
+    }
+
+    let url_data: HashMap<String, (String, i64)> = urls
+        .iter()
+        .map(|(url, bytes)| (url.clone(), (args.service.clone(), *bytes)))
+        .collect();
+    let files = removal_plan::plan_cache_files(
+        cache_dir,
+        &url_data,
+        scheme,
+        &protection.files(cache_dir, scheme),
+    );
+    let url_set: HashSet<String> = urls
+        .keys()
+        .filter(|url| !files.protected_urls.contains(*url))
+        .cloned()
+        .collect();
+    let log_purge =
+        removal_plan::plan_log_purge(log_dir, Some(&args.service), url_set, HashSet::new())?;
+    let database = removal_plan::plan_service_rows(pool, &args.service).await?;
+    Ok(RemovalPlan::new(
+        target, log_dir, cache_dir, scheme, files, log_purge, database,
     ))
 }
 
+/// LogEntries `delete_service_from_database` removes (alias `le`, $1 = lowercased service).
+fn deleted_log_entries_filter() -> String {
+    format!(
+        r#"LOWER(le."Service") = $1 AND NOT {}"#,
+        protected_games::log_entry_is_protected("le")
+    )
+}
+
+/// Downloads `delete_service_from_database` removes, read before its log delete runs (alias `d`,
+/// $1 = lowercased service): unprotected, and pointed at by no log entry the delete keeps.
+fn deleted_downloads_filter() -> String {
+    format!(
+        r#"LOWER(d."Service") = $1 AND NOT {}
+           AND NOT EXISTS (SELECT 1 FROM "LogEntries" le
+                           WHERE le."DownloadId" = d."Id" AND NOT ({}))"#,
+        protected_games::download_is_protected("d"),
+        deleted_log_entries_filter()
+    )
+}
+
 async fn delete_service_from_database(pool: &PgPool, service: &str) -> Result<u64> {
     eprintln!("Deleting database records for service '{}'...", service);
 
     let service_lower = service.to_lowercase();
 
-    // First delete LogEntries
-    let log_result = sqlx::query("DELETE FROM \"LogEntries\" WHERE LOWER(\"Service\") = $1")
-        .bind(&service_lower)
-        .execute(pool)
-        .await?;
+    // First delete LogEntries, keeping every row that describes a protected game's files
+    let log_result = sqlx::query(&format!(
+        "DELETE FROM \"LogEntries\" le WHERE {}",
+        deleted_log_entries_filter()
+    ))
+    .bind(&service_lower)
+    .execute(pool)
+    .await?;
     let log_deleted = log_result.rows_affected();
     eprintln!("  Deleted {} log entry records", log_deleted);
 
-    // Then delete Downloads
-    let downloads_result = sqlx::query("DELETE FROM \"Downloads\" WHERE LOWER(\"Service\") = $1")
-        .bind(&service_lower)
-        .execute(pool)
-        .await?;
+    // Then delete Downloads; a protected row, or one a kept LogEntries row still points at, stays
+    let downloads_result = sqlx::query(&format!(
+        "DELETE FROM \"Downloads\" d WHERE LOWER(d.\"Service\") = $1 AND NOT {} \
+         AND NOT EXISTS (SELECT 1 FROM \"LogEntries\" le WHERE le.\"DownloadId\" = d.\"Id\")",
+        protected_games::download_is_protected("d")
+    ))
+    .bind(&service_lower)
+    .execute(pool)
+    .await?;
     let downloads_deleted = downloads_result.rows_affected();
     eprintln!("  Deleted {} download records", downloads_deleted);
 
@@ -414,35 +558,78 @@ async fn main() -> Result<()> {
     eprintln!("  Service: {}", service);
 
     let pool = db::create_pool().await?;
+    let protection = Protection::load(&pool).await?;
 
     write_progress(&progress_path, &reporter, "starting", "signalr.serviceRemove.starting.default", json!({ "service": service }), 0.0, 0, 0)?;
 
+    let plan_run = PlanRun {
+        output_json: &output_json,
+        progress_path: &progress_path,
+        reporter: &reporter,
+        keys: &SERVICE_PLAN_KEYS,
+        identity: json!({ "service": service }),
+    };
+    if let Some(plan_path) = &args.plan.apply {
+        let target = PlanTarget::Service { service: service.to_lowercase() };
+        let plan = RemovalPlan::load(plan_path, &target, &log_dir, &cache_dir, key_scheme)?;
+        let protected = protection.files(&cache_dir, key_scheme);
+        let quarantine = args.quarantine.open(&cache_dir, "service_remove")?;
+        return removal_plan::apply(&pool, &plan, &protected, args.backup.policy().as_ref(), quarantine, &plan_run).await;
+    }
+
     // Step 1: Get all URLs for this service from database
     write_progress(&progress_path, &reporter, "querying_database", "signalr.serviceRemove.db.querying", json!({}), 5.0, 0, 0)?;
     let urls = get_service_urls_from_db(&pool, service).await?;
 
+    if args.plan.plan {
+        let plan = plan_removal(&pool, &args, key_scheme, &protection, &urls).await?;
+        return removal_plan::write_plan(&plan, &plan_run);
+    }
+
     if urls.is_empty() {
         eprintln!("No URLs found for service '{}'", service);
         write_progress(&progress_path, &reporter, "completed", "signalr.serviceRemove.noUrls", json!({}), 100.0, 0, 0)?;
         return Ok(());
     }
 
+    // Pre-flight backup of the rows `delete_service_from_database` removes, before anything
+    // is touched so a failed backup aborts the whole removal. A quarantined removal deletes no
+    // rows; the purge of its batch takes the backup instead.
+    let backup = match args.backup.policy().filter(|_| args.quarantine.quarantine_dir.is_none()) {
+        Some(policy) => {
+            eprintln!("\nBacking up database records to {}...", policy.dir.display());
+            let service_lower = service.to_lowercase();
+            let mut archive = BackupArchive::create(&policy, "service_remove", json!({ "service": service }))?;
+            archive
+                .add_downloads(&pool, &deleted_downloads_filter(), |q| q.bind(service_lower.clone()))
+                .await?;
+            archive
+                .add_rows(&pool, "LogEntries", "le", &deleted_log_entries_filter(), |q| q.bind(service_lower.clone()))
+                .await?;
+            let summary = archive.finish()?;
+            db_backup::log_summary(&summary);
+            Some(summary)
+        }
+        None => None,
+    };
+    let quarantine = args.quarantine.open(&cache_dir, "service_remove")?;
+
     // Step 2: Remove cache files
     let url_count = urls.len();
     write_progress(&progress_path, &reporter, "removing_cache", "signalr.serviceRemove.cache.removing", json!({ "count": url_count }), 10.0, 0, url_count)?;
-    let (
-        cache_files_deleted,
-        total_bytes_freed,
-        cache_permission_errors,
-        verification_skips,
-    ) = remove_cache_files_for_service(
+    let cache = remove_cache_files_for_service(
         &cache_dir,
         service,
         &urls,
         &progress_path,
         &reporter,
         key_scheme,
+        &protection.files(&cache_dir, key_scheme),
+        quarantine.as_ref(),
     )?;
+    let cache_files_deleted = cache.deleted_files;
+    let total_bytes_freed = cache.bytes_freed;
+    let cache_permission_errors = cache.permission_errors;
 
     // After cache removal: if cancellation arrived, flush partial progress and exit 0.
     // C# re-runs reconciliation/detection after a cancelled remove.
@@ -464,15 +651,40 @@ async fn main() -> Result<()> {
     // A failed bare-metal KEY check leaves the cache candidate untouched. Preserve its
     // access-log and database provenance, write the successfully completed cache portion,
     // and fail the logical removal so the caller can surface and retry it.
-    if let Err(error) = ensure_cache_deletions_verified(verification_skips) {
-        let report = RemovalReport::partial(service, cache_files_deleted, total_bytes_freed);
+    if let Err(error) = ensure_cache_deletions_verified(cache.verification_skips) {
+        let report = RemovalReport::partial(service, &cache);
         write_removal_report(&output_json, &report)?;
         return Err(error);
     }
 
+    // Lines of kept files stay, so later scans still see what is on disk.
+    let url_set: HashSet<String> = urls
+        .keys()
+        .filter(|url| !cache.protected_urls.contains(*url))
+        .cloned()
+        .collect();
+
+    // Quarantined: the log purge and row delete are recorded in the batch for its purge.
+    if let Some(quarantine) = quarantine {
+        let database = removal_plan::plan_service_rows(&pool, service).await?;
+        let report = RemovalReport {
+            quarantine_batch: Some(quarantine.id().to_string()),
+            ..RemovalReport::partial(service, &cache)
+        };
+        let finished = removal_core::finish_quarantine(
+            quarantine,
+            cache_permission_errors,
+            DeferredCleanup::removal(&log_dir, Some(service), url_set, HashSet::new(), database),
+        );
+        write_removal_report(&output_json, &report)?;
+        finished?;
+
+        let complete_context = json!({ "files": cache_files_deleted, "gb": total_bytes_freed as f64 / 1_073_741_824.0, "logEntries": 0, "dbRecords": 0, "service": service, "protectedSkipped": report.protected_files_skipped.len(), "quarantineBatch": report.quarantine_batch });
+        return write_progress(&progress_path, &reporter, "completed", "signalr.serviceRemove.complete", complete_context, 100.0, cache_files_deleted, url_count);
+    }
+
     // Step 3: Remove log entries
     write_progress(&progress_path, &reporter, "removing_logs", "signalr.serviceRemove.logs.removing", json!({}), 70.0, cache_files_deleted, url_count)?;
-    let url_set: HashSet<String> = urls.keys().cloned().collect();
     let (log_entries_removed, log_permission_errors) = remove_log_entries_for_service(&log_dir, service, &url_set)?;
 
     // CRITICAL: Check for permission errors before deleting database records
@@ -495,7 +707,16 @@ async fn main() -> Result<()> {
     write_progress(&progress_path, &reporter, "removing_database", "signalr.serviceRemove.db.deleting", json!({}), 90.0, cache_files_deleted, url_count)?;
     let database_entries_deleted = delete_service_from_database(&pool, service).await?;
 
-    write_progress(&progress_path, &reporter, "completed", "signalr.serviceRemove.complete", json!({ "files": cache_files_deleted, "gb": total_bytes_freed as f64 / 1_073_741_824.0, "logEntries": log_entries_removed, "dbRecords": database_entries_deleted, "service": service }), 100.0, cache_files_deleted, url_count)?;
+    let report = RemovalReport {
+        log_entries_removed,
+        database_entries_deleted,
+        ..RemovalReport::partial(service, &cache)
+    };
+    write_removal_report(&output_json, &report)?;
+
+    let mut complete_context = json!({ "files": cache_files_deleted, "gb": total_bytes_freed as f64 / 1_073_741_824.0, "logEntries": log_entries_removed, "dbRecords": database_entries_deleted, "service": service, "protectedSkipped": report.protected_files_skipped.len() });
+    db_backup::annotate_context(&mut complete_context, backup.as_ref());
+    write_progress(&progress_path, &reporter, "completed", "signalr.serviceRemove.complete", complete_context, 100.0, cache_files_deleted, url_count)?;
 
     eprintln!("\n=== Removal Summary ===");
     eprintln!("Service: {}", service);
@@ -503,6 +724,7 @@ async fn main() -> Result<()> {
     eprintln!("Bytes freed: {:.2} GB", total_bytes_freed as f64 / 1_073_741_824.0);
     eprintln!("Log entries removed: {}", log_entries_removed);
     eprintln!("Database entries deleted: {}", database_entries_deleted);
+    eprintln!("Protected files kept: {}", report.protected_files_skipped.len());
     eprintln!("Removal completed successfully");
 
     Ok(())
@@ -564,20 +786,26 @@ mod tests {
 
         let progress_path = temp.path().join("progress.json");
         let urls = HashMap::from([(url.to_string(), 0_i64)]);
-        let (deleted, bytes, permission_errors, verification_skips) =
-            remove_cache_files_for_service(
-                temp.path(),
-                service,
-                &urls,
-                &progress_path,
-                &ProgressReporter::new(false),
-                cache_utils::CacheKeyScheme::BareMetal,
-            )
-            .unwrap();
+        let outcome = remove_cache_files_for_service(
+            temp.path(),
+            service,
+            &urls,
+            &progress_path,
+            &ProgressReporter::new(false),
+            cache_utils::CacheKeyScheme::BareMetal,
+            &ProtectedFiles::none(),
+            None,
+        )
+        .unwrap();
 
         assert!(cache_path.exists(), "unverified file must remain untouched");
         assert_eq!(
-            (deleted, bytes, permission_errors, verification_skips),
+            (
+                outcome.deleted_files,
+                outcome.bytes_freed,
+                outcome.permission_errors,
+                outcome.verification_skips
+            ),
             (0, 0, 0, 1)
         );
         let progress: serde_json::Value =
@@ -595,7 +823,16 @@ mod tests {
 
         let temp = tempfile::tempdir().unwrap();
         let report_path = temp.path().join("report.json");
-        write_removal_report(&report_path, &RemovalReport::partial("steam", 3, 4096)).unwrap();
+        let cache = CacheRemovalOutcome {
+            deleted_files: 3,
+            bytes_freed: 4096,
+            parent_dirs: HashSet::new(),
+            permission_errors: 0,
+            verification_skips: 2,
+            protected_files: vec![PathBuf::from("/cache/ab/cd/0123abcd")],
+            protected_urls: HashSet::new(),
+        };
+        write_removal_report(&report_path, &RemovalReport::partial("steam", &cache)).unwrap();
         let report: serde_json::Value =
             serde_json::from_slice(&fs::read(report_path).unwrap()).unwrap();
         assert_eq!(report["service_name"], "steam");
@@ -603,6 +840,41 @@ mod tests {
         assert_eq!(report["total_bytes_freed"], 4096);
         assert_eq!(report["log_entries_removed"], 0);
         assert_eq!(report["database_entries_deleted"], 0);
+        assert_eq!(report["protected_files_skipped"][0], "/cache/ab/cd/0123abcd");
+    }
+
+    #[test]
+    fn protected_digests_survive_a_service_wide_removal() {
+        let temp = tempfile::tempdir().unwrap();
+        let urls = HashMap::from([
+            ("/depot/1/chunk/kept".to_string(), 0_i64),
+            ("/depot/1/chunk/gone".to_string(), 0_i64),
+        ]);
+        let kept = cache_utils::calculate_cache_path_no_range(temp.path(), "steam", "/depot/1/chunk/kept");
+        let gone = cache_utils::calculate_cache_path_no_range(temp.path(), "steam", "/depot/1/chunk/gone");
+        write_cache_file(&kept, None);
+        write_cache_file(&gone, None);
+        let protected: ProtectedFiles = [cache_utils::calculate_md5_digest("steam/depot/1/chunk/kept")]
+            .into_iter()
+            .collect();
+
+        let outcome = remove_cache_files_for_service(
+            temp.path(),
+            "steam",
+            &urls,
+            &temp.path().join("progress.json"),
+            &ProgressReporter::new(false),
+            cache_utils::CacheKeyScheme::Monolithic,
+            &protected,
+            None,
+        )
+        .unwrap();
+
+        assert!(kept.exists());
+        assert!(!gone.exists());
+        assert_eq!(outcome.deleted_files, 1);
+        assert_eq!(outcome.protected_files, vec![kept]);
+        assert!(outcome.protected_urls.contains("/depot/1/chunk/kept"));
     }
 
     #[test]
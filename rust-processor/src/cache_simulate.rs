@@ -0,0 +1,866 @@
+//! What-if cache sizing: replays the access history in LogEntries through models of an nginx
+//! slice cache at several sizes and reports what each would have achieved.
+//!
+//! Every request is mapped to the cache objects nginx would have stored for it with the same
+//! rules the detection and removal tools use: `physical_slices_for_request` for the monolithic
+//! `$cacheidentifier$uri$slice_range` recipe, and the per-vhost `lancache-*` keys for bare metal.
+//! A ranged request touches each 1 MiB slice it overlaps; a range-less request touches one
+//! unsliced object the size of the response. Objects are identified by their cache-key digest,
+//! so two URLs that nginx would store once are simulated once.
+//!
+//! All sizes and policies are simulated in a single pass over the log. LRU is what nginx does;
+//! LFU and a byte-weighted ARC are offered for comparison. The models start empty, so the first
+//! days of history are dominated by cold misses; `--warmup-days` replays them without counting.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use clap::{Parser, ValueEnum};
+use futures_util::TryStreamExt;
+use serde::Serialize;
+use serde_json::json;
+use sqlx::Row;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use lancache_processor::cache_utils::{self, CacheKeyScheme, ObservedByteRange};
+use lancache_processor::cancel;
+use lancache_processor::db;
+use lancache_processor::progress_events;
+use lancache_processor::progress_utils;
+use lancache_processor::service_utils;
+use progress_events::ProgressReporter;
+
+/// Log entries replayed between two progress updates.
+const PROGRESS_INTERVAL: usize = 100_000;
+
+#[derive(Parser, Debug)]
+#[command(name = "cache_simulate")]
+#[command(about = "Replays access history through LRU/LFU/ARC cache models at several sizes")]
+struct Args {
+    output_json: String,
+
+    /// Path to progress JSON file (use "none" to skip)
+    #[arg(default_value = "none")]
+    progress_json: String,
+
+    /// Cache sizes to simulate, comma separated, with k/m/g/t suffixes (e.g. 4t,8t,16t)
+    #[arg(long, value_delimiter = ',', required = true)]
+    sizes: Vec<String>,
+
+    /// Eviction policies to simulate, comma separated
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "lru")]
+    policies: Vec<PolicyKind>,
+
+    /// Datasource whose history is replayed, as recorded in LogEntries
+    #[arg(long, default_value = "default")]
+    datasource: String,
+
+    /// Cache-key recipe of the datasource: "monolithic" (default) | "bare_metal"
+    #[arg(long = "key-scheme", default_value = "monolithic")]
+    key_scheme: String,
+
+    /// Only replay the last N days of history (default: all of it)
+    #[arg(long)]
+    days: Option<i64>,
+
+    /// Replay this many days from the start of the history without counting them
+    #[arg(long, default_value_t = 0)]
+    warmup_days: i64,
+
+    /// Emit JSON progress events to stdout
+    #[arg(short, long)]
+    progress: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+enum PolicyKind {
+    Lru,
+    Lfu,
+    Arc,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProgressData {
+    status: String,
+    stage_key: String,
+    context: serde_json::Value,
+    percent_complete: f64,
+    requests_replayed: usize,
+    total_requests: i64,
+    timestamp: String,
+}
+
+/// What one object access did to a simulated cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Access {
+    hit: bool,
+    evicted_objects: u64,
+    evicted_bytes: u64,
+}
+
+impl Access {
+    fn evicted(&mut self, size: u64) {
+        self.evicted_objects += 1;
+        self.evicted_bytes += size;
+    }
+}
+
+trait CachePolicy {
+    /// Requests `digest`, inserting it on a miss and evicting whatever that displaces. An object
+    /// larger than the whole cache is never stored.
+    fn access(&mut self, digest: u128, size: u64) -> Access;
+}
+
+/// A byte-capacity cache that evicts the entry with the lowest rank. LRU ranks by last access;
+/// LFU ranks by access count and breaks ties by last access.
+struct RankedCache {
+    capacity: u64,
+    used: u64,
+    tick: u64,
+    by_frequency: bool,
+    entries: HashMap<u128, RankedEntry>,
+    order: BTreeMap<(u64, u64), u128>,
+}
+
+struct RankedEntry {
+    size: u64,
+    frequency: u64,
+    tick: u64,
+}
+
+impl RankedCache {
+    fn new(capacity: u64, by_frequency: bool) -> Self {
+        Self {
+            capacity,
+            used: 0,
+            tick: 0,
+            by_frequency,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    fn rank(&self, entry: &RankedEntry) -> (u64, u64) {
+        if self.by_frequency {
+            (entry.frequency, entry.tick)
+        } else {
+            (0, entry.tick)
+        }
+    }
+}
+
+impl CachePolicy for RankedCache {
+    fn access(&mut self, digest: u128, size: u64) -> Access {
+        self.tick += 1;
+        let mut access = Access::default();
+        if let Some(mut entry) = self.entries.remove(&digest) {
+            self.order.remove(&self.rank(&entry));
+            entry.frequency += 1;
+            entry.tick = self.tick;
+            self.order.insert(self.rank(&entry), digest);
+            self.entries.insert(digest, entry);
+            access.hit = true;
+            return access;
+        }
+        if size > self.capacity {
+            return access;
+        }
+        while self.used + size > self.capacity {
+            let Some((_, victim)) = self.order.pop_first() else {
+                break;
+            };
+            if let Some(evicted) = self.entries.remove(&victim) {
+                self.used -= evicted.size;
+                access.evicted(evicted.size);
+            }
+        }
+        let entry = RankedEntry {
+            size,
+            frequency: 1,
+            tick: self.tick,
+        };
+        self.order.insert(self.rank(&entry), digest);
+        self.entries.insert(digest, entry);
+        self.used += size;
+        access
+    }
+}
+
+/// One of ARC's four lists, kept in recency order with its total size.
+#[derive(Default)]
+struct ArcList {
+    bytes: u64,
+    entries: HashMap<u128, (u64, u64)>,
+    order: BTreeMap<u64, u128>,
+}
+
+impl ArcList {
+    fn push(&mut self, digest: u128, size: u64, tick: u64) {
+        self.entries.insert(digest, (size, tick));
+        self.order.insert(tick, digest);
+        self.bytes += size;
+    }
+
+    fn remove(&mut self, digest: u128) -> Option<u64> {
+        let (size, tick) = self.entries.remove(&digest)?;
+        self.order.remove(&tick);
+        self.bytes -= size;
+        Some(size)
+    }
+
+    fn pop_oldest(&mut self) -> Option<(u128, u64)> {
+        let (_, digest) = self.order.pop_first()?;
+        let (size, _) = self.entries.remove(&digest)?;
+        self.bytes -= size;
+        Some((digest, size))
+    }
+}
+
+/// Adaptive Replacement Cache with every count measured in bytes. `t1` holds objects seen once
+/// recently, `t2` objects seen at least twice; `b1` and `b2` remember what each evicted, and a
+/// hit on those ghosts moves `target`, the share of the cache `t1` may hold, towards whichever list was wrong.
+struct AdaptiveCache {
+    capacity: u64,
+    target: u64,
+    tick: u64,
+    t1: ArcList,
+    t2: ArcList,
+    b1: ArcList,
+    b2: ArcList,
+}
+
+impl AdaptiveCache {
+    fn new(capacity: u64) -> Self {
+        Self {
+            capacity,
+            target: 0,
+            tick: 0,
+            t1: ArcList::default(),
+            t2: ArcList::default(),
+            b1: ArcList::default(),
+            b2: ArcList::default(),
+        }
+    }
+
+    /// Evicts from `t1` or `t2` into the matching ghost list until `size` more bytes fit.
+    fn replace(&mut self, size: u64, ghost_hit_in_b2: bool, access: &mut Access) {
+        while self.t1.bytes + self.t2.bytes + size > self.capacity {
+            let from_t1 = self.t1.bytes > 0
+                && (self.t1.bytes > self.target
+                    || (ghost_hit_in_b2 && self.t1.bytes == self.target)
+                    || self.t2.bytes == 0);
+            let (source, ghosts) = if from_t1 {
+                (&mut self.t1, &mut self.b1)
+            } else {
+                (&mut self.t2, &mut self.b2)
+            };
+            let Some((victim, victim_size)) = source.pop_oldest() else {
+                break;
+            };
+            access.evicted(victim_size);
+            self.tick += 1;
+            ghosts.push(victim, victim_size, self.tick);
+        }
+    }
+
+    /// Keeps the directory at most twice the cache, with `t1 + b1` at most the cache.
+    fn trim_ghosts(&mut self) {
+        while self.t1.bytes + self.b1.bytes > self.capacity && self.b1.pop_oldest().is_some() {}
+        while self.t1.bytes + self.t2.bytes + self.b1.bytes + self.b2.bytes > 2 * self.capacity
+            && self.b2.pop_oldest().is_some()
+        {}
+    }
+}
+
+impl CachePolicy for AdaptiveCache {
+    fn access(&mut self, digest: u128, size: u64) -> Access {
+        self.tick += 1;
+        let mut access = Access::default();
+        if let Some(cached) = self.t1.remove(digest).or_else(|| self.t2.remove(digest)) {
+            self.t2.push(digest, cached, self.tick);
+            access.hit = true;
+            return access;
+        }
+        if size > self.capacity {
+            self.b1.remove(digest);
+            self.b2.remove(digest);
+            return access;
+        }
+        let (b1_bytes, b2_bytes) = (self.b1.bytes, self.b2.bytes);
+        if self.b1.remove(digest).is_some() {
+            let delta = size.max(size.saturating_mul(b2_bytes) / b1_bytes.max(1));
+            self.target = (self.target + delta).min(self.capacity);
+            self.replace(size, false, &mut access);
+            self.t2.push(digest, size, self.tick);
+        } else if self.b2.remove(digest).is_some() {
+            let delta = size.max(size.saturating_mul(b1_bytes) / b2_bytes.max(1));
+            self.target = self.target.saturating_sub(delta);
+            self.replace(size, true, &mut access);
+            self.t2.push(digest, size, self.tick);
+        } else {
+            self.replace(size, false, &mut access);
+            self.t1.push(digest, size, self.tick);
+        }
+        self.trim_ghosts();
+        access
+    }
+}
+
+/// One simulated cache and its running totals.
+struct Simulation {
+    policy: PolicyKind,
+    size_bytes: u64,
+    cache: Box<dyn CachePolicy>,
+    totals: SimulationTotals,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct SimulationTotals {
+    requests: u64,
+    request_hits: u64,
+    slice_accesses: u64,
+    slice_hits: u64,
+    bytes_served: u64,
+    bytes_from_cache: u64,
+    wan_bytes: u64,
+    evictions: u64,
+    evicted_bytes: u64,
+}
+
+impl Simulation {
+    fn new(policy: PolicyKind, size_bytes: u64) -> Self {
+        let cache: Box<dyn CachePolicy> = match policy {
+            PolicyKind::Lru => Box::new(RankedCache::new(size_bytes, false)),
+            PolicyKind::Lfu => Box::new(RankedCache::new(size_bytes, true)),
+            PolicyKind::Arc => Box::new(AdaptiveCache::new(size_bytes)),
+        };
+        Self {
+            policy,
+            size_bytes,
+            cache,
+            totals: SimulationTotals::default(),
+        }
+    }
+
+    /// Replays one request. A request is a hit only when every object it touches was cached;
+    /// its served bytes are credited to the cache in proportion to the objects that hit.
+    fn replay(&mut self, objects: &[CacheObject], bytes_served: u64, counted: bool) {
+        let mut hits = 0u64;
+        let mut access_totals = SimulationTotals::default();
+        for object in objects {
+            let access = self.cache.access(object.digest, object.size);
+            if access.hit {
+                hits += 1;
+            } else {
+                access_totals.wan_bytes += object.size;
+            }
+            access_totals.evictions += access.evicted_objects;
+            access_totals.evicted_bytes += access.evicted_bytes;
+        }
+        if !counted || objects.is_empty() {
+            return;
+        }
+        let slices = objects.len() as u64;
+        let totals = &mut self.totals;
+        totals.requests += 1;
+        totals.request_hits += u64::from(hits == slices);
+        totals.slice_accesses += slices;
+        totals.slice_hits += hits;
+        totals.bytes_served += bytes_served;
+        totals.bytes_from_cache += (bytes_served as u128 * hits as u128 / slices as u128) as u64;
+        totals.wan_bytes += access_totals.wan_bytes;
+        totals.evictions += access_totals.evictions;
+        totals.evicted_bytes += access_totals.evicted_bytes;
+    }
+
+    fn result(&self) -> SimulationResult {
+        let totals = &self.totals;
+        SimulationResult {
+            policy: self.policy,
+            size_bytes: self.size_bytes,
+            requests: totals.requests,
+            request_hit_ratio: ratio(totals.request_hits, totals.requests),
+            slice_accesses: totals.slice_accesses,
+            slice_hit_ratio: ratio(totals.slice_hits, totals.slice_accesses),
+            bytes_served: totals.bytes_served,
+            byte_hit_ratio: ratio(totals.bytes_from_cache, totals.bytes_served),
+            wan_bytes_saved: totals.bytes_from_cache,
+            wan_bytes: totals.wan_bytes,
+            evictions: totals.evictions,
+            evicted_bytes: totals.evicted_bytes,
+            churn: ratio(totals.evicted_bytes, self.size_bytes),
+        }
+    }
+}
+
+/// One object a request touches: the cache-key digest nginx would file it under, and its size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CacheObject {
+    digest: u128,
+    size: u64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SimulationResult {
+    policy: PolicyKind,
+    size_bytes: u64,
+    requests: u64,
+    /// Requests whose every slice was already cached.
+    request_hit_ratio: f64,
+    slice_accesses: u64,
+    slice_hit_ratio: f64,
+    bytes_served: u64,
+    byte_hit_ratio: f64,
+    /// Bytes served from cache instead of fetched over the WAN.
+    wan_bytes_saved: u64,
+    /// Bytes fetched upstream to fill missed objects, whole slices at a time.
+    wan_bytes: u64,
+    evictions: u64,
+    evicted_bytes: u64,
+    /// Evicted bytes as a multiple of the cache size: how many times the cache turned over.
+    churn: f64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SimulationReport {
+    success: bool,
+    datasource: String,
+    key_scheme: String,
+    first_request_at: Option<String>,
+    last_request_at: Option<String>,
+    counted_from: Option<String>,
+    requests_replayed: u64,
+    /// Requests whose range or service has no cache-key mapping; they are left out entirely.
+    requests_unmapped: u64,
+    distinct_objects: usize,
+    /// Total size of every distinct object requested: the cache that would never evict.
+    working_set_bytes: u64,
+    /// Byte hit ratio nginx actually logged over the counted period, for calibration.
+    observed_byte_hit_ratio: f64,
+    simulations: Vec<SimulationResult>,
+    timestamp: String,
+}
+
+fn ratio(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 / whole as f64
+    }
+}
+
+/// Parses a cache size with an optional binary k, m, g or t suffix ("16t" is 16 TiB).
+fn parse_size(value: &str) -> Result<u64> {
+    let value = value.trim();
+    let (digits, shift) = match value.char_indices().last() {
+        Some((index, 'k' | 'K')) => (&value[..index], 10),
+        Some((index, 'm' | 'M')) => (&value[..index], 20),
+        Some((index, 'g' | 'G')) => (&value[..index], 30),
+        Some((index, 't' | 'T')) => (&value[..index], 40),
+        _ => (value, 0),
+    };
+    let number: u64 = digits
+        .parse()
+        .with_context(|| format!("invalid cache size '{}'", value))?;
+    number
+        .checked_mul(1u64 << shift)
+        .filter(|bytes| *bytes > 0)
+        .with_context(|| format!("cache size '{}' is out of range", value))
+}
+
+/// The objects nginx would store for one logged request, or None when its range or service has
+/// no cache-key mapping under `scheme`.
+fn request_objects(
+    scheme: CacheKeyScheme,
+    service: &str,
+    url: &str,
+    http_range: &str,
+    bytes_served: u64,
+) -> Option<Vec<CacheObject>> {
+    let unsliced_size = bytes_served.max(1);
+    match scheme {
+        CacheKeyScheme::Monolithic => {
+            let mapping =
+                cache_utils::physical_slices_for_request(Path::new(""), service, url, http_range)?;
+            let slice_size = |kind: &cache_utils::CacheSliceKind| match kind {
+                cache_utils::CacheSliceKind::Ranged { start, end } => end - start + 1,
+                _ => unsliced_size,
+            };
+            let objects = mapping
+                .slices
+                .iter()
+                .map(|slice| {
+                    let name = slice.exact_path.file_name()?.to_str()?;
+                    Some(CacheObject {
+                        digest: cache_utils::parse_cache_file_digest(name)?,
+                        size: slice_size(&slice.kind),
+                    })
+                })
+                .collect::<Option<Vec<_>>>()?;
+            // A range-less request maps to both the sliced and the `::noslice` key because the
+            // log cannot tell them apart; nginx stores only one, so simulate the first.
+            Some(match mapping.observed_range {
+                ObservedByteRange::NoRange => objects.into_iter().take(1).collect(),
+                ObservedByteRange::Inclusive { .. } => objects,
+            })
+        }
+        CacheKeyScheme::BareMetal => {
+            let base = cache_utils::bare_metal_object_key_base(service, url)?;
+            let range = cache_utils::parse_http_byte_range(http_range)?;
+            if !cache_utils::bare_metal_service_slices(service)
+                || range == ObservedByteRange::NoRange
+            {
+                return Some(vec![CacheObject {
+                    digest: cache_utils::calculate_md5_digest(&base),
+                    size: unsliced_size,
+                }]);
+            }
+            Some(
+                cache_utils::aligned_slice_ranges(&range)?
+                    .into_iter()
+                    .map(|(start, end)| CacheObject {
+                        digest: cache_utils::calculate_md5_digest(&format!(
+                            "{}bytes={}-{}",
+                            base, start, end
+                        )),
+                        size: end - start + 1,
+                    })
+                    .collect(),
+            )
+        }
+    }
+}
+
+/// Writes the progress file (when a path was supplied) and THEN emits the matching stdout event.
+fn write_progress(
+    progress_path: Option<&Path>,
+    reporter: &ProgressReporter,
+    status: &str,
+    stage_key: &str,
+    requests_replayed: usize,
+    total_requests: i64,
+) -> Result<()> {
+    let percent_complete = match status {
+        "completed" => 100.0,
+        _ if total_requests <= 0 => 0.0,
+        _ => (requests_replayed as f64 / total_requests as f64 * 100.0).min(99.0),
+    };
+    let context = json!({
+        "requestsReplayed": requests_replayed,
+        "totalRequests": total_requests,
+    });
+    if let Some(path) = progress_path {
+        let progress = ProgressData {
+            status: status.to_string(),
+            stage_key: stage_key.to_string(),
+            context: context.clone(),
+            percent_complete,
+            requests_replayed,
+            total_requests,
+            timestamp: progress_utils::current_timestamp(),
+        };
+        progress_utils::write_progress_json(path, &progress)?;
+    }
+
+    match status {
+        "starting" => reporter.emit_started(stage_key, context),
+        "completed" => reporter.emit_complete(stage_key, context),
+        "cancelled" => reporter.emit_cancelled(stage_key, context),
+        _ => reporter.emit_progress(percent_complete, stage_key, context),
+    }
+
+    Ok(())
+}
+
+async fn run(args: &Args, progress_path: Option<&Path>, reporter: &ProgressReporter) -> Result<()> {
+    if !cache_utils::is_valid_scheme_str(&args.key_scheme) {
+        bail!(
+            "unknown --key-scheme '{}'; expected monolithic or bare_metal",
+            args.key_scheme
+        );
+    }
+    let scheme = CacheKeyScheme::from_config_str(&args.key_scheme);
+    let sizes = args
+        .sizes
+        .iter()
+        .map(|size| parse_size(size))
+        .collect::<Result<Vec<_>>>()?;
+    if args.days.is_some_and(|days| days <= 0) || args.warmup_days < 0 {
+        bail!("--days must be positive and --warmup-days must not be negative");
+    }
+    let mut simulations: Vec<Simulation> = sizes
+        .iter()
+        .flat_map(|size| {
+            args.policies
+                .iter()
+                .map(|policy| Simulation::new(*policy, *size))
+        })
+        .collect();
+    write_progress(
+        progress_path,
+        reporter,
+        "starting",
+        "signalr.cacheSimulate.starting",
+        0,
+        0,
+    )?;
+
+    let pool = db::create_pool().await?;
+    let since: Option<DateTime<Utc>> = args.days.map(|days| Utc::now() - Duration::days(days));
+    const FILTER: &str = "\"Datasource\" = $1 AND \"Method\" = 'GET' \
+        AND \"StatusCode\" IN (200, 206) \
+        AND ($2::timestamptz IS NULL OR \"Timestamp\" >= $2)";
+    let total_requests: i64 = sqlx::query_scalar(&format!(
+        "SELECT COUNT(*) FROM \"LogEntries\" WHERE {FILTER}"
+    ))
+    .bind(&args.datasource)
+    .bind(since)
+    .fetch_one(&pool)
+    .await
+    .context("failed to count log entries to replay")?;
+    let replay_query = format!(
+        "SELECT \"Timestamp\", \"Service\", \"Url\", COALESCE(\"HttpRange\", '') AS http_range, \
+            \"BytesServed\", \"CacheStatus\" \
+         FROM \"LogEntries\" WHERE {FILTER} ORDER BY \"Timestamp\", \"Id\""
+    );
+    let mut rows = sqlx::query(&replay_query)
+        .bind(&args.datasource)
+        .bind(since)
+        .fetch(&pool);
+
+    let mut replayed = 0usize;
+    let mut unmapped = 0u64;
+    let mut first_request_at: Option<DateTime<Utc>> = None;
+    let mut last_request_at: Option<DateTime<Utc>> = None;
+    let mut counted_from: Option<DateTime<Utc>> = None;
+    let mut working_set: HashMap<u128, u64> = HashMap::new();
+    let (mut observed_hit_bytes, mut observed_bytes) = (0u64, 0u64);
+    while let Some(row) = rows
+        .try_next()
+        .await
+        .context("failed to read log entries")?
+    {
+        replayed += 1;
+        if replayed.is_multiple_of(PROGRESS_INTERVAL) {
+            if cancel::is_cancelled() {
+                eprintln!("[CacheSimulate] Cancelled after {} requests", replayed);
+                return write_progress(
+                    progress_path,
+                    reporter,
+                    "cancelled",
+                    "signalr.cacheSimulate.cancelled",
+                    replayed,
+                    total_requests,
+                );
+            }
+            write_progress(
+                progress_path,
+                reporter,
+                "replaying",
+                "signalr.cacheSimulate.replaying",
+                replayed,
+                total_requests,
+            )?;
+        }
+
+        let timestamp: DateTime<Utc> = row.try_get("Timestamp")?;
+        let service: String = row.try_get("Service")?;
+        let url: String = row.try_get("Url")?;
+        let http_range: String = row.try_get("http_range")?;
+        let bytes_served = u64::try_from(row.try_get::<i64, _>("BytesServed")?).unwrap_or(0);
+        if service_utils::should_skip_url(&url) {
+            continue;
+        }
+        let Some(objects) = request_objects(scheme, &service, &url, &http_range, bytes_served)
+        else {
+            unmapped += 1;
+            continue;
+        };
+
+        let start = *first_request_at.get_or_insert(timestamp);
+        last_request_at = Some(timestamp);
+        let counted = timestamp >= start + Duration::days(args.warmup_days);
+        if counted {
+            counted_from.get_or_insert(timestamp);
+            observed_bytes += bytes_served;
+            if row.try_get::<String, _>("CacheStatus")? == "HIT" {
+                observed_hit_bytes += bytes_served;
+            }
+        }
+        for object in &objects {
+            let size = working_set.entry(object.digest).or_insert(object.size);
+            *size = (*size).max(object.size);
+        }
+        for simulation in &mut simulations {
+            simulation.replay(&objects, bytes_served, counted);
+        }
+    }
+
+    let format_time = |time: Option<DateTime<Utc>>| time.map(|time| time.to_rfc3339());
+    let report = SimulationReport {
+        success: true,
+        datasource: args.datasource.clone(),
+        key_scheme: scheme.as_config_str().to_string(),
+        first_request_at: format_time(first_request_at),
+        last_request_at: format_time(last_request_at),
+        counted_from: format_time(counted_from),
+        requests_replayed: replayed as u64,
+        requests_unmapped: unmapped,
+        distinct_objects: working_set.len(),
+        working_set_bytes: working_set.values().sum(),
+        observed_byte_hit_ratio: ratio(observed_hit_bytes, observed_bytes),
+        simulations: simulations.iter().map(Simulation::result).collect(),
+        timestamp: progress_utils::current_timestamp(),
+    };
+    let payload =
+        serde_json::to_string_pretty(&report).context("Failed to serialize simulation results")?;
+    fs::write(&args.output_json, payload)
+        .with_context(|| format!("Failed to write output JSON to {}", args.output_json))?;
+
+    for result in &report.simulations {
+        eprintln!(
+            "[CacheSimulate] {:?} at {} bytes: {:.1}% byte hit ratio, {} WAN bytes saved, {} evicted",
+            result.policy,
+            result.size_bytes,
+            result.byte_hit_ratio * 100.0,
+            result.wan_bytes_saved,
+            result.evicted_bytes
+        );
+    }
+    write_progress(
+        progress_path,
+        reporter,
+        "completed",
+        "signalr.cacheSimulate.complete",
+        replayed,
+        total_requests,
+    )
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    cancel::install();
+    let args = Args::parse();
+    let reporter = ProgressReporter::new(args.progress);
+    let progress_path = (args.progress_json != "none").then(|| PathBuf::from(&args.progress_json));
+    let result = run(&args, progress_path.as_deref(), &reporter).await;
+    progress_events::finish_or_exit(&reporter, "signalr.cacheSimulate.error.fatal", result);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hits(cache: &mut dyn CachePolicy, accesses: &[(u128, u64)]) -> Vec<bool> {
+        accesses
+            .iter()
+            .map(|(digest, size)| cache.access(*digest, *size).hit)
+            .collect()
+    }
+
+    #[test]
+    fn lru_evicts_the_least_recently_used_bytes_and_never_stores_oversized_objects() {
+        let mut lru = RankedCache::new(300, false);
+        assert_eq!(
+            hits(&mut lru, &[(1, 100), (2, 100), (3, 100), (1, 100)]),
+            vec![false, false, false, true]
+        );
+        // 2 is now the oldest, so making room for 4 evicts it and keeps 1.
+        let access = lru.access(4, 100);
+        assert_eq!(access.evicted_objects, 1);
+        assert_eq!(access.evicted_bytes, 100);
+        assert_eq!(hits(&mut lru, &[(1, 100), (2, 100)]), vec![true, false]);
+
+        assert_eq!(lru.access(9, 301), Access::default());
+        assert!(!lru.access(9, 301).hit);
+    }
+
+    #[test]
+    fn lfu_keeps_the_frequently_used_object_that_lru_would_drop() {
+        let pattern = [(1, 100), (1, 100), (1, 100), (2, 100), (3, 100), (1, 100)];
+        assert_eq!(
+            hits(&mut RankedCache::new(200, true), &pattern),
+            vec![false, true, true, false, false, true]
+        );
+        assert_eq!(
+            hits(&mut RankedCache::new(200, false), &pattern),
+            vec![false, true, true, false, false, false]
+        );
+    }
+
+    #[test]
+    fn arc_resists_a_one_pass_scan_that_flushes_lru() {
+        // A hot pair read twice, then a long scan of objects read once, then the hot pair again.
+        let mut pattern: Vec<(u128, u64)> = vec![(1, 100), (2, 100), (1, 100), (2, 100)];
+        pattern.extend((10..20).map(|digest| (digest, 100)));
+        pattern.extend([(1, 100), (2, 100)]);
+
+        let arc = hits(&mut AdaptiveCache::new(400), &pattern);
+        assert_eq!(&arc[arc.len() - 2..], &[true, true]);
+        let lru = hits(&mut RankedCache::new(400, false), &pattern);
+        assert_eq!(&lru[lru.len() - 2..], &[false, false]);
+    }
+
+    #[test]
+    fn requests_map_to_the_slices_detection_probes() {
+        let objects = request_objects(
+            CacheKeyScheme::Monolithic,
+            "Steam",
+            "/depot/1/chunk/abc",
+            "bytes=0-2097151",
+            2_097_152,
+        )
+        .unwrap();
+        assert_eq!(
+            objects,
+            vec![
+                CacheObject {
+                    digest: cache_utils::calculate_md5_digest(
+                        "steam/depot/1/chunk/abcbytes=0-1048575"
+                    ),
+                    size: 1_048_576,
+                },
+                CacheObject {
+                    digest: cache_utils::calculate_md5_digest(
+                        "steam/depot/1/chunk/abcbytes=1048576-2097151"
+                    ),
+                    size: 1_048_576,
+                },
+            ]
+        );
+
+        let unranged = request_objects(CacheKeyScheme::Monolithic, "steam", "/a", "", 500).unwrap();
+        assert_eq!(
+            unranged,
+            vec![CacheObject {
+                digest: cache_utils::calculate_md5_digest("steam/a"),
+                size: 500,
+            }]
+        );
+
+        let bare_metal =
+            request_objects(CacheKeyScheme::BareMetal, "steam", "/a", "bytes=0-9", 10).unwrap();
+        assert_eq!(
+            bare_metal[0].digest,
+            cache_utils::calculate_md5_digest("lancache-steam/a")
+        );
+        assert!(request_objects(CacheKeyScheme::BareMetal, "unknown", "/a", "", 10).is_none());
+        assert!(
+            request_objects(CacheKeyScheme::Monolithic, "steam", "/a", "bytes=5-", 10).is_none()
+        );
+    }
+
+    #[test]
+    fn sizes_accept_binary_suffixes() {
+        assert_eq!(parse_size("16t").unwrap(), 16 << 40);
+        assert_eq!(parse_size("512G").unwrap(), 512 << 30);
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("lots").is_err());
+    }
+}
@@ -2,14 +2,16 @@ use anyhow::{Context, Result};
 use jwalk::WalkDir;
 use rayon::prelude::*;
 use serde::Serialize;
+use std::collections::BTreeMap;
 use std::env;
 use std::fs;
 use std::path::Path;
 use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
 use lancache_processor::cancel;
+use lancache_processor::cache_structural_scanner;
 use lancache_processor::cache_utils;
 use lancache_processor::progress_events;
 use lancache_processor::progress_utils;
@@ -61,9 +63,122 @@ struct CacheSizeResult {
     estimated_deletion_times: EstimatedDeletionTimes,
     #[serde(rename = "formattedSize")]
     formatted_size: String,
+    /// Only present with `--by-service`; the default stat-only scan never opens a file.
+    #[serde(rename = "serviceBreakdown", skip_serializing_if = "Option::is_none")]
+    service_breakdown: Option<ServiceBreakdown>,
     timestamp: String,
 }
 
+#[derive(Serialize, Default, Clone, Copy)]
+struct SizeCount {
+    bytes: u64,
+    files: u64,
+}
+
+impl SizeCount {
+    fn add(&mut self, bytes: u64) {
+        self.bytes += bytes;
+        self.files += 1;
+    }
+
+    fn merge(&mut self, other: SizeCount) {
+        self.bytes += other.bytes;
+        self.files += other.files;
+    }
+}
+
+#[derive(Serialize)]
+struct ServiceSize {
+    service: String,
+    scheme: &'static str,
+    bytes: u64,
+    files: u64,
+}
+
+#[derive(Serialize)]
+struct SchemeSize {
+    scheme: &'static str,
+    bytes: u64,
+    files: u64,
+}
+
+#[derive(Serialize)]
+struct ServiceBreakdown {
+    /// Largest first. One row per (service, key scheme) pair.
+    services: Vec<ServiceSize>,
+    schemes: Vec<SchemeSize>,
+    /// Files with no readable version 5 header or KEY line (partial writes, foreign files).
+    unparseable: SizeCount,
+    /// Files whose KEY was read but matches neither key recipe.
+    unknown: SizeCount,
+}
+
+/// Per-directory tally for `--by-service`, merged into the shared one when the directory is done
+/// so rayon workers do not contend on a lock per file.
+#[derive(Default)]
+struct ServiceTally {
+    by_service: BTreeMap<(String, &'static str), SizeCount>,
+    unparseable: SizeCount,
+    unknown: SizeCount,
+}
+
+impl ServiceTally {
+    fn record(&mut self, path: &Path, bytes: u64) {
+        let key = match cache_structural_scanner::read_cache_header(path) {
+            Ok(Some(header)) => header.cache_key,
+            _ => None,
+        };
+        let Some(key) = key else {
+            self.unparseable.add(bytes);
+            return;
+        };
+        match cache_utils::split_cache_key(&key) {
+            Some(parts) => self
+                .by_service
+                .entry((parts.service, parts.scheme.as_config_str()))
+                .or_default()
+                .add(bytes),
+            None => self.unknown.add(bytes),
+        }
+    }
+
+    fn merge(&mut self, other: ServiceTally) {
+        for (key, count) in other.by_service {
+            self.by_service.entry(key).or_default().merge(count);
+        }
+        self.unparseable.merge(other.unparseable);
+        self.unknown.merge(other.unknown);
+    }
+
+    fn into_breakdown(self) -> ServiceBreakdown {
+        let mut schemes = BTreeMap::<&'static str, SizeCount>::new();
+        let mut services = Vec::with_capacity(self.by_service.len());
+        for ((service, scheme), count) in self.by_service {
+            schemes.entry(scheme).or_default().merge(count);
+            services.push(ServiceSize {
+                service,
+                scheme,
+                bytes: count.bytes,
+                files: count.files,
+            });
+        }
+        services.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.service.cmp(&b.service)));
+        ServiceBreakdown {
+            services,
+            schemes: schemes
+                .into_iter()
+                .map(|(scheme, count)| SchemeSize {
+                    scheme,
+                    bytes: count.bytes,
+                    files: count.files,
+                })
+                .collect(),
+            unparseable: self.unparseable,
+            unknown: self.unknown,
+        }
+    }
+}
+
 #[derive(Serialize)]
 struct EstimatedDeletionTimes {
     #[serde(rename = "preserveSeconds")]
@@ -896,7 +1011,12 @@ fn estimate_deletion_times_dynamic(
     }
 }
 
-fn calculate_cache_size(cache_path: &str, progress_path: &Path, reporter: &Arc<ProgressReporter>) -> Result<CacheSizeResult> {
+fn calculate_cache_size(
+    cache_path: &str,
+    progress_path: &Path,
+    reporter: &Arc<ProgressReporter>,
+    by_service: bool,
+) -> Result<CacheSizeResult> {
     let start_time = Instant::now();
     eprintln!("Starting cache size calculation...");
     eprintln!("Cache path: {}", cache_path);
@@ -1015,6 +1135,7 @@ fn calculate_cache_size(cache_path: &str, progress_path: &Path, reporter: &Arc<P
             scan_duration_ms: start_time.elapsed().as_millis() as u64,
             estimated_deletion_times: estimates,
             formatted_size: "0 bytes".to_string(),
+            service_breakdown: by_service.then(|| ServiceTally::default().into_breakdown()),
             timestamp: progress_utils::current_timestamp(),
         };
         write_result(progress_path, reporter, &result)?;
@@ -1023,7 +1144,8 @@ fn calculate_cache_size(cache_path: &str, progress_path: &Path, reporter: &Arc<P
 
     // For network filesystems (NFS/SMB), use system commands which are more reliable
     // The NFS client caches directory information and 'du' leverages this efficiently
-    if is_network_fs {
+    // The breakdown has to open every file, which du/find cannot do, so it always walks.
+    if is_network_fs && !by_service {
         eprintln!("Network filesystem detected - using optimized du/find approach");
         return calculate_cache_size_network(cache_dir, progress_path, total_hex_dirs, start_time, reporter);
     }
@@ -1038,6 +1160,10 @@ fn calculate_cache_size(cache_path: &str, progress_path: &Path, reporter: &Arc<P
     let dirs_scanned = Arc::new(AtomicUsize::new(0));
     let failed_entries = Arc::new(AtomicU64::new(0));
     let failed_metadata = Arc::new(AtomicU64::new(0));
+    let service_tally = by_service.then(|| Mutex::new(ServiceTally::default()));
+    if by_service {
+        eprintln!("Reading KEY headers for the per-service breakdown");
+    }
 
     // Write initial progress
     let progress = ProgressData {
@@ -1113,6 +1239,8 @@ fn calculate_cache_size(cache_path: &str, progress_path: &Path, reporter: &Arc<P
             return;
         }
 
+        let mut tally = service_tally.as_ref().map(|_| ServiceTally::default());
+
         // Use jwalk with serial mode for more reliable NFS handling
         for result in WalkDir::new(hex_dir)
             .skip_hidden(false)
@@ -1148,11 +1276,20 @@ fn calculate_cache_size(cache_path: &str, progress_path: &Path, reporter: &Arc<P
                     }
                 };
                 total_bytes.fetch_add(size, Ordering::Relaxed);
+                if let Some(tally) = tally.as_mut() {
+                    tally.record(&entry.path(), size);
+                }
             } else if file_type.is_dir() {
                 total_dirs.fetch_add(1, Ordering::Relaxed);
             }
         }
 
+        if let (Some(shared), Some(tally)) = (service_tally.as_ref(), tally) {
+            shared
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .merge(tally);
+        }
         dirs_scanned.fetch_add(1, Ordering::Relaxed);
     });
 
@@ -1235,6 +1372,12 @@ fn calculate_cache_size(cache_path: &str, progress_path: &Path, reporter: &Arc<P
         scan_duration_ms: scan_duration.as_millis() as u64,
         estimated_deletion_times: estimates,
         formatted_size: format_bytes(final_bytes),
+        service_breakdown: service_tally.map(|tally| {
+            tally
+                .into_inner()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .into_breakdown()
+        }),
         timestamp: progress_utils::current_timestamp(),
     };
 
@@ -1441,6 +1584,7 @@ fn calculate_cache_size_network(
         scan_duration_ms: scan_duration.as_millis() as u64,
         estimated_deletion_times: estimates,
         formatted_size: format_bytes(total_bytes),
+        service_breakdown: None,
         timestamp: progress_utils::current_timestamp(),
     };
 
@@ -1464,15 +1608,24 @@ fn main() -> anyhow::Result<()> {
     } else {
         false
     };
+    // Opt-in because it opens every cache file; the default stays a stat-only walk.
+    let by_service = if let Some(pos) = args.iter().position(|a| a == "--by-service") {
+        args.remove(pos);
+        true
+    } else {
+        false
+    };
 
     if args.len() != 3 {
         eprintln!("Usage:");
-        eprintln!("  cache_size <cache_path> <output_json_path> [--progress]");
+        eprintln!("  cache_size <cache_path> <output_json_path> [--progress] [--by-service]");
         eprintln!("\nExample:");
         eprintln!("  cache_size /var/cache/lancache ./data/cache_size.json");
         eprintln!("\nOutput:");
         eprintln!("  Writes JSON with total size, file count, and estimated deletion times");
-        anyhow::bail!("invalid arguments: expected <cache_path> <output_json_path> [--progress]");
+        eprintln!("  --by-service also reads each file's KEY header and adds bytes and file");
+        eprintln!("  counts per service and per key scheme (slower: every file is opened)");
+        anyhow::bail!("invalid arguments: expected <cache_path> <output_json_path> [--progress] [--by-service]");
     }
 
     let cache_path = &args[1];
@@ -1507,7 +1660,7 @@ fn main() -> anyhow::Result<()> {
     // uniform stdout `failed` event with a real `errorDetail`, replacing the old bespoke
     // error-to-file side channel - see progress_events::finish_or_exit.
     progress_events::run_or_exit(&reporter, "signalr.cacheSizeScan.error.fatal", || {
-        let result = calculate_cache_size(cache_path, output_path, &reporter)?;
+        let result = calculate_cache_size(cache_path, output_path, &reporter, by_service)?;
         let json = serde_json::to_string_pretty(&result).context("serialize cache-size result")?;
         println!("{}", json);
         Ok(())
@@ -6,14 +6,21 @@ use serde::Serialize;
 use serde_json::json;
 use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use lancache_processor::cache_utils;
 use lancache_processor::cancel;
 use lancache_processor::db;
+use lancache_processor::db_backup;
 use lancache_processor::log_purge;
 use lancache_processor::progress_events;
+use lancache_processor::protected_games::{self, ProtectedGame, Protection};
+use lancache_processor::quarantine::{DeferredCleanup, QuarantineArgs};
 use lancache_processor::removal_core;
+use lancache_processor::removal_plan::{
+    self, PlanRun, PlanStageKeys, PlanTarget, PlannedFiles, PlannedLogPurge, PlannedRows,
+    RemovalPlan,
+};
 use progress_events::ProgressReporter;
 use removal_core::{ProgressCadence, RemovalStageKeys};
 
@@ -24,7 +31,9 @@ use removal_core::{ProgressCadence, RemovalStageKeys};
 /// by the target game, so removing one game never strips another game's cache slices or
 /// HIT/MISS log lines (depots are many-to-one with AppId). The shared delete/cleanup/
 /// purge/permission tail lives in `removal_core`; this bin owns the depot head, the
-/// `--skip-file-probe` fast path, and the depot-bearing report.
+/// `--skip-file-probe` fast path, and the depot-bearing report. `--plan`/`--apply` split the
+/// removal into a reviewed plan and its execution (see `removal_plan`); `--quarantine-dir` moves
+/// the files into a `quarantine` batch whose purge runs the log and row cleanup.
 #[derive(clap::Parser, Debug)]
 #[command(name = "cache_steam_remove")]
 #[command(about = "Removes all cache files for a specific Steam game by scanning logs")]
@@ -55,11 +64,24 @@ struct Args {
     #[arg(long = "key-scheme", default_value = "monolithic")]
     key_scheme: String,
 
+    #[command(flatten)]
+    backup: db_backup::BackupArgs,
+
+    #[command(flatten)]
+    plan: removal_plan::PlanArgs,
+
+    #[command(flatten)]
+    quarantine: QuarantineArgs,
+
     /// Emit JSON progress events to stdout
     #[arg(short, long)]
     progress: bool,
 }
 
+/// Downloads of the Steam game (alias `d`, $1 = GameAppId), as planned; the removal backs up
+/// and deletes the unprotected ones.
+const STEAM_DOWNLOADS_FILTER: &str = r#"d."GameAppId" = $1"#;
+
 /// Steam removal stage keys (`signalr.gameRemove.*`). Only the per-file cache progress
 /// key is consumed by `removal_core`; the remaining lifecycle keys are emitted directly
 /// in `main` below with the same literal strings as before.
@@ -67,6 +89,16 @@ const STEAM_STAGE_KEYS: RemovalStageKeys = RemovalStageKeys {
     cache_file_progress: "signalr.gameRemove.cache.file.progress",
 };
 
+const STEAM_PLAN_KEYS: PlanStageKeys = PlanStageKeys {
+    planned: "signalr.gameRemove.planned",
+    cache_removing: "signalr.gameRemove.cache.removing",
+    cache_file_progress: "signalr.gameRemove.cache.file.progress",
+    dirs_cleaning: "signalr.gameRemove.dirs.cleaning",
+    logs_removing: "signalr.gameRemove.logs.removing",
+    db_deleting: "signalr.gameRemove.db.deleting",
+    complete: "signalr.gameRemove.complete",
+};
+
 #[derive(Debug, Serialize)]
 struct RemovalReport {
     game_app_id: u32,
@@ -76,6 +108,11 @@ struct RemovalReport {
     empty_dirs_removed: usize,
     log_entries_removed: u64,
     depot_ids: Vec<u32>,
+    /// Cache files left in place because a protected game also uses them.
+    protected_files_skipped: Vec<String>,
+    /// Batch holding the files when they were quarantined instead of deleted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quarantine_batch: Option<String>,
 }
 
 /// Preserve URL provenance when a bare-metal candidate's recipe-computed key
@@ -304,13 +341,98 @@ fn compute_safe_depot_ids(valid: &HashSet<u32>, shared: &HashSet<u32>) -> HashSe
     valid.difference(shared).copied().collect()
 }
 
+/// The (service, bytes) view of the url map that cache-file deletion works from; the depot set
+/// only drives the log purge.
+fn url_data_for_delete(
+    url_data: &HashMap<String, (String, i64, HashSet<u32>)>,
+) -> HashMap<String, (String, i64)> {
+    url_data
+        .iter()
+        .map(|(url, (service, bytes, _depots))| (url.clone(), (service.clone(), *bytes)))
+        .collect()
+}
+
+/// URLs and depots the access.log purge removes. Files a protected game shares stayed on disk,
+/// so their lines stay too: neither their URLs nor their depots may drive the purge.
+fn log_purge_scope(
+    url_data: &HashMap<String, (String, i64, HashSet<u32>)>,
+    protected_urls: &HashSet<String>,
+    safe_depot_ids: HashSet<u32>,
+) -> (HashSet<String>, HashSet<u32>) {
+    let urls_to_remove: HashSet<String> = url_data
+        .keys()
+        .filter(|url| !protected_urls.contains(*url))
+        .cloned()
+        .collect();
+    let mut safe_depot_ids = safe_depot_ids;
+    for url in protected_urls {
+        if let Some((_service, _bytes, depot_ids)) = url_data.get(url) {
+            for depot_id in depot_ids {
+                safe_depot_ids.remove(depot_id);
+            }
+        }
+    }
+    (urls_to_remove, safe_depot_ids)
+}
+
+/// `--plan`: what this removal would delete, scoped exactly like the removal below.
+async fn plan_removal(
+    pool: &PgPool,
+    args: &Args,
+    protection: &Protection,
+    url_data: &HashMap<String, (String, i64, HashSet<u32>)>,
+    safe_depot_ids: HashSet<u32>,
+) -> Result<RemovalPlan> {
+    let scheme = cache_utils::active_key_scheme();
+    let log_dir = Path::new(&args.log_dir);
+    let cache_dir = Path::new(&args.cache_dir);
+    let target = PlanTarget::Steam {
+        game_app_id: args.game_app_id,
+    };
+    // The removal stops at an empty URL set without touching logs or rows, and so does the plan.
+    if url_data.is_empty() {
+        return Ok(RemovalPlan::new(
+            target,
+            log_dir,
+            cache_dir,
+            scheme,
+            PlannedFiles::default(),
+            PlannedLogPurge::default(),
+            PlannedRows::default(),
+        ));
+    }
+
+    let files = if args.skip_file_probe {
+        PlannedFiles::default()
+    } else {
+        removal_plan::plan_cache_files(
+            cache_dir,
+            &url_data_for_delete(url_data),
+            scheme,
+            &protection.files(cache_dir, scheme),
+        )
+    };
+    let (urls, depot_ids) = log_purge_scope(url_data, &files.protected_urls, safe_depot_ids);
+    let log_purge = removal_plan::plan_log_purge(log_dir, None, urls, depot_ids)?;
+    let database = removal_plan::plan_download_rows(pool, STEAM_DOWNLOADS_FILTER, |q| {
+        q.bind(args.game_app_id as i64)
+    })
+    .await?;
+    Ok(RemovalPlan::new(
+        target, log_dir, cache_dir, scheme, files, log_purge, database,
+    ))
+}
+
 async fn delete_game_from_database(pool: &PgPool, game_app_id: u32) -> Result<u64> {
     eprintln!("Deleting database records for game AppID {}...", game_app_id);
 
+    // `main` refused a protected target; the predicate covers a protection added mid-run.
+    let downloads = protected_games::unprotected_downloads(STEAM_DOWNLOADS_FILTER);
+
     // First, delete LogEntries that reference these downloads (foreign key constraint)
-    let log_result = sqlx::query(
-        "DELETE FROM \"LogEntries\" WHERE \"DownloadId\" IN (SELECT \"Id\" FROM \"Downloads\" WHERE \"GameAppId\" = $1)"
-    )
+    let log_result = sqlx::query(&format!(
+        "DELETE FROM \"LogEntries\" WHERE \"DownloadId\" IN (SELECT d.\"Id\" FROM \"Downloads\" d WHERE {downloads})"
+    ))
     .bind(game_app_id as i64)
     .execute(pool)
     .await?;
@@ -318,7 +440,9 @@ async fn delete_game_from_database(pool: &PgPool, game_app_id: u32) -> Result<u6
     eprintln!("  Deleted {} log entry records", log_entries_deleted);
 
     // Now safe to delete the downloads
-    let downloads_result = sqlx::query("DELETE FROM \"Downloads\" WHERE \"GameAppId\" = $1")
+    let downloads_result = sqlx::query(&format!(
+        "DELETE FROM \"Downloads\" d WHERE {downloads}"
+    ))
         .bind(game_app_id as i64)
         .execute(pool)
         .await?;
@@ -363,6 +487,14 @@ async fn main() -> Result<()> {
     }
 
     let pool = db::create_pool().await?;
+    let protection = Protection::load(&pool).await?;
+    // A plan deletes nothing; a protected game's plan lists its slices as protected, which is
+    // what `cache_export` reads.
+    if !args.plan.plan {
+        protection.ensure_not_protected(&ProtectedGame::Steam {
+            app_id: game_app_id as i64,
+        })?;
+    }
 
     // Get game name from database
     let game_name = get_game_name_from_db(&pool, game_app_id).await?;
@@ -370,6 +502,26 @@ async fn main() -> Result<()> {
 
     removal_core::write_progress(&progress_path, &reporter, "starting", "signalr.gameRemove.starting", json!({ "gameName": game_name, "gameAppId": game_app_id }), 0.0, 0, 0)?;
 
+    let plan_run = PlanRun {
+        output_json: &output_json,
+        progress_path: &progress_path,
+        reporter: &reporter,
+        keys: &STEAM_PLAN_KEYS,
+        identity: json!({ "gameName": game_name, "gameAppId": game_app_id }),
+    };
+    if let Some(plan_path) = &args.plan.apply {
+        let plan = RemovalPlan::load(
+            plan_path,
+            &PlanTarget::Steam { game_app_id },
+            &log_dir,
+            &cache_dir,
+            cache_utils::active_key_scheme(),
+        )?;
+        let protected = protection.files(&cache_dir, cache_utils::active_key_scheme());
+        let quarantine = args.quarantine.open(&cache_dir, "steam_remove")?;
+        return removal_plan::apply(&pool, &plan, &protected, args.backup.policy().as_ref(), quarantine, &plan_run).await;
+    }
+
     // Get valid depot IDs for this game from database
     removal_core::write_progress(&progress_path, &reporter, "querying_database", "signalr.gameRemove.db.querying", json!({}), 5.0, 0, 0)?;
     let valid_depot_ids = get_game_depot_ids(&pool, game_app_id).await?;
@@ -405,6 +557,11 @@ async fn main() -> Result<()> {
     // Query database directly for URLs - much faster than scanning logs!
     let url_data = get_game_urls_from_db(&pool, game_app_id).await?;
 
+    if args.plan.plan {
+        let plan = plan_removal(&pool, &args, &protection, &url_data, safe_depot_ids).await?;
+        return removal_plan::write_plan(&plan, &plan_run);
+    }
+
     if url_data.is_empty() {
         eprintln!("No URLs found in logs for game AppID {}", game_app_id);
 
@@ -416,6 +573,8 @@ async fn main() -> Result<()> {
             empty_dirs_removed: 0,
             log_entries_removed: 0,
             depot_ids: vec![],
+            protected_files_skipped: vec![],
+            quarantine_batch: None,
         };
 
         let json = serde_json::to_string_pretty(&report)?;
@@ -428,6 +587,20 @@ async fn main() -> Result<()> {
 
     eprintln!("Found {} unique URLs for '{}'", url_data.len(), game_name);
 
+    // Pre-flight backup of the rows `delete_game_from_database` removes; taken before anything
+    // is touched so a failed backup aborts the whole removal. A quarantined removal deletes no
+    // rows; the purge of its batch takes the backup instead.
+    let backup = db_backup::backup_download_cascade(
+        &pool,
+        args.backup.policy().filter(|_| args.quarantine.quarantine_dir.is_none()).as_ref(),
+        "steam_remove",
+        json!({ "gameAppId": game_app_id, "gameName": game_name }),
+        &protected_games::unprotected_downloads(STEAM_DOWNLOADS_FILTER),
+        |q| q.bind(game_app_id as i64),
+    )
+    .await?;
+    let quarantine = args.quarantine.open(&cache_dir, "steam_remove")?;
+
     // File-probe + directory cleanup phase.
     // Skipped when `--skip-file-probe` is set (caller already knows every row for
     // this game is IsEvicted, so the lancache has nothing to delete on disk).
@@ -438,11 +611,13 @@ async fn main() -> Result<()> {
         empty_dirs_removed,
         cache_permission_errors,
         verification_skips,
+        protected_urls,
+        protected_files_skipped,
     ) = if args.skip_file_probe {
         eprintln!("\nSkipping cache file probe for {} URLs (fully evicted game)", url_data.len());
         removal_core::write_progress(&progress_path, &reporter, "removing_cache", "signalr.gameRemove.cache.skippedEvicted", json!({}), 10.0, 0, 0)?;
         removal_core::write_progress(&progress_path, &reporter, "cleaning_directories", "signalr.gameRemove.dirs.skippedEvicted", json!({}), 70.0, 0, 0)?;
-        (0usize, 0u64, 0usize, 0usize, 0usize)
+        (0usize, 0u64, 0usize, 0usize, 0usize, HashSet::new(), Vec::new())
     } else {
         let count = url_data.len();
         removal_core::write_progress(&progress_path, &reporter, "removing_cache", "signalr.gameRemove.cache.removing", json!({ "count": count }), 10.0, 0, 0)?;
@@ -452,19 +627,18 @@ async fn main() -> Result<()> {
         // (service, bytes) view of its (service, bytes, depots) map and uses the Steam
         // cadence (percent-advance OR every 8th probe). The depot set rides along in the
         // report below; cache-file deletion is purely (service, url) based.
-        let url_data_for_delete: HashMap<String, (String, i64)> = url_data
-            .iter()
-            .map(|(url, (service, bytes, _depots))| (url.clone(), (service.clone(), *bytes)))
-            .collect();
         let outcome = removal_core::remove_cache_files(
             &cache_dir,
-            &url_data_for_delete,
+            &url_data_for_delete(&url_data),
             &progress_path,
             &reporter,
             &STEAM_STAGE_KEYS,
             ProgressCadence::OnPercentAdvanceOrEveryEighth,
             cache_utils::active_key_scheme(),
+            &protection.files(&cache_dir, cache_utils::active_key_scheme()),
+            quarantine.as_ref(),
         )?;
+        let protected_files_skipped = outcome.protected_file_names();
 
         // If cancellation arrived during cache removal, finish directory cleanup of dirs
         // already collected, then exit 0.  Log/DB work is skipped — C# re-runs detection.
@@ -483,6 +657,8 @@ async fn main() -> Result<()> {
             empty_dirs,
             outcome.permission_errors,
             outcome.verification_skips,
+            outcome.protected_urls,
+            protected_files_skipped,
         )
     };
 
@@ -495,17 +671,54 @@ async fn main() -> Result<()> {
             empty_dirs_removed,
             log_entries_removed: 0,
             depot_ids: vec![],
+            protected_files_skipped,
+            quarantine_batch: None,
         };
         let json = serde_json::to_string_pretty(&report)?;
         fs::write(&output_json, json)?;
         return Err(error);
     }
 
+    let (urls_to_remove, safe_depot_ids) = log_purge_scope(&url_data, &protected_urls, safe_depot_ids);
+
+    // Quarantined: the log purge and row delete are recorded in the batch for its purge.
+    if let Some(quarantine) = quarantine {
+        let database = removal_plan::plan_download_rows(&pool, STEAM_DOWNLOADS_FILTER, |q| {
+            q.bind(game_app_id as i64)
+        })
+        .await?;
+        let report = RemovalReport {
+            game_app_id,
+            game_name: game_name.clone(),
+            cache_files_deleted: deleted_files,
+            total_bytes_freed: bytes_freed,
+            empty_dirs_removed,
+            log_entries_removed: 0,
+            depot_ids: url_data
+                .values()
+                .flat_map(|(_service, _bytes, depot_ids)| depot_ids.iter().copied())
+                .collect::<HashSet<u32>>()
+                .into_iter()
+                .collect(),
+            protected_files_skipped,
+            quarantine_batch: Some(quarantine.id().to_string()),
+        };
+        let finished = removal_core::finish_quarantine(
+            quarantine,
+            cache_permission_errors,
+            DeferredCleanup::removal(&log_dir, None, urls_to_remove, safe_depot_ids, database),
+        );
+        fs::write(&output_json, serde_json::to_string_pretty(&report)?)?;
+        finished?;
+
+        let complete_context = json!({ "files": report.cache_files_deleted, "gb": report.total_bytes_freed as f64 / 1_073_741_824.0, "logEntries": 0, "gameName": game_name, "gameAppId": game_app_id, "protectedSkipped": report.protected_files_skipped.len(), "quarantineBatch": report.quarantine_batch });
+        return removal_core::write_progress(&progress_path, &reporter, "completed", "signalr.gameRemove.complete", complete_context, 100.0, 0, 0);
+    }
+
     // Remove log entries for this game. Per-file progress fills the 80-90% band
     // so fast --skip-file-probe runs still surface visible stages.
     removal_core::write_progress(&progress_path, &reporter, "removing_logs", "signalr.gameRemove.logs.removing", json!({}), 80.0, 0, 0)?;
     eprintln!("\nRemoving log entries...");
-    let urls_to_remove: HashSet<String> = url_data.keys().cloned().collect();
     let log_file_progress = |processed: usize, total: usize| {
         let percent = 80.0 + (processed as f64 / total.max(1) as f64) * 10.0;
         let _ = removal_core::write_progress(
@@ -551,6 +764,8 @@ async fn main() -> Result<()> {
             empty_dirs_removed,
             log_entries_removed,
             depot_ids: vec![],
+            protected_files_skipped,
+            quarantine_batch: None,
         };
         let json = serde_json::to_string_pretty(&report)?;
         fs::write(&output_json, json)?;
@@ -577,12 +792,16 @@ async fn main() -> Result<()> {
         empty_dirs_removed,
         log_entries_removed,
         depot_ids: all_depot_ids.into_iter().collect(),
+        protected_files_skipped,
+        quarantine_batch: None,
     };
 
     let json = serde_json::to_string_pretty(&report)?;
     fs::write(&output_json, json)?;
 
-    removal_core::write_progress(&progress_path, &reporter, "completed", "signalr.gameRemove.complete", json!({ "files": report.cache_files_deleted, "gb": report.total_bytes_freed as f64 / 1_073_741_824.0, "logEntries": report.log_entries_removed, "gameName": game_name, "gameAppId": game_app_id }), 100.0, 0, 0)?;
+    let mut complete_context = json!({ "files": report.cache_files_deleted, "gb": report.total_bytes_freed as f64 / 1_073_741_824.0, "logEntries": report.log_entries_removed, "gameName": game_name, "gameAppId": game_app_id, "protectedSkipped": report.protected_files_skipped.len() });
+    db_backup::annotate_context(&mut complete_context, backup.as_ref());
+    removal_core::write_progress(&progress_path, &reporter, "completed", "signalr.gameRemove.complete", complete_context, 100.0, 0, 0)?;
 
     eprintln!("\n=== Removal Summary ===");
     eprintln!("Cache files deleted: {}", report.cache_files_deleted);
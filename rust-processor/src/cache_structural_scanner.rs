@@ -59,6 +59,9 @@ struct NginxCacheHeaderV5Layout {
 struct Layout {
     size: usize,
     version: usize,
+    valid_sec: usize,
+    last_modified: usize,
+    date: usize,
     crc32: usize,
     header_start: usize,
     body_start: usize,
@@ -74,6 +77,9 @@ impl Layout {
             Some(Self {
                 size: std::mem::size_of::<NginxCacheHeaderV5Layout>(),
                 version: std::mem::offset_of!(NginxCacheHeaderV5Layout, version),
+                valid_sec: std::mem::offset_of!(NginxCacheHeaderV5Layout, valid_sec),
+                last_modified: std::mem::offset_of!(NginxCacheHeaderV5Layout, last_modified),
+                date: std::mem::offset_of!(NginxCacheHeaderV5Layout, date),
                 crc32: std::mem::offset_of!(NginxCacheHeaderV5Layout, crc32),
                 header_start: std::mem::offset_of!(NginxCacheHeaderV5Layout, header_start),
                 body_start: std::mem::offset_of!(NginxCacheHeaderV5Layout, body_start),
@@ -109,6 +115,9 @@ impl Layout {
         Self {
             size: 336,
             version: 0,
+            valid_sec: 8,
+            last_modified: 32,
+            date: 40,
             crc32: 48,
             header_start: 54,
             body_start: 56,
@@ -691,6 +700,15 @@ fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
     ))
 }
 
+fn read_time(bytes: &[u8], offset: usize) -> Option<i64> {
+    let raw: [u8; std::mem::size_of::<libc::time_t>()] = bytes
+        .get(offset..offset.checked_add(std::mem::size_of::<libc::time_t>())?)?
+        .try_into()
+        .ok()?;
+    #[allow(clippy::unnecessary_cast)] // time_t is 32-bit on some targets.
+    Some(libc::time_t::from_ne_bytes(raw) as i64)
+}
+
 fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
     Some(u16::from_ne_bytes(
         bytes.get(offset..offset.checked_add(2)?)?.try_into().ok()?,
@@ -1062,8 +1080,135 @@ fn proven_with_key(
     })
 }
 
+/// What the cache inventory keeps from a file's nginx header.
+///
+/// Unlike `parse_prefix` this judges nothing: a file whose framing is wrong still occupies disk,
+/// so it is recorded with whatever could be read and the structural scan decides whether it is
+/// corrupt. Timestamps are Unix seconds; nginx writes 0 for ones it never set, which read as None.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheFileHeader {
+    pub cache_key: Option<String>,
+    pub valid_until: Option<i64>,
+    pub last_modified: Option<i64>,
+    pub date: Option<i64>,
+}
+
+/// Reads the header of one cache file without following symlinks. None when this platform has no
+/// known header layout or the file is not a version 5 cache file.
+pub fn read_cache_header(path: &Path) -> Result<Option<CacheFileHeader>> {
+    let Some(layout) = Layout::native() else {
+        return Ok(None);
+    };
+    let mut file = open_nofollow(path)?;
+    let file_len = file
+        .metadata()
+        .with_context(|| format!("failed to stat cache file {}", path.display()))?
+        .len();
+    let (prefix, _) = read_prefix(&mut file, file_len, layout)
+        .with_context(|| format!("failed to read cache header for {}", path.display()))?;
+    Ok(header_from_prefix(&prefix, layout))
+}
+
+fn header_from_prefix(prefix: &[u8], layout: Layout) -> Option<CacheFileHeader> {
+    let fixed_end = layout.size.checked_add(KEY_MARKER.len())?;
+    if prefix.len() < fixed_end || read_usize(prefix, layout.version)? != 5 {
+        return None;
+    }
+    let time = |offset| read_time(prefix, offset).filter(|value| *value > 0);
+    let cache_key = read_u16(prefix, layout.header_start)
+        .map(usize::from)
+        .filter(|header_start| {
+            prefix.get(layout.size..fixed_end) == Some(KEY_MARKER)
+                && *header_start > fixed_end
+                && prefix.get(header_start - 1) == Some(&b'\n')
+        })
+        .and_then(|header_start| {
+            String::from_utf8(prefix[fixed_end..header_start - 1].to_vec()).ok()
+        })
+        .filter(|key| !key.is_empty());
+    Some(CacheFileHeader {
+        cache_key,
+        valid_until: time(layout.valid_sec),
+        last_modified: time(layout.last_modified),
+        date: time(layout.date),
+    })
+}
+
+/// A cache file's nginx header re-serialized under another key. The file's body, from
+/// `body_offset` on, follows it unchanged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RekeyedHeader {
+    pub header: Vec<u8>,
+    pub body_offset: u64,
+}
+
+/// Re-serializes the header of an open cache file for `new_key`. The `KEY:` line is replaced and
+/// the key CRC32, `header_start` and `body_start` are updated, which is everything nginx compares
+/// on read; validity times, ETag and the stored HTTP headers are kept. None when this platform has
+/// no known header layout, or the file is not an intact version 5 file keyed by `old_key`, is
+/// stored under a Vary variant (named by a digest other than its key's), or would need an offset
+/// nginx's 16-bit fields cannot hold.
+pub fn rekey_cache_header(
+    file: &mut File,
+    old_key: &str,
+    new_key: &str,
+) -> Result<Option<RekeyedHeader>> {
+    let Some(layout) = Layout::native() else {
+        return Ok(None);
+    };
+    let file_len = file.metadata().context("failed to stat cache file")?.len();
+    file.seek(SeekFrom::Start(0))?;
+    let (prefix, _) = read_prefix(file, file_len, layout)?;
+    Ok(rekey_prefix(&prefix, layout, old_key, new_key))
+}
+
+fn rekey_prefix(
+    prefix: &[u8],
+    layout: Layout,
+    old_key: &str,
+    new_key: &str,
+) -> Option<RekeyedHeader> {
+    let fixed_end = layout.size.checked_add(KEY_MARKER.len())?;
+    if prefix.len() < fixed_end
+        || read_usize(prefix, layout.version)? != 5
+        || prefix.get(layout.size..fixed_end) != Some(KEY_MARKER)
+        || prefix.get(layout.vary_len).copied()? != 0
+    {
+        return None;
+    }
+    let header_start = usize::from(read_u16(prefix, layout.header_start)?);
+    let body_start = usize::from(read_u16(prefix, layout.body_start)?);
+    if header_start != fixed_end + old_key.len() + 1
+        || body_start < header_start
+        || prefix.len() < body_start
+        || prefix.get(fixed_end..header_start - 1) != Some(old_key.as_bytes())
+        || prefix[header_start - 1] != b'\n'
+        || read_u32(prefix, layout.crc32)? != crc32fast::hash(old_key.as_bytes())
+    {
+        return None;
+    }
+    let new_header_start = u16::try_from(fixed_end + new_key.len() + 1).ok()?;
+    let new_body_start =
+        u16::try_from(usize::from(new_header_start) + (body_start - header_start)).ok()?;
+
+    let mut header = Vec::with_capacity(usize::from(new_body_start));
+    header.extend_from_slice(&prefix[..fixed_end]);
+    header[layout.crc32..layout.crc32 + 4]
+        .copy_from_slice(&crc32fast::hash(new_key.as_bytes()).to_ne_bytes());
+    header[layout.header_start..layout.header_start + 2]
+        .copy_from_slice(&new_header_start.to_ne_bytes());
+    header[layout.body_start..layout.body_start + 2].copy_from_slice(&new_body_start.to_ne_bytes());
+    header.extend_from_slice(new_key.as_bytes());
+    header.push(b'\n');
+    header.extend_from_slice(&prefix[header_start..body_start]);
+    Some(RekeyedHeader {
+        header,
+        body_offset: body_start as u64,
+    })
+}
+
 #[cfg(unix)]
-fn fingerprint(metadata: &Metadata) -> FileFingerprint {
+pub(crate) fn fingerprint(metadata: &Metadata) -> FileFingerprint {
     use std::os::unix::fs::MetadataExt;
     FileFingerprint {
         dev: metadata.dev(),
@@ -1138,7 +1283,7 @@ pub fn path_fingerprint_matches(path: &Path, expected: &FileFingerprint) -> Resu
 }
 
 #[cfg(not(unix))]
-fn fingerprint(metadata: &Metadata) -> FileFingerprint {
+pub(crate) fn fingerprint(metadata: &Metadata) -> FileFingerprint {
     let modified = metadata
         .modified()
         .ok()
@@ -3303,6 +3448,55 @@ mod tests {
         assert!(require_complete_traversal(1, true).is_ok());
     }
 
+    #[test]
+    fn inventory_header_keeps_key_and_timestamps_even_when_framing_is_wrong() {
+        let layout = Layout::linux_x86_64();
+        // Content-Length disagrees with the payload: parse_prefix proves corruption, the
+        // inventory still records what the file is.
+        let (mut bytes, _) = fixture(
+            b"steam/depot/1/chunk/abbytes=0-1048575",
+            b"HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\n",
+            b"1234",
+        );
+        bytes[layout.date..layout.date + 8].copy_from_slice(&1_700_000_000i64.to_ne_bytes());
+        let header = header_from_prefix(&bytes, layout).unwrap();
+        assert_eq!(
+            header.cache_key.as_deref(),
+            Some("steam/depot/1/chunk/abbytes=0-1048575")
+        );
+        assert_eq!(header.date, Some(1_700_000_000));
+        assert_eq!(header.last_modified, None);
+
+        put_usize(&mut bytes, layout.version, 4);
+        assert_eq!(header_from_prefix(&bytes, layout), None);
+    }
+
+    #[test]
+    fn rekeyed_header_reads_back_as_the_new_key_with_the_same_response() {
+        let layout = Layout::linux_x86_64();
+        let old_key = "blizzard/tpr/wow/data/ab/cdbytes=0-1048575";
+        let new_key = "lancache-blizzard/tpr/wow/data/ab/cdbytes=0-1048575";
+        let headers = b"HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 0-3/4\r\n\r\n";
+        let (bytes, _) = fixture(old_key.as_bytes(), headers, b"1234");
+
+        let rekeyed = rekey_prefix(&bytes, layout, old_key, new_key).unwrap();
+        let mut rewritten = rekeyed.header.clone();
+        rewritten.extend_from_slice(&bytes[rekeyed.body_offset as usize..]);
+        let (expected, new_digest) = fixture(new_key.as_bytes(), headers, b"1234");
+        assert_eq!(rewritten, expected);
+        assert!(matches!(
+            parse_prefix(&rewritten, rewritten.len() as u64, new_digest, layout),
+            ParseOutcome::Consistent
+        ));
+
+        assert_eq!(rekey_prefix(&bytes, layout, new_key, old_key), None);
+        let mut varied = bytes.clone();
+        varied[layout.vary_len] = 6;
+        assert_eq!(rekey_prefix(&varied, layout, old_key, new_key), None);
+        let long_key = format!("lancache-blizzard/{}", "a".repeat(usize::from(u16::MAX)));
+        assert_eq!(rekey_prefix(&bytes, layout, old_key, &long_key), None);
+    }
+
     #[cfg(unix)]
     #[test]
     fn durable_modes_build_reuse_refresh_change_and_prune() {
@@ -652,6 +652,14 @@ impl CacheKeyScheme {
             CacheKeyScheme::Monolithic
         }
     }
+
+    /// The datasource-config wire value for this scheme; the inverse of [`Self::from_config_str`].
+    pub fn as_config_str(self) -> &'static str {
+        match self {
+            CacheKeyScheme::Monolithic => "monolithic",
+            CacheKeyScheme::BareMetal => "bare_metal",
+        }
+    }
 }
 
 /// Whether a cache-key scheme string is accepted by the datasource-config wire
@@ -1041,6 +1049,120 @@ pub fn cache_file_key_matches(path: &Path, expected_key: &str) -> Option<bool> {
     read_cache_file_key(path).map(|actual| actual == expected_key)
 }
 
+/// A cache key split back into the parts its recipe joined. `url` is the keyed `$uri` (or, for
+/// the bare-metal `$request_uri` vhosts, the request URI) exactly as it appears in the key, so it
+/// matches a stored log URL only where `nginx_cache_uri` leaves that URL unchanged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheKeyParts {
+    pub scheme: CacheKeyScheme,
+    /// Manager service name: the monolithic `$cacheidentifier` lowercased, or the service a
+    /// bare-metal `lancache-*` vhost maps back to (`windows-update` is `wsus`).
+    pub service: String,
+    pub url: String,
+    /// Inclusive byte range of a sliced key's `bytes=start-end` suffix.
+    pub slice: Option<(u64, u64)>,
+    /// The key came from the `@noslice` location (`::noslice` suffix).
+    pub noslice: bool,
+}
+
+/// Splits a literal `KEY:` header value into service, URL and slice range. Works for both key
+/// schemes, told apart by the `lancache-` vhost prefix. None when the key has no `/` after a
+/// non-empty identifier, which no stock recipe produces.
+#[allow(dead_code)]
+pub fn split_cache_key(key: &str) -> Option<CacheKeyParts> {
+    let split = key.find('/')?;
+    let (identifier, rest) = key.split_at(split);
+    if identifier.is_empty() {
+        return None;
+    }
+    let (scheme, service) = match identifier.strip_prefix("lancache-") {
+        Some("windows-update") => (CacheKeyScheme::BareMetal, "wsus".to_string()),
+        Some(vhost) if !vhost.is_empty() => (CacheKeyScheme::BareMetal, vhost.to_lowercase()),
+        _ => (CacheKeyScheme::Monolithic, identifier.to_lowercase()),
+    };
+    if let Some(url) = rest.strip_suffix("::noslice") {
+        return Some(CacheKeyParts {
+            scheme,
+            service,
+            url: url.to_string(),
+            slice: None,
+            noslice: true,
+        });
+    }
+    let slice = rest.rfind("bytes=").and_then(|at| {
+        let (start, end) = rest[at + "bytes=".len()..].split_once('-')?;
+        let range = (start.parse::<u64>().ok()?, end.parse::<u64>().ok()?);
+        (range.0 <= range.1).then_some((at, range))
+    });
+    Some(CacheKeyParts {
+        scheme,
+        service,
+        url: rest[..slice.map_or(rest.len(), |(at, _)| at)].to_string(),
+        slice: slice.map(|(_, range)| range),
+        noslice: false,
+    })
+}
+
+/// Why a cache key has no equivalent under the other scheme.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RekeyBlocker {
+    /// [`split_cache_key`] could not read the key.
+    UnparsableKey,
+    /// The service has no stock bare-metal vhost.
+    NoBareMetalVhost,
+    /// A 1 MiB slice of a service the bare-metal vhost does not slice, or an `@noslice` object,
+    /// which bare-metal has no location for. The stored response would be the wrong shape.
+    SliceShapeDiffers,
+    /// Bare-metal keys epicgames and riot by `$request_uri`, query string included; a monolithic
+    /// key only holds `$uri`, so the bare-metal key cannot be rebuilt.
+    RequestUriUnknown,
+}
+
+/// The key `key` is stored under by the other scheme's nginx, for the same response. A key already
+/// in `target`'s form is returned as it is.
+///
+/// Only the identifier and URL change: a slice stays the same slice and an unsliced object stays
+/// unsliced, so the cached response can be reused as it is. `$request_uri` keys lose their query
+/// string and escaping on the way to monolithic (`nginx_cache_uri`), the inverse of which is
+/// unknowable. Bare-metal `windows-update` objects become `wsus` keys: the vhost also serves Xbox,
+/// whose container identifier is `xboxlive`, and the key does not say which it was.
+#[allow(dead_code)]
+pub fn equivalent_cache_key(key: &str, target: CacheKeyScheme) -> Result<String, RekeyBlocker> {
+    let parts = split_cache_key(key).ok_or(RekeyBlocker::UnparsableKey)?;
+    if parts.scheme == target {
+        return Ok(key.to_string());
+    }
+    if parts.noslice {
+        return Err(RekeyBlocker::SliceShapeDiffers);
+    }
+    let slice = parts
+        .slice
+        .map(|(start, end)| format!("bytes={}-{}", start, end))
+        .unwrap_or_default();
+    match target {
+        CacheKeyScheme::BareMetal => {
+            let prefix = bare_metal_prefix(&parts.service).ok_or(RekeyBlocker::NoBareMetalVhost)?;
+            if bare_metal_service_uses_request_uri(&parts.service) {
+                return Err(RekeyBlocker::RequestUriUnknown);
+            }
+            if parts.slice.is_some() && !bare_metal_service_slices(&parts.service) {
+                return Err(RekeyBlocker::SliceShapeDiffers);
+            }
+            Ok(format!("{}{}{}", prefix, parts.url, slice))
+        }
+        CacheKeyScheme::Monolithic => {
+            bare_metal_prefix(&parts.service).ok_or(RekeyBlocker::NoBareMetalVhost)?;
+            let url = if bare_metal_service_uses_request_uri(&parts.service) {
+                nginx_cache_uri(&parts.url)
+            } else {
+                std::borrow::Cow::Borrowed(parts.url.as_str())
+            };
+            Ok(format!("{}{}{}", parts.service, url, slice))
+        }
+    }
+}
+
 #[allow(dead_code)]
 pub fn sorted_sample_urls<I, S>(urls: I, limit: usize) -> Vec<String>
 where
@@ -2385,4 +2507,71 @@ mod bare_metal_key_tests {
             Some(expected.as_str())
         );
     }
+
+    #[test]
+    fn keys_of_both_schemes_split_back_into_service_url_and_slice() {
+        let sliced = split_cache_key("steam/depot/440/chunk/abcbytes=0-1048575").unwrap();
+        assert_eq!(sliced.scheme, CacheKeyScheme::Monolithic);
+        assert_eq!(sliced.service, "steam");
+        assert_eq!(sliced.url, "/depot/440/chunk/abc");
+        assert_eq!(sliced.slice, Some((0, 1_048_575)));
+
+        let noslice = split_cache_key("epicgames/Builds/a.chunk::noslice").unwrap();
+        assert!(noslice.noslice);
+        assert_eq!(noslice.url, "/Builds/a.chunk");
+
+        let bare = split_cache_key("lancache-windows-update/f/x.cab").unwrap();
+        assert_eq!(bare.scheme, CacheKeyScheme::BareMetal);
+        assert_eq!(bare.service, "wsus");
+        assert_eq!(bare.slice, None);
+        assert_eq!(
+            split_cache_key("lancache-epicgames/a?b=1").unwrap().url,
+            "/a?b=1"
+        );
+
+        assert_eq!(split_cache_key("/no-identifier"), None);
+        assert_eq!(split_cache_key("no-slash"), None);
+    }
+
+    #[test]
+    fn equivalent_keys_keep_the_slice_and_swap_only_the_identifier() {
+        use CacheKeyScheme::{BareMetal, Monolithic};
+        let sliced = "wsus/d/x.cabbytes=1048576-2097151";
+        let bare = "lancache-windows-update/d/x.cabbytes=1048576-2097151";
+        assert_eq!(equivalent_cache_key(sliced, BareMetal).as_deref(), Ok(bare));
+        assert_eq!(
+            equivalent_cache_key(bare, Monolithic).as_deref(),
+            Ok(sliced)
+        );
+        assert_eq!(equivalent_cache_key(bare, BareMetal).as_deref(), Ok(bare));
+        assert_eq!(
+            equivalent_cache_key("lancache-steam/depot/1/chunk/ab", Monolithic).as_deref(),
+            Ok("steam/depot/1/chunk/ab")
+        );
+        assert_eq!(
+            equivalent_cache_key("lancache-epicgames/a%2Fb.chunk?t=1", Monolithic).as_deref(),
+            Ok("epicgames/a/b.chunk")
+        );
+
+        assert_eq!(
+            equivalent_cache_key("steam/depot/1/chunk/abbytes=0-1048575", BareMetal),
+            Err(RekeyBlocker::SliceShapeDiffers)
+        );
+        assert_eq!(
+            equivalent_cache_key("blizzard/a::noslice", BareMetal),
+            Err(RekeyBlocker::SliceShapeDiffers)
+        );
+        assert_eq!(
+            equivalent_cache_key("riot/a.bundle", BareMetal),
+            Err(RekeyBlocker::RequestUriUnknown)
+        );
+        assert_eq!(
+            equivalent_cache_key("origin/a.zipbytes=0-1048575", BareMetal),
+            Err(RekeyBlocker::NoBareMetalVhost)
+        );
+        assert_eq!(
+            equivalent_cache_key("no-slash", BareMetal),
+            Err(RekeyBlocker::UnparsableKey)
+        );
+    }
 }
@@ -0,0 +1,219 @@
+//! Detected games, their access history and the value score derived from both.
+//!
+//! On-disk sizes come from `CachedGameDetections`, the table the host fills from
+//! `cache_game_detect` reports. Each game is matched to its downloads the way the host matches
+//! them for eviction badges: Epic games by `EpicAppId`, named games (`GameAppId` 0) by service and
+//! name, everything else by `GameAppId`. From those downloads and their LogEntries the history
+//! takes the last HIT, the distinct clients, the HIT bytes served and the last download.
+//!
+//! The value score is what the cached copy has earned, discounted by how long it has sat idle:
+//!
+//! ```text
+//! (hit bytes per cached byte + ln(1 + distinct clients)) * 0.5 ^ (idle days / half-life days)
+//! ```
+//!
+//! A game served many times over, or to many machines, scores high until it goes quiet.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use sqlx::{PgPool, Row};
+use std::collections::HashMap;
+
+const SECONDS_PER_DAY: f64 = 86_400.0;
+
+/// How a detection row is matched to its downloads; mirrors the host's eviction-badge grouping.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum GameKey {
+    Epic(String),
+    Named { service: String, game_name: String },
+    Steam(i64),
+}
+
+/// Access history of one game's downloads.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GameActivity {
+    pub downloads: i64,
+    pub distinct_clients: i64,
+    pub hit_bytes: i64,
+    pub miss_bytes: i64,
+    pub last_hit_at: Option<DateTime<Utc>>,
+    pub last_download_at: Option<DateTime<Utc>>,
+}
+
+impl GameActivity {
+    /// The later of the last HIT and the last download; None when neither is on record.
+    pub fn last_access(&self) -> Option<DateTime<Utc>> {
+        self.last_hit_at.max(self.last_download_at)
+    }
+}
+
+/// One `CachedGameDetections` row that still has bytes on disk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DetectedGame {
+    /// Lowercased; detection rows without a service are Steam.
+    pub service: String,
+    pub game_app_id: i64,
+    pub epic_app_id: Option<String>,
+    pub game_name: String,
+    pub cached_bytes: i64,
+    pub cache_files: i64,
+}
+
+impl DetectedGame {
+    pub fn key(&self) -> GameKey {
+        match &self.epic_app_id {
+            Some(epic_app_id) => GameKey::Epic(epic_app_id.clone()),
+            None if self.game_app_id == 0 => GameKey::Named {
+                service: self.service.clone(),
+                game_name: self.game_name.clone(),
+            },
+            None => GameKey::Steam(self.game_app_id),
+        }
+    }
+}
+
+/// Loads the detected games that are not evicted and still hold bytes.
+pub async fn load_detected_games(pool: &PgPool) -> Result<Vec<DetectedGame>> {
+    let rows = sqlx::query(
+        "SELECT \"GameAppId\", \"GameName\", \"Service\", \"EpicAppId\", \
+            \"TotalSizeBytes\"::bigint AS cached_bytes, \"CacheFilesFound\"::bigint AS cache_files \
+         FROM \"CachedGameDetections\" \
+         WHERE NOT \"IsEvicted\" AND \"TotalSizeBytes\" > 0",
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to load cached game detections; has cache_game_detect run?")?;
+
+    let mut games = Vec::with_capacity(rows.len());
+    for row in rows {
+        let service: Option<String> = row.try_get("Service")?;
+        games.push(DetectedGame {
+            service: service.map_or_else(|| "steam".to_string(), |s| s.to_lowercase()),
+            game_app_id: row.try_get("GameAppId")?,
+            epic_app_id: row.try_get("EpicAppId")?,
+            game_name: row.try_get("GameName")?,
+            cached_bytes: row.try_get("cached_bytes")?,
+            cache_files: row.try_get("cache_files")?,
+        });
+    }
+    Ok(games)
+}
+
+/// Aggregates the download history of every game that has downloads on record.
+pub async fn load_activity(pool: &PgPool) -> Result<HashMap<GameKey, GameActivity>> {
+    let rows = sqlx::query(
+        "WITH keyed AS ( \
+            SELECT d.\"Id\", d.\"ClientIp\", d.\"CacheHitBytes\", d.\"CacheMissBytes\", \
+                d.\"StartTimeUtc\", \
+                CASE WHEN d.\"EpicAppId\" IS NOT NULL THEN 'epic' \
+                     WHEN d.\"GameAppId\" IS NOT NULL THEN 'steam' \
+                     ELSE 'named' END AS kind, \
+                d.\"EpicAppId\" AS epic_app_id, d.\"GameAppId\" AS game_app_id, \
+                LOWER(d.\"Service\") AS service, d.\"GameName\" AS game_name \
+            FROM \"Downloads\" d \
+            WHERE d.\"EpicAppId\" IS NOT NULL OR d.\"GameAppId\" IS NOT NULL \
+               OR d.\"GameName\" IS NOT NULL \
+         ) \
+         SELECT k.kind, \
+            k.epic_app_id, \
+            CASE WHEN k.kind = 'steam' THEN k.game_app_id END AS game_app_id, \
+            CASE WHEN k.kind = 'named' THEN k.service END AS service, \
+            CASE WHEN k.kind = 'named' THEN k.game_name END AS game_name, \
+            COUNT(*) AS downloads, COUNT(DISTINCT k.\"ClientIp\") AS distinct_clients, \
+            COALESCE(SUM(k.\"CacheHitBytes\"), 0)::bigint AS hit_bytes, \
+            COALESCE(SUM(k.\"CacheMissBytes\"), 0)::bigint AS miss_bytes, \
+            MAX(h.last_hit) AS last_hit_at, MAX(k.\"StartTimeUtc\") AS last_download_at \
+         FROM keyed k \
+         LEFT JOIN LATERAL ( \
+            SELECT MAX(l.\"Timestamp\") AS last_hit FROM \"LogEntries\" l \
+            WHERE l.\"DownloadId\" = k.\"Id\" AND l.\"CacheStatus\" = 'HIT' \
+         ) h ON TRUE \
+         GROUP BY 1, 2, 3, 4, 5",
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to aggregate download history per game")?;
+
+    let mut activity = HashMap::with_capacity(rows.len());
+    for row in rows {
+        let kind: String = row.try_get("kind")?;
+        let key = match kind.as_str() {
+            "epic" => GameKey::Epic(row.try_get("epic_app_id")?),
+            "steam" => GameKey::Steam(row.try_get("game_app_id")?),
+            _ => GameKey::Named {
+                service: row.try_get("service")?,
+                game_name: row.try_get("game_name")?,
+            },
+        };
+        activity.insert(
+            key,
+            GameActivity {
+                downloads: row.try_get("downloads")?,
+                distinct_clients: row.try_get("distinct_clients")?,
+                hit_bytes: row.try_get("hit_bytes")?,
+                miss_bytes: row.try_get("miss_bytes")?,
+                last_hit_at: row.try_get("last_hit_at")?,
+                last_download_at: row.try_get("last_download_at")?,
+            },
+        );
+    }
+    Ok(activity)
+}
+
+/// Scores one game; see the module docs. A game with no recorded access is idle since forever
+/// and scores zero.
+pub fn value_score(
+    hit_bytes_per_cached_byte: f64,
+    distinct_clients: i64,
+    idle_days: Option<f64>,
+    half_life_days: f64,
+) -> f64 {
+    let Some(idle_days) = idle_days else {
+        return 0.0;
+    };
+    let earned = hit_bytes_per_cached_byte + (1.0 + distinct_clients.max(0) as f64).ln();
+    earned * 0.5f64.powf(idle_days.max(0.0) / half_life_days)
+}
+
+pub fn days_between(earlier: DateTime<Utc>, later: DateTime<Utc>) -> f64 {
+    (later - earlier).num_seconds() as f64 / SECONDS_PER_DAY
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idle_games_lose_half_their_value_per_half_life() {
+        let fresh = value_score(3.0, 0, Some(0.0), 30.0);
+        assert_eq!(fresh, 3.0);
+        assert!((value_score(3.0, 0, Some(30.0), 30.0) - 1.5).abs() < 1e-9);
+        // Widely wanted content is worth more than the same reuse by one machine.
+        assert!(value_score(1.0, 8, Some(0.0), 30.0) > value_score(1.0, 1, Some(0.0), 30.0));
+        assert_eq!(value_score(10.0, 5, None, 30.0), 0.0);
+    }
+
+    #[test]
+    fn detection_rows_key_like_the_host_groups_downloads() {
+        let mut game = DetectedGame {
+            service: "blizzard".to_string(),
+            game_app_id: 0,
+            epic_app_id: None,
+            game_name: "Diablo IV".to_string(),
+            cached_bytes: 1,
+            cache_files: 1,
+        };
+        assert_eq!(
+            game.key(),
+            GameKey::Named {
+                service: "blizzard".to_string(),
+                game_name: "Diablo IV".to_string()
+            }
+        );
+        game.epic_app_id = Some("fn".to_string());
+        assert_eq!(game.key(), GameKey::Epic("fn".to_string()));
+        game.epic_app_id = None;
+        game.game_app_id = 730;
+        assert_eq!(game.key(), GameKey::Steam(730));
+    }
+}
@@ -0,0 +1,55 @@
+//! Flag stripping for the binaries that parse `env::args()` by hand (`database_reset`,
+//! `speed_tracker`). Optional flags are taken out of the argument list first, so the positional
+//! arguments those binaries check afterwards keep their places wherever the flags were given.
+
+use anyhow::Result;
+
+/// Removes `flag <value>` from `args` and returns the value. A flag given without a value is
+/// an error rather than silently swallowing the next positional argument.
+pub fn take_flag_value(args: &mut Vec<String>, flag: &str) -> Result<Option<String>> {
+    let Some(pos) = args.iter().position(|a| a == flag) else {
+        return Ok(None);
+    };
+    if pos + 1 >= args.len() || args[pos + 1].starts_with("--") {
+        anyhow::bail!("{} requires a value", flag);
+    }
+    let value = args.remove(pos + 1);
+    args.remove(pos);
+    Ok(Some(value))
+}
+
+/// Removes `flag` from `args`, returning whether it was given.
+pub fn take_switch(args: &mut Vec<String>, flag: &str) -> bool {
+    match args.iter().position(|a| a == flag) {
+        Some(pos) => {
+            args.remove(pos);
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn flags_are_stripped_without_moving_positionals() {
+        let mut list = args(&["bin", "--to", "x", "data", "--dry-run", "progress.json"]);
+        assert_eq!(take_flag_value(&mut list, "--to").unwrap().as_deref(), Some("x"));
+        assert!(take_switch(&mut list, "--dry-run"));
+        assert!(!take_switch(&mut list, "--dry-run"));
+        assert_eq!(take_flag_value(&mut list, "--from").unwrap(), None);
+        assert_eq!(list, args(&["bin", "data", "progress.json"]));
+    }
+
+    #[test]
+    fn a_flag_without_a_value_is_an_error() {
+        assert!(take_flag_value(&mut args(&["bin", "--to"]), "--to").is_err());
+        assert!(take_flag_value(&mut args(&["bin", "--to", "--from", "x"]), "--to").is_err());
+    }
+}
@@ -0,0 +1,205 @@
+//! Access-log and database cleanup for exact corruption evidence.
+//!
+//! `cache_corruption remove` runs it right after the corrupt slices are gone; when those slices
+//! were quarantined instead, `cache_quarantine purge` runs it with the observations the batch
+//! manifest kept. Either way only the exact observations are touched: a log line or `LogEntries`
+//! row must match every recorded field, never just the URL.
+
+use anyhow::{bail, Context, Result};
+use sqlx::{PgPool, Row};
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::log_purge::{self, ExactLogMatcher, ExactLogObservation};
+
+pub const EXACT_DB_DELETE_SQL: &str = r#"
+DELETE FROM "LogEntries"
+WHERE LOWER("Service") = LOWER($1)
+  AND "Datasource" = $2
+  AND "ClientIp" = $3
+  AND "Timestamp" = $4
+  AND "Method" = $5
+  AND "Url" = $6
+  AND "StatusCode" = $7
+  AND "CacheStatus" = $8
+  AND "HttpRange" = $9
+  AND "BytesServed" = $10
+RETURNING "DownloadId"
+"#;
+
+/// The URL as the log processor stores it: repeated slashes collapsed.
+pub fn stored_log_url(raw_url: &str) -> String {
+    if !raw_url.as_bytes().windows(2).any(|pair| pair == b"//") {
+        return raw_url.to_string();
+    }
+    let mut normalized = String::with_capacity(raw_url.len());
+    let mut previous_slash = false;
+    for character in raw_url.chars() {
+        if character == '/' {
+            if !previous_slash {
+                normalized.push(character);
+            }
+            previous_slash = true;
+        } else {
+            normalized.push(character);
+            previous_slash = false;
+        }
+    }
+    normalized
+}
+
+fn database_http_range(observation: &ExactLogObservation) -> &str {
+    observation.raw_range.as_deref().unwrap_or("")
+}
+
+fn affected_download_delete_sql(placeholders: &str) -> String {
+    format!(
+        "DELETE FROM \"Downloads\" WHERE \"Id\" IN ({placeholders}) AND NOT \"IsActive\" AND NOT EXISTS (SELECT 1 FROM \"LogEntries\" WHERE \"LogEntries\".\"DownloadId\" = \"Downloads\".\"Id\")"
+    )
+}
+
+fn affected_survivor_recompute_sql(placeholders: &str) -> String {
+    format!(
+        "UPDATE \"Downloads\" SET \"CacheHitBytes\" = COALESCE((SELECT SUM(\"BytesServed\") FROM \"LogEntries\" WHERE \"LogEntries\".\"DownloadId\" = \"Downloads\".\"Id\" AND \"CacheStatus\" = 'HIT'), 0), \"CacheMissBytes\" = COALESCE((SELECT SUM(\"BytesServed\") FROM \"LogEntries\" WHERE \"LogEntries\".\"DownloadId\" = \"Downloads\".\"Id\" AND \"CacheStatus\" IN ('MISS', 'UNKNOWN')), 0) WHERE \"Id\" IN ({placeholders})"
+    )
+}
+
+/// Removes the access-log lines of `observations`. A partial rewrite is an error, so the caller
+/// keeps the database rows for a retry.
+pub fn purge_log_observations(
+    log_dir: &Path,
+    observations: &[ExactLogObservation],
+    on_file_processed: Option<&(dyn Fn(usize, usize) + Send + Sync)>,
+) -> Result<u64> {
+    let matcher = ExactLogMatcher::new(observations.iter().cloned());
+    let prefilter = matcher.prefilter()?;
+    let outcome = log_purge::rewrite_matching_log_entries_strict(
+        log_dir,
+        "exact corruption evidence",
+        &prefilter,
+        |entry| matcher.matches(entry),
+        on_file_processed,
+    )?;
+    if outcome.permission_errors > 0 || outcome.other_errors > 0 {
+        bail!(
+            "access-log cleanup was partial ({} permission errors, {} other errors); database evidence was retained",
+            outcome.permission_errors,
+            outcome.other_errors
+        );
+    }
+    Ok(outcome.lines_removed)
+}
+
+/// Deletes the `LogEntries` rows of `observations` in one transaction, then the inactive
+/// downloads left without rows, and recomputes the byte totals of the survivors. Returns
+/// `(downloads_deleted, log_entries_deleted)`.
+pub async fn delete_database_observations(
+    pool: &PgPool,
+    datasource: &str,
+    observations: &[ExactLogObservation],
+) -> Result<(usize, usize)> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("failed to begin exact corruption database cleanup")?;
+    let mut affected_download_ids = HashSet::new();
+    let mut log_entries_deleted = 0usize;
+
+    for observation in observations {
+        let rows = sqlx::query(EXACT_DB_DELETE_SQL)
+            .bind(&observation.service)
+            .bind(datasource)
+            .bind(&observation.client_ip)
+            .bind(observation.timestamp)
+            .bind(&observation.method)
+            .bind(stored_log_url(&observation.raw_url))
+            .bind(observation.http_status)
+            .bind(&observation.cache_status)
+            // New no-range rows persist ""; historical nullable rows never match this predicate.
+            .bind(database_http_range(observation))
+            .bind(observation.bytes_served)
+            .fetch_all(&mut *transaction)
+            .await
+            .context("failed to delete exact corruption LogEntries")?;
+        log_entries_deleted += rows.len();
+        for row in rows {
+            if let Some(download_id) = row.try_get::<Option<i64>, _>("DownloadId")? {
+                affected_download_ids.insert(download_id);
+            }
+        }
+    }
+
+    let mut downloads_deleted = 0usize;
+    let download_ids: Vec<i64> = affected_download_ids.into_iter().collect();
+    for chunk in download_ids.chunks(400) {
+        let placeholders = (1..=chunk.len())
+            .map(|index| format!("${index}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let delete_downloads = affected_download_delete_sql(&placeholders);
+        let mut query = sqlx::query(&delete_downloads);
+        for id in chunk {
+            query = query.bind(*id);
+        }
+        downloads_deleted += query
+            .execute(&mut *transaction)
+            .await
+            .context("failed to delete affected inactive zero-row Downloads")?
+            .rows_affected() as usize;
+
+        let update_downloads = affected_survivor_recompute_sql(&placeholders);
+        let mut query = sqlx::query(&update_downloads);
+        for id in chunk {
+            query = query.bind(*id);
+        }
+        query
+            .execute(&mut *transaction)
+            .await
+            .context("failed to recompute affected survivor byte totals")?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("failed to commit exact corruption database cleanup")?;
+    Ok((downloads_deleted, log_entries_deleted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn database_cleanup_sql_preserves_exact_scope_and_survivor_recompute() {
+        assert!(EXACT_DB_DELETE_SQL.contains("\"Datasource\" = $2"));
+        assert!(EXACT_DB_DELETE_SQL.contains("\"ClientIp\" = $3"));
+        assert!(EXACT_DB_DELETE_SQL.contains("\"Timestamp\" = $4"));
+        assert!(EXACT_DB_DELETE_SQL.contains("\"BytesServed\" = $10"));
+        assert_eq!(
+            database_http_range(&ExactLogObservation {
+                service: "steam".into(),
+                raw_url: "/a".into(),
+                timestamp: Utc::now(),
+                client_ip: "client".into(),
+                method: "GET".into(),
+                http_status: 200,
+                bytes_served: 1,
+                cache_status: "MISS".into(),
+                raw_range: None,
+            }),
+            ""
+        );
+        let delete = affected_download_delete_sql("$1");
+        assert!(delete.contains("NOT \"IsActive\""));
+        assert!(delete.contains("NOT EXISTS"));
+        let update = affected_survivor_recompute_sql("$1");
+        assert!(update.contains("COALESCE((SELECT SUM(\"BytesServed\")"));
+    }
+
+    #[test]
+    fn stored_url_collapses_repeated_slashes_only() {
+        assert_eq!(stored_log_url("/depot/file.bin"), "/depot/file.bin");
+        assert_eq!(stored_log_url("//depot//file.bin"), "/depot/file.bin");
+    }
+}
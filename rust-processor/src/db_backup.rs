@@ -0,0 +1,548 @@
+//! Pre-flight backups for the binaries that delete database rows (`db_reset`,
+//! `cache_service_remove`, the game-removal bins).
+//!
+//! Backups are opt-in (`--backup-dir`). Before the first row is deleted, the rows the operation
+//! is about to remove are streamed into a gzip-compressed NDJSON archive named
+//! `<operation>_<UTC timestamp>.ndjson.gz`:
+//!
+//! * a header line (`format`, `version`, `operation`, `createdAt`, `context`);
+//! * one `{"table": ..., "row": ...}` line per row, the row being Postgres' own `row_to_json`;
+//! * a footer line with the per-table row counts, so a truncated archive is detectable.
+//!
+//! Tables are written parent-first (`Downloads` before the rows that reference them), which is
+//! the order `db_restore_backup` re-inserts them in. The archive is written under a `.partial`
+//! name and only renamed once the footer is on disk; a failed backup aborts the operation before
+//! anything is deleted. After each successful backup the directory's retention policy runs.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures_util::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::postgres::PgArguments;
+use sqlx::query::QueryScalar;
+use sqlx::{PgPool, Postgres};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+pub const ARCHIVE_FORMAT: &str = "lancache-db-backup";
+pub const ARCHIVE_VERSION: u32 = 1;
+pub const ARCHIVE_EXTENSION: &str = ".ndjson.gz";
+
+const PARTIAL_SUFFIX: &str = ".partial";
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%3fZ";
+
+/// Tables an archive may carry, in the order they are written and restored.
+pub const BACKUP_TABLES: [&str; 5] = [
+    "Downloads",
+    "EventDownloads",
+    "LogEntries",
+    "ClientStats",
+    "ServiceStats",
+];
+
+/// `--backup-*` flags shared by every binary that can take a pre-flight backup.
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct BackupArgs {
+    /// Write the rows about to be deleted to a compressed archive in this directory first
+    #[arg(long = "backup-dir")]
+    pub backup_dir: Option<PathBuf>,
+
+    /// Number of archives kept in the backup directory (0 keeps all)
+    #[arg(long = "backup-keep", default_value_t = DEFAULT_KEEP)]
+    pub backup_keep: usize,
+
+    /// Delete archives older than this many days (at least 1)
+    #[arg(long = "backup-max-age-days", value_parser = clap::value_parser!(u64).range(1..))]
+    pub backup_max_age_days: Option<u64>,
+}
+
+pub const DEFAULT_KEEP: usize = 10;
+
+impl BackupArgs {
+    /// `None` when no backup was requested.
+    pub fn policy(&self) -> Option<BackupPolicy> {
+        self.backup_dir.as_ref().map(|dir| BackupPolicy {
+            dir: dir.clone(),
+            keep: self.backup_keep,
+            max_age_days: self.backup_max_age_days,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupPolicy {
+    pub dir: PathBuf,
+    /// Newest archives kept; 0 disables the count limit.
+    pub keep: usize,
+    pub max_age_days: Option<u64>,
+}
+
+/// What a finished backup wrote.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupSummary {
+    pub path: PathBuf,
+    pub rows: u64,
+    pub tables: BTreeMap<String, u64>,
+    pub pruned: usize,
+}
+
+/// An archive being written. Dropping it unfinished deletes the partial file.
+pub struct BackupArchive {
+    policy: BackupPolicy,
+    final_path: PathBuf,
+    partial_path: PathBuf,
+    encoder: Option<GzEncoder<BufWriter<File>>>,
+    tables: BTreeMap<String, u64>,
+    last_table: usize,
+}
+
+impl BackupArchive {
+    /// Opens a new archive for `operation` (a short file-name-safe tag such as `db_reset`) and
+    /// writes its header. `context` records what the operation was asked to delete.
+    pub fn create(policy: &BackupPolicy, operation: &str, context: serde_json::Value) -> Result<Self> {
+        fs::create_dir_all(&policy.dir).with_context(|| {
+            format!("failed to create backup directory {}", policy.dir.display())
+        })?;
+
+        let created_at = Utc::now();
+        let final_path = policy.dir.join(archive_file_name(operation, created_at));
+        let mut partial_name = final_path.as_os_str().to_owned();
+        partial_name.push(PARTIAL_SUFFIX);
+        let partial_path = PathBuf::from(partial_name);
+
+        let file = File::create(&partial_path)
+            .with_context(|| format!("failed to create backup archive {}", partial_path.display()))?;
+        let mut archive = Self {
+            policy: policy.clone(),
+            final_path,
+            partial_path,
+            encoder: Some(GzEncoder::new(BufWriter::new(file), Compression::default())),
+            tables: BTreeMap::new(),
+            last_table: 0,
+        };
+        archive.write_line(&json!({
+            "format": ARCHIVE_FORMAT,
+            "version": ARCHIVE_VERSION,
+            "operation": operation,
+            "createdAt": created_at.to_rfc3339(),
+            "context": context,
+        }).to_string())?;
+        Ok(archive)
+    }
+
+    /// Streams the rows of `table` (aliased `alias` in `filter`) into the archive. `bind` binds
+    /// the filter's parameters. Tables must be added in [`BACKUP_TABLES`] order.
+    pub async fn add_rows<F>(
+        &mut self,
+        pool: &PgPool,
+        table: &str,
+        alias: &str,
+        filter: &str,
+        bind: F,
+    ) -> Result<u64>
+    where
+        F: for<'q> FnOnce(
+            QueryScalar<'q, Postgres, String, PgArguments>,
+        ) -> QueryScalar<'q, Postgres, String, PgArguments>,
+    {
+        let Some(rank) = BACKUP_TABLES.iter().position(|t| *t == table) else {
+            bail!("{} is not a table the backup archive can carry", table);
+        };
+        if rank < self.last_table {
+            bail!("{} must be backed up before {}", table, BACKUP_TABLES[self.last_table]);
+        }
+        self.last_table = rank;
+
+        let sql = format!(
+            r#"SELECT row_to_json({alias})::text FROM "{table}" {alias} WHERE {filter}"#
+        );
+        let mut rows = bind(sqlx::query_scalar::<_, String>(&sql)).fetch(pool);
+        let mut written = 0u64;
+        while let Some(row) = rows
+            .try_next()
+            .await
+            .with_context(|| format!("failed to read {} rows for the backup", table))?
+        {
+            // `row` is already JSON; splice it in rather than parsing it back.
+            self.write_line(&format!(r#"{{"table":"{}","row":{}}}"#, table, row))?;
+            written += 1;
+        }
+
+        *self.tables.entry(table.to_string()).or_default() += written;
+        Ok(written)
+    }
+
+    /// Backs up the `Downloads` rows matched by `filter` (alias `d`) and their `EventDownloads`
+    /// tags, which the foreign key cascade deletes with them.
+    pub async fn add_downloads<F>(&mut self, pool: &PgPool, filter: &str, bind: F) -> Result<u64>
+    where
+        F: for<'q> Fn(
+            QueryScalar<'q, Postgres, String, PgArguments>,
+        ) -> QueryScalar<'q, Postgres, String, PgArguments>,
+    {
+        let downloads = format!(r#"SELECT d."Id" FROM "Downloads" d WHERE {}"#, filter);
+        let written = self.add_rows(pool, "Downloads", "d", filter, &bind).await?;
+        let tags = self
+            .add_rows(
+                pool,
+                "EventDownloads",
+                "ed",
+                &format!(r#"ed."DownloadId" IN ({})"#, downloads),
+                &bind,
+            )
+            .await?;
+        Ok(written + tags)
+    }
+
+    /// [`add_downloads`](Self::add_downloads) plus every `LogEntries` row of those downloads.
+    pub async fn add_download_cascade<F>(&mut self, pool: &PgPool, filter: &str, bind: F) -> Result<u64>
+    where
+        F: for<'q> Fn(
+            QueryScalar<'q, Postgres, String, PgArguments>,
+        ) -> QueryScalar<'q, Postgres, String, PgArguments>,
+    {
+        let written = self.add_downloads(pool, filter, &bind).await?;
+        let entries = self
+            .add_rows(
+                pool,
+                "LogEntries",
+                "le",
+                &format!(
+                    r#"le."DownloadId" IN (SELECT d."Id" FROM "Downloads" d WHERE {})"#,
+                    filter
+                ),
+                &bind,
+            )
+            .await?;
+        Ok(written + entries)
+    }
+
+    /// Writes the footer, renames the archive into place and applies the retention policy.
+    pub fn finish(mut self) -> Result<BackupSummary> {
+        let footer = json!({ "footer": true, "tables": self.tables }).to_string();
+        self.write_line(&footer)?;
+
+        let encoder = self.encoder.take().expect("archive already finished");
+        let mut writer = encoder
+            .finish()
+            .context("failed to finish the backup archive")?;
+        writer.flush().context("failed to flush the backup archive")?;
+        writer
+            .get_ref()
+            .sync_all()
+            .context("failed to sync the backup archive")?;
+        drop(writer);
+        fs::rename(&self.partial_path, &self.final_path).with_context(|| {
+            format!("failed to move the backup archive to {}", self.final_path.display())
+        })?;
+
+        let pruned = match apply_retention(&self.policy, Utc::now(), Some(&self.final_path)) {
+            Ok(pruned) => pruned,
+            Err(e) => {
+                // The backup itself is safe on disk; a failed prune must not block the operation.
+                eprintln!("Warning: backup retention failed: {:#}", e);
+                0
+            }
+        };
+
+        Ok(BackupSummary {
+            path: self.final_path.clone(),
+            rows: self.tables.values().sum(),
+            tables: std::mem::take(&mut self.tables),
+            pruned,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        let encoder = self.encoder.as_mut().expect("archive already finished");
+        encoder
+            .write_all(line.as_bytes())
+            .and_then(|_| encoder.write_all(b"\n"))
+            .context("failed to write to the backup archive")
+    }
+}
+
+impl Drop for BackupArchive {
+    fn drop(&mut self) {
+        if self.encoder.take().is_some() {
+            let _ = fs::remove_file(&self.partial_path);
+        }
+    }
+}
+
+pub fn archive_file_name(operation: &str, created_at: DateTime<Utc>) -> String {
+    format!("{}_{}{}", operation, created_at.format(TIMESTAMP_FORMAT), ARCHIVE_EXTENSION)
+}
+
+/// Creation time encoded in an archive file name, `None` for anything that is not an archive.
+pub fn archive_created_at(file_name: &str) -> Option<DateTime<Utc>> {
+    let stem = file_name.strip_suffix(ARCHIVE_EXTENSION)?;
+    let (_, stamp) = stem.rsplit_once('_')?;
+    NaiveDateTime::parse_from_str(stamp, TIMESTAMP_FORMAT)
+        .ok()
+        .map(|t| t.and_utc())
+}
+
+/// Deletes archives beyond the newest `keep` and any older than `max_age_days`. Only files named
+/// like archives are considered, and `current` (the archive just written) never is; returns how
+/// many were deleted.
+pub fn apply_retention(policy: &BackupPolicy, now: DateTime<Utc>, current: Option<&Path>) -> Result<usize> {
+    let mut archives: Vec<(DateTime<Utc>, PathBuf)> = fs::read_dir(&policy.dir)
+        .with_context(|| format!("failed to list backup directory {}", policy.dir.display()))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().map(|t| t.is_file()).unwrap_or(false))
+        .filter_map(|entry| {
+            let created = archive_created_at(entry.file_name().to_str()?)?;
+            Some((created, entry.path()))
+        })
+        .collect();
+    archives.sort_by_key(|(created, _)| std::cmp::Reverse(*created));
+
+    let cutoff = policy
+        .max_age_days
+        .map(|days| now - chrono::Duration::days(days.min(i64::MAX as u64 / 86_400) as i64));
+
+    let mut pruned = 0;
+    for (index, (created, path)) in archives.iter().enumerate() {
+        if current.is_some_and(|current| current == path) {
+            continue;
+        }
+        let over_count = policy.keep > 0 && index >= policy.keep;
+        let too_old = cutoff.is_some_and(|cutoff| *created < cutoff);
+        if over_count || too_old {
+            fs::remove_file(path)
+                .with_context(|| format!("failed to delete old backup {}", path.display()))?;
+            pruned += 1;
+        }
+    }
+    Ok(pruned)
+}
+
+/// Pre-flight backup for the removal heads: when a policy is set, archives the `Downloads` rows
+/// matched by `filter` (alias `d`) with their tags and log entries, and reports it on stderr.
+pub async fn backup_download_cascade<F>(
+    pool: &PgPool,
+    policy: Option<&BackupPolicy>,
+    operation: &str,
+    context: serde_json::Value,
+    filter: &str,
+    bind: F,
+) -> Result<Option<BackupSummary>>
+where
+    F: for<'q> Fn(
+        QueryScalar<'q, Postgres, String, PgArguments>,
+    ) -> QueryScalar<'q, Postgres, String, PgArguments>,
+{
+    let Some(policy) = policy else {
+        return Ok(None);
+    };
+    eprintln!("\nBacking up database records to {}...", policy.dir.display());
+    let mut archive = BackupArchive::create(policy, operation, context)?;
+    archive.add_download_cascade(pool, filter, bind).await?;
+    let summary = archive.finish()?;
+    log_summary(&summary);
+    Ok(Some(summary))
+}
+
+/// Adds `backupArchive` to a completion context when a backup was taken.
+pub fn annotate_context(context: &mut serde_json::Value, backup: Option<&BackupSummary>) {
+    if let (Some(summary), Some(map)) = (backup, context.as_object_mut()) {
+        map.insert(
+            "backupArchive".to_string(),
+            json!(summary.path.display().to_string()),
+        );
+    }
+}
+
+/// Echo a finished backup to stderr, the way the removal bins narrate their steps.
+pub fn log_summary(summary: &BackupSummary) {
+    eprintln!(
+        "Backed up {} rows to {}",
+        summary.rows,
+        summary.path.display()
+    );
+    for (table, rows) in &summary.tables {
+        eprintln!("  {}: {}", table, rows);
+    }
+    if summary.pruned > 0 {
+        eprintln!("  Pruned {} old backup(s)", summary.pruned);
+    }
+}
+
+/// Path of the archive a restore should read, refusing partial files.
+pub fn validate_archive_path(path: &Path) -> Result<()> {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    if name.ends_with(PARTIAL_SUFFIX) {
+        bail!("{} is an unfinished backup and cannot be restored", path.display());
+    }
+    if !path.is_file() {
+        bail!("backup archive not found: {}", path.display());
+    }
+    Ok(())
+}
+
+/// The header line of an archive.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchiveHeader {
+    pub format: String,
+    pub version: u32,
+    pub operation: String,
+    pub created_at: String,
+    #[serde(default)]
+    pub context: serde_json::Value,
+}
+
+/// One decoded line of an archive.
+#[derive(Debug, Clone)]
+pub enum ArchiveLine {
+    Header(ArchiveHeader),
+    Row { table: String, row: serde_json::Value },
+    Footer(BTreeMap<String, u64>),
+}
+
+#[derive(Deserialize)]
+struct RowLine {
+    table: String,
+    row: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct FooterLine {
+    tables: BTreeMap<String, u64>,
+}
+
+pub fn parse_line(line: &str) -> Result<ArchiveLine> {
+    let value: serde_json::Value =
+        serde_json::from_str(line).context("backup archive line is not valid JSON")?;
+    if value.get("footer").is_some() {
+        let footer: FooterLine = serde_json::from_value(value).context("malformed backup footer")?;
+        return Ok(ArchiveLine::Footer(footer.tables));
+    }
+    if value.get("format").is_some() {
+        let header: ArchiveHeader =
+            serde_json::from_value(value).context("malformed backup header")?;
+        if header.format != ARCHIVE_FORMAT {
+            bail!("not a database backup archive (format {:?})", header.format);
+        }
+        if header.version > ARCHIVE_VERSION {
+            bail!(
+                "backup archive version {} is newer than this tool supports ({})",
+                header.version,
+                ARCHIVE_VERSION
+            );
+        }
+        return Ok(ArchiveLine::Header(header));
+    }
+    let row: RowLine = serde_json::from_value(value).context("malformed backup row")?;
+    if !BACKUP_TABLES.contains(&row.table.as_str()) {
+        bail!("backup archive carries rows for unexpected table {}", row.table);
+    }
+    Ok(ArchiveLine::Row {
+        table: row.table,
+        row: row.row,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn touch(dir: &Path, name: &str) {
+        fs::write(dir.join(name), b"").unwrap();
+    }
+
+    fn remaining(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn archive_names_round_trip_their_timestamp() {
+        let at = Utc.with_ymd_and_hms(2024, 5, 1, 12, 30, 5).unwrap();
+        let name = archive_file_name("steam_remove", at);
+
+        assert_eq!(name, "steam_remove_20240501T123005000Z.ndjson.gz");
+        assert_eq!(archive_created_at(&name), Some(at));
+        assert_eq!(archive_created_at("notes.txt"), None);
+        assert_eq!(archive_created_at(&format!("{}{}", name, PARTIAL_SUFFIX)), None);
+    }
+
+    #[test]
+    fn archive_lines_are_classified_and_checked() {
+        let header = r#"{"format":"lancache-db-backup","version":1,"operation":"db_reset","createdAt":"2024-05-01T00:00:00+00:00","context":{}}"#;
+        assert!(matches!(parse_line(header).unwrap(), ArchiveLine::Header(h) if h.operation == "db_reset"));
+
+        let row = r#"{"table":"Downloads","row":{"Id":7,"Service":"steam"}}"#;
+        assert!(matches!(parse_line(row).unwrap(), ArchiveLine::Row { table, row } if table == "Downloads" && row["Id"] == 7));
+
+        let footer = r#"{"footer":true,"tables":{"Downloads":1}}"#;
+        assert!(matches!(parse_line(footer).unwrap(), ArchiveLine::Footer(t) if t["Downloads"] == 1));
+
+        assert!(parse_line(r#"{"table":"Users","row":{}}"#).is_err());
+        assert!(parse_line(&header.replace("version\":1", "version\":9")).is_err());
+    }
+
+    #[test]
+    fn retention_keeps_the_newest_and_drops_the_stale() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = Utc.with_ymd_and_hms(2024, 5, 10, 6, 0, 0).unwrap();
+        for day in 1..=5 {
+            let at = Utc.with_ymd_and_hms(2024, 5, day, 0, 0, 0).unwrap();
+            touch(dir.path(), &archive_file_name("db_reset", at));
+        }
+        touch(dir.path(), "README.txt");
+
+        let policy = BackupPolicy {
+            dir: dir.path().to_path_buf(),
+            keep: 3,
+            max_age_days: Some(7),
+        };
+        assert_eq!(apply_retention(&policy, now, None).unwrap(), 3);
+
+        // Day 3 is within the count limit but more than seven days old.
+        assert_eq!(
+            remaining(dir.path()),
+            vec![
+                "README.txt".to_string(),
+                "db_reset_20240504T000000000Z.ndjson.gz".to_string(),
+                "db_reset_20240505T000000000Z.ndjson.gz".to_string(),
+            ]
+        );
+
+        // The archive just written survives even when the policy would take it.
+        let current = dir.path().join("db_reset_20240505T000000000Z.ndjson.gz");
+        let policy = BackupPolicy {
+            keep: 1,
+            max_age_days: Some(1),
+            ..policy
+        };
+        assert_eq!(apply_retention(&policy, now, Some(&current)).unwrap(), 1);
+        assert_eq!(remaining(dir.path()), vec!["README.txt".to_string(), "db_reset_20240505T000000000Z.ndjson.gz".to_string()]);
+    }
+
+    #[test]
+    fn an_abandoned_archive_leaves_no_partial_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let policy = BackupPolicy {
+            dir: dir.path().to_path_buf(),
+            keep: 0,
+            max_age_days: None,
+        };
+
+        drop(BackupArchive::create(&policy, "db_reset", json!({})).unwrap());
+
+        assert!(remaining(dir.path()).is_empty());
+    }
+}
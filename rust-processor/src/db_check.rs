@@ -0,0 +1,559 @@
+// Check the database invariants ingest maintains and report every violation.
+//
+// Each check is one SQL statement that selects the key of every violating row. The run is
+// read-only (`SET TRANSACTION READ ONLY`) unless `--repair` is given; then the checks that
+// have a safe, purely derived fix apply it in the same transaction after every check has been
+// counted, so the report always describes the state the run found. Checks without a safe fix
+// (missing sessions, entry-less downloads, ...) only point at `db_rebuild`.
+//
+// The JSON report lists, per check, the violation count and up to `--samples` row keys.
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use serde::Serialize;
+use serde_json::json;
+use sqlx::{Postgres, Transaction};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use lancache_processor::cancel;
+use lancache_processor::db;
+use lancache_processor::db_stats;
+use lancache_processor::progress_events;
+use lancache_processor::progress_utils;
+use progress_events::ProgressReporter;
+
+/// Check database invariants and optionally repair the safe violations.
+#[derive(clap::Parser, Debug)]
+#[command(name = "db_check")]
+#[command(about = "Checks Downloads, LogEntries and stats invariants and reports violations")]
+struct Args {
+    /// Path to output JSON report
+    output_json: String,
+
+    /// Path to progress JSON file (use "none" to skip)
+    #[arg(default_value = "none")]
+    progress_json: Option<String>,
+
+    /// Apply the fixes for the checks that have a safe one
+    #[arg(long)]
+    repair: bool,
+
+    /// Sample row keys to include per check
+    #[arg(long, default_value_t = 20)]
+    samples: i64,
+
+    /// Emit JSON progress events to stdout
+    #[arg(short, long)]
+    progress: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProgressData {
+    status: String,
+    stage_key: String,
+    context: serde_json::Value,
+    percent_complete: f64,
+    checks_completed: usize,
+    total_checks: usize,
+    violations_found: u64,
+    timestamp: String,
+}
+
+/// How a check's violations get fixed under `--repair`.
+#[derive(Debug, Clone, Copy)]
+enum Repair {
+    /// No safe automatic fix; the hint says what to run instead.
+    Manual(&'static str),
+    /// A self-contained statement that fixes every violation of the check.
+    Sql(&'static str),
+    /// Recompute the violating `ClientStats` keys.
+    ClientStats,
+    /// Recompute the violating `ServiceStats` keys.
+    ServiceStats,
+}
+
+struct Check {
+    name: &'static str,
+    description: &'static str,
+    /// Selects one `key` column per violating row.
+    violations_sql: &'static str,
+    repair: Repair,
+}
+
+/// Ordered so each repair runs before the checks whose fix depends on it: dangling links are
+/// cleared before totals are recomputed, and the stats come last.
+const CHECKS: &[Check] = &[
+    Check {
+        name: "dangling_log_entry_download",
+        description: "LogEntries whose DownloadId points at a Downloads row that no longer exists",
+        violations_sql: r#"SELECT le."Id" AS key FROM "LogEntries" le
+             WHERE le."DownloadId" IS NOT NULL
+               AND NOT EXISTS (SELECT 1 FROM "Downloads" d WHERE d."Id" = le."DownloadId")"#,
+        repair: Repair::Sql(
+            r#"UPDATE "LogEntries" le SET "DownloadId" = NULL
+             WHERE le."DownloadId" IS NOT NULL
+               AND NOT EXISTS (SELECT 1 FROM "Downloads" d WHERE d."Id" = le."DownloadId")"#,
+        ),
+    },
+    Check {
+        name: "unlinked_log_entry",
+        description: "LogEntries that belong to no download session",
+        violations_sql: r#"SELECT le."Id" AS key FROM "LogEntries" le WHERE le."DownloadId" IS NULL"#,
+        repair: Repair::Manual("run db_rebuild to regenerate the sessions"),
+    },
+    Check {
+        name: "download_byte_totals",
+        description: "Downloads whose CacheHitBytes/CacheMissBytes differ from their LogEntries",
+        violations_sql: r#"SELECT d."Id" AS key FROM "Downloads" d
+              JOIN (SELECT le."DownloadId",
+                           COALESCE(SUM(le."BytesServed") FILTER (WHERE le."CacheStatus" = 'HIT'), 0)::bigint AS hit,
+                           COALESCE(SUM(le."BytesServed") FILTER (WHERE le."CacheStatus" = 'MISS'), 0)::bigint AS miss
+                      FROM "LogEntries" le
+                     WHERE le."DownloadId" IS NOT NULL
+                     GROUP BY le."DownloadId") s ON s."DownloadId" = d."Id"
+             WHERE d."CacheHitBytes" <> s.hit OR d."CacheMissBytes" <> s.miss"#,
+        repair: Repair::Sql(
+            r#"UPDATE "Downloads" d SET "CacheHitBytes" = s.hit, "CacheMissBytes" = s.miss
+              FROM (SELECT le."DownloadId",
+                           COALESCE(SUM(le."BytesServed") FILTER (WHERE le."CacheStatus" = 'HIT'), 0)::bigint AS hit,
+                           COALESCE(SUM(le."BytesServed") FILTER (WHERE le."CacheStatus" = 'MISS'), 0)::bigint AS miss
+                      FROM "LogEntries" le
+                     WHERE le."DownloadId" IS NOT NULL
+                     GROUP BY le."DownloadId") s
+             WHERE s."DownloadId" = d."Id"
+               AND (d."CacheHitBytes" <> s.hit OR d."CacheMissBytes" <> s.miss)"#,
+        ),
+    },
+    Check {
+        name: "download_time_span",
+        description: "Downloads whose StartTimeUtc/EndTimeUtc differ from their first/last LogEntries",
+        violations_sql: r#"SELECT d."Id" AS key FROM "Downloads" d
+              JOIN (SELECT le."DownloadId", MIN(le."Timestamp") AS first_seen, MAX(le."Timestamp") AS last_seen
+                      FROM "LogEntries" le
+                     WHERE le."DownloadId" IS NOT NULL
+                     GROUP BY le."DownloadId") s ON s."DownloadId" = d."Id"
+             WHERE d."StartTimeUtc" <> s.first_seen OR d."EndTimeUtc" <> s.last_seen"#,
+        repair: Repair::Sql(
+            r#"UPDATE "Downloads" d SET "StartTimeUtc" = s.first_seen, "EndTimeUtc" = s.last_seen
+              FROM (SELECT le."DownloadId", MIN(le."Timestamp") AS first_seen, MAX(le."Timestamp") AS last_seen
+                      FROM "LogEntries" le
+                     WHERE le."DownloadId" IS NOT NULL
+                     GROUP BY le."DownloadId") s
+             WHERE s."DownloadId" = d."Id"
+               AND (d."StartTimeUtc" <> s.first_seen OR d."EndTimeUtc" <> s.last_seen)"#,
+        ),
+    },
+    Check {
+        name: "download_datasource",
+        description: "Downloads with LogEntries from a different datasource",
+        violations_sql: r#"SELECT DISTINCT d."Id" AS key FROM "Downloads" d
+              JOIN "LogEntries" le ON le."DownloadId" = d."Id"
+             WHERE le."Datasource" <> d."Datasource""#,
+        repair: Repair::Manual("run db_rebuild for the affected datasources"),
+    },
+    Check {
+        name: "empty_download",
+        description: "Inactive Downloads with no LogEntries left",
+        violations_sql: r#"SELECT d."Id" AS key FROM "Downloads" d
+             WHERE NOT d."IsActive"
+               AND NOT EXISTS (SELECT 1 FROM "LogEntries" le WHERE le."DownloadId" = d."Id")"#,
+        repair: Repair::Manual("run db_rebuild over their datasource or time range to drop them"),
+    },
+    Check {
+        name: "multiple_active_sessions",
+        description: "Active Downloads that are not the newest active session of their client and service",
+        violations_sql: r#"SELECT d."Id" AS key FROM "Downloads" d
+             WHERE d."IsActive"
+               AND EXISTS (SELECT 1 FROM "Downloads" n
+                            WHERE n."IsActive" AND n."ClientIp" = d."ClientIp" AND n."Service" = d."Service"
+                              AND (n."StartTimeUtc", n."Id") > (d."StartTimeUtc", d."Id"))"#,
+        repair: Repair::Sql(
+            r#"UPDATE "Downloads" d SET "IsActive" = false
+             WHERE d."IsActive"
+               AND EXISTS (SELECT 1 FROM "Downloads" n
+                            WHERE n."IsActive" AND n."ClientIp" = d."ClientIp" AND n."Service" = d."Service"
+                              AND (n."StartTimeUtc", n."Id") > (d."StartTimeUtc", d."Id"))"#,
+        ),
+    },
+    Check {
+        name: "client_stats",
+        description: "ClientStats rows missing, left over, or with byte totals that differ from LogEntries",
+        violations_sql: r#"SELECT COALESCE(cs."ClientIp", s."ClientIp") AS key
+              FROM "ClientStats" cs
+              FULL JOIN (SELECT le."ClientIp",
+                                COALESCE(SUM(le."BytesServed") FILTER (WHERE le."CacheStatus" = 'HIT'), 0)::bigint AS hit,
+                                COALESCE(SUM(le."BytesServed") FILTER (WHERE le."CacheStatus" = 'MISS'), 0)::bigint AS miss
+                           FROM "LogEntries" le
+                          GROUP BY le."ClientIp") s ON s."ClientIp" = cs."ClientIp"
+             WHERE cs."ClientIp" IS NULL OR s."ClientIp" IS NULL
+                OR cs."TotalCacheHitBytes" <> s.hit OR cs."TotalCacheMissBytes" <> s.miss"#,
+        repair: Repair::ClientStats,
+    },
+    Check {
+        name: "service_stats",
+        description: "ServiceStats rows missing, left over, or with byte totals that differ from LogEntries",
+        violations_sql: r#"SELECT COALESCE(ss."Service", s."Service") AS key
+              FROM "ServiceStats" ss
+              FULL JOIN (SELECT le."Service",
+                                COALESCE(SUM(le."BytesServed") FILTER (WHERE le."CacheStatus" = 'HIT'), 0)::bigint AS hit,
+                                COALESCE(SUM(le."BytesServed") FILTER (WHERE le."CacheStatus" = 'MISS'), 0)::bigint AS miss
+                           FROM "LogEntries" le
+                          GROUP BY le."Service") s ON s."Service" = ss."Service"
+             WHERE ss."Service" IS NULL OR s."Service" IS NULL
+                OR ss."TotalCacheHitBytes" <> s.hit OR ss."TotalCacheMissBytes" <> s.miss"#,
+        repair: Repair::ServiceStats,
+    },
+];
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CheckResult {
+    name: &'static str,
+    description: &'static str,
+    violations: u64,
+    sample_keys: Vec<serde_json::Value>,
+    repairable: bool,
+    repaired: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hint: Option<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CheckReport {
+    success: bool,
+    repair: bool,
+    cancelled: bool,
+    total_violations: u64,
+    total_repaired: u64,
+    checks: Vec<CheckResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Row ids are reported as numbers, stats keys (client IPs, services) as strings.
+fn sample_key(key: String) -> serde_json::Value {
+    match key.parse::<i64>() {
+        Ok(id) => json!(id),
+        Err(_) => json!(key),
+    }
+}
+
+/// Writes the progress file (when a path was supplied) and THEN emits the matching stdout event.
+#[allow(clippy::too_many_arguments)]
+fn write_progress(
+    progress_path: Option<&Path>,
+    reporter: &ProgressReporter,
+    status: &str,
+    stage_key: &str,
+    context: serde_json::Value,
+    percent_complete: f64,
+    checks_completed: usize,
+    violations_found: u64,
+) -> Result<()> {
+    if let Some(path) = progress_path {
+        let progress = ProgressData {
+            status: status.to_string(),
+            stage_key: stage_key.to_string(),
+            context: context.clone(),
+            percent_complete,
+            checks_completed,
+            total_checks: CHECKS.len(),
+            violations_found,
+            timestamp: progress_utils::current_timestamp(),
+        };
+        progress_utils::write_progress_json(path, &progress)?;
+    }
+
+    match status {
+        "starting" => reporter.emit_started(stage_key, context),
+        "completed" => reporter.emit_complete(stage_key, context),
+        "cancelled" => reporter.emit_cancelled(stage_key, context),
+        _ => reporter.emit_progress(percent_complete, stage_key, context),
+    }
+
+    Ok(())
+}
+
+async fn run_check(
+    tx: &mut Transaction<'_, Postgres>,
+    check: &Check,
+    samples: i64,
+) -> Result<(u64, Vec<String>)> {
+    let sql = format!(
+        "WITH v AS ({}) SELECT (SELECT COUNT(*) FROM v), ARRAY(SELECT key::text FROM v ORDER BY key LIMIT $1)",
+        check.violations_sql
+    );
+    let (count, keys): (i64, Vec<String>) = sqlx::query_as(&sql)
+        .bind(samples)
+        .fetch_one(&mut **tx)
+        .await
+        .with_context(|| format!("check {} failed", check.name))?;
+    Ok((count as u64, keys))
+}
+
+async fn violation_keys(tx: &mut Transaction<'_, Postgres>, check: &Check) -> Result<Vec<String>> {
+    let sql = format!("SELECT key::text FROM ({}) v", check.violations_sql);
+    sqlx::query_scalar::<_, String>(&sql)
+        .fetch_all(&mut **tx)
+        .await
+        .with_context(|| format!("failed to list violations of {}", check.name))
+}
+
+async fn repair_check(tx: &mut Transaction<'_, Postgres>, check: &Check) -> Result<u64> {
+    let repaired = match check.repair {
+        Repair::Manual(_) => 0,
+        Repair::Sql(sql) => sqlx::query(sql)
+            .execute(&mut **tx)
+            .await
+            .with_context(|| format!("repair of {} failed", check.name))?
+            .rows_affected(),
+        Repair::ClientStats => {
+            let keys = violation_keys(tx, check).await?;
+            let outcome = db_stats::recompute_client_stats(tx, &keys).await?;
+            outcome.upserted + outcome.deleted
+        }
+        Repair::ServiceStats => {
+            let keys = violation_keys(tx, check).await?;
+            let outcome = db_stats::recompute_service_stats(tx, &keys).await?;
+            outcome.upserted + outcome.deleted
+        }
+    };
+    Ok(repaired)
+}
+
+async fn check_database(
+    args: &Args,
+    progress_path: Option<&Path>,
+    reporter: &ProgressReporter,
+) -> Result<CheckReport> {
+    let pool = db::create_pool().await?;
+    let mut tx = pool.begin().await.context("failed to begin check transaction")?;
+    if !args.repair {
+        sqlx::query("SET TRANSACTION READ ONLY")
+            .execute(&mut *tx)
+            .await
+            .context("failed to make the check transaction read-only")?;
+    }
+
+    let samples = args.samples.max(0);
+    let mut report = CheckReport {
+        success: true,
+        repair: args.repair,
+        cancelled: false,
+        total_violations: 0,
+        total_repaired: 0,
+        checks: Vec::with_capacity(CHECKS.len()),
+        error: None,
+    };
+
+    for (index, check) in CHECKS.iter().enumerate() {
+        if cancel::is_cancelled() {
+            tx.rollback().await.ok();
+            report.cancelled = true;
+            return Ok(report);
+        }
+
+        let (violations, keys) = run_check(&mut tx, check, samples).await?;
+        eprintln!("[DbCheck] {}: {} violation(s)", check.name, violations);
+        report.total_violations += violations;
+        report.checks.push(CheckResult {
+            name: check.name,
+            description: check.description,
+            violations,
+            sample_keys: keys.into_iter().map(sample_key).collect(),
+            repairable: !matches!(check.repair, Repair::Manual(_)),
+            repaired: 0,
+            hint: match check.repair {
+                Repair::Manual(hint) if violations > 0 => Some(hint),
+                _ => None,
+            },
+        });
+
+        let percent = (index + 1) as f64 / CHECKS.len() as f64 * if args.repair { 70.0 } else { 95.0 };
+        write_progress(
+            progress_path,
+            reporter,
+            "running",
+            "signalr.dbCheck.checking",
+            json!({ "check": check.name, "violations": violations }),
+            percent,
+            index + 1,
+            report.total_violations,
+        )?;
+    }
+
+    if !args.repair {
+        tx.rollback().await.ok();
+        return Ok(report);
+    }
+
+    for (index, check) in CHECKS.iter().enumerate() {
+        if cancel::is_cancelled() {
+            // Nothing is committed until every repair succeeded.
+            tx.rollback().await.ok();
+            report.cancelled = true;
+            for result in &mut report.checks {
+                result.repaired = 0;
+            }
+            report.total_repaired = 0;
+            return Ok(report);
+        }
+        if report.checks[index].violations == 0 {
+            continue;
+        }
+
+        let repaired = repair_check(&mut tx, check).await?;
+        if repaired > 0 {
+            eprintln!("[DbCheck] {}: repaired {} row(s)", check.name, repaired);
+        }
+        report.checks[index].repaired = repaired;
+        report.total_repaired += repaired;
+
+        write_progress(
+            progress_path,
+            reporter,
+            "running",
+            "signalr.dbCheck.repairing",
+            json!({ "check": check.name, "repaired": repaired }),
+            70.0 + (index + 1) as f64 / CHECKS.len() as f64 * 25.0,
+            CHECKS.len(),
+            report.total_violations,
+        )?;
+    }
+
+    tx.commit().await.context("failed to commit repairs")?;
+    Ok(report)
+}
+
+fn write_report(output_json: &str, report: &CheckReport) -> Result<()> {
+    let payload = serde_json::to_string_pretty(report).context("Failed to serialize check report")?;
+    fs::write(output_json, payload)
+        .with_context(|| format!("Failed to write output JSON to {}", output_json))
+}
+
+async fn run(args: &Args, progress_path: Option<&Path>, reporter: &ProgressReporter) -> Result<()> {
+    write_progress(
+        progress_path,
+        reporter,
+        "starting",
+        "signalr.dbCheck.starting",
+        json!({ "repair": args.repair, "totalChecks": CHECKS.len() }),
+        0.0,
+        0,
+        0,
+    )?;
+
+    let report = match check_database(args, progress_path, reporter).await {
+        Ok(report) => report,
+        Err(e) => {
+            // Still write a failure report so the caller can parse it.
+            let failure = CheckReport {
+                success: false,
+                repair: args.repair,
+                cancelled: false,
+                total_violations: 0,
+                total_repaired: 0,
+                checks: Vec::new(),
+                error: Some(format!("{e:#}")),
+            };
+            let _ = write_report(&args.output_json, &failure);
+            return Err(e);
+        }
+    };
+    write_report(&args.output_json, &report)?;
+
+    let context = json!({
+        "repair": report.repair,
+        "totalViolations": report.total_violations,
+        "totalRepaired": report.total_repaired,
+        "checksCompleted": report.checks.len(),
+    });
+    if report.cancelled {
+        eprintln!("[DbCheck] Cancelled - no repairs were committed");
+        return write_progress(
+            progress_path,
+            reporter,
+            "cancelled",
+            "signalr.dbCheck.cancelled",
+            context,
+            0.0,
+            report.checks.len(),
+            report.total_violations,
+        );
+    }
+
+    eprintln!(
+        "[DbCheck] {} violation(s) across {} checks, {} row(s) repaired",
+        report.total_violations,
+        CHECKS.len(),
+        report.total_repaired
+    );
+    write_progress(
+        progress_path,
+        reporter,
+        "completed",
+        "signalr.dbCheck.complete",
+        context,
+        100.0,
+        CHECKS.len(),
+        report.total_violations,
+    )
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    cancel::install();
+    let args = Args::parse();
+    let reporter = ProgressReporter::new(args.progress);
+
+    let progress_path = match args.progress_json.as_deref() {
+        Some("none") | None => None,
+        Some(p) => Some(PathBuf::from(p)),
+    };
+
+    let result = run(&args, progress_path.as_deref(), &reporter).await;
+    progress_events::finish_or_exit(&reporter, "signalr.dbCheck.error.fatal", result);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn check_names_are_unique_and_select_a_key() {
+        let mut names = HashSet::new();
+        for check in CHECKS {
+            assert!(names.insert(check.name), "duplicate check {}", check.name);
+            assert!(check.violations_sql.contains(" AS key"), "{} selects no key", check.name);
+        }
+    }
+
+    #[test]
+    fn sql_repairs_only_update() {
+        for check in CHECKS {
+            if let Repair::Sql(sql) = check.repair {
+                assert!(sql.trim_start().starts_with("UPDATE"), "{} repair is not an UPDATE", check.name);
+                assert!(!sql.contains("DELETE"), "{} repair deletes rows", check.name);
+            }
+        }
+    }
+
+    #[test]
+    fn dangling_links_are_repaired_before_download_totals() {
+        let position = |name: &str| CHECKS.iter().position(|c| c.name == name).unwrap();
+
+        assert!(position("dangling_log_entry_download") < position("download_byte_totals"));
+        assert_eq!(position("service_stats"), CHECKS.len() - 1);
+    }
+
+    #[test]
+    fn sample_keys_keep_ids_numeric() {
+        assert_eq!(sample_key("42".to_string()), json!(42));
+        assert_eq!(sample_key("10.0.0.5".to_string()), json!("10.0.0.5"));
+    }
+}
@@ -0,0 +1,777 @@
+// Report table/index bloat and dead tuples, then VACUUM (ANALYZE) and REINDEX CONCURRENTLY
+// each table.
+//
+// Heavy purges (`db_reset`, the removal bins) delete millions of `LogEntries` rows and leave the
+// heap and its indexes full of dead space that autovacuum reclaims slowly, if at all for the
+// indexes. The report is computed from the catalog and `pg_stats` alone - no extension needed -
+// so the bloat figures are estimates: the expected size of a table or btree index is derived
+// from its live tuple count and average row/key width, and whatever the relation occupies beyond
+// that is reported as bloat.
+//
+// Maintenance runs table by table, largest first. Neither statement can run in a transaction, so
+// a cancel is delivered to the server with `pg_cancel_backend`: a cancelled VACUUM keeps the work
+// it finished, and a cancelled REINDEX CONCURRENTLY has the invalid `_ccnew`/`_ccold` indexes it
+// leaves behind dropped again before the binary exits. `--report-only` skips maintenance.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use serde::Serialize;
+use serde_json::json;
+use sqlx::{PgPool, Row};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use lancache_processor::cancel;
+use lancache_processor::db;
+use lancache_processor::progress_events;
+use lancache_processor::progress_utils;
+use progress_events::ProgressReporter;
+
+/// How often a running statement checks for a cancel request.
+const CANCEL_POLL: Duration = Duration::from_millis(250);
+
+/// Leaf fill factor btree indexes are built with.
+const BTREE_FILL_FACTOR: f64 = 0.9;
+
+/// `REINDEX ... CONCURRENTLY` needs PostgreSQL 12.
+const MIN_CONCURRENT_REINDEX_VERSION: i32 = 120_000;
+
+/// Report database bloat and vacuum/reindex the tables.
+#[derive(clap::Parser, Debug)]
+#[command(name = "db_maintain")]
+#[command(about = "Reports table/index bloat and runs VACUUM (ANALYZE) and REINDEX CONCURRENTLY")]
+struct Args {
+    /// Path to output JSON report
+    output_json: String,
+
+    /// Path to progress JSON file (use "none" to skip)
+    #[arg(default_value = "none")]
+    progress_json: Option<String>,
+
+    /// Only report bloat and dead tuples, change nothing
+    #[arg(long)]
+    report_only: bool,
+
+    /// Vacuum but do not rebuild indexes
+    #[arg(long)]
+    no_reindex: bool,
+
+    /// Maintain only this table (repeatable); default is every table
+    #[arg(long = "table")]
+    tables: Vec<String>,
+
+    /// Skip tables whose dead tuples and estimated bloat are both below this percentage
+    #[arg(long, default_value_t = 0.0)]
+    min_bloat_percent: f64,
+
+    /// Emit JSON progress events to stdout
+    #[arg(short, long)]
+    progress: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProgressData {
+    status: String,
+    stage_key: String,
+    context: serde_json::Value,
+    percent_complete: f64,
+    tables_completed: usize,
+    total_tables: usize,
+    bytes_reclaimed: i64,
+    timestamp: String,
+}
+
+/// Writes the progress file (when a path was supplied) and THEN emits the matching stdout event.
+#[allow(clippy::too_many_arguments)]
+fn write_progress(
+    progress_path: Option<&Path>,
+    reporter: &ProgressReporter,
+    status: &str,
+    stage_key: &str,
+    context: serde_json::Value,
+    percent_complete: f64,
+    tables_completed: usize,
+    total_tables: usize,
+    bytes_reclaimed: i64,
+) -> Result<()> {
+    if let Some(path) = progress_path {
+        let progress = ProgressData {
+            status: status.to_string(),
+            stage_key: stage_key.to_string(),
+            context: context.clone(),
+            percent_complete,
+            tables_completed,
+            total_tables,
+            bytes_reclaimed,
+            timestamp: progress_utils::current_timestamp(),
+        };
+        progress_utils::write_progress_json(path, &progress)?;
+    }
+
+    match status {
+        "starting" => reporter.emit_started(stage_key, context),
+        "completed" => reporter.emit_complete(stage_key, context),
+        "cancelled" => reporter.emit_cancelled(stage_key, context),
+        _ => reporter.emit_progress(percent_complete, stage_key, context),
+    }
+
+    Ok(())
+}
+
+/// `ceil(n / d)` for the page arithmetic below.
+fn pages_for(tuples: f64, tuples_per_page: f64) -> f64 {
+    (tuples / tuples_per_page.max(1.0)).ceil()
+}
+
+fn maxalign(bytes: f64) -> f64 {
+    (bytes / 8.0).ceil() * 8.0
+}
+
+/// Smallest heap that holds `live_tuples` rows of `row_width` bytes: a 24-byte page header, a
+/// 24-byte tuple header (23 + padding) and a 4-byte line pointer per row.
+fn expected_heap_bytes(live_tuples: f64, row_width: f64, block_size: f64) -> i64 {
+    if live_tuples <= 0.0 {
+        return 0;
+    }
+    let tuple = maxalign(24.0 + row_width) + 4.0;
+    let per_page = ((block_size - 24.0) / tuple).floor();
+    (pages_for(live_tuples, per_page) * block_size) as i64
+}
+
+/// Freshly built btree holding `tuples` keys of `key_width` bytes: leaf pages filled to the
+/// default fill factor (8-byte index tuple header, 4-byte line pointer, 16-byte special space)
+/// plus the metapage. Internal pages are a rounding error and are ignored.
+fn expected_btree_bytes(tuples: f64, key_width: f64, block_size: f64) -> i64 {
+    let tuple = maxalign(8.0 + key_width) + 4.0;
+    let per_page = ((block_size - 24.0 - 16.0) * BTREE_FILL_FACTOR / tuple).floor();
+    let leaves = if tuples <= 0.0 { 1.0 } else { pages_for(tuples, per_page) };
+    ((leaves + 1.0) * block_size) as i64
+}
+
+/// Bytes beyond the expected size, and that excess as a share of the actual size.
+fn bloat(actual: i64, expected: Option<i64>) -> (Option<i64>, Option<f64>) {
+    match expected {
+        Some(expected) if actual > 0 => {
+            let excess = (actual - expected).max(0);
+            (Some(excess), Some(round1(excess as f64 * 100.0 / actual as f64)))
+        }
+        Some(_) => (Some(0), Some(0.0)),
+        None => (None, None),
+    }
+}
+
+fn round1(value: f64) -> f64 {
+    (value * 10.0).round() / 10.0
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct IndexStats {
+    name: String,
+    bytes: i64,
+    scans: i64,
+    valid: bool,
+    /// `None` for non-btree and expression indexes, whose size is not estimated.
+    estimated_bloat_bytes: Option<i64>,
+    estimated_bloat_percent: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TableStats {
+    live_tuples: i64,
+    dead_tuples: i64,
+    dead_tuple_percent: f64,
+    table_bytes: i64,
+    index_bytes: i64,
+    total_bytes: i64,
+    /// `None` until the table has been analyzed.
+    estimated_bloat_bytes: Option<i64>,
+    estimated_bloat_percent: Option<f64>,
+    last_vacuum: Option<String>,
+    last_autovacuum: Option<String>,
+    last_analyze: Option<String>,
+    last_autoanalyze: Option<String>,
+    indexes: Vec<IndexStats>,
+}
+
+impl TableStats {
+    /// The larger of the dead-tuple share and any estimated bloat share (heap or index).
+    fn worst_percent(&self) -> f64 {
+        self.indexes
+            .iter()
+            .filter_map(|i| i.estimated_bloat_percent)
+            .chain(self.estimated_bloat_percent)
+            .fold(self.dead_tuple_percent, f64::max)
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TableReport {
+    name: String,
+    before: TableStats,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    after: Option<TableStats>,
+    skipped: bool,
+    vacuumed: bool,
+    reindexed: bool,
+    bytes_reclaimed: i64,
+    duration_seconds: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct MaintainReport {
+    success: bool,
+    report_only: bool,
+    cancelled: bool,
+    concurrent_reindex_supported: bool,
+    total_bytes_before: i64,
+    total_bytes_after: i64,
+    bytes_reclaimed: i64,
+    tables: Vec<TableReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+const TABLE_STATS_SQL: &str = r#"
+SELECT c.relname::text AS name,
+       s.n_live_tup AS live_tuples,
+       s.n_dead_tup AS dead_tuples,
+       pg_relation_size(c.oid) AS table_bytes,
+       pg_indexes_size(c.oid) AS index_bytes,
+       pg_total_relation_size(c.oid) AS total_bytes,
+       (SELECT SUM(ps.avg_width)::float8 FROM pg_stats ps
+         WHERE ps.schemaname = n.nspname AND ps.tablename = c.relname) AS row_width,
+       s.last_vacuum, s.last_autovacuum, s.last_analyze, s.last_autoanalyze
+  FROM pg_class c
+  JOIN pg_namespace n ON n.oid = c.relnamespace
+  JOIN pg_stat_user_tables s ON s.relid = c.oid
+ WHERE n.nspname = current_schema() AND c.relkind = 'r'
+   AND ($1::text IS NULL OR c.relname = $1)
+ ORDER BY pg_total_relation_size(c.oid) DESC, c.relname"#;
+
+/// Index key width is only known when every key column is a plain column with `pg_stats`.
+const INDEX_STATS_SQL: &str = r#"
+SELECT ic.relname::text AS name,
+       pg_relation_size(ic.oid) AS bytes,
+       COALESCE(si.idx_scan, 0) AS scans,
+       i.indisvalid AS valid,
+       am.amname::text AS method,
+       ic.reltuples::float8 AS tuples,
+       (SELECT CASE WHEN COUNT(ps.avg_width) = i.indnkeyatts THEN SUM(ps.avg_width)::float8 END
+          FROM pg_attribute a
+          LEFT JOIN pg_stats ps ON ps.schemaname = n.nspname AND ps.tablename = t.relname
+                               AND ps.attname = a.attname
+         WHERE a.attrelid = ic.oid AND a.attnum > 0 AND a.attnum <= i.indnkeyatts) AS key_width
+  FROM pg_index i
+  JOIN pg_class ic ON ic.oid = i.indexrelid
+  JOIN pg_class t ON t.oid = i.indrelid
+  JOIN pg_namespace n ON n.oid = t.relnamespace
+  JOIN pg_am am ON am.oid = ic.relam
+  LEFT JOIN pg_stat_user_indexes si ON si.indexrelid = i.indexrelid
+ WHERE n.nspname = current_schema() AND t.relname = $1
+ ORDER BY ic.relname"#;
+
+/// Leftovers of an interrupted `REINDEX CONCURRENTLY` on one table.
+const INVALID_REINDEX_LEFTOVERS_SQL: &str = r#"
+SELECT ic.relname::text
+  FROM pg_index i
+  JOIN pg_class ic ON ic.oid = i.indexrelid
+  JOIN pg_class t ON t.oid = i.indrelid
+  JOIN pg_namespace n ON n.oid = t.relnamespace
+ WHERE n.nspname = current_schema() AND t.relname = $1 AND NOT i.indisvalid
+   AND (ic.relname ~ '_ccnew[0-9]*$' OR ic.relname ~ '_ccold[0-9]*$')"#;
+
+async fn block_size(pool: &PgPool) -> Result<f64> {
+    let size: String = sqlx::query_scalar("SELECT current_setting('block_size')")
+        .fetch_one(pool)
+        .await
+        .context("failed to read block_size")?;
+    size.parse::<f64>().context("block_size is not a number")
+}
+
+async fn table_names(pool: &PgPool) -> Result<Vec<String>> {
+    sqlx::query_scalar::<_, String>(
+        r#"SELECT c.relname::text FROM pg_class c
+             JOIN pg_namespace n ON n.oid = c.relnamespace
+            WHERE n.nspname = current_schema() AND c.relkind = 'r'
+            ORDER BY pg_total_relation_size(c.oid) DESC, c.relname"#,
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to list tables")
+}
+
+async fn collect_stats(pool: &PgPool, table: &str, block_size: f64) -> Result<TableStats> {
+    let row = sqlx::query(TABLE_STATS_SQL)
+        .bind(table)
+        .fetch_optional(pool)
+        .await
+        .with_context(|| format!("failed to read statistics of {}", table))?
+        .with_context(|| format!("table {} does not exist", table))?;
+
+    let live_tuples: i64 = row.get("live_tuples");
+    let dead_tuples: i64 = row.get("dead_tuples");
+    let table_bytes: i64 = row.get("table_bytes");
+    let row_width: Option<f64> = row.get("row_width");
+    let expected = row_width.map(|w| expected_heap_bytes(live_tuples as f64, w, block_size));
+    let (estimated_bloat_bytes, estimated_bloat_percent) = bloat(table_bytes, expected);
+
+    let index_rows = sqlx::query(INDEX_STATS_SQL)
+        .bind(table)
+        .fetch_all(pool)
+        .await
+        .with_context(|| format!("failed to read index statistics of {}", table))?;
+    let indexes = index_rows
+        .iter()
+        .map(|r| {
+            let bytes: i64 = r.get("bytes");
+            let method: String = r.get("method");
+            let tuples: f64 = r.get("tuples");
+            let key_width: Option<f64> = r.get("key_width");
+            // reltuples is -1 until the index's table is first vacuumed or analyzed.
+            let expected = match (method.as_str(), key_width) {
+                ("btree", Some(width)) if tuples >= 0.0 => {
+                    Some(expected_btree_bytes(tuples, width, block_size))
+                }
+                _ => None,
+            };
+            let (estimated_bloat_bytes, estimated_bloat_percent) = bloat(bytes, expected);
+            IndexStats {
+                name: r.get("name"),
+                bytes,
+                scans: r.get("scans"),
+                valid: r.get("valid"),
+                estimated_bloat_bytes,
+                estimated_bloat_percent,
+            }
+        })
+        .collect();
+
+    let total_tuples = live_tuples + dead_tuples;
+    Ok(TableStats {
+        live_tuples,
+        dead_tuples,
+        dead_tuple_percent: if total_tuples > 0 {
+            round1(dead_tuples as f64 * 100.0 / total_tuples as f64)
+        } else {
+            0.0
+        },
+        table_bytes,
+        index_bytes: row.get("index_bytes"),
+        total_bytes: row.get("total_bytes"),
+        estimated_bloat_bytes,
+        estimated_bloat_percent,
+        last_vacuum: timestamp(&row, "last_vacuum"),
+        last_autovacuum: timestamp(&row, "last_autovacuum"),
+        last_analyze: timestamp(&row, "last_analyze"),
+        last_autoanalyze: timestamp(&row, "last_autoanalyze"),
+        indexes,
+    })
+}
+
+fn timestamp(row: &sqlx::postgres::PgRow, column: &str) -> Option<String> {
+    row.get::<Option<DateTime<Utc>>, _>(column).map(|t| t.to_rfc3339())
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+enum StatementOutcome {
+    Completed,
+    Cancelled,
+}
+
+/// Runs one maintenance statement on its own connection while a watcher turns a cancel request
+/// into `pg_cancel_backend` for that connection.
+async fn run_cancellable(pool: &PgPool, sql: &str) -> Result<StatementOutcome> {
+    let mut conn = pool.acquire().await.context("failed to acquire a connection")?;
+    let pid: i32 = sqlx::query_scalar("SELECT pg_backend_pid()")
+        .fetch_one(&mut *conn)
+        .await
+        .context("failed to read the backend pid")?;
+
+    let watcher_pool = pool.clone();
+    let watcher = tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(CANCEL_POLL).await;
+            if cancel::is_cancelled() {
+                let _ = sqlx::query("SELECT pg_cancel_backend($1)")
+                    .bind(pid)
+                    .execute(&watcher_pool)
+                    .await;
+                return;
+            }
+        }
+    });
+
+    let result = sqlx::query(sql).execute(&mut *conn).await;
+    watcher.abort();
+
+    match result {
+        Ok(_) => Ok(StatementOutcome::Completed),
+        Err(sqlx::Error::Database(e))
+            if cancel::is_cancelled() && e.code().as_deref() == Some("57014") =>
+        {
+            Ok(StatementOutcome::Cancelled)
+        }
+        Err(e) => Err(e).with_context(|| format!("{} failed", sql)),
+    }
+}
+
+/// Drops the invalid indexes an interrupted `REINDEX CONCURRENTLY` left on `table`.
+async fn drop_reindex_leftovers(pool: &PgPool, table: &str) -> Result<usize> {
+    let leftovers: Vec<String> = sqlx::query_scalar(INVALID_REINDEX_LEFTOVERS_SQL)
+        .bind(table)
+        .fetch_all(pool)
+        .await
+        .with_context(|| format!("failed to look for interrupted reindex leftovers on {}", table))?;
+    for index in &leftovers {
+        eprintln!("[DbMaintain] Dropping invalid index {} left by an interrupted reindex", index);
+        sqlx::query(&format!("DROP INDEX CONCURRENTLY IF EXISTS {}", quote_ident(index)))
+            .execute(pool)
+            .await
+            .with_context(|| format!("failed to drop invalid index {}", index))?;
+    }
+    Ok(leftovers.len())
+}
+
+async fn maintain(
+    args: &Args,
+    progress_path: Option<&Path>,
+    reporter: &ProgressReporter,
+) -> Result<MaintainReport> {
+    let pool = db::create_pool().await?;
+    let block_size = block_size(&pool).await?;
+    let server_version: i32 = sqlx::query_scalar("SELECT current_setting('server_version_num')::int")
+        .fetch_one(&pool)
+        .await
+        .context("failed to read the server version")?;
+    let concurrent_reindex = server_version >= MIN_CONCURRENT_REINDEX_VERSION;
+    let reindex = !args.no_reindex && concurrent_reindex;
+    if !args.no_reindex && !concurrent_reindex && !args.report_only {
+        eprintln!("[DbMaintain] PostgreSQL {} has no REINDEX CONCURRENTLY; indexes are only reported", server_version);
+    }
+
+    let existing = table_names(&pool).await?;
+    let tables: Vec<String> = if args.tables.is_empty() {
+        existing
+    } else {
+        // Keep the size order of the catalog, not the order the flags came in.
+        for table in &args.tables {
+            if !existing.contains(table) {
+                bail!("table {} does not exist", table);
+            }
+        }
+        existing.into_iter().filter(|t| args.tables.contains(t)).collect()
+    };
+    let total_tables = tables.len();
+
+    let mut report = MaintainReport {
+        success: true,
+        report_only: args.report_only,
+        cancelled: false,
+        concurrent_reindex_supported: concurrent_reindex,
+        total_bytes_before: 0,
+        total_bytes_after: 0,
+        bytes_reclaimed: 0,
+        tables: Vec::with_capacity(total_tables),
+        error: None,
+    };
+
+    // Report pass (0-10%).
+    for (index, table) in tables.iter().enumerate() {
+        let before = collect_stats(&pool, table, block_size).await?;
+        eprintln!(
+            "[DbMaintain] {}: {} live / {} dead tuples, {} bytes (est. bloat {} heap)",
+            table,
+            before.live_tuples,
+            before.dead_tuples,
+            before.total_bytes,
+            before
+                .estimated_bloat_bytes
+                .map(|b| b.to_string())
+                .unwrap_or_else(|| "unknown".to_string())
+        );
+        report.total_bytes_before += before.total_bytes;
+        write_progress(
+            progress_path,
+            reporter,
+            "running",
+            "signalr.dbMaintain.analyzing",
+            json!({ "tableName": table, "deadTuples": before.dead_tuples, "totalBytes": before.total_bytes }),
+            (index + 1) as f64 / total_tables.max(1) as f64 * 10.0,
+            0,
+            total_tables,
+            0,
+        )?;
+        let skipped = !args.report_only && before.worst_percent() < args.min_bloat_percent;
+        report.tables.push(TableReport {
+            name: table.clone(),
+            before,
+            after: None,
+            skipped,
+            vacuumed: false,
+            reindexed: false,
+            bytes_reclaimed: 0,
+            duration_seconds: 0.0,
+            error: None,
+        });
+    }
+
+    if args.report_only {
+        report.total_bytes_after = report.total_bytes_before;
+        return Ok(report);
+    }
+
+    // Maintenance pass (10-100%), one or two statements per table.
+    let steps_per_table = if reindex { 2 } else { 1 };
+    let total_steps = (total_tables * steps_per_table).max(1);
+    let mut steps_done = 0usize;
+    for index in 0..report.tables.len() {
+        if cancel::is_cancelled() {
+            report.cancelled = true;
+            break;
+        }
+        let table = report.tables[index].name.clone();
+        if report.tables[index].skipped {
+            steps_done += steps_per_table;
+            continue;
+        }
+        let started = Instant::now();
+
+        write_progress(
+            progress_path,
+            reporter,
+            "running",
+            "signalr.dbMaintain.vacuuming",
+            json!({ "tableName": table, "tableIndex": index + 1, "totalTables": total_tables }),
+            10.0 + steps_done as f64 / total_steps as f64 * 90.0,
+            index,
+            total_tables,
+            report.bytes_reclaimed,
+        )?;
+        match run_cancellable(&pool, &format!("VACUUM (ANALYZE) {}", quote_ident(&table))).await {
+            Ok(StatementOutcome::Completed) => report.tables[index].vacuumed = true,
+            Ok(StatementOutcome::Cancelled) => report.cancelled = true,
+            Err(e) => {
+                eprintln!("[DbMaintain] {}: {:#}", table, e);
+                report.tables[index].error = Some(format!("{e:#}"));
+            }
+        }
+        steps_done += 1;
+
+        if reindex && !report.cancelled && report.tables[index].error.is_none() {
+            write_progress(
+                progress_path,
+                reporter,
+                "running",
+                "signalr.dbMaintain.reindexing",
+                json!({ "tableName": table, "tableIndex": index + 1, "totalTables": total_tables }),
+                10.0 + steps_done as f64 / total_steps as f64 * 90.0,
+                index,
+                total_tables,
+                report.bytes_reclaimed,
+            )?;
+            match run_cancellable(&pool, &format!("REINDEX TABLE CONCURRENTLY {}", quote_ident(&table))).await {
+                Ok(StatementOutcome::Completed) => report.tables[index].reindexed = true,
+                Ok(StatementOutcome::Cancelled) => report.cancelled = true,
+                Err(e) => {
+                    eprintln!("[DbMaintain] {}: {:#}", table, e);
+                    report.tables[index].error = Some(format!("{e:#}"));
+                }
+            }
+            if !report.tables[index].reindexed {
+                drop_reindex_leftovers(&pool, &table).await?;
+            }
+        }
+        steps_done += steps_per_table - 1;
+
+        let after = collect_stats(&pool, &table, block_size).await?;
+        let entry = &mut report.tables[index];
+        entry.bytes_reclaimed = entry.before.total_bytes - after.total_bytes;
+        entry.duration_seconds = round1(started.elapsed().as_secs_f64());
+        entry.after = Some(after);
+        report.bytes_reclaimed += entry.bytes_reclaimed;
+        if entry.error.is_some() {
+            report.success = false;
+        }
+        if report.cancelled {
+            break;
+        }
+    }
+
+    report.total_bytes_after = report
+        .tables
+        .iter()
+        .map(|t| t.after.as_ref().unwrap_or(&t.before).total_bytes)
+        .sum();
+    Ok(report)
+}
+
+fn write_report(output_json: &str, report: &MaintainReport) -> Result<()> {
+    let payload =
+        serde_json::to_string_pretty(report).context("Failed to serialize maintenance report")?;
+    fs::write(output_json, payload)
+        .with_context(|| format!("Failed to write output JSON to {}", output_json))
+}
+
+async fn run(args: &Args, progress_path: Option<&Path>, reporter: &ProgressReporter) -> Result<()> {
+    write_progress(
+        progress_path,
+        reporter,
+        "starting",
+        "signalr.dbMaintain.starting",
+        json!({ "reportOnly": args.report_only, "reindex": !args.no_reindex }),
+        0.0,
+        0,
+        0,
+        0,
+    )?;
+
+    let report = match maintain(args, progress_path, reporter).await {
+        Ok(report) => report,
+        Err(e) => {
+            // Still write a failure report so the caller can parse it.
+            let failure = MaintainReport {
+                success: false,
+                report_only: args.report_only,
+                cancelled: false,
+                concurrent_reindex_supported: false,
+                total_bytes_before: 0,
+                total_bytes_after: 0,
+                bytes_reclaimed: 0,
+                tables: Vec::new(),
+                error: Some(format!("{e:#}")),
+            };
+            let _ = write_report(&args.output_json, &failure);
+            return Err(e);
+        }
+    };
+    write_report(&args.output_json, &report)?;
+
+    let maintained = report.tables.iter().filter(|t| t.after.is_some()).count();
+    let context = json!({
+        "reportOnly": report.report_only,
+        "tablesMaintained": maintained,
+        "totalTables": report.tables.len(),
+        "totalBytesBefore": report.total_bytes_before,
+        "totalBytesAfter": report.total_bytes_after,
+        "bytesReclaimed": report.bytes_reclaimed,
+        "failedTables": report.tables.iter().filter(|t| t.error.is_some()).count(),
+    });
+    if report.cancelled {
+        eprintln!("[DbMaintain] Cancelled after {} table(s)", maintained);
+        return write_progress(
+            progress_path,
+            reporter,
+            "cancelled",
+            "signalr.dbMaintain.cancelled",
+            context,
+            0.0,
+            maintained,
+            report.tables.len(),
+            report.bytes_reclaimed,
+        );
+    }
+
+    eprintln!(
+        "[DbMaintain] {} table(s) maintained, {} bytes reclaimed",
+        maintained, report.bytes_reclaimed
+    );
+    write_progress(
+        progress_path,
+        reporter,
+        "completed",
+        "signalr.dbMaintain.complete",
+        context,
+        100.0,
+        maintained,
+        report.tables.len(),
+        report.bytes_reclaimed,
+    )
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    cancel::install();
+    let args = Args::parse();
+    let reporter = ProgressReporter::new(args.progress);
+
+    let progress_path = match args.progress_json.as_deref() {
+        Some("none") | None => None,
+        Some(p) => Some(PathBuf::from(p)),
+    };
+
+    let result = run(&args, progress_path.as_deref(), &reporter).await;
+    progress_events::finish_or_exit(&reporter, "signalr.dbMaintain.error.fatal", result);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn an_empty_table_expects_no_heap_and_one_btree_leaf() {
+        assert_eq!(expected_heap_bytes(0.0, 120.0, 8192.0), 0);
+        assert_eq!(expected_btree_bytes(0.0, 8.0, 8192.0), 2 * 8192);
+    }
+
+    #[test]
+    fn heap_estimate_packs_whole_tuples_per_page() {
+        // 24-byte header + 100 bytes of data aligns to 128, plus a 4-byte line pointer:
+        // 61 tuples fit in the 8168 usable bytes of a page.
+        assert_eq!(expected_heap_bytes(61.0, 100.0, 8192.0), 8192);
+        assert_eq!(expected_heap_bytes(62.0, 100.0, 8192.0), 2 * 8192);
+    }
+
+    #[test]
+    fn bloat_is_the_excess_over_the_estimate() {
+        assert_eq!(bloat(10 * 8192, Some(4 * 8192)), (Some(6 * 8192), Some(60.0)));
+        assert_eq!(bloat(4 * 8192, Some(10 * 8192)), (Some(0), Some(0.0)));
+        assert_eq!(bloat(8192, None), (None, None));
+    }
+
+    #[test]
+    fn the_worst_share_decides_whether_a_table_is_maintained() {
+        let stats = TableStats {
+            live_tuples: 90,
+            dead_tuples: 10,
+            dead_tuple_percent: 10.0,
+            table_bytes: 0,
+            index_bytes: 0,
+            total_bytes: 0,
+            estimated_bloat_bytes: None,
+            estimated_bloat_percent: Some(5.0),
+            last_vacuum: None,
+            last_autovacuum: None,
+            last_analyze: None,
+            last_autoanalyze: None,
+            indexes: vec![IndexStats {
+                name: "IX_LogEntries_Timestamp".to_string(),
+                bytes: 0,
+                scans: 0,
+                valid: true,
+                estimated_bloat_bytes: None,
+                estimated_bloat_percent: Some(42.0),
+            }],
+        };
+
+        assert_eq!(stats.worst_percent(), 42.0);
+    }
+
+    #[test]
+    fn identifiers_are_quoted() {
+        assert_eq!(quote_ident("LogEntries"), r#""LogEntries""#);
+        assert_eq!(quote_ident(r#"we"ird"#), r#""we""ird""#);
+    }
+}
@@ -0,0 +1,1130 @@
+// Regenerate the derived tables (`Downloads`, `ClientStats`, `ServiceStats`) from `LogEntries`.
+//
+// After partial purges, corruption removals or manual SQL fixes the derived tables drift from
+// the log entries they summarise. This binary replays the scoped `LogEntries` in timestamp
+// order through the same session rules ingest uses (`download_sessions::session_key` plus a
+// `SessionTracker` with the ingest gap), writes one fresh `Downloads` row per session, repoints
+// the entries at it and recomputes the stats rows of every client and service it touched.
+//
+// The scope (datasource and/or `--from`/`--to`) is widened first so it never cuts through an
+// existing session: any download with an entry inside the window is rebuilt whole. Everything
+// happens in ONE transaction, so a cancel or failure leaves the database exactly as it was.
+//
+// What the log cannot provide is carried over from the download(s) a session replaces: event
+// tags, IsEvicted, EpicAppId, GameImageUrl, the Riot game (LogEntries do not store the CDN host)
+// and any name the resolvers here cannot produce.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use clap::Parser;
+use serde::Serialize;
+use serde_json::json;
+use sqlx::{Postgres, Row, Transaction};
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use lancache_processor::cancel;
+use lancache_processor::db;
+use lancache_processor::db_scope::{self, LogEntryScope};
+use lancache_processor::db_stats;
+use lancache_processor::download_sessions;
+use lancache_processor::progress_events;
+use lancache_processor::progress_utils;
+use lancache_processor::session::SessionTracker;
+use lancache_processor::tact_products;
+use progress_events::ProgressReporter;
+
+/// Rows pulled from the scan cursor per round trip.
+const FETCH_BATCH: i64 = 5000;
+
+/// Upper bound on scope-widening passes. Each pass can only pull in sessions that straddle the
+/// previous bounds, so real data settles in two or three.
+const MAX_WIDEN_PASSES: usize = 16;
+
+/// The per-URL Xbox match cache is dropped once it holds this many URLs.
+const XBOX_URL_CACHE_LIMIT: usize = 100_000;
+
+/// Regenerate Downloads sessions, ClientStats and ServiceStats from LogEntries.
+#[derive(clap::Parser, Debug)]
+#[command(name = "db_rebuild")]
+#[command(about = "Regenerates Downloads, ClientStats and ServiceStats from LogEntries")]
+struct Args {
+    /// Path to progress JSON file (use "none" to skip)
+    #[arg(default_value = "none")]
+    progress_json: Option<String>,
+
+    /// Only rebuild rows of this datasource
+    #[arg(long)]
+    datasource: Option<String>,
+
+    /// Start of the window to rebuild, RFC 3339 (inclusive)
+    #[arg(long)]
+    from: Option<DateTime<Utc>>,
+
+    /// End of the window to rebuild, RFC 3339 (exclusive)
+    #[arg(long)]
+    to: Option<DateTime<Utc>>,
+
+    /// Emit JSON progress events to stdout
+    #[arg(short, long)]
+    progress: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProgressData {
+    status: String,
+    stage_key: String,
+    context: serde_json::Value,
+    percent_complete: f64,
+    entries_processed: u64,
+    total_entries: u64,
+    downloads_removed: u64,
+    downloads_created: u64,
+    timestamp: String,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct RebuildCounters {
+    entries_processed: u64,
+    total_entries: u64,
+    downloads_removed: u64,
+    downloads_created: u64,
+}
+
+impl RebuildCounters {
+    fn context(&self) -> serde_json::Value {
+        json!({
+            "entriesProcessed": self.entries_processed,
+            "totalEntries": self.total_entries,
+            "downloadsRemoved": self.downloads_removed,
+            "downloadsCreated": self.downloads_created,
+        })
+    }
+}
+
+/// Writes the progress file (when a path was supplied) and THEN emits the matching stdout event.
+fn write_progress(
+    progress_path: Option<&Path>,
+    reporter: &ProgressReporter,
+    status: &str,
+    stage_key: &str,
+    context: serde_json::Value,
+    percent_complete: f64,
+    counters: &RebuildCounters,
+) -> Result<()> {
+    if let Some(path) = progress_path {
+        let progress = ProgressData {
+            status: status.to_string(),
+            stage_key: stage_key.to_string(),
+            context: context.clone(),
+            percent_complete,
+            entries_processed: counters.entries_processed,
+            total_entries: counters.total_entries,
+            downloads_removed: counters.downloads_removed,
+            downloads_created: counters.downloads_created,
+            timestamp: progress_utils::current_timestamp(),
+        };
+        progress_utils::write_progress_json(path, &progress)?;
+    }
+
+    match status {
+        "starting" => reporter.emit_started(stage_key, context),
+        "completed" => reporter.emit_complete(stage_key, context),
+        "cancelled" => reporter.emit_cancelled(stage_key, context),
+        "failed" => {
+            let error_detail = context
+                .get("errorDetail")
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_string());
+            reporter.emit_failed(stage_key, context, error_detail);
+        }
+        _ => reporter.emit_progress(percent_complete, stage_key, context),
+    }
+
+    Ok(())
+}
+
+/// One scoped `LogEntries` row as read from the scan cursor. `download_id` is the session the
+/// entry belonged to before the rebuild.
+#[derive(Debug, Clone)]
+struct ScopedEntry {
+    id: i64,
+    timestamp: DateTime<Utc>,
+    client_ip: String,
+    service: String,
+    url: String,
+    cache_status: String,
+    bytes_served: i64,
+    depot_id: Option<i64>,
+    download_id: Option<i64>,
+    datasource: String,
+}
+
+/// The parts of a replaced `Downloads` row that the log cannot reproduce.
+#[derive(Debug, Clone)]
+struct PriorDownload {
+    service: String,
+    client_ip: String,
+    game_app_id: Option<i64>,
+    game_name: Option<String>,
+    game_image_url: Option<String>,
+    epic_app_id: Option<String>,
+    xbox_product_id: Option<String>,
+    is_active: bool,
+    is_evicted: bool,
+}
+
+/// Per-entry facts that feed the session key but are not columns of `LogEntries`.
+#[derive(Debug, Clone, Default)]
+struct EntryContext {
+    tact_product: Option<String>,
+    riot_game: Option<String>,
+    /// Resolved Xbox (title, product id).
+    xbox: Option<(String, String)>,
+}
+
+/// A regenerated session, accumulated entry by entry.
+#[derive(Debug, Clone)]
+struct RebuiltSession {
+    key: String,
+    datasource: String,
+    client_ip: String,
+    service: String,
+    xbox: Option<(String, String)>,
+    riot_game: Option<String>,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    hit_bytes: i64,
+    miss_bytes: i64,
+    depots: HashMap<u32, u64>,
+    tact_products: HashMap<String, u64>,
+    last_url: String,
+    entry_ids: Vec<i64>,
+    priors: HashMap<i64, u64>,
+}
+
+impl RebuiltSession {
+    fn open(key: String, entry: &ScopedEntry, context: &EntryContext) -> Self {
+        Self {
+            key,
+            datasource: entry.datasource.clone(),
+            client_ip: entry.client_ip.clone(),
+            service: entry.service.clone(),
+            xbox: context.xbox.clone(),
+            riot_game: context.riot_game.clone(),
+            start: entry.timestamp,
+            end: entry.timestamp,
+            hit_bytes: 0,
+            miss_bytes: 0,
+            depots: HashMap::new(),
+            tact_products: HashMap::new(),
+            last_url: String::new(),
+            entry_ids: Vec::new(),
+            priors: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, entry: &ScopedEntry, context: &EntryContext) {
+        self.end = self.end.max(entry.timestamp);
+        match entry.cache_status.as_str() {
+            "HIT" => self.hit_bytes += entry.bytes_served,
+            "MISS" => self.miss_bytes += entry.bytes_served,
+            _ => {}
+        }
+        if let Some(depot) = entry.depot_id.and_then(|d| u32::try_from(d).ok()) {
+            *self.depots.entry(depot).or_insert(0) += 1;
+        }
+        if let Some(product) = &context.tact_product {
+            *self.tact_products.entry(product.clone()).or_insert(0) += 1;
+        }
+        if let Some(prior) = entry.download_id {
+            *self.priors.entry(prior).or_insert(0) += 1;
+        }
+        self.last_url = entry.url.clone();
+        self.entry_ids.push(entry.id);
+    }
+
+    /// Identity service of the `Downloads` row: matched Xbox traffic is `xbox`, exactly as
+    /// ingest canonicalizes it.
+    fn download_service(&self) -> &str {
+        if self.xbox.is_some() {
+            "xbox"
+        } else {
+            &self.service
+        }
+    }
+
+    /// Most common depot; ties go to the lower id so the pick is deterministic.
+    fn primary_depot(&self) -> Option<u32> {
+        most_common(self.depots.iter().map(|(depot, count)| (*depot, *count)))
+    }
+
+    fn primary_tact_product(&self) -> Option<&str> {
+        most_common(self.tact_products.iter().map(|(p, count)| (p.as_str(), *count)))
+    }
+
+    /// The replaced download that contributed the most entries.
+    fn majority_prior(&self) -> Option<i64> {
+        most_common(self.priors.iter().map(|(id, count)| (*id, *count)))
+    }
+}
+
+fn most_common<K: Ord + Copy>(counts: impl Iterator<Item = (K, u64)>) -> Option<K> {
+    counts
+        .max_by(|(ka, ca), (kb, cb)| ca.cmp(cb).then_with(|| kb.cmp(ka)))
+        .map(|(key, _)| key)
+}
+
+/// Splits the ordered entry stream into sessions with the ingest gap rule.
+struct SessionAssembler {
+    tracker: SessionTracker,
+    open: HashMap<String, RebuiltSession>,
+}
+
+impl SessionAssembler {
+    fn new() -> Self {
+        let gap_minutes = download_sessions::SESSION_GAP_MINUTES;
+        Self {
+            tracker: SessionTracker::new(Duration::from_secs(gap_minutes as u64 * 60)),
+            open: HashMap::new(),
+        }
+    }
+
+    /// Adds one entry (entries must arrive in timestamp order) and returns the session it
+    /// closed, if the gap on its key had expired.
+    fn push(
+        &mut self,
+        key: String,
+        entry: &ScopedEntry,
+        context: &EntryContext,
+    ) -> Option<RebuiltSession> {
+        let timestamp = entry.timestamp.naive_utc();
+        let mut closed = None;
+        if self.tracker.should_create_new_session(&key, timestamp) {
+            closed = self.open.remove(&key);
+        }
+        self.tracker.update_session(&key, timestamp);
+        self.open
+            .entry(key.clone())
+            .or_insert_with(|| RebuiltSession::open(key, entry, context))
+            .add(entry, context);
+        closed
+    }
+
+    /// Closes every session whose key has been idle for longer than the gap at `now`, by the
+    /// tracker's whole-second rule, so a later entry on the key could not have joined it.
+    fn drain_idle(&mut self, now: DateTime<Utc>) -> Vec<RebuiltSession> {
+        let now = now.naive_utc();
+        let idle: Vec<String> = self
+            .open
+            .keys()
+            .filter(|key| self.tracker.should_create_new_session(key, now))
+            .cloned()
+            .collect();
+        let mut closed: Vec<RebuiltSession> =
+            idle.iter().filter_map(|key| self.open.remove(key)).collect();
+        sort_sessions(&mut closed);
+        closed
+    }
+
+    fn finish(self) -> Vec<RebuiltSession> {
+        let mut closed: Vec<RebuiltSession> = self.open.into_values().collect();
+        sort_sessions(&mut closed);
+        closed
+    }
+}
+
+fn sort_sessions(sessions: &mut [RebuiltSession]) {
+    sessions.sort_by(|a, b| a.start.cmp(&b.start).then_with(|| a.key.cmp(&b.key)));
+}
+
+/// Game identity resolved for a session, before falling back to the replaced download.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct GameIdentity {
+    app_id: Option<i64>,
+    name: Option<String>,
+}
+
+/// Name resolvers with per-run memos. Mirrors the ingest naming: Steam via the owner depot
+/// mapping, Blizzard via the TACT segment, Xbox via the CDN fragment, Riot via the game the
+/// replaced download already carried.
+#[derive(Default)]
+struct GameNames {
+    depots: HashMap<u32, Option<(i64, Option<String>)>>,
+    xbox_patterns: Vec<(String, String, String)>,
+    xbox_urls: HashMap<String, Option<(String, String)>>,
+}
+
+impl GameNames {
+    async fn load_xbox_patterns(&mut self, tx: &mut Transaction<'_, Postgres>) -> Result<()> {
+        let rows = sqlx::query(download_sessions::XBOX_PATTERNS_SQL)
+            .fetch_all(&mut **tx)
+            .await
+            .context("failed to load Xbox CDN patterns")?;
+        self.xbox_patterns = rows
+            .iter()
+            .filter_map(|row| {
+                download_sessions::xbox_pattern_row(
+                    row.try_get("UrlFragment").ok(),
+                    row.try_get("Title").ok(),
+                    row.try_get("ProductId").ok(),
+                )
+            })
+            .collect();
+        Ok(())
+    }
+
+    fn xbox_game(&mut self, service: &str, url: &str) -> Option<(String, String)> {
+        if self.xbox_patterns.is_empty() || !download_sessions::is_xbox_cache_service(service) {
+            return None;
+        }
+        if let Some(hit) = self.xbox_urls.get(url) {
+            return hit.clone();
+        }
+        let matched = download_sessions::match_xbox_fragment(&self.xbox_patterns, url)
+            .map(|(_, title, product_id)| (title.clone(), product_id.clone()));
+        if self.xbox_urls.len() >= XBOX_URL_CACHE_LIMIT {
+            self.xbox_urls.clear();
+        }
+        self.xbox_urls.insert(url.to_string(), matched.clone());
+        matched
+    }
+
+    async fn depot(
+        &mut self,
+        tx: &mut Transaction<'_, Postgres>,
+        depot_id: u32,
+    ) -> Result<Option<(i64, Option<String>)>> {
+        if let Some(mapped) = self.depots.get(&depot_id) {
+            return Ok(mapped.clone());
+        }
+        let row = sqlx::query(
+            r#"SELECT "AppId", "AppName" FROM "SteamDepotMappings" WHERE "DepotId" = $1 AND "IsOwner" = true ORDER BY "AppId" LIMIT 1"#,
+        )
+        .bind(depot_id as i64)
+        .fetch_optional(&mut **tx)
+        .await
+        .with_context(|| format!("failed to look up depot {}", depot_id))?;
+        let mapped = row.map(|r| (r.get::<i64, _>("AppId"), r.get::<Option<String>, _>("AppName")));
+        self.depots.insert(depot_id, mapped.clone());
+        Ok(mapped)
+    }
+
+    async fn resolve(
+        &mut self,
+        tx: &mut Transaction<'_, Postgres>,
+        session: &RebuiltSession,
+    ) -> Result<GameIdentity> {
+        if let Some((title, _)) = &session.xbox {
+            return Ok(GameIdentity {
+                app_id: None,
+                name: Some(title.clone()),
+            });
+        }
+        let identity = match session.service.to_lowercase().as_str() {
+            "steam" => match session.primary_depot() {
+                Some(depot) => match self.depot(tx, depot).await? {
+                    Some((app_id, name)) => GameIdentity {
+                        app_id: Some(app_id),
+                        name,
+                    },
+                    None => GameIdentity::default(),
+                },
+                None => GameIdentity::default(),
+            },
+            "blizzard" => GameIdentity {
+                app_id: None,
+                name: session.primary_tact_product().and_then(|product| {
+                    match tact_products::resolve_tact_segment(product) {
+                        tact_products::TactResolution::Game(name) => Some(name),
+                        tact_products::TactResolution::Shared(label) => Some(label),
+                        tact_products::TactResolution::Unknown => None,
+                    }
+                }),
+            },
+            "riot" => GameIdentity {
+                app_id: None,
+                name: session.riot_game.clone(),
+            },
+            _ => GameIdentity::default(),
+        };
+        Ok(identity)
+    }
+}
+
+/// Widen the window until no download with an entry inside it has entries outside it.
+async fn widen_scope(
+    tx: &mut Transaction<'_, Postgres>,
+    scope: &LogEntryScope,
+) -> Result<LogEntryScope> {
+    let mut widened = scope.clone();
+    if widened.from.is_none() && widened.to.is_none() {
+        return Ok(widened);
+    }
+
+    let sql = format!(
+        r#"SELECT MIN(x."Timestamp"), MAX(x."Timestamp")
+             FROM "LogEntries" x
+            WHERE x."DownloadId" IN (
+                SELECT DISTINCT le."DownloadId" FROM "LogEntries" le
+                 WHERE le."DownloadId" IS NOT NULL AND {}
+            )"#,
+        db_scope::LOG_ENTRY_PREDICATE
+    );
+    for _ in 0..MAX_WIDEN_PASSES {
+        let (min, max): (Option<DateTime<Utc>>, Option<DateTime<Utc>>) =
+            widened.bind(sqlx::query(&sql)).fetch_one(&mut **tx).await.map(|row| {
+                (row.get(0), row.get(1))
+            })
+            .context("failed to measure the sessions inside the window")?;
+        let (Some(min), Some(max)) = (min, max) else {
+            break;
+        };
+        let next = LogEntryScope {
+            from: widened.from.map(|from| from.min(min)),
+            to: widened
+                .to
+                .map(|to| to.max(max + ChronoDuration::microseconds(1))),
+            ..widened.clone()
+        };
+        if next == widened {
+            break;
+        }
+        widened = next;
+    }
+    Ok(widened)
+}
+
+/// Downloads the rebuild replaces: every session with an entry in scope, plus sessions in scope
+/// that no longer have any entries at all.
+async fn load_priors(
+    tx: &mut Transaction<'_, Postgres>,
+    scope: &LogEntryScope,
+) -> Result<HashMap<i64, PriorDownload>> {
+    let sql = format!(
+        r#"SELECT d."Id", d."Service", d."ClientIp", d."GameAppId", d."GameName", d."GameImageUrl",
+                  d."EpicAppId", d."XboxProductId", d."IsActive", d."IsEvicted"
+             FROM "Downloads" d
+            WHERE d."Id" IN (
+                      SELECT DISTINCT le."DownloadId" FROM "LogEntries" le
+                       WHERE le."DownloadId" IS NOT NULL AND {entries}
+                  )
+               OR ({downloads}
+                   AND NOT EXISTS (SELECT 1 FROM "LogEntries" x WHERE x."DownloadId" = d."Id"))"#,
+        entries = db_scope::LOG_ENTRY_PREDICATE,
+        downloads = db_scope::DOWNLOAD_PREDICATE,
+    );
+    let rows = scope
+        .bind(sqlx::query(&sql))
+        .fetch_all(&mut **tx)
+        .await
+        .context("failed to load the downloads being rebuilt")?;
+
+    Ok(rows
+        .iter()
+        .map(|row| {
+            (
+                row.get::<i64, _>("Id"),
+                PriorDownload {
+                    service: row.get("Service"),
+                    client_ip: row.get("ClientIp"),
+                    game_app_id: row.get("GameAppId"),
+                    game_name: row.get("GameName"),
+                    game_image_url: row.get("GameImageUrl"),
+                    epic_app_id: row.get("EpicAppId"),
+                    xbox_product_id: row.get("XboxProductId"),
+                    is_active: row.get("IsActive"),
+                    is_evicted: row.get("IsEvicted"),
+                },
+            )
+        })
+        .collect())
+}
+
+fn entry_context(
+    entry: &ScopedEntry,
+    priors: &HashMap<i64, PriorDownload>,
+    names: &mut GameNames,
+) -> EntryContext {
+    let service = entry.service.to_lowercase();
+    let tact_product = if service == "blizzard" {
+        tact_products::extract_tact_product(&entry.url)
+    } else {
+        None
+    };
+    let riot_game = if service == "riot" {
+        entry
+            .download_id
+            .and_then(|id| priors.get(&id))
+            .filter(|prior| prior.service.eq_ignore_ascii_case("riot"))
+            .and_then(|prior| prior.game_name.clone())
+    } else {
+        None
+    };
+    EntryContext {
+        tact_product,
+        riot_game,
+        xbox: names.xbox_game(&entry.service, &entry.url),
+    }
+}
+
+/// Sessions never merge across datasources: ingest runs one datasource at a time.
+fn entry_key(entry: &ScopedEntry, context: &EntryContext) -> String {
+    let key = download_sessions::session_key(&download_sessions::SessionFields {
+        client_ip: &entry.client_ip,
+        service: &entry.service,
+        url: &entry.url,
+        depot_id: entry.depot_id.and_then(|d| u32::try_from(d).ok()),
+        tact_product: context.tact_product.as_deref(),
+        cdn_host: None,
+        riot_game: context.riot_game.as_deref(),
+        xbox_title: context.xbox.as_ref().map(|(title, _)| title.as_str()),
+    });
+    format!("{}|{}", entry.datasource, key)
+}
+
+/// Newest rebuilt download per (client, download service), for restoring `IsActive`.
+type NewestSessions = HashMap<(String, String), (DateTime<Utc>, i64)>;
+
+async fn write_session(
+    tx: &mut Transaction<'_, Postgres>,
+    session: &RebuiltSession,
+    priors: &HashMap<i64, PriorDownload>,
+    names: &mut GameNames,
+    newest: &mut NewestSessions,
+) -> Result<i64> {
+    let download_service = session.download_service().to_string();
+    let prior = session
+        .majority_prior()
+        .and_then(|id| priors.get(&id))
+        .filter(|prior| prior.service == download_service);
+
+    let mut identity = names.resolve(tx, session).await?;
+    let resolved = identity.app_id.is_some() || identity.name.is_some();
+    if let Some(prior) = prior {
+        if !resolved {
+            identity = GameIdentity {
+                app_id: prior.game_app_id,
+                name: prior.game_name.clone(),
+            };
+        }
+    }
+    // The image belongs to the game, so it only carries over when the game did.
+    let game_image_url = prior
+        .filter(|p| p.game_app_id == identity.app_id && p.game_name == identity.name)
+        .and_then(|p| p.game_image_url.clone());
+    let xbox_product_id = session
+        .xbox
+        .as_ref()
+        .map(|(_, product_id)| product_id.clone())
+        .or_else(|| prior.and_then(|p| p.xbox_product_id.clone()));
+
+    let download_id: i64 = sqlx::query_scalar(
+        r#"INSERT INTO "Downloads" ("Service", "ClientIp", "StartTimeUtc", "EndTimeUtc", "CacheHitBytes", "CacheMissBytes", "IsActive", "IsEvicted", "LastUrl", "DepotId", "GameAppId", "GameName", "GameImageUrl", "EpicAppId", "XboxProductId", "Datasource")
+           VALUES ($1, $2, $3, $4, $5, $6, false, $7, $8, $9, $10, $11, $12, $13, $14, $15)
+           RETURNING "Id""#,
+    )
+    .bind(&download_service)
+    .bind(&session.client_ip)
+    .bind(session.start)
+    .bind(session.end)
+    .bind(session.hit_bytes)
+    .bind(session.miss_bytes)
+    .bind(prior.map(|p| p.is_evicted).unwrap_or(false))
+    .bind(&session.last_url)
+    .bind(session.primary_depot().map(i64::from))
+    .bind(identity.app_id)
+    .bind(&identity.name)
+    .bind(game_image_url)
+    .bind(prior.and_then(|p| p.epic_app_id.clone()))
+    .bind(xbox_product_id)
+    .bind(&session.datasource)
+    .fetch_one(&mut **tx)
+    .await
+    .context("failed to insert rebuilt download")?;
+
+    sqlx::query(r#"UPDATE "LogEntries" SET "DownloadId" = $1 WHERE "Id" = ANY($2)"#)
+        .bind(download_id)
+        .bind(&session.entry_ids)
+        .execute(&mut **tx)
+        .await
+        .context("failed to repoint log entries at the rebuilt download")?;
+
+    let prior_ids: Vec<i64> = session.priors.keys().copied().collect();
+    if !prior_ids.is_empty() {
+        sqlx::query(
+            r#"INSERT INTO "EventDownloads" ("EventId", "DownloadId", "TaggedAtUtc", "AutoTagged")
+               SELECT ed."EventId", $1, MIN(ed."TaggedAtUtc"), BOOL_AND(ed."AutoTagged")
+                 FROM "EventDownloads" ed
+                WHERE ed."DownloadId" = ANY($2)
+                GROUP BY ed."EventId"
+               ON CONFLICT ("EventId", "DownloadId") DO NOTHING"#,
+        )
+        .bind(download_id)
+        .bind(&prior_ids)
+        .execute(&mut **tx)
+        .await
+        .context("failed to carry event tags over to the rebuilt download")?;
+    }
+
+    let slot = newest
+        .entry((session.client_ip.clone(), download_service))
+        .or_insert((session.start, download_id));
+    if session.start >= slot.0 {
+        *slot = (session.start, download_id);
+    }
+
+    Ok(download_id)
+}
+
+/// Ingest keeps at most one active session per client and identity service: the newest one.
+/// Restore that for every pair whose replaced downloads included an active one.
+async fn restore_active_sessions(
+    tx: &mut Transaction<'_, Postgres>,
+    priors: &HashMap<i64, PriorDownload>,
+    newest: &NewestSessions,
+) -> Result<()> {
+    let mut active_ids: Vec<i64> = priors
+        .values()
+        .filter(|prior| prior.is_active)
+        .filter_map(|prior| newest.get(&(prior.client_ip.clone(), prior.service.clone())))
+        .map(|(_, id)| *id)
+        .collect();
+    active_ids.sort_unstable();
+    active_ids.dedup();
+    if active_ids.is_empty() {
+        return Ok(());
+    }
+
+    sqlx::query(r#"UPDATE "Downloads" SET "IsActive" = true WHERE "Id" = ANY($1)"#)
+        .bind(&active_ids)
+        .execute(&mut **tx)
+        .await
+        .context("failed to restore active sessions")?;
+    Ok(())
+}
+
+enum RebuildOutcome {
+    Completed(serde_json::Value),
+    Cancelled,
+}
+
+async fn rebuild(
+    scope: &LogEntryScope,
+    progress_path: Option<&Path>,
+    reporter: &ProgressReporter,
+    counters: &mut RebuildCounters,
+) -> Result<RebuildOutcome> {
+    let pool = db::create_pool().await?;
+    let mut tx = pool.begin().await.context("failed to begin rebuild transaction")?;
+
+    let scope = widen_scope(&mut tx, scope).await?;
+    counters.total_entries = scope
+        .bind_scalar(sqlx::query_scalar::<_, i64>(&format!(
+            r#"SELECT COUNT(*) FROM "LogEntries" le WHERE {}"#,
+            db_scope::LOG_ENTRY_PREDICATE
+        )))
+        .fetch_one(&mut *tx)
+        .await
+        .context("failed to count log entries in scope")? as u64;
+
+    let priors = load_priors(&mut tx, &scope).await?;
+    let mut names = GameNames::default();
+    names.load_xbox_patterns(&mut tx).await?;
+
+    eprintln!(
+        "[DbRebuild] Scope {}: {} log entries, {} downloads to replace",
+        scope.describe(),
+        counters.total_entries,
+        priors.len()
+    );
+    write_progress(
+        progress_path,
+        reporter,
+        "running",
+        "signalr.dbRebuild.scanning",
+        json!({ "scope": scope.describe(), "downloadsToReplace": priors.len() }),
+        5.0,
+        counters,
+    )?;
+
+    // The cursor reads the snapshot taken here, so it keeps seeing each entry's previous
+    // DownloadId while the loop below repoints the rows.
+    scope
+        .bind(sqlx::query(&format!(
+            r#"DECLARE db_rebuild_entries NO SCROLL CURSOR FOR
+               SELECT le."Id", le."Timestamp", le."ClientIp", le."Service", le."Url", le."CacheStatus",
+                      le."BytesServed", le."DepotId", le."DownloadId", le."Datasource"
+                 FROM "LogEntries" le
+                WHERE {}
+                ORDER BY le."Timestamp", le."Id""#,
+            db_scope::LOG_ENTRY_PREDICATE
+        )))
+        .execute(&mut *tx)
+        .await
+        .context("failed to open the log entry cursor")?;
+
+    let mut assembler = SessionAssembler::new();
+    let mut newest = NewestSessions::new();
+    let mut clients: BTreeSet<String> = BTreeSet::new();
+    let mut services: BTreeSet<String> = BTreeSet::new();
+
+    loop {
+        if cancel::is_cancelled() {
+            tx.rollback().await.ok();
+            return Ok(RebuildOutcome::Cancelled);
+        }
+
+        let rows = sqlx::query(&format!("FETCH FORWARD {} FROM db_rebuild_entries", FETCH_BATCH))
+            .fetch_all(&mut *tx)
+            .await
+            .context("failed to read log entries")?;
+        if rows.is_empty() {
+            break;
+        }
+
+        let mut closed = Vec::new();
+        let mut batch_end = None;
+        for row in &rows {
+            let entry = ScopedEntry {
+                id: row.get("Id"),
+                timestamp: row.get("Timestamp"),
+                client_ip: row.get("ClientIp"),
+                service: row.get("Service"),
+                url: row.get("Url"),
+                cache_status: row.get("CacheStatus"),
+                bytes_served: row.get("BytesServed"),
+                depot_id: row.get("DepotId"),
+                download_id: row.get("DownloadId"),
+                datasource: row.get("Datasource"),
+            };
+            if !clients.contains(&entry.client_ip) {
+                clients.insert(entry.client_ip.clone());
+            }
+            if !services.contains(&entry.service) {
+                services.insert(entry.service.clone());
+            }
+            let context = entry_context(&entry, &priors, &mut names);
+            let key = entry_key(&entry, &context);
+            closed.extend(assembler.push(key, &entry, &context));
+            batch_end = Some(entry.timestamp);
+        }
+        if let Some(now) = batch_end {
+            closed.extend(assembler.drain_idle(now));
+        }
+
+        for session in &closed {
+            write_session(&mut tx, session, &priors, &mut names, &mut newest).await?;
+        }
+        counters.entries_processed += rows.len() as u64;
+        counters.downloads_created += closed.len() as u64;
+
+        let fraction = if counters.total_entries > 0 {
+            counters.entries_processed as f64 / counters.total_entries as f64
+        } else {
+            1.0
+        };
+        write_progress(
+            progress_path,
+            reporter,
+            "running",
+            "signalr.dbRebuild.rebuilding",
+            counters.context(),
+            5.0 + fraction.min(1.0) * 85.0,
+            counters,
+        )?;
+    }
+
+    let remaining = assembler.finish();
+    for session in &remaining {
+        write_session(&mut tx, session, &priors, &mut names, &mut newest).await?;
+    }
+    counters.downloads_created += remaining.len() as u64;
+
+    sqlx::query("CLOSE db_rebuild_entries")
+        .execute(&mut *tx)
+        .await
+        .context("failed to close the log entry cursor")?;
+
+    restore_active_sessions(&mut tx, &priors, &newest).await?;
+
+    // A prior still referenced by an entry outside the (widened) scope stays put rather than
+    // orphaning that entry; it only happens when widening hit its pass limit.
+    let prior_ids: Vec<i64> = priors.keys().copied().collect();
+    counters.downloads_removed = sqlx::query(
+        r#"DELETE FROM "Downloads" d
+            WHERE d."Id" = ANY($1)
+              AND NOT EXISTS (SELECT 1 FROM "LogEntries" x WHERE x."DownloadId" = d."Id")"#,
+    )
+    .bind(&prior_ids)
+    .execute(&mut *tx)
+    .await
+    .context("failed to delete replaced downloads")?
+    .rows_affected();
+    let downloads_kept = prior_ids.len() as u64 - counters.downloads_removed;
+
+    if cancel::is_cancelled() {
+        tx.rollback().await.ok();
+        return Ok(RebuildOutcome::Cancelled);
+    }
+
+    write_progress(
+        progress_path,
+        reporter,
+        "running",
+        "signalr.dbRebuild.stats",
+        counters.context(),
+        90.0,
+        counters,
+    )?;
+    for prior in priors.values() {
+        clients.insert(prior.client_ip.clone());
+    }
+    let clients: Vec<String> = clients.into_iter().collect();
+    let services: Vec<String> = services.into_iter().collect();
+    let client_stats = db_stats::recompute_client_stats(&mut tx, &clients).await?;
+    let service_stats = db_stats::recompute_service_stats(&mut tx, &services).await?;
+
+    tx.commit().await.context("failed to commit rebuild")?;
+
+    let mut summary = counters.context();
+    summary["scope"] = scope.describe();
+    summary["downloadsKept"] = json!(downloads_kept);
+    summary["clientStatsUpserted"] = json!(client_stats.upserted);
+    summary["clientStatsDeleted"] = json!(client_stats.deleted);
+    summary["serviceStatsUpserted"] = json!(service_stats.upserted);
+    summary["serviceStatsDeleted"] = json!(service_stats.deleted);
+    Ok(RebuildOutcome::Completed(summary))
+}
+
+async fn run(
+    args: &Args,
+    progress_path: Option<&Path>,
+    reporter: &ProgressReporter,
+) -> Result<()> {
+    let mut counters = RebuildCounters::default();
+    let scope = LogEntryScope::new(args.datasource.clone(), args.from, args.to)?;
+
+    write_progress(
+        progress_path,
+        reporter,
+        "starting",
+        "signalr.dbRebuild.starting",
+        json!({ "scope": scope.describe() }),
+        0.0,
+        &counters,
+    )?;
+
+    match rebuild(&scope, progress_path, reporter, &mut counters).await {
+        Ok(RebuildOutcome::Completed(summary)) => {
+            eprintln!(
+                "[DbRebuild] Rebuilt {} downloads from {} log entries (replaced {})",
+                counters.downloads_created, counters.entries_processed, counters.downloads_removed
+            );
+            write_progress(
+                progress_path,
+                reporter,
+                "completed",
+                "signalr.dbRebuild.complete",
+                summary,
+                100.0,
+                &counters,
+            )
+        }
+        Ok(RebuildOutcome::Cancelled) => {
+            eprintln!("[DbRebuild] Cancelled - transaction rolled back, nothing changed");
+            let cancelled = RebuildCounters {
+                total_entries: counters.total_entries,
+                ..RebuildCounters::default()
+            };
+            write_progress(
+                progress_path,
+                reporter,
+                "cancelled",
+                "signalr.dbRebuild.cancelled",
+                cancelled.context(),
+                0.0,
+                &cancelled,
+            )
+        }
+        Err(e) => {
+            // The failed terminal itself comes from finish_or_exit; only the file is written here.
+            if let Some(path) = progress_path {
+                let _ = progress_utils::write_progress_json(
+                    path,
+                    &ProgressData {
+                        status: "failed".to_string(),
+                        stage_key: "signalr.dbRebuild.error.fatal".to_string(),
+                        context: json!({ "errorDetail": format!("{e:#}") }),
+                        percent_complete: 0.0,
+                        entries_processed: counters.entries_processed,
+                        total_entries: counters.total_entries,
+                        downloads_removed: 0,
+                        downloads_created: 0,
+                        timestamp: progress_utils::current_timestamp(),
+                    },
+                );
+            }
+            Err(e)
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    cancel::install();
+    let args = Args::parse();
+    let reporter = ProgressReporter::new(args.progress);
+
+    let progress_path = match args.progress_json.as_deref() {
+        Some("none") | None => None,
+        Some(p) => Some(PathBuf::from(p)),
+    };
+
+    let result = run(&args, progress_path.as_deref(), &reporter).await;
+    progress_events::finish_or_exit(&reporter, "signalr.dbRebuild.error.fatal", result);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(minute: u32, second: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, 1, 12, minute, second).unwrap()
+    }
+
+    fn entry(id: i64, timestamp: DateTime<Utc>, depot: Option<i64>, prior: Option<i64>) -> ScopedEntry {
+        ScopedEntry {
+            id,
+            timestamp,
+            client_ip: "10.0.0.5".to_string(),
+            service: "steam".to_string(),
+            url: format!("/depot/{}/chunk/{}", depot.unwrap_or(0), id),
+            cache_status: if id % 2 == 0 { "HIT" } else { "MISS" }.to_string(),
+            bytes_served: 100,
+            depot_id: depot,
+            download_id: prior,
+            datasource: "default".to_string(),
+        }
+    }
+
+    fn push_all(entries: &[ScopedEntry]) -> Vec<RebuiltSession> {
+        let mut assembler = SessionAssembler::new();
+        let mut closed = Vec::new();
+        for e in entries {
+            let context = EntryContext::default();
+            closed.extend(assembler.push(entry_key(e, &context), e, &context));
+        }
+        closed.extend(assembler.finish());
+        closed
+    }
+
+    #[test]
+    fn a_gap_longer_than_the_ingest_gap_splits_the_session() {
+        let sessions = push_all(&[
+            entry(1, at(0, 0), Some(731), Some(9)),
+            entry(2, at(4, 0), Some(731), Some(9)),
+            entry(3, at(9, 1), Some(731), Some(9)),
+        ]);
+
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].entry_ids, vec![1, 2]);
+        assert_eq!(sessions[1].entry_ids, vec![3]);
+    }
+
+    #[test]
+    fn different_depots_for_one_client_are_separate_sessions() {
+        let sessions = push_all(&[
+            entry(1, at(0, 0), Some(731), None),
+            entry(2, at(0, 1), Some(732), None),
+            entry(3, at(0, 2), Some(731), None),
+        ]);
+
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].primary_depot(), Some(731));
+        assert_eq!(sessions[0].entry_ids, vec![1, 3]);
+    }
+
+    #[test]
+    fn datasources_never_share_a_session() {
+        let mut other = entry(2, at(0, 1), Some(731), None);
+        other.datasource = "secondary".to_string();
+
+        let sessions = push_all(&[entry(1, at(0, 0), Some(731), None), other]);
+
+        assert_eq!(sessions.len(), 2);
+    }
+
+    #[test]
+    fn byte_totals_follow_the_ingest_hit_miss_rules() {
+        let mut unknown = entry(3, at(0, 2), Some(731), None);
+        unknown.cache_status = "UNKNOWN".to_string();
+
+        let sessions = push_all(&[
+            entry(1, at(0, 0), Some(731), None),
+            entry(2, at(0, 1), Some(731), None),
+            unknown,
+        ]);
+
+        assert_eq!(sessions[0].hit_bytes, 100);
+        assert_eq!(sessions[0].miss_bytes, 100);
+        assert_eq!(sessions[0].start, at(0, 0));
+        assert_eq!(sessions[0].end, at(0, 2));
+    }
+
+    #[test]
+    fn idle_sessions_drain_in_start_order() {
+        let mut assembler = SessionAssembler::new();
+        let context = EntryContext::default();
+        for e in [
+            entry(1, at(0, 0), Some(732), None),
+            entry(2, at(0, 0), Some(731), None),
+            entry(3, at(8, 0), Some(733), None),
+        ] {
+            assembler.push(entry_key(&e, &context), &e, &context);
+        }
+
+        let drained = assembler.drain_idle(at(8, 0));
+
+        let depots: Vec<_> = drained.iter().map(|s| s.primary_depot()).collect();
+        assert_eq!(depots, vec![Some(731), Some(732)]);
+        assert_eq!(assembler.finish().len(), 1);
+    }
+
+    #[test]
+    fn a_batch_ending_a_fraction_past_the_gap_keeps_the_session_open() {
+        let mut assembler = SessionAssembler::new();
+        let context = EntryContext::default();
+        let first = entry(1, at(0, 0), Some(731), None);
+        assembler.push(entry_key(&first, &context), &first, &context);
+
+        // 300.4 s idle is 300 whole seconds: not past the gap, as ingest counts it.
+        let batch_end = at(5, 0) + ChronoDuration::milliseconds(400);
+        assert!(assembler.drain_idle(batch_end).is_empty());
+
+        let next = entry(2, batch_end, Some(731), None);
+        assert!(assembler
+            .push(entry_key(&next, &context), &next, &context)
+            .is_none());
+        let sessions = assembler.finish();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].entry_ids, vec![1, 2]);
+    }
+
+    #[test]
+    fn ties_pick_the_lowest_depot_and_prior() {
+        let sessions = push_all(&[
+            entry(1, at(0, 0), Some(731), Some(20)),
+            entry(2, at(0, 1), Some(731), Some(10)),
+        ]);
+        let mut session = sessions[0].clone();
+        session.depots.insert(730, 2);
+
+        assert_eq!(session.primary_depot(), Some(730));
+        assert_eq!(session.majority_prior(), Some(10));
+    }
+}
@@ -1,4 +1,5 @@
 use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
 use serde::Serialize;
 use serde_json::{json, Map, Value};
 use std::env;
@@ -7,7 +8,11 @@ use std::path::Path;
 use std::time::Instant;
 
 use lancache_processor::cancel;
+use lancache_processor::cli_flags::take_flag_value;
 use lancache_processor::db;
+use lancache_processor::db_backup::{self, BackupArchive, BackupPolicy, BackupSummary};
+use lancache_processor::db_scope::{self, LogEntryScope};
+use lancache_processor::db_stats;
 use lancache_processor::progress_events;
 use lancache_processor::progress_utils;
 use progress_events::ProgressReporter;
@@ -94,10 +99,19 @@ fn write_progress(
     Ok(())
 }
 
+/// Complete-stage context entry naming the pre-flight backup, if one was taken.
+fn backup_context(backup: Option<&BackupSummary>) -> Value {
+    match backup {
+        Some(summary) => json!({ "backupArchive": summary.path.display().to_string() }),
+        None => json!({}),
+    }
+}
+
 async fn reset_database(
     data_directory: &str,
     progress_path: &Path,
     reporter: &ProgressReporter,
+    backup: Option<&BackupPolicy>,
 ) -> Result<()> {
     let start_time = Instant::now();
 
@@ -128,6 +142,22 @@ async fn reset_database(
 
     let tables = vec!["LogEntries", "Downloads", "ClientStats", "ServiceStats"];
 
+    // Pre-flight backup of everything the reset is about to clear. A failure here aborts the
+    // reset before a single row is deleted.
+    let backup = match backup {
+        Some(policy) => {
+            println!("Backing up the database to {}...", policy.dir.display());
+            let mut archive = BackupArchive::create(policy, "db_reset", json!({ "scope": "all" }))?;
+            for table in db_backup::BACKUP_TABLES {
+                archive.add_rows(&pool, table, "t", "TRUE", |q| q).await?;
+            }
+            let summary = archive.finish()?;
+            db_backup::log_summary(&summary);
+            Some(summary)
+        }
+        None => None,
+    };
+
     // Disable foreign key constraints using session replication role
     // (equivalent to PostgreSQL's way to bypass FK checks during bulk delete)
     sqlx::query("SET session_replication_role = 'replica'")
@@ -398,7 +428,7 @@ async fn reset_database(
         reporter,
         "signalr.dbReset.complete",
         &mut progress,
-        json!({}),
+        backup_context(backup.as_ref()),
     )?;
 
     println!("\nDatabase reset completed successfully!");
@@ -409,6 +439,398 @@ async fn reset_database(
     Ok(())
 }
 
+/// Ids of the sessions a scoped reset touches, in chunks small enough for one `ANY($1)` bind.
+const DOWNLOAD_ID_CHUNK: usize = 5000;
+
+/// Deletes only the `LogEntries` inside `scope`, then brings the derived tables back in line
+/// with what is left: sessions that lost every entry are deleted, sessions that lost some
+/// (a date range cutting through them) get their totals and span recomputed from the survivors,
+/// and the stats rows of every affected client and service are recomputed. Progress goes
+/// through the same `signalr.dbReset.*` stage keys as the full reset, one `tableName` at a time.
+///
+/// The data-directory files (`position.txt`, ...) are left alone: they describe the whole log,
+/// not one slice of it.
+///
+/// A cancel stops the LogEntries deletion at a batch boundary but still reconciles the sessions
+/// and stats for the rows already deleted, so the tables never disagree with each other.
+async fn reset_scoped(
+    scope: &LogEntryScope,
+    progress_path: &Path,
+    reporter: &ProgressReporter,
+    backup: Option<&BackupPolicy>,
+) -> Result<()> {
+    let start_time = Instant::now();
+
+    println!("Starting scoped database reset...");
+    println!("Scope: {}", scope.describe());
+    println!("Progress file: {}", progress_path.display());
+
+    let mut progress = ProgressData::new(
+        true,
+        0.0,
+        "starting".to_string(),
+        "Starting scoped database reset...".to_string(),
+        0,
+        4,
+        0,
+    );
+    write_progress(
+        progress_path,
+        reporter,
+        "signalr.dbReset.starting",
+        &mut progress,
+        json!({ "scope": scope.describe() }),
+    )?;
+
+    let pool = db::create_pool().await?;
+
+    let total_rows = scope
+        .bind_scalar(sqlx::query_scalar::<_, i64>(&format!(
+            r#"SELECT COUNT(*) FROM "LogEntries" le WHERE {}"#,
+            db_scope::LOG_ENTRY_PREDICATE
+        )))
+        .fetch_one(&pool)
+        .await
+        .context("Failed to count log entries in scope")?;
+    println!("Log entries in scope: {}", total_rows);
+
+    // Collected before anything is deleted: afterwards the scope no longer finds them.
+    let touched_downloads: Vec<i64> = scope
+        .bind_scalar(sqlx::query_scalar::<_, i64>(&format!(
+            r#"SELECT DISTINCT le."DownloadId" FROM "LogEntries" le
+                WHERE le."DownloadId" IS NOT NULL AND {}"#,
+            db_scope::LOG_ENTRY_PREDICATE
+        )))
+        .fetch_all(&pool)
+        .await
+        .context("Failed to collect the sessions in scope")?;
+    let clients: Vec<String> = scope
+        .bind_scalar(sqlx::query_scalar::<_, String>(&format!(
+            r#"SELECT DISTINCT le."ClientIp" FROM "LogEntries" le WHERE {}
+               UNION
+               SELECT DISTINCT d."ClientIp" FROM "Downloads" d WHERE {}"#,
+            db_scope::LOG_ENTRY_PREDICATE,
+            db_scope::DOWNLOAD_PREDICATE
+        )))
+        .fetch_all(&pool)
+        .await
+        .context("Failed to collect the clients in scope")?;
+    let services: Vec<String> = scope
+        .bind_scalar(sqlx::query_scalar::<_, String>(&format!(
+            r#"SELECT DISTINCT le."Service" FROM "LogEntries" le WHERE {}"#,
+            db_scope::LOG_ENTRY_PREDICATE
+        )))
+        .fetch_all(&pool)
+        .await
+        .context("Failed to collect the services in scope")?;
+
+    // Pre-flight backup: the entries in scope, and every session the reset deletes or
+    // recomputes (with its event tags), so a restore puts the original totals back.
+    let backup = match backup {
+        Some(policy) => {
+            println!("Backing up the rows in scope to {}...", policy.dir.display());
+            let mut archive =
+                BackupArchive::create(policy, "db_reset", json!({ "scope": scope.describe() }))?;
+            let downloads = format!(
+                r#"(d."Id" = ANY($5) OR ({} AND NOT EXISTS
+                       (SELECT 1 FROM "LogEntries" x WHERE x."DownloadId" = d."Id")))"#,
+                db_scope::DOWNLOAD_PREDICATE
+            );
+            archive
+                .add_downloads(&pool, &downloads, |q| {
+                    scope.bind_scalar(q).bind(touched_downloads.clone())
+                })
+                .await?;
+            archive
+                .add_rows(&pool, "LogEntries", "le", db_scope::LOG_ENTRY_PREDICATE, |q| {
+                    scope.bind_scalar(q)
+                })
+                .await?;
+            let summary = archive.finish()?;
+            db_backup::log_summary(&summary);
+            Some(summary)
+        }
+        None => None,
+    };
+
+    // --- LogEntries -------------------------------------------------------------------
+    let delete_sql = format!(
+        r#"DELETE FROM "LogEntries" WHERE "Id" IN (
+               SELECT le."Id" FROM "LogEntries" le WHERE {} LIMIT 5000)"#,
+        db_scope::LOG_ENTRY_PREDICATE
+    );
+    let mut deleted_rows = 0i64;
+    let mut cancelled = false;
+    loop {
+        let deleted = scope
+            .bind(sqlx::query(&delete_sql))
+            .execute(&pool)
+            .await
+            .context("Failed to delete log entries in scope")?
+            .rows_affected();
+        if deleted == 0 {
+            break;
+        }
+        deleted_rows += deleted as i64;
+
+        progress.message = format!(
+            "Clearing LogEntries... ({} / {} rows)",
+            deleted_rows, total_rows
+        );
+        progress.percent_complete = if total_rows > 0 {
+            (deleted_rows as f64 / total_rows as f64 * 70.0).min(70.0)
+        } else {
+            0.0
+        };
+        progress.status = "deleting".to_string();
+        write_progress(
+            progress_path,
+            reporter,
+            "signalr.dbReset.deleting",
+            &mut progress,
+            json!({
+                "tableName": "LogEntries",
+                "deletedRows": deleted_rows,
+                "totalRows": total_rows,
+            }),
+        )?;
+
+        if cancel::is_cancelled() {
+            println!(
+                "Cancel requested — stopping after {} log entries, reconciling what was deleted",
+                deleted_rows
+            );
+            cancelled = true;
+            break;
+        }
+    }
+    if !cancelled {
+        progress.tables_cleared += 1;
+    }
+
+    // --- Downloads --------------------------------------------------------------------
+    let mut downloads_deleted = 0u64;
+    let mut downloads_recomputed = 0u64;
+    for chunk in touched_downloads.chunks(DOWNLOAD_ID_CHUNK) {
+        let ids = chunk.to_vec();
+        downloads_deleted += sqlx::query(
+            r#"DELETE FROM "Downloads" d
+                WHERE d."Id" = ANY($1)
+                  AND NOT EXISTS (SELECT 1 FROM "LogEntries" x WHERE x."DownloadId" = d."Id")"#,
+        )
+        .bind(&ids)
+        .execute(&pool)
+        .await
+        .context("Failed to delete emptied downloads")?
+        .rows_affected();
+        downloads_recomputed += sqlx::query(
+            r#"UPDATE "Downloads" d
+                  SET "CacheHitBytes" = s.hit, "CacheMissBytes" = s.miss,
+                      "StartTimeUtc" = s.first_seen, "EndTimeUtc" = s.last_seen
+                 FROM (SELECT x."DownloadId",
+                              COALESCE(SUM(x."BytesServed") FILTER (WHERE x."CacheStatus" = 'HIT'), 0)::bigint AS hit,
+                              COALESCE(SUM(x."BytesServed") FILTER (WHERE x."CacheStatus" = 'MISS'), 0)::bigint AS miss,
+                              MIN(x."Timestamp") AS first_seen,
+                              MAX(x."Timestamp") AS last_seen
+                         FROM "LogEntries" x
+                        WHERE x."DownloadId" = ANY($1)
+                        GROUP BY x."DownloadId") s
+                WHERE d."Id" = s."DownloadId""#,
+        )
+        .bind(&ids)
+        .execute(&pool)
+        .await
+        .context("Failed to recompute partially cleared downloads")?
+        .rows_affected();
+
+        progress.message = format!(
+            "Clearing Downloads... ({} deleted, {} recomputed)",
+            downloads_deleted, downloads_recomputed
+        );
+        progress.percent_complete = 75.0;
+        write_progress(
+            progress_path,
+            reporter,
+            "signalr.dbReset.deleting",
+            &mut progress,
+            json!({
+                "tableName": "Downloads",
+                "deletedRows": downloads_deleted,
+                "recomputedRows": downloads_recomputed,
+            }),
+        )?;
+    }
+    if !cancelled {
+        // Sessions in scope that had no entries left to find them by.
+        downloads_deleted += scope
+            .bind(sqlx::query(&format!(
+                r#"DELETE FROM "Downloads" d
+                    WHERE {}
+                      AND NOT EXISTS (SELECT 1 FROM "LogEntries" x WHERE x."DownloadId" = d."Id")"#,
+                db_scope::DOWNLOAD_PREDICATE
+            )))
+            .execute(&pool)
+            .await
+            .context("Failed to delete entry-less downloads in scope")?
+            .rows_affected();
+        progress.tables_cleared += 1;
+    }
+
+    // --- ClientStats / ServiceStats ---------------------------------------------------
+    let mut tx = pool.begin().await.context("Failed to begin stats transaction")?;
+    let client_stats = db_stats::recompute_client_stats(&mut tx, &clients).await?;
+    progress.tables_cleared += 1;
+    progress.message = "Recomputing ClientStats...".to_string();
+    progress.percent_complete = 80.0;
+    write_progress(
+        progress_path,
+        reporter,
+        "signalr.dbReset.deleting",
+        &mut progress,
+        json!({
+            "tableName": "ClientStats",
+            "deletedRows": client_stats.deleted,
+            "recomputedRows": client_stats.upserted,
+        }),
+    )?;
+    let service_stats = db_stats::recompute_service_stats(&mut tx, &services).await?;
+    tx.commit().await.context("Failed to commit stats recompute")?;
+    progress.tables_cleared += 1;
+    progress.message = "Recomputing ServiceStats...".to_string();
+    progress.percent_complete = 85.0;
+    write_progress(
+        progress_path,
+        reporter,
+        "signalr.dbReset.deleting",
+        &mut progress,
+        json!({
+            "tableName": "ServiceStats",
+            "deletedRows": service_stats.deleted,
+            "recomputedRows": service_stats.upserted,
+        }),
+    )?;
+
+    let mut summary = json!({
+        "scope": scope.describe(),
+        "logEntriesDeleted": deleted_rows,
+        "downloadsDeleted": downloads_deleted,
+        "downloadsRecomputed": downloads_recomputed,
+    });
+
+    if let Some(backup) = &backup {
+        summary["backupArchive"] = json!(backup.path.display().to_string());
+    }
+
+    if cancelled {
+        progress.is_processing = false;
+        progress.status = "cancelled".to_string();
+        progress.message = format!(
+            "Scoped database reset cancelled after {} log entries; sessions and stats reconciled.",
+            deleted_rows
+        );
+        write_progress(
+            progress_path,
+            reporter,
+            "signalr.dbReset.cancelled",
+            &mut progress,
+            summary,
+        )?;
+        return Ok(());
+    }
+
+    progress.message = "Optimizing database...".to_string();
+    progress.percent_complete = 90.0;
+    progress.status = "optimizing".to_string();
+    write_progress(
+        progress_path,
+        reporter,
+        "signalr.dbReset.optimizing",
+        &mut progress,
+        json!({}),
+    )?;
+    sqlx::query(r#"ANALYZE "LogEntries", "Downloads", "ClientStats", "ServiceStats""#)
+        .execute(&pool)
+        .await
+        .context("Failed to analyze database")?;
+
+    let elapsed = start_time.elapsed();
+    progress.is_processing = false;
+    progress.percent_complete = 100.0;
+    progress.status = "completed".to_string();
+    progress.message = format!(
+        "Scoped database reset completed in {:.2}s. Deleted {} log entries and {} downloads.",
+        elapsed.as_secs_f64(),
+        deleted_rows,
+        downloads_deleted
+    );
+    write_progress(
+        progress_path,
+        reporter,
+        "signalr.dbReset.complete",
+        &mut progress,
+        summary,
+    )?;
+
+    println!("\nScoped database reset completed successfully!");
+    println!("  Log entries deleted: {}", deleted_rows);
+    println!("  Downloads deleted: {}", downloads_deleted);
+    println!("  Downloads recomputed: {}", downloads_recomputed);
+    println!("  Time elapsed: {:.2}s", elapsed.as_secs_f64());
+
+    Ok(())
+}
+
+fn parse_time_flag(flag: &str, value: Option<String>) -> Result<Option<DateTime<Utc>>> {
+    value
+        .map(|v| {
+            DateTime::parse_from_rfc3339(&v)
+                .map(|t| t.with_timezone(&Utc))
+                .with_context(|| format!("{} must be an RFC 3339 timestamp, got {}", flag, v))
+        })
+        .transpose()
+}
+
+/// Strips the backup flags (`--backup-dir`, `--backup-keep`, `--backup-max-age-days`) from
+/// `args`. Returns `None` when no backup directory was given.
+fn take_backup(args: &mut Vec<String>) -> Result<Option<BackupPolicy>> {
+    let dir = take_flag_value(args, "--backup-dir")?;
+    let keep = take_flag_value(args, "--backup-keep")?
+        .map(|v| v.parse::<usize>().with_context(|| format!("--backup-keep must be a count, got {}", v)))
+        .transpose()?;
+    let max_age_days = take_flag_value(args, "--backup-max-age-days")?
+        .map(|v| {
+            v.parse::<u64>()
+                .ok()
+                .filter(|days| *days > 0)
+                .with_context(|| format!("--backup-max-age-days must be at least 1 day, got {}", v))
+        })
+        .transpose()?;
+
+    let Some(dir) = dir else {
+        if keep.is_some() || max_age_days.is_some() {
+            anyhow::bail!("--backup-keep and --backup-max-age-days require --backup-dir");
+        }
+        return Ok(None);
+    };
+    Ok(Some(BackupPolicy {
+        dir: dir.into(),
+        keep: keep.unwrap_or(db_backup::DEFAULT_KEEP),
+        max_age_days,
+    }))
+}
+
+/// Strips the scope flags (`--datasource`, `--service`, `--from`, `--to`) from `args`.
+/// Returns `None` when none was given, i.e. a full reset.
+fn take_scope(args: &mut Vec<String>) -> Result<Option<LogEntryScope>> {
+    let datasource = take_flag_value(args, "--datasource")?;
+    let service = take_flag_value(args, "--service")?;
+    let from = parse_time_flag("--from", take_flag_value(args, "--from")?)?;
+    let to = parse_time_flag("--to", take_flag_value(args, "--to")?)?;
+
+    let scope = LogEntryScope::new(datasource, from, to)?.with_service(service);
+    Ok((!scope.is_unbounded()).then_some(scope))
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let mut args: Vec<String> = env::args().collect();
@@ -425,10 +847,18 @@ async fn main() -> anyhow::Result<()> {
             false
         };
 
+    // Optional scope flags, stripped the same way. Any of them turns the wholesale reset into
+    // a scoped one (see `reset_scoped`).
+    let scope = take_scope(&mut args)?;
+    let backup = take_backup(&mut args)?;
+
     if args.len() != 3 {
         eprintln!("Usage: database_reset <data_directory> <progress_json_path> [--progress]");
+        eprintln!("         [--datasource <name>] [--service <name>] [--from <rfc3339>] [--to <rfc3339>]");
+        eprintln!("         [--backup-dir <dir>] [--backup-keep <n>] [--backup-max-age-days <days>]");
         eprintln!("\nExample:");
         eprintln!("  database_reset ./data ./data/reset_progress.json");
+        eprintln!("  database_reset ./data ./data/reset_progress.json --datasource secondary");
         eprintln!(
             "\nNote: Database connection is configured via DATABASE_URL environment variable."
         );
@@ -447,7 +877,12 @@ async fn main() -> anyhow::Result<()> {
         anyhow::bail!("failed to create data directory: {e}");
     }
 
-    match reset_database(data_directory, progress_path, &reporter).await {
+    let result = match &scope {
+        Some(scope) => reset_scoped(scope, progress_path, &reporter, backup.as_ref()).await,
+        None => reset_database(data_directory, progress_path, &reporter, backup.as_ref()).await,
+    };
+
+    match result {
         Ok(_) => Ok(()),
         Err(e) => {
             eprintln!("Error: {:?}", e);
@@ -547,4 +982,77 @@ mod tests {
             .expect("deserialize progress");
         assert_eq!(saved["context"]["errorDetail"], "reset failed");
     }
+
+    fn argv(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn scope_flags_are_stripped_before_the_positional_checks() {
+        let mut args = argv(&[
+            "db_reset",
+            "--datasource",
+            "secondary",
+            "./data",
+            "--from",
+            "2024-05-01T00:00:00Z",
+            "./progress.json",
+            "--service",
+            "Steam",
+        ]);
+
+        let scope = take_scope(&mut args).expect("parse").expect("scoped");
+
+        assert_eq!(args, argv(&["db_reset", "./data", "./progress.json"]));
+        assert_eq!(scope.datasource.as_deref(), Some("secondary"));
+        assert_eq!(scope.service.as_deref(), Some("steam"));
+        assert!(scope.from.is_some() && scope.to.is_none());
+    }
+
+    #[test]
+    fn no_scope_flags_means_a_full_reset() {
+        let mut args = argv(&["db_reset", "./data", "./progress.json"]);
+
+        assert!(take_scope(&mut args).expect("parse").is_none());
+        assert_eq!(args.len(), 3);
+    }
+
+    #[test]
+    fn malformed_scope_flags_are_rejected() {
+        assert!(take_scope(&mut argv(&["db_reset", "./data", "--datasource"])).is_err());
+        assert!(take_scope(&mut argv(&["db_reset", "--from", "yesterday"])).is_err());
+        assert!(take_scope(&mut argv(&[
+            "db_reset",
+            "--from",
+            "2024-05-02T00:00:00Z",
+            "--to",
+            "2024-05-01T00:00:00Z",
+        ]))
+        .is_err());
+    }
+
+    #[test]
+    fn backup_flags_are_stripped_and_need_a_directory() {
+        let mut args = argv(&[
+            "db_reset",
+            "./data",
+            "--backup-dir",
+            "./backups",
+            "./progress.json",
+            "--backup-keep",
+            "3",
+        ]);
+
+        let policy = take_backup(&mut args).expect("parse").expect("backup requested");
+
+        assert_eq!(args, argv(&["db_reset", "./data", "./progress.json"]));
+        assert_eq!(policy.dir, std::path::PathBuf::from("./backups"));
+        assert_eq!(policy.keep, 3);
+        assert_eq!(policy.max_age_days, None);
+
+        assert!(take_backup(&mut argv(&["db_reset", "./data"])).expect("parse").is_none());
+        assert!(take_backup(&mut argv(&["db_reset", "--backup-keep", "3"])).is_err());
+        assert!(take_backup(&mut argv(&["db_reset", "--backup-dir", "b", "--backup-keep", "x"])).is_err());
+        assert!(take_backup(&mut argv(&["db_reset", "--backup-dir", "b", "--backup-max-age-days", "0"])).is_err());
+    }
 }
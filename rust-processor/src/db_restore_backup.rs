@@ -0,0 +1,625 @@
+// Re-insert the rows of a pre-flight backup archive (see `db_backup`).
+//
+// The archive is read twice. The first pass checks it end to end - header, known tables, a
+// footer whose counts match the rows - before the database is touched, so a truncated or
+// foreign file is rejected up front. The second pass inserts the rows parent-first in batches,
+// inside ONE transaction: a cancel or failure leaves the database exactly as it was.
+//
+// Rows that still exist are not duplicated. `Downloads` and the stats rows are overwritten with
+// the archived values (a scoped reset recomputes the totals of sessions it only partly cleared,
+// and restoring the entries makes the archived totals right again); `LogEntries` and
+// `EventDownloads` keep the live row. Tags whose event has since been deleted and entries whose
+// session no longer exists are skipped rather than failing the restore. Afterwards the identity
+// sequences are moved past the restored ids and the stats of every restored client and service
+// are recomputed from the rows now present.
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use flate2::read::GzDecoder;
+use serde::Serialize;
+use serde_json::json;
+use sqlx::{Postgres, Transaction};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+
+use lancache_processor::cancel;
+use lancache_processor::db;
+use lancache_processor::db_backup::{self, ArchiveHeader, ArchiveLine};
+use lancache_processor::db_stats;
+use lancache_processor::progress_events;
+use lancache_processor::progress_utils;
+use progress_events::ProgressReporter;
+
+/// Rows sent per `INSERT ... json_populate_recordset` round trip.
+const INSERT_BATCH: usize = 1000;
+
+/// Restore the rows of a database backup archive.
+#[derive(clap::Parser, Debug)]
+#[command(name = "db_restore_backup")]
+#[command(about = "Re-inserts the rows of a database backup archive")]
+struct Args {
+    /// Backup archive to restore (*.ndjson.gz)
+    archive: PathBuf,
+
+    /// Path to progress JSON file (use "none" to skip)
+    #[arg(default_value = "none")]
+    progress_json: Option<String>,
+
+    /// Emit JSON progress events to stdout
+    #[arg(short, long)]
+    progress: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProgressData {
+    status: String,
+    stage_key: String,
+    context: serde_json::Value,
+    percent_complete: f64,
+    rows_restored: u64,
+    rows_skipped: u64,
+    total_rows: u64,
+    timestamp: String,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct RestoreCounters {
+    rows_restored: u64,
+    rows_skipped: u64,
+    total_rows: u64,
+}
+
+impl RestoreCounters {
+    fn context(&self) -> serde_json::Value {
+        json!({
+            "rowsRestored": self.rows_restored,
+            "rowsSkipped": self.rows_skipped,
+            "totalRows": self.total_rows,
+        })
+    }
+
+    fn percent(&self) -> f64 {
+        if self.total_rows == 0 {
+            return 90.0;
+        }
+        (self.rows_restored + self.rows_skipped) as f64 / self.total_rows as f64 * 90.0
+    }
+}
+
+/// Writes the progress file (when a path was supplied) and THEN emits the matching stdout event.
+fn write_progress(
+    progress_path: Option<&Path>,
+    reporter: &ProgressReporter,
+    status: &str,
+    stage_key: &str,
+    context: serde_json::Value,
+    percent_complete: f64,
+    counters: &RestoreCounters,
+) -> Result<()> {
+    if let Some(path) = progress_path {
+        let progress = ProgressData {
+            status: status.to_string(),
+            stage_key: stage_key.to_string(),
+            context: context.clone(),
+            percent_complete,
+            rows_restored: counters.rows_restored,
+            rows_skipped: counters.rows_skipped,
+            total_rows: counters.total_rows,
+            timestamp: progress_utils::current_timestamp(),
+        };
+        progress_utils::write_progress_json(path, &progress)?;
+    }
+
+    match status {
+        "starting" => reporter.emit_started(stage_key, context),
+        "completed" => reporter.emit_complete(stage_key, context),
+        "cancelled" => reporter.emit_cancelled(stage_key, context),
+        _ => reporter.emit_progress(percent_complete, stage_key, context),
+    }
+
+    Ok(())
+}
+
+/// How a restored row that collides with a live one is handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OnConflict {
+    /// Overwrite the live row with the archived one (conflict on these key columns).
+    Overwrite(&'static [&'static str]),
+    /// Keep the live row.
+    Keep,
+}
+
+/// Per-table restore rules. `guard` filters the archived rows (alias `r`) so references to rows
+/// that no longer exist are skipped instead of violating a foreign key.
+struct RestoreTable {
+    name: &'static str,
+    on_conflict: OnConflict,
+    guard: Option<&'static str>,
+}
+
+const RESTORE_TABLES: [RestoreTable; 5] = [
+    RestoreTable {
+        name: "Downloads",
+        on_conflict: OnConflict::Overwrite(&["Id"]),
+        guard: None,
+    },
+    RestoreTable {
+        name: "EventDownloads",
+        on_conflict: OnConflict::Keep,
+        guard: Some(
+            r#"EXISTS (SELECT 1 FROM "Events" e WHERE e."Id" = r."EventId")
+               AND EXISTS (SELECT 1 FROM "Downloads" d WHERE d."Id" = r."DownloadId")"#,
+        ),
+    },
+    RestoreTable {
+        name: "LogEntries",
+        on_conflict: OnConflict::Keep,
+        guard: Some(
+            r#"(r."DownloadId" IS NULL
+                OR EXISTS (SELECT 1 FROM "Downloads" d WHERE d."Id" = r."DownloadId"))"#,
+        ),
+    },
+    RestoreTable {
+        name: "ClientStats",
+        on_conflict: OnConflict::Overwrite(&["ClientIp"]),
+        guard: None,
+    },
+    RestoreTable {
+        name: "ServiceStats",
+        on_conflict: OnConflict::Overwrite(&["Service"]),
+        guard: None,
+    },
+];
+
+fn restore_table(name: &str) -> Result<&'static RestoreTable> {
+    RESTORE_TABLES
+        .iter()
+        .find(|t| t.name == name)
+        .with_context(|| format!("no restore rules for table {}", name))
+}
+
+fn open_archive(path: &Path) -> Result<impl Iterator<Item = Result<ArchiveLine>>> {
+    let file = File::open(path)
+        .with_context(|| format!("failed to open backup archive {}", path.display()))?;
+    let reader = BufReader::new(GzDecoder::new(file));
+    Ok(reader.lines().map(|line| {
+        let line = line.context("failed to read the backup archive")?;
+        db_backup::parse_line(&line)
+    }))
+}
+
+/// What the verification pass learned about an archive.
+#[derive(Debug)]
+struct ArchiveOverview {
+    header: ArchiveHeader,
+    tables: BTreeMap<String, u64>,
+}
+
+/// Reads the whole archive once and checks its structure against the footer.
+fn verify_archive(path: &Path) -> Result<ArchiveOverview> {
+    let mut header = None;
+    let mut footer = None;
+    let mut seen: BTreeMap<String, u64> = BTreeMap::new();
+
+    for (index, line) in open_archive(path)?.enumerate() {
+        let line = line.with_context(|| format!("line {} of the backup archive", index + 1))?;
+        match line {
+            ArchiveLine::Header(h) if index == 0 => header = Some(h),
+            ArchiveLine::Header(_) => bail!("line {}: unexpected second header", index + 1),
+            _ if header.is_none() => bail!("the backup archive has no header"),
+            _ if footer.is_some() => bail!("line {}: data after the footer", index + 1),
+            ArchiveLine::Row { table, .. } => *seen.entry(table).or_default() += 1,
+            ArchiveLine::Footer(tables) => footer = Some(tables),
+        }
+    }
+
+    let header = header.context("the backup archive is empty")?;
+    let Some(footer) = footer else {
+        bail!("the backup archive has no footer; it was not written completely");
+    };
+    check_counts(&footer, &seen)?;
+
+    Ok(ArchiveOverview {
+        header,
+        tables: footer,
+    })
+}
+
+fn check_counts(footer: &BTreeMap<String, u64>, seen: &BTreeMap<String, u64>) -> Result<()> {
+    let tables: BTreeSet<&String> = footer.keys().chain(seen.keys()).collect();
+    for table in tables {
+        let expected = footer.get(table).copied().unwrap_or(0);
+        let found = seen.get(table).copied().unwrap_or(0);
+        if expected != found {
+            bail!(
+                "the backup archive is inconsistent: footer lists {} {} rows, found {}",
+                expected,
+                table,
+                found
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Columns of `table` in table order, as the live schema has them.
+async fn live_columns(tx: &mut Transaction<'_, Postgres>, table: &str) -> Result<Vec<String>> {
+    let columns: Vec<String> = sqlx::query_scalar(
+        r#"SELECT column_name::text FROM information_schema.columns
+            WHERE table_schema = current_schema() AND table_name = $1
+            ORDER BY ordinal_position"#,
+    )
+    .bind(table)
+    .fetch_all(&mut **tx)
+    .await
+    .with_context(|| format!("failed to read the columns of {}", table))?;
+    if columns.is_empty() {
+        bail!("table {} does not exist in this database", table);
+    }
+    Ok(columns)
+}
+
+/// The `INSERT` for one batch. Only columns present in both the archive rows and the live table
+/// are written, so an archive taken before a column was added restores with the column's default.
+fn insert_sql(table: &RestoreTable, columns: &[&str]) -> String {
+    let quoted: Vec<String> = columns.iter().map(|c| format!(r#""{}""#, c)).collect();
+    let list = quoted.join(", ");
+    let selected: Vec<String> = quoted.iter().map(|c| format!("r.{}", c)).collect();
+    let mut sql = format!(
+        r#"INSERT INTO "{table}" ({list})
+           SELECT {select} FROM json_populate_recordset(NULL::"{table}", $1::json) r"#,
+        table = table.name,
+        list = list,
+        select = selected.join(", "),
+    );
+    if let Some(guard) = table.guard {
+        sql.push_str(" WHERE ");
+        sql.push_str(guard);
+    }
+    match table.on_conflict {
+        OnConflict::Keep => sql.push_str(" ON CONFLICT DO NOTHING"),
+        OnConflict::Overwrite(keys) => {
+            let target: Vec<String> = keys.iter().map(|k| format!(r#""{}""#, k)).collect();
+            let updates: Vec<String> = columns
+                .iter()
+                .filter(|c| !keys.contains(c))
+                .map(|c| format!(r#""{c}" = EXCLUDED."{c}""#))
+                .collect();
+            if updates.is_empty() {
+                sql.push_str(&format!(" ON CONFLICT ({}) DO NOTHING", target.join(", ")));
+            } else {
+                sql.push_str(&format!(
+                    " ON CONFLICT ({}) DO UPDATE SET {}",
+                    target.join(", "),
+                    updates.join(", ")
+                ));
+            }
+        }
+    }
+    sql
+}
+
+/// Keys whose stats rows must be recomputed once the rows are back.
+#[derive(Debug, Default)]
+struct StatsKeys {
+    clients: BTreeSet<String>,
+    services: BTreeSet<String>,
+}
+
+impl StatsKeys {
+    fn note(&mut self, table: &str, row: &serde_json::Value) {
+        let text = |key: &str| row.get(key).and_then(|v| v.as_str()).map(str::to_string);
+        match table {
+            "LogEntries" => {
+                self.clients.extend(text("ClientIp"));
+                self.services.extend(text("Service"));
+            }
+            "Downloads" | "ClientStats" => self.clients.extend(text("ClientIp")),
+            "ServiceStats" => self.services.extend(text("Service")),
+            _ => {}
+        }
+    }
+}
+
+/// One table's pending rows.
+struct Batch {
+    table: &'static RestoreTable,
+    live_columns: Vec<String>,
+    rows: Vec<serde_json::Value>,
+}
+
+async fn flush(
+    tx: &mut Transaction<'_, Postgres>,
+    batch: &mut Batch,
+    counters: &mut RestoreCounters,
+) -> Result<()> {
+    if batch.rows.is_empty() {
+        return Ok(());
+    }
+
+    let present: BTreeSet<&str> = batch
+        .rows
+        .iter()
+        .filter_map(|r| r.as_object())
+        .flat_map(|r| r.keys().map(String::as_str))
+        .collect();
+    let columns: Vec<&str> = batch
+        .live_columns
+        .iter()
+        .map(String::as_str)
+        .filter(|c| present.contains(c))
+        .collect();
+    if let OnConflict::Overwrite(keys) = batch.table.on_conflict {
+        if let Some(missing) = keys.iter().find(|k| !columns.contains(k)) {
+            bail!("{} rows in the archive have no {} column", batch.table.name, missing);
+        }
+    }
+
+    let payload = serde_json::to_string(&batch.rows)?;
+    let inserted = sqlx::query(&insert_sql(batch.table, &columns))
+        .bind(payload)
+        .execute(&mut **tx)
+        .await
+        .with_context(|| format!("failed to restore {} rows", batch.table.name))?
+        .rows_affected();
+
+    let sent = batch.rows.len() as u64;
+    counters.rows_restored += inserted.min(sent);
+    counters.rows_skipped += sent.saturating_sub(inserted);
+    batch.rows.clear();
+    Ok(())
+}
+
+/// Moves the table's identity sequence past the largest id now present. Deletes never rewind a
+/// sequence, so this only matters when the database was recreated between backup and restore.
+async fn advance_identity(tx: &mut Transaction<'_, Postgres>, table: &str) -> Result<()> {
+    let sql = format!(
+        r#"SELECT setval(s.seq::regclass, s.max_id)
+             FROM (SELECT pg_get_serial_sequence('"{table}"', 'Id') AS seq,
+                          (SELECT MAX("Id") FROM "{table}") AS max_id) s
+            WHERE s.seq IS NOT NULL AND s.max_id IS NOT NULL
+              AND s.max_id > COALESCE(pg_sequence_last_value(s.seq::regclass), 0)"#
+    );
+    sqlx::query(&sql)
+        .execute(&mut **tx)
+        .await
+        .with_context(|| format!("failed to advance the {} id sequence", table))?;
+    Ok(())
+}
+
+enum RestoreOutcome {
+    Completed(serde_json::Value),
+    Cancelled,
+}
+
+async fn restore(
+    archive: &Path,
+    overview: &ArchiveOverview,
+    progress_path: Option<&Path>,
+    reporter: &ProgressReporter,
+    counters: &mut RestoreCounters,
+) -> Result<RestoreOutcome> {
+    let pool = db::create_pool().await?;
+    let mut tx = pool.begin().await.context("failed to start the restore transaction")?;
+
+    let mut columns_by_table: HashMap<&str, Vec<String>> = HashMap::new();
+    for table in &RESTORE_TABLES {
+        if overview.tables.get(table.name).copied().unwrap_or(0) > 0 {
+            columns_by_table.insert(table.name, live_columns(&mut tx, table.name).await?);
+        }
+    }
+
+    let mut stats_keys = StatsKeys::default();
+    let mut batch: Option<Batch> = None;
+    for line in open_archive(archive)? {
+        let ArchiveLine::Row { table, row } = line? else {
+            continue;
+        };
+
+        if batch.as_ref().is_some_and(|b| b.table.name != table) {
+            if let Some(mut finished) = batch.take() {
+                flush(&mut tx, &mut finished, counters).await?;
+            }
+        }
+        let current = match &mut batch {
+            Some(current) => current,
+            None => batch.insert(Batch {
+                table: restore_table(&table)?,
+                live_columns: columns_by_table.get(table.as_str()).cloned().unwrap_or_default(),
+                rows: Vec::with_capacity(INSERT_BATCH),
+            }),
+        };
+
+        stats_keys.note(&table, &row);
+        current.rows.push(row);
+        if current.rows.len() >= INSERT_BATCH {
+            flush(&mut tx, current, counters).await?;
+            write_progress(
+                progress_path,
+                reporter,
+                "restoring",
+                "signalr.dbRestore.restoring",
+                json!({ "tableName": table, "rowsRestored": counters.rows_restored, "totalRows": counters.total_rows }),
+                counters.percent(),
+                counters,
+            )?;
+
+            if cancel::is_cancelled() {
+                tx.rollback().await.context("failed to roll back the restore")?;
+                return Ok(RestoreOutcome::Cancelled);
+            }
+        }
+    }
+    if let Some(mut finished) = batch.take() {
+        flush(&mut tx, &mut finished, counters).await?;
+    }
+
+    for table in ["Downloads", "EventDownloads", "LogEntries"] {
+        if columns_by_table.contains_key(table) {
+            advance_identity(&mut tx, table).await?;
+        }
+    }
+
+    write_progress(
+        progress_path,
+        reporter,
+        "stats",
+        "signalr.dbRestore.stats",
+        json!({ "clients": stats_keys.clients.len(), "services": stats_keys.services.len() }),
+        92.0,
+        counters,
+    )?;
+    let clients: Vec<String> = stats_keys.clients.into_iter().collect();
+    let services: Vec<String> = stats_keys.services.into_iter().collect();
+    let client_stats = db_stats::recompute_client_stats(&mut tx, &clients).await?;
+    let service_stats = db_stats::recompute_service_stats(&mut tx, &services).await?;
+
+    if cancel::is_cancelled() {
+        tx.rollback().await.context("failed to roll back the restore")?;
+        return Ok(RestoreOutcome::Cancelled);
+    }
+    tx.commit().await.context("failed to commit the restore")?;
+
+    let mut summary = counters.context();
+    summary["archive"] = json!(archive.display().to_string());
+    summary["operation"] = json!(overview.header.operation);
+    summary["createdAt"] = json!(overview.header.created_at);
+    summary["tables"] = json!(overview.tables);
+    summary["clientStatsUpdated"] = json!(client_stats.upserted + client_stats.deleted);
+    summary["serviceStatsUpdated"] = json!(service_stats.upserted + service_stats.deleted);
+    Ok(RestoreOutcome::Completed(summary))
+}
+
+async fn run(args: &Args, progress_path: Option<&Path>, reporter: &ProgressReporter) -> Result<()> {
+    let mut counters = RestoreCounters::default();
+
+    write_progress(
+        progress_path,
+        reporter,
+        "starting",
+        "signalr.dbRestore.starting",
+        json!({ "archive": args.archive.display().to_string() }),
+        0.0,
+        &counters,
+    )?;
+
+    let outcome = async {
+        db_backup::validate_archive_path(&args.archive)?;
+        let overview = verify_archive(&args.archive)?;
+        counters.total_rows = overview.tables.values().sum();
+        eprintln!(
+            "[DbRestore] {} backup from {}: {} rows",
+            overview.header.operation, overview.header.created_at, counters.total_rows
+        );
+        restore(&args.archive, &overview, progress_path, reporter, &mut counters).await
+    }
+    .await;
+
+    match outcome {
+        Ok(RestoreOutcome::Completed(summary)) => {
+            eprintln!(
+                "[DbRestore] Restored {} rows ({} already present or no longer referable)",
+                counters.rows_restored, counters.rows_skipped
+            );
+            write_progress(
+                progress_path,
+                reporter,
+                "completed",
+                "signalr.dbRestore.complete",
+                summary,
+                100.0,
+                &counters,
+            )
+        }
+        Ok(RestoreOutcome::Cancelled) => {
+            eprintln!("[DbRestore] Cancelled - transaction rolled back, nothing changed");
+            let cancelled = RestoreCounters {
+                total_rows: counters.total_rows,
+                ..RestoreCounters::default()
+            };
+            write_progress(
+                progress_path,
+                reporter,
+                "cancelled",
+                "signalr.dbRestore.cancelled",
+                cancelled.context(),
+                0.0,
+                &cancelled,
+            )
+        }
+        Err(e) => {
+            // The failed terminal itself comes from finish_or_exit; only the file is written here.
+            if let Some(path) = progress_path {
+                let _ = progress_utils::write_progress_json(
+                    path,
+                    &ProgressData {
+                        status: "failed".to_string(),
+                        stage_key: "signalr.dbRestore.error.fatal".to_string(),
+                        context: json!({ "errorDetail": format!("{e:#}") }),
+                        percent_complete: 0.0,
+                        rows_restored: 0,
+                        rows_skipped: 0,
+                        total_rows: counters.total_rows,
+                        timestamp: progress_utils::current_timestamp(),
+                    },
+                );
+            }
+            Err(e)
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    cancel::install();
+    let args = Args::parse();
+    let reporter = ProgressReporter::new(args.progress);
+
+    let progress_path = match args.progress_json.as_deref() {
+        Some("none") | None => None,
+        Some(p) => Some(PathBuf::from(p)),
+    };
+
+    let result = run(&args, progress_path.as_deref(), &reporter).await;
+    progress_events::finish_or_exit(&reporter, "signalr.dbRestore.error.fatal", result);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restore_order_matches_the_backup_order() {
+        let names: Vec<&str> = RESTORE_TABLES.iter().map(|t| t.name).collect();
+        assert_eq!(names, db_backup::BACKUP_TABLES);
+    }
+
+    #[test]
+    fn overwrite_updates_every_column_but_the_key() {
+        let sql = insert_sql(restore_table("ClientStats").unwrap(), &["ClientIp", "TotalDownloads"]);
+
+        assert!(sql.contains(r#"ON CONFLICT ("ClientIp") DO UPDATE SET "TotalDownloads" = EXCLUDED."TotalDownloads""#));
+        assert!(!sql.contains(r#""ClientIp" = EXCLUDED"#));
+    }
+
+    #[test]
+    fn kept_tables_skip_conflicts_and_dangling_references() {
+        let sql = insert_sql(restore_table("EventDownloads").unwrap(), &["Id", "EventId", "DownloadId"]);
+
+        assert!(sql.ends_with("ON CONFLICT DO NOTHING"));
+        assert!(sql.contains(r#"FROM "Events" e"#));
+    }
+
+    #[test]
+    fn footer_mismatch_is_reported() {
+        let footer = BTreeMap::from([("Downloads".to_string(), 2)]);
+        let seen = BTreeMap::from([("Downloads".to_string(), 1)]);
+
+        assert!(check_counts(&footer, &seen).is_err());
+        assert!(check_counts(&footer, &footer).is_ok());
+    }
+}
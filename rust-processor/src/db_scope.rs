@@ -0,0 +1,137 @@
+//! Row scoping shared by the database maintenance binaries (`db_rebuild`, ...).
+//!
+//! A scope narrows an operation to one datasource, one service and/or a half-open time window
+//! (`from <= Timestamp < to`). Every bound is optional; an empty scope means "the whole
+//! table". The SQL predicates take the bounds as the first four bind parameters so a
+//! caller can append its own parameters from `$5` on.
+
+use anyhow::{bail, Result};
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use sqlx::postgres::PgArguments;
+use sqlx::query::{Query, QueryScalar};
+use sqlx::Postgres;
+
+/// `LogEntries` rows (aliased `le`) inside the scope. Binds `$1` datasource, `$2` from, `$3` to,
+/// `$4` service.
+pub const LOG_ENTRY_PREDICATE: &str = r#"($1::text IS NULL OR le."Datasource" = $1)
+   AND ($2::timestamptz IS NULL OR le."Timestamp" >= $2)
+   AND ($3::timestamptz IS NULL OR le."Timestamp" < $3)
+   AND ($4::text IS NULL OR le."Service" = $4)"#;
+
+/// `Downloads` rows (aliased `d`) whose session overlaps the scope. Same binds as
+/// [`LOG_ENTRY_PREDICATE`]; the service matches the download's own `Service`, so a `wsus`
+/// scope does not reach the `xbox` rows ingest canonicalized out of `wsus` traffic.
+pub const DOWNLOAD_PREDICATE: &str = r#"($1::text IS NULL OR d."Datasource" = $1)
+   AND ($2::timestamptz IS NULL OR d."EndTimeUtc" >= $2)
+   AND ($3::timestamptz IS NULL OR d."StartTimeUtc" < $3)
+   AND ($4::text IS NULL OR d."Service" = $4)"#;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogEntryScope {
+    pub datasource: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub service: Option<String>,
+}
+
+impl LogEntryScope {
+    pub fn new(
+        datasource: Option<String>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<Self> {
+        if let (Some(from), Some(to)) = (from, to) {
+            if from >= to {
+                bail!("--from ({}) must be earlier than --to ({})", from, to);
+            }
+        }
+        Ok(Self {
+            datasource: non_blank(datasource),
+            from,
+            to,
+            service: None,
+        })
+    }
+
+    /// Narrow the scope to one cache service (`LogEntries.Service`, e.g. `steam`, `wsus`).
+    pub fn with_service(mut self, service: Option<String>) -> Self {
+        self.service = non_blank(service).map(|s| s.to_lowercase());
+        self
+    }
+
+    pub fn is_unbounded(&self) -> bool {
+        self.datasource.is_none() && self.from.is_none() && self.to.is_none() && self.service.is_none()
+    }
+
+    /// Scope echoed into progress contexts and reports.
+    pub fn describe(&self) -> serde_json::Value {
+        json!({
+            "datasource": self.datasource,
+            "from": self.from.map(|t| t.to_rfc3339()),
+            "to": self.to.map(|t| t.to_rfc3339()),
+            "service": self.service,
+        })
+    }
+
+    /// Bind `$1..$4` of a scope predicate.
+    pub fn bind<'q>(
+        &self,
+        query: Query<'q, Postgres, PgArguments>,
+    ) -> Query<'q, Postgres, PgArguments> {
+        query
+            .bind(self.datasource.clone())
+            .bind(self.from)
+            .bind(self.to)
+            .bind(self.service.clone())
+    }
+
+    /// [`bind`](Self::bind) for `query_scalar`.
+    pub fn bind_scalar<'q, O>(
+        &self,
+        query: QueryScalar<'q, Postgres, O, PgArguments>,
+    ) -> QueryScalar<'q, Postgres, O, PgArguments> {
+        query
+            .bind(self.datasource.clone())
+            .bind(self.from)
+            .bind(self.to)
+            .bind(self.service.clone())
+    }
+}
+
+fn non_blank(value: Option<String>) -> Option<String> {
+    value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn inverted_window_is_rejected() {
+        let from = Utc.with_ymd_and_hms(2024, 5, 2, 0, 0, 0).unwrap();
+        let to = Utc.with_ymd_and_hms(2024, 5, 1, 0, 0, 0).unwrap();
+
+        assert!(LogEntryScope::new(None, Some(from), Some(to)).is_err());
+        assert!(LogEntryScope::new(None, Some(to), Some(from)).is_ok());
+    }
+
+    #[test]
+    fn blank_datasource_means_every_datasource() {
+        let scope = LogEntryScope::new(Some("  ".to_string()), None, None).unwrap();
+
+        assert!(scope.is_unbounded());
+        assert_eq!(scope.describe()["datasource"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn service_scope_uses_the_stored_lowercase_name() {
+        let scope = LogEntryScope::new(None, None, None)
+            .unwrap()
+            .with_service(Some(" Steam ".to_string()));
+
+        assert_eq!(scope.service.as_deref(), Some("steam"));
+        assert!(!scope.is_unbounded());
+    }
+}
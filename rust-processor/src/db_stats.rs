@@ -0,0 +1,157 @@
+//! Recompute `ClientStats` and `ServiceStats` from the rows they summarise.
+//!
+//! Ingest maintains both tables incrementally (`log_processor` adds each batch's HIT/MISS bytes
+//! and bumps `TotalDownloads` when it opens a session), so any tool that deletes or rewrites
+//! `LogEntries` / `Downloads` leaves them stale. These helpers rebuild the rows for a given set
+//! of keys from scratch, using the same byte rules ingest uses:
+//!
+//! * hit bytes are `LogEntries` rows with `CacheStatus = 'HIT'`, miss bytes rows with `'MISS'`;
+//! * `LastActivityUtc` is the newest `LogEntries.Timestamp` for the key;
+//! * `ClientStats.TotalDownloads` counts the client's `Downloads` rows, and
+//!   `ServiceStats.TotalDownloads` counts the distinct sessions the service's log entries
+//!   belong to. The per-service count goes through `LogEntries` because `ServiceStats` is keyed
+//!   on the cache-hash service (`wsus`) while an Xbox `Downloads` row carries `xbox`.
+//!
+//! Stats rows are global (they carry no datasource), so the recompute always covers every
+//! datasource's rows for a key. A key that no longer has any log entries loses its row.
+//! `ClientStats.TotalDurationSeconds` is not derivable from the log and is left untouched.
+
+use anyhow::{Context, Result};
+use sqlx::{Postgres, Transaction};
+
+/// Rows written and rows deleted by one recompute.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StatsRecomputeOutcome {
+    pub upserted: u64,
+    pub deleted: u64,
+}
+
+pub const RECOMPUTE_CLIENT_STATS_SQL: &str = r#"WITH agg AS (
+    SELECT le."ClientIp",
+           COALESCE(SUM(le."BytesServed") FILTER (WHERE le."CacheStatus" = 'HIT'), 0)::bigint AS hit,
+           COALESCE(SUM(le."BytesServed") FILTER (WHERE le."CacheStatus" = 'MISS'), 0)::bigint AS miss,
+           MAX(le."Timestamp") AS last_activity
+      FROM "LogEntries" le
+     WHERE le."ClientIp" = ANY($1)
+     GROUP BY le."ClientIp"
+), sessions AS (
+    SELECT d."ClientIp", COUNT(*)::int AS downloads
+      FROM "Downloads" d
+     WHERE d."ClientIp" = ANY($1)
+     GROUP BY d."ClientIp"
+)
+INSERT INTO "ClientStats" ("ClientIp", "TotalCacheHitBytes", "TotalCacheMissBytes", "LastActivityUtc", "TotalDownloads", "TotalDurationSeconds")
+SELECT agg."ClientIp", agg.hit, agg.miss, agg.last_activity, COALESCE(sessions.downloads, 0), 0.0
+  FROM agg
+  LEFT JOIN sessions ON sessions."ClientIp" = agg."ClientIp"
+ON CONFLICT ("ClientIp") DO UPDATE SET
+    "TotalCacheHitBytes" = EXCLUDED."TotalCacheHitBytes",
+    "TotalCacheMissBytes" = EXCLUDED."TotalCacheMissBytes",
+    "LastActivityUtc" = EXCLUDED."LastActivityUtc",
+    "TotalDownloads" = EXCLUDED."TotalDownloads""#;
+
+pub const DELETE_EMPTY_CLIENT_STATS_SQL: &str = r#"DELETE FROM "ClientStats" cs
+ WHERE cs."ClientIp" = ANY($1)
+   AND NOT EXISTS (SELECT 1 FROM "LogEntries" le WHERE le."ClientIp" = cs."ClientIp")"#;
+
+pub const RECOMPUTE_SERVICE_STATS_SQL: &str = r#"WITH agg AS (
+    SELECT le."Service",
+           COALESCE(SUM(le."BytesServed") FILTER (WHERE le."CacheStatus" = 'HIT'), 0)::bigint AS hit,
+           COALESCE(SUM(le."BytesServed") FILTER (WHERE le."CacheStatus" = 'MISS'), 0)::bigint AS miss,
+           MAX(le."Timestamp") AS last_activity,
+           COUNT(DISTINCT le."DownloadId")::int AS downloads
+      FROM "LogEntries" le
+     WHERE le."Service" = ANY($1)
+     GROUP BY le."Service"
+)
+INSERT INTO "ServiceStats" ("Service", "TotalCacheHitBytes", "TotalCacheMissBytes", "LastActivityUtc", "TotalDownloads")
+SELECT agg."Service", agg.hit, agg.miss, agg.last_activity, agg.downloads
+  FROM agg
+ON CONFLICT ("Service") DO UPDATE SET
+    "TotalCacheHitBytes" = EXCLUDED."TotalCacheHitBytes",
+    "TotalCacheMissBytes" = EXCLUDED."TotalCacheMissBytes",
+    "LastActivityUtc" = EXCLUDED."LastActivityUtc",
+    "TotalDownloads" = EXCLUDED."TotalDownloads""#;
+
+pub const DELETE_EMPTY_SERVICE_STATS_SQL: &str = r#"DELETE FROM "ServiceStats" ss
+ WHERE ss."Service" = ANY($1)
+   AND NOT EXISTS (SELECT 1 FROM "LogEntries" le WHERE le."Service" = ss."Service")"#;
+
+/// Recompute the `ClientStats` rows for `client_ips` inside the caller's transaction.
+pub async fn recompute_client_stats(
+    tx: &mut Transaction<'_, Postgres>,
+    client_ips: &[String],
+) -> Result<StatsRecomputeOutcome> {
+    if client_ips.is_empty() {
+        return Ok(StatsRecomputeOutcome::default());
+    }
+
+    let upserted = sqlx::query(RECOMPUTE_CLIENT_STATS_SQL)
+        .bind(client_ips)
+        .execute(&mut **tx)
+        .await
+        .context("failed to recompute ClientStats")?
+        .rows_affected();
+    let deleted = sqlx::query(DELETE_EMPTY_CLIENT_STATS_SQL)
+        .bind(client_ips)
+        .execute(&mut **tx)
+        .await
+        .context("failed to delete empty ClientStats rows")?
+        .rows_affected();
+
+    Ok(StatsRecomputeOutcome { upserted, deleted })
+}
+
+/// Recompute the `ServiceStats` rows for `services` inside the caller's transaction.
+pub async fn recompute_service_stats(
+    tx: &mut Transaction<'_, Postgres>,
+    services: &[String],
+) -> Result<StatsRecomputeOutcome> {
+    if services.is_empty() {
+        return Ok(StatsRecomputeOutcome::default());
+    }
+
+    let upserted = sqlx::query(RECOMPUTE_SERVICE_STATS_SQL)
+        .bind(services)
+        .execute(&mut **tx)
+        .await
+        .context("failed to recompute ServiceStats")?
+        .rows_affected();
+    let deleted = sqlx::query(DELETE_EMPTY_SERVICE_STATS_SQL)
+        .bind(services)
+        .execute(&mut **tx)
+        .await
+        .context("failed to delete empty ServiceStats rows")?
+        .rows_affected();
+
+    Ok(StatsRecomputeOutcome { upserted, deleted })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_rules_match_ingest() {
+        for sql in [RECOMPUTE_CLIENT_STATS_SQL, RECOMPUTE_SERVICE_STATS_SQL] {
+            assert!(sql.contains("\"CacheStatus\" = 'HIT'"));
+            assert!(sql.contains("\"CacheStatus\" = 'MISS'"));
+            assert!(!sql.contains("UNKNOWN"));
+        }
+    }
+
+    #[test]
+    fn client_recompute_keeps_the_stored_duration() {
+        let update = RECOMPUTE_CLIENT_STATS_SQL
+            .split("DO UPDATE SET")
+            .nth(1)
+            .expect("upsert clause");
+        assert!(!update.contains("TotalDurationSeconds"));
+    }
+
+    #[test]
+    fn service_sessions_are_counted_through_log_entries() {
+        assert!(RECOMPUTE_SERVICE_STATS_SQL.contains("COUNT(DISTINCT le.\"DownloadId\")"));
+        assert!(!RECOMPUTE_SERVICE_STATS_SQL.contains("\"Downloads\""));
+    }
+}
@@ -0,0 +1,225 @@
+//! Download-session identity shared by ingest (`log_processor`) and the database rebuild
+//! tooling (`db_rebuild`).
+//!
+//! A `Downloads` row is one session of one client pulling one game: the rows are keyed on
+//! client + service + a per-service game discriminator, and a gap longer than
+//! `SESSION_GAP_MINUTES` on that key starts a new session. Rebuilding the table from
+//! `LogEntries` only reproduces what ingest wrote if both sides derive that key the same
+//! way, so the key rules live here rather than in either binary.
+
+use crate::cache_utils;
+use crate::riot_hosts;
+use crate::tact_products;
+
+/// Idle gap on one session key after which the next request starts a new download session.
+pub const SESSION_GAP_MINUTES: i64 = 5;
+
+/// Xbox CDN fragment -> (title, product id), longest fragment first so the most specific
+/// fragment wins. A `XboxGameMappings` title overrides the fragment's own title.
+pub const XBOX_PATTERNS_SQL: &str = "SELECT p.\"UrlFragment\", COALESCE(m.\"Title\", p.\"Title\") AS \"Title\", p.\"ProductId\" \
+     FROM \"XboxCdnPatterns\" p \
+     LEFT JOIN \"XboxGameMappings\" m ON p.\"ProductId\" = m.\"ProductId\" \
+     ORDER BY LENGTH(p.\"UrlFragment\") DESC";
+
+/// The request fields a session key is derived from. `cdn_host` is only known while parsing
+/// the access log; `LogEntries` does not store it, so a rebuild passes the Riot game it
+/// already knows through `riot_game` instead.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SessionFields<'a> {
+    pub client_ip: &'a str,
+    pub service: &'a str,
+    pub url: &'a str,
+    pub depot_id: Option<u32>,
+    pub tact_product: Option<&'a str>,
+    pub cdn_host: Option<&'a str>,
+    pub riot_game: Option<&'a str>,
+    pub xbox_title: Option<&'a str>,
+}
+
+/// Group key for one request: client + service + game discriminator, so different games
+/// pulled by the same client never merge into one session.
+pub fn session_key(fields: &SessionFields<'_>) -> String {
+    let depot_suffix = if let Some(id) = fields.depot_id {
+        format!("_{}", id)
+    } else if let Some(title) = fields.xbox_title {
+        // Xbox content is tagged `wsus` (DO-client) or `xboxlive` (prefill, assets1) over
+        // opaque CDN URLs; key the session on the resolved title so distinct Xbox games (and
+        // distinct from generic Windows Update / Xbox Live, which keep `_nodepot`) get
+        // distinct sessions.
+        format!("_xboxgame:{}", title)
+    } else if fields.service.to_lowercase().contains("epic") {
+        // For Epic entries, use the CDN path prefix (e.g., /Builds/Org/o-xxx/hash/default)
+        // as the session discriminator to keep different games in separate sessions
+        extract_epic_path_prefix(fields.url)
+            .map(|prefix| format!("_epic:{}", prefix))
+            .unwrap_or_else(|| "_nodepot".to_string())
+    } else if let Some(product) = fields.tact_product {
+        // For Blizzard entries, key the session on the RESOLVED game so a
+        // title's multiple CDN paths (e.g. configs + data) canonicalize into
+        // ONE session instead of splitting into several `_tact:<raw-seg>`
+        // sessions. Shared product-agnostic paths collapse together under the
+        // shared label; genuinely unknown segments keep the raw segment so
+        // distinct unknown games still get distinct sessions.
+        match tact_products::resolve_tact_segment(product) {
+            tact_products::TactResolution::Game(name) => format!("_tactgame:{}", name),
+            tact_products::TactResolution::Shared(label) => format!("_tactgame:{}", label),
+            tact_products::TactResolution::Unknown => format!("_tact:{}", product),
+        }
+    } else if let Some(host) = fields.cdn_host {
+        // For Riot entries, key the session on the CDN host because every
+        // game's bundle URL shares the identical path
+        // (/channels/public/bundles/<hash>.bundle) — only the host subdomain
+        // (lol/valorant/bacon) distinguishes the games. Keying on the resolved
+        // game name keeps a title's traffic in ONE session; unknown hosts keep
+        // the raw host so distinct unknown Riot products still get distinct
+        // sessions instead of collapsing together.
+        match riot_hosts::resolve_riot_host(host) {
+            Some(name) => format!("_riotgame:{}", name),
+            None => format!("_riot:{}", host),
+        }
+    } else if let Some(name) = fields.riot_game {
+        // Same key a resolved host produces, for callers that only know the game.
+        format!("_riotgame:{}", name)
+    } else {
+        "_nodepot".to_string()
+    };
+    format!("{}_{}{}", fields.client_ip, fields.service, depot_suffix)
+}
+
+/// Extract a path prefix from an Epic CDN URL to use as a session discriminator.
+/// Epic CDN URLs follow the pattern: /Builds/Org/o-<orgHash>/<buildHash>/default/<chunkFile>
+/// We extract the first 5 segments (/Builds/Org/o-xxx/hash/default) which uniquely identify a game.
+/// Returns None if the URL doesn't have enough segments, falling back to `_nodepot` behavior.
+pub fn extract_epic_path_prefix(url: &str) -> Option<String> {
+    // Split the URL path into segments, skipping empty segments from leading slash
+    let segments: Vec<&str> = url.split('/').filter(|s| !s.is_empty()).collect();
+    // Need at least 5 segments for a meaningful Epic CDN path prefix
+    if segments.len() >= 5 {
+        // Rejoin the first 5 segments as the prefix key
+        Some(format!("/{}", segments[..5].join("/")))
+    } else {
+        None
+    }
+}
+
+/// True for lancache service tags that carry Xbox / Microsoft Store delivery traffic. Two shapes
+/// reach the cache: Delivery-Optimization CLIENT traffic tagged `wsus` (shared with generic
+/// Windows Update, over `/filestreamingservice/files/<GUID>`), and prefill-daemon traffic pulled
+/// direct from assets1.xboxlive.com tagged `xboxlive` (over `/<d>/<guid>/<guid>/<ver>.<guid>/<pkg>`).
+/// Both must be considered, but we only canonicalize the rows whose URL also matches a stored
+/// Xbox fragment, so generic OS updates and generic Xbox Live traffic are untouched. Mirrors the
+/// C# `ResolveDownloadsAsync` candidate filter (`%wsus%` OR `%xboxlive%`).
+pub fn is_xbox_cache_service(service: &str) -> bool {
+    let s = service.to_lowercase();
+    s.contains("wsus") || s.contains("xboxlive")
+}
+
+/// Keep ONLY well-formed `/filestreamingservice/files/<GUID>` fragments that carry a title.
+/// Empty / "/" / non-GUID fragments would `contains()`-match generic wsus URLs and relabel
+/// Windows Update traffic as a game — the same shape guard the C# resolver
+/// (XboxMappingService.IsValidFragment) applies. This Rust path is the PRIMARY canonicalizer,
+/// so the guard MUST live here too.
+/// A blank title is dropped exactly like an absent one. This loader is the only source of Xbox
+/// names, and a match canonicalizes the download to `Service='xbox'` with `GameName` = the
+/// title. An empty `GameName` splits the codebase — the identity key rule buckets it as
+/// `steam:0` while the detection queries test the column against NULL and call it a named game
+/// — and the C# re-resolver only ever revisits `wsus`/`xboxlive` rows, so an `xbox` row stamped
+/// with `""` can never be given its real title. Dropping the pattern keeps the URL generic
+/// `wsus`, which stays re-resolvable once a real title arrives.
+pub fn xbox_pattern_row(
+    fragment: Option<String>,
+    title: Option<String>,
+    product_id: Option<String>,
+) -> Option<(String, String, String)> {
+    match (fragment, title, product_id) {
+        (Some(frag), Some(name), Some(pid))
+            if cache_utils::is_valid_xbox_fragment(&frag) && !name.trim().is_empty() =>
+        {
+            Some((frag, name, pid))
+        }
+        _ => None,
+    }
+}
+
+/// ASCII-case-insensitively find the (longest-first) Xbox pattern whose fragment is contained in
+/// `url`. Xbox CDN fragments are `/filestreamingservice/files/<GUID>` paths; the GUID hex casing
+/// the daemon stores (from the manifest URI) can differ from the casing in the nginx access-log
+/// URL, so a case-sensitive `contains` would miss a real match and leave the row generic `wsus`.
+/// The C# resolver (XboxMappingService.cs) already compares with `StringComparison.OrdinalIgnoreCase`;
+/// this keeps the primary Rust canonicalizer consistent. ASCII lowercasing is exact for these
+/// paths, and callers cache per URL so each unique URL is lowercased at most once.
+pub fn match_xbox_fragment<'a>(
+    patterns: &'a [(String, String, String)],
+    url: &str,
+) -> Option<&'a (String, String, String)> {
+    let url_lower = url.to_ascii_lowercase();
+    patterns
+        .iter()
+        .find(|(fragment, _, _)| url_lower.contains(&fragment.to_ascii_lowercase()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields<'a>(client_ip: &'a str, service: &'a str, url: &'a str) -> SessionFields<'a> {
+        SessionFields {
+            client_ip,
+            service,
+            url,
+            ..SessionFields::default()
+        }
+    }
+
+    #[test]
+    fn depot_keys_split_games_for_one_client() {
+        let a = SessionFields {
+            depot_id: Some(731),
+            ..fields("10.0.0.5", "steam", "/depot/731/chunk/a")
+        };
+        let b = SessionFields {
+            depot_id: Some(732),
+            ..fields("10.0.0.5", "steam", "/depot/732/chunk/b")
+        };
+
+        assert_eq!(session_key(&a), "10.0.0.5_steam_731");
+        assert_ne!(session_key(&a), session_key(&b));
+    }
+
+    #[test]
+    fn epic_keys_on_the_build_prefix() {
+        let key = session_key(&fields(
+            "10.0.0.5",
+            "epicgames",
+            "/Builds/Org/o-abc/hash/default/ChunksV4/00/1.chunk",
+        ));
+
+        assert_eq!(key, "10.0.0.5_epicgames_epic:/Builds/Org/o-abc/hash/default");
+    }
+
+    #[test]
+    fn a_known_riot_game_keys_like_its_resolved_host() {
+        let from_host = SessionFields {
+            cdn_host: Some("lol.dyn.riotcdn.net"),
+            ..fields("10.0.0.5", "riot", "/channels/public/bundles/x.bundle")
+        };
+        let from_game = SessionFields {
+            riot_game: Some("League of Legends"),
+            ..fields("10.0.0.5", "riot", "/channels/public/bundles/x.bundle")
+        };
+
+        assert_eq!(session_key(&from_host), session_key(&from_game));
+    }
+
+    #[test]
+    fn xbox_title_wins_over_the_generic_wsus_key() {
+        let generic = fields("10.0.0.5", "wsus", "/filestreamingservice/files/x");
+        let titled = SessionFields {
+            xbox_title: Some("Halo"),
+            ..generic
+        };
+
+        assert_eq!(session_key(&generic), "10.0.0.5_wsus_nodepot");
+        assert_eq!(session_key(&titled), "10.0.0.5_wsus_xboxgame:Halo");
+    }
+}
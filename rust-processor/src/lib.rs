@@ -4,13 +4,23 @@
 //! copy per binary and made Rust report anything one binary did not call as dead code. Owning
 //! them here means the analysis runs once, against the whole surface.
 
+pub mod cache_archive;
 pub mod cache_corruption_detector;
+pub mod cache_inventory_store;
 pub mod cache_structural_scanner;
 pub mod cache_structural_state;
 pub mod cache_utils;
+pub mod cached_game_value;
+pub mod corruption_cleanup;
 pub mod cancel;
+pub mod cli_flags;
 pub mod content_scan;
 pub mod db;
+pub mod db_backup;
+pub mod db_scope;
+pub mod db_stats;
+pub mod download_sessions;
+pub mod local_http;
 pub mod log_discovery;
 pub mod log_layout;
 pub mod log_purge;
@@ -21,8 +31,16 @@ pub mod parser;
 pub mod parser_http_detailed;
 pub mod progress_events;
 pub mod progress_utils;
+pub mod protected_games;
+pub mod quarantine;
+pub mod rekey_core;
 pub mod removal_core;
+pub mod removal_plan;
 pub mod riot_hosts;
 pub mod service_utils;
 pub mod session;
+pub mod session_health;
+pub mod snapshot_server;
+pub mod speed_history;
+pub mod speed_metrics;
 pub mod tact_products;
@@ -0,0 +1,172 @@
+//! The little HTTP/1.1 the tracker's local endpoints need: read one request head, answer it.
+//!
+//! `speed_metrics` serves a single scrape route and `snapshot_server` a long-lived stream, so
+//! neither needs routing, bodies, keep-alive or chunked encoding. What they share is parsing the
+//! request line, headers and query string under a size and time limit, which lives here.
+
+use anyhow::{Context, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Largest request head accepted. Scrapers and browsers send well under this.
+const MAX_REQUEST_HEAD_BYTES: usize = 8 * 1024;
+
+/// How long a connection may take to send its request head before it is dropped.
+const REQUEST_TIMEOUT_SECONDS: u64 = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestHead {
+    pub method: String,
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    headers: Vec<(String, String)>,
+}
+
+impl RequestHead {
+    /// Case-insensitive header lookup; the first occurrence wins.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Whether a comma-separated header (`Connection`, `Upgrade`) lists `token`.
+    pub fn header_has_token(&self, name: &str, token: &str) -> bool {
+        self.header(name)
+            .is_some_and(|value| value.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)))
+    }
+}
+
+/// Read and parse a request head, giving up after the size or time limit.
+pub async fn read_request_head<S: AsyncRead + Unpin>(stream: &mut S) -> Result<RequestHead> {
+    let timeout = std::time::Duration::from_secs(REQUEST_TIMEOUT_SECONDS);
+    let raw = tokio::time::timeout(timeout, read_raw_head(stream))
+        .await
+        .context("timed out reading request")??;
+    parse_request_head(&raw)
+}
+
+async fn read_raw_head<S: AsyncRead + Unpin>(stream: &mut S) -> Result<String> {
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    // Byte at a time so nothing past the blank line is consumed: a WebSocket client may send its
+    // first frame right behind the handshake, and that belongs to the caller.
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_REQUEST_HEAD_BYTES {
+            anyhow::bail!("request head exceeds {MAX_REQUEST_HEAD_BYTES} bytes");
+        }
+        if stream.read(&mut byte).await? == 0 {
+            break;
+        }
+        head.push(byte[0]);
+    }
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
+
+fn parse_request_head(raw: &str) -> Result<RequestHead> {
+    let mut lines = raw.split("\r\n");
+    let request_line = lines.next().unwrap_or_default();
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        anyhow::bail!("malformed request line");
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let headers = lines
+        .take_while(|line| !line.is_empty())
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect();
+    Ok(RequestHead {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: parse_query(query),
+        headers,
+    })
+}
+
+fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect()
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let decoded = std::str::from_utf8(&bytes[i + 1..i + 3])
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                match decoded {
+                    Some(decoded) => {
+                        out.push(decoded);
+                        i += 2;
+                    }
+                    None => out.push(b'%'),
+                }
+            }
+            other => out.push(other),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Write a complete response and close the write side.
+pub async fn write_response<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> Result<()> {
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_request_line_headers_and_query() {
+        let head = parse_request_head(
+            "GET /snapshots?service=steam&client=10.0.0.1&datasource=Main%20Cache HTTP/1.1\r\n\
+             Host: localhost\r\nConnection: keep-alive, Upgrade\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(head.method, "GET");
+        assert_eq!(head.path, "/snapshots");
+        assert_eq!(
+            head.query,
+            vec![
+                ("service".to_string(), "steam".to_string()),
+                ("client".to_string(), "10.0.0.1".to_string()),
+                ("datasource".to_string(), "Main Cache".to_string()),
+            ]
+        );
+        assert_eq!(head.header("host"), Some("localhost"));
+        assert!(head.header_has_token("Connection", "upgrade"));
+        assert!(parse_request_head("\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn malformed_percent_escapes_are_kept_literally() {
+        assert_eq!(percent_decode("a%2"), "a%2");
+        assert_eq!(percent_decode("a%zzb"), "a%zzb");
+        assert_eq!(percent_decode("a%2Cb+c"), "a,b c");
+    }
+}
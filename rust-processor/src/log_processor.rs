@@ -16,6 +16,7 @@ use std::time::{Duration, Instant};
 use lancache_processor::cache_utils;
 use lancache_processor::cancel;
 use lancache_processor::db;
+use lancache_processor::download_sessions;
 use lancache_processor::log_discovery;
 use lancache_processor::log_layout;
 use lancache_processor::log_reader;
@@ -168,7 +169,6 @@ fn load_positions(path: &str) -> Result<HashMap<String, u64>> {
 }
 
 const BULK_BATCH_SIZE: usize = 5_000;
-const SESSION_GAP_MINUTES: i64 = 5;
 const LINE_BUFFER_CAPACITY: usize = 1024;
 const LOG_ENTRY_INSERT_SQL: &str = r#"INSERT INTO "LogEntries" ("Timestamp", "ClientIp", "Service", "Method", "HttpRange", "Url", "StatusCode", "BytesServed", "CacheStatus", "DepotId", "DownloadId", "CreatedAt", "Datasource")
        SELECT * FROM UNNEST($1::timestamptz[], $2::text[], $3::text[], $4::text[], $5::text[], $6::text[], $7::int[], $8::bigint[], $9::text[], $10::bigint[], $11::bigint[], $12::timestamptz[], $13::text[])"#;
@@ -438,7 +438,7 @@ impl Processor {
             parser: LogParser::new(local_tz),
             detailed_parser: HttpDetailedParser::new(local_tz),
             session_tracker: SessionTracker::new(Duration::from_secs(
-                SESSION_GAP_MINUTES as u64 * 60,
+                download_sessions::SESSION_GAP_MINUTES as u64 * 60,
             )),
             total_lines: AtomicU64::new(0),
             lines_parsed: AtomicU64::new(0),
@@ -1032,22 +1032,6 @@ impl Processor {
         Ok(FileProcessingOutcome::Completed)
     }
 
-    /// Extract a path prefix from an Epic CDN URL to use as a session discriminator.
-    /// Epic CDN URLs follow the pattern: /Builds/Org/o-<orgHash>/<buildHash>/default/<chunkFile>
-    /// We extract the first 5 segments (/Builds/Org/o-xxx/hash/default) which uniquely identify a game.
-    /// Returns None if the URL doesn't have enough segments, falling back to `_nodepot` behavior.
-    fn extract_epic_path_prefix(url: &str) -> Option<String> {
-        // Split the URL path into segments, skipping empty segments from leading slash
-        let segments: Vec<&str> = url.split('/').filter(|s| !s.is_empty()).collect();
-        // Need at least 5 segments for a meaningful Epic CDN path prefix
-        if segments.len() >= 5 {
-            // Rejoin the first 5 segments as the prefix key
-            Some(format!("/{}", segments[..5].join("/")))
-        } else {
-            None
-        }
-    }
-
     async fn process_batch(&mut self, entries: &[LogEntry]) -> Result<()> {
         if entries.is_empty() {
             return Ok(());
@@ -1061,7 +1045,7 @@ impl Processor {
         // wsus/xboxlive entries that match no Xbox fragment resolve to None and stay generic.
         let mut entry_xbox_titles: Vec<Option<String>> = Vec::with_capacity(entries.len());
         for entry in entries {
-            if Self::is_xbox_cache_service(&entry.service) {
+            if download_sessions::is_xbox_cache_service(&entry.service) {
                 entry_xbox_titles.push(
                     self.lookup_xbox_game(&entry.url)
                         .await
@@ -1077,48 +1061,16 @@ impl Processor {
         // so different Epic games get separate sessions instead of being merged into one
         let mut grouped: HashMap<String, Vec<&LogEntry>> = HashMap::new();
         for (entry, xbox_title) in entries.iter().zip(entry_xbox_titles.iter()) {
-            let depot_suffix = if let Some(id) = entry.depot_id {
-                format!("_{}", id)
-            } else if let Some(title) = xbox_title {
-                // Xbox content is tagged `wsus` (DO-client) or `xboxlive` (prefill, assets1) over
-                // opaque CDN URLs; key the session on the resolved title so distinct Xbox games (and
-                // distinct from generic Windows Update / Xbox Live, which keep `_nodepot`) get
-                // distinct sessions.
-                format!("_xboxgame:{}", title)
-            } else if entry.service.to_lowercase().contains("epic") {
-                // For Epic entries, use the CDN path prefix (e.g., /Builds/Org/o-xxx/hash/default)
-                // as the session discriminator to keep different games in separate sessions
-                Self::extract_epic_path_prefix(&entry.url)
-                    .map(|prefix| format!("_epic:{}", prefix))
-                    .unwrap_or_else(|| "_nodepot".to_string())
-            } else if let Some(product) = entry.tact_product.as_deref() {
-                // For Blizzard entries, key the session on the RESOLVED game so a
-                // title's multiple CDN paths (e.g. configs + data) canonicalize into
-                // ONE session instead of splitting into several `_tact:<raw-seg>`
-                // sessions. Shared product-agnostic paths collapse together under the
-                // shared label; genuinely unknown segments keep the raw segment so
-                // distinct unknown games still get distinct sessions.
-                match tact_products::resolve_tact_segment(product) {
-                    tact_products::TactResolution::Game(name) => format!("_tactgame:{}", name),
-                    tact_products::TactResolution::Shared(label) => format!("_tactgame:{}", label),
-                    tact_products::TactResolution::Unknown => format!("_tact:{}", product),
-                }
-            } else if let Some(host) = entry.cdn_host.as_deref() {
-                // For Riot entries, key the session on the CDN host because every
-                // game's bundle URL shares the identical path
-                // (/channels/public/bundles/<hash>.bundle) — only the host subdomain
-                // (lol/valorant/bacon) distinguishes the games. Keying on the resolved
-                // game name keeps a title's traffic in ONE session; unknown hosts keep
-                // the raw host so distinct unknown Riot products still get distinct
-                // sessions instead of collapsing together.
-                match riot_hosts::resolve_riot_host(host) {
-                    Some(name) => format!("_riotgame:{}", name),
-                    None => format!("_riot:{}", host),
-                }
-            } else {
-                "_nodepot".to_string()
-            };
-            let key = format!("{}_{}{}", entry.client_ip, entry.service, depot_suffix);
+            let key = download_sessions::session_key(&download_sessions::SessionFields {
+                client_ip: &entry.client_ip,
+                service: &entry.service,
+                url: &entry.url,
+                depot_id: entry.depot_id,
+                tact_product: entry.tact_product.as_deref(),
+                cdn_host: entry.cdn_host.as_deref(),
+                riot_game: None,
+                xbox_title: xbox_title.as_deref(),
+            });
             grouped.entry(key).or_insert_with(Vec::new).push(entry);
         }
 
@@ -1186,18 +1138,6 @@ impl Processor {
         }
     }
 
-    /// True for lancache service tags that carry Xbox / Microsoft Store delivery traffic. Two shapes
-    /// reach the cache: Delivery-Optimization CLIENT traffic tagged `wsus` (shared with generic
-    /// Windows Update, over `/filestreamingservice/files/<GUID>`), and prefill-daemon traffic pulled
-    /// direct from assets1.xboxlive.com tagged `xboxlive` (over `/<d>/<guid>/<guid>/<ver>.<guid>/<pkg>`).
-    /// Both must be considered, but we only canonicalize the rows whose URL also matches a stored
-    /// Xbox fragment, so generic OS updates and generic Xbox Live traffic are untouched. Mirrors the
-    /// C# `ResolveDownloadsAsync` candidate filter (`%wsus%` OR `%xboxlive%`).
-    fn is_xbox_cache_service(service: &str) -> bool {
-        let s = service.to_lowercase();
-        s.contains("wsus") || s.contains("xboxlive")
-    }
-
     /// Reload the Xbox CDN fragment -> title patterns from the DB, throttled to
     /// `XBOX_PATTERN_RELOAD`. The tables fill over time as daemons contribute fragments, so we
     /// reload periodically during a long run. Errors (e.g. tables not created yet) are ignored.
@@ -1208,21 +1148,16 @@ impl Processor {
             }
         }
 
-        let result = sqlx::query(
-            "SELECT p.\"UrlFragment\", COALESCE(m.\"Title\", p.\"Title\") AS \"Title\", p.\"ProductId\" \
-             FROM \"XboxCdnPatterns\" p \
-             LEFT JOIN \"XboxGameMappings\" m ON p.\"ProductId\" = m.\"ProductId\" \
-             ORDER BY LENGTH(p.\"UrlFragment\") DESC"
-        )
-        .fetch_all(&self.pool)
-        .await;
+        let result = sqlx::query(download_sessions::XBOX_PATTERNS_SQL)
+            .fetch_all(&self.pool)
+            .await;
 
         match result {
             Ok(rows) => {
                 self.xbox_patterns = rows
                     .iter()
                     .filter_map(|row| {
-                        Self::xbox_pattern_row(
+                        download_sessions::xbox_pattern_row(
                             row.get("UrlFragment"),
                             row.get("Title"),
                             row.get("ProductId"),
@@ -1246,33 +1181,6 @@ impl Processor {
         }
     }
 
-    /// Keep ONLY well-formed `/filestreamingservice/files/<GUID>` fragments that carry a title.
-    /// Empty / "/" / non-GUID fragments would `contains()`-match generic wsus URLs and relabel
-    /// Windows Update traffic as a game — the same shape guard the C# resolver
-    /// (XboxMappingService.IsValidFragment) applies. This Rust path is the PRIMARY canonicalizer,
-    /// so the guard MUST live here too.
-    /// A blank title is dropped exactly like an absent one. This loader is the only source of Xbox
-    /// names, and a match canonicalizes the download to `Service='xbox'` with `GameName` = the
-    /// title. An empty `GameName` splits the codebase — the identity key rule buckets it as
-    /// `steam:0` while the detection queries test the column against NULL and call it a named game
-    /// — and the C# re-resolver only ever revisits `wsus`/`xboxlive` rows, so an `xbox` row stamped
-    /// with `""` can never be given its real title. Dropping the pattern keeps the URL generic
-    /// `wsus`, which stays re-resolvable once a real title arrives.
-    fn xbox_pattern_row(
-        fragment: Option<String>,
-        title: Option<String>,
-        product_id: Option<String>,
-    ) -> Option<(String, String, String)> {
-        match (fragment, title, product_id) {
-            (Some(frag), Some(name), Some(pid))
-                if cache_utils::is_valid_xbox_fragment(&frag) && !name.trim().is_empty() =>
-            {
-                Some((frag, name, pid))
-            }
-            _ => None,
-        }
-    }
-
     /// Resolve a `wsus`/`xboxlive` URL to its Xbox `(title, product_id)` via a stored fragment match
     /// (longest-first), or None if it is generic Windows Update / Xbox Live. Per-URL cached (None
     /// caches too, so a confirmed non-match is never re-walked). Loads the patterns on first use.
@@ -1287,7 +1195,7 @@ impl Processor {
 
         self.load_xbox_patterns().await;
 
-        let result = Self::match_xbox_fragment(&self.xbox_patterns, url)
+        let result = download_sessions::match_xbox_fragment(&self.xbox_patterns, url)
             .map(|(_, name, pid)| (name.clone(), pid.clone()));
 
         match &result {
@@ -1301,23 +1209,6 @@ impl Processor {
         result
     }
 
-    /// ASCII-case-insensitively find the (longest-first) Xbox pattern whose fragment is contained in
-    /// `url`. Xbox CDN fragments are `/filestreamingservice/files/<GUID>` paths; the GUID hex casing
-    /// the daemon stores (from the manifest URI) can differ from the casing in the nginx access-log
-    /// URL, so a case-sensitive `contains` would miss a real match and leave the row generic `wsus`.
-    /// The C# resolver (XboxMappingService.cs) already compares with `StringComparison.OrdinalIgnoreCase`;
-    /// this keeps the primary Rust canonicalizer consistent. ASCII lowercasing is exact for these
-    /// paths, and `lookup_xbox_game`'s per-URL cache means each unique URL is lowercased at most once.
-    fn match_xbox_fragment<'a>(
-        patterns: &'a [(String, String, String)],
-        url: &str,
-    ) -> Option<&'a (String, String, String)> {
-        let url_lower = url.to_ascii_lowercase();
-        patterns
-            .iter()
-            .find(|(fragment, _, _)| url_lower.contains(&fragment.to_ascii_lowercase()))
-    }
-
     /// For a batch of `wsus`/`xboxlive` entries, pick the dominant resolved Xbox game (most frequent
     /// match). Returns `("xbox", Some(title), Some(product_id))` when the batch is a recognized Xbox
     /// download, else `(service, None, None)` so unmatched traffic stays generic Windows Update /
@@ -1328,7 +1219,7 @@ impl Processor {
         service: &str,
         new_entries: &[&LogEntry],
     ) -> (String, Option<String>, Option<String>) {
-        if !Self::is_xbox_cache_service(service) {
+        if !download_sessions::is_xbox_cache_service(service) {
             return (service.to_string(), None, None);
         }
 
@@ -1732,7 +1623,7 @@ impl Processor {
                 .map(|r| r.get::<i64, _>("Id"))
             } else if service.to_lowercase().contains("epic") {
                 // For Epic services, match by URL path prefix to find the correct game session
-                if let Some(path_prefix) = last_url.and_then(|u| Self::extract_epic_path_prefix(u))
+                if let Some(path_prefix) = last_url.and_then(download_sessions::extract_epic_path_prefix)
                 {
                     let like_pattern = format!("{}%", path_prefix);
                     sqlx::query(
@@ -2545,7 +2436,10 @@ mod log_entry_clamp_tests {
 
 #[cfg(test)]
 mod xbox_fragment_guard_tests {
-    use super::{Processor, LOG_ENTRY_INSERT_SQL};
+    use super::LOG_ENTRY_INSERT_SQL;
+    use lancache_processor::download_sessions::{
+        is_xbox_cache_service, match_xbox_fragment, xbox_pattern_row,
+    };
     // The shape guard now lives in the shared `cache_utils` module so `log_processor` and
     // `speed_tracker` apply ONE identical check. These tests exercise it through the same path
     // the log_processor pattern loader uses.
@@ -2586,7 +2480,7 @@ mod xbox_fragment_guard_tests {
     #[test]
     fn pattern_row_keeps_a_fragment_that_has_a_title() {
         let frag = format!("/filestreamingservice/files/{}", GUID);
-        let kept = Processor::xbox_pattern_row(
+        let kept = xbox_pattern_row(
             Some(frag.clone()),
             Some("Halo Infinite".to_string()),
             Some("9NBLGGH537DL".to_string()),
@@ -2606,15 +2500,15 @@ mod xbox_fragment_guard_tests {
         let pid = Some("9NBLGGH537DL".to_string());
 
         assert_eq!(
-            Processor::xbox_pattern_row(Some(frag.clone()), None, pid.clone()),
+            xbox_pattern_row(Some(frag.clone()), None, pid.clone()),
             None
         );
         assert_eq!(
-            Processor::xbox_pattern_row(Some(frag.clone()), Some(String::new()), pid.clone()),
+            xbox_pattern_row(Some(frag.clone()), Some(String::new()), pid.clone()),
             None
         );
         assert_eq!(
-            Processor::xbox_pattern_row(Some(frag), Some("   ".to_string()), pid),
+            xbox_pattern_row(Some(frag), Some("   ".to_string()), pid),
             None
         );
     }
@@ -2677,7 +2571,7 @@ mod xbox_fragment_guard_tests {
             "Halo",
         )];
         let url = "http://assets1.xboxlive.com/filestreamingservice/files/ABCDEF12-3456-7890-ABCD-EF1234567890?P1=1";
-        let m = Processor::match_xbox_fragment(&patterns, url);
+        let m = match_xbox_fragment(&patterns, url);
         assert!(
             m.is_some(),
             "uppercase-URL vs lowercase-fragment must match"
@@ -2693,7 +2587,7 @@ mod xbox_fragment_guard_tests {
         )];
         let url = "http://cdn/filestreamingservice/files/abcdef12-3456-7890-abcd-ef1234567890";
         assert_eq!(
-            Processor::match_xbox_fragment(&patterns, url).unwrap().1,
+            match_xbox_fragment(&patterns, url).unwrap().1,
             "Forza"
         );
     }
@@ -2713,7 +2607,7 @@ mod xbox_fragment_guard_tests {
         ];
         let url = "/FILESTREAMINGSERVICE/FILES/ABCDEF12-3456-7890-ABCD-EF1234567890/EXTRA";
         assert_eq!(
-            Processor::match_xbox_fragment(&patterns, url).unwrap().1,
+            match_xbox_fragment(&patterns, url).unwrap().1,
             "Specific"
         );
     }
@@ -2725,7 +2619,7 @@ mod xbox_fragment_guard_tests {
             "Halo",
         )];
         let url = "http://cdn/c/msdownload/update/software/secu/2024/01/something.cab";
-        assert!(Processor::match_xbox_fragment(&patterns, url).is_none());
+        assert!(match_xbox_fragment(&patterns, url).is_none());
     }
 
     // --- assets1.xboxlive.com prefill fragment shape (the naming-bug fix) ---
@@ -2763,7 +2657,7 @@ mod xbox_fragment_guard_tests {
         // consumer strips) must resolve back to the title.
         let patterns = vec![pattern(BO4_FRAGMENT, BO4_TITLE)];
         let url = format!("http://assets1.xboxlive.com{}?P1=1", BO4_FRAGMENT);
-        let m = Processor::match_xbox_fragment(&patterns, &url);
+        let m = match_xbox_fragment(&patterns, &url);
         assert!(
             m.is_some(),
             "stored BO4 fragment must match the same access-log URL"
@@ -2775,10 +2669,10 @@ mod xbox_fragment_guard_tests {
     fn xbox_cache_service_guard_includes_xboxlive_and_wsus_not_steam() {
         // The consumer guard must fire for BOTH tags Xbox traffic lands under, and never for an
         // unrelated service (so a steam row is never relabeled).
-        assert!(Processor::is_xbox_cache_service("xboxlive"));
-        assert!(Processor::is_xbox_cache_service("XBOXLIVE"));
-        assert!(Processor::is_xbox_cache_service("wsus"));
-        assert!(!Processor::is_xbox_cache_service("steam"));
-        assert!(!Processor::is_xbox_cache_service("epicgames"));
+        assert!(is_xbox_cache_service("xboxlive"));
+        assert!(is_xbox_cache_service("XBOXLIVE"));
+        assert!(is_xbox_cache_service("wsus"));
+        assert!(!is_xbox_cache_service("steam"));
+        assert!(!is_xbox_cache_service("epicgames"));
     }
 }
@@ -24,12 +24,13 @@
 //    rotated logs are archival, slightly larger output is accepted).
 
 use anyhow::{Context, Result};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::collections::HashSet;
 use std::fs::File;
 use std::io::{BufWriter, Write as IoWrite};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use aho_corasick::AhoCorasick;
 use flate2::write::GzEncoder;
@@ -53,7 +54,8 @@ pub struct RemovalPrefilter {
 
 /// One exact stored corruption observation. Matching every field keeps log cleanup inside the
 /// immutable evidence window; a URL match alone is intentionally insufficient.
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "StoredObservation", try_from = "StoredObservation")]
 pub struct ExactLogObservation {
     pub service: String,
     pub raw_url: String,
@@ -66,6 +68,58 @@ pub struct ExactLogObservation {
     pub raw_range: Option<String>,
 }
 
+/// Serialized form of an [`ExactLogObservation`] (a quarantine manifest keeps them until purge).
+/// chrono is built without serde, so the timestamp is RFC 3339 with full precision.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StoredObservation {
+    service: String,
+    raw_url: String,
+    timestamp: String,
+    client_ip: String,
+    method: String,
+    http_status: i32,
+    bytes_served: i64,
+    cache_status: String,
+    raw_range: Option<String>,
+}
+
+impl From<ExactLogObservation> for StoredObservation {
+    fn from(observation: ExactLogObservation) -> Self {
+        Self {
+            service: observation.service,
+            raw_url: observation.raw_url,
+            timestamp: observation
+                .timestamp
+                .to_rfc3339_opts(SecondsFormat::Nanos, true),
+            client_ip: observation.client_ip,
+            method: observation.method,
+            http_status: observation.http_status,
+            bytes_served: observation.bytes_served,
+            cache_status: observation.cache_status,
+            raw_range: observation.raw_range,
+        }
+    }
+}
+
+impl TryFrom<StoredObservation> for ExactLogObservation {
+    type Error = chrono::ParseError;
+
+    fn try_from(stored: StoredObservation) -> std::result::Result<Self, Self::Error> {
+        Ok(Self {
+            service: stored.service,
+            raw_url: stored.raw_url,
+            timestamp: DateTime::parse_from_rfc3339(&stored.timestamp)?.with_timezone(&Utc),
+            client_ip: stored.client_ip,
+            method: stored.method,
+            http_status: stored.http_status,
+            bytes_served: stored.bytes_served,
+            cache_status: stored.cache_status,
+            raw_range: stored.raw_range,
+        })
+    }
+}
+
 /// Hash-set matcher and safe raw-line prefilter derived from exact observations.
 pub struct ExactLogMatcher {
     observations: HashSet<ExactLogObservation>,
@@ -593,6 +647,111 @@ pub fn remove_log_entries_for_service(
     )
 }
 
+/// The line predicate of a planned removal, and its prefilter. A line matches when its URL is in
+/// `urls` or its depot id is in `depot_ids`, and, when `service` is given, it also carries that
+/// service: the union of the predicates of [`remove_log_entries_for_game`] and
+/// [`remove_log_entries_for_service`]. With `logged_until`, lines logged after it never match,
+/// so traffic that arrived after the removal was planned stays in the logs.
+fn removal_predicate<'a>(
+    service: Option<&str>,
+    urls: &'a HashSet<String>,
+    depot_ids: &'a HashSet<u32>,
+    logged_until: Option<NaiveDateTime>,
+) -> Result<(RemovalPrefilter, impl Fn(&LogEntry) -> bool + Send + Sync + 'a)> {
+    let normalized_service = service.map(service_utils::normalize_service_name);
+    let patterns = urls
+        .iter()
+        .map(|url| Cow::<[u8]>::Borrowed(url.as_bytes()))
+        .chain(
+            depot_ids
+                .iter()
+                .map(|depot_id| Cow::<[u8]>::Owned(format!("/depot/{depot_id}/").into_bytes())),
+        );
+    let prefilter = RemovalPrefilter::new(patterns)?;
+    let should_remove_entry = move |entry: &LogEntry| {
+        logged_until.is_none_or(|until| entry.timestamp <= until)
+            && normalized_service
+                .as_ref()
+                .is_none_or(|service| &entry.service == service)
+            && (urls.contains(&entry.url)
+                || entry
+                    .depot_id
+                    .is_some_and(|depot_id| depot_ids.contains(&depot_id)))
+    };
+    Ok((prefilter, should_remove_entry))
+}
+
+/// Per-file count of the lines a removal would purge (see [`removal_predicate`]), without
+/// rewriting anything. Files without a match are left out.
+pub fn count_log_entries_for_removal(
+    log_dir: &Path,
+    service: Option<&str>,
+    urls: &HashSet<String>,
+    depot_ids: &HashSet<u32>,
+    logged_until: Option<NaiveDateTime>,
+) -> Result<Vec<(PathBuf, u64)>> {
+    use rayon::prelude::*;
+
+    let (prefilter, should_remove_entry) =
+        removal_predicate(service, urls, depot_ids, logged_until)?;
+    let cachelog = LogParser::new(chrono_tz::UTC);
+    let detailed = HttpDetailedParser::new(chrono_tz::UTC);
+    let log_files: Vec<_> = discover_log_sources(log_dir)?
+        .sources
+        .into_iter()
+        .flat_map(|source| {
+            let source_kind = source.kind;
+            source
+                .files
+                .into_iter()
+                .map(move |file| (file.path, source_kind.clone()))
+        })
+        .collect();
+
+    let mut counts = log_files
+        .par_iter()
+        .map(|(path, source_kind)| {
+            let (_lines_total, lines_matched) = scan_file_for_matches(
+                path,
+                &prefilter,
+                &cachelog,
+                &detailed,
+                source_kind,
+                &should_remove_entry,
+            )
+            .with_context(|| format!("failed to scan {}", path.display()))?;
+            Ok((path.clone(), lines_matched))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    counts.retain(|(_path, lines)| *lines > 0);
+    counts.sort();
+    Ok(counts)
+}
+
+/// Rewrites the access logs without the lines [`count_log_entries_for_removal`] counts for the
+/// same arguments. Returns `(lines_removed, permission_errors)`.
+pub fn remove_log_entries_for_removal(
+    log_dir: &Path,
+    service: Option<&str>,
+    urls: &HashSet<String>,
+    depot_ids: &HashSet<u32>,
+    logged_until: Option<NaiveDateTime>,
+) -> Result<(u64, usize)> {
+    let (prefilter, should_remove_entry) =
+        removal_predicate(service, urls, depot_ids, logged_until)?;
+    let description = match service {
+        Some(service) => format!("service '{}'", service),
+        None => "game".to_string(),
+    };
+    rewrite_matching_log_entries(
+        log_dir,
+        &description,
+        &prefilter,
+        should_remove_entry,
+        None,
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -668,6 +827,36 @@ mod tests {
         output
     }
 
+    #[test]
+    fn removal_count_matches_the_purge_and_leaves_the_file_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("access.log");
+        let contents = [
+            log_line("/depot/1/chunk/aa", "MISS"),
+            log_line("/depot/1/chunk/aa", "HIT"),
+            log_line("/depot/2/chunk/bb", "HIT"),
+            log_line("/depot/3/chunk/cc", "HIT"),
+        ]
+        .join("\n")
+            + "\n";
+        fs::write(&log_path, &contents).unwrap();
+        let urls: HashSet<String> = ["/depot/1/chunk/aa".to_string()].into_iter().collect();
+        let depot_ids: HashSet<u32> = [2].into_iter().collect();
+
+        let counts = count_log_entries_for_removal(dir.path(), None, &urls, &depot_ids, None).unwrap();
+        assert_eq!(counts, vec![(log_path.clone(), 3)]);
+        assert_eq!(fs::read_to_string(&log_path).unwrap(), contents);
+        assert!(
+            count_log_entries_for_removal(dir.path(), Some("epic"), &urls, &depot_ids, None)
+                .unwrap()
+                .is_empty()
+        );
+
+        let (removed, _) =
+            remove_log_entries_for_game(dir.path(), &urls, &depot_ids, None).unwrap();
+        assert_eq!(removed, 3);
+    }
+
     #[test]
     fn exact_matcher_keeps_same_url_with_different_observation_identity() {
         let dir = tempfile::tempdir().unwrap();
@@ -742,6 +931,17 @@ mod tests {
         assert!(remaining.contains(&keep_bytes));
     }
 
+    #[test]
+    fn exact_observation_round_trips_through_json_with_subsecond_timestamp() {
+        let mut observation = target_observation();
+        observation.timestamp += chrono::Duration::nanoseconds(123_456_789);
+        let json = serde_json::to_value(&observation).unwrap();
+        assert_eq!(json["timestamp"], "2024-01-01T00:00:00.123456789Z");
+        assert_eq!(json["rawRange"], "bytes=1048576-2097151");
+        let restored: ExactLogObservation = serde_json::from_value(json).unwrap();
+        assert_eq!(restored, observation);
+    }
+
     #[test]
     fn exact_matcher_preserves_scope_in_gzip_and_zstd_logs() {
         let dir = tempfile::tempdir().unwrap();
@@ -30,4 +30,10 @@ pub struct LogEntry {
     /// URLs carry no product slug, so the host is the only per-game discriminator — used
     /// to map a Riot download to a game name and to group/dedup sessions per game.
     pub cdn_host: Option<String>,
+    /// Bytes nginx fetched from the origin for this request (`$upstream_response_length`, summed
+    /// across upstream retries). Only http-detailed records carry it; None for cachelog lines and
+    /// when the request never went upstream.
+    pub upstream_bytes: Option<i64>,
+    /// Seconds nginx spent on the request (`$request_time`). Only http-detailed records carry it.
+    pub request_time_secs: Option<f64>,
 }
@@ -10,6 +10,12 @@
 //! report are all delegated to [`crate::removal_core`] (the tail shared with the
 //! Steam and Epic bins). This module owns only the name-keyed HEAD: the DB queries
 //! that map `(service, game_name)` to URLs and the DB-row delete.
+//!
+//! A game on the protection list is refused outright (a `--plan` of one only lists its slices
+//! as protected); files it shares with the target are kept and listed in the report's
+//! `protected_files_skipped`. `--plan`/`--apply` go through [`crate::removal_plan`];
+//! `--quarantine-dir` moves the files into a [`crate::quarantine`] batch and leaves the logs
+//! and rows to its purge.
 
 use anyhow::Result;
 use clap::Parser;
@@ -22,9 +28,13 @@ use std::fs;
 use std::path::PathBuf;
 
 use crate::db;
+use crate::db_backup;
 use crate::cache_utils;
 use crate::progress_events::ProgressReporter;
+use crate::protected_games::{self, ProtectedGame, Protection};
+use crate::quarantine::{DeferredCleanup, QuarantineArgs};
 use crate::removal_core::{self, LogScope, ProgressCadence, RemovalStageKeys};
+use crate::removal_plan::{self, PlanRun, PlanStageKeys, PlanTarget, RemovalPlan};
 
 /// Positional args for a name-keyed removal bin. The owning service is pinned by the
 /// wrapper (it is NOT a positional arg), so the contract matches the Epic bin:
@@ -50,6 +60,15 @@ struct Args {
     #[arg(long = "key-scheme", default_value = "monolithic")]
     key_scheme: String,
 
+    #[command(flatten)]
+    backup: db_backup::BackupArgs,
+
+    #[command(flatten)]
+    plan: removal_plan::PlanArgs,
+
+    #[command(flatten)]
+    quarantine: QuarantineArgs,
+
     /// Emit JSON progress events to stdout
     #[arg(short, long)]
     progress: bool,
@@ -62,6 +81,11 @@ struct RemovalReport {
     total_bytes_freed: u64,
     empty_dirs_removed: usize,
     log_entries_removed: u64,
+    /// Cache files left in place because a protected game also uses them.
+    protected_files_skipped: Vec<String>,
+    /// Batch holding the files when they were quarantined instead of deleted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quarantine_batch: Option<String>,
 }
 
 /// Name-keyed services reuse the Steam removal stage keys (`signalr.gameRemove.*`)
@@ -85,6 +109,21 @@ const NAMED_GAME_REMOVE_STARTING_KEY: &str = "signalr.namedRemove.starting";
 /// Stage key for the completed progress event. Same AppID-free rationale.
 const NAMED_GAME_REMOVE_COMPLETE_KEY: &str = "signalr.namedRemove.complete";
 
+const NAMED_PLAN_KEYS: PlanStageKeys = PlanStageKeys {
+    planned: "signalr.namedRemove.planned",
+    cache_removing: "signalr.gameRemove.cache.removing",
+    cache_file_progress: "signalr.gameRemove.cache.file.progress",
+    dirs_cleaning: "signalr.gameRemove.dirs.cleaning",
+    logs_removing: "signalr.gameRemove.logs.removing",
+    db_deleting: "signalr.gameRemove.db.deleting",
+    complete: NAMED_GAME_REMOVE_COMPLETE_KEY,
+};
+
+/// Downloads of the named game (alias `d`, $1 = GameName, $2 = lowercased service), as planned;
+/// the removal backs up and deletes the unprotected ones.
+const NAMED_DOWNLOADS_FILTER: &str = r#"d."GameName" = $1 AND d."GameAppId" IS NULL AND d."EpicAppId" IS NULL
+           AND LOWER(d."Service") = $2"#;
+
 /// Context for the starting progress event. Carries `gameName` and `service`, and
 /// deliberately never a `gameAppId` key — named games (blizzard/riot/xbox) don't have
 /// one. Extracted as a pure fn so the shape is unit-testable without a live removal run.
@@ -239,16 +278,16 @@ async fn delete_named_game_from_database(
 ) -> Result<(u64, u64)> {
     eprintln!("Deleting database records for named game '{}/{}'...", service, game_name);
 
+    // The target was checked against the protection list before anything was deleted; the
+    // predicate re-checks it in case the game was protected while the removal ran.
+    let downloads = protected_games::unprotected_downloads(NAMED_DOWNLOADS_FILTER);
+
     // First, delete LogEntries that reference these downloads (foreign key constraint)
-    let log_result = sqlx::query(
+    let log_result = sqlx::query(&format!(
         "DELETE FROM \"LogEntries\" WHERE \"DownloadId\" IN (
-             SELECT \"Id\" FROM \"Downloads\"
-             WHERE \"GameName\" = $1
-               AND \"GameAppId\" IS NULL
-               AND \"EpicAppId\" IS NULL
-               AND LOWER(\"Service\") = $2
+             SELECT d.\"Id\" FROM \"Downloads\" d WHERE {downloads}
          )"
-    )
+    ))
     .bind(game_name)
     .bind(service)
     .execute(pool)
@@ -257,13 +296,9 @@ async fn delete_named_game_from_database(
     eprintln!("  Deleted {} log entry records", log_entries_deleted);
 
     // Now safe to delete the downloads
-    let downloads_result = sqlx::query(
-        "DELETE FROM \"Downloads\"
-         WHERE \"GameName\" = $1
-           AND \"GameAppId\" IS NULL
-           AND \"EpicAppId\" IS NULL
-           AND LOWER(\"Service\") = $2"
-    )
+    let downloads_result = sqlx::query(&format!(
+        "DELETE FROM \"Downloads\" d WHERE {downloads}"
+    ))
     .bind(game_name)
     .bind(service)
     .execute(pool)
@@ -326,13 +361,51 @@ pub async fn run(service: &str) -> Result<()> {
     }
 
     let pool = db::create_pool().await?;
+    let protection = Protection::load(&pool).await?;
+    // A plan deletes nothing; a protected game's plan lists its slices as protected, which is
+    // what `cache_export` reads.
+    if !args.plan.plan {
+        protection.ensure_not_protected(&ProtectedGame::named(&service, game_name))?;
+    }
 
     removal_core::write_progress(&progress_path, &reporter, "starting", NAMED_GAME_REMOVE_STARTING_KEY, starting_context(game_name, &service), 0.0, 0, 0)?;
 
+    let scheme = cache_utils::active_key_scheme();
+    let target = PlanTarget::Named { service: service.clone(), game_name: game_name.clone() };
+    let plan_run = PlanRun {
+        output_json: &output_json,
+        progress_path: &progress_path,
+        reporter: &reporter,
+        keys: &NAMED_PLAN_KEYS,
+        identity: starting_context(game_name, &service),
+    };
+    if let Some(plan_path) = &args.plan.apply {
+        let plan = RemovalPlan::load(plan_path, &target, &log_dir, &cache_dir, scheme)?;
+        let protected = protection.files(&cache_dir, scheme);
+        let quarantine = args.quarantine.open(&cache_dir, &format!("{}_remove", service))?;
+        return removal_plan::apply(&pool, &plan, &protected, args.backup.policy().as_ref(), quarantine, &plan_run).await;
+    }
+
     // Query database for URLs
     removal_core::write_progress(&progress_path, &reporter, "querying_database", "signalr.gameRemove.db.querying", json!({}), 5.0, 0, 0)?;
     let url_data = get_named_game_urls_from_db(&pool, &service, game_name).await?;
 
+    if args.plan.plan {
+        let plan = removal_plan::plan_url_removal(
+            &pool,
+            target,
+            &log_dir,
+            &cache_dir,
+            scheme,
+            &url_data,
+            &protection.files(&cache_dir, scheme),
+            NAMED_DOWNLOADS_FILTER,
+            |q| q.bind(game_name.to_string()).bind(service.clone()),
+        )
+        .await?;
+        return removal_plan::write_plan(&plan, &plan_run);
+    }
+
     if url_data.is_empty() {
         eprintln!("No URLs found for named game '{}/{}'", service, game_name);
 
@@ -342,6 +415,8 @@ pub async fn run(service: &str) -> Result<()> {
             total_bytes_freed: 0,
             empty_dirs_removed: 0,
             log_entries_removed: 0,
+            protected_files_skipped: Vec::new(),
+            quarantine_batch: None,
         };
 
         let json = serde_json::to_string_pretty(&report)?;
@@ -353,6 +428,20 @@ pub async fn run(service: &str) -> Result<()> {
 
     eprintln!("Found {} unique URLs for '{}/{}'", url_data.len(), service, game_name);
 
+    // Pre-flight backup of the rows `delete_named_game_from_database` removes. A quarantined
+    // removal deletes no rows; the purge of its batch takes the backup instead.
+    let operation = format!("{}_remove", service);
+    let backup = db_backup::backup_download_cascade(
+        &pool,
+        args.backup.policy().filter(|_| args.quarantine.quarantine_dir.is_none()).as_ref(),
+        &operation,
+        json!({ "service": service, "gameName": game_name }),
+        &protected_games::unprotected_downloads(NAMED_DOWNLOADS_FILTER),
+        |q| q.bind(game_name.to_string()).bind(service.clone()),
+    )
+    .await?;
+    let quarantine = args.quarantine.open(&cache_dir, &operation)?;
+
     // Step 1: Remove cache files
     let url_count = url_data.len();
     removal_core::write_progress(&progress_path, &reporter, "removing_cache", "signalr.gameRemove.cache.removing", json!({ "count": url_count }), 10.0, 0, 0)?;
@@ -365,7 +454,10 @@ pub async fn run(service: &str) -> Result<()> {
         &NAMED_STAGE_KEYS,
         ProgressCadence::OnPercentAdvance,
         cache_utils::active_key_scheme(),
+        &protection.files(&cache_dir, cache_utils::active_key_scheme()),
+        quarantine.as_ref(),
     )?;
+    let protected_files_skipped = outcome.protected_file_names();
 
     // If cancellation arrived during cache removal, do directory cleanup and exit 0.
     if crate::cancel::is_cancelled() {
@@ -389,16 +481,68 @@ pub async fn run(service: &str) -> Result<()> {
             total_bytes_freed: outcome.bytes_freed,
             empty_dirs_removed,
             log_entries_removed: 0,
+            protected_files_skipped,
+            quarantine_batch: None,
         };
         let json = serde_json::to_string_pretty(&report)?;
         fs::write(&output_json, json)?;
         return Err(error);
     }
 
+    // Lines of kept files stay, so later scans still see what is on disk.
+    let urls_to_remove: HashSet<String> = url_data
+        .keys()
+        .filter(|url| !outcome.protected_urls.contains(*url))
+        .cloned()
+        .collect();
+
+    // Quarantined: the log purge and row delete are recorded in the batch for its purge.
+    if let Some(quarantine) = quarantine {
+        let database = removal_plan::plan_download_rows(&pool, NAMED_DOWNLOADS_FILTER, |q| {
+            q.bind(game_name.to_string()).bind(service.clone())
+        })
+        .await?;
+        let report = RemovalReport {
+            game_name: game_name.clone(),
+            cache_files_deleted: outcome.deleted_files,
+            total_bytes_freed: outcome.bytes_freed,
+            empty_dirs_removed,
+            log_entries_removed: 0,
+            protected_files_skipped,
+            quarantine_batch: Some(quarantine.id().to_string()),
+        };
+        let finished = removal_core::finish_quarantine(
+            quarantine,
+            outcome.permission_errors,
+            DeferredCleanup::removal(&log_dir, None, urls_to_remove, HashSet::new(), database),
+        );
+        fs::write(&output_json, serde_json::to_string_pretty(&report)?)?;
+        finished?;
+
+        let mut completed = complete_context(
+            game_name,
+            &service,
+            report.cache_files_deleted,
+            report.total_bytes_freed as f64 / 1_073_741_824.0,
+            0,
+        );
+        completed["protectedSkipped"] = json!(report.protected_files_skipped.len());
+        completed["quarantineBatch"] = json!(report.quarantine_batch);
+        return removal_core::write_progress(
+            &progress_path,
+            &reporter,
+            "completed",
+            NAMED_GAME_REMOVE_COMPLETE_KEY,
+            completed,
+            100.0,
+            0,
+            0,
+        );
+    }
+
     // Step 3: Remove log entries from access log text files
     removal_core::write_progress(&progress_path, &reporter, "removing_logs", "signalr.gameRemove.logs.removing", json!({}), 80.0, 0, 0)?;
     eprintln!("\nRemoving log entries...");
-    let urls_to_remove: HashSet<String> = url_data.keys().cloned().collect();
     let (log_entries_removed, log_permission_errors) =
         removal_core::purge_log_entries(&log_dir, &urls_to_remove, &LogScope::Urls)?;
 
@@ -418,6 +562,8 @@ pub async fn run(service: &str) -> Result<()> {
             total_bytes_freed: outcome.bytes_freed,
             empty_dirs_removed,
             log_entries_removed,
+            protected_files_skipped,
+            quarantine_batch: None,
         };
         let json = serde_json::to_string_pretty(&report)?;
         fs::write(&output_json, json)?;
@@ -437,23 +583,28 @@ pub async fn run(service: &str) -> Result<()> {
         total_bytes_freed: outcome.bytes_freed,
         empty_dirs_removed,
         log_entries_removed,
+        protected_files_skipped,
+        quarantine_batch: None,
     };
 
     let json = serde_json::to_string_pretty(&report)?;
     fs::write(&output_json, json)?;
 
+    let mut completed = complete_context(
+        game_name,
+        &service,
+        report.cache_files_deleted,
+        report.total_bytes_freed as f64 / 1_073_741_824.0,
+        report.log_entries_removed,
+    );
+    completed["protectedSkipped"] = json!(report.protected_files_skipped.len());
+    db_backup::annotate_context(&mut completed, backup.as_ref());
     removal_core::write_progress(
         &progress_path,
         &reporter,
         "completed",
         NAMED_GAME_REMOVE_COMPLETE_KEY,
-        complete_context(
-            game_name,
-            &service,
-            report.cache_files_deleted,
-            report.total_bytes_freed as f64 / 1_073_741_824.0,
-            report.log_entries_removed,
-        ),
+        completed,
         100.0,
         0,
         0,
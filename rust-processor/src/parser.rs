@@ -139,6 +139,8 @@ impl LogParser {
             tact_product,
             http_range,
             cdn_host,
+            upstream_bytes: None,
+            request_time_secs: None,
         })
     }
 
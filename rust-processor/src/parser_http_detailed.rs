@@ -49,6 +49,8 @@ struct DetailedRecord<'a> {
     body_bytes: i64,
     cache_status: &'a str,
     host: &'a str,
+    upstream_bytes: Option<i64>,
+    request_time_secs: Option<f64>,
     referer: &'a str,
     user_agent: &'a str,
 }
@@ -109,8 +111,9 @@ impl HttpDetailedParser {
     /// nginx tried more than one upstream: `", "` separates servers within one upstream
     /// group and `" : "` separates groups (internal redirects / X-Accel), e.g.
     /// `"0, 10 : 20"`. `$upstream_cache_status` and `$host` are per-request variables
-    /// and always single tokens.
-    fn parse_tail(rest: &str) -> Option<(&str, &str)> {
+    /// and always single tokens. Returns the cache status, host and the summed upstream
+    /// response length (None when every element is `-`).
+    fn parse_tail(rest: &str) -> Option<(&str, &str, Option<i64>)> {
         let tokens: Vec<&str> = rest.split_whitespace().collect();
         let mut idx = 0usize;
         let dash_or_digits = |value: &str| {
@@ -137,10 +140,16 @@ impl HttpDetailedParser {
             }
         };
 
-        // Field 13: $upstream_response_length (comma- and colon-group list aware).
+        // Field 13: $upstream_response_length (comma- and colon-group list aware). Every
+        // element is one upstream attempt's body, so the origin traffic is their sum.
         if !Self::consume_upstream_field(&tokens, &mut idx, dash_or_digits) {
             return None;
         }
+        let upstream_bytes = tokens[..idx]
+            .iter()
+            .flat_map(|token| token.split(','))
+            .filter_map(|value| value.parse::<i64>().ok())
+            .reduce(|total, value| total.saturating_add(value));
 
         // Field 14: $upstream_cache_status — single token, `-` or an nginx cache status word.
         let cache_status = *tokens.get(idx)?;
@@ -167,7 +176,7 @@ impl HttpDetailedParser {
             return None;
         }
 
-        Some((cache_status, host))
+        Some((cache_status, host, upstream_bytes))
     }
 
     fn capture<'a>(&self, line: &'a str) -> Option<DetailedRecord<'a>> {
@@ -179,7 +188,7 @@ impl HttpDetailedParser {
         if rest.contains('"') {
             return None;
         }
-        let (cache_status, host) = Self::parse_tail(rest)?;
+        let (cache_status, host, upstream_bytes) = Self::parse_tail(rest)?;
 
         let parse_dash_i64 = |name: &str| -> Option<i64> {
             let s = captures.name(name)?.as_str();
@@ -204,6 +213,8 @@ impl HttpDetailedParser {
             body_bytes: parse_dash_i64("body_bytes")?,
             cache_status,
             host,
+            upstream_bytes,
+            request_time_secs: captures.name("reqtime").and_then(|m| m.as_str().parse::<f64>().ok()),
             referer: captures.name("referer")?.as_str(),
             user_agent: captures.name("ua")?.as_str(),
         })
@@ -290,6 +301,8 @@ impl HttpDetailedParser {
             tact_product,
             http_range: record.range.to_string(),
             cdn_host,
+            upstream_bytes: record.upstream_bytes,
+            request_time_secs: record.request_time_secs,
         })
     }
 }
@@ -665,6 +678,7 @@ mod tests {
             .expect("comma-list line");
         assert_eq!(e.cache_status, "MISS");
         assert_eq!(e.bytes_served, 9);
+        assert_eq!(e.upstream_bytes, Some(1048576), "every upstream attempt counts");
     }
 
     #[test]
@@ -691,6 +705,8 @@ mod tests {
             .expect("colon-group line");
         assert_eq!(e.cache_status, "HIT");
         assert_eq!(e.bytes_served, 9);
+        assert_eq!(e.upstream_bytes, Some(30));
+        assert_eq!(e.request_time_secs, Some(0.005));
     }
 
     #[test]
@@ -0,0 +1,443 @@
+//! Games that no destructive operation may touch.
+//!
+//! The list lives in `protected_games`, one row per game identity: a Steam AppId, an Epic AppId,
+//! or a service plus GameName for the name-keyed services, the same three identities the removal
+//! bins and `CachedGameDetections` use. `cache_protect` manages it.
+//!
+//! Enforcement happens in three places:
+//!   * a removal bin whose target is itself protected refuses to start;
+//!   * cache files are compared against `ProtectedFiles`, the on-disk slices of every URL a
+//!     protected game's downloads requested, and skipped files are reported back to the caller;
+//!   * database deletes add `download_is_protected` / `log_entry_is_protected` so the rows that
+//!     describe a protected game's files survive even a service-wide delete.
+//!
+//! Skipping a file keeps its access-log lines too: callers drop the URLs of skipped files from
+//! the purge set, so the detection and eviction scans still account for what stayed on disk.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sqlx::{Connection, PgPool, Row};
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::cache_utils::{self, CacheKeyScheme};
+use crate::db;
+
+/// Serializes first-time table creation against a second bin starting on the same database.
+const SCHEMA_SETUP_LOCK_KEY: i64 = i64::from_be_bytes(*b"protgame");
+
+/// One protected game identity.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum ProtectedGame {
+    #[serde(rename_all = "camelCase")]
+    Steam { app_id: i64 },
+    #[serde(rename_all = "camelCase")]
+    Epic { epic_app_id: String },
+    /// Blizzard, Riot, Xbox and any other game keyed by service and name. `service` is stored
+    /// lowercase, the way `Downloads.Service` is compared everywhere else.
+    #[serde(rename_all = "camelCase")]
+    Named { service: String, game_name: String },
+}
+
+impl ProtectedGame {
+    pub fn named(service: &str, game_name: &str) -> Self {
+        ProtectedGame::Named {
+            service: service.to_lowercase(),
+            game_name: game_name.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ProtectedGame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtectedGame::Steam { app_id } => write!(f, "Steam app {app_id}"),
+            ProtectedGame::Epic { epic_app_id } => write!(f, "Epic app {epic_app_id}"),
+            ProtectedGame::Named { service, game_name } => {
+                write!(f, "{service} game '{game_name}'")
+            }
+        }
+    }
+}
+
+/// A stored protection, as `cache_protect list` reports it.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProtectionEntry {
+    pub id: i64,
+    #[serde(flatten)]
+    pub game: ProtectedGame,
+    pub note: Option<String>,
+    /// RFC 3339.
+    pub created_at: String,
+}
+
+pub async fn initialize_schema(pool: &PgPool) -> Result<()> {
+    let mut connection = pool.acquire().await?;
+    let mut transaction = connection
+        .begin()
+        .await
+        .context("failed to begin protected games schema setup")?;
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(SCHEMA_SETUP_LOCK_KEY)
+        .execute(&mut *transaction)
+        .await?;
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS protected_games( \
+            id BIGSERIAL PRIMARY KEY, \
+            steam_app_id BIGINT NULL, \
+            epic_app_id TEXT NULL, \
+            service TEXT NULL, \
+            game_name TEXT NULL, \
+            note TEXT NULL, \
+            created_at TIMESTAMPTZ NOT NULL DEFAULT now(), \
+            CHECK(num_nonnulls(steam_app_id, epic_app_id, game_name) = 1), \
+            CHECK((service IS NULL) = (game_name IS NULL)) \
+         )",
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query(
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_protected_games_identity ON protected_games( \
+            COALESCE(steam_app_id, -1), COALESCE(epic_app_id, ''), \
+            COALESCE(service, ''), COALESCE(game_name, ''))",
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction
+        .commit()
+        .await
+        .context("failed to commit protected games schema setup")?;
+    Ok(())
+}
+
+fn identity_columns(
+    game: &ProtectedGame,
+) -> (Option<i64>, Option<&str>, Option<&str>, Option<&str>) {
+    match game {
+        ProtectedGame::Steam { app_id } => (Some(*app_id), None, None, None),
+        ProtectedGame::Epic { epic_app_id } => (None, Some(epic_app_id), None, None),
+        ProtectedGame::Named { service, game_name } => (None, None, Some(service), Some(game_name)),
+    }
+}
+
+/// Adds a protection; false when the game was already protected.
+pub async fn add(pool: &PgPool, game: &ProtectedGame, note: Option<&str>) -> Result<bool> {
+    initialize_schema(pool).await?;
+    let (steam_app_id, epic_app_id, service, game_name) = identity_columns(game);
+    let inserted = sqlx::query(
+        "INSERT INTO protected_games(steam_app_id, epic_app_id, service, game_name, note) \
+         VALUES($1, $2, $3, $4, $5) ON CONFLICT DO NOTHING",
+    )
+    .bind(steam_app_id)
+    .bind(epic_app_id)
+    .bind(service)
+    .bind(game_name)
+    .bind(note)
+    .execute(pool)
+    .await
+    .with_context(|| format!("failed to protect {game}"))?;
+    Ok(inserted.rows_affected() > 0)
+}
+
+/// Removes a protection; false when the game was not protected.
+pub async fn remove(pool: &PgPool, game: &ProtectedGame) -> Result<bool> {
+    initialize_schema(pool).await?;
+    let (steam_app_id, epic_app_id, service, game_name) = identity_columns(game);
+    let deleted = sqlx::query(
+        "DELETE FROM protected_games \
+         WHERE steam_app_id IS NOT DISTINCT FROM $1 AND epic_app_id IS NOT DISTINCT FROM $2 \
+           AND service IS NOT DISTINCT FROM $3 AND game_name IS NOT DISTINCT FROM $4",
+    )
+    .bind(steam_app_id)
+    .bind(epic_app_id)
+    .bind(service)
+    .bind(game_name)
+    .execute(pool)
+    .await
+    .with_context(|| format!("failed to unprotect {game}"))?;
+    Ok(deleted.rows_affected() > 0)
+}
+
+pub async fn list(pool: &PgPool) -> Result<Vec<ProtectionEntry>> {
+    initialize_schema(pool).await?;
+    let rows = sqlx::query(
+        "SELECT id, steam_app_id, epic_app_id, service, game_name, note, created_at \
+         FROM protected_games ORDER BY id",
+    )
+    .fetch_all(pool)
+    .await
+    .context("failed to load the protected game list")?;
+    let mut entries = Vec::with_capacity(rows.len());
+    for row in rows {
+        let steam_app_id: Option<i64> = row.try_get("steam_app_id")?;
+        let epic_app_id: Option<String> = row.try_get("epic_app_id")?;
+        let service: Option<String> = row.try_get("service")?;
+        let game_name: Option<String> = row.try_get("game_name")?;
+        let game = match (steam_app_id, epic_app_id, service, game_name) {
+            (Some(app_id), None, None, None) => ProtectedGame::Steam { app_id },
+            (None, Some(epic_app_id), None, None) => ProtectedGame::Epic { epic_app_id },
+            (None, None, Some(service), Some(game_name)) => {
+                ProtectedGame::Named { service, game_name }
+            }
+            _ => bail!(
+                "protected_games row {} has no single identity",
+                row.try_get::<i64, _>("id")?
+            ),
+        };
+        entries.push(ProtectionEntry {
+            id: row.try_get("id")?,
+            game,
+            note: row.try_get("note")?,
+            created_at: row.try_get::<DateTime<Utc>, _>("created_at")?.to_rfc3339(),
+        });
+    }
+    Ok(entries)
+}
+
+/// SQL predicate, true when the `Downloads` row aliased `alias` belongs to a protected game.
+/// Matches downloads the way the host groups them: Epic by EpicAppId, Steam by GameAppId, and
+/// name-keyed games by service and name when neither id is set.
+pub fn download_is_protected(alias: &str) -> String {
+    format!(
+        "EXISTS (SELECT 1 FROM protected_games pg \
+            WHERE pg.steam_app_id = {alias}.\"GameAppId\" \
+               OR pg.epic_app_id = {alias}.\"EpicAppId\" \
+               OR ({alias}.\"GameAppId\" IS NULL AND {alias}.\"EpicAppId\" IS NULL \
+                   AND pg.service = LOWER({alias}.\"Service\") \
+                   AND pg.game_name = {alias}.\"GameName\"))"
+    )
+}
+
+/// `filter` over `Downloads` (alias `d`) narrowed to the rows a removal deletes and backs up:
+/// protected games' downloads stay.
+pub fn unprotected_downloads(filter: &str) -> String {
+    format!("({filter}) AND NOT {}", download_is_protected("d"))
+}
+
+/// SQL predicate, true when the `LogEntries` row aliased `alias` belongs to a protected game:
+/// its download is protected, or its depot maps to a protected Steam app.
+pub fn log_entry_is_protected(alias: &str) -> String {
+    format!(
+        "(EXISTS (SELECT 1 FROM \"Downloads\" pd \
+            WHERE pd.\"Id\" = {alias}.\"DownloadId\" AND {protected}) \
+          OR EXISTS (SELECT 1 FROM \"SteamDepotMappings\" psdm \
+            JOIN protected_games pg ON pg.steam_app_id = psdm.\"AppId\" \
+            WHERE psdm.\"DepotId\" = {alias}.\"DepotId\"))",
+        protected = download_is_protected("pd")
+    )
+}
+
+/// The protection list with the (service, URL) pairs its games requested.
+#[derive(Debug, Default)]
+pub struct Protection {
+    games: HashSet<ProtectedGame>,
+    /// (lowercased LogEntries.Service, Url): the pair cache files are hashed from.
+    urls: HashSet<(String, String)>,
+}
+
+impl Protection {
+    /// Loads the list and, when it is not empty, every URL its games requested.
+    pub async fn load(pool: &PgPool) -> Result<Self> {
+        let games: HashSet<ProtectedGame> = list(pool)
+            .await?
+            .into_iter()
+            .map(|entry| entry.game)
+            .collect();
+        if games.is_empty() {
+            return Ok(Self::default());
+        }
+        let query = format!(
+            "SELECT DISTINCT LOWER(le.\"Service\") AS service, le.\"Url\" AS url \
+             FROM \"LogEntries\" le \
+             WHERE le.\"Url\" IS NOT NULL AND le.\"Service\" IS NOT NULL AND {}",
+            log_entry_is_protected("le")
+        );
+        let mut urls = HashSet::new();
+        let mut rows = sqlx::query(&query).fetch(pool);
+        while let Some(row) = rows
+            .try_next()
+            .await
+            .context("failed to load the URLs of protected games")?
+        {
+            urls.insert((row.try_get("service")?, row.try_get("url")?));
+        }
+        eprintln!(
+            "Protection list: {} game(s), {} protected URL(s)",
+            games.len(),
+            urls.len()
+        );
+        Ok(Self { games, urls })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.games.is_empty()
+    }
+
+    pub fn protects(&self, game: &ProtectedGame) -> bool {
+        self.games.contains(game)
+    }
+
+    /// Fails when `game` is on the list; removal bins call this before touching anything.
+    pub fn ensure_not_protected(&self, game: &ProtectedGame) -> Result<()> {
+        if self.protects(game) {
+            bail!("{game} is on the protection list; remove it with `cache_protect remove` first");
+        }
+        Ok(())
+    }
+
+    pub fn protects_url(&self, service: &str, url: &str) -> bool {
+        !self.urls.is_empty()
+            && self
+                .urls
+                .contains(&(service.to_lowercase(), url.to_string()))
+    }
+
+    /// Probes the cache for every slice of every protected URL.
+    pub fn files(&self, cache_dir: &Path, scheme: CacheKeyScheme) -> ProtectedFiles {
+        let digests: HashSet<u128> = self
+            .urls
+            .par_iter()
+            .flat_map_iter(|(service, url)| {
+                let exists =
+                    |digest| cache_utils::cache_path_for_digest(cache_dir, digest).exists();
+                match scheme {
+                    CacheKeyScheme::Monolithic => {
+                        cache_utils::existing_cache_digests_for_url(service, url, exists)
+                    }
+                    CacheKeyScheme::BareMetal => {
+                        cache_utils::existing_bare_metal_keyed_digests_for_url(service, url, exists)
+                            .into_iter()
+                            .map(|(digest, _key)| digest)
+                            .collect()
+                    }
+                }
+            })
+            .collect();
+        ProtectedFiles { digests }
+    }
+}
+
+/// Connects, loads the list and probes the cache for its files. Fails closed: a bin that
+/// cannot read the list must not delete anything.
+pub async fn load_files(cache_dir: &Path, scheme: CacheKeyScheme) -> Result<ProtectedFiles> {
+    let protection = async {
+        let pool = db::create_pool().await?;
+        Protection::load(&pool).await
+    }
+    .await
+    .context("failed to load the protection list; nothing was deleted")?;
+    Ok(protection.files(cache_dir, scheme))
+}
+
+/// [`load_files`] for the synchronous bins.
+pub fn load_files_blocking(cache_dir: &Path, scheme: CacheKeyScheme) -> Result<ProtectedFiles> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .context("failed to create protection list runtime")?
+        .block_on(load_files(cache_dir, scheme))
+}
+
+/// Cache files of protected games, by file-name digest so that canonicalized and plain paths
+/// under the same cache root compare equal.
+#[derive(Debug, Default, Clone)]
+pub struct ProtectedFiles {
+    digests: HashSet<u128>,
+}
+
+impl FromIterator<u128> for ProtectedFiles {
+    fn from_iter<I: IntoIterator<Item = u128>>(digests: I) -> Self {
+        Self {
+            digests: digests.into_iter().collect(),
+        }
+    }
+}
+
+impl ProtectedFiles {
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.digests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.digests.is_empty()
+    }
+
+    pub fn contains_digest(&self, digest: u128) -> bool {
+        self.digests.contains(&digest)
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        !self.digests.is_empty()
+            && path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(cache_utils::parse_cache_file_digest)
+                .is_some_and(|digest| self.digests.contains(&digest))
+    }
+
+    /// The first-level cache directories (`<cache>/xx`) holding at least one protected file.
+    pub fn level_one_dirs(&self, cache_dir: &Path) -> HashSet<PathBuf> {
+        self.digests
+            .iter()
+            .filter_map(|digest| {
+                cache_utils::cache_path_for_digest(cache_dir, *digest)
+                    .parent()
+                    .and_then(Path::parent)
+                    .map(Path::to_path_buf)
+            })
+            .collect()
+    }
+
+    pub fn paths(&self, cache_dir: &Path) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = self
+            .digests
+            .iter()
+            .map(|digest| cache_utils::cache_path_for_digest(cache_dir, *digest))
+            .collect();
+        paths.sort();
+        paths
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn protected_files_match_by_digest_whatever_the_root() {
+        let digest = cache_utils::calculate_md5_digest("steam/depot/1/chunk/a");
+        let files: ProtectedFiles = [digest].into_iter().collect();
+        let plain = cache_utils::cache_path_for_digest(Path::new("/cache"), digest);
+        let other_root = cache_utils::cache_path_for_digest(Path::new("/mnt/real/cache"), digest);
+        assert!(files.contains(&plain));
+        assert!(files.contains(&other_root));
+        assert!(!files.contains(Path::new("/cache/00/00/not-a-digest")));
+        assert_eq!(
+            files.level_one_dirs(Path::new("/cache")),
+            HashSet::from([plain.parent().unwrap().parent().unwrap().to_path_buf()])
+        );
+        assert!(!ProtectedFiles::none().contains(&plain));
+    }
+
+    #[test]
+    fn identities_serialize_with_their_kind() {
+        let named = ProtectedGame::named("Blizzard", "Diablo IV");
+        assert_eq!(
+            serde_json::to_value(&named).unwrap(),
+            serde_json::json!({ "kind": "named", "service": "blizzard", "gameName": "Diablo IV" })
+        );
+        assert_eq!(named.to_string(), "blizzard game 'Diablo IV'");
+        let steam: ProtectedGame = serde_json::from_str(r#"{"kind":"steam","appId":730}"#).unwrap();
+        assert_eq!(steam, ProtectedGame::Steam { app_id: 730 });
+    }
+}
@@ -0,0 +1,665 @@
+//! Quarantine batches: cache files moved aside instead of deleted.
+//!
+//! With `--quarantine-dir`, the removal bins, `cache_clear` and `cache_corruption remove` rename
+//! what they would delete into `<quarantine dir>/<batch id>/files/`, keeping each file's path
+//! under the cache root. A rename within one filesystem is instant and frees nothing, so the
+//! directory must be on the cache filesystem, and outside the cache tree where nginx would not
+//! see it. The access-log and database cleanup the removal would have done is recorded in the
+//! batch's `manifest.json` and only runs when `cache_quarantine purge` deletes the batch;
+//! `cache_quarantine restore` moves the files back, and nothing else needs undoing.
+//!
+//! The manifest is written before the first file moves, with `complete: false`, and rewritten
+//! with the deferred cleanup once the removal has finished. A batch whose removal failed or was
+//! cancelled stays incomplete: it restores like any other, and purging it deletes its files but
+//! defers nothing, which is the state an interrupted removal leaves behind anyway.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::corruption_cleanup;
+use crate::db_backup::{BackupPolicy, BackupSummary};
+use crate::log_purge::ExactLogObservation;
+use crate::progress_utils;
+use crate::removal_core;
+use crate::removal_plan::{self, PlannedLogPurge, PlannedRows};
+
+pub const MANIFEST_VERSION: u32 = 1;
+pub const MANIFEST_FILE: &str = "manifest.json";
+const FILES_DIR: &str = "files";
+const BATCH_TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%3fZ";
+
+/// `--quarantine-dir`, shared by every binary that can quarantine instead of delete.
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct QuarantineArgs {
+    /// Move removed cache files into a batch in this directory (on the cache filesystem) instead
+    /// of deleting them; log and database cleanup waits for `cache_quarantine purge`
+    #[arg(long = "quarantine-dir", value_name = "DIR")]
+    pub quarantine_dir: Option<PathBuf>,
+}
+
+impl QuarantineArgs {
+    /// Opens a batch for `operation`; `None` when files are deleted outright.
+    pub fn open(&self, cache_dir: &Path, operation: &str) -> Result<Option<Quarantine>> {
+        self.quarantine_dir
+            .as_deref()
+            .map(|root| Quarantine::open(root, cache_dir, operation))
+            .transpose()
+    }
+}
+
+/// What a removal left for the purge of its batch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum DeferredCleanup {
+    /// The files are all there is (`cache_clear`, structural corruption removal, and any batch
+    /// whose removal did not finish).
+    None,
+    /// A game or service removal: its access-log purge and its rows.
+    #[serde(rename_all = "camelCase")]
+    Removal {
+        log_dir: PathBuf,
+        log_purge: PlannedLogPurge,
+        database: PlannedRows,
+    },
+    /// A repeated-MISS corruption removal: the exact observations of the removed slices.
+    #[serde(rename_all = "camelCase")]
+    CorruptionEvidence {
+        log_dir: PathBuf,
+        datasource: String,
+        observations: Vec<ExactLogObservation>,
+    },
+}
+
+impl DeferredCleanup {
+    /// A removal head's log purge (`service` for a whole-service removal) and rows. The purge is
+    /// bounded to the lines logged by now, so traffic between quarantine and purge stays logged.
+    pub fn removal(
+        log_dir: &Path,
+        service: Option<&str>,
+        urls: HashSet<String>,
+        depot_ids: HashSet<u32>,
+        database: PlannedRows,
+    ) -> Self {
+        Self::Removal {
+            log_dir: log_dir.to_path_buf(),
+            log_purge: PlannedLogPurge {
+                logged_until: Some(progress_utils::current_timestamp()),
+                ..PlannedLogPurge::scope(service, urls, depot_ids)
+            },
+            database,
+        }
+    }
+
+    fn needs_database(&self) -> bool {
+        !matches!(self, Self::None)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuarantineManifest {
+    pub version: u32,
+    pub id: String,
+    pub created_at: String,
+    /// What quarantined the files, e.g. `steam_remove` or `cache_clear`.
+    pub operation: String,
+    pub cache_dir: PathBuf,
+    /// False until the removal finished; an incomplete batch defers nothing.
+    pub complete: bool,
+    pub deferred: DeferredCleanup,
+}
+
+fn write_manifest(batch_dir: &Path, manifest: &QuarantineManifest) -> Result<()> {
+    progress_utils::write_progress_json(&batch_dir.join(MANIFEST_FILE), manifest).with_context(
+        || {
+            format!(
+                "failed to write the manifest of quarantine batch {}",
+                manifest.id
+            )
+        },
+    )
+}
+
+/// A batch being filled by a removal.
+pub struct Quarantine {
+    cache_dir: PathBuf,
+    canonical_cache_dir: PathBuf,
+    batch_dir: PathBuf,
+    files_dir: PathBuf,
+    manifest: QuarantineManifest,
+}
+
+impl Quarantine {
+    /// Creates a new batch under `root` and proves, with a probe rename, that files can be moved
+    /// into it from `cache_dir` without a copy.
+    pub fn open(root: &Path, cache_dir: &Path, operation: &str) -> Result<Self> {
+        fs::create_dir_all(root)
+            .with_context(|| format!("failed to create quarantine directory {}", root.display()))?;
+        let canonical_root = root
+            .canonicalize()
+            .with_context(|| format!("failed to resolve {}", root.display()))?;
+        let canonical_cache_dir = cache_dir
+            .canonicalize()
+            .with_context(|| format!("failed to resolve {}", cache_dir.display()))?;
+        if canonical_root.starts_with(&canonical_cache_dir) {
+            bail!(
+                "quarantine directory {} must be outside the cache directory {}",
+                root.display(),
+                cache_dir.display()
+            );
+        }
+
+        let created_at = Utc::now();
+        let id = format!(
+            "{}_{}",
+            operation,
+            created_at.format(BATCH_TIMESTAMP_FORMAT)
+        );
+        let batch_dir = root.join(&id);
+        fs::create_dir(&batch_dir).with_context(|| {
+            format!("failed to create quarantine batch {}", batch_dir.display())
+        })?;
+        let files_dir = batch_dir.join(FILES_DIR);
+        let opened = fs::create_dir(&files_dir)
+            .with_context(|| format!("failed to create {}", files_dir.display()))
+            .and_then(|()| ensure_rename_possible(&files_dir, cache_dir, &id));
+        if let Err(error) = opened {
+            let _ = fs::remove_dir_all(&batch_dir);
+            return Err(error);
+        }
+
+        let manifest = QuarantineManifest {
+            version: MANIFEST_VERSION,
+            id,
+            created_at: created_at.to_rfc3339(),
+            operation: operation.to_string(),
+            cache_dir: canonical_cache_dir.clone(),
+            complete: false,
+            deferred: DeferredCleanup::None,
+        };
+        write_manifest(&batch_dir, &manifest)?;
+        eprintln!("Quarantining cache files into {}", batch_dir.display());
+        Ok(Self {
+            cache_dir: cache_dir.to_path_buf(),
+            canonical_cache_dir,
+            batch_dir,
+            files_dir,
+            manifest,
+        })
+    }
+
+    pub fn id(&self) -> &str {
+        &self.manifest.id
+    }
+
+    pub fn batch_dir(&self) -> &Path {
+        &self.batch_dir
+    }
+
+    /// Moves one cache file (or a whole cache directory) into the batch. Fails the way
+    /// `fs::remove_file` does, so it drops in wherever a removal unlinks.
+    pub fn take(&self, path: &Path) -> io::Result<()> {
+        let relative = self.relative_path(path)?;
+        let target = self.files_dir.join(relative);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(path, &target)
+    }
+
+    fn relative_path<'p>(&self, path: &'p Path) -> io::Result<&'p Path> {
+        let relative = path
+            .strip_prefix(&self.cache_dir)
+            .or_else(|_| path.strip_prefix(&self.canonical_cache_dir))
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is not under the cache directory", path.display()),
+                )
+            })?;
+        if relative.as_os_str().is_empty()
+            || !relative
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("refusing to quarantine {}", path.display()),
+            ));
+        }
+        Ok(relative)
+    }
+
+    /// Records what the purge must clean up and marks the batch complete.
+    pub fn finish(mut self, deferred: DeferredCleanup) -> Result<QuarantineManifest> {
+        self.manifest.complete = true;
+        self.manifest.deferred = deferred;
+        write_manifest(&self.batch_dir, &self.manifest)?;
+        eprintln!(
+            "Quarantine batch {} is complete; restore or purge it with cache_quarantine",
+            self.manifest.id
+        );
+        Ok(self.manifest)
+    }
+}
+
+/// Renames a probe file from the batch into the cache root and back. Across filesystems (or
+/// across bind mounts of one) a rename fails, and the batch would otherwise only find out on its
+/// first real file.
+fn ensure_rename_possible(files_dir: &Path, cache_dir: &Path, id: &str) -> Result<()> {
+    let probe = files_dir.join(".probe");
+    let cache_probe = cache_dir.join(format!(".quarantine-probe-{id}"));
+    fs::write(&probe, b"").with_context(|| format!("failed to write {}", probe.display()))?;
+    if let Err(error) = fs::rename(&probe, &cache_probe) {
+        let _ = fs::remove_file(&probe);
+        bail!(
+            "cannot move files between {} and {} ({}); the quarantine directory must be on the cache filesystem",
+            cache_dir.display(),
+            files_dir.display(),
+            error
+        );
+    }
+    fs::remove_file(&cache_probe)
+        .with_context(|| format!("failed to remove {}", cache_probe.display()))
+}
+
+/// A batch on disk.
+#[derive(Debug, Clone)]
+pub struct QuarantineBatch {
+    pub dir: PathBuf,
+    pub manifest: QuarantineManifest,
+}
+
+/// Files and bytes held by a batch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchContents {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// What a restore did. Files nginx has re-cached since stay in the batch.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RestoreOutcome {
+    pub restored: BatchContents,
+    pub conflicts: Vec<PathBuf>,
+    /// Whether the batch is gone (nothing was left in it).
+    pub batch_removed: bool,
+}
+
+/// What a purge deleted.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PurgeOutcome {
+    pub id: String,
+    pub purged: BatchContents,
+    pub log_lines_removed: u64,
+    pub database_rows_deleted: u64,
+    pub backup: Option<BackupSummary>,
+}
+
+/// Every batch under `root`, oldest first. Directories without a readable manifest are skipped.
+pub fn list_batches(root: &Path) -> Result<Vec<QuarantineBatch>> {
+    let mut batches = Vec::new();
+    let entries = match fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(batches),
+        Err(error) => {
+            return Err(error).with_context(|| format!("failed to read {}", root.display()))
+        }
+    };
+    for entry in entries {
+        let dir = entry?.path();
+        if !dir.join(MANIFEST_FILE).is_file() {
+            continue;
+        }
+        match QuarantineBatch::load(&dir) {
+            Ok(batch) => batches.push(batch),
+            Err(error) => eprintln!("  Warning: skipping {}: {:#}", dir.display(), error),
+        }
+    }
+    batches.sort_by(|left, right| left.manifest.created_at.cmp(&right.manifest.created_at));
+    Ok(batches)
+}
+
+/// The batch `id` under `root`.
+pub fn load_batch(root: &Path, id: &str) -> Result<QuarantineBatch> {
+    let mut components = Path::new(id).components();
+    if !matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    ) {
+        bail!("'{id}' is not a quarantine batch id");
+    }
+    QuarantineBatch::load(&root.join(id))
+}
+
+impl QuarantineBatch {
+    fn load(dir: &Path) -> Result<Self> {
+        let path = dir.join(MANIFEST_FILE);
+        let bytes =
+            fs::read(&path).with_context(|| format!("failed to read {}", path.display()))?;
+        let manifest: QuarantineManifest = serde_json::from_slice(&bytes)
+            .with_context(|| format!("{} is not a quarantine manifest", path.display()))?;
+        if manifest.version != MANIFEST_VERSION {
+            bail!(
+                "{} has manifest version {}; this build reads version {}",
+                path.display(),
+                manifest.version,
+                MANIFEST_VERSION
+            );
+        }
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            manifest,
+        })
+    }
+
+    fn files_dir(&self) -> PathBuf {
+        self.dir.join(FILES_DIR)
+    }
+
+    pub fn created_at(&self) -> Option<DateTime<Utc>> {
+        DateTime::parse_from_rfc3339(&self.manifest.created_at)
+            .ok()
+            .map(|created_at| created_at.with_timezone(&Utc))
+    }
+
+    /// Whether purging this batch touches the database.
+    pub fn needs_database(&self) -> bool {
+        self.manifest.complete && self.manifest.deferred.needs_database()
+    }
+
+    /// Walks the batch's files.
+    pub fn contents(&self) -> Result<BatchContents> {
+        tree_contents(&self.files_dir())
+    }
+
+    /// Moves the files back under the cache root. A whole directory goes back with one rename
+    /// when the cache has nothing at its place; a file the cache holds again is left in the
+    /// batch and reported. The batch is deleted once it is empty.
+    pub fn restore(&self) -> Result<RestoreOutcome> {
+        let mut outcome = RestoreOutcome::default();
+        restore_tree(&self.files_dir(), &self.manifest.cache_dir, &mut outcome)?;
+        outcome.conflicts.sort();
+        if outcome.conflicts.is_empty() {
+            fs::remove_dir_all(&self.dir)
+                .with_context(|| format!("failed to remove {}", self.dir.display()))?;
+            outcome.batch_removed = true;
+        }
+        Ok(outcome)
+    }
+
+    /// Runs the deferred cleanup, then deletes the batch. The cleanup runs first so a failure
+    /// keeps the files restorable; re-running a purge repeats it harmlessly.
+    pub async fn purge(
+        &self,
+        pool: Option<&PgPool>,
+        backup: Option<&BackupPolicy>,
+    ) -> Result<PurgeOutcome> {
+        let mut outcome = PurgeOutcome {
+            id: self.manifest.id.clone(),
+            purged: self.contents()?,
+            ..PurgeOutcome::default()
+        };
+        if self.manifest.complete {
+            self.run_deferred(pool, backup, &mut outcome).await?;
+        } else {
+            eprintln!(
+                "  Batch {} is incomplete: deleting its files, leaving logs and rows alone",
+                self.manifest.id
+            );
+        }
+        fs::remove_dir_all(&self.dir)
+            .with_context(|| format!("failed to delete {}", self.dir.display()))?;
+        Ok(outcome)
+    }
+
+    async fn run_deferred(
+        &self,
+        pool: Option<&PgPool>,
+        backup: Option<&BackupPolicy>,
+        outcome: &mut PurgeOutcome,
+    ) -> Result<()> {
+        let needs_pool = || {
+            pool.with_context(|| format!("purging batch {} needs the database", self.manifest.id))
+        };
+        match &self.manifest.deferred {
+            DeferredCleanup::None => {}
+            DeferredCleanup::Removal {
+                log_dir,
+                log_purge,
+                database,
+            } => {
+                let pool = needs_pool()?;
+                outcome.backup = removal_plan::backup_planned_rows(
+                    pool,
+                    backup,
+                    &self.manifest.operation,
+                    database,
+                    serde_json::json!({ "quarantineBatch": self.manifest.id }),
+                )
+                .await?;
+                eprintln!("  Removing log entries...");
+                let (lines, permission_errors) =
+                    removal_plan::purge_planned_logs(log_dir, log_purge)?;
+                outcome.log_lines_removed = lines;
+                if permission_errors > 0 {
+                    bail!(
+                        "{}",
+                        removal_core::permission_error_message(
+                            permission_errors,
+                            0,
+                            permission_errors
+                        )
+                    );
+                }
+                eprintln!("  Removing database records...");
+                outcome.database_rows_deleted =
+                    removal_plan::delete_planned_rows(pool, database).await?;
+            }
+            DeferredCleanup::CorruptionEvidence {
+                log_dir,
+                datasource,
+                observations,
+            } => {
+                let pool = needs_pool()?;
+                eprintln!("  Removing exact corruption log lines...");
+                outcome.log_lines_removed =
+                    corruption_cleanup::purge_log_observations(log_dir, observations, None)?;
+                eprintln!("  Removing exact corruption database rows...");
+                let (downloads, log_entries) = corruption_cleanup::delete_database_observations(
+                    pool,
+                    datasource,
+                    observations,
+                )
+                .await?;
+                outcome.database_rows_deleted = (downloads + log_entries) as u64;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn tree_contents(root: &Path) -> Result<BatchContents> {
+    let mut contents = BatchContents::default();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+            Err(error) => {
+                return Err(error).with_context(|| format!("failed to read {}", dir.display()))
+            }
+        };
+        for entry in entries {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                pending.push(entry.path());
+            } else {
+                contents.files += 1;
+                contents.bytes += metadata.len();
+            }
+        }
+    }
+    Ok(contents)
+}
+
+fn restore_tree(source: &Path, target: &Path, outcome: &mut RestoreOutcome) -> Result<()> {
+    let entries = match fs::read_dir(source) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(error) => {
+            return Err(error).with_context(|| format!("failed to read {}", source.display()))
+        }
+    };
+    fs::create_dir_all(target).with_context(|| format!("failed to create {}", target.display()))?;
+    for entry in entries {
+        let entry = entry?;
+        let from = entry.path();
+        let to = target.join(entry.file_name());
+        let is_dir = entry.file_type()?.is_dir();
+        match fs::symlink_metadata(&to) {
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                let moved = if is_dir {
+                    tree_contents(&from)?
+                } else {
+                    BatchContents {
+                        files: 1,
+                        bytes: entry.metadata()?.len(),
+                    }
+                };
+                fs::rename(&from, &to).with_context(|| {
+                    format!("failed to move {} back to {}", from.display(), to.display())
+                })?;
+                outcome.restored.files += moved.files;
+                outcome.restored.bytes += moved.bytes;
+            }
+            Err(error) => {
+                return Err(error).with_context(|| format!("failed to inspect {}", to.display()))
+            }
+            Ok(existing) if is_dir && existing.is_dir() => {
+                restore_tree(&from, &to, outcome)?;
+                let _ = fs::remove_dir(&from);
+            }
+            Ok(_) => outcome.conflicts.push(to),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_file(path: &Path, contents: &[u8]) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn quarantined_files_restore_and_recached_files_stay_in_the_batch() {
+        let temp = tempfile::tempdir().unwrap();
+        let cache_dir = temp.path().join("cache");
+        let root = temp.path().join("quarantine");
+        let kept = cache_dir.join("ab/cd/kept");
+        let recached = cache_dir.join("ab/ef/recached");
+        write_file(&kept, b"kept");
+        write_file(&recached, b"old");
+
+        let quarantine = Quarantine::open(&root, &cache_dir, "steam_remove").unwrap();
+        quarantine.take(&kept).unwrap();
+        quarantine.take(&recached).unwrap();
+        assert!(!kept.exists() && !recached.exists());
+        let id = quarantine.id().to_string();
+        quarantine.finish(DeferredCleanup::None).unwrap();
+
+        let batches = list_batches(&root).unwrap();
+        assert_eq!(batches.len(), 1);
+        assert!(batches[0].manifest.complete);
+        assert!(!batches[0].needs_database());
+        assert_eq!(
+            batches[0].contents().unwrap(),
+            BatchContents { files: 2, bytes: 7 }
+        );
+
+        write_file(&recached, b"new");
+        let outcome = load_batch(&root, &id).unwrap().restore().unwrap();
+        assert_eq!(outcome.restored, BatchContents { files: 1, bytes: 4 });
+        assert_eq!(
+            outcome.conflicts,
+            vec![cache_dir.canonicalize().unwrap().join("ab/ef/recached")]
+        );
+        assert!(!outcome.batch_removed);
+        assert_eq!(fs::read(&kept).unwrap(), b"kept");
+        assert_eq!(fs::read(&recached).unwrap(), b"new");
+    }
+
+    #[test]
+    fn quarantine_refuses_a_root_inside_the_cache_and_paths_outside_it() {
+        let temp = tempfile::tempdir().unwrap();
+        let cache_dir = temp.path().join("cache");
+        fs::create_dir_all(&cache_dir).unwrap();
+        assert!(
+            Quarantine::open(&cache_dir.join("quarantine"), &cache_dir, "cache_clear").is_err()
+        );
+
+        let quarantine =
+            Quarantine::open(&temp.path().join("quarantine"), &cache_dir, "cache_clear").unwrap();
+        let outside = temp.path().join("outside");
+        write_file(&outside, b"x");
+        assert!(quarantine.take(&outside).is_err());
+        assert!(quarantine
+            .take(&cache_dir.join("ab/../../outside"))
+            .is_err());
+        assert!(quarantine.take(&cache_dir).is_err());
+        assert!(outside.exists());
+        assert!(load_batch(temp.path(), "../quarantine").is_err());
+    }
+
+    #[tokio::test]
+    async fn purge_defers_nothing_for_incomplete_batches_and_keeps_files_without_a_database() {
+        let temp = tempfile::tempdir().unwrap();
+        let cache_dir = temp.path().join("cache");
+        let root = temp.path().join("quarantine");
+        let file = cache_dir.join("00/11/file");
+        write_file(&file, b"data");
+
+        let quarantine = Quarantine::open(&root, &cache_dir, "steam_remove").unwrap();
+        quarantine.take(&file).unwrap();
+        quarantine
+            .finish(DeferredCleanup::removal(
+                temp.path(),
+                None,
+                HashSet::from(["/depot/1/chunk/a".to_string()]),
+                HashSet::from([1]),
+                PlannedRows::default(),
+            ))
+            .unwrap();
+        let complete = list_batches(&root).unwrap().remove(0);
+        assert!(matches!(
+            &complete.manifest.deferred,
+            DeferredCleanup::Removal { log_purge, .. } if log_purge.logged_until.is_some()
+        ));
+        assert!(complete.needs_database());
+        assert!(complete.purge(None, None).await.is_err());
+        assert!(complete.dir.exists());
+
+        write_file(&file, b"data");
+        let incomplete = Quarantine::open(&root, &cache_dir, "epic_remove").unwrap();
+        incomplete.take(&file).unwrap();
+        let incomplete = load_batch(&root, incomplete.id()).unwrap();
+        assert!(!incomplete.needs_database());
+        let outcome = incomplete.purge(None, None).await.unwrap();
+        assert_eq!(outcome.purged, BatchContents { files: 1, bytes: 4 });
+        assert!(!incomplete.dir.exists());
+    }
+}
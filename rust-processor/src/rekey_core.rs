@@ -0,0 +1,388 @@
+//! Moving cache files between the monolithic and bare-metal key schemes, one file at a time.
+//!
+//! A file is rekeyed only on the strength of its own `KEY:` header, which must hash to its name.
+//! `cache_utils::equivalent_cache_key` names the key the other scheme's nginx would look it up
+//! by; the header is rewritten for that key (`cache_structural_scanner::rekey_cache_header`), the
+//! copy is staged next to its new path, hard-linked into place so an existing file is never
+//! replaced, and only then is the original removed.
+//!
+//! Every step can be repeated. A file already in the target scheme is left alone, and a file whose
+//! new path already holds exactly its rekeyed copy is the leftover of a run stopped between the
+//! link and the removal, so removing it finishes the move. The `Checkpoint` lets `cache_rekey`
+//! skip the top-level directories an earlier run completed.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::cache_archive::{self, Placement};
+use crate::cache_structural_scanner::{self, RekeyedHeader};
+use crate::cache_utils::{self, CacheKeyScheme, RekeyBlocker};
+
+/// Suffix of the staged copy `rekey_file` writes before linking it into place.
+pub const STAGING_SUFFIX: &str = ".rekey";
+
+/// What rekeying one cache file did (or, on a dry run, would do).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RekeyOutcome {
+    /// Moved to the equivalent key, whose digest names the new file; `bytes` is its size.
+    Rekeyed { bytes: u64, new_digest: u128 },
+    /// The file's key is already in the target scheme.
+    AlreadyTarget,
+    /// The new path already held this file's rekeyed copy; the original was removed.
+    FinishedMove,
+    /// The new path holds a different file, which is left alone, and so is this one.
+    Conflict,
+    /// The key has no equivalent in the target scheme.
+    Blocked(RekeyBlocker),
+    /// The `KEY:` header is missing or does not hash to the file name, or the nginx header is not
+    /// one this build can rewrite.
+    Unverified,
+}
+
+/// Whether `name` is a staged copy left by a `rekey_file` that did not finish.
+pub fn is_staging_file(name: &str) -> bool {
+    name.starts_with('.') && name.ends_with(STAGING_SUFFIX)
+}
+
+/// Rekeys the cache file at `path` to `target`. New hash-level directories get `placement`; the
+/// file keeps its own owner and mode.
+pub fn rekey_file(
+    cache_dir: &Path,
+    path: &Path,
+    target: CacheKeyScheme,
+    placement: &Placement,
+    dry_run: bool,
+) -> Result<RekeyOutcome> {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return Ok(RekeyOutcome::Unverified);
+    };
+    let Some(digest) = cache_utils::parse_cache_file_digest(name) else {
+        return Ok(RekeyOutcome::Unverified);
+    };
+    let Some(key) = cache_utils::read_cache_file_key(path) else {
+        return Ok(RekeyOutcome::Unverified);
+    };
+    if cache_utils::calculate_md5_digest(&key) != digest {
+        return Ok(RekeyOutcome::Unverified);
+    }
+    let new_key = match cache_utils::equivalent_cache_key(&key, target) {
+        Ok(new_key) if new_key == key => return Ok(RekeyOutcome::AlreadyTarget),
+        Ok(new_key) => new_key,
+        Err(blocker) => return Ok(RekeyOutcome::Blocked(blocker)),
+    };
+
+    let mut source =
+        File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let Some(rekeyed) =
+        cache_structural_scanner::rekey_cache_header(&mut source, &key, &new_key)
+            .with_context(|| format!("failed to read the header of {}", path.display()))?
+    else {
+        return Ok(RekeyOutcome::Unverified);
+    };
+    let metadata = source.metadata()?;
+    let new_name = cache_utils::calculate_md5(&new_key);
+    let new_digest = cache_utils::calculate_md5_digest(&new_key);
+    let destination = cache_utils::cache_path_for_digest(cache_dir, new_digest);
+    let bytes = rekeyed.header.len() as u64 + metadata.len().saturating_sub(rekeyed.body_offset);
+
+    if fs::symlink_metadata(&destination).is_ok() {
+        return finish_or_conflict(path, &mut source, &rekeyed, &destination, dry_run);
+    }
+    if dry_run {
+        return Ok(RekeyOutcome::Rekeyed { bytes, new_digest });
+    }
+
+    let parent = destination
+        .parent()
+        .context("cache file path has no parent")?;
+    cache_archive::create_level_dirs(cache_dir, parent, placement)?;
+    let staging = parent.join(format!(".{}.{}{}", new_name, name, STAGING_SUFFIX));
+    let _ = fs::remove_file(&staging);
+    write_rekeyed(&mut source, &rekeyed, &staging)
+        .with_context(|| format!("failed to write {}", staging.display()))?;
+    file_owner(&metadata)
+        .apply(&staging, cache_archive::file_mode(&metadata))
+        .with_context(|| format!("failed to set the owner of {}", staging.display()))?;
+
+    // A hard link never replaces: if another file took the path meanwhile, it is compared below.
+    let linked = fs::hard_link(&staging, &destination);
+    let _ = fs::remove_file(&staging);
+    match linked {
+        Ok(()) => {}
+        Err(error) if error.kind() == io::ErrorKind::AlreadyExists => {
+            return finish_or_conflict(path, &mut source, &rekeyed, &destination, dry_run);
+        }
+        Err(error) => {
+            return Err(error)
+                .with_context(|| format!("failed to place {}", destination.display()));
+        }
+    }
+    drop(source);
+    fs::remove_file(path).with_context(|| format!("failed to remove {}", path.display()))?;
+    Ok(RekeyOutcome::Rekeyed { bytes, new_digest })
+}
+
+fn finish_or_conflict(
+    path: &Path,
+    source: &mut File,
+    rekeyed: &RekeyedHeader,
+    destination: &Path,
+    dry_run: bool,
+) -> Result<RekeyOutcome> {
+    if !holds_rekeyed_copy(source, rekeyed, destination)
+        .with_context(|| format!("failed to compare {}", destination.display()))?
+    {
+        return Ok(RekeyOutcome::Conflict);
+    }
+    if !dry_run {
+        fs::remove_file(path).with_context(|| format!("failed to remove {}", path.display()))?;
+    }
+    Ok(RekeyOutcome::FinishedMove)
+}
+
+/// Writes `rekeyed` and the body of `source` after it to the new file `staging`.
+pub(crate) fn write_rekeyed(
+    source: &mut File,
+    rekeyed: &RekeyedHeader,
+    staging: &Path,
+) -> io::Result<()> {
+    let mut target = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(staging)?;
+    target.write_all(&rekeyed.header)?;
+    source.seek(SeekFrom::Start(rekeyed.body_offset))?;
+    io::copy(source, &mut target)?;
+    target.sync_all()
+}
+
+/// Whether `destination` is byte for byte the rekeyed form of `source`.
+fn holds_rekeyed_copy(
+    source: &mut File,
+    rekeyed: &RekeyedHeader,
+    destination: &Path,
+) -> io::Result<bool> {
+    let metadata = fs::symlink_metadata(destination)?;
+    let body_len = source.metadata()?.len().saturating_sub(rekeyed.body_offset);
+    if !metadata.is_file() || metadata.len() != rekeyed.header.len() as u64 + body_len {
+        return Ok(false);
+    }
+    let mut existing = File::open(destination)?;
+    let mut header = vec![0u8; rekeyed.header.len()];
+    existing.read_exact(&mut header)?;
+    if header != rekeyed.header {
+        return Ok(false);
+    }
+    source.seek(SeekFrom::Start(rekeyed.body_offset))?;
+    let mut expected = vec![0u8; 1 << 16];
+    let mut actual = vec![0u8; 1 << 16];
+    loop {
+        let read = source.read(&mut expected)?;
+        if read == 0 {
+            return Ok(true);
+        }
+        existing.read_exact(&mut actual[..read])?;
+        if expected[..read] != actual[..read] {
+            return Ok(false);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn file_owner(metadata: &fs::Metadata) -> Placement {
+    use std::os::unix::fs::MetadataExt;
+    Placement {
+        owner: Some((metadata.uid(), metadata.gid())),
+        dir_mode: None,
+    }
+}
+
+#[cfg(not(unix))]
+fn file_owner(_metadata: &fs::Metadata) -> Placement {
+    Placement::default()
+}
+
+/// The top-level hash directories (`00` … `ff`) a rekey of `cache_dir` finished, so a rerun after
+/// a cancel or crash starts where it stopped.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Checkpoint {
+    pub cache_dir: PathBuf,
+    /// `monolithic` | `bare_metal`
+    pub target_scheme: String,
+    pub completed_dirs: BTreeSet<String>,
+}
+
+impl Checkpoint {
+    /// The checkpoint at `path`, or a fresh one when there is none. A checkpoint of another cache
+    /// or target scheme is refused rather than trusted.
+    pub fn load_or_new(path: &Path, cache_dir: &Path, target: CacheKeyScheme) -> Result<Self> {
+        let target_scheme = target.as_config_str().to_string();
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                return Ok(Self {
+                    cache_dir: cache_dir.to_path_buf(),
+                    target_scheme,
+                    completed_dirs: BTreeSet::new(),
+                });
+            }
+            Err(error) => {
+                return Err(error).with_context(|| format!("failed to read {}", path.display()))
+            }
+        };
+        let checkpoint: Self = serde_json::from_slice(&bytes)
+            .with_context(|| format!("{} is not a rekey checkpoint", path.display()))?;
+        if checkpoint.cache_dir != cache_dir || checkpoint.target_scheme != target_scheme {
+            bail!(
+                "{} records a rekey of {} to {}; remove it to rekey {} to {}",
+                path.display(),
+                checkpoint.cache_dir.display(),
+                checkpoint.target_scheme,
+                cache_dir.display(),
+                target_scheme
+            );
+        }
+        Ok(checkpoint)
+    }
+}
+
+#[cfg(all(test, unix, target_pointer_width = "64"))]
+mod tests {
+    use super::*;
+    use crate::cache_structural_scanner::write_linux_v5_test_fixture;
+
+    const MONOLITHIC: &str = "blizzard/tpr/wow/data/ab/cdbytes=0-1048575";
+    const BARE_METAL: &str = "lancache-blizzard/tpr/wow/data/ab/cdbytes=0-1048575";
+    const HEADERS: &[u8] = b"HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 0-3/4\r\n\r\n";
+
+    fn key_path(cache_dir: &Path, key: &str) -> PathBuf {
+        cache_utils::cache_path_for_digest(cache_dir, cache_utils::calculate_md5_digest(key))
+    }
+
+    #[test]
+    fn a_slice_moves_to_its_bare_metal_key_and_back() {
+        let temp = tempfile::tempdir().unwrap();
+        let cache = temp.path();
+        let original = write_linux_v5_test_fixture(cache, MONOLITHIC.as_bytes(), HEADERS, b"body");
+        let before = fs::read(&original).unwrap();
+        let placement = Placement::default();
+
+        assert_eq!(
+            rekey_file(
+                cache,
+                &original,
+                CacheKeyScheme::BareMetal,
+                &placement,
+                true
+            )
+            .unwrap(),
+            RekeyOutcome::Rekeyed {
+                bytes: before.len() as u64 + (BARE_METAL.len() - MONOLITHIC.len()) as u64,
+                new_digest: cache_utils::calculate_md5_digest(BARE_METAL),
+            }
+        );
+        assert!(original.exists());
+        assert!(!key_path(cache, BARE_METAL).exists());
+
+        rekey_file(
+            cache,
+            &original,
+            CacheKeyScheme::BareMetal,
+            &placement,
+            false,
+        )
+        .unwrap();
+        let moved = key_path(cache, BARE_METAL);
+        assert!(!original.exists());
+        assert_eq!(
+            cache_utils::read_cache_file_key(&moved).as_deref(),
+            Some(BARE_METAL)
+        );
+        assert_eq!(
+            rekey_file(cache, &moved, CacheKeyScheme::BareMetal, &placement, false).unwrap(),
+            RekeyOutcome::AlreadyTarget
+        );
+
+        rekey_file(cache, &moved, CacheKeyScheme::Monolithic, &placement, false).unwrap();
+        assert_eq!(fs::read(&original).unwrap(), before);
+        assert!(!moved.exists());
+    }
+
+    #[test]
+    fn an_interrupted_move_is_finished_and_a_foreign_file_is_left_alone() {
+        let temp = tempfile::tempdir().unwrap();
+        let cache = temp.path();
+        let original = write_linux_v5_test_fixture(cache, MONOLITHIC.as_bytes(), HEADERS, b"body");
+        let placement = Placement::default();
+
+        // The state a run leaves when it stops after linking the copy.
+        let copy = temp.path().join("copy");
+        fs::copy(&original, &copy).unwrap();
+        rekey_file(
+            cache,
+            &original,
+            CacheKeyScheme::BareMetal,
+            &placement,
+            false,
+        )
+        .unwrap();
+        fs::rename(&copy, &original).unwrap();
+        assert_eq!(
+            rekey_file(
+                cache,
+                &original,
+                CacheKeyScheme::BareMetal,
+                &placement,
+                false
+            )
+            .unwrap(),
+            RekeyOutcome::FinishedMove
+        );
+        assert!(!original.exists());
+
+        let other = write_linux_v5_test_fixture(cache, MONOLITHIC.as_bytes(), HEADERS, b"else");
+        assert_eq!(
+            rekey_file(cache, &other, CacheKeyScheme::BareMetal, &placement, false).unwrap(),
+            RekeyOutcome::Conflict
+        );
+        assert!(other.exists());
+    }
+
+    #[test]
+    fn unverified_and_blocked_files_stay_where_they_are() {
+        let temp = tempfile::tempdir().unwrap();
+        let cache = temp.path();
+        let placement = Placement::default();
+        let steam = write_linux_v5_test_fixture(
+            cache,
+            b"steam/depot/1/chunk/abbytes=0-1048575",
+            HEADERS,
+            b"body",
+        );
+        assert_eq!(
+            rekey_file(cache, &steam, CacheKeyScheme::BareMetal, &placement, false).unwrap(),
+            RekeyOutcome::Blocked(RekeyBlocker::SliceShapeDiffers)
+        );
+
+        let misnamed = cache.join("00").join("00").join(format!("{:032x}", 0));
+        fs::create_dir_all(misnamed.parent().unwrap()).unwrap();
+        fs::copy(&steam, &misnamed).unwrap();
+        assert_eq!(
+            rekey_file(
+                cache,
+                &misnamed,
+                CacheKeyScheme::BareMetal,
+                &placement,
+                false
+            )
+            .unwrap(),
+            RekeyOutcome::Unverified
+        );
+        assert!(steam.exists() && misnamed.exists());
+    }
+}
@@ -18,6 +18,14 @@
 //!     every integer-percent advance only),
 //!   * the `LogScope` enum reproduces the two existing access.log purge predicates
 //!     (Steam = url ∪ safe-depot-id; Epic/named = url-only).
+//!
+//! Files of games on the protection list (`protected_games`) are never deleted: they are
+//! reported in `CacheRemovalOutcome::protected_files`, and their URLs come back in
+//! `protected_urls` so each bin can keep those lines out of its access.log purge.
+//!
+//! Given a `Quarantine`, `remove_cache_files` moves the files into its batch instead of
+//! unlinking them; the bin then records its log purge and row delete in the batch (see
+//! `quarantine`) rather than running them.
 
 use anyhow::Result;
 use serde::Serialize;
@@ -31,6 +39,8 @@ use crate::cancel;
 use crate::log_purge;
 use crate::progress_events::ProgressReporter;
 use crate::progress_utils;
+use crate::protected_games::ProtectedFiles;
+use crate::quarantine::{DeferredCleanup, Quarantine, QuarantineManifest};
 
 /// Progress JSON written to the progress file and tailed by the C# poller.
 /// Identical shape (and camelCase field names) to every removal bin's prior
@@ -149,6 +159,20 @@ pub struct CacheRemovalOutcome {
     /// read): left untouched. Some bins consume this to stop before deleting provenance.
     #[allow(dead_code)]
     pub verification_skips: usize,
+    /// Files left in place because they belong to a protected game.
+    pub protected_files: Vec<PathBuf>,
+    /// URLs with at least one protected file; they must stay in the access.log.
+    pub protected_urls: HashSet<String>,
+}
+
+impl CacheRemovalOutcome {
+    /// `protected_files` as report strings.
+    pub fn protected_file_names(&self) -> Vec<String> {
+        self.protected_files
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect()
+    }
 }
 
 /// Parallel cache-file deletion with progress reporting (the 10%-70% band).
@@ -157,7 +181,10 @@ pub struct CacheRemovalOutcome {
 /// on-disk slice for each (service, url) via `existing_cache_paths_for_url`, then
 /// rayon-delete with a symlink/escape guard, atomic counters, cooperative cancel,
 /// and a permission-error tally. The only parameterized difference is `cadence`,
-/// which selects between the two pre-existing emit frequencies.
+/// which selects between the two pre-existing emit frequencies. Paths in `protected`
+/// are skipped and reported instead of deleted; with `quarantine`, "deleted" files are
+/// moved into its batch.
+#[allow(clippy::too_many_arguments)]
 pub fn remove_cache_files(
     cache_dir: &Path,
     url_data: &HashMap<String, (String, i64)>,
@@ -166,6 +193,8 @@ pub fn remove_cache_files(
     keys: &RemovalStageKeys,
     cadence: ProgressCadence,
     scheme: cache_utils::CacheKeyScheme,
+    protected: &ProtectedFiles,
+    quarantine: Option<&Quarantine>,
 ) -> Result<CacheRemovalOutcome> {
     use rayon::prelude::*;
     use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
@@ -176,6 +205,7 @@ pub fn remove_cache_files(
     let permission_errors = AtomicUsize::new(0);
     let verification_skips = AtomicUsize::new(0);
     let parent_dirs = Mutex::new(HashSet::new());
+    let protected_hits: Mutex<Vec<(PathBuf, &str)>> = Mutex::new(Vec::new());
 
     eprintln!("Collecting cache file paths for deletion...");
 
@@ -185,10 +215,12 @@ pub fn remove_cache_files(
     // truncated to slice 0. The walk stat-probes every on-disk slice for the URL, so
     // `total_bytes` is no longer needed here. Under the bare-metal scheme each
     // candidate carries the literal key it must prove before deletion.
-    let paths_to_check: Vec<(std::path::PathBuf, Option<String>)> = url_data
+    let paths_to_check: Vec<(std::path::PathBuf, Option<String>, &str)> = url_data
         .par_iter()
-        .flat_map(|(url, (service, _total_bytes))| {
+        .flat_map_iter(|(url, (service, _total_bytes))| {
             cache_utils::existing_keyed_paths_for_url_with_scheme(scheme, cache_dir, service, url)
+                .into_iter()
+                .map(move |(path, expected_key)| (path, expected_key, url.as_str()))
         })
         .collect();
 
@@ -199,7 +231,7 @@ pub fn remove_cache_files(
     let last_reported_percent = AtomicUsize::new(0);
 
     // Parallel deletion with progress reporting
-    paths_to_check.par_iter().for_each(|(path, expected_key)| {
+    paths_to_check.par_iter().for_each(|(path, expected_key, url)| {
         // Cooperative cancellation: skip remaining files if cancel was requested.
         // Already-deleted files stay deleted — consistent partial state that C# reconciles.
         if cancel::is_cancelled() {
@@ -221,7 +253,8 @@ pub fn remove_cache_files(
             // vhost, Vary variant, foreign file) — never delete on doubt. Keep going
             // to the progress block after a skip so a fully skipped batch can still
             // report that every candidate was processed.
-            let verified_for_deletion = match scheme {
+            let is_protected = protected.contains(path);
+            let verified_for_deletion = is_protected || match scheme {
                 cache_utils::CacheKeyScheme::Monolithic => true,
                 cache_utils::CacheKeyScheme::BareMetal => expected_key
                     .as_deref()
@@ -229,7 +262,14 @@ pub fn remove_cache_files(
                     == Some(true),
             };
 
-            if !verified_for_deletion {
+            if is_protected {
+                match protected_hits.lock() {
+                    Ok(mut hits) => hits.push((path.clone(), *url)),
+                    Err(err) => {
+                        eprintln!("  Warning: failed to track protected file: {}", err);
+                    }
+                }
+            } else if !verified_for_deletion {
                 let skips = verification_skips.fetch_add(1, Ordering::Relaxed) + 1;
                 if skips <= 5 {
                     eprintln!(
@@ -242,7 +282,11 @@ pub fn remove_cache_files(
                     bytes_freed.fetch_add(metadata.len(), Ordering::Relaxed);
                 }
 
-                match fs::remove_file(path) {
+                let removed = match quarantine {
+                    Some(quarantine) => quarantine.take(path),
+                    None => fs::remove_file(path),
+                };
+                match removed {
                     Ok(_) => {
                         let count = deleted_files.fetch_add(1, Ordering::Relaxed) + 1;
 
@@ -356,6 +400,21 @@ pub fn remove_cache_files(
         );
     }
 
+    let mut protected_files = Vec::new();
+    let mut protected_urls = HashSet::new();
+    for (path, url) in protected_hits.into_inner().unwrap_or_else(|err| err.into_inner()) {
+        protected_files.push(path);
+        protected_urls.insert(url.to_string());
+    }
+    protected_files.sort();
+    if !protected_files.is_empty() {
+        eprintln!(
+            "  Kept {} file(s) of protected games ({} URL(s))",
+            protected_files.len(),
+            protected_urls.len()
+        );
+    }
+
     // After the parallel deletion phase: flush partial progress on cancel.
     if cancel::is_cancelled() {
         eprintln!("Cancellation requested — flushing partial progress and stopping.");
@@ -378,6 +437,8 @@ pub fn remove_cache_files(
         parent_dirs: final_dirs,
         permission_errors: final_permission_errors,
         verification_skips: final_verification_skips,
+        protected_files,
+        protected_urls,
     })
 }
 
@@ -418,6 +479,23 @@ pub fn permission_error_message(
     )
 }
 
+/// Quarantine-mode tail of a removal: closes the batch with the log purge and row delete the bin
+/// would otherwise run now. Cache permission errors leave the batch incomplete (so it defers
+/// nothing), just as they stop an unlinking removal before its database step.
+pub fn finish_quarantine(
+    quarantine: Quarantine,
+    cache_permission_errors: usize,
+    deferred: DeferredCleanup,
+) -> Result<QuarantineManifest> {
+    if cache_permission_errors > 0 {
+        let error_msg =
+            permission_error_message(cache_permission_errors, cache_permission_errors, 0);
+        eprintln!("\n{}", error_msg);
+        anyhow::bail!("{}", error_msg);
+    }
+    quarantine.finish(deferred)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -454,6 +532,8 @@ mod tests {
             &TEST_STAGE_KEYS,
             ProgressCadence::OnPercentAdvance,
             scheme,
+            &ProtectedFiles::none(),
+            None,
         )
         .unwrap()
     }
@@ -534,4 +614,43 @@ mod tests {
         assert_eq!(outcome.deleted_files, 1);
         assert_eq!(outcome.verification_skips, 0);
     }
+
+    #[test]
+    fn protected_files_are_kept_and_reported() {
+        let temp = tempfile::tempdir().unwrap();
+        let kept_url = "/depot/1/chunk/protected";
+        let removed_url = "/depot/1/chunk/unprotected";
+        let kept = cache_utils::calculate_cache_path_no_range(temp.path(), "steam", kept_url);
+        let removed = cache_utils::calculate_cache_path_no_range(temp.path(), "steam", removed_url);
+        write_cache_file(&kept, None);
+        write_cache_file(&removed, None);
+        let protected: ProtectedFiles =
+            [cache_utils::calculate_md5_digest(&format!("steam{kept_url}"))]
+                .into_iter()
+                .collect();
+        assert!(protected.contains(&kept), "fixture digest must name the kept file");
+
+        let url_data = HashMap::from([
+            (kept_url.to_string(), ("steam".to_string(), 0_i64)),
+            (removed_url.to_string(), ("steam".to_string(), 0_i64)),
+        ]);
+        let outcome = remove_cache_files(
+            temp.path(),
+            &url_data,
+            &temp.path().join("progress.json"),
+            &ProgressReporter::new(false),
+            &TEST_STAGE_KEYS,
+            ProgressCadence::OnPercentAdvance,
+            cache_utils::CacheKeyScheme::Monolithic,
+            &protected,
+            None,
+        )
+        .unwrap();
+
+        assert!(kept.exists());
+        assert!(!removed.exists());
+        assert_eq!(outcome.deleted_files, 1);
+        assert_eq!(outcome.protected_files, vec![kept]);
+        assert_eq!(outcome.protected_urls, HashSet::from([kept_url.to_string()]));
+    }
 }
@@ -0,0 +1,1278 @@
+//! Plan/apply for the removal heads (`cache_steam_remove`, `cache_epic_remove`, the
+//! name-keyed bins and `cache_service_remove`).
+//!
+//! `--plan` runs a head up to the point where it would start deleting and writes a
+//! [`RemovalPlan`] to `output_json` instead: the exact cache slices with their file
+//! fingerprints, the access-log lines per file, and the `Downloads` rows (with their log-entry
+//! count) the removal would delete. Nothing is touched.
+//!
+//! `--apply <plan>` executes that plan and nothing else. The plan must name the same target,
+//! directories and key scheme as the invocation, and every planned slice is re-fingerprinted
+//! before the first delete: a slice replaced on disk since planning, or one that now belongs to
+//! a protected game, refuses the whole apply. Slices nginx already evicted are counted and
+//! skipped. The log purge uses the planned URLs/depots and is recounted before the first delete;
+//! it only takes lines logged up to planning, and the database delete is bounded by the planned
+//! download ids (service removals also by the highest log-entry id at planning), so lines and
+//! rows that arrived after the plan are kept.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::postgres::PgArguments;
+use sqlx::query::QueryScalar;
+use sqlx::{PgPool, Postgres};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cache_corruption_detector::FileFingerprint;
+use crate::cache_structural_scanner;
+use crate::cache_utils::{self, CacheKeyScheme};
+use crate::cancel;
+use crate::db_backup::{self, BackupArchive, BackupPolicy};
+use crate::log_purge;
+use crate::progress_events::ProgressReporter;
+use crate::progress_utils;
+use crate::protected_games::{self, ProtectedFiles};
+use crate::quarantine::{DeferredCleanup, Quarantine};
+use crate::removal_core;
+
+pub const PLAN_VERSION: u32 = 2;
+
+/// How many changed or newly protected slices an apply refusal names.
+const DRIFT_EXAMPLES: usize = 5;
+
+/// `--plan` / `--apply` flags shared by every removal head.
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct PlanArgs {
+    /// Write the removal plan to output_json and delete nothing
+    #[arg(long, conflicts_with = "apply")]
+    pub plan: bool,
+
+    /// Execute exactly the removal recorded in this plan file
+    #[arg(long, value_name = "PLAN_JSON")]
+    pub apply: Option<PathBuf>,
+}
+
+/// What a plan removes. Compared against the invocation before a plan is applied.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "head", rename_all = "camelCase")]
+pub enum PlanTarget {
+    #[serde(rename_all = "camelCase")]
+    Steam { game_app_id: u32 },
+    #[serde(rename_all = "camelCase")]
+    Epic { game_name: String },
+    #[serde(rename_all = "camelCase")]
+    Named { service: String, game_name: String },
+    #[serde(rename_all = "camelCase")]
+    Service { service: String },
+}
+
+impl std::fmt::Display for PlanTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Steam { game_app_id } => write!(f, "Steam app {}", game_app_id),
+            Self::Epic { game_name } => write!(f, "Epic game '{}'", game_name),
+            Self::Named { service, game_name } => write!(f, "{} game '{}'", service, game_name),
+            Self::Service { service } => write!(f, "service '{}'", service),
+        }
+    }
+}
+
+impl PlanTarget {
+    /// The removal bin that plans this target, and its positional target argument.
+    pub fn head(&self) -> (String, String) {
+        match self {
+            Self::Steam { game_app_id } => {
+                ("cache_steam_remove".to_string(), game_app_id.to_string())
+            }
+            Self::Epic { game_name } => ("cache_epic_remove".to_string(), game_name.clone()),
+            Self::Named { service, game_name } => {
+                (format!("cache_{}_remove", service), game_name.clone())
+            }
+            Self::Service { service } => ("cache_service_remove".to_string(), service.clone()),
+        }
+    }
+
+    /// Tag of the pre-flight backup archive, matching the tags the heads already use.
+    fn backup_operation(&self) -> String {
+        match self {
+            Self::Steam { .. } => "steam_remove".to_string(),
+            Self::Epic { .. } => "epic_remove".to_string(),
+            Self::Named { service, .. } => format!("{}_remove", service),
+            Self::Service { .. } => "service_remove".to_string(),
+        }
+    }
+}
+
+/// One cache slice the plan deletes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlannedFile {
+    pub path: PathBuf,
+    pub url: String,
+    pub fingerprint: FileFingerprint,
+}
+
+/// The cache slices of a removal, as found on disk.
+#[derive(Debug, Default)]
+pub struct PlannedFiles {
+    pub files: Vec<PlannedFile>,
+    /// Slices left alone because a protected game uses them.
+    pub protected_files: Vec<PathBuf>,
+    /// URLs with at least one protected slice; their log lines are kept.
+    pub protected_urls: HashSet<String>,
+    /// Bare-metal slices whose KEY header did not verify.
+    pub unverified_files: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlannedLogFile {
+    pub path: PathBuf,
+    pub lines: u64,
+}
+
+/// The access-log purge of a plan: lines whose URL or depot id is listed (and, for a service
+/// removal, that carry the service), logged no later than `logged_until`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlannedLogPurge {
+    pub service: Option<String>,
+    pub urls: Vec<String>,
+    pub depot_ids: Vec<u32>,
+    /// RFC 3339. Lines logged after it are kept; `None` purges every matching line.
+    #[serde(default)]
+    pub logged_until: Option<String>,
+    pub files: Vec<PlannedLogFile>,
+    pub lines: u64,
+}
+
+impl PlannedLogPurge {
+    /// The purge predicate alone, without the per-file line counts of a plan.
+    pub fn scope(service: Option<&str>, urls: HashSet<String>, depot_ids: HashSet<u32>) -> Self {
+        let mut urls: Vec<String> = urls.into_iter().collect();
+        urls.sort();
+        let mut depot_ids: Vec<u32> = depot_ids.into_iter().collect();
+        depot_ids.sort_unstable();
+        Self {
+            service: service.map(str::to_string),
+            urls,
+            depot_ids,
+            logged_until: None,
+            files: Vec::new(),
+            lines: 0,
+        }
+    }
+
+    fn logged_until(&self) -> Result<Option<NaiveDateTime>> {
+        self.logged_until
+            .as_deref()
+            .map(|until| {
+                DateTime::parse_from_rfc3339(until)
+                    .map(|until| until.naive_utc())
+                    .with_context(|| format!("invalid logged-until bound {}", until))
+            })
+            .transpose()
+    }
+
+    /// The lines this purge removes from the logs as they are now, per file.
+    fn count(&self, log_dir: &Path) -> Result<Vec<(PathBuf, u64)>> {
+        if self.urls.is_empty() && self.depot_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        log_purge::count_log_entries_for_removal(
+            log_dir,
+            self.service.as_deref(),
+            &self.urls.iter().cloned().collect(),
+            &self.depot_ids.iter().copied().collect(),
+            self.logged_until()?,
+        )
+    }
+}
+
+/// Bounds the log-entry delete of a service removal.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceRows {
+    /// Lowercased service.
+    pub service: String,
+    /// Highest `LogEntries.Id` at planning; later rows are kept.
+    pub max_log_entry_id: i64,
+}
+
+/// The database rows of a plan.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlannedRows {
+    pub download_ids: Vec<i64>,
+    /// Log entries deleted with those downloads (or, for a service removal, the service's).
+    pub log_entries: u64,
+    pub service: Option<ServiceRows>,
+}
+
+/// Everything one removal would delete.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RemovalPlan {
+    pub version: u32,
+    pub created_at: String,
+    pub target: PlanTarget,
+    pub log_dir: PathBuf,
+    pub cache_dir: PathBuf,
+    /// `monolithic` | `bare_metal`
+    pub key_scheme: String,
+    pub files: Vec<PlannedFile>,
+    pub total_bytes: u64,
+    pub protected_files: Vec<PathBuf>,
+    pub unverified_files: Vec<PathBuf>,
+    pub log_purge: PlannedLogPurge,
+    pub database: PlannedRows,
+}
+
+impl RemovalPlan {
+    pub fn new(
+        target: PlanTarget,
+        log_dir: &Path,
+        cache_dir: &Path,
+        key_scheme: CacheKeyScheme,
+        files: PlannedFiles,
+        log_purge: PlannedLogPurge,
+        database: PlannedRows,
+    ) -> Self {
+        Self {
+            version: PLAN_VERSION,
+            created_at: progress_utils::current_timestamp(),
+            target,
+            log_dir: log_dir.to_path_buf(),
+            cache_dir: cache_dir.to_path_buf(),
+            key_scheme: key_scheme.as_config_str().to_string(),
+            total_bytes: files.files.iter().map(|file| file.fingerprint.len).sum(),
+            files: files.files,
+            protected_files: files.protected_files,
+            unverified_files: files.unverified_files,
+            log_purge,
+            database,
+        }
+    }
+
+    /// Reads a plan without checking what it was written for.
+    pub fn read(path: &Path) -> Result<Self> {
+        let file = fs::File::open(path)
+            .with_context(|| format!("failed to open plan file {}", path.display()))?;
+        let plan: Self = serde_json::from_reader(std::io::BufReader::new(file))
+            .with_context(|| format!("failed to parse plan file {}", path.display()))?;
+        if plan.version != PLAN_VERSION {
+            bail!("unsupported removal plan version {}", plan.version);
+        }
+        Ok(plan)
+    }
+
+    /// Reads a plan and checks it was written for this invocation.
+    pub fn load(
+        path: &Path,
+        target: &PlanTarget,
+        log_dir: &Path,
+        cache_dir: &Path,
+        key_scheme: CacheKeyScheme,
+    ) -> Result<Self> {
+        let plan = Self::read(path)?;
+        if &plan.target != target {
+            bail!(
+                "the plan removes {}, not {}; write a plan for this target first",
+                plan.target,
+                target
+            );
+        }
+        if plan.log_dir != log_dir || plan.cache_dir != cache_dir {
+            bail!(
+                "the plan was written for log directory {} and cache directory {}",
+                plan.log_dir.display(),
+                plan.cache_dir.display()
+            );
+        }
+        if plan.key_scheme != key_scheme.as_config_str() {
+            bail!(
+                "the plan was written for the {} key scheme",
+                plan.key_scheme
+            );
+        }
+        if !plan.unverified_files.is_empty() {
+            bail!(
+                "the plan holds {} cache file(s) whose embedded KEY did not verify; nothing can be applied from it",
+                plan.unverified_files.len()
+            );
+        }
+        Ok(plan)
+    }
+}
+
+/// Stage keys of one head. The lifecycle keys are the ones each head already emits; `planned`
+/// completes a `--plan` run.
+pub struct PlanStageKeys {
+    pub planned: &'static str,
+    pub cache_removing: &'static str,
+    pub cache_file_progress: &'static str,
+    pub dirs_cleaning: &'static str,
+    pub logs_removing: &'static str,
+    pub db_deleting: &'static str,
+    pub complete: &'static str,
+}
+
+/// Where a `--plan` or `--apply` run reports to.
+pub struct PlanRun<'a> {
+    pub output_json: &'a Path,
+    pub progress_path: &'a Path,
+    pub reporter: &'a ProgressReporter,
+    pub keys: &'a PlanStageKeys,
+    /// The head's identity fields (`gameAppId`, `gameName`, `service`), added to the
+    /// completion context and the backup header.
+    pub identity: serde_json::Value,
+}
+
+impl PlanRun<'_> {
+    fn progress(
+        &self,
+        status: &str,
+        stage_key: &str,
+        context: serde_json::Value,
+        percent_complete: f64,
+        files_processed: usize,
+        total_files: usize,
+    ) -> Result<()> {
+        removal_core::write_progress(
+            self.progress_path,
+            self.reporter,
+            status,
+            stage_key,
+            context,
+            percent_complete,
+            files_processed,
+            total_files,
+        )
+    }
+}
+
+fn file_verified_for_deletion(
+    scheme: CacheKeyScheme,
+    path: &Path,
+    expected_key: Option<&str>,
+) -> bool {
+    match scheme {
+        CacheKeyScheme::Monolithic => true,
+        CacheKeyScheme::BareMetal => {
+            expected_key.and_then(|expected| cache_utils::cache_file_key_matches(path, expected))
+                == Some(true)
+        }
+    }
+}
+
+/// Finds every on-disk slice of `url_data` (url -> (service, bytes)) the way
+/// `removal_core::remove_cache_files` does, fingerprinting the ones it would delete.
+pub fn plan_cache_files(
+    cache_dir: &Path,
+    url_data: &HashMap<String, (String, i64)>,
+    scheme: CacheKeyScheme,
+    protected: &ProtectedFiles,
+) -> PlannedFiles {
+    use rayon::prelude::*;
+
+    enum Found {
+        Delete(PlannedFile),
+        Protected(PathBuf, String),
+        Unverified(PathBuf),
+    }
+
+    let found: Vec<Found> = url_data
+        .par_iter()
+        .flat_map_iter(|(url, (service, _bytes))| {
+            cache_utils::existing_keyed_paths_for_url_with_scheme(scheme, cache_dir, service, url)
+                .into_iter()
+                .filter_map(move |(path, expected_key)| {
+                    let metadata = fs::symlink_metadata(&path).ok()?;
+                    if !metadata.file_type().is_file()
+                        || cache_utils::safe_path_under_root(cache_dir, &path).is_err()
+                    {
+                        return None;
+                    }
+                    if protected.contains(&path) {
+                        return Some(Found::Protected(path, url.clone()));
+                    }
+                    if !file_verified_for_deletion(scheme, &path, expected_key.as_deref()) {
+                        return Some(Found::Unverified(path));
+                    }
+                    Some(Found::Delete(PlannedFile {
+                        fingerprint: cache_structural_scanner::fingerprint(&metadata),
+                        path,
+                        url: url.clone(),
+                    }))
+                })
+        })
+        .collect();
+
+    let mut planned = PlannedFiles::default();
+    for item in found {
+        match item {
+            Found::Delete(file) => planned.files.push(file),
+            Found::Protected(path, url) => {
+                planned.protected_files.push(path);
+                planned.protected_urls.insert(url);
+            }
+            Found::Unverified(path) => planned.unverified_files.push(path),
+        }
+    }
+    planned.files.sort_by(|a, b| a.path.cmp(&b.path));
+    planned.protected_files.sort();
+    planned.unverified_files.sort();
+    planned
+}
+
+/// Counts the access-log lines the purge would remove.
+pub fn plan_log_purge(
+    log_dir: &Path,
+    service: Option<&str>,
+    urls: HashSet<String>,
+    depot_ids: HashSet<u32>,
+) -> Result<PlannedLogPurge> {
+    let scope = PlannedLogPurge {
+        logged_until: Some(progress_utils::current_timestamp()),
+        ..PlannedLogPurge::scope(service, urls, depot_ids)
+    };
+    let files: Vec<PlannedLogFile> = scope
+        .count(log_dir)?
+        .into_iter()
+        .map(|(path, lines)| PlannedLogFile { path, lines })
+        .collect();
+    Ok(PlannedLogPurge {
+        lines: files.iter().map(|file| file.lines).sum(),
+        files,
+        ..scope
+    })
+}
+
+/// The unprotected `Downloads` rows matched by `filter` (alias `d`, the same filter the head
+/// backs up) and the log entries referencing them.
+pub async fn plan_download_rows<F>(pool: &PgPool, filter: &str, bind: F) -> Result<PlannedRows>
+where
+    F: for<'q> FnOnce(
+        QueryScalar<'q, Postgres, i64, PgArguments>,
+    ) -> QueryScalar<'q, Postgres, i64, PgArguments>,
+{
+    let sql = format!(
+        r#"SELECT d."Id" FROM "Downloads" d WHERE {} ORDER BY d."Id""#,
+        protected_games::unprotected_downloads(filter)
+    );
+    let download_ids: Vec<i64> = bind(sqlx::query_scalar(&sql)).fetch_all(pool).await?;
+    let log_entries: i64 =
+        sqlx::query_scalar(r#"SELECT COUNT(*) FROM "LogEntries" WHERE "DownloadId" = ANY($1)"#)
+            .bind(&download_ids)
+            .fetch_one(pool)
+            .await?;
+    Ok(PlannedRows {
+        download_ids,
+        log_entries: log_entries as u64,
+        service: None,
+    })
+}
+
+/// The rows `cache_service_remove` deletes: the service's unprotected log entries up to the
+/// current highest id, and its unprotected downloads.
+pub async fn plan_service_rows(pool: &PgPool, service: &str) -> Result<PlannedRows> {
+    let service = service.to_lowercase();
+    let max_log_entry_id: i64 =
+        sqlx::query_scalar(r#"SELECT COALESCE(MAX("Id"), 0) FROM "LogEntries""#)
+            .fetch_one(pool)
+            .await?;
+    let log_entries: i64 = sqlx::query_scalar(&format!(
+        r#"SELECT COUNT(*) FROM "LogEntries" le
+           WHERE LOWER(le."Service") = $1 AND le."Id" <= $2 AND NOT {}"#,
+        protected_games::log_entry_is_protected("le")
+    ))
+    .bind(&service)
+    .bind(max_log_entry_id)
+    .fetch_one(pool)
+    .await?;
+    let download_ids: Vec<i64> = sqlx::query_scalar(&format!(
+        r#"SELECT d."Id" FROM "Downloads" d WHERE LOWER(d."Service") = $1 AND NOT {}
+           ORDER BY d."Id""#,
+        protected_games::download_is_protected("d")
+    ))
+    .bind(&service)
+    .fetch_all(pool)
+    .await?;
+    Ok(PlannedRows {
+        download_ids,
+        log_entries: log_entries as u64,
+        service: Some(ServiceRows {
+            service,
+            max_log_entry_id,
+        }),
+    })
+}
+
+/// Plan of a URL-scoped head (Epic, name-keyed): the slices of `url_data`, the log lines of
+/// every URL no protected slice shares, and the downloads matched by `filter` (alias `d`). Like
+/// the removal, an empty URL set plans nothing.
+#[allow(clippy::too_many_arguments)]
+pub async fn plan_url_removal<F>(
+    pool: &PgPool,
+    target: PlanTarget,
+    log_dir: &Path,
+    cache_dir: &Path,
+    scheme: CacheKeyScheme,
+    url_data: &HashMap<String, (String, i64)>,
+    protected: &ProtectedFiles,
+    filter: &str,
+    bind: F,
+) -> Result<RemovalPlan>
+where
+    F: for<'q> FnOnce(
+        QueryScalar<'q, Postgres, i64, PgArguments>,
+    ) -> QueryScalar<'q, Postgres, i64, PgArguments>,
+{
+    if url_data.is_empty() {
+        return Ok(RemovalPlan::new(
+            target,
+            log_dir,
+            cache_dir,
+            scheme,
+            PlannedFiles::default(),
+            PlannedLogPurge::default(),
+            PlannedRows::default(),
+        ));
+    }
+    let files = plan_cache_files(cache_dir, url_data, scheme, protected);
+    let urls: HashSet<String> = url_data
+        .keys()
+        .filter(|url| !files.protected_urls.contains(*url))
+        .cloned()
+        .collect();
+    let log_purge = plan_log_purge(log_dir, None, urls, HashSet::new())?;
+    let database = plan_download_rows(pool, filter, bind).await?;
+    Ok(RemovalPlan::new(
+        target, log_dir, cache_dir, scheme, files, log_purge, database,
+    ))
+}
+
+/// Writes `plan` to `output_json` and completes the run with the head's `planned` key.
+pub fn write_plan(plan: &RemovalPlan, run: &PlanRun) -> Result<()> {
+    fs::write(run.output_json, serde_json::to_string_pretty(plan)?)
+        .with_context(|| format!("failed to write plan to {}", run.output_json.display()))?;
+
+    eprintln!("\n=== Removal Plan ===");
+    eprintln!(
+        "Cache files: {} ({:.2} MB)",
+        plan.files.len(),
+        plan.total_bytes as f64 / 1_048_576.0
+    );
+    eprintln!(
+        "Log lines: {} in {} file(s)",
+        plan.log_purge.lines,
+        plan.log_purge.files.len()
+    );
+    eprintln!(
+        "Database: {} download(s), {} log entries",
+        plan.database.download_ids.len(),
+        plan.database.log_entries
+    );
+    if !plan.protected_files.is_empty() {
+        eprintln!("Protected files kept: {}", plan.protected_files.len());
+    }
+    if !plan.unverified_files.is_empty() {
+        eprintln!(
+            "WARNING: {} file(s) failed KEY verification; this plan cannot be applied",
+            plan.unverified_files.len()
+        );
+    }
+    eprintln!("Plan saved to: {}", run.output_json.display());
+
+    let mut context = json!({
+        "files": plan.files.len(),
+        "gb": plan.total_bytes as f64 / 1_073_741_824.0,
+        "logEntries": plan.log_purge.lines,
+        "downloads": plan.database.download_ids.len(),
+        "dbLogEntries": plan.database.log_entries,
+        "protectedSkipped": plan.protected_files.len(),
+        "unverified": plan.unverified_files.len(),
+    });
+    merge_context(&mut context, run.identity.clone());
+    run.progress(
+        "completed",
+        run.keys.planned,
+        context,
+        100.0,
+        0,
+        plan.files.len(),
+    )
+}
+
+fn merge_context(context: &mut serde_json::Value, extra: serde_json::Value) {
+    if let (Some(map), serde_json::Value::Object(extra)) = (context.as_object_mut(), extra) {
+        map.extend(extra);
+    }
+}
+
+/// Planned slices sorted by what re-fingerprinting found.
+#[derive(Debug, Default)]
+struct Revalidation {
+    ready: Vec<usize>,
+    gone: usize,
+    changed: Vec<PathBuf>,
+    protected: Vec<PathBuf>,
+}
+
+fn revalidate_files(plan: &RemovalPlan, protected: &ProtectedFiles) -> Result<Revalidation> {
+    let mut revalidation = Revalidation::default();
+    for (index, file) in plan.files.iter().enumerate() {
+        if protected.contains(&file.path) {
+            revalidation.protected.push(file.path.clone());
+        } else if cache_structural_scanner::path_fingerprint_matches(&file.path, &file.fingerprint)?
+        {
+            revalidation.ready.push(index);
+        } else if fs::symlink_metadata(&file.path).is_err() {
+            revalidation.gone += 1;
+        } else {
+            revalidation.changed.push(file.path.clone());
+        }
+    }
+    Ok(revalidation)
+}
+
+fn ensure_plan_still_holds(revalidation: &Revalidation) -> Result<()> {
+    let refuse = |what: &str, paths: &[PathBuf]| -> Result<()> {
+        if paths.is_empty() {
+            return Ok(());
+        }
+        let examples: Vec<String> = paths
+            .iter()
+            .take(DRIFT_EXAMPLES)
+            .map(|path| path.display().to_string())
+            .collect();
+        bail!(
+            "{} planned cache file(s) {} since the plan was written (e.g. {}); nothing was deleted, write a new plan",
+            paths.len(),
+            what,
+            examples.join(", ")
+        )
+    };
+    refuse("changed on disk", &revalidation.changed)?;
+    refuse("became protected", &revalidation.protected)
+}
+
+/// Recounts the planned log purge. Lines logged since planning are outside it, so a different
+/// count means the logs themselves changed (rotated, pruned or rewritten) and the purge would no
+/// longer remove what the plan lists.
+fn ensure_log_purge_still_holds(plan: &RemovalPlan) -> Result<()> {
+    let planned: Vec<(PathBuf, u64)> = plan
+        .log_purge
+        .files
+        .iter()
+        .map(|file| (file.path.clone(), file.lines))
+        .collect();
+    let current = plan.log_purge.count(&plan.log_dir)?;
+    if current == planned {
+        return Ok(());
+    }
+    let drifted: Vec<String> = planned
+        .iter()
+        .filter(|file| !current.contains(file))
+        .chain(current.iter().filter(|file| !planned.contains(file)))
+        .map(|(path, _)| path.display().to_string())
+        .collect::<std::collections::BTreeSet<_>>()
+        .into_iter()
+        .take(DRIFT_EXAMPLES)
+        .collect();
+    bail!(
+        "the planned access-log lines changed since the plan was written ({} planned, {} now; e.g. {}); nothing was deleted, write a new plan",
+        plan.log_purge.lines,
+        current.iter().map(|(_, lines)| lines).sum::<u64>(),
+        drifted.join(", ")
+    )
+}
+
+#[derive(Debug, Serialize)]
+struct AppliedPlanReport {
+    target: PlanTarget,
+    plan_created_at: String,
+    cache_files_deleted: usize,
+    cache_files_already_gone: usize,
+    total_bytes_freed: u64,
+    empty_dirs_removed: usize,
+    log_entries_removed: u64,
+    database_entries_deleted: u64,
+    protected_files_skipped: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quarantine_batch: Option<String>,
+}
+
+struct AppliedFiles {
+    deleted_files: usize,
+    bytes_freed: u64,
+    parent_dirs: HashSet<PathBuf>,
+    permission_errors: usize,
+}
+
+fn delete_planned_files(
+    plan: &RemovalPlan,
+    ready: &[usize],
+    run: &PlanRun,
+    quarantine: Option<&Quarantine>,
+) -> AppliedFiles {
+    use rayon::prelude::*;
+    use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    let total = ready.len();
+    let deleted_files = AtomicUsize::new(0);
+    let bytes_freed = AtomicU64::new(0);
+    let permission_errors = AtomicUsize::new(0);
+    let checked = AtomicUsize::new(0);
+    let last_reported_percent = AtomicUsize::new(0);
+    let parent_dirs = Mutex::new(HashSet::new());
+
+    ready.par_iter().for_each(|index| {
+        if cancel::is_cancelled() {
+            return;
+        }
+        let file = &plan.files[*index];
+        let done = checked.fetch_add(1, Ordering::Relaxed) + 1;
+
+        // Re-check right before the unlink so a slice nginx rewrote mid-apply survives.
+        if matches!(
+            cache_structural_scanner::path_fingerprint_matches(&file.path, &file.fingerprint),
+            Ok(true)
+        ) {
+            let removed = match quarantine {
+                Some(quarantine) => quarantine.take(&file.path),
+                None => fs::remove_file(&file.path),
+            };
+            match removed {
+                Ok(()) => {
+                    deleted_files.fetch_add(1, Ordering::Relaxed);
+                    bytes_freed.fetch_add(file.fingerprint.len, Ordering::Relaxed);
+                    if let Some(parent) = file.path.parent() {
+                        parent_dirs
+                            .lock()
+                            .unwrap_or_else(|err| err.into_inner())
+                            .insert(parent.to_path_buf());
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                    let err_count = permission_errors.fetch_add(1, Ordering::Relaxed) + 1;
+                    if err_count <= 5 {
+                        eprintln!(
+                            "  ERROR: Permission denied deleting {}: {}",
+                            file.path.display(),
+                            e
+                        );
+                    }
+                }
+                Err(e) => {
+                    eprintln!("  Warning: Failed to delete {}: {}", file.path.display(), e);
+                }
+            }
+        }
+
+        let current_pct = done * 100 / total.max(1);
+        let prev_pct = last_reported_percent.load(Ordering::Relaxed);
+        if current_pct > prev_pct
+            && last_reported_percent
+                .compare_exchange(prev_pct, current_pct, Ordering::SeqCst, Ordering::Relaxed)
+                .is_ok()
+        {
+            let del_count = deleted_files.load(Ordering::Relaxed);
+            let _ = run.progress(
+                "removing_cache",
+                run.keys.cache_file_progress,
+                json!({ "n": del_count, "total": total }),
+                10.0 + (done as f64 / total as f64) * 60.0,
+                del_count,
+                total,
+            );
+        }
+    });
+
+    AppliedFiles {
+        deleted_files: deleted_files.into_inner(),
+        bytes_freed: bytes_freed.into_inner(),
+        parent_dirs: parent_dirs
+            .into_inner()
+            .unwrap_or_else(|err| err.into_inner()),
+        permission_errors: permission_errors.into_inner(),
+    }
+}
+
+/// Backs up the rows `delete_planned_rows` would delete, when a backup was requested.
+/// `operation` names the archive (e.g. `steam_remove`).
+pub async fn backup_planned_rows(
+    pool: &PgPool,
+    policy: Option<&BackupPolicy>,
+    operation: &str,
+    rows: &PlannedRows,
+    context: serde_json::Value,
+) -> Result<Option<db_backup::BackupSummary>> {
+    let Some(policy) = policy else {
+        return Ok(None);
+    };
+    eprintln!(
+        "\nBacking up database records to {}...",
+        policy.dir.display()
+    );
+    let mut archive = BackupArchive::create(policy, operation, context)?;
+    let ids = &rows.download_ids;
+    match &rows.service {
+        Some(service) => {
+            archive
+                .add_downloads(pool, r#"d."Id" = ANY($1)"#, |q| q.bind(ids.clone()))
+                .await?;
+            archive
+                .add_rows(
+                    pool,
+                    "LogEntries",
+                    "le",
+                    r#"LOWER(le."Service") = $1 AND le."Id" <= $2"#,
+                    |q| q.bind(service.service.clone()).bind(service.max_log_entry_id),
+                )
+                .await?;
+        }
+        None => {
+            archive
+                .add_download_cascade(pool, r#"d."Id" = ANY($1)"#, |q| q.bind(ids.clone()))
+                .await?;
+        }
+    }
+    let summary = archive.finish()?;
+    db_backup::log_summary(&summary);
+    Ok(Some(summary))
+}
+
+/// Runs a planned access-log purge, leaving lines logged after `logged_until` alone. Returns
+/// `(lines_removed, permission_errors)`.
+pub fn purge_planned_logs(log_dir: &Path, purge: &PlannedLogPurge) -> Result<(u64, usize)> {
+    log_purge::remove_log_entries_for_removal(
+        log_dir,
+        purge.service.as_deref(),
+        &purge.urls.iter().cloned().collect(),
+        &purge.depot_ids.iter().copied().collect(),
+        purge.logged_until()?,
+    )
+}
+
+/// Deletes the planned rows. Protection is re-checked in SQL, and a download a kept log entry
+/// still references stays.
+pub async fn delete_planned_rows(pool: &PgPool, rows: &PlannedRows) -> Result<u64> {
+    let log_result = match &rows.service {
+        Some(service) => {
+            sqlx::query(&format!(
+                r#"DELETE FROM "LogEntries" le
+                   WHERE LOWER(le."Service") = $1 AND le."Id" <= $2 AND NOT {}"#,
+                protected_games::log_entry_is_protected("le")
+            ))
+            .bind(&service.service)
+            .bind(service.max_log_entry_id)
+            .execute(pool)
+            .await?
+        }
+        None => {
+            sqlx::query(&format!(
+                r#"DELETE FROM "LogEntries" WHERE "DownloadId" IN (
+                       SELECT d."Id" FROM "Downloads" d WHERE d."Id" = ANY($1) AND NOT {}
+                   )"#,
+                protected_games::download_is_protected("d")
+            ))
+            .bind(&rows.download_ids)
+            .execute(pool)
+            .await?
+        }
+    };
+    eprintln!("  Deleted {} log entry records", log_result.rows_affected());
+
+    let downloads_result = sqlx::query(&format!(
+        r#"DELETE FROM "Downloads" d WHERE d."Id" = ANY($1) AND NOT {}
+           AND NOT EXISTS (SELECT 1 FROM "LogEntries" le WHERE le."DownloadId" = d."Id")"#,
+        protected_games::download_is_protected("d")
+    ))
+    .bind(&rows.download_ids)
+    .execute(pool)
+    .await?;
+    eprintln!(
+        "  Deleted {} download records",
+        downloads_result.rows_affected()
+    );
+
+    Ok(log_result.rows_affected() + downloads_result.rows_affected())
+}
+
+/// Executes a loaded plan: revalidate, back up, delete slices, purge logs, delete rows. The head
+/// has already refused a protected target and emitted its starting event. With `quarantine`, the
+/// slices move into its batch and the rest of the plan (backup included) waits for its purge.
+pub async fn apply(
+    pool: &PgPool,
+    plan: &RemovalPlan,
+    protected: &ProtectedFiles,
+    backup: Option<&BackupPolicy>,
+    quarantine: Option<Quarantine>,
+    run: &PlanRun<'_>,
+) -> Result<()> {
+    eprintln!(
+        "Applying removal plan written at {} ({} cache files, {} log lines, {} downloads)",
+        plan.created_at,
+        plan.files.len(),
+        plan.log_purge.lines,
+        plan.database.download_ids.len()
+    );
+
+    let revalidation = revalidate_files(plan, protected)?;
+    ensure_plan_still_holds(&revalidation)?;
+    ensure_log_purge_still_holds(plan)?;
+    if revalidation.gone > 0 {
+        eprintln!(
+            "  {} planned cache file(s) are already gone from disk",
+            revalidation.gone
+        );
+    }
+
+    let mut backup_context = run.identity.clone();
+    merge_context(
+        &mut backup_context,
+        json!({ "planCreatedAt": plan.created_at }),
+    );
+    let backup = backup_planned_rows(
+        pool,
+        backup.filter(|_| quarantine.is_none()),
+        &plan.target.backup_operation(),
+        &plan.database,
+        backup_context,
+    )
+    .await?;
+
+    let total = revalidation.ready.len();
+    run.progress(
+        "removing_cache",
+        run.keys.cache_removing,
+        json!({ "count": total }),
+        10.0,
+        0,
+        total,
+    )?;
+    eprintln!("\nRemoving cache files...");
+    let files = delete_planned_files(plan, &revalidation.ready, run, quarantine.as_ref());
+
+    if cancel::is_cancelled() {
+        eprintln!("Cancellation confirmed — cleaning up partial directories and exiting.");
+        cache_utils::cleanup_empty_directories(&plan.cache_dir, files.parent_dirs);
+        return Ok(());
+    }
+
+    run.progress(
+        "cleaning_directories",
+        run.keys.dirs_cleaning,
+        json!({}),
+        70.0,
+        files.deleted_files,
+        total,
+    )?;
+    let empty_dirs_removed =
+        cache_utils::cleanup_empty_directories(&plan.cache_dir, files.parent_dirs);
+
+    let mut report = AppliedPlanReport {
+        target: plan.target.clone(),
+        plan_created_at: plan.created_at.clone(),
+        cache_files_deleted: files.deleted_files,
+        cache_files_already_gone: revalidation.gone,
+        total_bytes_freed: files.bytes_freed,
+        empty_dirs_removed,
+        log_entries_removed: 0,
+        database_entries_deleted: 0,
+        protected_files_skipped: plan
+            .protected_files
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect(),
+        quarantine_batch: None,
+    };
+
+    if let Some(quarantine) = quarantine {
+        report.quarantine_batch = Some(quarantine.id().to_string());
+        let finished = removal_core::finish_quarantine(
+            quarantine,
+            files.permission_errors,
+            DeferredCleanup::Removal {
+                log_dir: plan.log_dir.clone(),
+                log_purge: plan.log_purge.clone(),
+                database: plan.database.clone(),
+            },
+        );
+        fs::write(run.output_json, serde_json::to_string_pretty(&report)?)?;
+        let manifest = finished?;
+        let mut context = json!({
+            "files": report.cache_files_deleted,
+            "gb": report.total_bytes_freed as f64 / 1_073_741_824.0,
+            "logEntries": 0,
+            "dbRecords": 0,
+            "alreadyGone": report.cache_files_already_gone,
+            "protectedSkipped": report.protected_files_skipped.len(),
+            "quarantineBatch": manifest.id,
+        });
+        merge_context(&mut context, run.identity.clone());
+        return run.progress(
+            "completed",
+            run.keys.complete,
+            context,
+            100.0,
+            files.deleted_files,
+            total,
+        );
+    }
+
+    run.progress(
+        "removing_logs",
+        run.keys.logs_removing,
+        json!({}),
+        80.0,
+        files.deleted_files,
+        total,
+    )?;
+    eprintln!("\nRemoving log entries...");
+    let (log_entries_removed, log_permission_errors) =
+        purge_planned_logs(&plan.log_dir, &plan.log_purge)?;
+    report.log_entries_removed = log_entries_removed;
+
+    let total_permission_errors = files.permission_errors + log_permission_errors;
+    if total_permission_errors > 0 {
+        let error_msg = removal_core::permission_error_message(
+            total_permission_errors,
+            files.permission_errors,
+            log_permission_errors,
+        );
+        eprintln!("\n{}", error_msg);
+        fs::write(run.output_json, serde_json::to_string_pretty(&report)?)?;
+        bail!("{}", error_msg);
+    }
+
+    run.progress(
+        "removing_database",
+        run.keys.db_deleting,
+        json!({}),
+        90.0,
+        files.deleted_files,
+        total,
+    )?;
+    eprintln!("\nRemoving database records...");
+    report.database_entries_deleted = delete_planned_rows(pool, &plan.database).await?;
+    fs::write(run.output_json, serde_json::to_string_pretty(&report)?)?;
+
+    let mut context = json!({
+        "files": report.cache_files_deleted,
+        "gb": report.total_bytes_freed as f64 / 1_073_741_824.0,
+        "logEntries": report.log_entries_removed,
+        "dbRecords": report.database_entries_deleted,
+        "alreadyGone": report.cache_files_already_gone,
+        "protectedSkipped": report.protected_files_skipped.len(),
+    });
+    merge_context(&mut context, run.identity.clone());
+    db_backup::annotate_context(&mut context, backup.as_ref());
+    run.progress(
+        "completed",
+        run.keys.complete,
+        context,
+        100.0,
+        files.deleted_files,
+        total,
+    )?;
+
+    eprintln!("\n=== Removal Summary ===");
+    eprintln!("Cache files deleted: {}", report.cache_files_deleted);
+    eprintln!("Already gone: {}", report.cache_files_already_gone);
+    eprintln!(
+        "Space freed: {:.2} MB",
+        report.total_bytes_freed as f64 / 1_048_576.0
+    );
+    eprintln!("Log entries removed: {}", report.log_entries_removed);
+    eprintln!(
+        "Database entries deleted: {}",
+        report.database_entries_deleted
+    );
+    eprintln!("Report saved to: {}", run.output_json.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn steam_url_data(urls: &[&str]) -> HashMap<String, (String, i64)> {
+        urls.iter()
+            .map(|url| (url.to_string(), ("steam".to_string(), 0)))
+            .collect()
+    }
+
+    fn write_slice(cache_dir: &Path, url: &str) -> PathBuf {
+        let path = cache_utils::calculate_cache_path_no_range(cache_dir, "steam", url);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, b"slice").unwrap();
+        path
+    }
+
+    fn plan_for(cache_dir: &Path, files: PlannedFiles) -> RemovalPlan {
+        RemovalPlan::new(
+            PlanTarget::Steam { game_app_id: 730 },
+            Path::new("/logs"),
+            cache_dir,
+            CacheKeyScheme::Monolithic,
+            files,
+            PlannedLogPurge::default(),
+            PlannedRows::default(),
+        )
+    }
+
+    #[test]
+    fn planning_fingerprints_slices_and_sets_protected_ones_aside() {
+        let temp = tempfile::tempdir().unwrap();
+        let kept = write_slice(temp.path(), "/depot/1/chunk/a");
+        let removed = write_slice(temp.path(), "/depot/1/chunk/b");
+        let protected: ProtectedFiles =
+            [
+                cache_utils::parse_cache_file_digest(kept.file_name().unwrap().to_str().unwrap())
+                    .unwrap(),
+            ]
+            .into_iter()
+            .collect();
+
+        let planned = plan_cache_files(
+            temp.path(),
+            &steam_url_data(&["/depot/1/chunk/a", "/depot/1/chunk/b"]),
+            CacheKeyScheme::Monolithic,
+            &protected,
+        );
+
+        assert_eq!(planned.files.len(), 1);
+        assert_eq!(planned.files[0].path, removed);
+        assert_eq!(planned.files[0].fingerprint.len, 5);
+        assert_eq!(planned.protected_files, vec![kept]);
+        assert!(planned.protected_urls.contains("/depot/1/chunk/a"));
+        assert!(planned.unverified_files.is_empty());
+    }
+
+    #[test]
+    fn apply_refuses_a_plan_whose_slices_changed_and_skips_evicted_ones() {
+        let temp = tempfile::tempdir().unwrap();
+        let changed = write_slice(temp.path(), "/depot/1/chunk/a");
+        let evicted = write_slice(temp.path(), "/depot/1/chunk/b");
+        let untouched = write_slice(temp.path(), "/depot/1/chunk/c");
+        let plan = plan_for(
+            temp.path(),
+            plan_cache_files(
+                temp.path(),
+                &steam_url_data(&["/depot/1/chunk/a", "/depot/1/chunk/b", "/depot/1/chunk/c"]),
+                CacheKeyScheme::Monolithic,
+                &ProtectedFiles::none(),
+            ),
+        );
+
+        fs::remove_file(&evicted).unwrap();
+        let revalidation = revalidate_files(&plan, &ProtectedFiles::none()).unwrap();
+        assert_eq!(revalidation.gone, 1);
+        assert_eq!(revalidation.ready.len(), 2);
+        assert!(ensure_plan_still_holds(&revalidation).is_ok());
+
+        fs::remove_file(&changed).unwrap();
+        fs::write(&changed, b"a different object").unwrap();
+        let revalidation = revalidate_files(&plan, &ProtectedFiles::none()).unwrap();
+        assert_eq!(revalidation.changed, vec![changed]);
+        let error = ensure_plan_still_holds(&revalidation)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("changed on disk"));
+        assert!(error.contains("nothing was deleted"));
+        assert!(untouched.exists());
+    }
+
+    #[test]
+    fn a_plan_only_loads_for_the_invocation_it_was_written_for() {
+        let temp = tempfile::tempdir().unwrap();
+        let plan_path = temp.path().join("plan.json");
+        let plan = plan_for(temp.path(), PlannedFiles::default());
+        fs::write(&plan_path, serde_json::to_string(&plan).unwrap()).unwrap();
+        let logs = Path::new("/logs");
+        let steam = PlanTarget::Steam { game_app_id: 730 };
+
+        assert!(RemovalPlan::load(
+            &plan_path,
+            &steam,
+            logs,
+            temp.path(),
+            CacheKeyScheme::Monolithic
+        )
+        .is_ok());
+        assert!(RemovalPlan::load(
+            &plan_path,
+            &PlanTarget::Steam { game_app_id: 570 },
+            logs,
+            temp.path(),
+            CacheKeyScheme::Monolithic
+        )
+        .is_err());
+        assert!(RemovalPlan::load(
+            &plan_path,
+            &steam,
+            Path::new("/other"),
+            temp.path(),
+            CacheKeyScheme::Monolithic
+        )
+        .is_err());
+        assert!(RemovalPlan::load(
+            &plan_path,
+            &steam,
+            logs,
+            temp.path(),
+            CacheKeyScheme::BareMetal
+        )
+        .is_err());
+    }
+
+    fn access_line(timestamp: &str, url: &str) -> String {
+        format!(
+            "[steam] 192.168.1.50 / - - - [{timestamp}] \"GET {url} HTTP/1.1\" 200 1024 \"-\" \"Valve/Steam\" \"HIT\" \"-\" \"-\"\n"
+        )
+    }
+
+    #[test]
+    fn the_planned_log_purge_keeps_later_lines_and_refuses_changed_logs() {
+        let temp = tempfile::tempdir().unwrap();
+        let log_dir = temp.path().join("logs");
+        fs::create_dir_all(&log_dir).unwrap();
+        let log_path = log_dir.join("access.log");
+        let planned_lines = access_line("01/Jan/2024:00:00:00 +0000", "/depot/1/chunk/a")
+            + &access_line("01/Jan/2024:00:00:01 +0000", "/depot/2/chunk/b");
+        fs::write(&log_path, &planned_lines).unwrap();
+        let urls: HashSet<String> = ["/depot/1/chunk/a".to_string()].into_iter().collect();
+        let log_purge = plan_log_purge(&log_dir, None, urls, [2].into_iter().collect()).unwrap();
+        assert_eq!(log_purge.lines, 2);
+        let plan = RemovalPlan::new(
+            PlanTarget::Steam { game_app_id: 730 },
+            &log_dir,
+            temp.path(),
+            CacheKeyScheme::Monolithic,
+            PlannedFiles::default(),
+            log_purge,
+            PlannedRows::default(),
+        );
+
+        let later = access_line("01/Jan/2099:00:00:00 +0000", "/depot/1/chunk/a");
+        fs::write(&log_path, planned_lines.clone() + &later).unwrap();
+        assert!(ensure_log_purge_still_holds(&plan).is_ok());
+
+        fs::write(&log_path, planned_lines.lines().next().unwrap().to_string() + "\n" + &later)
+            .unwrap();
+        let error = ensure_log_purge_still_holds(&plan).unwrap_err().to_string();
+        assert!(error.contains("2 planned, 1 now"));
+        assert!(error.contains("nothing was deleted"));
+
+        fs::write(&log_path, planned_lines + &later).unwrap();
+        let (removed, permission_errors) = purge_planned_logs(&log_dir, &plan.log_purge).unwrap();
+        assert_eq!((removed, permission_errors), (2, 0));
+        assert_eq!(fs::read_to_string(&log_path).unwrap(), later);
+    }
+}
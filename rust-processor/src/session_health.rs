@@ -0,0 +1,402 @@
+//! Stalled and erroring download detection for the live speed tracker.
+//!
+//! A live snapshot only shows what moved bytes in the last couple of seconds, so a download that
+//! stopped making progress simply drops off it. This module keeps the two signals the window
+//! cannot: each session's recent peak throughput (so a collapse is visible after the bytes stop)
+//! and a short lookback of every request, including the zero-byte ones the speed window discards
+//! (so 4xx/5xx storms and MISS retry loops are visible at all).
+//!
+//! The tracker feeds it and names the results; nothing here touches the database.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Arc;
+
+use chrono::NaiveDateTime;
+use serde::Serialize;
+
+/// How far back requests are kept for the error and MISS checks.
+pub const HEALTH_LOOKBACK_SECONDS: i64 = 60;
+
+/// Upper bound on retained requests, so a request flood cannot grow the lookback without limit.
+const MAX_TRACKED_REQUESTS: usize = 50_000;
+
+/// A session only counts as stalled after its throughput has stayed collapsed this long; shorter
+/// dips are normal between depots and chunk batches.
+pub const STALL_AFTER_SECONDS: i64 = 30;
+
+/// Sessions that never got faster than this are too small to judge a collapse against.
+const STALL_MIN_PEAK_BYTES_PER_SECOND: f64 = 1024.0 * 1024.0;
+
+/// Throughput below this fraction of the session's peak counts as collapsed.
+const STALL_FRACTION: f64 = 0.05;
+
+/// A session that went quiet this close to its expected size is treated as finished, not stalled.
+const STALL_PROGRESS_CEILING: f64 = 95.0;
+
+/// Fewest requests in the lookback before an error share means anything.
+const ERROR_MIN_REQUESTS: u32 = 10;
+
+/// Share of 4xx/5xx responses at which a group is reported as erroring.
+const ERROR_SHARE: f64 = 0.5;
+
+/// MISSes on one URL and byte range within the lookback that count as a retry loop. A healthy
+/// client fetches a chunk once; the cache fills it and later requests HIT.
+const REPEATED_MISS_COUNT: u32 = 3;
+
+/// One parsed request, whatever it served. Zero-byte and error responses are kept here even though
+/// the speed window ignores them.
+#[derive(Debug, Clone)]
+pub struct RequestOutcome {
+    pub timestamp: NaiveDateTime,
+    pub client_ip: String,
+    pub service: String,
+    pub depot_id: Option<u32>,
+    pub url: String,
+    pub http_range: String,
+    pub status_code: i32,
+    pub is_miss: bool,
+    pub datasource: Arc<str>,
+}
+
+/// What went wrong in a group's recent requests.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorBreakdown {
+    pub requests: u32,
+    pub client_errors: u32,
+    pub server_errors: u32,
+    /// Counts per error status code, keyed by the code as a string so the JSON stays an object.
+    pub by_status: BTreeMap<String, u32>,
+    /// MISSes on the most-retried URL and range, and that URL.
+    pub repeated_misses: u32,
+    pub repeated_miss_url: Option<String>,
+}
+
+impl ErrorBreakdown {
+    fn error_share(&self) -> f64 {
+        if self.requests == 0 {
+            return 0.0;
+        }
+        f64::from(self.client_errors + self.server_errors) / f64::from(self.requests)
+    }
+
+    fn is_erroring(&self) -> bool {
+        (self.requests >= ERROR_MIN_REQUESTS && self.error_share() >= ERROR_SHARE)
+            || self.repeated_misses >= REPEATED_MISS_COUNT
+    }
+}
+
+/// A client's requests for one service (and depot, when the URL carries one) that look broken.
+#[derive(Debug, Clone)]
+pub struct ErroringGroup {
+    pub client_ip: String,
+    pub service: String,
+    pub depot_id: Option<u32>,
+    pub last_url: String,
+    pub first_error_at: NaiveDateTime,
+    pub breakdown: ErrorBreakdown,
+    pub datasources: Vec<Arc<str>>,
+}
+
+/// The latest view of one session, as the tracker saw it in its game row.
+#[derive(Debug, Clone)]
+pub struct SessionObservation {
+    pub client_ip: String,
+    pub service: String,
+    pub game_name: Option<String>,
+    pub game_app_id: Option<u32>,
+    pub depot_id: u32,
+    pub last_url: Option<String>,
+    pub percent_complete: Option<f64>,
+    pub datasources: Vec<Arc<str>>,
+}
+
+/// A session whose throughput collapsed and has not recovered.
+#[derive(Debug, Clone)]
+pub struct StalledSession {
+    pub session: SessionObservation,
+    pub peak_bytes_per_second: f64,
+    pub bytes_per_second: f64,
+    pub stalled_since: NaiveDateTime,
+    /// Requests still arriving for this client and service; a hung client sends none.
+    pub recent_requests: u32,
+}
+
+#[derive(Debug, Clone)]
+struct ThroughputState {
+    session: SessionObservation,
+    peak_bytes_per_second: f64,
+    bytes_per_second: f64,
+    last_healthy: NaiveDateTime,
+    last_seen: NaiveDateTime,
+}
+
+#[derive(Debug, Default)]
+pub struct HealthMonitor {
+    requests: VecDeque<RequestOutcome>,
+    sessions: HashMap<String, ThroughputState>,
+}
+
+impl HealthMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn observe_request(&mut self, outcome: RequestOutcome) {
+        if self.requests.len() >= MAX_TRACKED_REQUESTS {
+            self.requests.pop_front();
+        }
+        self.requests.push_back(outcome);
+    }
+
+    /// Record a session's throughput for this snapshot. Sessions missing from a snapshot are not
+    /// observed at all, which reads as zero throughput when they are evaluated.
+    pub fn observe_session(&mut self, key: &str, observation: SessionObservation, bytes_per_second: f64, now: NaiveDateTime) {
+        let state = self.sessions.entry(key.to_string()).or_insert_with(|| ThroughputState {
+            session: observation.clone(),
+            peak_bytes_per_second: 0.0,
+            bytes_per_second: 0.0,
+            last_healthy: now,
+            last_seen: now,
+        });
+        state.peak_bytes_per_second = state.peak_bytes_per_second.max(bytes_per_second);
+        state.bytes_per_second = bytes_per_second;
+        state.last_seen = now;
+        if bytes_per_second >= state.peak_bytes_per_second * STALL_FRACTION {
+            state.last_healthy = now;
+        }
+        // Keep the last known URL when this snapshot's row has none.
+        let last_url = observation.last_url.clone().or_else(|| state.session.last_url.take());
+        state.session = SessionObservation { last_url, ..observation };
+    }
+
+    /// Drop requests older than the lookback and sessions idle longer than `session_gap_seconds`
+    /// (those have ended, and a later download starts a fresh peak).
+    pub fn prune(&mut self, now: NaiveDateTime, session_gap_seconds: i64) {
+        let cutoff = now - chrono::Duration::seconds(HEALTH_LOOKBACK_SECONDS);
+        while self.requests.front().is_some_and(|r| r.timestamp < cutoff) {
+            self.requests.pop_front();
+        }
+        let gap = chrono::Duration::seconds(session_gap_seconds);
+        self.sessions.retain(|_, state| now - state.last_seen <= gap);
+    }
+
+    /// Sessions that reached a meaningful peak and have stayed under a sliver of it for
+    /// `STALL_AFTER_SECONDS`. A session that went silent is only reported while its client is
+    /// still sending requests for the service, or while it is known to be short of its expected
+    /// size; otherwise going quiet is what a finished download looks like.
+    pub fn stalled(&self, now: NaiveDateTime) -> Vec<StalledSession> {
+        let mut stalled: Vec<StalledSession> = self
+            .sessions
+            .values()
+            .filter(|state| state.peak_bytes_per_second >= STALL_MIN_PEAK_BYTES_PER_SECOND)
+            .filter(|state| now - state.last_healthy >= chrono::Duration::seconds(STALL_AFTER_SECONDS))
+            .filter_map(|state| {
+                let recent_requests = self
+                    .requests
+                    .iter()
+                    .filter(|r| {
+                        r.timestamp > state.last_healthy
+                            && r.client_ip == state.session.client_ip
+                            && r.service == state.session.service
+                    })
+                    .count() as u32;
+                let short_of_expected = state
+                    .session
+                    .percent_complete
+                    .is_some_and(|p| p < STALL_PROGRESS_CEILING);
+                if recent_requests == 0 && !short_of_expected {
+                    return None;
+                }
+                // Not observed this snapshot means nothing moved.
+                let bytes_per_second = if state.last_seen == now { state.bytes_per_second } else { 0.0 };
+                Some(StalledSession {
+                    session: state.session.clone(),
+                    peak_bytes_per_second: state.peak_bytes_per_second,
+                    bytes_per_second,
+                    stalled_since: state.last_healthy,
+                    recent_requests,
+                })
+            })
+            .collect();
+        stalled.sort_by(|a, b| {
+            a.stalled_since
+                .cmp(&b.stalled_since)
+                .then_with(|| a.session.client_ip.cmp(&b.session.client_ip))
+        });
+        stalled
+    }
+
+    /// Client/service/depot groups whose lookback is dominated by 4xx/5xx responses or holds a
+    /// MISS retry loop on one URL and range.
+    pub fn erroring(&self) -> Vec<ErroringGroup> {
+        type GroupKey = (String, String, Option<u32>);
+        let mut groups: HashMap<GroupKey, Vec<&RequestOutcome>> = HashMap::new();
+        for request in &self.requests {
+            groups
+                .entry((request.client_ip.clone(), request.service.clone(), request.depot_id))
+                .or_default()
+                .push(request);
+        }
+
+        let mut erroring: Vec<ErroringGroup> = groups
+            .into_iter()
+            .filter_map(|((client_ip, service, depot_id), requests)| {
+                let breakdown = error_breakdown(&requests);
+                if !breakdown.is_erroring() {
+                    return None;
+                }
+                let first_error_at = requests
+                    .iter()
+                    .filter(|r| r.status_code >= 400 || r.is_miss)
+                    .map(|r| r.timestamp)
+                    .min()?;
+                let last_url = requests.iter().max_by_key(|r| r.timestamp).map(|r| r.url.clone())?;
+                let mut datasources: Vec<Arc<str>> = requests.iter().map(|r| Arc::clone(&r.datasource)).collect();
+                datasources.sort();
+                datasources.dedup();
+                Some(ErroringGroup {
+                    client_ip,
+                    service,
+                    depot_id,
+                    last_url,
+                    first_error_at,
+                    breakdown,
+                    datasources,
+                })
+            })
+            .collect();
+        erroring.sort_by(|a, b| {
+            a.client_ip
+                .cmp(&b.client_ip)
+                .then_with(|| a.service.cmp(&b.service))
+                .then_with(|| a.depot_id.cmp(&b.depot_id))
+        });
+        erroring
+    }
+}
+
+fn error_breakdown(requests: &[&RequestOutcome]) -> ErrorBreakdown {
+    let mut breakdown = ErrorBreakdown {
+        requests: requests.len() as u32,
+        ..ErrorBreakdown::default()
+    };
+    let mut misses: HashMap<(&str, &str), u32> = HashMap::new();
+    for request in requests {
+        match request.status_code {
+            400..=499 => breakdown.client_errors += 1,
+            500..=599 => breakdown.server_errors += 1,
+            _ => {}
+        }
+        if request.status_code >= 400 {
+            *breakdown.by_status.entry(request.status_code.to_string()).or_insert(0) += 1;
+        }
+        if request.is_miss {
+            *misses.entry((request.url.as_str(), request.http_range.as_str())).or_insert(0) += 1;
+        }
+    }
+    if let Some(((url, _), count)) = misses
+        .into_iter()
+        .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(&a.0)))
+    {
+        breakdown.repeated_misses = count;
+        breakdown.repeated_miss_url = (count >= REPEATED_MISS_COUNT).then(|| url.to_string());
+    }
+    breakdown
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn at(seconds: i64) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 1, 1).unwrap().and_hms_opt(12, 0, 0).unwrap()
+            + chrono::Duration::seconds(seconds)
+    }
+
+    fn request(seconds: i64, url: &str, status_code: i32, is_miss: bool) -> RequestOutcome {
+        RequestOutcome {
+            timestamp: at(seconds),
+            client_ip: "10.0.0.5".to_string(),
+            service: "steam".to_string(),
+            depot_id: Some(1001),
+            url: url.to_string(),
+            http_range: String::new(),
+            status_code,
+            is_miss,
+            datasource: Arc::from("/logs"),
+        }
+    }
+
+    fn observation(percent_complete: Option<f64>) -> SessionObservation {
+        SessionObservation {
+            client_ip: "10.0.0.5".to_string(),
+            service: "steam".to_string(),
+            game_name: Some("Counter-Strike 2".to_string()),
+            game_app_id: Some(730),
+            depot_id: 1001,
+            last_url: Some("/depot/1001/chunk/a".to_string()),
+            percent_complete,
+            datasources: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn collapsed_throughput_is_stalled_only_while_the_client_keeps_asking() {
+        let mut monitor = HealthMonitor::new();
+        monitor.observe_session("app:730", observation(None), 50.0 * 1024.0 * 1024.0, at(0));
+        monitor.observe_session("app:730", observation(None), 1024.0, at(10));
+        assert!(monitor.stalled(at(20)).is_empty(), "a dip shorter than the stall threshold is normal");
+
+        // Silent after the collapse with no known size left to fetch: indistinguishable from done.
+        assert!(monitor.stalled(at(40)).is_empty());
+
+        monitor.observe_request(request(35, "/depot/1001/chunk/b", 200, true));
+        let stalled = monitor.stalled(at(40));
+        assert_eq!(stalled.len(), 1);
+        assert_eq!(stalled[0].stalled_since, at(0));
+        assert_eq!(stalled[0].bytes_per_second, 0.0, "not observed this snapshot");
+        assert_eq!(stalled[0].session.last_url.as_deref(), Some("/depot/1001/chunk/a"));
+
+        // A known shortfall reports even a silent session, until the session gap ends it.
+        let mut silent = HealthMonitor::new();
+        silent.observe_session("app:730", observation(Some(40.0)), 50.0 * 1024.0 * 1024.0, at(0));
+        assert_eq!(silent.stalled(at(40)).len(), 1);
+        silent.prune(at(400), 300);
+        assert!(silent.stalled(at(400)).is_empty());
+    }
+
+    #[test]
+    fn error_share_and_miss_loops_mark_a_group_erroring() {
+        let mut monitor = HealthMonitor::new();
+        for i in 0..6 {
+            monitor.observe_request(request(i, &format!("/depot/1001/chunk/{i}"), 503, false));
+        }
+        for i in 6..10 {
+            monitor.observe_request(request(i, &format!("/depot/1001/chunk/{i}"), 200, false));
+        }
+        let erroring = monitor.erroring();
+        assert_eq!(erroring.len(), 1);
+        assert_eq!(erroring[0].breakdown.server_errors, 6);
+        assert_eq!(erroring[0].breakdown.by_status.get("503"), Some(&6));
+        assert_eq!(erroring[0].last_url, "/depot/1001/chunk/9");
+
+        let mut looping = HealthMonitor::new();
+        for i in 0..3 {
+            looping.observe_request(request(i, "/depot/1001/chunk/x", 200, true));
+        }
+        let erroring = looping.erroring();
+        assert_eq!(erroring[0].breakdown.repeated_misses, 3);
+        assert_eq!(erroring[0].breakdown.repeated_miss_url.as_deref(), Some("/depot/1001/chunk/x"));
+
+        // Range requests against one file are distinct fetches, not retries.
+        let mut ranged = HealthMonitor::new();
+        for i in 0..3 {
+            let mut r = request(i, "/filestreamingservice/files/abc", 206, true);
+            r.http_range = format!("bytes={}-{}", i * 100, i * 100 + 99);
+            ranged.observe_request(r);
+        }
+        assert!(ranged.erroring().is_empty());
+    }
+}
@@ -0,0 +1,613 @@
+//! Extra consumers for `speed_tracker`'s snapshot stream.
+//!
+//! Stdout stays the parent process's channel and is untouched by anything here. When asked, the
+//! tracker also publishes each snapshot to this server, which fans it out to any number of local
+//! subscribers over three transports:
+//!
+//! - a unix socket that writes one snapshot JSON object per line;
+//! - `GET /snapshots` as server-sent events (`data: <json>` per snapshot);
+//! - `GET /snapshots` with a WebSocket upgrade, one text message per snapshot.
+//!
+//! Every subscriber has a `SubscriptionFilter`. HTTP subscribers set it with the query string
+//! (`?service=steam&client=10.0.0.5&datasource=main`, each key repeatable or comma-separated);
+//! socket and WebSocket subscribers may also send a filter object as a line or text message at
+//! any time to replace it. A subscriber that falls behind skips to the newest snapshot instead of
+//! slowing the tracker or the other subscribers.
+
+use anyhow::{Context, Result};
+use base64::Engine as _;
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream, UnixListener};
+use tokio::sync::{broadcast, mpsc};
+
+use crate::local_http::{self, RequestHead};
+
+/// Snapshots buffered per subscriber before it is considered lagging. At the tracker's 500ms
+/// broadcast this is several seconds of slack for a slow reader.
+const SUBSCRIBER_BUFFER: usize = 16;
+
+/// Largest WebSocket frame accepted from a client; filters are a few hundred bytes.
+const MAX_CLIENT_MESSAGE_BYTES: usize = 64 * 1024;
+
+/// RFC 6455's fixed key suffix for the handshake accept hash.
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Which parts of a snapshot a subscriber wants. An empty list does not filter; values within a
+/// list are alternatives. Matching is case-insensitive.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase", default, deny_unknown_fields)]
+pub struct SubscriptionFilter {
+    pub services: Vec<String>,
+    pub clients: Vec<String>,
+    pub datasources: Vec<String>,
+}
+
+impl SubscriptionFilter {
+    /// Build a filter from `service`, `client` and `datasource` query parameters.
+    pub fn from_query(query: &[(String, String)]) -> Result<Self> {
+        let mut filter = Self::default();
+        for (key, value) in query {
+            let list = match key.as_str() {
+                "service" => &mut filter.services,
+                "client" => &mut filter.clients,
+                "datasource" => &mut filter.datasources,
+                other => anyhow::bail!("unknown filter parameter '{other}'"),
+            };
+            list.extend(
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|v| !v.is_empty())
+                    .map(str::to_string),
+            );
+        }
+        Ok(filter)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.services.is_empty() && self.clients.is_empty() && self.datasources.is_empty()
+    }
+
+    pub fn matches_service(&self, service: &str) -> bool {
+        matches_any(&self.services, service)
+    }
+
+    pub fn matches_client(&self, client: &str) -> bool {
+        matches_any(&self.clients, client)
+    }
+
+    /// A row fed by several datasources matches when any of them does.
+    pub fn matches_datasources<'a>(&self, mut datasources: impl Iterator<Item = &'a str>) -> bool {
+        self.datasources.is_empty() || datasources.any(|d| matches_any(&self.datasources, d))
+    }
+}
+
+fn matches_any(wanted: &[String], value: &str) -> bool {
+    wanted.is_empty() || wanted.iter().any(|w| w.eq_ignore_ascii_case(value))
+}
+
+/// A snapshot the server can narrow per subscriber. The tracker's snapshot type implements this
+/// so the filtering (and recomputing totals for the rows that remain) stays next to the type.
+pub trait FilterableSnapshot: Send + Sync {
+    /// Serialize the snapshot, keeping only what `filter` selects. An empty filter must produce
+    /// exactly what stdout carries.
+    fn filtered_json(&self, filter: &SubscriptionFilter) -> String;
+}
+
+type Published = Arc<dyn FilterableSnapshot>;
+
+/// Fan-out point for published snapshots. Cloning shares the same subscribers.
+#[derive(Clone)]
+pub struct SnapshotServer {
+    sender: broadcast::Sender<Published>,
+}
+
+impl Default for SnapshotServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SnapshotServer {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(SUBSCRIBER_BUFFER);
+        Self { sender }
+    }
+
+    /// Hand a snapshot to every current subscriber. Never blocks; with nobody listening the
+    /// snapshot is simply dropped.
+    pub fn publish(&self, snapshot: Published) {
+        let _ = self.sender.send(snapshot);
+    }
+
+    /// Listen on a unix socket at `path`, replacing a stale socket file left by an earlier run.
+    pub async fn serve_unix(&self, path: &Path) -> Result<()> {
+        if let Ok(metadata) = std::fs::symlink_metadata(path) {
+            use std::os::unix::fs::FileTypeExt;
+            if !metadata.file_type().is_socket() {
+                anyhow::bail!("{} exists and is not a socket", path.display());
+            }
+            std::fs::remove_file(path)
+                .with_context(|| format!("failed to remove stale socket {}", path.display()))?;
+        }
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("failed to bind snapshot socket {}", path.display()))?;
+        let server = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let receiver = server.sender.subscribe();
+                        tokio::spawn(async move {
+                            let (read, write) = stream.into_split();
+                            if let Err(e) = stream_lines(read, write, receiver).await {
+                                if !is_disconnect(&e) {
+                                    eprintln!("Snapshot socket subscriber dropped: {e:#}");
+                                }
+                            }
+                        });
+                    }
+                    Err(e) => eprintln!("Snapshot socket accept failed: {e}"),
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Serve SSE and WebSocket subscribers on `addr`. Returns the bound address.
+    pub async fn serve_http(&self, addr: SocketAddr) -> Result<SocketAddr> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("failed to bind snapshot listener on {addr}"))?;
+        let local = listener.local_addr()?;
+        let server = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let server = server.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = server.handle_http(stream).await {
+                                if !is_disconnect(&e) {
+                                    eprintln!("Snapshot subscriber dropped: {e:#}");
+                                }
+                            }
+                        });
+                    }
+                    Err(e) => eprintln!("Snapshot listener accept failed: {e}"),
+                }
+            }
+        });
+        Ok(local)
+    }
+
+    async fn handle_http(&self, mut stream: TcpStream) -> Result<()> {
+        let head = local_http::read_request_head(&mut stream).await?;
+        if head.path != "/snapshots" {
+            return local_http::write_response(&mut stream, "404 Not Found", "text/plain; charset=utf-8", "not found\n").await;
+        }
+        if head.method != "GET" {
+            return local_http::write_response(&mut stream, "405 Method Not Allowed", "text/plain; charset=utf-8", "method not allowed\n").await;
+        }
+        let filter = match SubscriptionFilter::from_query(&head.query) {
+            Ok(filter) => filter,
+            Err(e) => {
+                return local_http::write_response(&mut stream, "400 Bad Request", "text/plain; charset=utf-8", &format!("{e}\n")).await;
+            }
+        };
+        let receiver = self.sender.subscribe();
+        if head.header_has_token("Connection", "upgrade") && head.header_has_token("Upgrade", "websocket") {
+            let Some(accept) = websocket_accept(&head) else {
+                return local_http::write_response(&mut stream, "400 Bad Request", "text/plain; charset=utf-8", "missing Sec-WebSocket-Key\n").await;
+            };
+            let handshake = format!(
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {accept}\r\n\r\n"
+            );
+            stream.write_all(handshake.as_bytes()).await?;
+            let (read, write) = stream.into_split();
+            stream_websocket(read, write, filter, receiver).await
+        } else {
+            stream
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n",
+                )
+                .await?;
+            stream_sse(stream, filter, receiver).await
+        }
+    }
+}
+
+/// Subscribers leave by hanging up, which surfaces as a failed write; that is not worth a log line.
+fn is_disconnect(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<std::io::Error>().is_some_and(|e| {
+        matches!(
+            e.kind(),
+            std::io::ErrorKind::BrokenPipe
+                | std::io::ErrorKind::ConnectionReset
+                | std::io::ErrorKind::UnexpectedEof
+        )
+    })
+}
+
+/// Receive the next snapshot, skipping over any this subscriber was too slow to take.
+async fn next_snapshot(receiver: &mut broadcast::Receiver<Published>) -> Option<Published> {
+    loop {
+        match receiver.recv().await {
+            Ok(snapshot) => return Some(snapshot),
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => return None,
+        }
+    }
+}
+
+fn parse_filter_message(message: &str) -> Result<SubscriptionFilter> {
+    serde_json::from_str(message.trim()).context("filter must be a JSON object with services, clients and datasources lists")
+}
+
+fn error_json(message: &str) -> String {
+    serde_json::json!({ "error": message }).to_string()
+}
+
+async fn stream_lines<R, W>(read: R, mut write: W, mut receiver: broadcast::Receiver<Published>) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut lines = BufReader::new(read).lines();
+    let mut filter = SubscriptionFilter::default();
+    let mut reading = true;
+    loop {
+        tokio::select! {
+            line = lines.next_line(), if reading => match line? {
+                Some(line) if line.trim().is_empty() => {}
+                Some(line) => match parse_filter_message(&line) {
+                    Ok(updated) => filter = updated,
+                    Err(e) => write.write_all(format!("{}\n", error_json(&format!("{e:#}"))).as_bytes()).await?,
+                },
+                // The subscriber closed its write side; keep streaming until it hangs up.
+                None => reading = false,
+            },
+            snapshot = next_snapshot(&mut receiver) => {
+                let Some(snapshot) = snapshot else { return Ok(()) };
+                let mut line = snapshot.filtered_json(&filter);
+                line.push('\n');
+                write.write_all(line.as_bytes()).await?;
+            }
+        }
+    }
+}
+
+async fn stream_sse(mut stream: TcpStream, filter: SubscriptionFilter, mut receiver: broadcast::Receiver<Published>) -> Result<()> {
+    while let Some(snapshot) = next_snapshot(&mut receiver).await {
+        let event = format!("data: {}\n\n", snapshot.filtered_json(&filter));
+        stream.write_all(event.as_bytes()).await?;
+    }
+    Ok(())
+}
+
+fn websocket_accept(head: &RequestHead) -> Option<String> {
+    let key = head.header("Sec-WebSocket-Key")?;
+    let mut hasher = Sha1::new();
+    hasher.update(key.trim().as_bytes());
+    hasher.update(WEBSOCKET_GUID.as_bytes());
+    Some(base64::engine::general_purpose::STANDARD.encode(hasher.finalize()))
+}
+
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+/// Encode one unfragmented, unmasked server frame.
+fn websocket_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 10);
+    frame.push(0x80 | opcode);
+    match payload.len() {
+        len if len < 126 => frame.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Read one client frame, returning its opcode and unmasked payload. Clients only send small
+/// control frames and filter messages, so fragmented messages are not reassembled.
+async fn read_websocket_frame<R: AsyncRead + Unpin>(read: &mut R) -> Result<(u8, Vec<u8>)> {
+    let mut header = [0u8; 2];
+    read.read_exact(&mut header).await?;
+    let opcode = header[0] & 0x0F;
+    if header[0] & 0x80 == 0 {
+        anyhow::bail!("fragmented client messages are not supported");
+    }
+    let masked = header[1] & 0x80 != 0;
+    let len = match header[1] & 0x7F {
+        126 => u64::from(read.read_u16().await?),
+        127 => read.read_u64().await?,
+        len => u64::from(len),
+    };
+    if len > MAX_CLIENT_MESSAGE_BYTES as u64 {
+        anyhow::bail!("client frame of {len} bytes exceeds the limit");
+    }
+    if !masked {
+        anyhow::bail!("client frames must be masked");
+    }
+    let mut mask = [0u8; 4];
+    read.read_exact(&mut mask).await?;
+    let mut payload = vec![0u8; len as usize];
+    read.read_exact(&mut payload).await?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok((opcode, payload))
+}
+
+/// Client frames waiting for `stream_websocket`; the reader pauses once this many are queued.
+const CLIENT_FRAME_QUEUE: usize = 4;
+
+/// Whole client frames (opcode, payload) in arrival order, ending after an error or close.
+type ClientFrames = mpsc::Receiver<Result<(u8, Vec<u8>)>>;
+
+/// Read client frames on their own task. A frame read dropped half-way by `select!` would leave
+/// the stream mid-frame, so the reads run to completion here and only whole frames are handed
+/// over. The task ends after the first error or close frame, or once the receiver is dropped.
+fn spawn_websocket_reader<R>(mut read: R) -> (tokio::task::JoinHandle<()>, ClientFrames)
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    let (sender, frames) = mpsc::channel(CLIENT_FRAME_QUEUE);
+    let reader = tokio::spawn(async move {
+        loop {
+            let frame = read_websocket_frame(&mut read).await;
+            let last = !matches!(frame, Ok((opcode, _)) if opcode != OPCODE_CLOSE);
+            if sender.send(frame).await.is_err() || last {
+                return;
+            }
+        }
+    });
+    (reader, frames)
+}
+
+async fn stream_websocket<R, W>(
+    read: R,
+    mut write: W,
+    mut filter: SubscriptionFilter,
+    mut receiver: broadcast::Receiver<Published>,
+) -> Result<()>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+    W: AsyncWrite + Unpin,
+{
+    let (reader, mut frames) = spawn_websocket_reader(read);
+    let result = async {
+        loop {
+            tokio::select! {
+                frame = frames.recv() => {
+                    let Some(frame) = frame else { return Ok(()) };
+                    let (opcode, payload) = frame?;
+                    match opcode {
+                        OPCODE_TEXT => match parse_filter_message(&String::from_utf8_lossy(&payload)) {
+                            Ok(updated) => filter = updated,
+                            Err(e) => {
+                                let message = error_json(&format!("{e:#}"));
+                                write.write_all(&websocket_frame(OPCODE_TEXT, message.as_bytes())).await?;
+                            }
+                        },
+                        OPCODE_PING => write.write_all(&websocket_frame(OPCODE_PONG, &payload)).await?,
+                        OPCODE_CLOSE => {
+                            write.write_all(&websocket_frame(OPCODE_CLOSE, &payload)).await?;
+                            return Ok(());
+                        }
+                        _ => {}
+                    }
+                }
+                snapshot = next_snapshot(&mut receiver) => {
+                    let Some(snapshot) = snapshot else {
+                        write.write_all(&websocket_frame(OPCODE_CLOSE, &[])).await?;
+                        return Ok(());
+                    };
+                    let message = snapshot.filtered_json(&filter);
+                    write.write_all(&websocket_frame(OPCODE_TEXT, message.as_bytes())).await?;
+                }
+            }
+        }
+    }
+    .await;
+    reader.abort();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::UnixStream;
+
+    /// Stand-in snapshot: a list of (service, client) rows filtered by the subscriber.
+    struct Rows(Vec<(&'static str, &'static str)>);
+
+    impl FilterableSnapshot for Rows {
+        fn filtered_json(&self, filter: &SubscriptionFilter) -> String {
+            let rows: Vec<String> = self
+                .0
+                .iter()
+                .filter(|(service, client)| filter.matches_service(service) && filter.matches_client(client))
+                .map(|(service, client)| format!("{service}/{client}"))
+                .collect();
+            serde_json::to_string(&rows).unwrap()
+        }
+    }
+
+    fn sample() -> Published {
+        Arc::new(Rows(vec![("steam", "10.0.0.1"), ("epic", "10.0.0.2")]))
+    }
+
+    /// Publish until the subscriber has had time to register, since a broadcast only reaches
+    /// receivers that exist when it is sent.
+    fn keep_publishing(server: &SnapshotServer) -> tokio::task::JoinHandle<()> {
+        let server = server.clone();
+        tokio::spawn(async move {
+            loop {
+                server.publish(sample());
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            }
+        })
+    }
+
+    #[test]
+    fn query_filters_accept_repeats_and_commas() {
+        let query = vec![
+            ("service".to_string(), "steam,epic".to_string()),
+            ("service".to_string(), "riot".to_string()),
+            ("datasource".to_string(), "main".to_string()),
+        ];
+        let filter = SubscriptionFilter::from_query(&query).unwrap();
+        assert_eq!(filter.services, vec!["steam", "epic", "riot"]);
+        assert!(filter.matches_service("STEAM"));
+        assert!(!filter.matches_service("wsus"));
+        assert!(filter.matches_client("anything"));
+        assert!(filter.matches_datasources(["other", "Main"].into_iter()));
+        assert!(SubscriptionFilter::from_query(&[("sevice".to_string(), "x".to_string())]).is_err());
+    }
+
+    #[tokio::test]
+    async fn websocket_accept_matches_the_rfc_example() {
+        let mut request: &[u8] = b"GET /snapshots HTTP/1.1\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n";
+        let head = local_http::read_request_head(&mut request).await.unwrap();
+        assert_eq!(websocket_accept(&head).as_deref(), Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+    }
+
+    #[tokio::test]
+    async fn socket_subscriber_can_replace_its_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("speed.sock");
+        let server = SnapshotServer::new();
+        server.serve_unix(&path).await.unwrap();
+        let publisher = keep_publishing(&server);
+
+        let stream = UnixStream::connect(&path).await.unwrap();
+        let (read, mut write) = stream.into_split();
+        let mut lines = BufReader::new(read).lines();
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), r#"["steam/10.0.0.1","epic/10.0.0.2"]"#);
+
+        write.write_all(b"{\"services\":[\"epic\"]}\n").await.unwrap();
+        let filtered = loop {
+            let line = lines.next_line().await.unwrap().unwrap();
+            if line != r#"["steam/10.0.0.1","epic/10.0.0.2"]"# {
+                break line;
+            }
+        };
+        assert_eq!(filtered, r#"["epic/10.0.0.2"]"#);
+
+        write.write_all(b"not json\n").await.unwrap();
+        let error = loop {
+            let line = lines.next_line().await.unwrap().unwrap();
+            if line.starts_with("{\"error\"") {
+                break line;
+            }
+        };
+        assert!(error.contains("filter must be a JSON object"));
+        publisher.abort();
+    }
+
+    #[tokio::test]
+    async fn sse_and_websocket_subscribers_receive_filtered_snapshots() {
+        let server = SnapshotServer::new();
+        let addr = server.serve_http("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let publisher = keep_publishing(&server);
+
+        let mut sse = TcpStream::connect(addr).await.unwrap();
+        sse.write_all(b"GET /snapshots?client=10.0.0.1 HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        let mut lines = BufReader::new(sse).lines();
+        assert_eq!(lines.next_line().await.unwrap().unwrap(), "HTTP/1.1 200 OK");
+        let event = loop {
+            let line = lines.next_line().await.unwrap().unwrap();
+            if let Some(data) = line.strip_prefix("data: ") {
+                break data.to_string();
+            }
+        };
+        assert_eq!(event, r#"["steam/10.0.0.1"]"#);
+
+        let mut ws = TcpStream::connect(addr).await.unwrap();
+        ws.write_all(
+            b"GET /snapshots?service=epic HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade\r\n\
+              Upgrade: websocket\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+        )
+        .await
+        .unwrap();
+        let head = local_http::read_request_head(&mut ws).await.unwrap();
+        assert_eq!(head.path, "101");
+        assert_eq!(head.header("Sec-WebSocket-Accept"), Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+        let mut header = [0u8; 2];
+        ws.read_exact(&mut header).await.unwrap();
+        assert_eq!(header[0], 0x80 | OPCODE_TEXT);
+        let mut payload = vec![0u8; usize::from(header[1])];
+        ws.read_exact(&mut payload).await.unwrap();
+        assert_eq!(String::from_utf8(payload).unwrap(), r#"["epic/10.0.0.2"]"#);
+        publisher.abort();
+    }
+
+    #[tokio::test]
+    async fn a_websocket_filter_sent_slowly_is_applied_whole() {
+        let server = SnapshotServer::new();
+        let addr = server.serve_http("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let publisher = keep_publishing(&server);
+
+        let mut ws = TcpStream::connect(addr).await.unwrap();
+        ws.write_all(
+            b"GET /snapshots?service=epic HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade\r\n\
+              Upgrade: websocket\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n",
+        )
+        .await
+        .unwrap();
+        local_http::read_request_head(&mut ws).await.unwrap();
+        let (mut read, mut write) = ws.into_split();
+
+        // Dribble a masked filter frame across several snapshots, so the server reads it in pieces
+        // while it keeps streaming.
+        let writer = tokio::spawn(async move {
+            let filter = br#"{"services":["steam"]}"#;
+            let mask = [1u8, 2, 3, 4];
+            let mut frame = vec![0x80 | OPCODE_TEXT, 0x80 | filter.len() as u8];
+            frame.extend_from_slice(&mask);
+            frame.extend(filter.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+            for byte in frame {
+                write.write_all(&[byte]).await.unwrap();
+                tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            }
+            write
+        });
+
+        let mut messages = Vec::new();
+        let switched = tokio::time::timeout(std::time::Duration::from_secs(10), async {
+            loop {
+                let mut header = [0u8; 2];
+                read.read_exact(&mut header).await.unwrap();
+                assert_eq!(header[0], 0x80 | OPCODE_TEXT);
+                let mut payload = vec![0u8; usize::from(header[1])];
+                read.read_exact(&mut payload).await.unwrap();
+                let message = String::from_utf8(payload).unwrap();
+                if message == r#"["steam/10.0.0.1"]"# {
+                    return;
+                }
+                messages.push(message);
+            }
+        })
+        .await;
+        assert!(switched.is_ok(), "the filter never applied");
+        assert!(messages.len() > 1);
+        assert!(messages.iter().all(|message| message == r#"["epic/10.0.0.2"]"#));
+        drop(writer.await.unwrap());
+        publisher.abort();
+    }
+}
@@ -0,0 +1,543 @@
+//! Persisted speed history for `speed_tracker`.
+//!
+//! The tracker's stdout snapshots are a live view: every broadcast replaces the last one and
+//! nothing survives the process. This module keeps a downsampled copy in Postgres so throughput
+//! can be charted after the fact. Snapshots are averaged into one row per series per second
+//! (`Downsampler`), written in batches by a background task (`HistoryRecorder`), and rolled up
+//! from 1-second to 1-minute to 1-hour rows, each resolution with its own retention.
+//!
+//! A row's rates are averages over the seconds the tracker was actually running. Every observed
+//! second writes a `total` row, so the rollup divides each series by the `total` row's covered
+//! seconds: a second where a series was idle counts as zero, a second where the tracker was down
+//! does not count at all.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use sqlx::{Connection, PgPool};
+use std::collections::HashMap;
+use tokio::sync::mpsc;
+
+/// How long completed rows wait in the writer before one batched insert. Rows still buffered
+/// when the tracker is killed are lost, so this is also the most history a restart can drop.
+const FLUSH_INTERVAL_SECONDS: u64 = 5;
+
+/// How often the writer rolls up finished buckets and applies retention.
+const MAINTENANCE_INTERVAL_SECONDS: u64 = 60;
+
+/// Completed seconds the tracker may hand the writer before new ones are dropped. The broadcast
+/// loop never waits on the database; a stalled connection costs history, not live snapshots.
+const QUEUE_DEPTH: usize = 600;
+
+/// Serializes first-time table creation against a second tracker starting on the same database.
+const SCHEMA_SETUP_LOCK_KEY: i64 = i64::from_be_bytes(*b"spdhistr");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    Second,
+    Minute,
+    Hour,
+}
+
+impl Resolution {
+    pub fn seconds(self) -> i32 {
+        match self {
+            Self::Second => 1,
+            Self::Minute => 60,
+            Self::Hour => 3600,
+        }
+    }
+
+    /// The `date_trunc` unit that maps a finer row onto this resolution's bucket.
+    fn trunc_unit(self) -> &'static str {
+        match self {
+            Self::Second => "second",
+            Self::Minute => "minute",
+            Self::Hour => "hour",
+        }
+    }
+
+    fn bucket_start(self, at: DateTime<Utc>) -> DateTime<Utc> {
+        at.duration_trunc(Duration::seconds(i64::from(self.seconds())))
+            .unwrap_or(at)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SeriesKind {
+    Total,
+    Service,
+    Client,
+    Game,
+}
+
+impl SeriesKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Total => "total",
+            Self::Service => "service",
+            Self::Client => "client",
+            Self::Game => "game",
+        }
+    }
+}
+
+/// One series' reading from a single snapshot. `key` is empty for the total series.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeriesSample {
+    pub kind: SeriesKind,
+    pub key: String,
+    pub bytes_per_second: f64,
+    pub hit_bytes_per_second: f64,
+    pub miss_bytes_per_second: f64,
+    pub active_downloads: i32,
+}
+
+/// One persisted bucket. Rates are averages over the seconds the bucket's `total` row covers, so
+/// series in the same bucket add up. `sample_seconds` is how many of those seconds this series
+/// was present for, the peak is the highest single-snapshot rate, and `active_downloads` is the
+/// most seen at once.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryRow {
+    pub bucket_start: DateTime<Utc>,
+    pub kind: SeriesKind,
+    pub key: String,
+    pub bytes_per_second: f64,
+    pub hit_bytes_per_second: f64,
+    pub miss_bytes_per_second: f64,
+    pub peak_bytes_per_second: f64,
+    pub active_downloads: i32,
+    pub sample_seconds: i32,
+}
+
+/// How long each resolution is kept. Every resolution is bounded, so the table stops growing
+/// once the longest horizon has passed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    pub second: Duration,
+    pub minute: Duration,
+    pub hour: Duration,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            second: Duration::hours(24),
+            minute: Duration::days(30),
+            hour: Duration::days(365),
+        }
+    }
+}
+
+impl RetentionPolicy {
+    fn keep(&self, resolution: Resolution) -> Duration {
+        match resolution {
+            Resolution::Second => self.second,
+            Resolution::Minute => self.minute,
+            Resolution::Hour => self.hour,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct Accumulator {
+    bytes_per_second: f64,
+    hit_bytes_per_second: f64,
+    miss_bytes_per_second: f64,
+    peak_bytes_per_second: f64,
+    active_downloads: i32,
+}
+
+/// Averages snapshots into one row per series per wall-clock second. A series missing from some
+/// of a second's snapshots is averaged as zero for those, so a download that stops half way
+/// through a second reports half its rate rather than its last rate.
+#[derive(Debug, Default)]
+pub struct Downsampler {
+    bucket: Option<DateTime<Utc>>,
+    snapshots: u32,
+    series: HashMap<(SeriesKind, String), Accumulator>,
+}
+
+impl Downsampler {
+    /// Add one snapshot taken at `at`. Returns the previous second's rows once `at` falls in a
+    /// later second, otherwise nothing.
+    pub fn record(&mut self, at: DateTime<Utc>, samples: &[SeriesSample]) -> Vec<HistoryRow> {
+        let bucket = Resolution::Second.bucket_start(at);
+        let completed = match self.bucket {
+            Some(current) if current != bucket => self.flush(),
+            _ => Vec::new(),
+        };
+        self.bucket = Some(bucket);
+        self.snapshots += 1;
+        for sample in samples {
+            let acc = self
+                .series
+                .entry((sample.kind, sample.key.clone()))
+                .or_default();
+            acc.bytes_per_second += sample.bytes_per_second;
+            acc.hit_bytes_per_second += sample.hit_bytes_per_second;
+            acc.miss_bytes_per_second += sample.miss_bytes_per_second;
+            acc.peak_bytes_per_second = acc.peak_bytes_per_second.max(sample.bytes_per_second);
+            acc.active_downloads = acc.active_downloads.max(sample.active_downloads);
+        }
+        completed
+    }
+
+    /// Close the current second and return its rows, sorted by series.
+    pub fn flush(&mut self) -> Vec<HistoryRow> {
+        let Some(bucket_start) = self.bucket.take() else {
+            return Vec::new();
+        };
+        let snapshots = f64::from(self.snapshots.max(1));
+        self.snapshots = 0;
+        let mut rows: Vec<HistoryRow> = self
+            .series
+            .drain()
+            .map(|((kind, key), acc)| HistoryRow {
+                bucket_start,
+                kind,
+                key,
+                bytes_per_second: acc.bytes_per_second / snapshots,
+                hit_bytes_per_second: acc.hit_bytes_per_second / snapshots,
+                miss_bytes_per_second: acc.miss_bytes_per_second / snapshots,
+                peak_bytes_per_second: acc.peak_bytes_per_second,
+                active_downloads: acc.active_downloads,
+                sample_seconds: 1,
+            })
+            .collect();
+        rows.sort_by(|a, b| (a.kind, &a.key).cmp(&(b.kind, &b.key)));
+        rows
+    }
+}
+
+/// The tracker's handle on history: downsamples in the broadcast loop and hands completed
+/// seconds to a background writer without ever waiting on it.
+pub struct HistoryRecorder {
+    downsampler: Downsampler,
+    sender: mpsc::Sender<Vec<HistoryRow>>,
+    dropped: u64,
+}
+
+impl HistoryRecorder {
+    /// Start the writer task on the current runtime.
+    pub fn spawn(pool: PgPool, retention: RetentionPolicy) -> Self {
+        let (sender, receiver) = mpsc::channel(QUEUE_DEPTH);
+        tokio::spawn(run_writer(pool, retention, receiver));
+        Self {
+            downsampler: Downsampler::default(),
+            sender,
+            dropped: 0,
+        }
+    }
+
+    pub fn record(&mut self, at: DateTime<Utc>, samples: &[SeriesSample]) {
+        let completed = self.downsampler.record(at, samples);
+        if completed.is_empty() {
+            return;
+        }
+        match self.sender.try_send(completed) {
+            Ok(()) => {
+                if self.dropped > 0 {
+                    eprintln!(
+                        "Speed history writer caught up; {} second(s) of history were dropped",
+                        self.dropped
+                    );
+                    self.dropped = 0;
+                }
+            }
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                if self.dropped == 0 {
+                    eprintln!("WARNING: speed history writer is behind; dropping history until it catches up");
+                }
+                self.dropped += 1;
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => {}
+        }
+    }
+}
+
+async fn run_writer(
+    pool: PgPool,
+    retention: RetentionPolicy,
+    mut receiver: mpsc::Receiver<Vec<HistoryRow>>,
+) {
+    let mut pending: Vec<HistoryRow> = Vec::new();
+    let mut schema_ready = false;
+    let mut flush = tokio::time::interval(std::time::Duration::from_secs(FLUSH_INTERVAL_SECONDS));
+    let mut maintenance =
+        tokio::time::interval(std::time::Duration::from_secs(MAINTENANCE_INTERVAL_SECONDS));
+
+    loop {
+        tokio::select! {
+            received = receiver.recv() => match received {
+                Some(rows) => pending.extend(rows),
+                None => break,
+            },
+            _ = flush.tick() => {
+                if !pending.is_empty() && ensure_schema(&pool, &mut schema_ready).await {
+                    match insert_rows(&pool, &pending).await {
+                        Ok(()) => pending.clear(),
+                        Err(e) => {
+                            eprintln!("WARNING: failed to write speed history: {e:#}");
+                            // Keep retrying through a short outage, but not forever.
+                            let excess = pending.len().saturating_sub(QUEUE_DEPTH * 16);
+                            pending.drain(..excess);
+                        }
+                    }
+                }
+            }
+            _ = maintenance.tick() => {
+                if ensure_schema(&pool, &mut schema_ready).await {
+                    if let Err(e) = maintain(&pool, &retention, Utc::now()).await {
+                        eprintln!("WARNING: speed history rollup failed: {e:#}");
+                    }
+                }
+            }
+        }
+    }
+
+    if !pending.is_empty() && ensure_schema(&pool, &mut schema_ready).await {
+        if let Err(e) = insert_rows(&pool, &pending).await {
+            eprintln!("WARNING: failed to write speed history: {e:#}");
+        }
+    }
+}
+
+async fn ensure_schema(pool: &PgPool, ready: &mut bool) -> bool {
+    if !*ready {
+        match initialize_schema(pool).await {
+            Ok(()) => *ready = true,
+            Err(e) => eprintln!("WARNING: speed history is unavailable: {e:#}"),
+        }
+    }
+    *ready
+}
+
+/// Create the history table if it does not exist yet.
+pub async fn initialize_schema(pool: &PgPool) -> Result<()> {
+    let mut connection = pool.acquire().await?;
+    let mut transaction = connection
+        .begin()
+        .await
+        .context("failed to begin speed history schema setup")?;
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(SCHEMA_SETUP_LOCK_KEY)
+        .execute(&mut *transaction)
+        .await?;
+    // One table for all three resolutions: a chart switches resolution by changing one filter,
+    // and the rollup is an INSERT ... SELECT within the table.
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS speed_history( \
+            resolution_seconds INTEGER NOT NULL CHECK(resolution_seconds IN (1, 60, 3600)), \
+            bucket_start TIMESTAMPTZ NOT NULL, \
+            series_kind TEXT NOT NULL CHECK(series_kind IN ('total','service','client','game')), \
+            series_key TEXT NOT NULL, \
+            bytes_per_second DOUBLE PRECISION NOT NULL, \
+            hit_bytes_per_second DOUBLE PRECISION NOT NULL, \
+            miss_bytes_per_second DOUBLE PRECISION NOT NULL, \
+            peak_bytes_per_second DOUBLE PRECISION NOT NULL, \
+            active_downloads INTEGER NOT NULL, \
+            sample_seconds INTEGER NOT NULL, \
+            PRIMARY KEY(resolution_seconds, series_kind, series_key, bucket_start) \
+         )",
+    )
+    .execute(&mut *transaction)
+    .await?;
+    sqlx::query(
+        "CREATE INDEX IF NOT EXISTS idx_speed_history_bucket \
+         ON speed_history(resolution_seconds, bucket_start)",
+    )
+    .execute(&mut *transaction)
+    .await?;
+    transaction.commit().await?;
+    Ok(())
+}
+
+async fn insert_rows(pool: &PgPool, rows: &[HistoryRow]) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO speed_history(resolution_seconds, bucket_start, series_kind, series_key, \
+            bytes_per_second, hit_bytes_per_second, miss_bytes_per_second, peak_bytes_per_second, \
+            active_downloads, sample_seconds) \
+         SELECT 1, * FROM UNNEST($1::timestamptz[], $2::text[], $3::text[], $4::float8[], \
+            $5::float8[], $6::float8[], $7::float8[], $8::int[], $9::int[]) \
+         ON CONFLICT (resolution_seconds, series_kind, series_key, bucket_start) DO UPDATE SET \
+            bytes_per_second = EXCLUDED.bytes_per_second, \
+            hit_bytes_per_second = EXCLUDED.hit_bytes_per_second, \
+            miss_bytes_per_second = EXCLUDED.miss_bytes_per_second, \
+            peak_bytes_per_second = EXCLUDED.peak_bytes_per_second, \
+            active_downloads = EXCLUDED.active_downloads, \
+            sample_seconds = EXCLUDED.sample_seconds",
+    )
+    .bind(rows.iter().map(|r| r.bucket_start).collect::<Vec<_>>())
+    .bind(rows.iter().map(|r| r.kind.as_str()).collect::<Vec<_>>())
+    .bind(rows.iter().map(|r| r.key.as_str()).collect::<Vec<_>>())
+    .bind(rows.iter().map(|r| r.bytes_per_second).collect::<Vec<_>>())
+    .bind(rows.iter().map(|r| r.hit_bytes_per_second).collect::<Vec<_>>())
+    .bind(rows.iter().map(|r| r.miss_bytes_per_second).collect::<Vec<_>>())
+    .bind(rows.iter().map(|r| r.peak_bytes_per_second).collect::<Vec<_>>())
+    .bind(rows.iter().map(|r| r.active_downloads).collect::<Vec<_>>())
+    .bind(rows.iter().map(|r| r.sample_seconds).collect::<Vec<_>>())
+    .execute(pool)
+    .await
+    .context("failed to insert speed history rows")?;
+    Ok(())
+}
+
+/// Roll finished buckets up one resolution at a time, then drop rows past their retention.
+pub async fn maintain(pool: &PgPool, retention: &RetentionPolicy, now: DateTime<Utc>) -> Result<()> {
+    rollup(pool, Resolution::Second, Resolution::Minute, now).await?;
+    rollup(pool, Resolution::Minute, Resolution::Hour, now).await?;
+    for resolution in [Resolution::Second, Resolution::Minute, Resolution::Hour] {
+        sqlx::query(
+            "DELETE FROM speed_history WHERE resolution_seconds = $1 AND bucket_start < $2",
+        )
+        .bind(resolution.seconds())
+        .bind(now - retention.keep(resolution))
+        .execute(pool)
+        .await
+        .with_context(|| format!("failed to prune {}-second speed history", resolution.seconds()))?;
+    }
+    Ok(())
+}
+
+/// Aggregate `source` rows into every `target` bucket that has ended and is not yet rolled up.
+/// Each source row is weighted by the seconds its own bucket covered, which is the `total` row's
+/// `sample_seconds` there, not the series' own.
+/// The newest existing target bucket is recomputed too, since rows for its last seconds may
+/// have reached the table after it was first rolled up; the upsert makes that idempotent.
+/// Buckets are cut in UTC so an hour is the same hour whatever the server's time zone.
+async fn rollup(
+    pool: &PgPool,
+    source: Resolution,
+    target: Resolution,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    let until = target.bucket_start(now);
+    let watermark: Option<DateTime<Utc>> = sqlx::query_scalar(
+        "SELECT MAX(bucket_start) FROM speed_history WHERE resolution_seconds = $1",
+    )
+    .bind(target.seconds())
+    .fetch_one(pool)
+    .await?;
+    sqlx::query(
+        "WITH src AS ( \
+            SELECT date_trunc($3, s.bucket_start AT TIME ZONE 'UTC') AT TIME ZONE 'UTC' AS bucket, \
+                s.*, t.sample_seconds AS weight \
+            FROM speed_history s \
+            JOIN speed_history t ON t.resolution_seconds = s.resolution_seconds \
+                AND t.series_kind = 'total' AND t.bucket_start = s.bucket_start \
+            WHERE s.resolution_seconds = $1 \
+            AND s.bucket_start >= COALESCE($4, '-infinity') AND s.bucket_start < $5 \
+         ), covered AS ( \
+            SELECT bucket, SUM(sample_seconds)::float8 AS seconds FROM src \
+            WHERE series_kind = 'total' GROUP BY bucket \
+         ) \
+         INSERT INTO speed_history(resolution_seconds, bucket_start, series_kind, series_key, \
+            bytes_per_second, hit_bytes_per_second, miss_bytes_per_second, peak_bytes_per_second, \
+            active_downloads, sample_seconds) \
+         SELECT $2, s.bucket, s.series_kind, s.series_key, \
+            SUM(s.bytes_per_second * s.weight) / c.seconds, \
+            SUM(s.hit_bytes_per_second * s.weight) / c.seconds, \
+            SUM(s.miss_bytes_per_second * s.weight) / c.seconds, \
+            MAX(s.peak_bytes_per_second), MAX(s.active_downloads), SUM(s.sample_seconds) \
+         FROM src s JOIN covered c ON c.bucket = s.bucket \
+         GROUP BY s.bucket, s.series_kind, s.series_key, c.seconds \
+         ON CONFLICT (resolution_seconds, series_kind, series_key, bucket_start) DO UPDATE SET \
+            bytes_per_second = EXCLUDED.bytes_per_second, \
+            hit_bytes_per_second = EXCLUDED.hit_bytes_per_second, \
+            miss_bytes_per_second = EXCLUDED.miss_bytes_per_second, \
+            peak_bytes_per_second = EXCLUDED.peak_bytes_per_second, \
+            active_downloads = EXCLUDED.active_downloads, \
+            sample_seconds = EXCLUDED.sample_seconds",
+    )
+    .bind(source.seconds())
+    .bind(target.seconds())
+    .bind(target.trunc_unit())
+    .bind(watermark)
+    .bind(until)
+    .execute(pool)
+    .await
+    .with_context(|| format!("failed to roll speed history up to {}-second rows", target.seconds()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(second: u32, millis: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, second).unwrap() + Duration::milliseconds(i64::from(millis))
+    }
+
+    fn sample(kind: SeriesKind, key: &str, bytes_per_second: f64, active: i32) -> SeriesSample {
+        SeriesSample {
+            kind,
+            key: key.to_string(),
+            bytes_per_second,
+            hit_bytes_per_second: bytes_per_second * 0.75,
+            miss_bytes_per_second: bytes_per_second * 0.25,
+            active_downloads: active,
+        }
+    }
+
+    #[test]
+    fn snapshots_within_a_second_average_into_one_row() {
+        let mut downsampler = Downsampler::default();
+        assert!(downsampler
+            .record(at(0, 0), &[sample(SeriesKind::Total, "", 100.0, 1)])
+            .is_empty());
+        assert!(downsampler
+            .record(at(0, 500), &[sample(SeriesKind::Total, "", 300.0, 2)])
+            .is_empty());
+
+        let rows = downsampler.record(at(1, 0), &[sample(SeriesKind::Total, "", 0.0, 0)]);
+        assert_eq!(rows.len(), 1);
+        let row = &rows[0];
+        assert_eq!(row.bucket_start, at(0, 0));
+        assert_eq!(row.bytes_per_second, 200.0);
+        assert_eq!(row.hit_bytes_per_second, 150.0);
+        assert_eq!(row.miss_bytes_per_second, 50.0);
+        assert_eq!(row.peak_bytes_per_second, 300.0);
+        assert_eq!(row.active_downloads, 2);
+        assert_eq!(row.sample_seconds, 1);
+    }
+
+    #[test]
+    fn series_missing_from_a_snapshot_counts_as_idle() {
+        let mut downsampler = Downsampler::default();
+        downsampler.record(
+            at(0, 0),
+            &[
+                sample(SeriesKind::Total, "", 400.0, 1),
+                sample(SeriesKind::Client, "10.0.0.1", 400.0, 1),
+            ],
+        );
+        downsampler.record(at(0, 500), &[sample(SeriesKind::Total, "", 0.0, 0)]);
+
+        let rows = downsampler.flush();
+        let client = rows
+            .iter()
+            .find(|r| r.kind == SeriesKind::Client)
+            .expect("client row");
+        assert_eq!(client.bytes_per_second, 200.0);
+        assert_eq!(client.peak_bytes_per_second, 400.0);
+        assert!(downsampler.flush().is_empty(), "flush leaves nothing behind");
+    }
+
+    #[test]
+    fn bucket_starts_truncate_to_their_resolution() {
+        let t = Utc.with_ymd_and_hms(2024, 5, 1, 12, 34, 56).unwrap() + Duration::milliseconds(789);
+        assert_eq!(
+            Resolution::Second.bucket_start(t),
+            Utc.with_ymd_and_hms(2024, 5, 1, 12, 34, 56).unwrap()
+        );
+        assert_eq!(
+            Resolution::Minute.bucket_start(t),
+            Utc.with_ymd_and_hms(2024, 5, 1, 12, 34, 0).unwrap()
+        );
+        assert_eq!(
+            Resolution::Hour.bucket_start(t),
+            Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap()
+        );
+    }
+}
@@ -0,0 +1,300 @@
+//! Prometheus text exposition for `speed_tracker`.
+//!
+//! The tracker counts every entry it tails and publishes gauges from each broadcast snapshot;
+//! `serve` answers `GET /metrics` on a local TCP listener with the current values. There is no
+//! HTTP framework here on purpose: the endpoint is one fixed route read by a scraper, and the
+//! whole exchange is a request line, a small header block and one response (see `local_http`).
+//!
+//! Every label is bounded. Services fold into `other` past `MAX_SERVICE_LABELS`, cache status is
+//! only `hit` or `miss`, games are the top `MAX_GAME_LABELS` by rate with the rest summed into
+//! `other`, and client addresses are never labels at all (only the active count is exported).
+
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::local_http;
+
+/// Distinct service label values before further services are counted as `other`. Service names
+/// come from the log's `[service]` tag, which is whatever the cache config says, so the set is
+/// small in practice but not closed.
+pub const MAX_SERVICE_LABELS: usize = 32;
+
+/// Games exported by name per scrape; the rest are summed into `game="other"`.
+pub const MAX_GAME_LABELS: usize = 20;
+
+pub const OTHER_LABEL: &str = "other";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Counter {
+    bytes: i64,
+    requests: u64,
+}
+
+/// Gauges published from one broadcast snapshot.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct WindowGauges {
+    pub total_bytes_per_second: f64,
+    pub service_bytes_per_second: Vec<(String, f64)>,
+    pub game_bytes_per_second: Vec<(String, f64)>,
+    pub active_clients: usize,
+    pub active_downloads: usize,
+    pub window_seconds: i64,
+    pub upstream_bytes_per_second: f64,
+    /// Uplink utilization; None when no uplink capacity is configured.
+    pub wan_utilization_percent: Option<f64>,
+}
+
+#[derive(Debug, Default)]
+struct MetricsState {
+    // (service label, cache status) -> running totals since the tracker started.
+    counters: BTreeMap<(String, &'static str), Counter>,
+    gauges: WindowGauges,
+}
+
+/// The tracker's side of the exporter. Counting happens on a local map so the per-entry path
+/// takes no lock; `publish` copies it into the shared state the server reads once per broadcast.
+#[derive(Debug, Default)]
+pub struct SpeedMetrics {
+    services: BTreeSet<String>,
+    counters: BTreeMap<(String, &'static str), Counter>,
+    shared: Arc<Mutex<MetricsState>>,
+}
+
+impl SpeedMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count one served request.
+    pub fn observe(&mut self, service: &str, is_cache_hit: bool, bytes: i64) {
+        let label = self.service_label(service);
+        let status = if is_cache_hit { "hit" } else { "miss" };
+        let counter = self.counters.entry((label, status)).or_default();
+        counter.bytes += bytes;
+        counter.requests += 1;
+    }
+
+    fn service_label(&mut self, service: &str) -> String {
+        if self.services.contains(service) {
+            return service.to_string();
+        }
+        if self.services.len() < MAX_SERVICE_LABELS {
+            self.services.insert(service.to_string());
+            return service.to_string();
+        }
+        OTHER_LABEL.to_string()
+    }
+
+    /// Make the current counters and `gauges` visible to scrapes. Service and game gauges are
+    /// folded to the same bounded label sets as the counters.
+    pub fn publish(&self, mut gauges: WindowGauges) {
+        gauges.service_bytes_per_second = fold_services(&self.services, gauges.service_bytes_per_second);
+        gauges.game_bytes_per_second = top_games(gauges.game_bytes_per_second);
+        let mut state = self.shared.lock().unwrap_or_else(|e| e.into_inner());
+        state.counters = self.counters.clone();
+        state.gauges = gauges;
+    }
+
+    /// Accept scrapes on `addr` in a background task. Binds before returning so a bad address
+    /// fails the tracker at startup rather than silently exporting nothing.
+    pub async fn serve(&self, addr: SocketAddr) -> Result<SocketAddr> {
+        let listener = TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("failed to bind metrics listener on {addr}"))?;
+        let local = listener.local_addr()?;
+        let shared = Arc::clone(&self.shared);
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let shared = Arc::clone(&shared);
+                        tokio::spawn(async move {
+                            if let Err(e) = handle_connection(stream, &shared).await {
+                                eprintln!("Metrics request failed: {e:#}");
+                            }
+                        });
+                    }
+                    Err(e) => eprintln!("Metrics listener accept failed: {e}"),
+                }
+            }
+        });
+        Ok(local)
+    }
+}
+
+fn fold_services(known: &BTreeSet<String>, rates: Vec<(String, f64)>) -> Vec<(String, f64)> {
+    let mut folded: BTreeMap<String, f64> = BTreeMap::new();
+    for (service, rate) in rates {
+        let label = if known.contains(&service) { service } else { OTHER_LABEL.to_string() };
+        *folded.entry(label).or_default() += rate;
+    }
+    folded.into_iter().collect()
+}
+
+fn top_games(mut rates: Vec<(String, f64)>) -> Vec<(String, f64)> {
+    let mut merged: BTreeMap<String, f64> = BTreeMap::new();
+    for (game, rate) in rates.drain(..) {
+        *merged.entry(game).or_default() += rate;
+    }
+    let mut ranked: Vec<(String, f64)> = merged.into_iter().collect();
+    ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    if ranked.len() > MAX_GAME_LABELS {
+        let rest: f64 = ranked.drain(MAX_GAME_LABELS..).map(|(_, rate)| rate).sum();
+        ranked.push((OTHER_LABEL.to_string(), rest));
+    }
+    ranked
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn render(state: &MetricsState) -> String {
+    let mut out = String::new();
+
+    write_header(&mut out, "lancache_served_bytes_total", "counter", "Bytes served since the tracker started.");
+    for ((service, status), counter) in &state.counters {
+        let _ = writeln!(
+            out,
+            "lancache_served_bytes_total{{service=\"{}\",cache_status=\"{status}\"}} {}",
+            escape_label(service),
+            counter.bytes
+        );
+    }
+    write_header(&mut out, "lancache_requests_total", "counter", "Requests served since the tracker started.");
+    for ((service, status), counter) in &state.counters {
+        let _ = writeln!(
+            out,
+            "lancache_requests_total{{service=\"{}\",cache_status=\"{status}\"}} {}",
+            escape_label(service),
+            counter.requests
+        );
+    }
+
+    let gauges = &state.gauges;
+    write_header(&mut out, "lancache_bytes_per_second", "gauge", "Current transfer rate over the rolling window.");
+    let _ = writeln!(out, "lancache_bytes_per_second {}", gauges.total_bytes_per_second);
+    write_header(&mut out, "lancache_service_bytes_per_second", "gauge", "Current transfer rate per service.");
+    for (service, rate) in &gauges.service_bytes_per_second {
+        let _ = writeln!(out, "lancache_service_bytes_per_second{{service=\"{}\"}} {rate}", escape_label(service));
+    }
+    write_header(&mut out, "lancache_game_bytes_per_second", "gauge", "Current transfer rate per game, top games only.");
+    for (game, rate) in &gauges.game_bytes_per_second {
+        let _ = writeln!(out, "lancache_game_bytes_per_second{{game=\"{}\"}} {rate}", escape_label(game));
+    }
+    write_header(&mut out, "lancache_active_clients", "gauge", "Clients with traffic in the rolling window.");
+    let _ = writeln!(out, "lancache_active_clients {}", gauges.active_clients);
+    write_header(&mut out, "lancache_active_downloads", "gauge", "Game downloads with traffic in the rolling window.");
+    let _ = writeln!(out, "lancache_active_downloads {}", gauges.active_downloads);
+    write_header(&mut out, "lancache_window_seconds", "gauge", "Length of the rolling window the rates are measured over.");
+    let _ = writeln!(out, "lancache_window_seconds {}", gauges.window_seconds);
+    write_header(&mut out, "lancache_upstream_bytes_per_second", "gauge", "Current rate pulled over the uplink for cache misses.");
+    let _ = writeln!(out, "lancache_upstream_bytes_per_second {}", gauges.upstream_bytes_per_second);
+    if let Some(utilization) = gauges.wan_utilization_percent {
+        write_header(&mut out, "lancache_wan_utilization_percent", "gauge", "Uplink utilization against the configured capacity.");
+        let _ = writeln!(out, "lancache_wan_utilization_percent {utilization}");
+    }
+    out
+}
+
+async fn handle_connection(mut stream: TcpStream, shared: &Mutex<MetricsState>) -> Result<()> {
+    let head = local_http::read_request_head(&mut stream).await?;
+    let (status, content_type, body) = match (head.method.as_str(), head.path.as_str()) {
+        ("GET", "/metrics") => {
+            let body = render(&shared.lock().unwrap_or_else(|e| e.into_inner()));
+            ("200 OK", "text/plain; version=0.0.4; charset=utf-8", body)
+        }
+        (_, "/metrics") => ("405 Method Not Allowed", "text/plain; charset=utf-8", "method not allowed\n".to_string()),
+        _ => ("404 Not Found", "text/plain; charset=utf-8", "not found\n".to_string()),
+    };
+    local_http::write_response(&mut stream, status, content_type, &body).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn services_past_the_label_limit_count_as_other() {
+        let mut metrics = SpeedMetrics::new();
+        for i in 0..MAX_SERVICE_LABELS + 5 {
+            metrics.observe(&format!("service{i}"), true, 10);
+        }
+        metrics.observe("service0", false, 7);
+        metrics.publish(WindowGauges::default());
+
+        let labels: BTreeSet<&str> = metrics.counters.keys().map(|(s, _)| s.as_str()).collect();
+        assert_eq!(labels.len(), MAX_SERVICE_LABELS + 1);
+        assert_eq!(metrics.counters[&(OTHER_LABEL.to_string(), "hit")].requests, 5);
+        assert_eq!(metrics.counters[&("service0".to_string(), "miss")].bytes, 7);
+    }
+
+    #[test]
+    fn games_past_the_label_limit_are_summed_into_other() {
+        let rates: Vec<(String, f64)> = (0..MAX_GAME_LABELS + 3)
+            .map(|i| (format!("Game {i:02}"), (i + 1) as f64))
+            .collect();
+        let top = top_games(rates);
+        assert_eq!(top.len(), MAX_GAME_LABELS + 1);
+        assert_eq!(top[0], (format!("Game {:02}", MAX_GAME_LABELS + 2), (MAX_GAME_LABELS + 3) as f64));
+        assert_eq!(top.last().unwrap(), &(OTHER_LABEL.to_string(), 6.0), "games 0-2 at 1+2+3 B/s");
+    }
+
+    #[test]
+    fn labels_are_escaped() {
+        assert_eq!(escape_label("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+
+    #[tokio::test]
+    async fn scrape_over_plain_http_returns_exposition_text() {
+        let mut metrics = SpeedMetrics::new();
+        metrics.observe("steam", true, 1500);
+        metrics.observe("steam", false, 500);
+        metrics.publish(WindowGauges {
+            total_bytes_per_second: 1000.0,
+            service_bytes_per_second: vec![("steam".to_string(), 1000.0)],
+            game_bytes_per_second: vec![("Counter-Strike 2".to_string(), 1000.0)],
+            active_clients: 1,
+            active_downloads: 1,
+            window_seconds: 2,
+            upstream_bytes_per_second: 500.0,
+            wan_utilization_percent: Some(40.0),
+        });
+        let addr = metrics.serve("127.0.0.1:0".parse().unwrap()).await.unwrap();
+
+        let get = |request: &'static str| async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        let response = get("GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(response.contains("text/plain; version=0.0.4"));
+        assert!(response.contains("lancache_served_bytes_total{service=\"steam\",cache_status=\"hit\"} 1500\n"));
+        assert!(response.contains("lancache_requests_total{service=\"steam\",cache_status=\"miss\"} 1\n"));
+        assert!(response.contains("lancache_game_bytes_per_second{game=\"Counter-Strike 2\"} 1000\n"));
+        assert!(response.contains("lancache_active_clients 1\n"));
+        assert!(response.contains("lancache_wan_utilization_percent 40\n"));
+
+        let response = get("GET /other HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 404"), "{response}");
+        let response = get("POST /metrics HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 405"), "{response}");
+    }
+}
@@ -1,24 +1,35 @@
-use anyhow::Result;
-use chrono::{NaiveDateTime, Utc};
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDateTime, Timelike, Utc};
 use chrono_tz::Tz;
 use serde::Serialize;
 use sqlx::PgPool;
 use sqlx::Row;
-use std::collections::{HashMap, VecDeque};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
 use std::env;
 use std::fs::File;
 use std::io::{Read, Seek, SeekFrom};
-use std::path::PathBuf;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use lancache_processor::cache_utils;
+use lancache_processor::cli_flags::{take_flag_value, take_switch};
 use lancache_processor::db;
+use lancache_processor::download_sessions;
 use lancache_processor::log_layout;
+use lancache_processor::log_reader::LogFileReader;
+use lancache_processor::models::LogEntry;
 use lancache_processor::parser;
 use lancache_processor::parser_http_detailed;
 use lancache_processor::progress_events;
 use lancache_processor::riot_hosts;
 use lancache_processor::service_utils;
+use lancache_processor::session_health::{ErrorBreakdown, HealthMonitor, RequestOutcome, SessionObservation};
+use lancache_processor::speed_history::{self, HistoryRecorder, SeriesKind, SeriesSample};
+use lancache_processor::snapshot_server::{FilterableSnapshot, SnapshotServer, SubscriptionFilter};
+use lancache_processor::speed_metrics::{SpeedMetrics, WindowGauges};
 use lancache_processor::tact_products;
 use log_layout::{discover_log_sources, SourceKind};
 use parser::LogParser;
@@ -43,6 +54,25 @@ const POLL_INTERVAL_MS: u64 = 100;
 /// iteration, and the in-window `entries` deque stays bounded. The checkpoint resumes exactly where
 /// the poll stopped, so nothing is skipped between polls.
 const MAX_POLL_BYTES: u64 = 8 * 1024 * 1024;
+/// How long a game's expected-size history is reused before it is re-read from Downloads. The
+/// history only changes when a session finishes, so minutes of staleness cost nothing.
+const PROGRESS_HISTORY_REFRESH_SECS: u64 = 300;
+/// Uplink utilization at which the WAN is reported saturated. Below full capacity on purpose:
+/// TCP backs off before the link reads 100%, and the 2s window averages out short peaks.
+const WAN_SATURATION_PERCENT: f64 = 90.0;
+/// How many clients and games a saturated WAN report names.
+const WAN_TOP_CONTRIBUTORS: usize = 5;
+/// Client request-behavior outlier thresholds. A client is flagged when it clears the absolute
+/// floor and, once there are enough clients for a median to mean anything, also runs at
+/// `OUTLIER_MEDIAN_FACTOR` times the median client. A Steam client on a fast LAN sustains roughly
+/// a dozen to twenty chunk requests in flight; the floors sit well above that.
+const OUTLIER_MIN_CLIENTS: usize = 3;
+const OUTLIER_MEDIAN_FACTOR: f64 = 4.0;
+const OUTLIER_REQUESTS_PER_SECOND: f64 = 50.0;
+const OUTLIER_CONCURRENT_REQUESTS: f64 = 32.0;
+/// Many requests this small are range-splitting a file far finer than the CDN chunk size.
+const OUTLIER_SMALL_REQUEST_BYTES: f64 = 64.0 * 1024.0;
+const OUTLIER_SMALL_REQUEST_RATE: f64 = 20.0;
 
 fn replace_pattern_lookup_cache(cache: &mut HashMap<u128, Option<String>>) {
     *cache = HashMap::new();
@@ -57,13 +87,23 @@ struct SpeedLogEntry {
     bytes_sent: i64,
     is_cache_hit: bool,
     request_url: String,
+    /// The datasource directory the line was tailed from (see `TrackedSource::datasource`).
+    datasource: Arc<str>,
+    /// Seconds nginx spent serving the request (`$request_time`); None for cachelog lines.
+    request_time: Option<f64>,
+    /// Bytes this request pulled over the uplink: `$upstream_response_length` when the log has it,
+    /// else the served bytes of a MISS/EXPIRED/UPDATING response, else 0 (see `upstream_bytes`).
+    upstream_bytes: i64,
+    /// Not yet counted toward a progress session. Cleared on every snapshot, so each entry's
+    /// bytes are added to its session exactly once however many snapshots it stays in the window.
+    fresh: bool,
     /// Riot CDN host (access.log `$host`, 4th quoted field), lowercased; only set
     /// for the riot service (None otherwise). Riot bundle URLs have no slug, so the
     /// host subdomain (lol/valorant/bacon) is the only live per-game discriminator.
     cdn_host: Option<String>,
 }
 
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct GameSpeedInfo {
     depot_id: u32,
@@ -77,9 +117,86 @@ struct GameSpeedInfo {
     cache_hit_bytes: i64,
     cache_miss_bytes: i64,
     cache_hit_percent: f64,
+    upstream_bytes_per_second: f64,
+    /// Bytes this client has pulled for this game since the tracker saw the session start.
+    session_bytes: i64,
+    /// Estimated size of the whole download, from Downloads history; None without history.
+    expected_bytes: Option<i64>,
+    percent_complete: Option<f64>,
+    eta_seconds: Option<f64>,
+    progress_confidence: ProgressConfidence,
+    // Bytes from entries not yet counted toward the session, and the depots this row covers.
+    // Inputs to the progress fields above, not part of the wire shape.
+    #[serde(skip)]
+    fresh_bytes: i64,
+    #[serde(skip)]
+    depot_ids: Vec<u32>,
+    // URL of the newest request in the row, for stalled-session reports.
+    #[serde(skip)]
+    last_url: Option<String>,
+    /// Datasources that fed this row, sorted.
+    datasources: Vec<Arc<str>>,
+    // The row's bytes split by datasource, for the per-datasource aggregates.
+    #[serde(skip)]
+    datasource_bytes: Vec<DatasourceBytes>,
 }
 
-#[derive(Serialize)]
+#[derive(Clone, Debug, PartialEq)]
+struct DatasourceBytes {
+    datasource: Arc<str>,
+    total_bytes: i64,
+    cache_hit_bytes: i64,
+    upstream_bytes: i64,
+}
+
+/// Throughput of one service or one datasource across every client in the window.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AggregateSpeedInfo {
+    name: String,
+    bytes_per_second: f64,
+    total_bytes: i64,
+    cache_hit_bytes: i64,
+    cache_miss_bytes: i64,
+    cache_hit_percent: f64,
+    upstream_bytes_per_second: f64,
+    active_clients: usize,
+    active_downloads: usize,
+}
+
+/// How far `expectedBytes` can be trusted. `High`: every depot seen this session (or, for a
+/// depot-less game, the game itself) has finished downloads on record. `Low`: some depots have
+/// no history, or the session already outgrew the estimate. `None`: no history at all, so there
+/// is no percentage or ETA.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ProgressConfidence {
+    High,
+    Low,
+    #[default]
+    None,
+}
+
+/// Finished-download sizes for one game: the largest per depot, and the largest overall.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct GameHistory {
+    per_depot: HashMap<u32, i64>,
+    largest: Option<i64>,
+}
+
+/// One `(DepotId, finished bytes)` row of the game-history queries.
+type HistoryRow = (Option<i64>, Option<i64>);
+
+/// One client's running download of one game, for progress. Reset after the same idle gap that
+/// splits Downloads sessions, so a re-download later counts from zero.
+#[derive(Debug, Clone)]
+struct ProgressSession {
+    bytes: i64,
+    depots: Vec<u32>,
+    last_seen: NaiveDateTime,
+}
+
+#[derive(Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
 struct ClientSpeedInfo {
     client_ip: String,
@@ -88,6 +205,34 @@ struct ClientSpeedInfo {
     active_games: usize,
     cache_hit_bytes: i64,
     cache_miss_bytes: i64,
+    upstream_bytes_per_second: f64,
+    /// Datasources this client's rows came from, sorted.
+    datasources: Vec<Arc<str>>,
+    #[serde(flatten)]
+    requests: RequestBehavior,
+}
+
+/// How a client talks to the cache over the window: how often it asks, how much per request and
+/// how many requests it keeps in flight. `outliers` names the measures where it stands out.
+#[derive(Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RequestBehavior {
+    requests_per_second: f64,
+    average_request_bytes: f64,
+    concurrent_requests: f64,
+    concurrency_source: ConcurrencySource,
+    outliers: Vec<&'static str>,
+}
+
+/// Where `concurrentRequests` came from. `RequestTime`: Little's law over logged `$request_time`
+/// (total time in flight divided by the window). `Timestamps`: no durations logged, so the peak
+/// count of requests finishing within one log-timestamp second, a lower bound on parallelism.
+#[derive(Clone, Copy, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum ConcurrencySource {
+    RequestTime,
+    #[default]
+    Timestamps,
 }
 
 #[derive(Serialize)]
@@ -97,9 +242,86 @@ struct DownloadSpeedSnapshot {
     total_bytes_per_second: f64,
     game_speeds: Vec<GameSpeedInfo>,
     client_speeds: Vec<ClientSpeedInfo>,
+    service_speeds: Vec<AggregateSpeedInfo>,
+    datasource_speeds: Vec<AggregateSpeedInfo>,
     window_seconds: i64,
     entries_in_window: usize,
     has_active_downloads: bool,
+    /// Rate the window's MISS traffic pulled over the uplink, as opposed to LAN-served bytes.
+    upstream_bytes_per_second: f64,
+    /// Uplink utilization; None unless `--uplink-mbps` gives the capacity.
+    wan: Option<WanStatus>,
+    stalled: Vec<StalledDownload>,
+    erroring: Vec<ErroringDownload>,
+}
+
+/// Uplink utilization against the configured capacity. While saturated, names the clients and
+/// games pulling the most upstream bytes, each with its share of the upstream rate.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WanStatus {
+    capacity_bytes_per_second: f64,
+    utilization_percent: f64,
+    saturated: bool,
+    saturated_since: Option<String>,
+    top_clients: Vec<WanClient>,
+    top_games: Vec<WanGame>,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WanClient {
+    client_ip: String,
+    upstream_bytes_per_second: f64,
+    share_percent: f64,
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct WanGame {
+    client_ip: String,
+    service: String,
+    game_name: Option<String>,
+    depot_id: u32,
+    upstream_bytes_per_second: f64,
+    share_percent: f64,
+}
+
+/// A download whose throughput collapsed to a sliver of its peak and has not recovered. See
+/// `session_health` for when a quiet session counts as stalled rather than finished.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StalledDownload {
+    client_ip: String,
+    service: String,
+    game_name: Option<String>,
+    game_app_id: Option<u32>,
+    depot_id: u32,
+    last_url: Option<String>,
+    bytes_per_second: f64,
+    peak_bytes_per_second: f64,
+    stalled_since: String,
+    stalled_seconds: i64,
+    percent_complete: Option<f64>,
+    recent_requests: u32,
+    #[serde(skip)]
+    datasources: Vec<Arc<str>>,
+}
+
+/// A client's recent requests for one game that are mostly failing or stuck re-MISSing a chunk.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ErroringDownload {
+    client_ip: String,
+    service: String,
+    game_name: Option<String>,
+    game_app_id: Option<u32>,
+    depot_id: Option<u32>,
+    last_url: String,
+    erroring_since: String,
+    error_breakdown: ErrorBreakdown,
+    #[serde(skip)]
+    datasources: Vec<Arc<str>>,
 }
 
 /// One discovered log source reduced to the single file the tracker tails: its CURRENT
@@ -111,6 +333,125 @@ struct DownloadSpeedSnapshot {
 struct TrackedSource {
     path: PathBuf,
     kind: SourceKind,
+    /// The datasource this source belongs to: the name given with `--datasource <name>=<dir>`, or
+    /// for a bare directory argument the directory exactly as given. Rows report it and
+    /// subscribers filter by it (or by its last path component).
+    datasource: Arc<str>,
+}
+
+/// A datasource directory to discover sources in, with the name its rows report.
+#[derive(Debug, Clone)]
+struct DatasourceDir {
+    name: Arc<str>,
+    dir: PathBuf,
+}
+
+impl DatasourceDir {
+    /// A directory given without a name reports itself as the name.
+    fn unnamed(dir: PathBuf) -> Self {
+        Self { name: Arc::from(dir.display().to_string()), dir }
+    }
+}
+
+/// The names a datasource filter may use for a row's datasources: the directory exactly as given
+/// on the command line, or its last path component, so `main` selects `/data/logs/main`.
+fn datasource_names(datasources: &[Arc<str>]) -> impl Iterator<Item = &str> {
+    datasources.iter().flat_map(|datasource| {
+        let full: &str = datasource;
+        let last = Path::new(full).file_name().and_then(|name| name.to_str());
+        std::iter::once(full).chain(last)
+    })
+}
+
+impl FilterableSnapshot for DownloadSpeedSnapshot {
+    /// Keep the game rows the filter selects and recompute everything else from them, so a
+    /// filtered subscriber sees a self-consistent snapshot of just that slice. Client rows are
+    /// rebuilt from the remaining game rows, which narrows a client's totals to the selected
+    /// services and datasources. A game row fed by several datasources is kept whole when any of
+    /// them matches, since the window does not split one row's bytes by source.
+    fn filtered_json(&self, filter: &SubscriptionFilter) -> String {
+        if filter.is_empty() {
+            return serde_json::to_string(self).unwrap_or_default();
+        }
+        let game_speeds: Vec<GameSpeedInfo> = self
+            .game_speeds
+            .iter()
+            .filter(|game| {
+                filter.matches_service(&game.service)
+                    && filter.matches_client(&game.client_ip)
+                    && filter.matches_datasources(datasource_names(&game.datasources))
+            })
+            .cloned()
+            .collect();
+
+        let mut clients: HashMap<&str, ClientSpeedInfo> = HashMap::new();
+        for game in &game_speeds {
+            let client = clients.entry(&game.client_ip).or_insert_with(|| ClientSpeedInfo {
+                client_ip: game.client_ip.clone(),
+                bytes_per_second: 0.0,
+                total_bytes: 0,
+                active_games: 0,
+                cache_hit_bytes: 0,
+                cache_miss_bytes: 0,
+                upstream_bytes_per_second: 0.0,
+                datasources: client_datasources(&game.client_ip, &game_speeds),
+                // Request behavior describes the client's connection as a whole, so it is kept
+                // from the unfiltered row rather than re-derived from the selected slice.
+                requests: self
+                    .client_speeds
+                    .iter()
+                    .find(|c| c.client_ip == game.client_ip)
+                    .map(|c| c.requests.clone())
+                    .unwrap_or_default(),
+            });
+            client.bytes_per_second += game.bytes_per_second;
+            client.total_bytes += game.total_bytes;
+            client.active_games += 1;
+            client.cache_hit_bytes += game.cache_hit_bytes;
+            client.cache_miss_bytes += game.cache_miss_bytes;
+            client.upstream_bytes_per_second += game.upstream_bytes_per_second;
+        }
+        let mut client_speeds: Vec<ClientSpeedInfo> = clients.into_values().collect();
+        client_speeds.sort_by(|a, b| b.bytes_per_second.partial_cmp(&a.bytes_per_second).unwrap_or(std::cmp::Ordering::Equal));
+        let (service_speeds, datasource_speeds) = aggregate_speeds(&game_speeds);
+
+        let filtered = DownloadSpeedSnapshot {
+            timestamp_utc: self.timestamp_utc.clone(),
+            // Folded from 0.0 rather than summed: an empty f64 sum is -0.0, which would serialize as such.
+            total_bytes_per_second: game_speeds.iter().fold(0.0, |total, g| total + g.bytes_per_second),
+            entries_in_window: game_speeds.iter().map(|g| g.request_count).sum(),
+            has_active_downloads: !game_speeds.is_empty(),
+            upstream_bytes_per_second: game_speeds.iter().fold(0.0, |total, g| total + g.upstream_bytes_per_second),
+            // The uplink is shared, so its status is link-wide rather than a slice of the filter.
+            wan: self.wan.clone(),
+            game_speeds,
+            client_speeds,
+            service_speeds,
+            datasource_speeds,
+            window_seconds: self.window_seconds,
+            stalled: self
+                .stalled
+                .iter()
+                .filter(|s| {
+                    filter.matches_service(&s.service)
+                        && filter.matches_client(&s.client_ip)
+                        && filter.matches_datasources(datasource_names(&s.datasources))
+                })
+                .cloned()
+                .collect(),
+            erroring: self
+                .erroring
+                .iter()
+                .filter(|e| {
+                    filter.matches_service(&e.service)
+                        && filter.matches_client(&e.client_ip)
+                        && filter.matches_datasources(datasource_names(&e.datasources))
+                })
+                .cloned()
+                .collect(),
+        };
+        serde_json::to_string(&filtered).unwrap_or_default()
+    }
 }
 
 /// Resolve every datasource directory to the concrete files worth tailing. A directory may
@@ -119,9 +460,9 @@ struct TrackedSource {
 /// surviving member is a rotated/compressed archive has no live file and is skipped, and the
 /// fallback series is never ingested. Discovery reuses `log_layout::discover_log_sources`, so the
 /// tracker carries no filename or layout grammar of its own.
-fn discover_tracked_sources(dirs: &[PathBuf]) -> Vec<TrackedSource> {
+fn discover_tracked_sources(dirs: &[DatasourceDir]) -> Vec<TrackedSource> {
     let mut tracked = Vec::new();
-    for dir in dirs {
+    for DatasourceDir { name: datasource, dir } in dirs {
         let set = match discover_log_sources(dir) {
             Ok(set) => set,
             Err(e) => {
@@ -143,32 +484,93 @@ fn discover_tracked_sources(dirs: &[PathBuf]) -> Vec<TrackedSource> {
             tracked.push(TrackedSource {
                 path: current.path.clone(),
                 kind: source.kind.clone(),
+                datasource: Arc::clone(datasource),
             });
         }
     }
     tracked
 }
 
-/// Parse one tailed line into a `SpeedLogEntry`, or None. Canonical order (identical to the
-/// record processor and the content scan): the cachelog parser runs first everywhere so an
-/// explicit `[service]` tag always wins; otherwise a per-service source parses with its stem's
-/// service hint. The manager's own probe traffic is synthetic and never live activity, and only
-/// requests that actually transferred bytes count toward live speed. Maps the canonical
-/// `LogEntry` onto the tracker's `SpeedLogEntry` (riot `cdn_host` comes straight from the parsed
-/// entry, whose parsers own that grammar).
-fn parse_speed_entry(
+/// Every file of every ingestible source, oldest rotation first, for `--replay`. Unlike live
+/// tailing, rotated and compressed members are included: an incident window usually lies in them.
+fn discover_replay_sources(dirs: &[DatasourceDir]) -> Vec<TrackedSource> {
+    let mut replay = Vec::new();
+    for DatasourceDir { name: datasource, dir } in dirs {
+        let set = match discover_log_sources(dir) {
+            Ok(set) => set,
+            Err(e) => {
+                eprintln!("Failed to discover log sources in {}: {}", dir.display(), e);
+                continue;
+            }
+        };
+        for source in set.sources {
+            if matches!(source.kind, SourceKind::Fallback) {
+                continue;
+            }
+            for file in &source.files {
+                replay.push(TrackedSource {
+                    path: file.path.clone(),
+                    kind: source.kind.clone(),
+                    datasource: Arc::clone(datasource),
+                });
+            }
+        }
+    }
+    replay
+}
+
+/// One replayed file, read a line at a time. nginx writes each file in time order, so the merge
+/// in `replay` only ever needs every cursor's next request in `[prime_start, to]`.
+struct ReplayCursor<'a> {
+    source: &'a TrackedSource,
+    reader: LogFileReader,
+    buf: Vec<u8>,
+    prime_start: NaiveDateTime,
+    to: NaiveDateTime,
+}
+
+impl<'a> ReplayCursor<'a> {
+    fn open(source: &'a TrackedSource, prime_start: NaiveDateTime, to: NaiveDateTime) -> Result<Self> {
+        let reader = LogFileReader::open(&source.path)
+            .with_context(|| format!("Failed to open {}", source.path.display()))?;
+        Ok(Self { source, reader, buf: Vec::new(), prime_start, to })
+    }
+
+    fn next_request(&mut self, cachelog: &LogParser, detailed: &HttpDetailedParser) -> Result<Option<LogEntry>> {
+        loop {
+            self.buf.clear();
+            if self.reader.read_until_newline(&mut self.buf)? == 0 {
+                return Ok(None);
+            }
+            let decoded = String::from_utf8_lossy(&self.buf);
+            let Some(request) = parse_request(cachelog, detailed, decoded.trim(), self.source) else {
+                continue;
+            };
+            if request.timestamp >= self.prime_start && request.timestamp <= self.to {
+                return Ok(Some(request));
+            }
+        }
+    }
+}
+
+/// Parse one tailed line into a request, or None. Canonical order (identical to the record
+/// processor and the content scan): the cachelog parser runs first everywhere so an explicit
+/// `[service]` tag always wins; otherwise a per-service source parses with its stem's service
+/// hint. The manager's own probe traffic is synthetic and never live activity. Zero-byte and error
+/// responses are kept here for health detection; `speed_entry` drops them from the speed window.
+fn parse_request(
     cachelog: &LogParser,
     detailed: &HttpDetailedParser,
     line: &str,
-    kind: &SourceKind,
-) -> Option<SpeedLogEntry> {
+    source: &TrackedSource,
+) -> Option<LogEntry> {
     if service_utils::is_manager_probe(line) {
         return None;
     }
 
     let entry = if let Some(entry) = cachelog.parse_line(line) {
         entry
-    } else if let SourceKind::Service(service) = kind {
+    } else if let SourceKind::Service(service) = &source.kind {
         detailed.parse_line(line, service)?
     } else {
         // Monolithic http-detailed content is hint-less; there is no service to attribute.
@@ -178,11 +580,46 @@ fn parse_speed_entry(
     if service_utils::should_skip_url(&entry.url) {
         return None;
     }
+    Some(entry)
+}
+
+fn request_outcome(entry: &LogEntry, source: &TrackedSource) -> RequestOutcome {
+    RequestOutcome {
+        timestamp: entry.timestamp,
+        client_ip: entry.client_ip.clone(),
+        service: entry.service.clone(),
+        depot_id: entry.depot_id,
+        url: entry.url.clone(),
+        http_range: entry.http_range.clone(),
+        status_code: entry.status_code,
+        is_miss: entry.cache_status.eq_ignore_ascii_case("MISS"),
+        datasource: Arc::clone(&source.datasource),
+    }
+}
+
+/// Bytes a request pulled over the uplink. http-detailed logs record `$upstream_response_length`
+/// directly; cachelog lines do not, so a response served from an upstream fetch (MISS, EXPIRED,
+/// UPDATING) is counted at its served size and everything else as LAN-only.
+fn upstream_bytes(entry: &LogEntry) -> i64 {
+    entry.upstream_bytes.unwrap_or_else(|| {
+        let status = entry.cache_status.to_ascii_uppercase();
+        if matches!(status.as_str(), "MISS" | "EXPIRED" | "UPDATING") {
+            entry.bytes_served.max(0)
+        } else {
+            0
+        }
+    })
+}
+
+/// Map the canonical `LogEntry` onto the tracker's `SpeedLogEntry` (riot `cdn_host` comes straight
+/// from the parsed entry, whose parsers own that grammar).
+fn speed_entry(entry: LogEntry, source: &TrackedSource) -> Option<SpeedLogEntry> {
     // Live speed measures bytes actually served; `-`/zero/negative rows move no data.
     if entry.bytes_served <= 0 {
         return None;
     }
 
+    let upstream_bytes = upstream_bytes(&entry);
     Some(SpeedLogEntry {
         timestamp: entry.timestamp,
         client_ip: entry.client_ip,
@@ -191,6 +628,10 @@ fn parse_speed_entry(
         bytes_sent: entry.bytes_served,
         is_cache_hit: entry.cache_status.eq_ignore_ascii_case("HIT"),
         request_url: entry.url,
+        datasource: Arc::clone(&source.datasource),
+        upstream_bytes,
+        request_time: entry.request_time_secs,
+        fresh: true,
         cdn_host: entry.cdn_host,
     })
 }
@@ -287,6 +728,20 @@ struct SpeedTracker {
     xbox_cdn_cache: HashMap<u128, Option<String>>, // md5(url) -> game_name (None = no match)
     xbox_patterns: Vec<(String, String)>,          // (UrlFragment, Title), longest-first
     last_xbox_pattern_load: Option<Instant>,
+    // Downsampled copy of every broadcast for speed_history; None when --no-history is given.
+    history: Option<HistoryRecorder>,
+    // Prometheus counters and gauges; None unless --metrics-listen is given.
+    metrics: Option<SpeedMetrics>,
+    // Fan-out to socket/SSE/WebSocket subscribers; None unless --serve-socket or --serve-listen.
+    snapshot_server: Option<SnapshotServer>,
+    // (progress key, client_ip) -> running session, and progress key -> Downloads history.
+    progress_sessions: HashMap<(String, String), ProgressSession>,
+    game_history: HashMap<String, (Instant, GameHistory)>,
+    // Recent requests and per-session peaks for stalled/erroring detection.
+    health: HealthMonitor,
+    // Uplink capacity from --uplink-mbps, and when the current saturation spell began.
+    uplink_bytes_per_second: Option<f64>,
+    wan_saturated_since: Option<NaiveDateTime>,
 }
 
 impl SpeedTracker {
@@ -308,6 +763,14 @@ impl SpeedTracker {
             xbox_cdn_cache: HashMap::new(),
             xbox_patterns: Vec::new(),
             last_xbox_pattern_load: None,
+            history: None,
+            metrics: None,
+            snapshot_server: None,
+            progress_sessions: HashMap::new(),
+            game_history: HashMap::new(),
+            health: HealthMonitor::new(),
+            uplink_bytes_per_second: None,
+            wan_saturated_since: None,
         }
     }
 
@@ -330,17 +793,27 @@ impl SpeedTracker {
             }
 
             // Clean old entries
-            self.clean_old_entries();
+            self.clean_old_entries(Utc::now().naive_utc());
 
             // Broadcast if interval passed
             if last_broadcast.elapsed() >= Duration::from_millis(BROADCAST_INTERVAL_MS) {
-                let snapshot = self.calculate_snapshot().await;
+                let snapshot = self.calculate_snapshot(Utc::now()).await;
 
                 // Output JSON to stdout (C# will read this) via the shared emission
                 // primitive in progress_events — same compact serialize + println +
                 // flush guarantee, no envelope wrapping (see emit_json_line docs).
                 progress_events::emit_json_line(&snapshot);
 
+                if let Some(history) = self.history.as_mut() {
+                    history.record(Utc::now(), &history_samples(&snapshot));
+                }
+                if let Some(metrics) = self.metrics.as_ref() {
+                    metrics.publish(window_gauges(&snapshot));
+                }
+                if let Some(server) = self.snapshot_server.as_ref() {
+                    server.publish(Arc::new(snapshot));
+                }
+
                 last_broadcast = Instant::now();
             }
 
@@ -348,6 +821,71 @@ impl SpeedTracker {
         }
     }
 
+    /// Rebuild the snapshot series for `[from, to]` from archived logs and print it as NDJSON, one
+    /// snapshot per broadcast interval of log time. The clock is the replay position, not the wall
+    /// clock, so windows, speeds, progress sessions and stall timers all behave as they would have
+    /// live. Every rotation (compressed or not) is read through its own cursor and the cursors are
+    /// merged by timestamp, so requests stream into the clock loop instead of being held in memory;
+    /// the window is primed with the `MAX_WINDOW_SECONDS` before `from` so the first snapshot is not
+    /// artificially empty.
+    async fn replay(&mut self, dirs: &[DatasourceDir], from: DateTime<Utc>, to: DateTime<Utc>) -> Result<()> {
+        let from_naive = from.naive_utc();
+        let to_naive = to.naive_utc();
+        let prime_start = from_naive - chrono::Duration::seconds(MAX_WINDOW_SECONDS);
+        let sources = discover_replay_sources(dirs);
+        eprintln!("Replaying {} log file(s) from {} to {}", sources.len(), from.to_rfc3339(), to.to_rfc3339());
+
+        let mut cursors: Vec<Option<ReplayCursor>> = Vec::with_capacity(sources.len());
+        let mut heads: Vec<Option<LogEntry>> = Vec::with_capacity(sources.len());
+        // Min-heap of each cursor's next timestamp; the index breaks ties, so lines with equal
+        // timestamps keep their file order.
+        let mut queue: BinaryHeap<Reverse<(NaiveDateTime, usize)>> = BinaryHeap::new();
+        for (index, source) in sources.iter().enumerate() {
+            // A file last written before the primed window holds nothing in range.
+            let modified = std::fs::metadata(&source.path).and_then(|m| m.modified()).ok();
+            if modified.is_some_and(|m| DateTime::<Utc>::from(m).naive_utc() < prime_start) {
+                cursors.push(None);
+                heads.push(None);
+                continue;
+            }
+            let mut cursor = ReplayCursor::open(source, prime_start, to_naive)?;
+            let head = cursor.next_request(&self.cachelog, &self.detailed)?;
+            if let Some(request) = &head {
+                queue.push(Reverse((request.timestamp, index)));
+            }
+            cursors.push(Some(cursor));
+            heads.push(head);
+        }
+
+        let step = chrono::Duration::milliseconds(BROADCAST_INTERVAL_MS as i64);
+        let mut replayed = 0usize;
+        let mut clock = from;
+        while clock <= to {
+            let clock_naive = clock.naive_utc();
+            while let Some(&Reverse((timestamp, index))) = queue.peek() {
+                if timestamp > clock_naive {
+                    break;
+                }
+                queue.pop();
+                let Some(request) = heads[index].take() else { continue };
+                if let Some(cursor) = cursors[index].as_mut() {
+                    heads[index] = cursor.next_request(&self.cachelog, &self.detailed)?;
+                    if let Some(next) = &heads[index] {
+                        queue.push(Reverse((next.timestamp, index)));
+                    }
+                }
+                self.ingest_request(request, &sources[index]);
+                replayed += 1;
+            }
+            self.clean_old_entries(clock_naive);
+            let snapshot = self.calculate_snapshot(clock).await;
+            progress_events::emit_json_line(&snapshot);
+            clock += step;
+        }
+        eprintln!("Replayed {} request(s)", replayed);
+        Ok(())
+    }
+
     fn read_new_entries(&mut self, source: &TrackedSource) -> Result<()> {
         let log_path = &source.path;
 
@@ -453,12 +991,12 @@ impl SpeedTracker {
                         .position(|&b| b == b'\n')
                         .unwrap_or(index);
                     let resume = first_newline + 1;
-                    self.parse_records(&buffer[resume..complete_len], &source.kind);
+                    self.parse_records(&buffer[resume..complete_len], source);
                     state.discarding = false;
                 } else {
                     // Commit only PAST complete, newline-terminated records. Everything after the
                     // last newline is an incomplete final record read again next poll.
-                    self.parse_records(&buffer[..complete_len], &source.kind);
+                    self.parse_records(&buffer[..complete_len], source);
                 }
                 // Advance the committed checkpoint past the last complete record and resync the
                 // scan cursor to it: no byte is skipped, and the last complete record is not re-read.
@@ -509,10 +1047,10 @@ impl SpeedTracker {
 
     /// Parse a newline-delimited slice of complete records into `entries`. Decode lossily and trim,
     /// exactly like canonical ingestion (`String::from_utf8_lossy(raw).trim()`): a record carrying
-    /// invalid UTF-8 becomes a classified-invalid line that parse_speed_entry rejects and we advance
+    /// invalid UTF-8 becomes a classified-invalid line that parse_request rejects and we advance
     /// past, instead of an error that would drop the batch's checkpoint and replay earlier valid
     /// records on every later poll.
-    fn parse_records(&mut self, bytes: &[u8], kind: &SourceKind) {
+    fn parse_records(&mut self, bytes: &[u8], source: &TrackedSource) {
         for record in bytes.split(|&b| b == b'\n') {
             if record.is_empty() {
                 continue;
@@ -522,9 +1060,19 @@ impl SpeedTracker {
             if trimmed.is_empty() {
                 continue;
             }
-            if let Some(entry) = parse_speed_entry(&self.cachelog, &self.detailed, trimmed, kind) {
-                self.entries.push_back(entry);
+            if let Some(request) = parse_request(&self.cachelog, &self.detailed, trimmed, source) {
+                self.ingest_request(request, source);
+            }
+        }
+    }
+
+    fn ingest_request(&mut self, request: LogEntry, source: &TrackedSource) {
+        self.health.observe_request(request_outcome(&request, source));
+        if let Some(entry) = speed_entry(request, source) {
+            if let Some(metrics) = self.metrics.as_mut() {
+                metrics.observe(&entry.service, entry.is_cache_hit, entry.bytes_sent);
             }
+            self.entries.push_back(entry);
         }
     }
 
@@ -546,18 +1094,18 @@ impl SpeedTracker {
         (eff.ceil() as i64).clamp(WINDOW_SECONDS, MAX_WINDOW_SECONDS)
     }
 
-    fn clean_old_entries(&mut self) {
+    fn clean_old_entries(&mut self, now: NaiveDateTime) {
         // Retention is fixed at the backstop horizon and decoupled from the adaptive snapshot
         // window: every downstream read re-filters by the current window_start, so retaining a bit
         // more is always safe, memory stays bounded, and a window that widens on a later burst can
         // retroactively re-include entries a narrower window would already have evicted.
-        let cutoff = Utc::now().naive_utc() - chrono::Duration::seconds(MAX_WINDOW_SECONDS);
+        let cutoff = now - chrono::Duration::seconds(MAX_WINDOW_SECONDS);
 
         self.entries.retain(|entry| entry.timestamp >= cutoff);
     }
 
-    async fn calculate_snapshot(&mut self) -> DownloadSpeedSnapshot {
-        let now = Utc::now();
+    /// Build the snapshot as of `now`: wall-clock time when tailing, the replay clock in `--replay`.
+    async fn calculate_snapshot(&mut self, now: DateTime<Utc>) -> DownloadSpeedSnapshot {
         let now_naive = now.naive_utc();
         let window_secs = self.effective_window_secs();
         let window_start = now_naive - chrono::Duration::seconds(window_secs);
@@ -567,6 +1115,9 @@ impl SpeedTracker {
             .filter(|e| e.timestamp >= window_start)
             .cloned()
             .collect();
+        for entry in self.entries.iter_mut() {
+            entry.fresh = false;
+        }
 
         // Speed divides by OBSERVED coverage, not the whole window. The newest in-window timestamp
         // (clamped to now so a future-dated line cannot shrink the divisor) marks how much of the
@@ -591,16 +1142,28 @@ impl SpeedTracker {
         let (total_bytes_per_second, entries_count, has_active_downloads) =
             headline_aggregates(&self.entries, window_start, speed_divisor);
 
-        // Per-client (total, cache-hit) byte aggregates - only three fields are read per
-        // entry, so no entry clone is needed.
-        let mut client_aggregates: HashMap<String, (i64, i64)> = HashMap::new();
+        // Per-client (total, cache-hit, upstream) byte aggregates - only a few fields are read
+        // per entry, so no entry clone is needed.
+        let mut client_aggregates: HashMap<String, (i64, i64, i64)> = HashMap::new();
+        let mut upstream_bytes_total: i64 = 0;
         for entry in &window_entries {
-            let aggregate = client_aggregates.entry(entry.client_ip.clone()).or_insert((0, 0));
+            let aggregate = client_aggregates.entry(entry.client_ip.clone()).or_insert((0, 0, 0));
             aggregate.0 += entry.bytes_sent;
             if entry.is_cache_hit {
                 aggregate.1 += entry.bytes_sent;
             }
+            aggregate.2 += entry.upstream_bytes;
+            upstream_bytes_total += entry.upstream_bytes;
+        }
+        let upstream_bytes_per_second = upstream_bytes_total as f64 / speed_divisor;
+        let mut client_entries: HashMap<&str, Vec<&SpeedLogEntry>> = HashMap::new();
+        for entry in &window_entries {
+            client_entries.entry(entry.client_ip.as_str()).or_default().push(entry);
         }
+        let mut client_requests: HashMap<String, RequestBehavior> = client_entries
+            .into_iter()
+            .map(|(client_ip, entries)| (client_ip.to_string(), request_behavior(&entries, speed_divisor)))
+            .collect();
 
         // Group by depot + client for game speeds (Steam and other services with depot IDs)
         let mut depot_groups: HashMap<(u32, String), Vec<SpeedLogEntry>> = HashMap::new();
@@ -732,17 +1295,22 @@ impl SpeedTracker {
             game_speeds.push(build_game_speed_info(entries, 0, client_ip, service, Some(game_name), None, speed_divisor));
         }
 
+        self.annotate_progress(&mut game_speeds, now_naive).await;
+        let (stalled, erroring) = self.evaluate_health(&game_speeds, now_naive).await;
+
         // Sort by speed descending
         game_speeds.sort_by(|a, b| b.bytes_per_second.partial_cmp(&a.bytes_per_second).unwrap_or(std::cmp::Ordering::Equal));
 
         // Client speeds from the per-client aggregates computed before the grouping.
         let mut client_speeds: Vec<ClientSpeedInfo> = client_aggregates.into_iter()
-            .map(|(client_ip, (total_bytes, cache_hit_bytes))| {
+            .map(|(client_ip, (total_bytes, cache_hit_bytes, upstream_bytes))| {
                 let cache_miss_bytes = total_bytes - cache_hit_bytes;
                 // Count active games as this client's rows in the collapsed game_speeds
                 // list, so the client card agrees with the games list (counting raw
                 // depot IDs would show one multi-depot game as several games).
                 let active_games = game_speeds.iter().filter(|g| g.client_ip == client_ip).count();
+                let requests = client_requests.remove(&client_ip).unwrap_or_default();
+                let datasources = client_datasources(&client_ip, &game_speeds);
 
                 ClientSpeedInfo {
                     client_ip,
@@ -751,23 +1319,206 @@ impl SpeedTracker {
                     active_games,
                     cache_hit_bytes,
                     cache_miss_bytes,
+                    upstream_bytes_per_second: upstream_bytes as f64 / speed_divisor,
+                    datasources,
+                    requests,
                 }
             })
             .collect();
+        flag_request_outliers(&mut client_speeds);
 
         client_speeds.sort_by(|a, b| b.bytes_per_second.partial_cmp(&a.bytes_per_second).unwrap_or(std::cmp::Ordering::Equal));
 
+        let (service_speeds, datasource_speeds) = aggregate_speeds(&game_speeds);
+
+        let wan = self.uplink_bytes_per_second.map(|capacity| {
+            let saturated = upstream_bytes_per_second / capacity * 100.0 >= WAN_SATURATION_PERCENT;
+            self.wan_saturated_since = match (saturated, self.wan_saturated_since) {
+                (false, _) => None,
+                (true, since) => Some(since.unwrap_or(now_naive)),
+            };
+            wan_status(capacity, upstream_bytes_per_second, self.wan_saturated_since, &game_speeds, &client_speeds)
+        });
+
         DownloadSpeedSnapshot {
             timestamp_utc: now.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
             total_bytes_per_second,
             game_speeds,
             client_speeds,
+            service_speeds,
+            datasource_speeds,
             window_seconds: window_secs,
             entries_in_window: entries_count,
             has_active_downloads,
+            upstream_bytes_per_second,
+            wan,
+            stalled,
+            erroring,
         }
     }
 
+    /// Feed this snapshot's game rows to the health monitor and name what it reports. Erroring
+    /// groups are keyed by depot or service rather than game, so they are resolved here the same
+    /// way the game rows are: depot mapping for depot URLs, Epic/Xbox URL patterns, else the
+    /// service's display name.
+    async fn evaluate_health(
+        &mut self,
+        game_speeds: &[GameSpeedInfo],
+        now: NaiveDateTime,
+    ) -> (Vec<StalledDownload>, Vec<ErroringDownload>) {
+        self.health.prune(now, download_sessions::SESSION_GAP_MINUTES * 60);
+        for game in game_speeds {
+            let observation = SessionObservation {
+                client_ip: game.client_ip.clone(),
+                service: game.service.clone(),
+                game_name: game.game_name.clone(),
+                game_app_id: game.game_app_id,
+                depot_id: game.depot_id,
+                last_url: game.last_url.clone(),
+                percent_complete: game.percent_complete,
+                datasources: game.datasources.clone(),
+            };
+            let key = format!("{}|{}", game.client_ip, progress_key(game));
+            self.health.observe_session(&key, observation, game.bytes_per_second, now);
+        }
+
+        let stalled = self
+            .health
+            .stalled(now)
+            .into_iter()
+            .map(|stall| StalledDownload {
+                client_ip: stall.session.client_ip,
+                service: stall.session.service,
+                game_name: stall.session.game_name,
+                game_app_id: stall.session.game_app_id,
+                depot_id: stall.session.depot_id,
+                last_url: stall.session.last_url,
+                bytes_per_second: stall.bytes_per_second,
+                peak_bytes_per_second: stall.peak_bytes_per_second,
+                stalled_since: stall.stalled_since.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+                stalled_seconds: (now - stall.stalled_since).num_seconds(),
+                percent_complete: stall.session.percent_complete,
+                recent_requests: stall.recent_requests,
+                datasources: stall.session.datasources,
+            })
+            .collect();
+
+        let mut erroring = Vec::new();
+        for group in self.health.erroring() {
+            let (game_name, game_app_id) = if let Some(depot_id) = group.depot_id {
+                self.lookup_depot(depot_id).await
+            } else if group.service.contains("epic") {
+                (self.lookup_epic_game(&group.last_url).await, None)
+            } else if group.service.contains("wsus") || group.service.contains("xboxlive") {
+                (self.lookup_xbox_game(&group.last_url).await, None)
+            } else {
+                (None, None)
+            };
+            // Unresolved depots stay nameless, like their game rows; other services fall back to
+            // the service label.
+            let game_name = match group.depot_id {
+                Some(_) => game_name,
+                None => game_name.or_else(|| Some(get_service_display_name(&group.service))),
+            };
+            erroring.push(ErroringDownload {
+                game_name,
+                game_app_id,
+                client_ip: group.client_ip,
+                service: group.service,
+                depot_id: group.depot_id,
+                last_url: group.last_url,
+                erroring_since: group.first_error_at.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
+                error_breakdown: group.breakdown,
+                datasources: group.datasources,
+            });
+        }
+        (stalled, erroring)
+    }
+
+    /// Fill each game row's progress fields: add its fresh bytes to the client's running session,
+    /// then compare the session against the game's Downloads history. Sessions idle longer than
+    /// the Downloads session gap are dropped, so they restart from zero like a new Downloads row.
+    /// A tracker started mid-download only counts what it has seen, so its percentage starts low.
+    async fn annotate_progress(&mut self, game_speeds: &mut [GameSpeedInfo], now: NaiveDateTime) {
+        let gap = chrono::Duration::minutes(download_sessions::SESSION_GAP_MINUTES);
+        self.progress_sessions.retain(|_, session| now - session.last_seen <= gap);
+
+        for game in game_speeds.iter_mut() {
+            let key = progress_key(game);
+            let session = self
+                .progress_sessions
+                .entry((key.clone(), game.client_ip.clone()))
+                .or_insert_with(|| ProgressSession { bytes: 0, depots: Vec::new(), last_seen: now });
+            session.bytes += game.fresh_bytes;
+            session.last_seen = now;
+            for depot in &game.depot_ids {
+                if !session.depots.contains(depot) {
+                    session.depots.push(*depot);
+                }
+            }
+            let (session_bytes, depots) = (session.bytes, session.depots.clone());
+
+            let history = self.game_history_for(&key, game).await;
+            let (expected, confidence) = estimate_expected_bytes(&history, &depots);
+            apply_progress(game, session_bytes, expected, confidence);
+        }
+    }
+
+    async fn game_history_for(&mut self, key: &str, game: &GameSpeedInfo) -> GameHistory {
+        if let Some((loaded, history)) = self.game_history.get(key) {
+            if loaded.elapsed() < Duration::from_secs(PROGRESS_HISTORY_REFRESH_SECS) {
+                return history.clone();
+            }
+        }
+        let history = self.load_game_history(game).await;
+        self.game_history.insert(key.to_string(), (Instant::now(), history.clone()));
+        history
+    }
+
+    /// Finished Downloads of the same game: by app id (per depot) for resolved Steam games, by
+    /// depot for unresolved depots, and by name within the service otherwise. Active rows are
+    /// left out, since one of them is the session being measured. Errors read as no history.
+    async fn load_game_history(&self, game: &GameSpeedInfo) -> GameHistory {
+        let rows: Result<Vec<HistoryRow>, sqlx::Error> = if let Some(app_id) = game.game_app_id {
+            sqlx::query_as(
+                "SELECT \"DepotId\", MAX(\"CacheHitBytes\" + \"CacheMissBytes\") FROM \"Downloads\" \
+                 WHERE \"GameAppId\" = $1 AND NOT \"IsActive\" GROUP BY \"DepotId\"",
+            )
+            .bind(i64::from(app_id))
+            .fetch_all(&self.pool)
+            .await
+        } else if game.depot_id != 0 {
+            sqlx::query_as(
+                "SELECT \"DepotId\", MAX(\"CacheHitBytes\" + \"CacheMissBytes\") FROM \"Downloads\" \
+                 WHERE \"DepotId\" = $1 AND NOT \"IsActive\" GROUP BY \"DepotId\"",
+            )
+            .bind(i64::from(game.depot_id))
+            .fetch_all(&self.pool)
+            .await
+        } else if let Some(name) = &game.game_name {
+            sqlx::query_as(
+                "SELECT NULL::bigint, MAX(\"CacheHitBytes\" + \"CacheMissBytes\") FROM \"Downloads\" \
+                 WHERE \"GameName\" = $1 AND LOWER(\"Service\") = LOWER($2) AND NOT \"IsActive\"",
+            )
+            .bind(name)
+            .bind(&game.service)
+            .fetch_all(&self.pool)
+            .await
+        } else {
+            Ok(Vec::new())
+        };
+
+        let mut history = GameHistory::default();
+        for (depot_id, bytes) in rows.unwrap_or_default() {
+            let Some(bytes) = bytes.filter(|b| *b > 0) else { continue };
+            history.largest = Some(history.largest.map_or(bytes, |l| l.max(bytes)));
+            if let Some(depot_id) = depot_id.and_then(|d| u32::try_from(d).ok()) {
+                history.per_depot.insert(depot_id, bytes);
+            }
+        }
+        history
+    }
+
     async fn load_epic_patterns(&mut self) {
         // Only reload every 60 seconds
         if let Some(last) = self.last_epic_pattern_load {
@@ -961,6 +1712,279 @@ fn headline_aggregates(
     (total_bytes as f64 / speed_divisor, count, count > 0)
 }
 
+/// Split a rate into its hit and miss parts by the byte shares of the window it came from.
+fn split_hit_miss(bytes_per_second: f64, hit_bytes: i64, total_bytes: i64) -> (f64, f64) {
+    if total_bytes <= 0 {
+        return (0.0, 0.0);
+    }
+    let hit = bytes_per_second * hit_bytes as f64 / total_bytes as f64;
+    (hit, bytes_per_second - hit)
+}
+
+/// The name a game series is persisted under. An unresolved depot keeps its depot id, which is
+/// what the live view shows for it too.
+fn game_series_key(game: &GameSpeedInfo) -> String {
+    match &game.game_name {
+        Some(name) => name.clone(),
+        None => format!("Depot {}", game.depot_id),
+    }
+}
+
+/// Reduce one snapshot to the series speed_history persists: the total, and per service, client
+/// and game. A game row is one (game, client) pair, so `active_downloads` counts those rows: the
+/// whole snapshot for the total, the service's rows, the client's games, and a game's clients.
+/// The total series is always present, even when idle, because the rollup measures coverage by it.
+fn history_samples(snapshot: &DownloadSpeedSnapshot) -> Vec<SeriesSample> {
+    let (window_hit, window_total) = snapshot
+        .client_speeds
+        .iter()
+        .fold((0, 0), |(hit, total), c| (hit + c.cache_hit_bytes, total + c.total_bytes));
+    let (hit, miss) = split_hit_miss(snapshot.total_bytes_per_second, window_hit, window_total);
+    let mut samples = vec![SeriesSample {
+        kind: SeriesKind::Total,
+        key: String::new(),
+        bytes_per_second: snapshot.total_bytes_per_second,
+        hit_bytes_per_second: hit,
+        miss_bytes_per_second: miss,
+        active_downloads: snapshot.game_speeds.len() as i32,
+    }];
+
+    let mut grouped: HashMap<(SeriesKind, String), SeriesSample> = HashMap::new();
+    for game in &snapshot.game_speeds {
+        let (hit, miss) = split_hit_miss(game.bytes_per_second, game.cache_hit_bytes, game.total_bytes);
+        for (kind, key) in [
+            (SeriesKind::Service, game.service.clone()),
+            (SeriesKind::Game, game_series_key(game)),
+        ] {
+            let sample = grouped.entry((kind, key.clone())).or_insert_with(|| SeriesSample {
+                kind,
+                key,
+                bytes_per_second: 0.0,
+                hit_bytes_per_second: 0.0,
+                miss_bytes_per_second: 0.0,
+                active_downloads: 0,
+            });
+            sample.bytes_per_second += game.bytes_per_second;
+            sample.hit_bytes_per_second += hit;
+            sample.miss_bytes_per_second += miss;
+            sample.active_downloads += 1;
+        }
+    }
+    let mut grouped: Vec<SeriesSample> = grouped.into_values().collect();
+    grouped.sort_by(|a, b| (a.kind, &a.key).cmp(&(b.kind, &b.key)));
+    samples.extend(grouped);
+
+    for client in &snapshot.client_speeds {
+        let (hit, miss) = split_hit_miss(client.bytes_per_second, client.cache_hit_bytes, client.total_bytes);
+        samples.push(SeriesSample {
+            kind: SeriesKind::Client,
+            key: client.client_ip.clone(),
+            bytes_per_second: client.bytes_per_second,
+            hit_bytes_per_second: hit,
+            miss_bytes_per_second: miss,
+            active_downloads: client.active_games as i32,
+        });
+    }
+    samples
+}
+
+/// Request rate, size and estimated concurrency for one client's window entries.
+fn request_behavior(entries: &[&SpeedLogEntry], speed_divisor: f64) -> RequestBehavior {
+    let count = entries.len();
+    if count == 0 {
+        return RequestBehavior::default();
+    }
+    let total_bytes: i64 = entries.iter().map(|e| e.bytes_sent).sum();
+    let durations: Option<Vec<f64>> = entries.iter().map(|e| e.request_time).collect();
+    let (concurrent_requests, concurrency_source) = match durations {
+        Some(durations) => (
+            durations.iter().fold(0.0, |total, d| total + d) / speed_divisor,
+            ConcurrencySource::RequestTime,
+        ),
+        None => {
+            let mut per_second: HashMap<NaiveDateTime, usize> = HashMap::new();
+            for entry in entries {
+                *per_second.entry(entry.timestamp.with_nanosecond(0).unwrap_or(entry.timestamp)).or_default() += 1;
+            }
+            (per_second.into_values().max().unwrap_or(0) as f64, ConcurrencySource::Timestamps)
+        }
+    };
+    RequestBehavior {
+        requests_per_second: count as f64 / speed_divisor,
+        average_request_bytes: total_bytes as f64 / count as f64,
+        concurrent_requests,
+        concurrency_source,
+        outliers: Vec::new(),
+    }
+}
+
+/// Flag clients whose request rate or concurrency is far above normal, or who split downloads into
+/// many tiny requests. See the `OUTLIER_*` constants for the thresholds.
+fn flag_request_outliers(clients: &mut [ClientSpeedInfo]) {
+    let median = |values: Vec<f64>| -> Option<f64> {
+        if clients.len() < OUTLIER_MIN_CLIENTS {
+            return None;
+        }
+        let mut values = values;
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let mid = values.len() / 2;
+        Some(if values.len().is_multiple_of(2) { (values[mid - 1] + values[mid]) / 2.0 } else { values[mid] })
+    };
+    let rate_median = median(clients.iter().map(|c| c.requests.requests_per_second).collect());
+    let concurrency_median = median(clients.iter().map(|c| c.requests.concurrent_requests).collect());
+    let stands_out = |value: f64, floor: f64, median: Option<f64>| {
+        value >= floor && median.is_none_or(|m| value >= m * OUTLIER_MEDIAN_FACTOR)
+    };
+    for client in clients.iter_mut() {
+        let requests = &mut client.requests;
+        if stands_out(requests.requests_per_second, OUTLIER_REQUESTS_PER_SECOND, rate_median) {
+            requests.outliers.push("requestRate");
+        }
+        if stands_out(requests.concurrent_requests, OUTLIER_CONCURRENT_REQUESTS, concurrency_median) {
+            requests.outliers.push("concurrency");
+        }
+        if requests.average_request_bytes < OUTLIER_SMALL_REQUEST_BYTES
+            && requests.requests_per_second >= OUTLIER_SMALL_REQUEST_RATE
+        {
+            requests.outliers.push("smallRequests");
+        }
+    }
+}
+
+/// Uplink status for one snapshot. `saturated_since` is the start of the current saturation spell
+/// (None when not saturated); contributors are listed only while saturated.
+fn wan_status(
+    capacity: f64,
+    upstream_bytes_per_second: f64,
+    saturated_since: Option<NaiveDateTime>,
+    game_speeds: &[GameSpeedInfo],
+    client_speeds: &[ClientSpeedInfo],
+) -> WanStatus {
+    let share = |rate: f64| {
+        if upstream_bytes_per_second > 0.0 {
+            rate / upstream_bytes_per_second * 100.0
+        } else {
+            0.0
+        }
+    };
+    let by_upstream = |a: &f64, b: &f64| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal);
+    let (mut top_clients, mut top_games) = (Vec::new(), Vec::new());
+    if saturated_since.is_some() {
+        top_clients = client_speeds
+            .iter()
+            .filter(|c| c.upstream_bytes_per_second > 0.0)
+            .map(|c| WanClient {
+                client_ip: c.client_ip.clone(),
+                upstream_bytes_per_second: c.upstream_bytes_per_second,
+                share_percent: share(c.upstream_bytes_per_second),
+            })
+            .collect();
+        top_clients.sort_by(|a, b| by_upstream(&a.upstream_bytes_per_second, &b.upstream_bytes_per_second));
+        top_clients.truncate(WAN_TOP_CONTRIBUTORS);
+        top_games = game_speeds
+            .iter()
+            .filter(|g| g.upstream_bytes_per_second > 0.0)
+            .map(|g| WanGame {
+                client_ip: g.client_ip.clone(),
+                service: g.service.clone(),
+                game_name: g.game_name.clone(),
+                depot_id: g.depot_id,
+                upstream_bytes_per_second: g.upstream_bytes_per_second,
+                share_percent: share(g.upstream_bytes_per_second),
+            })
+            .collect();
+        top_games.sort_by(|a, b| by_upstream(&a.upstream_bytes_per_second, &b.upstream_bytes_per_second));
+        top_games.truncate(WAN_TOP_CONTRIBUTORS);
+    }
+    WanStatus {
+        capacity_bytes_per_second: capacity,
+        utilization_percent: upstream_bytes_per_second / capacity * 100.0,
+        saturated: saturated_since.is_some(),
+        saturated_since: saturated_since.map(|since| since.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()),
+        top_clients,
+        top_games,
+    }
+}
+
+/// The gauges `/metrics` exports for one snapshot. Per-service rates sum the service's game rows;
+/// per-game rates sum a game across clients. speed_metrics bounds both label sets.
+fn window_gauges(snapshot: &DownloadSpeedSnapshot) -> WindowGauges {
+    let mut services: HashMap<String, f64> = HashMap::new();
+    for game in &snapshot.game_speeds {
+        *services.entry(game.service.clone()).or_default() += game.bytes_per_second;
+    }
+    WindowGauges {
+        total_bytes_per_second: snapshot.total_bytes_per_second,
+        service_bytes_per_second: services.into_iter().collect(),
+        game_bytes_per_second: snapshot
+            .game_speeds
+            .iter()
+            .map(|game| (game_series_key(game), game.bytes_per_second))
+            .collect(),
+        active_clients: snapshot.client_speeds.len(),
+        active_downloads: snapshot.game_speeds.len(),
+        window_seconds: snapshot.window_seconds,
+        upstream_bytes_per_second: snapshot.upstream_bytes_per_second,
+        wan_utilization_percent: snapshot.wan.as_ref().map(|wan| wan.utilization_percent),
+    }
+}
+
+/// The identity a progress session and its history are keyed on: the Steam app when resolved,
+/// else the depot, else the service and game name.
+fn progress_key(game: &GameSpeedInfo) -> String {
+    match (game.game_app_id, game.depot_id, &game.game_name) {
+        (Some(app_id), _, _) => format!("app:{app_id}"),
+        (None, depot_id, _) if depot_id != 0 => format!("depot:{depot_id}"),
+        (None, _, name) => format!("name:{}:{}", game.service, name.as_deref().unwrap_or_default()),
+    }
+}
+
+/// Expected size of a download from its history. With per-depot history, the estimate is the sum
+/// of the largest finished download of each depot seen this session, and never less than the
+/// largest single finished download of the game (a session that has only reached its first depot
+/// so far still expects the whole game). Without depots, the largest finished download is used.
+fn estimate_expected_bytes(history: &GameHistory, depots: &[u32]) -> (Option<i64>, ProgressConfidence) {
+    let Some(largest) = history.largest else {
+        return (None, ProgressConfidence::None);
+    };
+    if depots.is_empty() || history.per_depot.is_empty() {
+        let confidence = if depots.is_empty() { ProgressConfidence::High } else { ProgressConfidence::Low };
+        return (Some(largest), confidence);
+    }
+    let mut known = 0i64;
+    let mut missing = false;
+    for depot in depots {
+        match history.per_depot.get(depot) {
+            Some(bytes) => known += bytes,
+            None => missing = true,
+        }
+    }
+    let confidence = if missing { ProgressConfidence::Low } else { ProgressConfidence::High };
+    (Some(known.max(largest)), confidence)
+}
+
+/// Fill a row's progress fields. A session that has already pulled more than the estimate is
+/// past what history knows: it stays just short of complete, with no ETA and low confidence.
+fn apply_progress(game: &mut GameSpeedInfo, session_bytes: i64, expected: Option<i64>, confidence: ProgressConfidence) {
+    game.session_bytes = session_bytes;
+    game.expected_bytes = expected;
+    game.progress_confidence = confidence;
+    let Some(expected) = expected.filter(|e| *e > 0) else {
+        game.percent_complete = None;
+        game.eta_seconds = None;
+        return;
+    };
+    if session_bytes >= expected {
+        game.percent_complete = Some(99.0);
+        game.eta_seconds = None;
+        game.progress_confidence = ProgressConfidence::Low;
+        return;
+    }
+    game.percent_complete = Some(session_bytes as f64 / expected as f64 * 100.0);
+    game.eta_seconds = (game.bytes_per_second > 0.0)
+        .then(|| (expected - session_bytes) as f64 / game.bytes_per_second);
+}
+
 /// Build a GameSpeedInfo from a group of log entries
 fn build_game_speed_info(
     entries: Vec<SpeedLogEntry>,
@@ -974,6 +1998,34 @@ fn build_game_speed_info(
     let total_bytes: i64 = entries.iter().map(|e| e.bytes_sent).sum();
     let cache_hit_bytes: i64 = entries.iter().filter(|e| e.is_cache_hit).map(|e| e.bytes_sent).sum();
     let cache_miss_bytes = total_bytes - cache_hit_bytes;
+    let upstream_bytes: i64 = entries.iter().map(|e| e.upstream_bytes).sum();
+    let mut datasource_bytes: Vec<DatasourceBytes> = Vec::new();
+    for entry in &entries {
+        let split = match datasource_bytes.iter_mut().find(|d| d.datasource == entry.datasource) {
+            Some(split) => split,
+            None => {
+                datasource_bytes.push(DatasourceBytes {
+                    datasource: Arc::clone(&entry.datasource),
+                    total_bytes: 0,
+                    cache_hit_bytes: 0,
+                    upstream_bytes: 0,
+                });
+                datasource_bytes.last_mut().expect("just pushed")
+            }
+        };
+        split.total_bytes += entry.bytes_sent;
+        if entry.is_cache_hit {
+            split.cache_hit_bytes += entry.bytes_sent;
+        }
+        split.upstream_bytes += entry.upstream_bytes;
+    }
+    datasource_bytes.sort_by(|a, b| a.datasource.cmp(&b.datasource));
+    let datasources: Vec<Arc<str>> = datasource_bytes.iter().map(|d| Arc::clone(&d.datasource)).collect();
+    let fresh_bytes: i64 = entries.iter().filter(|e| e.fresh).map(|e| e.bytes_sent).sum();
+    let mut depot_ids: Vec<u32> = entries.iter().filter_map(|e| e.depot_id).collect();
+    depot_ids.sort_unstable();
+    depot_ids.dedup();
+    let last_url = entries.iter().max_by_key(|e| e.timestamp).map(|e| e.request_url.clone());
     let cache_hit_percent = if total_bytes > 0 {
         (cache_hit_bytes as f64 / total_bytes as f64) * 100.0
     } else {
@@ -992,9 +2044,109 @@ fn build_game_speed_info(
         cache_hit_bytes,
         cache_miss_bytes,
         cache_hit_percent,
+        upstream_bytes_per_second: upstream_bytes as f64 / speed_divisor,
+        session_bytes: 0,
+        expected_bytes: None,
+        percent_complete: None,
+        eta_seconds: None,
+        progress_confidence: ProgressConfidence::None,
+        fresh_bytes,
+        depot_ids,
+        last_url,
+        datasources,
+        datasource_bytes,
     }
 }
 
+/// The datasources behind a client's game rows, sorted and deduplicated.
+fn client_datasources(client_ip: &str, game_speeds: &[GameSpeedInfo]) -> Vec<Arc<str>> {
+    let mut datasources: Vec<Arc<str>> = game_speeds
+        .iter()
+        .filter(|game| game.client_ip == client_ip)
+        .flat_map(|game| game.datasources.iter().cloned())
+        .collect();
+    datasources.sort();
+    datasources.dedup();
+    datasources
+}
+
+/// Per-service and per-datasource aggregates over a set of game rows, busiest first. The rows
+/// partition the window's entries, so their sums equal the entries' sums. A row fed by several
+/// datasources adds each datasource's own share of its bytes, at the row's bytes-per-byte rate
+/// (the row's speed divided by its bytes), which keeps a filtered subset consistent without
+/// knowing the window divisor.
+fn aggregate_speeds(game_speeds: &[GameSpeedInfo]) -> (Vec<AggregateSpeedInfo>, Vec<AggregateSpeedInfo>) {
+    #[derive(Default)]
+    struct Totals<'a> {
+        bytes_per_second: f64,
+        total_bytes: i64,
+        cache_hit_bytes: i64,
+        upstream_bytes_per_second: f64,
+        clients: std::collections::HashSet<&'a str>,
+        downloads: usize,
+    }
+    impl<'a> Totals<'a> {
+        fn add(&mut self, game: &'a GameSpeedInfo, total: i64, hit: i64, upstream_rate: f64) {
+            let rate = if game.total_bytes > 0 { game.bytes_per_second / game.total_bytes as f64 } else { 0.0 };
+            self.bytes_per_second += total as f64 * rate;
+            self.total_bytes += total;
+            self.cache_hit_bytes += hit;
+            self.upstream_bytes_per_second += upstream_rate;
+            self.clients.insert(&game.client_ip);
+            self.downloads += 1;
+        }
+    }
+
+    let mut services: HashMap<&str, Totals> = HashMap::new();
+    let mut datasources: HashMap<&str, Totals> = HashMap::new();
+    for game in game_speeds {
+        services.entry(&game.service).or_default().add(
+            game,
+            game.total_bytes,
+            game.cache_hit_bytes,
+            game.upstream_bytes_per_second,
+        );
+        let rate = if game.total_bytes > 0 { game.bytes_per_second / game.total_bytes as f64 } else { 0.0 };
+        for split in &game.datasource_bytes {
+            datasources.entry(&split.datasource).or_default().add(
+                game,
+                split.total_bytes,
+                split.cache_hit_bytes,
+                split.upstream_bytes as f64 * rate,
+            );
+        }
+    }
+
+    let finish = |totals: HashMap<&str, Totals>| {
+        let mut rows: Vec<AggregateSpeedInfo> = totals
+            .into_iter()
+            .map(|(name, t)| AggregateSpeedInfo {
+                name: name.to_string(),
+                bytes_per_second: t.bytes_per_second,
+                total_bytes: t.total_bytes,
+                cache_hit_bytes: t.cache_hit_bytes,
+                cache_miss_bytes: t.total_bytes - t.cache_hit_bytes,
+                cache_hit_percent: if t.total_bytes > 0 {
+                    t.cache_hit_bytes as f64 / t.total_bytes as f64 * 100.0
+                } else {
+                    0.0
+                },
+                upstream_bytes_per_second: t.upstream_bytes_per_second,
+                active_clients: t.clients.len(),
+                active_downloads: t.downloads,
+            })
+            .collect();
+        rows.sort_by(|a, b| {
+            b.bytes_per_second
+                .partial_cmp(&a.bytes_per_second)
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        rows
+    };
+    (finish(services), finish(datasources))
+}
+
 /// Collapse Steam depot buckets that resolve to the same app into ONE `GameSpeedInfo`
 /// per (app, client), summing throughput and request counts. One Steam game spans many
 /// depots, so without this a chunk/depot rollover inside the rolling window briefly
@@ -1095,15 +2247,126 @@ fn get_service_display_name(service: &str) -> String {
     }
 }
 
+/// Strips every `--datasource <name>=<dir>` from `args`. The name is everything before the first
+/// `=`, so a directory may itself contain `=`.
+fn take_datasources(args: &mut Vec<String>) -> Result<Vec<DatasourceDir>> {
+    let mut named = Vec::new();
+    while let Some(value) = take_flag_value(args, "--datasource")? {
+        let Some((name, dir)) = value.split_once('=').filter(|(name, dir)| !name.is_empty() && !dir.is_empty()) else {
+            anyhow::bail!("--datasource must be <name>=<dir>, got {}", value);
+        };
+        named.push(DatasourceDir { name: Arc::from(name), dir: PathBuf::from(dir) });
+    }
+    Ok(named)
+}
+
+/// Strips `--uplink-mbps <n>` from `args` and returns the capacity in bytes per second.
+fn take_uplink(args: &mut Vec<String>) -> Result<Option<f64>> {
+    take_flag_value(args, "--uplink-mbps")?
+        .map(|value| {
+            value
+                .parse::<f64>()
+                .ok()
+                .filter(|mbps| mbps.is_finite() && *mbps > 0.0)
+                .map(|mbps| mbps * 1_000_000.0 / 8.0)
+                .with_context(|| format!("--uplink-mbps must be a positive number, got {}", value))
+        })
+        .transpose()
+}
+
+/// Strips `--replay <from> <to>` from `args`. Both bounds are RFC 3339 timestamps.
+fn take_replay(args: &mut Vec<String>) -> Result<Option<(DateTime<Utc>, DateTime<Utc>)>> {
+    let Some(pos) = args.iter().position(|a| a == "--replay") else {
+        return Ok(None);
+    };
+    if pos + 2 >= args.len() || args[pos + 1].starts_with("--") || args[pos + 2].starts_with("--") {
+        anyhow::bail!("--replay requires <from> and <to>");
+    }
+    let bounds: Vec<String> = args.drain(pos..pos + 3).skip(1).collect();
+    let parse = |value: &str| {
+        DateTime::parse_from_rfc3339(value)
+            .map(|t| t.with_timezone(&Utc))
+            .with_context(|| format!("--replay bounds must be RFC 3339 timestamps, got {}", value))
+    };
+    let (from, to) = (parse(&bounds[0])?, parse(&bounds[1])?);
+    if from > to {
+        anyhow::bail!("--replay <from> must not be after <to>");
+    }
+    Ok(Some((from, to)))
+}
+
+fn take_listen_addr(args: &mut Vec<String>, flag: &str) -> Result<Option<SocketAddr>> {
+    take_flag_value(args, flag)?
+        .map(|value| {
+            value
+                .parse::<SocketAddr>()
+                .with_context(|| format!("{} must be an address like 127.0.0.1:9184, got {}", flag, value))
+        })
+        .transpose()
+}
+
+/// Strips the speed history flags from `args`. Returns `None` when `--no-history` is given,
+/// otherwise the retention to run with (defaults for any horizon not overridden).
+fn take_history(args: &mut Vec<String>) -> Result<Option<speed_history::RetentionPolicy>> {
+    let mut retention = speed_history::RetentionPolicy::default();
+    let mut overridden = false;
+    for (flag, hours_per_unit, slot) in [
+        ("--history-second-hours", 1, &mut retention.second),
+        ("--history-minute-days", 24, &mut retention.minute),
+        ("--history-hour-days", 24, &mut retention.hour),
+    ] {
+        if let Some(value) = take_flag_value(args, flag)? {
+            let count = value
+                .parse::<u32>()
+                .ok()
+                .filter(|count| *count > 0)
+                .with_context(|| format!("{} must be a positive whole number, got {}", flag, value))?;
+            *slot = chrono::Duration::hours(i64::from(count) * hours_per_unit);
+            overridden = true;
+        }
+    }
+    if take_switch(args, "--no-history") {
+        if overridden {
+            anyhow::bail!("--no-history cannot be combined with the --history-* retention flags");
+        }
+        return Ok(None);
+    }
+    Ok(Some(retention))
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args: Vec<String> = env::args().collect();
-
-    if args.len() < 2 {
-        eprintln!("Usage: {} <log_dir> [log_dir2] ...", args[0]);
+    let mut args: Vec<String> = env::args().collect();
+    let history = take_history(&mut args)?;
+    let metrics_listen = take_listen_addr(&mut args, "--metrics-listen")?;
+    let serve_listen = take_listen_addr(&mut args, "--serve-listen")?;
+    let serve_socket = take_flag_value(&mut args, "--serve-socket")?.map(PathBuf::from);
+    let replay = take_replay(&mut args)?;
+    let uplink = take_uplink(&mut args)?;
+    let named_dirs = take_datasources(&mut args)?;
+
+    if args.len() < 2 && named_dirs.is_empty() {
+        eprintln!("Usage: {} <log_dir> [log_dir2] ... [options]", args[0]);
         eprintln!("  log_dir: Path to a datasource log directory. Every log source inside it");
         eprintln!("           (a monolithic access.log and/or per-service bare-metal *-access.log");
         eprintln!("           files) is discovered and tailed; access.log is not assumed.");
+        eprintln!("           Rows name the datasource by the directory as given.");
+        eprintln!();
+        eprintln!("Options:");
+        eprintln!("  --datasource <name>=<dir>   Track <dir> as the datasource <name> (repeatable)");
+        eprintln!("  --no-history                Do not persist speed history to speed_history");
+        eprintln!("  --history-second-hours <n>  Keep 1-second rows for n hours (default 24)");
+        eprintln!("  --history-minute-days <n>   Keep 1-minute rows for n days (default 30)");
+        eprintln!("  --history-hour-days <n>     Keep 1-hour rows for n days (default 365)");
+        eprintln!("  --metrics-listen <addr>     Serve Prometheus metrics at http://<addr>/metrics");
+        eprintln!("  --serve-socket <path>       Stream snapshots as JSON lines on a unix socket");
+        eprintln!("  --serve-listen <addr>       Stream snapshots as SSE or WebSocket at http://<addr>/snapshots");
+        eprintln!("                              (filter with ?service=&client=&datasource=)");
+        eprintln!("  --uplink-mbps <n>           Internet uplink capacity in megabits/s; reports WAN");
+        eprintln!("                              utilization and flags saturation at {}%", WAN_SATURATION_PERCENT);
+        eprintln!("  --replay <from> <to>        Replay archived logs (rotated and compressed included)");
+        eprintln!("                              between two RFC 3339 timestamps and exit; the");
+        eprintln!("                              window follows log time. No history, metrics or serving.");
         eprintln!();
         eprintln!("Database connection is configured via DATABASE_URL environment variable.");
         eprintln!("Outputs JSON speed snapshots to stdout every {}ms", BROADCAST_INTERVAL_MS);
@@ -1118,7 +2381,20 @@ async fn main() -> Result<()> {
     // Discover the concrete current files to tail across every datasource directory. A directory
     // may hold a monolithic access.log and/or per-service bare-metal logs; the Rust side owns
     // discovery so C# only has to pass the datasource directory.
-    let dirs: Vec<PathBuf> = args[1..].iter().map(PathBuf::from).collect();
+    let mut dirs: Vec<DatasourceDir> = args[1..].iter().map(|dir| DatasourceDir::unnamed(PathBuf::from(dir))).collect();
+    dirs.extend(named_dirs);
+
+    if let Some((from, to)) = replay {
+        if metrics_listen.is_some() || serve_listen.is_some() || serve_socket.is_some() {
+            anyhow::bail!("--replay cannot be combined with --metrics-listen, --serve-socket or --serve-listen");
+        }
+        // Replay never writes speed_history: the series it rebuilds is already in the past.
+        let pool = db::create_pool().await?;
+        let mut tracker = SpeedTracker::new(pool, Vec::new());
+        tracker.uplink_bytes_per_second = uplink;
+        return tracker.replay(&dirs, from, to).await;
+    }
+
     let sources = discover_tracked_sources(&dirs);
 
     if sources.is_empty() {
@@ -1126,21 +2402,49 @@ async fn main() -> Result<()> {
     }
 
     let pool = db::create_pool().await?;
-    let mut tracker = SpeedTracker::new(pool, sources);
+    let mut tracker = SpeedTracker::new(pool.clone(), sources);
+    tracker.uplink_bytes_per_second = uplink;
+    if let Some(retention) = history {
+        tracker.history = Some(HistoryRecorder::spawn(pool, retention));
+    }
+    if let Some(addr) = metrics_listen {
+        let metrics = SpeedMetrics::new();
+        let bound = metrics.serve(addr).await?;
+        eprintln!("Serving Prometheus metrics at http://{}/metrics", bound);
+        tracker.metrics = Some(metrics);
+    }
+    if serve_socket.is_some() || serve_listen.is_some() {
+        let server = SnapshotServer::new();
+        if let Some(path) = &serve_socket {
+            server.serve_unix(path).await?;
+            eprintln!("Serving snapshots on unix socket {}", path.display());
+        }
+        if let Some(addr) = serve_listen {
+            let bound = server.serve_http(addr).await?;
+            eprintln!("Serving snapshots at http://{}/snapshots", bound);
+        }
+        tracker.snapshot_server = Some(server);
+    }
     tracker.run().await
 }
 
 #[cfg(test)]
 mod tests {
     use super::{
-        build_game_speed_info, collapse_depot_groups, discover_tracked_sources,
-        headline_aggregates, replace_pattern_lookup_cache, SourceKind, SpeedLogEntry, SpeedTracker,
-        TrackedSource, MAX_POLL_BYTES, WINDOW_SECONDS,
+        aggregate_speeds, apply_progress, build_game_speed_info, collapse_depot_groups,
+        discover_tracked_sources, estimate_expected_bytes, flag_request_outliers,
+        headline_aggregates, history_samples, replace_pattern_lookup_cache, request_behavior,
+        take_datasources, take_history, take_replay, upstream_bytes, wan_status, ClientSpeedInfo,
+        ConcurrencySource, DatasourceDir, DownloadSpeedSnapshot, ErrorBreakdown, ErroringDownload,
+        FilterableSnapshot, GameHistory, ProgressConfidence, RequestBehavior, SeriesKind,
+        SourceKind, SpeedLogEntry, SpeedTracker, SubscriptionFilter, TrackedSource, MAX_POLL_BYTES,
+        WINDOW_SECONDS,
     };
-    use chrono::{Duration, NaiveDateTime, Utc};
+    use chrono::{Duration, NaiveDateTime, Timelike, Utc};
     use sqlx::postgres::PgPoolOptions;
     use std::collections::HashMap;
     use std::io::Write;
+    use std::sync::Arc;
 
     // A minimal in-window Steam entry; the collapse logic only reads client_ip, depot_id,
     // bytes_sent and service, so the rest use inert defaults.
@@ -1153,6 +2457,10 @@ mod tests {
             bytes_sent: bytes,
             is_cache_hit: false,
             request_url: String::new(),
+            datasource: Arc::from("/logs/main"),
+            request_time: None,
+            upstream_bytes: bytes,
+            fresh: true,
             cdn_host: None,
         }
     }
@@ -1378,7 +2686,7 @@ mod tests {
         )
         .unwrap();
 
-        let tracked = discover_tracked_sources(&[dir.to_path_buf()]);
+        let tracked = discover_tracked_sources(&[DatasourceDir::unnamed(dir.to_path_buf())]);
         assert_eq!(
             tracked.len(),
             2,
@@ -1479,7 +2787,7 @@ mod tests {
         let access = dir.join("access.log");
         std::fs::write(&access, b"").unwrap();
 
-        let tracked = discover_tracked_sources(&[dir.to_path_buf()]);
+        let tracked = discover_tracked_sources(&[DatasourceDir::unnamed(dir.to_path_buf())]);
         assert_eq!(tracked.len(), 1);
         let mut tracker = lazy_tracker(tracked.clone());
 
@@ -1520,7 +2828,7 @@ mod tests {
         let steam = dir.join("steam-access.log");
         std::fs::write(&steam, b"").unwrap();
 
-        let tracked = discover_tracked_sources(&[dir.to_path_buf()]);
+        let tracked = discover_tracked_sources(&[DatasourceDir::unnamed(dir.to_path_buf())]);
         assert_eq!(tracked.len(), 1);
         assert_eq!(tracked[0].kind, SourceKind::Service("steam".to_string()));
         let mut tracker = lazy_tracker(tracked.clone());
@@ -1566,7 +2874,7 @@ mod tests {
         let access = dir.join("access.log");
         std::fs::write(&access, b"").unwrap();
 
-        let tracked = discover_tracked_sources(&[dir.to_path_buf()]);
+        let tracked = discover_tracked_sources(&[DatasourceDir::unnamed(dir.to_path_buf())]);
         assert_eq!(tracked.len(), 1);
         let mut tracker = lazy_tracker(tracked.clone());
 
@@ -1628,7 +2936,7 @@ mod tests {
         let steam = dir.join("steam-access.log");
         std::fs::write(&steam, b"").unwrap();
 
-        let tracked = discover_tracked_sources(&[dir.to_path_buf()]);
+        let tracked = discover_tracked_sources(&[DatasourceDir::unnamed(dir.to_path_buf())]);
         assert_eq!(tracked.len(), 1);
         let mut tracker = lazy_tracker(tracked.clone());
 
@@ -1703,7 +3011,7 @@ mod tests {
         let steam = dir.join("steam-access.log");
         std::fs::write(&steam, b"").unwrap();
 
-        let tracked = discover_tracked_sources(&[dir.to_path_buf()]);
+        let tracked = discover_tracked_sources(&[DatasourceDir::unnamed(dir.to_path_buf())]);
         assert_eq!(tracked.len(), 1);
         let mut tracker = lazy_tracker(tracked.clone());
         tracker.read_new_entries(&tracked[0]).unwrap();
@@ -1743,7 +3051,7 @@ mod tests {
         let steam = dir.join("steam-access.log");
         std::fs::write(&steam, b"").unwrap();
 
-        let tracked = discover_tracked_sources(&[dir.to_path_buf()]);
+        let tracked = discover_tracked_sources(&[DatasourceDir::unnamed(dir.to_path_buf())]);
         assert_eq!(tracked.len(), 1);
         let mut tracker = lazy_tracker(tracked.clone());
         tracker.read_new_entries(&tracked[0]).unwrap();
@@ -1783,7 +3091,7 @@ mod tests {
         let steam = dir.join("steam-access.log");
         std::fs::write(&steam, b"").unwrap();
 
-        let tracked = discover_tracked_sources(&[dir.to_path_buf()]);
+        let tracked = discover_tracked_sources(&[DatasourceDir::unnamed(dir.to_path_buf())]);
         assert_eq!(tracked.len(), 1);
         let mut tracker = lazy_tracker(tracked.clone());
         tracker.read_new_entries(&tracked[0]).unwrap();
@@ -1828,4 +3136,389 @@ mod tests {
             "rotation resets file positions but preserves the learned cadence"
         );
     }
+
+    // The persisted series are derived from the same snapshot the parent reads: services and
+    // games sum their (game, client) rows, clients come from client_speeds, and the hit/miss
+    // split follows each row's window byte shares.
+    #[test]
+    fn history_samples_cover_total_service_client_and_game() {
+        let game = |name: &str, client: &str, bps: f64, hit: i64, total: i64| {
+            let mut info = build_game_speed_info(Vec::new(), 0, client.to_string(), "steam".to_string(), Some(name.to_string()), Some(730), 1.0);
+            info.bytes_per_second = bps;
+            info.cache_hit_bytes = hit;
+            info.total_bytes = total;
+            info
+        };
+        let client = |ip: &str, bps: f64, hit: i64, total: i64| ClientSpeedInfo {
+            client_ip: ip.to_string(),
+            bytes_per_second: bps,
+            total_bytes: total,
+            active_games: 1,
+            cache_hit_bytes: hit,
+            cache_miss_bytes: total - hit,
+            upstream_bytes_per_second: 0.0,
+            datasources: Vec::new(),
+            requests: RequestBehavior::default(),
+        };
+        let snapshot = DownloadSpeedSnapshot {
+            timestamp_utc: String::new(),
+            total_bytes_per_second: 300.0,
+            game_speeds: vec![
+                game("Counter-Strike 2", "10.0.0.1", 200.0, 100, 400),
+                game("Counter-Strike 2", "10.0.0.2", 100.0, 200, 200),
+            ],
+            client_speeds: vec![client("10.0.0.1", 200.0, 100, 400), client("10.0.0.2", 100.0, 200, 200)],
+            service_speeds: Vec::new(),
+            datasource_speeds: Vec::new(),
+            window_seconds: 2,
+            entries_in_window: 2,
+            has_active_downloads: true,
+            upstream_bytes_per_second: 0.0,
+            wan: None,
+            stalled: Vec::new(),
+            erroring: Vec::new(),
+        };
+
+        let samples = history_samples(&snapshot);
+        let find = |kind: SeriesKind, key: &str| {
+            samples.iter().find(|s| s.kind == kind && s.key == key).unwrap().clone()
+        };
+        let total = find(SeriesKind::Total, "");
+        assert_eq!(total.active_downloads, 2);
+        assert_eq!(total.hit_bytes_per_second, 150.0, "300 B/s at a 300/600 hit share");
+        let steam = find(SeriesKind::Service, "steam");
+        assert_eq!(steam.bytes_per_second, 300.0);
+        assert_eq!(steam.hit_bytes_per_second, 150.0);
+        assert_eq!(steam.miss_bytes_per_second, 150.0);
+        let cs2 = find(SeriesKind::Game, "Counter-Strike 2");
+        assert_eq!(cs2.active_downloads, 2, "one game downloading to two clients");
+        assert_eq!(find(SeriesKind::Client, "10.0.0.2").miss_bytes_per_second, 0.0);
+        assert_eq!(samples.len(), 5);
+    }
+
+    #[test]
+    fn idle_snapshot_still_records_the_total_series() {
+        let snapshot = DownloadSpeedSnapshot {
+            timestamp_utc: String::new(),
+            total_bytes_per_second: 0.0,
+            game_speeds: Vec::new(),
+            client_speeds: Vec::new(),
+            service_speeds: Vec::new(),
+            datasource_speeds: Vec::new(),
+            window_seconds: 2,
+            entries_in_window: 0,
+            has_active_downloads: false,
+            upstream_bytes_per_second: 0.0,
+            wan: None,
+            stalled: Vec::new(),
+            erroring: Vec::new(),
+        };
+        let samples = history_samples(&snapshot);
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].kind, SeriesKind::Total);
+        assert_eq!(samples[0].bytes_per_second, 0.0);
+    }
+
+    #[test]
+    fn history_flags_are_stripped_from_the_directory_list() {
+        let mut args: Vec<String> = ["speed_tracker", "/logs", "--history-minute-days", "7", "/logs2"]
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        let retention = take_history(&mut args).unwrap().expect("history is on by default");
+        assert_eq!(retention.minute, Duration::days(7));
+        assert_eq!(retention.second, Duration::hours(24));
+        assert_eq!(args, vec!["speed_tracker", "/logs", "/logs2"]);
+
+        let mut args: Vec<String> = vec!["speed_tracker".into(), "--no-history".into()];
+        assert!(take_history(&mut args).unwrap().is_none());
+
+        let mut args: Vec<String> = vec!["speed_tracker".into(), "--history-hour-days".into(), "0".into()];
+        assert!(take_history(&mut args).is_err());
+    }
+
+    #[test]
+    fn replay_bounds_are_stripped_and_validated() {
+        let mut args: Vec<String> =
+            ["speed_tracker", "/logs", "--replay", "2024-05-01T12:00:00Z", "2024-05-01T13:00:00+01:00"]
+                .iter()
+                .map(|a| a.to_string())
+                .collect();
+        let (from, to) = take_replay(&mut args).unwrap().unwrap();
+        assert_eq!(from, to, "bounds are normalized to UTC");
+        assert_eq!(args, vec!["speed_tracker", "/logs"]);
+
+        let mut args: Vec<String> = vec!["speed_tracker".into(), "--replay".into(), "2024-05-01T12:00:00Z".into()];
+        assert!(take_replay(&mut args).is_err());
+        let mut args: Vec<String> =
+            vec!["speed_tracker".into(), "--replay".into(), "2024-05-02T00:00:00Z".into(), "2024-05-01T00:00:00Z".into()];
+        assert!(take_replay(&mut args).is_err(), "from after to");
+    }
+
+    // A filtered subscriber gets only the matching game rows, with the client rows and headline
+    // totals recomputed from them; an empty filter is byte-for-byte the stdout line.
+    #[test]
+    fn filtered_snapshot_recomputes_totals_from_matching_rows() {
+        let mut epic = steam_entry("10.0.0.1", 0, 600);
+        epic.service = "epic".to_string();
+        epic.depot_id = None;
+        epic.is_cache_hit = true;
+        epic.datasource = Arc::from("/logs/second");
+        let steam = build_game_speed_info(vec![steam_entry("10.0.0.1", 1001, 1000)], 1001, "10.0.0.1".to_string(), "steam".to_string(), None, None, 2.0);
+        let epic = build_game_speed_info(vec![epic], 0, "10.0.0.1".to_string(), "epic".to_string(), Some("Fortnite".to_string()), None, 2.0);
+        let snapshot = DownloadSpeedSnapshot {
+            timestamp_utc: "2024-05-01T12:00:00.000Z".to_string(),
+            total_bytes_per_second: 800.0,
+            game_speeds: vec![steam, epic],
+            client_speeds: vec![ClientSpeedInfo {
+                client_ip: "10.0.0.1".to_string(),
+                bytes_per_second: 800.0,
+                total_bytes: 1600,
+                active_games: 2,
+                cache_hit_bytes: 600,
+                cache_miss_bytes: 1000,
+                upstream_bytes_per_second: 0.0,
+                datasources: Vec::new(),
+                requests: RequestBehavior::default(),
+            }],
+            service_speeds: Vec::new(),
+            datasource_speeds: Vec::new(),
+            window_seconds: 2,
+            entries_in_window: 2,
+            has_active_downloads: true,
+            upstream_bytes_per_second: 0.0,
+            wan: None,
+            stalled: Vec::new(),
+            erroring: vec![ErroringDownload {
+                client_ip: "10.0.0.1".to_string(),
+                service: "steam".to_string(),
+                game_name: Some("Counter-Strike 2".to_string()),
+                game_app_id: Some(730),
+                depot_id: Some(1001),
+                last_url: "/depot/1001/chunk/a".to_string(),
+                erroring_since: "2024-05-01T11:59:40.000Z".to_string(),
+                error_breakdown: ErrorBreakdown::default(),
+                datasources: vec![Arc::from("/logs/main")],
+            }],
+        };
+
+        let unfiltered = snapshot.filtered_json(&SubscriptionFilter::default());
+        assert_eq!(unfiltered, serde_json::to_string(&snapshot).unwrap());
+        let json: serde_json::Value = serde_json::from_str(&unfiltered).unwrap();
+        assert_eq!(json["gameSpeeds"][1]["datasources"][0], "/logs/second");
+
+        let by_service = SubscriptionFilter { services: vec!["epic".to_string()], ..Default::default() };
+        let json: serde_json::Value = serde_json::from_str(&snapshot.filtered_json(&by_service)).unwrap();
+        assert_eq!(json["totalBytesPerSecond"], 300.0);
+        assert_eq!(json["entriesInWindow"], 1);
+        assert_eq!(json["gameSpeeds"].as_array().unwrap().len(), 1);
+        assert_eq!(json["clientSpeeds"][0]["bytesPerSecond"], 300.0);
+        assert_eq!(json["clientSpeeds"][0]["activeGames"], 1);
+        assert_eq!(json["clientSpeeds"][0]["datasources"], serde_json::json!(["/logs/second"]));
+        assert_eq!(json["serviceSpeeds"].as_array().unwrap().len(), 1);
+        assert_eq!(json["datasourceSpeeds"][0]["name"], "/logs/second");
+        assert_eq!(json["datasourceSpeeds"][0]["cacheHitPercent"], 100.0);
+        assert_eq!(json["erroring"].as_array().unwrap().len(), 0, "alerts follow the same filter");
+
+        let by_datasource = SubscriptionFilter { datasources: vec!["main".to_string()], ..Default::default() };
+        let json: serde_json::Value = serde_json::from_str(&snapshot.filtered_json(&by_datasource)).unwrap();
+        assert_eq!(json["gameSpeeds"][0]["service"], "steam");
+        assert_eq!(json["totalBytesPerSecond"], 500.0);
+        assert_eq!(json["erroring"][0]["errorBreakdown"]["requests"], 0);
+
+        let nothing = SubscriptionFilter { clients: vec!["10.9.9.9".to_string()], ..Default::default() };
+        let json: serde_json::Value = serde_json::from_str(&snapshot.filtered_json(&nothing)).unwrap();
+        assert_eq!(json["hasActiveDownloads"], false);
+        assert_eq!(json["clientSpeeds"].as_array().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn expected_bytes_sums_observed_depots_but_never_undercuts_the_largest_download() {
+        let history = GameHistory {
+            per_depot: HashMap::from([(1001, 40_000), (1002, 25_000)]),
+            largest: Some(50_000),
+        };
+        // One depot so far: the game's largest finished download still bounds the estimate.
+        assert_eq!(estimate_expected_bytes(&history, &[1001]), (Some(50_000), ProgressConfidence::High));
+        assert_eq!(estimate_expected_bytes(&history, &[1001, 1002]), (Some(65_000), ProgressConfidence::High));
+        // A depot history has never seen lowers confidence but keeps the estimate.
+        assert_eq!(estimate_expected_bytes(&history, &[1001, 1003]), (Some(50_000), ProgressConfidence::Low));
+        assert_eq!(estimate_expected_bytes(&GameHistory::default(), &[1001]), (None, ProgressConfidence::None));
+    }
+
+    #[test]
+    fn progress_reports_percent_and_eta_and_caps_overgrown_sessions() {
+        let mut game = build_game_speed_info(vec![steam_entry("10.0.0.1", 1001, 1000)], 1001, "10.0.0.1".to_string(), "steam".to_string(), None, Some(730), 1.0);
+        assert_eq!(game.fresh_bytes, 1000);
+        assert_eq!(game.depot_ids, vec![1001]);
+
+        apply_progress(&mut game, 2500, Some(10_000), ProgressConfidence::High);
+        assert_eq!(game.percent_complete, Some(25.0));
+        assert_eq!(game.eta_seconds, Some(7.5));
+
+        apply_progress(&mut game, 12_000, Some(10_000), ProgressConfidence::High);
+        assert_eq!(game.percent_complete, Some(99.0));
+        assert_eq!(game.eta_seconds, None);
+        assert_eq!(game.progress_confidence, ProgressConfidence::Low);
+
+        apply_progress(&mut game, 2500, None, ProgressConfidence::None);
+        assert_eq!(game.percent_complete, None);
+        let json = serde_json::to_value(&game).unwrap();
+        assert_eq!(json["progressConfidence"], "none");
+        assert_eq!(json["sessionBytes"], 2500);
+        assert!(json.get("freshBytes").is_none());
+    }
+
+    #[test]
+    fn upstream_bytes_prefer_the_logged_length_and_fall_back_to_miss_bytes() {
+        let cachelog = lancache_processor::parser::LogParser::new(chrono_tz::UTC);
+        let line = |status: &str| {
+            format!(
+                "[steam] 10.0.0.1 / - - - [01/May/2024:12:00:00 +0000] \"GET /depot/1001/chunk/a HTTP/1.1\" 200 5000 \"-\" \"Valve\" \"{status}\" \"cdn\" \"-\""
+            )
+        };
+        let mut entry = cachelog.parse_line(&line("MISS")).unwrap();
+        assert_eq!(upstream_bytes(&entry), 5000);
+        entry.upstream_bytes = Some(1_048_576);
+        assert_eq!(upstream_bytes(&entry), 1_048_576, "a logged upstream length wins");
+        assert_eq!(upstream_bytes(&cachelog.parse_line(&line("HIT")).unwrap()), 0);
+        assert_eq!(upstream_bytes(&cachelog.parse_line(&line("EXPIRED")).unwrap()), 5000);
+    }
+
+    #[test]
+    fn saturated_wan_names_the_heaviest_upstream_contributors() {
+        let heavy = build_game_speed_info(vec![steam_entry("10.0.0.1", 1001, 3000)], 1001, "10.0.0.1".to_string(), "steam".to_string(), None, None, 1.0);
+        let light = build_game_speed_info(vec![steam_entry("10.0.0.2", 1002, 1000)], 1002, "10.0.0.2".to_string(), "steam".to_string(), None, None, 1.0);
+        let client = |ip: &str, upstream: f64| ClientSpeedInfo {
+            client_ip: ip.to_string(),
+            bytes_per_second: upstream,
+            total_bytes: upstream as i64,
+            active_games: 1,
+            cache_hit_bytes: 0,
+            cache_miss_bytes: upstream as i64,
+            upstream_bytes_per_second: upstream,
+            datasources: Vec::new(),
+            requests: RequestBehavior::default(),
+        };
+        let clients = vec![client("10.0.0.2", 1000.0), client("10.0.0.1", 3000.0)];
+        let games = vec![light, heavy];
+
+        let quiet = wan_status(8000.0, 4000.0, None, &games, &clients);
+        assert_eq!(quiet.utilization_percent, 50.0);
+        assert!(!quiet.saturated && quiet.top_clients.is_empty());
+
+        let since = Utc::now().naive_utc();
+        let saturated = wan_status(4000.0, 4000.0, Some(since), &games, &clients);
+        assert!(saturated.saturated);
+        assert_eq!(saturated.top_clients[0].client_ip, "10.0.0.1");
+        assert_eq!(saturated.top_clients[0].share_percent, 75.0);
+        assert_eq!(saturated.top_games[0].depot_id, 1001);
+    }
+
+    #[test]
+    fn request_behavior_prefers_logged_request_times() {
+        let base = Utc::now().naive_utc().with_nanosecond(0).unwrap();
+        let mut entries: Vec<SpeedLogEntry> = (0..4).map(|_| steam_entry("10.0.0.1", 1001, 1024)).collect();
+        for (i, entry) in entries.iter_mut().enumerate() {
+            entry.timestamp = base + Duration::milliseconds(if i < 3 { 0 } else { 1500 });
+        }
+        let refs: Vec<&SpeedLogEntry> = entries.iter().collect();
+        let behavior = request_behavior(&refs, 2.0);
+        assert_eq!(behavior.requests_per_second, 2.0);
+        assert_eq!(behavior.average_request_bytes, 1024.0);
+        assert_eq!(behavior.concurrent_requests, 3.0, "peak completions in one timestamp second");
+        assert!(matches!(behavior.concurrency_source, ConcurrencySource::Timestamps));
+
+        for entry in entries.iter_mut() {
+            entry.request_time = Some(1.5);
+        }
+        let refs: Vec<&SpeedLogEntry> = entries.iter().collect();
+        let behavior = request_behavior(&refs, 2.0);
+        assert_eq!(behavior.concurrent_requests, 3.0, "6s in flight over a 2s window");
+        assert!(matches!(behavior.concurrency_source, ConcurrencySource::RequestTime));
+    }
+
+    #[test]
+    fn outliers_need_the_floor_and_a_lead_over_the_median() {
+        let client = |ip: &str, rate: f64, concurrency: f64, size: f64| ClientSpeedInfo {
+            client_ip: ip.to_string(),
+            bytes_per_second: rate * size,
+            total_bytes: 0,
+            active_games: 1,
+            cache_hit_bytes: 0,
+            cache_miss_bytes: 0,
+            upstream_bytes_per_second: 0.0,
+            datasources: Vec::new(),
+            requests: RequestBehavior {
+                requests_per_second: rate,
+                average_request_bytes: size,
+                concurrent_requests: concurrency,
+                ..RequestBehavior::default()
+            },
+        };
+        let mut clients = vec![
+            client("10.0.0.1", 10.0, 8.0, 1_048_576.0),
+            client("10.0.0.2", 12.0, 10.0, 1_048_576.0),
+            client("10.0.0.3", 200.0, 64.0, 16_384.0),
+            client("10.0.0.4", 60.0, 12.0, 1_048_576.0),
+        ];
+        flag_request_outliers(&mut clients);
+        assert!(clients[0].requests.outliers.is_empty());
+        assert_eq!(clients[2].requests.outliers, vec!["requestRate", "concurrency", "smallRequests"]);
+        assert!(clients[3].requests.outliers.is_empty(), "above the floor but not 4x the median");
+
+        // Too few clients for a median: the absolute floor alone decides.
+        let mut pair = vec![client("10.0.0.1", 10.0, 8.0, 1_048_576.0), client("10.0.0.4", 60.0, 12.0, 1_048_576.0)];
+        flag_request_outliers(&mut pair);
+        assert_eq!(pair[1].requests.outliers, vec!["requestRate"]);
+    }
+
+    #[test]
+    fn aggregates_split_multi_datasource_rows_by_their_own_bytes() {
+        let mut second = steam_entry("10.0.0.2", 1001, 3000);
+        second.datasource = Arc::from("second");
+        second.is_cache_hit = true;
+        let shared = build_game_speed_info(
+            vec![steam_entry("10.0.0.1", 1001, 1000), second],
+            1001,
+            "10.0.0.1".to_string(),
+            "steam".to_string(),
+            None,
+            Some(730),
+            2.0,
+        );
+        let mut epic = steam_entry("10.0.0.2", 0, 2000);
+        epic.service = "epic".to_string();
+        epic.depot_id = None;
+        let epic = build_game_speed_info(vec![epic], 0, "10.0.0.2".to_string(), "epic".to_string(), Some("Fortnite".to_string()), None, 2.0);
+
+        let (services, datasources) = aggregate_speeds(&[shared, epic]);
+        assert_eq!(services[0].name, "steam");
+        assert_eq!(services[0].bytes_per_second, 2000.0);
+        assert_eq!(services[0].cache_hit_percent, 75.0);
+        let main = datasources.iter().find(|d| d.name == "/logs/main").unwrap();
+        assert_eq!(main.bytes_per_second, 1500.0);
+        assert_eq!(main.active_clients, 2);
+        assert_eq!(main.active_downloads, 2);
+        let second = datasources.iter().find(|d| d.name == "second").unwrap();
+        assert_eq!(second.bytes_per_second, 1500.0);
+        assert_eq!(second.upstream_bytes_per_second, 1500.0, "fixture entries count as upstream");
+    }
+
+    #[test]
+    fn named_datasources_are_stripped_from_the_arguments() {
+        let mut args: Vec<String> = ["speed_tracker", "--datasource", "Main=/data/a=b", "/logs", "--datasource", "Edge=/edge"]
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        let named = take_datasources(&mut args).unwrap();
+        assert_eq!(named.len(), 2);
+        assert_eq!(&*named[0].name, "Main");
+        assert_eq!(named[0].dir, std::path::PathBuf::from("/data/a=b"));
+        assert_eq!(args, vec!["speed_tracker", "/logs"]);
+        assert_eq!(&*DatasourceDir::unnamed("/logs".into()).name, "/logs");
+
+        let mut args: Vec<String> = vec!["speed_tracker".into(), "--datasource".into(), "/no-name".into()];
+        assert!(take_datasources(&mut args).is_err());
+    }
 }